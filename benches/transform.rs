@@ -0,0 +1,38 @@
+// Compares the batched vertex transform against the one-at-a-time fallback. Build with
+// AVX2 to measure the vectorized path:
+//
+// RUSTFLAGS="-C target-cpu=native" cargo bench --bench transform
+
+#[macro_use]
+extern crate criterion;
+extern crate flight;
+extern crate nalgebra;
+
+use criterion::Criterion;
+use nalgebra::{Matrix4, Vector3, Isometry3};
+
+use flight::math::simd::{batch_transform_vertices, scalar_transform_vertices};
+
+const COUNT: usize = 10_000;
+
+fn setup() -> (Vec<[f32; 3]>, Matrix4<f32>, Vec<[f32; 3]>) {
+    let verts = (0..COUNT)
+        .map(|i| [(i % 100) as f32 * 0.01, (i / 100) as f32 * 0.01, (i % 7) as f32])
+        .collect();
+    let matrix = Isometry3::new(Vector3::new(1., 2., 3.), Vector3::new(0.1, 0.2, 0.3)).to_homogeneous();
+    (verts, matrix, vec![[0.; 3]; COUNT])
+}
+
+fn transform(c: &mut Criterion) {
+    c.bench_function("scalar_transform_vertices", |b| {
+        let (verts, matrix, mut out) = setup();
+        b.iter(|| scalar_transform_vertices(&verts, &matrix, &mut out))
+    });
+    c.bench_function("batch_transform_vertices", |b| {
+        let (verts, matrix, mut out) = setup();
+        b.iter(|| batch_transform_vertices(&verts, &matrix, &mut out))
+    });
+}
+
+criterion_group!(benches, transform);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+// Measures the CPU time spent encoding a large static scene, comparing
+// `Painter::draw` every frame against submitting a `BakedScene`.
+//
+// Run with `cargo run --release -p flight-drawbench [mesh count] [frame count]`
+
+extern crate flight as lib;
+extern crate gfx;
+extern crate nalgebra;
+extern crate glutin;
+extern crate gfx_device_gl;
+extern crate gfx_window_glutin;
+
+use std::time::{Duration, Instant};
+use gfx::Device;
+use gfx::format::*;
+use nalgebra::{self as na, Translation3, Transform3};
+
+use lib::{Texture, UberMesh};
+use lib::mesh::*;
+use lib::mesh::gen::Surface;
+use lib::draw::{DrawParams, Painter, UberStyle, UberMaterial, NormalEncoding};
+
+fn quad() -> MeshSource<VertNTT, ()> {
+    let vert = |x: f32, y: f32| VertNTT {
+        pos: [x - 0.5, y - 0.5, 0.],
+        norm: [0., 0., 1.],
+        tex: [x, y],
+        tan: [1., 0., 0.],
+        bitan: [0., 1., 0.],
+    };
+    MeshSource {
+        verts: vec![vert(0., 0.), vert(1., 0.), vert(1., 1.), vert(0., 1.)],
+        inds: Indexing::Inds(vec![0, 1, 2, 0, 2, 3]),
+        prim: Primitive::TriangleList,
+        mat: (),
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000. + d.subsec_nanos() as f64 / 1_000_000.
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(5000);
+    let frames: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(100);
+
+    let events_loop = glutin::EventsLoop::new();
+    let window_builder = glutin::WindowBuilder::new()
+        .with_visibility(false)
+        .with_dimensions(256, 256)
+        .with_title("Flight draw benchmark");
+    let context = glutin::ContextBuilder::new();
+    let (_window, mut device, mut factory, color, depth) =
+        gfx_window_glutin::init::<Rgba8, lib::DepthFormat>(window_builder, context, &events_loop);
+
+    let mut painter: Painter<_, UberStyle<_>> = Painter::new(&mut factory).unwrap();
+    painter.setup(&mut factory, Primitive::TriangleList).unwrap();
+
+    let meshes: Vec<UberMesh<_>> = (0..count).map(|_| {
+        let mat = UberMaterial {
+            normal: Texture::uniform_value(&mut factory, [0x80, 0x80, 0xFF, 0xFF]).unwrap(),
+            albedo: Texture::uniform_value(&mut factory, [0xA0, 0xA0, 0xA0, 0xFF]).unwrap(),
+            knobs: Texture::uniform_value(&mut factory, [0x00, 0x80, 0x00, 0xFF]).unwrap(),
+            bent: Texture::uniform_value(&mut factory, [0x80, 0x80, 0xFF, 0xFF]).unwrap(),
+            surface: Surface::Mesh,
+            normal_encoding: NormalEncoding::Rgb,
+        };
+        quad().upload(&mut factory).with_material(mat)
+    }).collect();
+    let models: Vec<Transform3<f32>> = (0..count)
+        .map(|i| na::convert(Translation3::new((i % 100) as f32, (i / 100) as f32, -10.)))
+        .collect();
+
+    let mut ctx = DrawParams::new(factory.create_command_buffer().into(), color, depth);
+
+    let mut immediate = Duration::new(0, 0);
+    for _ in 0..frames {
+        let start = Instant::now();
+        for (model, mesh) in models.iter().zip(&meshes) {
+            painter.draw(&mut ctx, *model, mesh);
+        }
+        immediate += start.elapsed();
+        ctx.encoder.flush(&mut device);
+        device.cleanup();
+    }
+
+    let start = Instant::now();
+    let mut scene = painter.bake(&ctx, models.iter().cloned().zip(&meshes)).unwrap();
+    let bake = start.elapsed();
+
+    let mut baked = Duration::new(0, 0);
+    for _ in 0..frames {
+        let start = Instant::now();
+        painter.submit(&mut ctx, &mut scene).unwrap();
+        baked += start.elapsed();
+        ctx.encoder.flush(&mut device);
+        device.cleanup();
+    }
+
+    println!("{} meshes, {} frames", count, frames);
+    println!("draw:   {:.3} ms/frame", millis(immediate) / frames as f64);
+    println!("bake:   {:.3} ms (once)", millis(bake));
+    println!("submit: {:.3} ms/frame", millis(baked) / frames as f64);
+}
@@ -9,7 +9,7 @@ use nalgebra::{self as na, UnitQuaternion, Similarity3, Translation3, Point2, Ve
 use lib::{Texture, UberMesh, Error};
 use lib::mesh::*;
 use lib::load;
-use lib::draw::{DrawParams, Painter, SolidStyle, UberStyle, UberMaterial};
+use lib::draw::{DrawParams, Painter, SolidStyle, UberStyle, UberMaterial, NormalEncoding, FrameCounter, DEPTH_CONVENTION};
 use lib::vr::{primary, secondary, VrMoment, MappedController, Trackable};
 
 pub const NEAR_PLANE: f64 = 0.1;
@@ -117,10 +117,18 @@ fn load_my_simple_object<P, R, F>(
         albedo: Texture::<_, (R8_G8_B8_A8, Srgb)>::uniform_value(f, albedo)?,
         normal: Texture::<_, (R8_G8_B8_A8, Unorm)>::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
         knobs: Texture::<_, (R8_G8_B8_A8, Unorm)>::uniform_value(f, knobs)?,
+        bent: Texture::<_, (R8_G8_B8_A8, Unorm)>::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+        surface: gen::Surface::Mesh,
+        normal_encoding: NormalEncoding::Rgb,
     }).upload(f))
 }
 
 impl<R: gfx::Resources> App<R> {
+    /// Share the frame count of the draw context with the painters that need it
+    pub fn set_frame_counter(&self, frames: FrameCounter) {
+        self.uber.cfg(|inputs| inputs.set_frame_counter(frames));
+    }
+
     pub fn new<F: Factory<R> + FactoryExt<R>>(factory: &mut F) -> Result<Self, Error> {
         // Setup Painters
         let mut solid = Painter::new(factory)?;
@@ -227,7 +235,7 @@ impl<R: gfx::Resources> App<R> {
         }
 
         // Clear targets
-        ctx.encoder.clear_depth(&ctx.depth, FAR_PLANE as f32);
+        ctx.encoder.clear_depth(&ctx.depth, DEPTH_CONVENTION.far());
         ctx.encoder.clear(&ctx.color, [0., 0., 0., 0.]);
         self.uber.clear_env(ctx);
         
@@ -61,7 +61,7 @@ fn main() {
     let context = glutin::ContextBuilder::new();
     // Fuuny thing I found here: changing `_window` to `_` (ignoring it) makes everything explode because of early drop.
     let (window, mut device, mut factory, wcolor, wdepth) =
-        gfx_window_glutin::init::<Rgba8, DepthStencil>(window_builder, context, &events_loop);
+        gfx_window_glutin::init::<Rgba8, lib::DepthFormat>(window_builder, context, &events_loop);
 
     // Create texture to render to
     let (tex, texture_id) = {
@@ -96,13 +96,13 @@ fn main() {
     };
 
     // setup context
-    let mut ctx = draw::DrawParams {
-        encoder: factory.create_command_buffer().into(),
-        color: if mock { wcolor } else { surface },
-        depth: if mock { wdepth } else { depth },
-        left: Default::default(),
-        right: Default::default(),
-    };
+    let mut ctx = draw::DrawParams::new(
+        factory.create_command_buffer().into(),
+        if mock { wcolor } else { surface },
+        if mock { wdepth } else { depth },
+    );
+
+    application.set_frame_counter(ctx.frames.clone());
 
     if mock { window.show() }
 
@@ -122,7 +122,9 @@ fn main() {
         ctx.right = hmd.right;
 
         // Draw frame
+        ctx.begin_frame();
         application.draw(&mut ctx, &vrm);
+        ctx.end_frame();
 
         // Send instructions to OpenGL
         // TODO: Move flush to separate thread
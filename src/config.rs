@@ -0,0 +1,3 @@
+/// Render settings read from a file while the program runs
+pub mod live_config;
+pub use self::live_config::{LiveConfig, LiveSettings, LIVE_CONFIG_FILE, parse_live_config};
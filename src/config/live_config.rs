@@ -0,0 +1,150 @@
+use gfx::Resources;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ::draw::UberInputs;
+use ::{Error, FlightError};
+
+/// The file `LiveConfig::new` reads, in the working directory
+pub const LIVE_CONFIG_FILE: &str = "vr_config.toml";
+
+/// The settings of a config file as `section.key` and value, in the order they appear
+pub type LiveSettings = Vec<(String, f32)>;
+
+/// Parse the subset of TOML used by config files: `[section]` headers, `key = number`
+/// lines and `#` comments. Keys before any header have no section.
+pub fn parse_live_config(text: &str) -> Result<LiveSettings, Error> {
+    let mut section = String::new();
+    let mut settings = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let invalid = |reason| FlightError::InvalidConfig { line: n + 1, reason: reason };
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue }
+        if line.starts_with('[') {
+            ensure!(line.ends_with(']') && line.len() > 2, invalid("unclosed section header"));
+            section = line[1..line.len() - 1].trim().to_owned();
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().ok_or_else(|| invalid("expected key = value"))?.trim();
+        ensure!(!key.is_empty(), invalid("missing key"));
+        let value: f32 = value.parse().map_err(|_| invalid("values must be numbers"))?;
+        let key = if section.is_empty() { key.to_owned() } else { format!("{}.{}", section, key) };
+        settings.push((key, value));
+    }
+    Ok(settings)
+}
+
+/// Render settings read from a file while the program runs, for tuning in the headset
+/// without rebuilding. The file is read again whenever its modification time changes, and
+/// a missing or broken file leaves the settings alone, so it is safe to keep polling in
+/// builds that ship without one.
+///
+/// ```toml
+/// [render]
+/// gamma = 2.2
+/// exposure_ev = 1.0
+/// shadow_depth_bias = 0.001
+/// ```
+///
+/// `render.gamma` goes to `UberInputs::set_gamma`, `render.exposure_ev` to
+/// `UberInputs::set_exposure` as `2^ev` (`render.exposure` sets the exposure directly) and
+/// `render.shadow_depth_bias` to `UberInputs::set_shadow_depth_bias`, which is warned about
+/// once since the bias has no effect yet. Other keys are warned about and ignored.
+pub struct LiveConfig {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    warned_bias: bool,
+}
+
+impl LiveConfig {
+    /// Watch `LIVE_CONFIG_FILE` in the working directory
+    pub fn new() -> LiveConfig {
+        LiveConfig::with_path(LIVE_CONFIG_FILE)
+    }
+
+    /// Watch the file at `path`
+    pub fn with_path<P: AsRef<Path>>(path: P) -> LiveConfig {
+        LiveConfig {
+            path: path.as_ref().to_owned(),
+            modified: None,
+            warned_bias: false,
+        }
+    }
+
+    /// The settings in the file if it has changed since it was last read (or this is the
+    /// first look at it), otherwise `None`. Parse errors are logged.
+    pub fn changed(&mut self) -> Option<LiveSettings> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) { return None }
+        self.modified = Some(modified);
+        let parsed = fs::read_to_string(&self.path)
+            .map_err(Error::from)
+            .and_then(|text| parse_live_config(&text));
+        match parsed {
+            Ok(settings) => Some(settings),
+            Err(e) => {
+                warn!("ignoring {}: {}", self.path.display(), e);
+                None
+            },
+        }
+    }
+
+    /// Apply the file to `inputs` if it has changed. Call once per frame, it only checks the
+    /// modification time otherwise. Returns true if settings were applied.
+    pub fn poll<R: Resources>(&mut self, inputs: &mut UberInputs<R>) -> bool {
+        let settings = match self.changed() {
+            Some(s) => s,
+            None => return false,
+        };
+        for (key, value) in settings {
+            match &key[..] {
+                "render.gamma" => inputs.set_gamma(value),
+                "render.exposure_ev" => inputs.set_exposure(2f32.powf(value)),
+                "render.exposure" => inputs.set_exposure(value),
+                "render.shadow_depth_bias" => {
+                    if !self.warned_bias {
+                        warn!("render.shadow_depth_bias has no effect until the uber style samples its shadow map");
+                        self.warned_bias = true;
+                    }
+                    inputs.set_shadow_depth_bias(value);
+                },
+                _ => warn!("{} has no setting {}", self.path.display(), key),
+            }
+        }
+        true
+    }
+}
+
+impl Default for LiveConfig {
+    fn default() -> LiveConfig {
+        LiveConfig::new()
+    }
+}
+
+#[test]
+fn live_config() {
+    let text = "# tuning\ntop = 1\n[render]\ngamma = 2.2 # display\n\nexposure_ev = -0.5\nshadow_depth_bias = 1e-3\n";
+    let settings = parse_live_config(text).unwrap();
+    assert_eq!(settings, vec![
+        ("top".to_owned(), 1.),
+        ("render.gamma".to_owned(), 2.2),
+        ("render.exposure_ev".to_owned(), -0.5),
+        ("render.shadow_depth_bias".to_owned(), 0.001),
+    ]);
+    assert!(parse_live_config("[render\n").is_err());
+    assert!(parse_live_config("gamma 2.2\n").is_err());
+    assert!(parse_live_config("gamma = \"high\"\n").is_err());
+
+    let path = ::std::env::temp_dir().join(format!("flight_live_config_{}.toml", ::std::process::id()));
+    let mut config = LiveConfig::with_path(&path);
+    // no file, no settings
+    assert!(config.changed().is_none());
+    fs::write(&path, text).unwrap();
+    assert_eq!(config.changed(), Some(settings));
+    // unchanged since the last read
+    assert!(config.changed().is_none());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,169 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+
+use super::{StyleInputs, Style, TransformBlock, FrameCounter};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The texture and opacity of a surface drawn by `AlphaHashStyle`
+#[derive(Clone, PartialEq)]
+pub struct AlphaHashMaterial<R: Resources> {
+    /// Color and opacity, in display space (like `UnlitMaterial`)
+    pub color: Texture<R, ColorFormat>,
+    /// Multiplied into the opacity of the texture
+    pub alpha: f32,
+}
+
+gfx_defines!{
+    constant AlphaHashBlock {
+        alpha: f32 = "alpha",
+        frame: i32 = "frame",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<AlphaHashBlock> = "alpha_hash",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        texture: gfx::TextureSampler<[f32; 4]> = "color_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX")
+        .define("TAN"),
+    fragment: static_file!("shaders/alpha_hash.f.glsl")
+        .define_to("I_TEX", "v_tex")
+});
+
+/// The configuration for alpha hashed rendering
+pub struct AlphaHashInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    params_block: Buffer<R, AlphaHashBlock>,
+    frames: FrameCounter,
+    fade: f32,
+}
+
+impl<R: Resources> AlphaHashInputs<R> {
+    /// Share the frame count of the draw context, so that the pattern of kept fragments
+    /// changes every frame. Usually given `DrawParams::frames`.
+    pub fn set_frame_counter(&mut self, frames: FrameCounter) {
+        self.frames = frames;
+    }
+
+    /// Multiply the opacity of every material drawn from now on by `fade`, without
+    /// touching the materials themselves. This is 1 unless set otherwise.
+    pub fn set_fade(&mut self, fade: f32) {
+        self.fade = fade;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for AlphaHashInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Pipeline data bound by `AlphaHashStyle`, along with the opacity of the material
+pub struct AlphaHashBound<R: Resources> {
+    data: pl::Data<R>,
+    alpha: f32,
+}
+
+/// Draws see-through surfaces without sorting by keeping a pseudo-random fraction of their
+/// fragments, as many as their opacity, and writing depth like opaque ones. Intersecting
+/// surfaces and draw order are handled exactly, but each frame alone is noisy. This
+/// requires temporal anti-aliasing to accumulate the frames into smooth transparency, and
+/// `set_frame_counter` so the pattern changes between them.
+pub struct AlphaHashStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for AlphaHashStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = AlphaHashInputs<R>;
+    type Material = AlphaHashMaterial<R>;
+    type Bound = AlphaHashBound<R>;
+
+    fn sampled(mat: &AlphaHashMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        vec![mat.color.buffer.raw().clone()]
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut AlphaHashInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(AlphaHashStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<AlphaHashInputs<R>, Error> {
+        Ok(AlphaHashInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            params_block: f.create_constant_buffer(1),
+            frames: FrameCounter::new(),
+            fade: 1.,
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &AlphaHashInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &AlphaHashMaterial<R>,
+    ) -> AlphaHashBound<R> {
+        AlphaHashBound {
+            data: pl::Data {
+                color: color,
+                depth: depth,
+                verts: buf,
+                scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+                transform: inputs.transform_block.clone(),
+                params: inputs.params_block.clone(),
+                texture: mat.color.clone().into_tuple(),
+            },
+            alpha: mat.alpha,
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut AlphaHashInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut AlphaHashBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        enc.update_constant_buffer(&inputs.params_block, &AlphaHashBlock {
+            alpha: bound.alpha * inputs.fade,
+            frame: inputs.frames.get() as i32,
+        });
+        bound.data.scissor = scissor;
+        enc.draw(slice, &self.pso, &bound.data);
+        Ok(())
+    }
+}
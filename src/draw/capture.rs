@@ -0,0 +1,268 @@
+use gfx::{self, Resources, CommandBuffer, Device, Factory, Rect};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, DepthStencilView, RenderTargetView, Sampler, ShaderResourceView};
+use gfx::memory::{Bind, Usage, Typed};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+use nalgebra::{Point3, Vector3, Perspective3, Transform3};
+use std::f32::consts::FRAC_PI_2;
+use std::path::{Path, PathBuf};
+
+use super::{DrawParams, EyeParams, Hdr32Image, double_x};
+use super::post::fullscreen_slice;
+use super::probe::{cube_face, face_view};
+use ::mesh::Primitive;
+use ::{Error, FlightError, ColorFormat, DepthFormat};
+
+/// The pixel format equirectangular images are resolved into
+pub type EquirectFormat = (R32_G32_B32_A32, Float);
+
+gfx_defines!{
+    constant EquirectBlock {
+        size: [f32; 2] = "size",
+        source_gamma: f32 = "source_gamma",
+    }
+
+    pipeline equirect {
+        params: gfx::ConstantBuffer<EquirectBlock> = "equirect_params",
+        color: gfx::RenderTarget<EquirectFormat> = "f_color",
+        source: gfx::TextureSampler<[f32; 4]> = "source_map",
+    }
+}
+
+shader!(equirect_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/equirect.f.glsl")
+});
+
+/// Renders stereo 360 degree images of a scene for VR video, as a pair of equirectangular
+/// images in linear light (longitude across, -z in the middle, and latitude up). Each eye
+/// draws the six faces of a cube, each face from eyes `ipd` apart across the direction it
+/// looks in, so the depth is right in the middle of each horizontal face but jumps at their
+/// edges. Looking straight up or down the eyes are in the same place, which keeps the poles
+/// comfortable. The scene is drawn into the usual color format, so the images hold no
+/// values above 1.
+pub struct Stereo360Capture<R: Resources> {
+    /// The distance between the eyes
+    pub ipd: f32,
+    /// The near and far clipping distances
+    pub clip: (f32, f32),
+    resolution: u16,
+    width: u16,
+    height: u16,
+    faces: Vec<RenderTargetView<R, ColorFormat>>,
+    face_depth: DepthStencilView<R, DepthFormat>,
+    cube: ShaderResourceView<R, [f32; 4]>,
+    sampler: Sampler<R>,
+    target: gfx::handle::Texture<R, R32_G32_B32_A32>,
+    target_view: RenderTargetView<R, EquirectFormat>,
+    download: Buffer<R, [f32; 4]>,
+    pso: PipelineState<R, equirect::Meta>,
+    params: Buffer<R, EquirectBlock>,
+    images: Option<(Hdr32Image, Hdr32Image)>,
+}
+
+impl<R: Resources> Stereo360Capture<R> {
+    /// Capture 4096 by 2048 images
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<Stereo360Capture<R>, Error> {
+        Stereo360Capture::with_size(f, 4096, 2048)
+    }
+
+    /// Capture images `width` by `height` pixels in size, usually twice as wide as tall. The
+    /// cube faces are a quarter of the width across, matching the detail around the horizon.
+    pub fn with_size<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16)
+        -> Result<Stereo360Capture<R>, Error>
+    {
+        use gfx::texture::*;
+        let resolution = (width / 4).max(8);
+        let cube = f.create_texture::<<ColorFormat as Formatted>::Surface>(
+            Kind::Cube(resolution),
+            1,
+            Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+            Usage::Data,
+            Some(<<ColorFormat as Formatted>::Channel as ChannelTyped>::get_channel_type()),
+        )?;
+        let faces = (0..6)
+            .map(|i| f.view_texture_as_render_target::<ColorFormat>(&cube, 0, Some(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let target = f.create_texture::<R32_G32_B32_A32>(
+            Kind::D2(width, height, AaMode::Single),
+            1,
+            Bind::RENDER_TARGET | Bind::TRANSFER_SRC,
+            Usage::Data,
+            Some(ChannelType::Float),
+        )?;
+        let shaders = equirect_shader(f)?;
+        Ok(Stereo360Capture {
+            ipd: 0.065,
+            clip: (0.05, 100.),
+            resolution: resolution,
+            width: width,
+            height: height,
+            faces: faces,
+            face_depth: f.create_depth_stencil_view_only::<DepthFormat>(resolution, resolution)?,
+            cube: f.view_texture_as_shader_resource::<ColorFormat>(&cube, (0, 0), Swizzle::new())?,
+            sampler: f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp)),
+            target_view: f.view_texture_as_render_target::<EquirectFormat>(&target, 0, None)?,
+            target: target,
+            download: f.create_download_buffer::<[f32; 4]>(width as usize * height as usize)?,
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), equirect::new())?,
+            params: f.create_constant_buffer(1),
+            images: None,
+        })
+    }
+
+    /// The size of the captured images in pixels
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// An eye at `origin` looking out of a cube face, moved half the eye distance to the
+    /// left (`side` -1) or right (`side` 1) of the way it looks
+    fn face_eye(&self, origin: &Point3<f32>, face: usize, side: f32) -> EyeParams {
+        let pos = origin + eye_offset(face, side, self.ipd);
+        let proj = double_x(Perspective3::new(1., FRAC_PI_2, self.clip.0, self.clip.1).to_homogeneous());
+        EyeParams {
+            eye: pos,
+            view: Transform3::from_matrix_unchecked(face_view(&pos, face)),
+            proj: Transform3::from_matrix_unchecked(proj),
+            clip_offset: 0.,
+            clip: Rect { x: 0, y: 0, w: self.resolution, h: self.resolution },
+        }
+    }
+
+    /// Draw the cube of one eye with `draw_scene` and resolve it into the equirectangular
+    /// target
+    fn capture_eye<C, S>(&self, ctx: &mut DrawParams<R, C>, origin: &Point3<f32>, side: f32, draw_scene: &mut S)
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, S: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        let saved = (ctx.color.clone(), ctx.depth.clone());
+        let mut result = Ok(());
+        for face in 0..6 {
+            ctx.encoder.clear(&self.faces[face], [0., 0., 0., 1.]);
+            ctx.encoder.clear_depth(&self.face_depth, super::DEPTH_CONVENTION.far());
+            ctx.color = self.faces[face].clone();
+            ctx.depth = self.face_depth.clone();
+            ctx.push_eye(self.face_eye(origin, face, side));
+            result = draw_scene(ctx);
+            ctx.pop_camera();
+            if result.is_err() { break }
+        }
+        ctx.color = saved.0;
+        ctx.depth = saved.1;
+        result?;
+
+        profile_scope!("equirect");
+        ctx.encoder.update_constant_buffer(&self.params, &EquirectBlock {
+            size: [self.width as f32, self.height as f32],
+            source_gamma: ::OUTPUT_GAMMA,
+        });
+        ctx.encoder.draw(&fullscreen_slice(), &self.pso, &equirect::Data {
+            params: self.params.clone(),
+            color: self.target_view.clone(),
+            source: (self.cube.clone(), self.sampler.clone()),
+        });
+        ctx.draw_calls += 1;
+        Ok(())
+    }
+
+    /// Read the equirectangular target back, top row first
+    fn read_back<F, C, D>(&self, f: &mut F, ctx: &mut DrawParams<R, C>, device: &mut D) -> Result<Hdr32Image, Error>
+        where F: Factory<R>, C: CommandBuffer<R>, D: Device<Resources = R, CommandBuffer = C>
+    {
+        let info = self.target.get_info().to_raw_image_info(EquirectFormat::get_format().1, 0);
+        ctx.encoder.copy_texture_to_buffer_raw(self.target.raw(), None, info, self.download.raw(), 0)
+            .map_err(|e| FlightError::TextureCopy { reason: format!("{:?}", e) })?;
+        ctx.encoder.flush(device);
+        let reader = f.read_mapping(&self.download)?;
+        let width = self.width as usize;
+        let mut data = Vec::with_capacity(width * self.height as usize);
+        // textures are stored bottom row first
+        for row in reader.chunks(width).rev() {
+            data.extend(row.iter().map(|p| [p[0], p[1], p[2]]));
+        }
+        Ok(Hdr32Image {
+            width: self.width as u32,
+            height: self.height as u32,
+            data: data,
+        })
+    }
+
+    /// Draw the scene around `origin` with `draw_scene` six times for each eye, through
+    /// the draw parameters redirected to the cube faces, and read back the left and right
+    /// images. This flushes the encoder and waits for the GPU, so it is meant for
+    /// rendering video offline rather than while the headset shows the scene.
+    pub fn capture<F, C, D, S>(
+        &mut self,
+        f: &mut F,
+        ctx: &mut DrawParams<R, C>,
+        device: &mut D,
+        origin: Point3<f32>,
+        mut draw_scene: S,
+    )
+        -> Result<(&Hdr32Image, &Hdr32Image), Error>
+        where
+            F: Factory<R>,
+            C: CommandBuffer<R>,
+            D: Device<Resources = R, CommandBuffer = C>,
+            S: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>,
+    {
+        profile_scope!("stereo_360");
+        self.images = None;
+        self.capture_eye(ctx, &origin, -1., &mut draw_scene)?;
+        let left = self.read_back(f, ctx, device)?;
+        self.capture_eye(ctx, &origin, 1., &mut draw_scene)?;
+        let right = self.read_back(f, ctx, device)?;
+        self.images = Some((left, right));
+        match self.images {
+            Some((ref left, ref right)) => Ok((left, right)),
+            None => unreachable!(),
+        }
+    }
+
+    /// The paths `save_pair` writes to: `path_base` with `_left.hdr` and `_right.hdr` added
+    pub fn pair_paths(path_base: &Path) -> (PathBuf, PathBuf) {
+        let base = path_base.to_string_lossy();
+        (PathBuf::from(format!("{}_left.hdr", base)), PathBuf::from(format!("{}_right.hdr", base)))
+    }
+
+    /// Save the last captured images as Radiance files (see `pair_paths`)
+    pub fn save_pair(&self, path_base: &Path) -> Result<(), Error> {
+        let (left, right) = self.images.as_ref()
+            .ok_or(FlightError::NotCaptured { what: "stereo 360 capture" })?;
+        let (left_path, right_path) = Stereo360Capture::<R>::pair_paths(path_base);
+        left.save_hdr(left_path)?;
+        right.save_hdr(right_path)?;
+        Ok(())
+    }
+}
+
+/// How far the eye looking out of a cube face is moved from the capture origin, half the
+/// eye distance to the left (`side` -1) or right (`side` 1) of the way it looks
+fn eye_offset(face: usize, side: f32, ipd: f32) -> Vector3<f32> {
+    let (forward, _) = cube_face(face);
+    // zero for the faces looking up and down
+    forward.cross(&Vector3::y()) * side * ipd * 0.5
+}
+
+#[test]
+fn capture_eye_offsets() {
+    for face in 0..6 {
+        let (forward, _) = cube_face(face);
+        let (left, right) = (eye_offset(face, -1., 0.1), eye_offset(face, 1., 0.1));
+        if forward.y.abs() > 0.5 {
+            // the eyes meet looking up and down
+            assert!(relative_eq!(left, Vector3::zeros()));
+            assert!(relative_eq!(right, Vector3::zeros()));
+        } else {
+            // otherwise they are apart across the view, at eye level
+            assert!(relative_eq!((right - left).norm(), 0.1));
+            assert!(relative_eq!(right.dot(&forward), 0.));
+            assert!(relative_eq!(right.y, 0.));
+            // and the right eye is on the right
+            assert!(forward.cross(&Vector3::y()).dot(&right) > 0.);
+        }
+    }
+}
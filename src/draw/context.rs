@@ -1,7 +1,312 @@
-use gfx::{Rect, Encoder, Resources, CommandBuffer};
-use nalgebra::{self as na, Transform3, Point3};
+use gfx::{self, Rect, Encoder, Resources, CommandBuffer, Device, Factory, Primitive};
+use gfx::handle::{Buffer, RawRenderTargetView, RawShaderResourceView, ShaderResourceView};
+use gfx::format::{R32_G32_B32, R8_G8_B8_A8, Float};
+use gfx::memory::Typed;
+use gfx::traits::FactoryExt;
+use nalgebra::{self as na, Transform3, Point3, Matrix4, Vector3, Vector4};
+use fnv::FnvHashMap;
+use image::{Rgb, RgbaImage};
+use image::hdr::HDREncoder;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
 
-use ::{DepthRef, TargetRef};
+use super::{Painter, Style, OffscreenTarget};
+#[cfg(feature = "draw-inspector")]
+use gfx::Slice;
+#[cfg(feature = "draw-inspector")]
+use gfx::handle::{RawBuffer, RawDepthStencilView};
+#[cfg(feature = "draw-inspector")]
+use super::TransformBlock;
+use ::mesh::Mesh;
+use ::math::frustum::{extract_frustum_planes, merge_frustum_planes};
+use ::{DepthRef, TargetRef, Error, FlightError};
+
+/// Shades the periphery of each eye, which the lens compresses, at half rate. Outside a
+/// circle around the eye viewport center, every other pixel in a checkerboard is skipped,
+/// ramping in over `feather` pixels so the edge doesn't show. The skipped pixels are filled
+/// in by `post::LensResolve`. Only the uber, PBR and unlit styles skip pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensShading {
+    /// The radius of the full rate circle, as a fraction of half the viewport diagonal.
+    /// 1 or more shades everything at full rate.
+    pub radius: f32,
+    /// The width of the transition to half rate, in pixels
+    pub feather: f32,
+}
+
+impl LensShading {
+    /// Shade every pixel
+    pub fn off() -> LensShading {
+        LensShading { radius: 1., feather: 0. }
+    }
+
+    /// Only the far corners are shaded at half rate
+    pub fn quality() -> LensShading {
+        LensShading { radius: 0.8, feather: 32. }
+    }
+
+    /// A balance suited to most headsets
+    pub fn balanced() -> LensShading {
+        LensShading { radius: 0.65, feather: 24. }
+    }
+
+    /// Everything outside the sharpest part of the lens is shaded at half rate
+    pub fn performance() -> LensShading {
+        LensShading { radius: 0.5, feather: 16. }
+    }
+
+    /// True if any pixels are skipped
+    pub fn enabled(&self) -> bool {
+        self.radius < 1.
+    }
+
+    /// The center, full rate radius and feather of an eye viewport in pixels, for shaders.
+    /// The radius is 0 when nothing is skipped.
+    pub fn block(&self, clip: Rect) -> [f32; 4] {
+        if !self.enabled() {
+            return [0.; 4];
+        }
+        let (w, h) = (clip.w as f32, clip.h as f32);
+        let half_diagonal = (w * w + h * h).sqrt() / 2.;
+        [
+            clip.x as f32 + w / 2.,
+            clip.y as f32 + h / 2.,
+            (self.radius * half_diagonal).max(1.),
+            self.feather.max(1.),
+        ]
+    }
+
+    /// The fraction of a viewport's pixels that are shaded, to estimate the savings of a
+    /// setting (gfx has no GPU timers to measure them directly)
+    pub fn shaded_fraction(&self, width: u16, height: u16) -> f32 {
+        let lens = self.block(Rect { x: 0, y: 0, w: width, h: height });
+        if lens[2] == 0. || width == 0 || height == 0 {
+            return 1.;
+        }
+        // the same test as the shaders, on a coarse grid
+        let mut skipped = 0.;
+        let step = 4;
+        let mut count = 0.;
+        for y in (0..height).step_by(step) {
+            for x in (0..width).step_by(step) {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let r = ((px - lens[0]).powi(2) + (py - lens[1]).powi(2)).sqrt();
+                let t = ((r - lens[2]) / lens[3]).max(0.).min(1.);
+                // half of the pixels are on the checkerboard, and the dither thresholds
+                // are spread evenly from 1/8 to 7/8
+                skipped += 0.5 * ((t * 4. - 0.5).ceil().max(0.).min(4.) / 4.);
+                count += 1.;
+            }
+        }
+        1. - skipped / count
+    }
+}
+
+impl Default for LensShading {
+    fn default() -> LensShading {
+        LensShading::off()
+    }
+}
+
+/// The constant `C` of `LogDepth`. Larger values move precision towards the camera. At 1,
+/// a 24 bit depth buffer with the far plane at 1 km tells apart surfaces about 0.4 mm
+/// apart at 1 km and a micrometer apart at 1 m.
+pub const LOG_DEPTH_C: f32 = 1.;
+
+/// Logarithmic depth, which spreads the precision of the depth buffer evenly over orders of
+/// magnitude of distance instead of crowding it at the near plane. This stops distant
+/// surfaces z-fighting in large outdoor scenes (1 mm to 1 km). A point `w` along the view
+/// axis is drawn at depth `log2(C * w + 1) / log2(C * far + 1)`, where `C` is
+/// `LOG_DEPTH_C`.
+///
+/// The near plane of the projection no longer costs precision, so it should be small, such
+/// as 1 mm. Depth is computed per vertex and interpolated, so large triangles crossing
+/// close to the camera can be depth tested slightly wrong and should be split up. The water
+/// and soft particle shaders undo the encoding when they read the depth buffer back.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LogDepth {
+    /// The distance to the far plane, 0 for standard depth
+    pub far: f32,
+}
+
+impl LogDepth {
+    /// Standard depth from the projection
+    pub fn off() -> LogDepth {
+        LogDepth { far: 0. }
+    }
+
+    /// Logarithmic depth out to `far`
+    pub fn new(far: f32) -> LogDepth {
+        LogDepth { far: far }
+    }
+
+    /// True if depth is logarithmic
+    pub fn enabled(&self) -> bool {
+        self.far > 0.
+    }
+
+    /// `log2(C * far + 1)` for shaders, 0 when depth is standard
+    pub fn block(&self) -> f32 {
+        if !self.enabled() {
+            return 0.;
+        }
+        (LOG_DEPTH_C * self.far + 1.).log2()
+    }
+
+    /// The depth buffer value of a point `w` along the view axis, while enabled
+    pub fn depth(&self, w: f32) -> f32 {
+        (LOG_DEPTH_C * w.max(0.) + 1.).log2() / self.block()
+    }
+
+    /// The distance along the view axis of a depth buffer value, the inverse of `depth`
+    pub fn distance(&self, depth: f32) -> f32 {
+        ((depth * self.block()).exp2() - 1.) / LOG_DEPTH_C
+    }
+}
+
+impl Default for LogDepth {
+    fn default() -> LogDepth {
+        LogDepth::off()
+    }
+}
+
+/// Which end of the depth buffer is near the viewer
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DepthConvention {
+    /// The near plane is drawn at depth 0 and the far plane at 1
+    Standard,
+    /// The near plane is drawn at depth 1 and the far plane at 0. With float depth
+    /// (`depth-32f`) the precision of floats near 0 makes up for the perspective crowding
+    /// precision at the near plane, so depth is about as precise everywhere. OpenGL only
+    /// gets the full benefit with a 0 to 1 clip space depth range, which gfx doesn't set.
+    ReverseZ,
+}
+
+impl DepthConvention {
+    /// The depth buffer value of the far plane, which depth targets are cleared to
+    pub fn far(self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.,
+            DepthConvention::ReverseZ => 0.,
+        }
+    }
+
+    /// The comparison that passes like `fun` does with standard depth
+    pub fn comparison(self, fun: gfx::state::Comparison) -> gfx::state::Comparison {
+        use gfx::state::Comparison::*;
+        match (self, fun) {
+            (DepthConvention::Standard, _) => fun,
+            (_, Less) => Greater,
+            (_, LessEqual) => GreaterEqual,
+            (_, Greater) => Less,
+            (_, GreaterEqual) => LessEqual,
+            (_, other) => other,
+        }
+    }
+
+    /// `proj` changed to draw depth with this convention
+    pub fn projection(self, proj: &Matrix4<f32>) -> Matrix4<f32> {
+        match self {
+            DepthConvention::Standard => *proj,
+            DepthConvention::ReverseZ => {
+                // negating clip space z swaps the near and far ends of the depth range
+                let mut flip = Matrix4::identity();
+                flip[(2, 2)] = -1.;
+                flip * proj
+            },
+        }
+    }
+
+    /// The depth of `depth` in the standard convention
+    pub fn standard_depth(self, depth: f32) -> f32 {
+        match self {
+            DepthConvention::Standard => depth,
+            DepthConvention::ReverseZ => 1. - depth,
+        }
+    }
+}
+
+/// The depth convention of every draw, `ReverseZ` with the `reverse-z` feature. It is fixed
+/// when the crate is built because the depth test of each pipeline is.
+#[cfg(not(feature = "reverse-z"))]
+pub const DEPTH_CONVENTION: DepthConvention = DepthConvention::Standard;
+#[cfg(feature = "reverse-z")]
+pub const DEPTH_CONVENTION: DepthConvention = DepthConvention::ReverseZ;
+
+/// The depth test of pipelines that draw opaque surfaces, passing nearer or equal depth
+#[cfg(not(feature = "reverse-z"))]
+pub const DEPTH_WRITE: gfx::state::Depth = gfx::preset::depth::LESS_EQUAL_WRITE;
+#[cfg(feature = "reverse-z")]
+pub const DEPTH_WRITE: gfx::state::Depth = gfx::state::Depth {
+    fun: gfx::state::Comparison::GreaterEqual,
+    write: true,
+};
+/// The depth test of pipelines that draw behind opaque surfaces without writing depth
+#[cfg(not(feature = "reverse-z"))]
+pub const DEPTH_TEST: gfx::state::Depth = gfx::preset::depth::LESS_EQUAL_TEST;
+#[cfg(feature = "reverse-z")]
+pub const DEPTH_TEST: gfx::state::Depth = gfx::state::Depth {
+    fun: gfx::state::Comparison::GreaterEqual,
+    write: false,
+};
+
+/// The most depth biases a `Painter` sets up pipelines for, see `Painter::setup_depth_bias`
+pub const MAX_DEPTH_BIAS_VARIANTS: usize = 8;
+
+/// A polygon offset for drawing a surface over another in the same plane, such as a floor
+/// marking or decal, without the two fighting for depth. Positive values pull the surface
+/// towards the viewer: `constant` in steps of the depth buffer and `slope_scaled` in
+/// multiples of how much depth changes across a pixel, which keeps surfaces seen at grazing
+/// angles apart.
+///
+/// Depth is offset after projection, so with `ReverseZ` the sign of the offset is flipped to
+/// keep pulling towards the viewer. The steps are also of a different size there: with float
+/// depth a step is relative to the depth value, which is large near the viewer, so the same
+/// `constant` separates near surfaces more and far ones less than with standard depth.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub struct DepthBias {
+    pub constant: i32,
+    pub slope_scaled: i32,
+}
+
+impl DepthBias {
+    pub fn new(constant: i32, slope_scaled: i32) -> DepthBias {
+        DepthBias {
+            constant: constant,
+            slope_scaled: slope_scaled,
+        }
+    }
+
+    /// No offset, drawn by the usual pipelines
+    pub fn none() -> DepthBias {
+        DepthBias::default()
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == DepthBias::none()
+    }
+
+    /// The rasterizer offset in the crate's depth convention
+    pub fn offset(&self) -> Option<gfx::state::Offset> {
+        if self.is_none() { return None }
+        let sign = match DEPTH_CONVENTION {
+            DepthConvention::Standard => -1,
+            DepthConvention::ReverseZ => 1,
+        };
+        Some(gfx::state::Offset(sign * self.slope_scaled, sign * self.constant))
+    }
+
+    /// A filled rasterizer with this offset, for creating pipelines
+    pub fn rasterizer(&self) -> gfx::state::Rasterizer {
+        gfx::state::Rasterizer {
+            offset: self.offset(),
+            .. gfx::state::Rasterizer::new_fill()
+        }
+    }
+}
 
 /// Parameters that control the rendering of an eye
 #[derive(Copy, Clone)]
@@ -13,6 +318,15 @@ pub struct EyeParams {
     pub clip: Rect,
 }
 
+impl EyeParams {
+    /// The world space to clip space matrix of the eye, without the halving of x done by
+    /// the transform shader, so its frustum is the volume the eye sees
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        let half = Matrix4::new_nonuniform_scaling(&Vector3::new(0.5, 1., 1.));
+        half * self.proj.matrix() * self.view.matrix()
+    }
+}
+
 impl Default for EyeParams {
     fn default() -> EyeParams {
         EyeParams {
@@ -25,6 +339,601 @@ impl Default for EyeParams {
     }
 }
 
+/// Lifecycle events of the renderer
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RenderEvent {
+    /// A new frame is about to be drawn
+    FrameBegin,
+    /// A frame has been completely recorded
+    FrameEnd,
+    /// The shader with the given name was rebuilt. Nothing fires this yet, since shaders
+    /// are only built when a style is created.
+    ShaderReloaded(&'static str),
+    /// The draw targets were recreated with the given width and height
+    TargetsResized(u16, u16),
+}
+
+impl RenderEvent {
+    fn kind(&self) -> usize {
+        use self::RenderEvent::*;
+        match *self {
+            FrameBegin => 0,
+            FrameEnd => 1,
+            ShaderReloaded(_) => 2,
+            TargetsResized(..) => 3,
+        }
+    }
+}
+
+/// The information given to render event handlers
+#[derive(Copy, Clone, Debug)]
+pub struct RenderEventData {
+    /// The event that occurred
+    pub event: RenderEvent,
+    /// The number of frames begun before this event
+    pub frame: u64,
+}
+
+/// Calls subscribed handlers when render events occur, so that subsystems can react
+/// to frame boundaries, shader reloads and resizes without the main loop knowing about them.
+#[derive(Default)]
+pub struct RenderEventBus {
+    handlers: [Vec<Box<dyn FnMut(&RenderEventData)>>; 4],
+    frame: u64,
+}
+
+impl RenderEventBus {
+    /// Create a bus without any handlers
+    pub fn new() -> RenderEventBus {
+        Default::default()
+    }
+
+    /// Call the given handler every time an event of the same kind occurs. For events
+    /// carrying data (such as `TargetsResized`), the data given here is ignored.
+    pub fn subscribe(&mut self, event: RenderEvent, handler: Box<dyn FnMut(&RenderEventData)>) {
+        self.handlers[event.kind()].push(handler);
+    }
+
+    /// Call all the handlers subscribed to the given event
+    pub fn fire(&mut self, event: RenderEvent) {
+        if event == RenderEvent::FrameBegin {
+            self.frame += 1;
+        }
+        let data = RenderEventData {
+            event: event,
+            frame: self.frame,
+        };
+        for h in &mut self.handlers[event.kind()] {
+            h(&data);
+        }
+    }
+
+    /// The number of frames begun so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+/// The number of frames begun so far. Clones share the same count, so anything holding
+/// one sees the frame advance when `DrawParams::begin_frame` is called.
+#[derive(Clone, Debug, Default)]
+pub struct FrameCounter(Rc<Cell<u64>>);
+
+impl FrameCounter {
+    /// Create a counter starting at frame 0
+    pub fn new() -> FrameCounter {
+        Default::default()
+    }
+
+    /// The current frame index
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Move on to the next frame
+    pub fn advance(&self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+/// The number of buffers a `FrameRingBuffer` cycles through by default
+pub const FRAME_RING_SIZE: usize = 3;
+
+/// A set of constant buffers used in turn, one per frame, so that the CPU can write the
+/// next frame's data while the GPU is still reading a buffer from an earlier frame.
+pub struct FrameRingBuffer<R: Resources, T> {
+    buffers: Vec<Buffer<R, T>>,
+    counter: FrameCounter,
+}
+
+impl<R: Resources, T: Copy> FrameRingBuffer<R, T> {
+    /// Create `FRAME_RING_SIZE` constant buffers, selected by the given counter
+    pub fn new<F: Factory<R>>(f: &mut F, counter: FrameCounter) -> FrameRingBuffer<R, T> {
+        FrameRingBuffer::with_size(f, counter, FRAME_RING_SIZE)
+    }
+
+    /// Create `size` constant buffers (at least one), selected by the given counter
+    pub fn with_size<F: Factory<R>>(f: &mut F, counter: FrameCounter, size: usize) -> FrameRingBuffer<R, T> {
+        FrameRingBuffer {
+            buffers: (0..size.max(1)).map(|_| f.create_constant_buffer(1)).collect(),
+            counter: counter,
+        }
+    }
+
+    /// The buffer to use for the current frame
+    pub fn current(&self) -> &Buffer<R, T> {
+        &self.buffers[(self.counter.get() % self.buffers.len() as u64) as usize]
+    }
+
+    /// The frame the buffer returned by `current` belongs to
+    pub fn frame(&self) -> u64 {
+        self.counter.get()
+    }
+
+    /// Select buffers with a different counter, usually `DrawParams::frames`
+    pub fn set_counter(&mut self, counter: FrameCounter) {
+        self.counter = counter;
+    }
+
+    /// The number of buffers cycled through
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Always false, since a ring holds at least one buffer
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+/// Constant buffers mapped into CPU memory for as long as they live, written in place by
+/// `write_direct` instead of being copied through the driver like `update_constant_buffer`
+/// does. Each write takes a buffer of its own, since a mapped buffer holds whatever was
+/// written last when the GPU gets to it, and buffers are reused `FRAME_RING_SIZE` frames
+/// later like a `FrameRingBuffer`. Backends that can't map buffers for writing get plain
+/// constant buffers updated through the encoder.
+pub struct PersistentBuffer<R: Resources, T> {
+    frames: Vec<Vec<Buffer<R, T>>>,
+    used: usize,
+    frame: u64,
+    counter: FrameCounter,
+    persistent: bool,
+}
+
+impl<R: Resources, T: Copy> PersistentBuffer<R, T> {
+    /// Create an empty pool, finding out whether the backend can map buffers
+    pub fn new<F: Factory<R>>(f: &mut F, counter: FrameCounter) -> PersistentBuffer<R, T> {
+        use gfx::memory::{Bind, Usage};
+        let first = f.create_buffer(1, gfx::buffer::Role::Constant, Usage::Upload, Bind::empty()).ok();
+        let mut frames = vec![Vec::new(); FRAME_RING_SIZE];
+        let persistent = first.is_some();
+        frames[(counter.get() % FRAME_RING_SIZE as u64) as usize].extend(first);
+        PersistentBuffer {
+            frames: frames,
+            used: 0,
+            frame: counter.get(),
+            counter: counter,
+            persistent: persistent,
+        }
+    }
+
+    /// Whether writes go straight into mapped memory, rather than through the encoder
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Write `val` into a buffer not in use by the GPU and return it for binding. Without
+    /// persistent mapping the update is recorded into `enc` instead.
+    pub fn write_direct<F, C>(&mut self, f: &mut F, enc: &mut Encoder<R, C>, val: &T) -> Result<Buffer<R, T>, Error>
+        where F: Factory<R>, C: CommandBuffer<R>
+    {
+        use gfx::memory::{Bind, Usage};
+        let frame = self.counter.get();
+        if frame != self.frame {
+            self.frame = frame;
+            self.used = 0;
+        }
+        let ring = (frame % self.frames.len() as u64) as usize;
+        if self.used == self.frames[ring].len() {
+            let usage = if self.persistent { Usage::Upload } else { Usage::Dynamic };
+            let buf = f.create_buffer(1, gfx::buffer::Role::Constant, usage, Bind::empty())?;
+            self.frames[ring].push(buf);
+        }
+        let buf = self.frames[ring][self.used].clone();
+        self.used += 1;
+        if self.persistent {
+            f.write_mapping(&buf)?[0] = *val;
+        } else {
+            enc.update_constant_buffer(&buf, val);
+        }
+        Ok(buf)
+    }
+
+    /// Select buffers with a different counter, usually `DrawParams::frames`
+    pub fn set_counter(&mut self, counter: FrameCounter) {
+        self.counter = counter;
+    }
+
+    /// The number of buffers created so far
+    pub fn len(&self) -> usize {
+        self.frames.iter().map(|f| f.len()).sum()
+    }
+
+    /// True if no buffers have been created
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// One indexed draw, laid out like the arguments of `glDrawElementsIndirect`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DrawIndexedCommand {
+    /// The number of indices to draw
+    pub index_count: u32,
+    /// The number of instances to draw
+    pub instance_count: u32,
+    /// The first index to draw
+    pub first_index: u32,
+    /// Added to every index before reading the vertex buffer
+    pub base_vertex: u32,
+    /// The first instance to draw
+    pub base_instance: u32,
+}
+
+/// A list of draws of objects sharing one vertex and index buffer, filled after culling
+/// and drawn with `Painter::paint_draw_list`. The list is kept on the CPU and each command
+/// is still its own draw call for each eye, since gfx has no indirect or multi-draw call,
+/// but the pipeline data is bound once and no per-object transforms are set.
+pub struct DrawList {
+    /// The primitive type of every command
+    pub prim: Primitive,
+    commands: Vec<DrawIndexedCommand>,
+}
+
+impl DrawList {
+    /// Create an empty command list
+    pub fn new(prim: Primitive) -> DrawList {
+        DrawList {
+            prim: prim,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Add a draw of `index_count` indices starting at `first_index`. The object's
+    /// vertices start at `vertex_offset` in the shared buffer and its indices are relative
+    /// to `base_vertex` within them.
+    pub fn push(&mut self, vertex_offset: u32, index_count: u32, first_index: u32, base_vertex: u32, instance_count: u32) {
+        if index_count == 0 || instance_count == 0 {
+            return;
+        }
+        self.commands.push(DrawIndexedCommand {
+            index_count: index_count,
+            instance_count: instance_count,
+            first_index: first_index,
+            base_vertex: vertex_offset + base_vertex,
+            base_instance: 0,
+        });
+    }
+
+    /// The recorded commands
+    pub fn commands(&self) -> &[DrawIndexedCommand] {
+        &self.commands
+    }
+
+    /// The number of recorded commands
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// True if no commands have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Remove every command, usually before culling the next frame
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+}
+
+/// The world space bounds of one object tested by a `FrustumCuller`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ObjectData {
+    /// The world space corner of the bounds with the smallest coordinates
+    pub aabb_min: [f32; 3],
+    /// The world space corner of the bounds with the largest coordinates
+    pub aabb_max: [f32; 3],
+    /// The draw registered with `FrustumCuller::add_draw` that shows this object
+    pub draw_index: u32,
+}
+
+/// Builds the draw list for large numbers of objects sharing one vertex and index buffer,
+/// keeping the draw of each object whose bounds touch the view. The bounds are tested on
+/// the CPU, since gfx exposes no compute pipelines, and the surviving draws are written
+/// to a `DrawList`.
+pub struct FrustumCuller {
+    draws: Vec<DrawIndexedCommand>,
+}
+
+impl FrustumCuller {
+    /// Create a culler without any draws
+    pub fn new() -> FrustumCuller {
+        FrustumCuller {
+            draws: Vec::new(),
+        }
+    }
+
+    /// Register the draw of an object, returning the `draw_index` to give it in `ObjectData`
+    pub fn add_draw(&mut self, draw: DrawIndexedCommand) -> u32 {
+        self.draws.push(draw);
+        self.draws.len() as u32 - 1
+    }
+
+    /// The registered draws
+    pub fn draws(&self) -> &[DrawIndexedCommand] {
+        &self.draws
+    }
+
+    /// Test each object against the frustum planes (from `extract_frustum_planes` or
+    /// `DrawParams::stereo_frustum_planes`) and append the draws of the visible ones to
+    /// `output`, returning the number appended. Nothing is appended if an object names a
+    /// draw that was never registered.
+    pub fn run(&self, objects: &[ObjectData], frustum_planes: &[Vector4<f32>; 6], output: &mut DrawList)
+        -> Result<usize, Error>
+    {
+        let before = output.len();
+        for o in objects {
+            let d = match self.draws.get(o.draw_index as usize) {
+                Some(d) => d,
+                None => {
+                    output.commands.truncate(before);
+                    return Err(FlightError::IndexOutOfRange {
+                        what: "draw",
+                        index: o.draw_index as usize,
+                        len: self.draws.len(),
+                    }.into())
+                },
+            };
+            let outside = frustum_planes.iter().any(|p| {
+                // the corner furthest along the plane normal
+                let c = Vector4::new(
+                    if p.x >= 0. { o.aabb_max[0] } else { o.aabb_min[0] },
+                    if p.y >= 0. { o.aabb_max[1] } else { o.aabb_min[1] },
+                    if p.z >= 0. { o.aabb_max[2] } else { o.aabb_min[2] },
+                    1.,
+                );
+                p.dot(&c) < 0.
+            });
+            if !outside {
+                output.push(0, d.index_count, d.first_index, d.base_vertex, d.instance_count);
+            }
+        }
+        Ok(output.len() - before)
+    }
+}
+
+impl Default for FrustumCuller {
+    fn default() -> FrustumCuller {
+        FrustumCuller::new()
+    }
+}
+
+/// Remembers which texture views show the contents of which render targets, so that draws
+/// sampling the target they are drawing into (such as last frame's target bound as this
+/// frame's input without swapping) fail with `FlightError::ResourceStateConflict` instead
+/// of reading undefined results. Targets are only checked once registered, and nothing is
+/// checked while none are.
+pub struct ResourceStateTracker<R: Resources> {
+    views: FnvHashMap<RawShaderResourceView<R>, (RawRenderTargetView<R>, &'static str)>,
+}
+
+impl<R: Resources> ResourceStateTracker<R> {
+    /// A tracker with no registered targets
+    pub fn new() -> ResourceStateTracker<R> {
+        ResourceStateTracker {
+            views: FnvHashMap::default(),
+        }
+    }
+
+    /// Check draws against the color target of an offscreen target, calling it `name` in
+    /// errors
+    pub fn register(&mut self, name: &'static str, target: &OffscreenTarget<R>) {
+        self.register_view(name, target.color.raw(), target.texture.buffer.raw());
+    }
+
+    /// Check draws against a render target that `view` samples the contents of
+    pub fn register_view(&mut self, name: &'static str, target: &RawRenderTargetView<R>, view: &RawShaderResourceView<R>) {
+        self.views.insert(view.clone(), (target.clone(), name));
+    }
+
+    /// Stop checking draws against the targets sampled by `view`
+    pub fn unregister(&mut self, view: &RawShaderResourceView<R>) {
+        self.views.remove(view);
+    }
+
+    /// True if no targets are registered
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// Fail if any of the sampled views shows the contents of `target`
+    pub fn check(&self, target: &RawRenderTargetView<R>, sampled: &[RawShaderResourceView<R>]) -> Result<(), Error> {
+        for view in sampled {
+            if let Some(&(ref written, name)) = self.views.get(view) {
+                ensure!(written != target, FlightError::ResourceStateConflict { target: name });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Resources> Default for ResourceStateTracker<R> {
+    fn default() -> ResourceStateTracker<R> {
+        ResourceStateTracker::new()
+    }
+}
+
+/// The pipeline state of one draw call, taken by `DrawCallInspector::dump`. Handles are
+/// given in their debug formatting, which for OpenGL includes the object names shown by
+/// driver tools.
+#[cfg(feature = "draw-inspector")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawCallSnapshot {
+    /// The frame of the draw, from `DrawParams::frames`
+    pub frame: u64,
+    /// The number of draw calls before it in the frame
+    pub index: usize,
+    /// The type of the style that drew it
+    pub style: &'static str,
+    pub primitive: Primitive,
+    /// The `Painter::pso_key` of the pipeline state
+    pub pso_key: u64,
+    pub color_target: String,
+    pub depth_target: String,
+    /// The vertex buffer, unknown for draws from a `BakedScene`
+    pub vertex_buffer: Option<String>,
+    /// The textures of the material (see `Style::sampled`), unknown for draws from a
+    /// `BakedScene`
+    pub textures: Option<Vec<String>>,
+    pub transform: TransformBlock,
+    pub scissor: Rect,
+    /// The range of vertices, or indices if the draw was indexed
+    pub range: (u32, u32),
+    pub base_vertex: u32,
+    pub instances: u32,
+}
+
+#[cfg(feature = "draw-inspector")]
+impl DrawCallSnapshot {
+    /// The snapshot as JSON, for saving alongside a bug report
+    pub fn to_json(&self) -> ::serde_json::Value {
+        let t = &self.transform;
+        json!({
+            "frame": self.frame,
+            "index": self.index,
+            "style": self.style,
+            "primitive": format!("{:?}", self.primitive),
+            "pso_key": self.pso_key,
+            "color_target": self.color_target,
+            "depth_target": self.depth_target,
+            "vertex_buffer": self.vertex_buffer,
+            "textures": self.textures,
+            "transform": {
+                "model": t.model,
+                "view": t.view,
+                "proj": t.proj,
+                "eye": t.eye,
+                "lens": t.lens,
+                "clip_offset": t.clip_offset,
+                "log_depth": t.log_depth,
+            },
+            "scissor": [self.scissor.x, self.scissor.y, self.scissor.w, self.scissor.h],
+            "range": [self.range.0, self.range.1],
+            "base_vertex": self.base_vertex,
+            "instances": self.instances,
+        })
+    }
+}
+
+/// The pipeline a `Painter` is about to draw with
+#[cfg(feature = "draw-inspector")]
+#[derive(Clone)]
+struct InspectedPipeline<R: Resources> {
+    style: &'static str,
+    primitive: Primitive,
+    pso_key: u64,
+    vertex_buffer: Option<RawBuffer<R>>,
+    textures: Option<Vec<RawShaderResourceView<R>>>,
+}
+
+#[cfg(feature = "draw-inspector")]
+struct InspectedDraw<R: Resources> {
+    pipeline: InspectedPipeline<R>,
+    frame: u64,
+    index: usize,
+    color: RawRenderTargetView<R>,
+    depth: RawDepthStencilView<R>,
+    transform: TransformBlock,
+    scissor: Rect,
+    range: (u32, u32),
+    base_vertex: u32,
+    instances: u32,
+}
+
+/// Keeps the state of the most recent draw call made by a `Painter`: the style and
+/// pipeline, targets, vertex buffer, material textures and transform. This stands in for
+/// a GPU debugger where none is available, such as in Linux VR builds. Only handles are
+/// kept while drawing, they are formatted by `dump`.
+#[cfg(feature = "draw-inspector")]
+pub struct DrawCallInspector<R: Resources> {
+    pipeline: Option<InspectedPipeline<R>>,
+    last: Option<InspectedDraw<R>>,
+}
+
+#[cfg(feature = "draw-inspector")]
+impl<R: Resources> DrawCallInspector<R> {
+    /// An inspector that hasn't seen a draw
+    pub fn new() -> DrawCallInspector<R> {
+        DrawCallInspector {
+            pipeline: None,
+            last: None,
+        }
+    }
+
+    /// Note the pipeline of the draw calls that follow, called by `Painter` before
+    /// `DrawParams::inspect_draw`
+    pub fn set_pipeline(
+        &mut self,
+        style: &'static str,
+        primitive: Primitive,
+        pso_key: u64,
+        vertex_buffer: Option<RawBuffer<R>>,
+        textures: Option<Vec<RawShaderResourceView<R>>>,
+    ) {
+        self.pipeline = Some(InspectedPipeline {
+            style: style,
+            primitive: primitive,
+            pso_key: pso_key,
+            vertex_buffer: vertex_buffer,
+            textures: textures,
+        });
+    }
+
+    /// The state of the most recent draw call, or `None` if there hasn't been one
+    pub fn dump(&self) -> Option<DrawCallSnapshot> {
+        let d = self.last.as_ref()?;
+        Some(DrawCallSnapshot {
+            frame: d.frame,
+            index: d.index,
+            style: d.pipeline.style,
+            primitive: d.pipeline.primitive,
+            pso_key: d.pipeline.pso_key,
+            color_target: format!("{:?}", d.color),
+            depth_target: format!("{:?}", d.depth),
+            vertex_buffer: d.pipeline.vertex_buffer.as_ref().map(|b| format!("{:?}", b)),
+            textures: d.pipeline.textures.as_ref().map(|t| t.iter().map(|v| format!("{:?}", v)).collect()),
+            transform: d.transform,
+            scissor: d.scissor,
+            range: d.range,
+            base_vertex: d.base_vertex,
+            instances: d.instances,
+        })
+    }
+
+    /// Forget the last draw call, such as before drawing a frame that should be inspected
+    pub fn clear(&mut self) {
+        self.pipeline = None;
+        self.last = None;
+    }
+}
+
+#[cfg(feature = "draw-inspector")]
+impl<R: Resources> Default for DrawCallInspector<R> {
+    fn default() -> DrawCallInspector<R> {
+        DrawCallInspector::new()
+    }
+}
+
 /// Parameters to the draw system
 pub struct DrawParams<R: Resources, C: CommandBuffer<R>> {
     /// The gfx command encoder
@@ -37,4 +946,846 @@ pub struct DrawParams<R: Resources, C: CommandBuffer<R>> {
     pub left: EyeParams,
     /// Right eye parameters
     pub right: EyeParams,
-}
\ No newline at end of file
+    /// Render lifecycle events
+    pub events: RenderEventBus,
+    /// The number of draw calls recorded since the frame began
+    pub draw_calls: usize,
+    /// Counts frames for per-frame resources such as `FrameRingBuffer`
+    pub frames: FrameCounter,
+    /// Reduced rate shading of the periphery of each eye, off by default
+    pub lens_shading: LensShading,
+    /// Logarithmic depth for every draw, off by default
+    pub log_depth: LogDepth,
+    /// Targets checked against the textures sampled by each draw
+    pub resources: ResourceStateTracker<R>,
+    /// The layers drawn by queued draws (from `BatchAccumulator` and `BakedScene`), one per
+    /// bit. Change it between passes to leave layers out of some of them, such as the
+    /// player's body from a mirror. All layers are drawn by default.
+    pub layer_mask: u32,
+    /// The state of the most recent draw call
+    #[cfg(feature = "draw-inspector")]
+    pub inspector: DrawCallInspector<R>,
+    /// Eye parameters saved by `push_camera`
+    cameras: Vec<(EyeParams, EyeParams)>,
+}
+
+impl<R: Resources, C: CommandBuffer<R>> DrawParams<R, C> {
+    /// Create draw parameters for the given encoder and targets
+    pub fn new(encoder: Encoder<R, C>, color: TargetRef<R>, depth: DepthRef<R>) -> DrawParams<R, C> {
+        DrawParams {
+            encoder: encoder,
+            color: color,
+            depth: depth,
+            left: Default::default(),
+            right: Default::default(),
+            events: RenderEventBus::new(),
+            draw_calls: 0,
+            frames: FrameCounter::new(),
+            lens_shading: LensShading::off(),
+            log_depth: LogDepth::off(),
+            resources: ResourceStateTracker::new(),
+            layer_mask: ALL_LAYERS,
+            #[cfg(feature = "draw-inspector")]
+            inspector: DrawCallInspector::new(),
+            cameras: Vec::new(),
+        }
+    }
+
+    /// Notify subscribers that a new frame is starting
+    pub fn begin_frame(&mut self) {
+        self.draw_calls = 0;
+        self.frames.advance();
+        self.events.fire(RenderEvent::FrameBegin);
+    }
+
+    /// Notify subscribers that the frame has been recorded
+    pub fn end_frame(&mut self) {
+        self.events.fire(RenderEvent::FrameEnd);
+    }
+
+    /// The parameters of both eyes, left first
+    pub fn eyes(&self) -> [EyeParams; 2] {
+        [self.left, self.right]
+    }
+
+    /// Frustum planes covering what both eyes see (see `math::frustum::merge_frustum_planes`),
+    /// for culling the scene once per frame. With a single camera pushed this is its frustum.
+    pub fn stereo_frustum_planes(&self) -> [Vector4<f32>; 6] {
+        if self.right.clip.w == 0 || self.right.clip.h == 0 {
+            extract_frustum_planes(self.left.view_proj())
+        } else {
+            merge_frustum_planes(self.left.view_proj(), self.right.view_proj())
+        }
+    }
+
+    /// Draw following calls from a single secondary camera (such as a security camera feed)
+    /// into the `viewport` rectangle of the color target, instead of from both eyes. The
+    /// projection fills the viewport. Calls can be nested, each undone by `pop_camera`.
+    pub fn push_camera(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>, viewport: Rect) {
+        let (w, h, _, _) = self.color.get_dimensions();
+        self.push_eye(camera_eye(view, proj, viewport, w, h));
+    }
+
+    /// Draw following calls from a single eye (such as a cubemap face or a voxel slice)
+    /// instead of from both eyes. Undone by `pop_camera`.
+    pub fn push_eye(&mut self, eye: EyeParams) {
+        // the right eye draws nothing
+        self.push_eyes(eye, EyeParams { clip: Rect { x: 0, y: 0, w: 0, h: 0 }, .. eye });
+    }
+
+    /// Draw following calls from the given pair of eyes (such as the mirrored eyes of a
+    /// reflection) instead of the current ones. Undone by `pop_camera`.
+    pub fn push_eyes(&mut self, left: EyeParams, right: EyeParams) {
+        self.cameras.push((self.left, self.right));
+        self.left = left;
+        self.right = right;
+    }
+
+    /// Restore the eye parameters from before the last `push_camera`, `push_eye` or
+    /// `push_eyes`, returning false if there was nothing to restore
+    pub fn pop_camera(&mut self) -> bool {
+        match self.cameras.pop() {
+            Some((left, right)) => {
+                self.left = left;
+                self.right = right;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Record a draw call into `inspector`, with the pipeline last given to
+    /// `DrawCallInspector::set_pipeline`. Called by `Painter` before counting the draw.
+    #[cfg(feature = "draw-inspector")]
+    pub fn inspect_draw(&mut self, transform: TransformBlock, scissor: Rect, slice: &Slice<R>) {
+        let pipeline = match self.inspector.pipeline {
+            Some(ref p) => p.clone(),
+            None => return,
+        };
+        self.inspector.last = Some(InspectedDraw {
+            pipeline: pipeline,
+            frame: self.frames.get(),
+            index: self.draw_calls,
+            color: self.color.raw().clone(),
+            depth: self.depth.raw().clone(),
+            transform: transform,
+            scissor: scissor,
+            range: (slice.start, slice.end),
+            base_vertex: slice.base_vertex,
+            instances: slice.instances.map(|(n, _)| n).unwrap_or(1),
+        });
+    }
+}
+
+/// Double x of a projection, since the transform shader halves it to share targets between
+/// eyes. Eyes that draw into a target of their own need this to fill it.
+pub(super) fn double_x(proj: Matrix4<f32>) -> Matrix4<f32> {
+    Matrix4::new_nonuniform_scaling(&Vector3::new(2., 1., 1.)) * proj
+}
+
+/// Eye parameters drawing through the given camera into a viewport of a target with the
+/// given size
+fn camera_eye(view: Matrix4<f32>, proj: Matrix4<f32>, viewport: Rect, width: u16, height: u16) -> EyeParams {
+    let eye = view.try_inverse()
+        .map(|i| Point3::from_homogeneous(i * Vector4::new(0., 0., 0., 1.)).unwrap_or(Point3::origin()))
+        .unwrap_or(Point3::origin());
+    let (w, h) = (width.max(1) as f32, height.max(1) as f32);
+    let cx = 2. * (viewport.x as f32 + viewport.w as f32 / 2.) / w - 1.;
+    let cy = 2. * (viewport.y as f32 + viewport.h as f32 / 2.) / h - 1.;
+    // squeeze clip space into the viewport, doubling x since the transform shader halves it
+    let mut fit = Matrix4::identity();
+    fit[(0, 0)] = 2. * viewport.w as f32 / w;
+    fit[(0, 3)] = 2. * cx;
+    fit[(1, 1)] = viewport.h as f32 / h;
+    fit[(1, 3)] = cy;
+    EyeParams {
+        eye: eye,
+        view: Transform3::from_matrix_unchecked(view),
+        proj: Transform3::from_matrix_unchecked(fit * proj),
+        clip_offset: 0.,
+        clip: viewport,
+    }
+}
+
+/// A single draw recorded by a `BatchAccumulator`
+#[derive(Copy, Clone)]
+pub struct BatchEntry {
+    /// Index of the mesh to draw
+    pub mesh_index: usize,
+    /// Index of the material to draw the mesh with
+    pub material_index: usize,
+    /// Model matrix of the mesh
+    pub model: Transform3<f32>,
+    /// The layers the draw belongs to, one per bit
+    pub layers: u32,
+}
+
+/// The layer of draws that don't name one
+pub const DEFAULT_LAYER: u32 = 1;
+/// A layer mask drawing every layer
+pub const ALL_LAYERS: u32 = !0;
+
+/// Collects draws over a frame and submits them grouped by pipeline, so that
+/// the pipeline state changes as rarely as possible. Within a group, draws are
+/// ordered by material.
+pub struct BatchAccumulator {
+    groups: FnvHashMap<u64, Vec<BatchEntry>>,
+    order: Vec<u64>,
+}
+
+impl BatchAccumulator {
+    /// Create an empty accumulator
+    pub fn new() -> BatchAccumulator {
+        BatchAccumulator {
+            groups: Default::default(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Record a draw on `DEFAULT_LAYER`. `pso_key` usually comes from `Painter::pso_key`.
+    pub fn push(&mut self, pso_key: u64, mesh_index: usize, material_index: usize, model: Transform3<f32>) {
+        self.push_with_layer(pso_key, mesh_index, material_index, model, DEFAULT_LAYER);
+    }
+
+    /// Record a draw on the given layers (one per bit), which `flush` skips unless one of
+    /// them passes both the layer mask of the painter and of the draw parameters
+    pub fn push_with_layer(
+        &mut self,
+        pso_key: u64,
+        mesh_index: usize,
+        material_index: usize,
+        model: Transform3<f32>,
+        layers: u32,
+    ) {
+        let order = &mut self.order;
+        self.groups.entry(pso_key).or_insert_with(|| {
+            order.push(pso_key);
+            Vec::new()
+        }).push(BatchEntry {
+            mesh_index: mesh_index,
+            material_index: material_index,
+            model: model,
+            layers: layers,
+        });
+    }
+
+    /// The number of draws recorded since the last flush
+    pub fn len(&self) -> usize {
+        self.groups.values().map(|g| g.len()).sum()
+    }
+
+    /// True if no draws have been recorded since the last flush
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Submit every recorded draw on a visible layer with the given painter, then clear the
+    /// accumulator. Groups are submitted in the order their first draw was pushed.
+    pub fn flush<R, E, C, M>(
+        &mut self,
+        painter: &Painter<R, E>,
+        ctx: &mut DrawParams<R, C>,
+        meshes: &[Mesh<R, E::Vertex, M>],
+        materials: &[E::Material],
+    )
+        -> Result<(), Error>
+        where R: Resources, E: Style<R>, C: CommandBuffer<R>
+    {
+        let order = ::std::mem::replace(&mut self.order, Vec::new());
+        let mut groups = ::std::mem::replace(&mut self.groups, Default::default());
+        for key in order {
+            let mut group = match groups.remove(&key) {
+                Some(g) => g,
+                None => continue,
+            };
+            group.sort_by_key(|e| e.material_index);
+            for e in group {
+                ensure!(e.mesh_index < meshes.len(), FlightError::IndexOutOfRange {
+                    what: "mesh",
+                    index: e.mesh_index,
+                    len: meshes.len(),
+                });
+                ensure!(e.material_index < materials.len(), FlightError::IndexOutOfRange {
+                    what: "material",
+                    index: e.material_index,
+                    len: materials.len(),
+                });
+                if !painter.draws_layers(ctx, e.layers) { continue }
+                painter.try_draw_with(ctx, e.model, &meshes[e.mesh_index], &materials[e.material_index])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard every recorded draw
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.order.clear();
+    }
+}
+
+impl Default for BatchAccumulator {
+    fn default() -> BatchAccumulator {
+        BatchAccumulator::new()
+    }
+}
+
+/// A high dynamic range RGB image, stored top row first
+#[derive(Clone, Debug)]
+pub struct Hdr32Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<[f32; 3]>,
+}
+
+impl Hdr32Image {
+    /// Save this image as a Radiance RGBE (`.hdr`) file
+    pub fn save_hdr<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let pixels: Vec<Rgb<f32>> = self.data.iter().map(|&p| Rgb { data: p }).collect();
+        let out = BufWriter::new(File::create(path)?);
+        HDREncoder::new(out).encode(&pixels, self.width as usize, self.height as usize)?;
+        Ok(())
+    }
+}
+
+/// Read back an HDR color texture (usually the tonemapping input) into an image.
+/// This flushes everything recorded in the encoder so far and blocks until the
+/// GPU has finished, so it is only meant for screenshots.
+pub fn capture_frame_hdr<R, F, C, D>(
+    factory: &mut F,
+    enc: &mut Encoder<R, C>,
+    device: &mut D,
+    src: &gfx::handle::Texture<R, R32_G32_B32>,
+)
+    -> Result<Hdr32Image, Error>
+    where
+        R: Resources,
+        F: Factory<R> + FactoryExt<R>,
+        C: CommandBuffer<R>,
+        D: Device<Resources = R, CommandBuffer = C>,
+{
+    use gfx::format::Formatted;
+    let info = src.get_info().to_raw_image_info(<(R32_G32_B32, Float) as Formatted>::get_format().1, 0);
+    let (width, height) = (info.width as usize, info.height as usize);
+    let download = factory.create_download_buffer::<[f32; 3]>(width * height)?;
+    enc.copy_texture_to_buffer_raw(
+        src.raw(),
+        None,
+        info,
+        download.raw(),
+        0,
+    ).map_err(|e| FlightError::TextureCopy { reason: format!("{:?}", e) })?;
+    enc.flush(device);
+
+    let reader = factory.read_mapping(&download)?;
+    // textures are stored bottom row first
+    let mut data = Vec::with_capacity(width * height);
+    for row in reader.chunks(width).rev() {
+        data.extend_from_slice(row);
+    }
+    Ok(Hdr32Image {
+        width: width as u32,
+        height: height as u32,
+        data: data,
+    })
+}
+
+/// Read back an 8-bit color texture, such as `OffscreenTarget::color_texture`, into an
+/// image. Like `capture_frame_hdr` this flushes the encoder and waits for the GPU.
+pub fn capture_frame_rgba8<R, F, C, D>(
+    factory: &mut F,
+    enc: &mut Encoder<R, C>,
+    device: &mut D,
+    src: &gfx::handle::Texture<R, R8_G8_B8_A8>,
+)
+    -> Result<RgbaImage, Error>
+    where
+        R: Resources,
+        F: Factory<R> + FactoryExt<R>,
+        C: CommandBuffer<R>,
+        D: Device<Resources = R, CommandBuffer = C>,
+{
+    use gfx::format::Formatted;
+    let info = src.get_info().to_raw_image_info(<::ColorFormat as Formatted>::get_format().1, 0);
+    let (width, height) = (info.width as usize, info.height as usize);
+    let download = factory.create_download_buffer::<[u8; 4]>(width * height)?;
+    enc.copy_texture_to_buffer_raw(
+        src.raw(),
+        None,
+        info,
+        download.raw(),
+        0,
+    ).map_err(|e| FlightError::TextureCopy { reason: format!("{:?}", e) })?;
+    enc.flush(device);
+
+    let reader = factory.read_mapping(&download)?;
+    // textures are stored bottom row first
+    let mut data = Vec::with_capacity(width * height * 4);
+    for row in reader.chunks(width).rev() {
+        for p in row {
+            data.extend_from_slice(p);
+        }
+    }
+    Ok(RgbaImage::from_raw(width as u32, height as u32, data).expect("readback size"))
+}
+
+/// Move the current frame to `previous` and `previous` to `penultimate`, leaving the
+/// oldest in `current` to be drawn over
+fn rotate_history<T>(current: &mut T, previous: &mut T, penultimate: &mut T) {
+    ::std::mem::swap(penultimate, previous);
+    ::std::mem::swap(previous, current);
+}
+
+/// The color of the last few frames, shared by temporal effects (such as TAA and
+/// screen-space reflections) rather than each keeping a history of its own. Draw the frame
+/// into `current`, sample `previous` (and `penultimate`) while doing so, and `swap` once the
+/// frame is done. The views never change targets, so registering them once with a
+/// `ResourceStateTracker` catches sampling the frame being drawn.
+pub struct TemporalHistory<R: Resources> {
+    /// The target of the frame being drawn
+    pub current: OffscreenTarget<R>,
+    /// The last frame drawn
+    pub previous: OffscreenTarget<R>,
+    /// The frame before the last
+    pub penultimate: OffscreenTarget<R>,
+    frames: usize,
+}
+
+impl<R: Resources> TemporalHistory<R> {
+    /// Create history targets with the given size in pixels
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16) -> Result<TemporalHistory<R>, Error> {
+        Ok(TemporalHistory {
+            current: OffscreenTarget::new(f, width, height)?,
+            previous: OffscreenTarget::new(f, width, height)?,
+            penultimate: OffscreenTarget::new(f, width, height)?,
+            frames: 0,
+        })
+    }
+
+    /// Recreate the targets at a new size, forgetting the history, and fire
+    /// `TargetsResized` on `events`
+    pub fn resize<F: Factory<R>>(&mut self, f: &mut F, events: &mut RenderEventBus, width: u16, height: u16) -> Result<(), Error> {
+        *self = TemporalHistory::new(f, width, height)?;
+        events.fire(RenderEvent::TargetsResized(width, height));
+        Ok(())
+    }
+
+    /// Finish the current frame: it becomes `previous`, `previous` becomes `penultimate`,
+    /// and the oldest target is reused for the next frame
+    pub fn swap(&mut self) {
+        rotate_history(&mut self.current, &mut self.previous, &mut self.penultimate);
+        self.frames += 1;
+    }
+
+    /// The number of frames in the history, up to 2. Effects should ignore the history
+    /// targets that don't hold a frame yet, such as after `resize`.
+    pub fn frames(&self) -> usize {
+        self.frames.min(2)
+    }
+
+    /// The last frame, for sampling while drawing the current one
+    pub fn previous_as_texture(&self) -> ShaderResourceView<R, [f32; 4]> {
+        self.previous.texture.buffer.clone()
+    }
+
+    /// The frame before the last
+    pub fn penultimate_as_texture(&self) -> ShaderResourceView<R, [f32; 4]> {
+        self.penultimate.texture.buffer.clone()
+    }
+
+    /// Check draws against all three targets
+    pub fn register(&self, tracker: &mut ResourceStateTracker<R>) {
+        tracker.register("temporal history current", &self.current);
+        tracker.register("temporal history previous", &self.previous);
+        tracker.register("temporal history penultimate", &self.penultimate);
+    }
+}
+
+/// A copy of a texture on its way back to the CPU, like a pixel buffer object in OpenGL.
+/// Queue it while recording a frame and `poll` it in later frames: the copy is only read
+/// once `FRAME_RING_SIZE` more frames have begun, by when the GPU has finished it, so
+/// nothing ever waits. Use it for data the CPU needs regularly, such as terrain heights
+/// for physics, rather than screenshots.
+pub struct TextureDownload<R: Resources, T> {
+    buffer: Buffer<R, T>,
+    width: usize,
+    height: usize,
+    fence: FrameFence,
+}
+
+/// Work recorded in some frame, which the GPU has finished once `FRAME_RING_SIZE` more
+/// frames have begun
+#[derive(Clone, Debug)]
+struct FrameFence {
+    queued: u64,
+    counter: FrameCounter,
+}
+
+impl FrameFence {
+    fn new(counter: FrameCounter) -> FrameFence {
+        FrameFence {
+            queued: counter.get(),
+            counter: counter,
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.counter.get() >= self.queued + FRAME_RING_SIZE as u64
+    }
+}
+
+impl<R: Resources, T: gfx::memory::Pod + Copy> TextureDownload<R, T> {
+    /// Record a copy of the first mip level of `src` into the encoder of `ctx`, where `S` is
+    /// the format the texture was created with
+    pub fn queue<F, C, S>(factory: &mut F, ctx: &mut DrawParams<R, C>, src: &gfx::handle::Texture<R, S::Surface>)
+        -> Result<TextureDownload<R, T>, Error>
+        where F: Factory<R>, C: CommandBuffer<R>, S: gfx::format::Formatted,
+              S::Surface: gfx::format::SurfaceTyped<DataType = T>
+    {
+        let info = src.get_info().to_raw_image_info(S::get_format().1, 0);
+        let (width, height) = (info.width as usize, info.height as usize);
+        let buffer = factory.create_download_buffer::<T>(width * height)?;
+        ctx.encoder.copy_texture_to_buffer_raw(src.raw(), None, info, buffer.raw(), 0)
+            .map_err(|e| FlightError::TextureCopy { reason: format!("{:?}", e) })?;
+        Ok(TextureDownload {
+            buffer: buffer,
+            width: width,
+            height: height,
+            fence: FrameFence::new(ctx.frames.clone()),
+        })
+    }
+
+    /// The width and height of the texture in texels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// True once enough frames have begun for the copy to be finished
+    pub fn is_ready(&self) -> bool {
+        self.fence.is_ready()
+    }
+
+    /// The texels, top row first, or `None` if the copy may still be in flight
+    pub fn poll<F: Factory<R>>(&self, factory: &mut F) -> Option<Result<Vec<T>, Error>> {
+        if !self.is_ready() { return None }
+        Some(factory.read_mapping(&self.buffer)
+            .map_err(Error::from)
+            .map(|reader| {
+                // textures are stored bottom row first
+                let mut data = Vec::with_capacity(self.width * self.height);
+                for row in reader.chunks(self.width.max(1)).rev() {
+                    data.extend_from_slice(row);
+                }
+                data
+            }))
+    }
+}
+
+fn exr_attribute<W: Write>(out: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(kind.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(&(value.len() as i32).to_le_bytes())?;
+    out.write_all(value)
+}
+
+fn write_exr<W: Write>(out: &mut W, image: &Hdr32Image) -> io::Result<()> {
+    let (w, h) = (image.width as usize, image.height as usize);
+
+    // magic number, then version 2 as a single part scanline file
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    // channels must be listed in alphabetical order
+    let mut chlist = Vec::new();
+    for name in &["B", "G", "R"] {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // 32-bit float
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // linear flag, reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+    let mut window = Vec::new();
+    for &v in &[0, 0, w as i32 - 1, h as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "channels", "chlist", &chlist)?;
+    exr_attribute(&mut header, "compression", "compression", &[0])?;
+    exr_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    exr_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+    header.push(0);
+    out.write_all(&header)?;
+
+    // offset table, with one uncompressed scanline per block
+    let first = (header.len() + 8 * h) as u64;
+    let block_len = 8 + 12 * w as u64;
+    for y in 0..h as u64 {
+        out.write_all(&(first + y * block_len).to_le_bytes())?;
+    }
+
+    for (y, row) in image.data.chunks(w).enumerate() {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&((12 * w) as i32).to_le_bytes())?;
+        for c in (0..3).rev() {
+            for p in row {
+                out.write_all(&p[c].to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save an HDR image as an uncompressed 32-bit float OpenEXR file
+pub fn save_exr(path: &Path, data: &Hdr32Image) -> Result<(), Error> {
+    ensure!(
+        data.data.len() == data.width as usize * data.height as usize,
+        FlightError::TextureSizeMismatch {
+            expected: data.width as usize * data.height as usize,
+            given: data.data.len(),
+        }
+    );
+    let mut out = BufWriter::new(File::create(path)?);
+    write_exr(&mut out, data)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[test]
+fn render_events() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut bus = RenderEventBus::new();
+    let s = seen.clone();
+    bus.subscribe(RenderEvent::TargetsResized(0, 0), Box::new(move |d| s.borrow_mut().push((d.event, d.frame))));
+    let s = seen.clone();
+    bus.subscribe(RenderEvent::FrameEnd, Box::new(move |d| s.borrow_mut().push((d.event, d.frame))));
+
+    bus.fire(RenderEvent::FrameBegin);
+    bus.fire(RenderEvent::TargetsResized(640, 480));
+    bus.fire(RenderEvent::ShaderReloaded("uber"));
+    bus.fire(RenderEvent::FrameEnd);
+    bus.fire(RenderEvent::FrameBegin);
+    assert_eq!(bus.frame(), 2);
+    assert_eq!(*seen.borrow(), vec![(RenderEvent::TargetsResized(640, 480), 1), (RenderEvent::FrameEnd, 1)]);
+}
+
+#[test]
+fn log_depth() {
+    let log = LogDepth::new(1000.);
+    assert!(!LogDepth::default().enabled() && LogDepth::default().block() == 0.);
+    assert!(relative_eq!(log.depth(0.), 0.));
+    assert!(relative_eq!(log.depth(1000.), 1.));
+    for &w in &[0.001, 0.5, 20., 900.] {
+        assert!(relative_eq!(log.distance(log.depth(w)), w, max_relative = 1e-4));
+    }
+    // one step of a 24 bit depth buffer stays small all the way out
+    let step = 1. / (1 << 24) as f32;
+    assert!(log.distance(log.depth(1.) + step) - 1. < 1e-5);
+    assert!(log.distance(log.depth(1000.) - step) > 999.999);
+}
+
+#[test]
+fn reverse_z() {
+    use gfx::state::Comparison;
+    use super::TransformBlock;
+    let proj = TransformBlock::reversed_z_proj(1.2, 1.5, 0.1, 100.);
+    // window depth of a point `w` along the view axis
+    let depth = |w: f32| {
+        let c = proj * Vector4::new(0., 0., -w, 1.);
+        c.z / c.w * 0.5 + 0.5
+    };
+    assert!(relative_eq!(depth(0.1), 1., epsilon = 1e-5));
+    assert!(relative_eq!(depth(100.), 0., epsilon = 1e-5));
+    assert!(depth(1.) > depth(2.));
+    let standard = na::Perspective3::new(1.5, 1.2, 0.1, 100.).to_homogeneous();
+    assert_eq!(DepthConvention::Standard.projection(&standard), standard);
+    let c = standard * Vector4::new(0., 0., -2., 1.);
+    let d = DepthConvention::ReverseZ.standard_depth(depth(2.));
+    assert!(relative_eq!(d, c.z / c.w * 0.5 + 0.5, epsilon = 1e-5));
+
+    assert_eq!(DepthConvention::ReverseZ.comparison(Comparison::LessEqual), Comparison::GreaterEqual);
+    assert_eq!(DepthConvention::ReverseZ.comparison(Comparison::Equal), Comparison::Equal);
+    assert_eq!(DepthConvention::Standard.comparison(Comparison::Less), Comparison::Less);
+    assert_eq!(DepthConvention::ReverseZ.far(), 0.);
+    assert_eq!(DEPTH_WRITE.fun, DEPTH_CONVENTION.comparison(Comparison::LessEqual));
+    assert!(DEPTH_WRITE.write && !DEPTH_TEST.write);
+}
+
+#[test]
+fn depth_bias() {
+    assert!(DepthBias::none().is_none());
+    assert_eq!(DepthBias::none().rasterizer().offset, None);
+    let offset = DepthBias::new(2, 1).offset().unwrap();
+    // towards smaller depth normally, and larger with reversed depth
+    let sign = if DEPTH_CONVENTION == DepthConvention::Standard { -1 } else { 1 };
+    assert_eq!((offset.0, offset.1), (sign, sign * 2));
+    assert_eq!(DepthBias::new(2, 1).rasterizer().offset, Some(offset));
+}
+
+#[test]
+fn frame_counter() {
+    let frames = FrameCounter::new();
+    let shared = frames.clone();
+    assert_eq!(shared.get(), 0);
+    frames.advance();
+    frames.advance();
+    assert_eq!(shared.get(), 2);
+    // ring buffers pick their buffer with this modulo
+    assert_eq!(shared.get() % FRAME_RING_SIZE as u64, 2);
+    frames.advance();
+    assert_eq!(shared.get() % FRAME_RING_SIZE as u64, 0);
+}
+
+#[test]
+fn download_fence() {
+    let frames = FrameCounter::new();
+    frames.advance();
+    let fence = FrameFence::new(frames.clone());
+    // pending while the GPU may still be working on the frame it was queued in
+    for _ in 1..FRAME_RING_SIZE {
+        frames.advance();
+        assert!(!fence.is_ready());
+    }
+    frames.advance();
+    assert!(fence.is_ready());
+    // and stays ready
+    frames.advance();
+    assert!(fence.is_ready());
+    // a later copy waits on its own frame
+    assert!(!FrameFence::new(frames.clone()).is_ready());
+}
+
+#[test]
+fn draw_list_commands() {
+    let mut buf = DrawList::new(Primitive::TriangleList);
+    buf.push(100, 36, 0, 4, 1);
+    // culled down to nothing
+    buf.push(0, 36, 36, 0, 0);
+    buf.push(0, 0, 72, 0, 1);
+    assert_eq!(buf.commands(), &[DrawIndexedCommand {
+        index_count: 36,
+        instance_count: 1,
+        first_index: 0,
+        base_vertex: 104,
+        base_instance: 0,
+    }]);
+    buf.clear();
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn cpu_culling() {
+    use nalgebra::{Perspective3, Isometry3};
+
+    let proj = Perspective3::new(1., ::std::f32::consts::PI / 2., 0.1, 100.).to_homogeneous();
+    let view = Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zeros()).to_homogeneous();
+    let planes = extract_frustum_planes(proj * view);
+
+    let mut culler = FrustumCuller::new();
+    let cube = culler.add_draw(DrawIndexedCommand {
+        index_count: 36,
+        instance_count: 1,
+        first_index: 0,
+        base_vertex: 0,
+        base_instance: 0,
+    });
+    let at = |x: f32, z: f32| ObjectData {
+        aabb_min: [x - 1., -1., z - 1.],
+        aabb_max: [x + 1., 1., z + 1.],
+        draw_index: cube,
+    };
+    let mut out = DrawList::new(Primitive::TriangleList);
+    // in view, straddling the left plane, behind the eye, past the far plane
+    let objects = [at(0., 0.), at(-6., 0.), at(0., 10.), at(0., -200.)];
+    assert_eq!(culler.run(&objects, &planes, &mut out).unwrap(), 2);
+    assert_eq!(out.len(), 2);
+
+    // a bad draw index leaves the output as it was, even after visible objects
+    let bad = ObjectData { draw_index: 7, .. at(0., 0.) };
+    assert!(culler.run(&[at(0., 0.), bad], &planes, &mut out).is_err());
+    assert_eq!(out.len(), 2);
+}
+
+#[test]
+fn batch_layers() {
+    let mut batch = BatchAccumulator::new();
+    batch.push(7, 0, 0, Transform3::identity());
+    batch.push_with_layer(7, 1, 0, Transform3::identity(), 1 << 3);
+    assert_eq!(batch.len(), 2);
+    let layers: Vec<u32> = batch.groups[&7].iter().map(|e| e.layers).collect();
+    assert_eq!(layers, vec![DEFAULT_LAYER, 1 << 3]);
+    batch.clear();
+    assert!(batch.is_empty());
+}
+
+#[test]
+fn exr_layout() {
+    let image = Hdr32Image {
+        width: 2,
+        height: 3,
+        data: vec![[0.5, 1., 2.]; 6],
+    };
+    let mut out = Vec::new();
+    write_exr(&mut out, &image).unwrap();
+
+    // the first offset points just past the offset table, the blocks fill the rest
+    let mut first = [0u8; 8];
+    let table = out.len() - 3 * (8 + 12 * 2) - 3 * 8;
+    first.copy_from_slice(&out[table..table + 8]);
+    assert_eq!(u64::from_le_bytes(first) as usize, table + 3 * 8);
+    assert_eq!(&out[..4], &[0x76, 0x2f, 0x31, 0x01]);
+    // channels are written in alphabetical order, so the last value is the red channel
+    // of the last pixel
+    assert_eq!(&out[out.len() - 4..], &0.5f32.to_le_bytes());
+}
+
+#[test]
+fn camera_viewport() {
+    let view = Matrix4::new_translation(&Vector3::new(0., 0., -5.));
+    // the right half of a 200x100 target
+    let viewport = Rect { x: 100, y: 0, w: 100, h: 100 };
+    let eye = camera_eye(view, Matrix4::identity(), viewport, 200, 100);
+    assert!(relative_eq!(eye.eye, Point3::new(0., 0., 5.)));
+    // the corners of clip space land on the corners of the viewport, after the transform
+    // shader halves x
+    let corner = |x: f32, y: f32| {
+        let c = eye.proj.matrix() * Vector4::new(x, y, 0., 1.);
+        (c.x / 2., c.y)
+    };
+    let (x, y) = corner(-1., -1.);
+    assert!(relative_eq!(x, 0.) && relative_eq!(y, -1.));
+    let (x, y) = corner(1., 1.);
+    assert!(relative_eq!(x, 1.) && relative_eq!(y, 1.));
+}
+
+#[test]
+fn lens_shading_presets() {
+    assert_eq!(LensShading::off().shaded_fraction(1000, 1000), 1.);
+    assert_eq!(LensShading::off().block(Rect { x: 0, y: 0, w: 100, h: 100 }), [0.; 4]);
+    let quality = LensShading::quality().shaded_fraction(1000, 1000);
+    let balanced = LensShading::balanced().shaded_fraction(1000, 1000);
+    let performance = LensShading::performance().shaded_fraction(1000, 1000);
+    // never below half rate, and cheaper with each preset
+    assert!(performance >= 0.5);
+    assert!(performance < balanced && balanced < quality && quality < 1.);
+    // the circle is centered on the eye viewport
+    let b = LensShading::balanced().block(Rect { x: 100, y: 0, w: 100, h: 50 });
+    assert_eq!((b[0], b[1]), (150., 25.));
+}
+
+#[test]
+fn history_rotation() {
+    let (mut current, mut previous, mut penultimate) = (3, 2, 1);
+    rotate_history(&mut current, &mut previous, &mut penultimate);
+    assert_eq!((current, previous, penultimate), (1, 3, 2));
+    rotate_history(&mut current, &mut previous, &mut penultimate);
+    assert_eq!((current, previous, penultimate), (2, 1, 3));
+}
@@ -0,0 +1,146 @@
+use gfx::{Resources, CommandBuffer, Primitive, Slice};
+use gfx::traits::FactoryExt;
+use nalgebra::{self as na, Point3, Vector3, Matrix4, Transform3};
+
+use super::{Painter, SolidStyle, DrawParams};
+use ::mesh::{Mesh, VertC, Aabb};
+use ::{Error, Sun};
+
+/// Collects debugging lines (bounding boxes, spheres, frusta) over a frame and draws them
+/// all at once with a `SolidStyle` painter. When `enabled` is false, adding shapes does
+/// nothing, so calls can be left in release builds.
+pub struct DebugDraw {
+    /// Record and draw shapes
+    pub enabled: bool,
+    /// Color of the bounds recorded automatically for drawn meshes (see `Painter::set_debug`)
+    pub bounds_color: [f32; 3],
+    /// Also record bounding spheres for drawn meshes
+    pub bounds_spheres: bool,
+    lines: Vec<VertC>,
+}
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+impl DebugDraw {
+    /// Create a disabled debug drawer
+    pub fn new() -> DebugDraw {
+        DebugDraw {
+            enabled: false,
+            bounds_color: [1., 1., 0.],
+            bounds_spheres: false,
+            lines: Vec::new(),
+        }
+    }
+
+    /// The number of lines recorded this frame
+    pub fn line_count(&self) -> usize {
+        self.lines.len() / 2
+    }
+
+    /// Record a line segment
+    pub fn add_line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        if !self.enabled { return }
+        self.lines.push(VertC { pos: [a.x, a.y, a.z], color: color });
+        self.lines.push(VertC { pos: [b.x, b.y, b.z], color: color });
+    }
+
+    /// Record the edges of a box whose corners are in `Aabb::corners` order
+    pub fn add_box(&mut self, corners: &[Point3<f32>; 8], color: [f32; 3]) {
+        if !self.enabled { return }
+        for i in 0..8 {
+            for &bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.add_line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    /// Record a bounding box transformed by a model matrix
+    pub fn add_aabb(&mut self, bounds: &Aabb, model: &Transform3<f32>, color: [f32; 3]) {
+        if !self.enabled || bounds.is_empty() { return }
+        let mut corners = bounds.corners();
+        for c in corners.iter_mut() {
+            *c = model * *c;
+        }
+        self.add_box(&corners, color);
+    }
+
+    /// Record the bounding box of a mesh drawn with the given model matrix
+    pub fn add_mesh_bounds<R, V, M>(&mut self, mesh: &Mesh<R, V, M>, model: &Transform3<f32>, color: [f32; 3])
+        where R: Resources, V: ::mesh::Vertex
+    {
+        self.add_aabb(&mesh.bounds, model, color);
+    }
+
+    /// Record a sphere as three axis-aligned circles
+    pub fn add_sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 3]) {
+        if !self.enabled { return }
+        let axes = [Vector3::x(), Vector3::y(), Vector3::z()];
+        for i in 0..3 {
+            let (u, v) = (axes[(i + 1) % 3] * radius, axes[(i + 2) % 3] * radius);
+            let point = |s: usize| {
+                let a = s as f32 / CIRCLE_SEGMENTS as f32 * 2. * ::std::f32::consts::PI;
+                center + u * a.cos() + v * a.sin()
+            };
+            for s in 0..CIRCLE_SEGMENTS {
+                self.add_line(point(s), point(s + 1), color);
+            }
+        }
+    }
+
+    /// Record the frustum of a view-projection matrix, such as a shadow camera
+    pub fn add_frustum(&mut self, view_proj: &Matrix4<f32>, color: [f32; 3]) {
+        if !self.enabled { return }
+        let inv = match view_proj.try_inverse() {
+            Some(m) => m,
+            None => return,
+        };
+        let ndc = Aabb {
+            min: Point3::new(-1., -1., -1.),
+            max: Point3::new(1., 1., 1.),
+        };
+        let mut corners = ndc.corners();
+        for c in corners.iter_mut() {
+            let h = inv * c.to_homogeneous();
+            *c = Point3::from_homogeneous(h).unwrap_or(*c);
+        }
+        self.add_box(&corners, color);
+    }
+
+    /// Record the region covered by the sun's shadow, extruded from `near` to `far`
+    /// along the light direction
+    pub fn add_sun(&mut self, sun: &Sun, near: f32, far: f32, color: [f32; 3]) {
+        if !self.enabled { return }
+        let local = Aabb {
+            min: Point3::new(sun.min_corner.x, sun.min_corner.y, -far),
+            max: Point3::new(sun.max_corner.x, sun.max_corner.y, -near),
+        };
+        let model: Transform3<f32> = na::convert(sun.view);
+        self.add_aabb(&local, &model, color);
+    }
+
+    /// Draw and forget everything recorded this frame
+    pub fn draw<R, C, F>(&mut self, f: &mut F, ctx: &mut DrawParams<R, C>, painter: &Painter<R, SolidStyle<R>>)
+        -> Result<(), Error>
+        where R: Resources, C: CommandBuffer<R>, F: FactoryExt<R>
+    {
+        if self.lines.is_empty() { return Ok(()) }
+        let buf = f.create_vertex_buffer(&self.lines);
+        let mesh = Mesh {
+            slice: Slice::new_match_vertex_buffer(&buf),
+            buf: buf,
+            prim: Primitive::LineList,
+            bounds: Aabb::empty(),
+            mat: (),
+        };
+        self.lines.clear();
+        painter.try_draw(ctx, na::one(), &mesh)
+    }
+}
+
+impl Default for DebugDraw {
+    fn default() -> DebugDraw {
+        DebugDraw::new()
+    }
+}
@@ -0,0 +1,357 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, RenderTargetView};
+use gfx::memory::{Bind, Usage};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+use nalgebra::{Point3, Vector3, Matrix4, Transform3};
+
+use super::{DrawParams, EyeParams, Painter, double_x, StyleInputs, Style, TransformBlock, UberEnv, UberMaterial};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, TargetRef, DepthRef, Texture};
+use ::util::NativeRepr;
+
+/// The pixel format of voxel grids: radiance in RGB and opacity in alpha
+pub type VoxelFormat = (R16_G16_B16_A16, Float);
+
+gfx_defines!{
+    constant VoxelizeBlock {
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+        ambient: [f32; 4] = "ambient",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<VoxelizeBlock> = "voxelize",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<VoxelFormat> = "f_color",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX"),
+    fragment: static_file!("shaders/voxelize.f.glsl")
+        .define_to("I_NORM", "v_norm")
+        .define_to("I_TEX", "v_tex")
+});
+
+/// A 3D texture of voxel format, with every mip level
+fn voxel_texture<F: Factory<R>, R: Resources>(f: &mut F, size: u16, levels: u8)
+    -> Result<(gfx::handle::Texture<R, R16_G16_B16_A16>, Texture<R, VoxelFormat>), Error>
+{
+    use gfx::texture::*;
+    let bind = if size > 1 {
+        Bind::RENDER_TARGET | Bind::SHADER_RESOURCE
+    } else {
+        Bind::SHADER_RESOURCE
+    };
+    let raw = f.create_texture::<R16_G16_B16_A16>(
+        Kind::D3(size, size, size),
+        levels,
+        bind,
+        Usage::Data,
+        Some(ChannelType::Float),
+    )?;
+    let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Border));
+    let view = f.view_texture_as_shader_resource::<VoxelFormat>(&raw, (0, levels - 1), Swizzle::new())?;
+    Ok((raw, Texture {
+        buffer: view,
+        sampler: sampler,
+    }))
+}
+
+/// An empty 1 voxel grid, bound by the uber style when there is no `VoxelGrid`
+pub(super) fn empty_voxels<F: Factory<R>, R: Resources>(f: &mut F) -> Result<Texture<R, VoxelFormat>, Error> {
+    voxel_texture(f, 1, 1).map(|(_, t)| t)
+}
+
+/// The volumes of a voxel grid along x, y and z
+pub(super) type VoxelVolumes<R> = (Texture<R, VoxelFormat>, Texture<R, VoxelFormat>, Texture<R, VoxelFormat>);
+
+/// The configuration for voxelization
+pub struct VoxelInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    params: VoxelizeBlock,
+    params_update: bool,
+    params_block: Buffer<R, VoxelizeBlock>,
+    target: RenderTargetView<R, VoxelFormat>,
+}
+
+impl<R: Resources> VoxelInputs<R> {
+    /// Light voxels with the sun of an environment
+    pub fn set_sun(&mut self, env: &UberEnv<R>) {
+        let c = env.sun_color;
+        let dir = env.sun_rotation * Vector3::z();
+        self.params.sun_dir = dir.to_homogeneous().downgrade();
+        self.params.sun_color = [c[0] * c[3], c[1] * c[3], c[2] * c[3], 1.];
+        self.params_update = true;
+    }
+
+    /// Light voxels evenly from every direction, as a stand in for the sky
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.params.ambient = [ambient[0], ambient[1], ambient[2], 0.];
+        self.params_update = true;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for VoxelInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Draws uber meshes into a slice of a `VoxelGrid` as lit, opaque voxels. It draws into
+/// the slice set by `VoxelGrid::voxelize` rather than the color target of the draw
+/// parameters, so it is only useful inside `voxelize`.
+pub struct VoxelStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for VoxelStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = VoxelInputs<R>;
+    type Material = UberMaterial<R>;
+    type Bound = pl::Data<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut VoxelInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(VoxelStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<VoxelInputs<R>, Error> {
+        let (_, _, target) = f.create_render_target::<VoxelFormat>(1, 1)?;
+        Ok(VoxelInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            params: VoxelizeBlock {
+                sun_dir: [0., 1., 0., 0.],
+                sun_color: [1., 1., 1., 1.],
+                ambient: [0.1, 0.1, 0.1, 0.],
+            },
+            params_update: true,
+            params_block: f.create_constant_buffer(1),
+            target: target,
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &VoxelInputs<R>,
+        _: TargetRef<R>,
+        _: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &UberMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            verts: buf,
+            transform: inputs.transform_block.clone(),
+            params: inputs.params_block.clone(),
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            color: inputs.target.clone(),
+            albedo: mat.albedo.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut VoxelInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        if inputs.params_update {
+            enc.update_constant_buffer(&inputs.params_block, &inputs.params);
+            inputs.params_update = false;
+        }
+        // bindings are cached, but the slice changes between draws
+        data.color = inputs.target.clone();
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
+
+/// The voxels seen along one axis. The texture is laid out so that its depth runs along
+/// that axis: the grid axis `k` is texture z, `k + 1` is x and `k + 2` is y.
+struct AxisVolume<R: Resources> {
+    volume: Texture<R, VoxelFormat>,
+    slices: Vec<RenderTargetView<R, VoxelFormat>>,
+}
+
+/// The matrix taking world positions to the clip space of one slice of a voxel grid: x and
+/// y cover the grid across the slice, and z runs from -1 to 1 through its thickness
+fn slice_matrix(min: &Point3<f32>, size: f32, resolution: u16, axis: usize, slice: u16) -> Matrix4<f32> {
+    let mut m = Matrix4::zeros();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let across = 2. / size;
+    let through = 2. * resolution as f32 / size;
+    m[(0, u)] = across;
+    m[(0, 3)] = -across * min[u] - 1.;
+    m[(1, v)] = across;
+    m[(1, 3)] = -across * min[v] - 1.;
+    m[(2, axis)] = through;
+    m[(2, 3)] = -through * min[axis] - 2. * slice as f32 - 1.;
+    m[(3, 3)] = 1.;
+    m
+}
+
+/// A cube of voxels holding the light bouncing off the scene, traced in cones by the uber
+/// style for diffuse and glossy indirect light (Crassin et al. 2011, "Interactive Indirect
+/// Illumination Using Voxel Cone Tracing").
+///
+/// Voxelizing draws the scene through a `VoxelStyle` painter once per slice along each of
+/// the three axes, clipped to the slice, so surfaces edge on to one axis are caught by
+/// the others. That is hundreds of scene draws, so static scenes should voxelize once
+/// (or a few slices per frame) rather than every frame. Call `mip_filter` afterwards and
+/// pass the grid to `UberInputs::set_voxel_grid`.
+pub struct VoxelGrid<R: Resources> {
+    /// The corner of the grid with the smallest coordinates
+    pub min: Point3<f32>,
+    /// The width of the grid along every axis
+    pub size: f32,
+    /// How strongly traced light adds to diffuse lighting
+    pub diffuse: f32,
+    /// How strongly traced light adds to reflections
+    pub specular: f32,
+    resolution: u16,
+    levels: u8,
+    axes: Vec<AxisVolume<R>>,
+}
+
+impl<R: Resources> VoxelGrid<R> {
+    /// The usual number of voxels along each axis
+    pub const DEFAULT_RESOLUTION: u16 = 64;
+
+    /// Create an empty grid of `resolution` cubed voxels (a power of two) covering the cube
+    /// from `min` to `min + size`
+    pub fn new<F: Factory<R>>(f: &mut F, resolution: u16, min: Point3<f32>, size: f32) -> Result<VoxelGrid<R>, Error> {
+        let resolution = resolution.max(2).next_power_of_two();
+        let levels = (resolution as f32).log2() as u8 + 1;
+        let mut axes = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let (raw, volume) = voxel_texture(f, resolution, levels)?;
+            let slices = (0..resolution)
+                .map(|i| f.view_texture_as_render_target::<VoxelFormat>(&raw, 0, Some(i)))
+                .collect::<Result<Vec<_>, _>>()?;
+            axes.push(AxisVolume {
+                volume: volume,
+                slices: slices,
+            });
+        }
+        Ok(VoxelGrid {
+            min: min,
+            size: size,
+            diffuse: 1.,
+            specular: 1.,
+            resolution: resolution,
+            levels: levels,
+            axes: axes,
+        })
+    }
+
+    /// The number of voxels along each axis
+    pub fn resolution(&self) -> u16 {
+        self.resolution
+    }
+
+    /// The number of mip levels, from full resolution down to a single voxel
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
+    /// The voxels as seen along each axis, for binding
+    pub(super) fn volumes(&self) -> VoxelVolumes<R> {
+        (self.axes[0].volume.clone(), self.axes[1].volume.clone(), self.axes[2].volume.clone())
+    }
+
+    /// Clear the grid and draw the scene into it with `draw_scene`, which should draw
+    /// through `painter`. The draw parameters are redirected to each slice in turn and
+    /// restored afterwards.
+    pub fn voxelize<C, D>(
+        &mut self,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, VoxelStyle<R>>,
+        mut draw_scene: D,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, D: FnMut(&mut DrawParams<R, C>, &Painter<R, VoxelStyle<R>>) -> Result<(), Error>
+    {
+        profile_scope!("voxelize");
+        let proj = double_x(Matrix4::identity());
+        let mut result = Ok(());
+        'axes: for (axis, volume) in self.axes.iter().enumerate() {
+            for (i, slice) in volume.slices.iter().enumerate() {
+                ctx.encoder.clear(slice, [0.; 4]);
+                painter.cfg(|inputs| inputs.target = slice.clone());
+                let view = slice_matrix(&self.min, self.size, self.resolution, axis, i as u16);
+                ctx.push_eye(EyeParams {
+                    eye: self.min + Vector3::repeat(self.size / 2.),
+                    view: Transform3::from_matrix_unchecked(view),
+                    proj: Transform3::from_matrix_unchecked(proj),
+                    clip_offset: 0.,
+                    clip: Rect { x: 0, y: 0, w: self.resolution, h: self.resolution },
+                });
+                result = draw_scene(ctx, painter);
+                ctx.pop_camera();
+                if result.is_err() { break 'axes }
+            }
+        }
+        result
+    }
+
+    /// Average the voxels into every mip level, which wider cones sample from
+    pub fn mip_filter<C: CommandBuffer<R>>(&self, enc: &mut Encoder<R, C>) {
+        for axis in &self.axes {
+            enc.generate_mipmap(&axis.volume.buffer);
+        }
+    }
+
+    /// The grid parameters for the uber shader: the minimum corner and inverse size, then
+    /// whether the grid is used, the diffuse and specular strengths, and the level count
+    pub(super) fn blocks(&self) -> ([f32; 4], [f32; 4]) {
+        (
+            [self.min.x, self.min.y, self.min.z, 1. / self.size],
+            [1., self.diffuse, self.specular, self.levels as f32],
+        )
+    }
+}
+
+#[test]
+fn voxel_slices() {
+    use nalgebra::Vector4;
+
+    let min = Point3::new(-2., 0., -2.);
+    // 8 voxels half a unit wide
+    let m = slice_matrix(&min, 4., 8, 1, 3);
+    // the middle of slice 3 along y (1.5 to 2 units up) is halfway through its depth
+    let p = m * Vector4::new(0., 1.75, -2., 1.);
+    assert!(relative_eq!(p.z, 0.) && relative_eq!(p.w, 1.));
+    // y slices span z across x and x across y
+    assert!(relative_eq!(p.x, -1.) && relative_eq!(p.y, 0.));
+    assert!(relative_eq!((m * Vector4::new(0., 1.5, 0., 1.)).z, -1.));
+    let corner = m * Vector4::new(2., 2., 2., 1.);
+    assert!(relative_eq!(corner.x, 1.) && relative_eq!(corner.y, 1.) && relative_eq!(corner.z, 1.));
+}
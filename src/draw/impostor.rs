@@ -0,0 +1,590 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+use nalgebra::{self as na, Point3, Vector3, Matrix4, Orthographic3, Isometry3, Transform3};
+use std::f32::consts::PI;
+
+use super::{StyleInputs, Style, TransformBlock, Painter, DrawParams, EyeParams, OffscreenTarget};
+use super::{DrawDistance, LodLevel, UberEnv, UberMaterial, camera_distance};
+use ::mesh::{Primitive, Mesh, VertNTT, gen};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+use ::util::NativeRepr;
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<ImpostorBlock> = "impostor",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        atlas: gfx::TextureSampler<[f32; 4]> = "atlas_tex",
+        normals: gfx::TextureSampler<[f32; 4]> = "normal_atlas_tex",
+    }
+
+    pipeline normal_pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+    }
+
+    constant ImpostorBlock {
+        cell_a: [f32; 4] = "cell_a",
+        cell_b: [f32; 4] = "cell_b",
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+        ambient: [f32; 4] = "ambient",
+        blend: f32 = "blend",
+        fade: f32 = "fade",
+        alpha_cutoff: f32 = "alpha_cutoff",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("TEX"),
+    fragment: static_file!("shaders/impostor.f.glsl")
+        .define_to("I_TEX", "v_tex")
+});
+
+shader!(normal_shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX")
+        .define("TAN"),
+    fragment: static_file!("shaders/impostor_normal.f.glsl")
+        .define_to("I_NORM", "v_norm")
+        .define_to("I_TEX", "v_tex")
+        .define_to("I_TAN", "v_tan")
+        .define_to("I_BITAN", "v_bitan")
+});
+
+/// The arrangement of views in an impostor atlas
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImpostorLayout {
+    /// The number of views, evenly spaced around the vertical axis
+    pub views: u32,
+    /// The number of cells across the atlas
+    pub columns: u32,
+    /// The number of cells down the atlas
+    pub rows: u32,
+}
+
+impl ImpostorLayout {
+    /// The smallest square-ish grid holding the given number of views
+    pub fn new(views: u32) -> ImpostorLayout {
+        let views = views.max(1);
+        let columns = (views as f32).sqrt().ceil() as u32;
+        ImpostorLayout {
+            views: views,
+            columns: columns,
+            rows: (views + columns - 1) / columns,
+        }
+    }
+
+    /// The direction (in model space, from the center) view `i` is captured from
+    pub fn view_dir(&self, i: u32) -> Vector3<f32> {
+        let a = 2. * PI * i as f32 / self.views as f32;
+        Vector3::new(a.sin(), 0., a.cos())
+    }
+
+    /// The offset and size of the cell holding view `i`, in texture coordinates
+    pub fn cell(&self, i: u32) -> [f32; 4] {
+        let (w, h) = (1. / self.columns as f32, 1. / self.rows as f32);
+        [(i % self.columns) as f32 * w, (i / self.columns) as f32 * h, w, h]
+    }
+
+    /// The two views nearest to looking from the given model space direction, and how far
+    /// the direction is from the first towards the second
+    pub fn nearest_views(&self, dir: &Vector3<f32>) -> (u32, u32, f32) {
+        let a = dir.x.atan2(dir.z);
+        let a = if a < 0. { a + 2. * PI } else { a };
+        let t = a / (2. * PI) * self.views as f32;
+        let first = t.floor() as u32 % self.views;
+        (first, (first + 1) % self.views, t - t.floor())
+    }
+}
+
+/// Views of a mesh from evenly spaced directions around its vertical axis, rendered once
+/// into a grid of cells on an offscreen target
+pub struct ImpostorAtlas<R: Resources> {
+    /// The rendered views, cleared to transparent black around the mesh
+    pub target: OffscreenTarget<R>,
+    /// The model space normals of the same views, if baked with `bake_normals`
+    pub normals: Option<OffscreenTarget<R>>,
+    /// Where each view is in the atlas
+    pub layout: ImpostorLayout,
+    /// The center of the mesh bounds, in model space
+    pub center: Point3<f32>,
+    /// Half the width of the area captured by each view, in model units
+    pub radius: f32,
+    cell_size: u16,
+}
+
+impl<R: Resources> ImpostorAtlas<R> {
+    /// Render `views` views of a mesh into an atlas of square cells `cell_size` pixels across.
+    /// The mesh is drawn with the given painter, so its lighting is baked in as configured.
+    pub fn bake<F, C, E>(
+        f: &mut F,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, E>,
+        mesh: &Mesh<R, E::Vertex, E::Material>,
+        views: u32,
+        cell_size: u16,
+    )
+        -> Result<ImpostorAtlas<R>, Error>
+        where F: Factory<R>, C: CommandBuffer<R>, E: Style<R>
+    {
+        let layout = ImpostorLayout::new(views);
+        let target = OffscreenTarget::new(
+            f,
+            cell_size * layout.columns as u16,
+            cell_size * layout.rows as u16,
+        )?;
+        let (center, radius) = if mesh.bounds.is_empty() {
+            (Point3::origin(), 1.)
+        } else {
+            (mesh.bounds.center(), mesh.bounds.diagonal() / 2.)
+        };
+        let atlas = ImpostorAtlas {
+            target: target,
+            normals: None,
+            layout: layout,
+            center: center,
+            radius: radius.max(1e-3),
+            cell_size: cell_size,
+        };
+        atlas.draw_views(ctx, &atlas.target, [0., 0., 0., 0.], |ctx| painter.try_draw(ctx, na::one(), mesh))?;
+        Ok(atlas)
+    }
+
+    /// Render the model space normals of the same views of a mesh into `normals`, so that
+    /// impostors can be lit by the sun as they turn. The color views should then be baked
+    /// without lighting (such as with `UnlitStyle`, or uber materials that are fully flat),
+    /// or they will be lit twice. Call this before creating an `Impostor` from the atlas.
+    pub fn bake_normals<F, C>(
+        &mut self,
+        f: &mut F,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, ImpostorNormalStyle<R>>,
+        mesh: &Mesh<R, VertNTT, UberMaterial<R>>,
+    )
+        -> Result<(), Error>
+        where F: Factory<R>, C: CommandBuffer<R>
+    {
+        let normals = OffscreenTarget::new(f, self.target.width, self.target.height)?;
+        self.draw_views(ctx, &normals, [0.5, 0.5, 0.5, 0.], |ctx| painter.try_draw(ctx, na::one(), mesh))?;
+        self.normals = Some(normals);
+        Ok(())
+    }
+
+    /// Clear `target` and draw every view into it with `draw`, restoring the draw
+    /// parameters afterwards
+    fn draw_views<C, D>(&self, ctx: &mut DrawParams<R, C>, target: &OffscreenTarget<R>, clear: [f32; 4], mut draw: D)
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, D: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        let saved = (ctx.color.clone(), ctx.depth.clone());
+        ctx.encoder.clear(&target.color, clear);
+        ctx.encoder.clear_depth(&target.depth, super::DEPTH_CONVENTION.far());
+        ctx.color = target.color.clone();
+        ctx.depth = target.depth.clone();
+        let mut result = Ok(());
+        for i in 0..self.layout.views {
+            ctx.push_eye(self.view_eye(i, self.cell_size));
+            result = draw(ctx);
+            ctx.pop_camera();
+            if result.is_err() { break }
+        }
+        ctx.color = saved.0;
+        ctx.depth = saved.1;
+        result
+    }
+
+    /// An orthographic eye drawing view `i` into its cell
+    fn view_eye(&self, i: u32, cell_size: u16) -> EyeParams {
+        let l = &self.layout;
+        let (col, row) = (i % l.columns, i / l.columns);
+        let r = self.radius;
+        let eye = self.center + l.view_dir(i) * r * 2.;
+        let view = Isometry3::look_at_rh(&eye, &self.center, &Vector3::y());
+        let ortho = Orthographic3::new(-r, r, -r, r, r, r * 3.).to_homogeneous();
+        // squeeze clip space into the cell, doubling x since the transform shader halves it
+        let (n, m) = (l.columns as f32, l.rows as f32);
+        let mut cell = Matrix4::identity();
+        cell[(0, 0)] = 2. / n;
+        cell[(0, 3)] = 2. * ((2 * col + 1) as f32 / n - 1.);
+        cell[(1, 1)] = 1. / m;
+        cell[(1, 3)] = (2 * row + 1) as f32 / m - 1.;
+        EyeParams {
+            eye: eye,
+            view: Transform3::from_matrix_unchecked(view.to_homogeneous()),
+            proj: Transform3::from_matrix_unchecked(cell * ortho),
+            clip_offset: 0.,
+            clip: Rect {
+                x: col as u16 * cell_size,
+                y: row as u16 * cell_size,
+                w: cell_size,
+                h: cell_size,
+            },
+        }
+    }
+}
+
+/// The color and normal views of an `ImpostorAtlas`
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ImpostorMaterial<R: Resources> {
+    /// The color views, in display space
+    pub color: Texture<R, ColorFormat>,
+    /// The model space normals of the views, or `None` to show the colors unlit
+    pub normals: Option<Texture<R, ColorFormat>>,
+}
+
+/// The configuration for impostor rendering
+pub struct ImpostorInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    params: Option<ImpostorBlock>,
+    params_block: Buffer<R, ImpostorBlock>,
+    sun_dir: Vector3<f32>,
+    sun_color: [f32; 4],
+    ambient: [f32; 4],
+    /// Atlas texels less opaque than this are discarded
+    pub alpha_cutoff: f32,
+}
+
+impl<R: Resources> ImpostorInputs<R> {
+    /// Show the cells of the given views (see `ImpostorLayout::cell`), blended by `blend`,
+    /// with `fade` of the pixels kept (1 draws the whole impostor)
+    pub fn view(&mut self, cell_a: [f32; 4], cell_b: [f32; 4], blend: f32, fade: f32) {
+        self.params = Some(ImpostorBlock {
+            cell_a: cell_a,
+            cell_b: cell_b,
+            sun_dir: self.sun_dir.to_homogeneous().downgrade(),
+            sun_color: self.sun_color,
+            ambient: self.ambient,
+            blend: blend,
+            fade: fade,
+            alpha_cutoff: self.alpha_cutoff,
+        });
+    }
+
+    /// Light impostors that have normals with the sun of an environment
+    pub fn set_sun(&mut self, env: &UberEnv<R>) {
+        let c = env.sun_color;
+        self.sun_dir = env.sun_rotation * Vector3::z();
+        self.sun_color = [c[0] * c[3], c[1] * c[3], c[2] * c[3], 1.];
+    }
+
+    /// Light impostors that have normals evenly from every direction, as a stand in for the sky
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = [ambient[0], ambient[1], ambient[2], 0.];
+    }
+
+    /// Turn the sun of the current view into the model space of an impostor, and light it
+    /// if it has normals
+    fn light_model(&mut self, model: &Transform3<f32>, lit: bool) {
+        let dir = model.try_inverse()
+            .and_then(|inv| (inv * self.sun_dir).try_normalize(1e-6))
+            .unwrap_or(self.sun_dir);
+        if let Some(ref mut p) = self.params {
+            p.sun_dir = [dir.x, dir.y, dir.z, if lit { 1. } else { 0. }];
+        }
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for ImpostorInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Draws flat stand-ins for distant meshes from an `ImpostorAtlas`, alpha tested at the
+/// edges of the mesh
+pub struct ImpostorStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for ImpostorStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = ImpostorInputs<R>;
+    type Material = ImpostorMaterial<R>;
+    type Bound = pl::Data<R>;
+
+    fn sampled(mat: &ImpostorMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        let mut views = vec![mat.color.buffer.raw().clone()];
+        views.extend(mat.normals.as_ref().map(|n| n.buffer.raw().clone()));
+        views
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut ImpostorInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(ImpostorStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<ImpostorInputs<R>, Error> {
+        Ok(ImpostorInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            params: None,
+            params_block: f.create_constant_buffer(1),
+            sun_dir: Vector3::y(),
+            sun_color: [1., 1., 1., 1.],
+            ambient: [0.3, 0.3, 0.3, 0.],
+            alpha_cutoff: 0.5,
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &ImpostorInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &ImpostorMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            params: inputs.params_block.clone(),
+            atlas: mat.color.clone().into_tuple(),
+            normals: mat.normals.as_ref().unwrap_or(&mat.color).clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut ImpostorInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        if let Some(p) = inputs.params.take() {
+            enc.update_constant_buffer(&inputs.params_block, &p);
+        }
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
+
+/// The configuration for baking impostor normals
+pub struct ImpostorNormalInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+}
+
+impl<R: Resources> StyleInputs<R> for ImpostorNormalInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Draws the model space normals of uber meshes, normal mapped, for
+/// `ImpostorAtlas::bake_normals`. Texels with albedo less than half opaque are cut out.
+pub struct ImpostorNormalStyle<R: Resources> {
+    pso: PipelineState<R, normal_pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for ImpostorNormalStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = ImpostorNormalInputs<R>;
+    type Material = UberMaterial<R>;
+    type Bound = normal_pl::Data<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut ImpostorNormalInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(ImpostorNormalStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, normal_pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<ImpostorNormalInputs<R>, Error> {
+        Ok(ImpostorNormalInputs {
+            shaders: normal_shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &ImpostorNormalInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &UberMaterial<R>,
+    ) -> normal_pl::Data<R> {
+        normal_pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            normal: mat.normal.clone().into_tuple(),
+            albedo: mat.albedo.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut ImpostorNormalInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut normal_pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
+
+/// An object drawn as real meshes up close and as an impostor in the distance. The
+/// impostor replaces the low detail level of a `DrawDistance`, fading in over `fade`
+/// units past `lod2` while the medium detail mesh is still drawn, so the switch doesn't pop.
+pub struct Impostor<R: Resources> {
+    /// The baked views
+    pub atlas: ImpostorAtlas<R>,
+    /// The distance over which the impostor fades in
+    pub fade: f32,
+    /// Blend between the two nearest views instead of showing the nearest one
+    pub blend_views: bool,
+    quad: Mesh<R, VertNTT, ImpostorMaterial<R>>,
+}
+
+impl<R: Resources> Impostor<R> {
+    /// Create an impostor showing the given atlas, lit by the sun of the impostor painter if
+    /// the atlas has normals
+    pub fn new<F: Factory<R>>(f: &mut F, atlas: ImpostorAtlas<R>) -> Impostor<R> {
+        let mat = ImpostorMaterial {
+            color: atlas.target.texture.clone(),
+            normals: atlas.normals.as_ref().map(|n| n.texture.clone()),
+        };
+        let size = atlas.radius * 2.;
+        Impostor {
+            quad: gen::quad(size, size).map_material(|_| mat).upload(f),
+            atlas: atlas,
+            fade: 2.,
+            blend_views: true,
+        }
+    }
+
+    /// Draw the object at the given placement, choosing between `[full, medium]` detail
+    /// meshes and the impostor by distance. Returns the level that was chosen, where `Low`
+    /// means the impostor.
+    pub fn try_draw<C, E>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, E>,
+        impostor_painter: &Painter<R, ImpostorStyle<R>>,
+        model: Transform3<f32>,
+        distance: &DrawDistance,
+        lods: [&Mesh<R, E::Vertex, E::Material>; 2],
+    )
+        -> Result<LodLevel, Error>
+        where C: CommandBuffer<R>, E: Style<R>
+    {
+        let dist = camera_distance(ctx, &model);
+        let level = distance.select(dist);
+        let fade = if self.fade > 0. { ((dist - distance.lod2) / self.fade).max(0.).min(1.) } else { 1. };
+        match level {
+            LodLevel::Full => painter.try_draw(ctx, model, lods[0])?,
+            LodLevel::Medium => painter.try_draw(ctx, model, lods[1])?,
+            LodLevel::Low => {
+                if fade < 1. {
+                    painter.try_draw(ctx, model, lods[1])?;
+                }
+                self.draw_impostor(ctx, impostor_painter, &model, fade)?;
+            },
+            LodLevel::Culled => (),
+        }
+        Ok(level)
+    }
+
+    fn draw_impostor<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, ImpostorStyle<R>>,
+        model: &Transform3<f32>,
+        fade: f32,
+    )
+        -> Result<(), Error>
+    {
+        let a = &self.atlas;
+        let center = model * a.center;
+        let viewer = Point3::from_coordinates((ctx.left.eye.coords + ctx.right.eye.coords) * 0.5);
+        let inv = match model.try_inverse() {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let (first, second, t) = a.layout.nearest_views(&(inv * (viewer - center)));
+        let (first, t) = if self.blend_views { (first, t) } else if t < 0.5 { (first, 0.) } else { (second, 0.) };
+        let lit = self.quad.mat.normals.is_some();
+        painter.cfg(|i| {
+            i.view(a.layout.cell(first), a.layout.cell(second), t, fade);
+            i.light_model(model, lit);
+        });
+
+        // turn about the vertical to face the viewer, keeping the scale of the model
+        let to_viewer = viewer - center;
+        let yaw = to_viewer.x.atan2(to_viewer.z);
+        let scale = (model * Vector3::x()).norm();
+        let billboard = Isometry3::new(center.coords, Vector3::y() * yaw).to_homogeneous()
+            * Matrix4::new_scaling(scale);
+        painter.try_draw(ctx, Transform3::from_matrix_unchecked(billboard), &self.quad)
+    }
+}
+
+#[test]
+fn impostor_views() {
+    let atlas = ImpostorLayout::new(8);
+    assert_eq!((atlas.columns, atlas.rows), (3, 3));
+    let (a, b, t) = atlas.nearest_views(&Vector3::z());
+    assert_eq!((a, b), (0, 1));
+    assert!(relative_eq!(t, 0.));
+    let (a, b, t) = atlas.nearest_views(&Vector3::new(-(PI / 8.).sin(), 0., (PI / 8.).cos()));
+    assert_eq!((a, b), (7, 0));
+    assert!(relative_eq!(t, 0.5, epsilon = 1e-5));
+    assert!(relative_eq!(atlas.view_dir(2), Vector3::x(), epsilon = 1e-5));
+    let c = atlas.cell(4);
+    assert!(relative_eq!(c[0], 1. / 3.) && relative_eq!(c[1], 1. / 3.));
+}
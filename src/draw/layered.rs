@@ -0,0 +1,245 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView, ShaderResourceView, Sampler};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+
+use super::{Style, TransformBlock};
+use super::uber::{self, UberStyle, UberMaterial, UberInputs, UberBound, ShaderVariantKey, LinearFormat};
+use super::uber::{ParamsBlock, PreviousTransformBlock, SurfaceBlock, AreaLightBlock, VelocityFormat};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The most layers a `LayeredMaterial` blends over its base
+pub const MAX_MATERIAL_LAYERS: usize = 2;
+
+/// The format of layer masks, white where the layer covers the material below it
+pub type MaskFormat = (R8, Unorm);
+
+/// An uber material with layers such as snow, mud or damage blended over it, each where
+/// its mask is white. Layers are blended in order, so later layers cover earlier ones, and
+/// only the first `MAX_MATERIAL_LAYERS` are drawn. The bent normals, surface and normal
+/// encoding of the base apply to the whole material, and layer normal maps are read as
+/// `NormalEncoding::Rgb`.
+#[derive(Clone, PartialEq)]
+pub struct LayeredMaterial<R: Resources> {
+    pub base: UberMaterial<R>,
+    pub layers: Vec<(UberMaterial<R>, Texture<R, MaskFormat>)>,
+}
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        previous: gfx::ConstantBuffer<PreviousTransformBlock> = "previous_transform",
+        params: gfx::ConstantBuffer<ParamsBlock> = "params",
+        surface: gfx::ConstantBuffer<SurfaceBlock> = "surface",
+        area_lights: gfx::ConstantBuffer<AreaLightBlock> = "area_lights_layout",
+        scissor: gfx::Scissor = (),
+
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        velocity: gfx::RenderTarget<VelocityFormat> = "f_velocity",
+
+        normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+        knobs: gfx::TextureSampler<[f32; 4]> = "knobs_tex",
+        bent: gfx::TextureSampler<[f32; 4]> = "bent_tex",
+        irradiance: gfx::TextureSampler<[f32; 3]> = "irradiance_map",
+        radiance: gfx::TextureSampler<[f32; 3]> = "radiance_map",
+        irradiance_b: gfx::TextureSampler<[f32; 3]> = "irradiance_map_b",
+        radiance_b: gfx::TextureSampler<[f32; 3]> = "radiance_map_b",
+        integrated_brdf: gfx::TextureSampler<[f32; 2]> = "integrated_brdf_map",
+        ltc_matrix: gfx::TextureSampler<[f32; 2]> = "ltc_matrix_map",
+        ltc_norm: gfx::TextureSampler<[f32; 2]> = "ltc_norm_map",
+
+        shadow_depth: gfx::TextureSampler<f32> = "shadow_depth",
+        sun_cookie: gfx::TextureSampler<[f32; 4]> = "sun_cookie_tex",
+        sky_occlusion: gfx::TextureSampler<f32> = "sky_occlusion_tex",
+        voxel_x: gfx::TextureSampler<[f32; 4]> = "voxel_x",
+        voxel_y: gfx::TextureSampler<[f32; 4]> = "voxel_y",
+        voxel_z: gfx::TextureSampler<[f32; 4]> = "voxel_z",
+
+        layer0_normal: gfx::TextureSampler<[f32; 4]> = "layer0_normal",
+        layer0_albedo: gfx::TextureSampler<[f32; 4]> = "layer0_albedo",
+        layer0_knobs: gfx::TextureSampler<[f32; 4]> = "layer0_knobs",
+        layer0_mask: gfx::TextureSampler<f32> = "layer0_mask",
+        layer1_normal: gfx::TextureSampler<[f32; 4]> = "layer1_normal",
+        layer1_albedo: gfx::TextureSampler<[f32; 4]> = "layer1_albedo",
+        layer1_knobs: gfx::TextureSampler<[f32; 4]> = "layer1_knobs",
+        layer1_mask: gfx::TextureSampler<f32> = "layer1_mask",
+    }
+}
+
+type Sampled<R, T> = (ShaderResourceView<R, T>, Sampler<R>);
+
+/// The textures of one layer as bound to the pipeline
+#[derive(Clone)]
+struct LayerSlot<R: Resources> {
+    normal: Sampled<R, [f32; 4]>,
+    albedo: Sampled<R, [f32; 4]>,
+    knobs: Sampled<R, [f32; 4]>,
+    mask: Sampled<R, f32>,
+}
+
+impl<R: Resources> LayerSlot<R> {
+    fn new(mat: &UberMaterial<R>, mask: &Texture<R, MaskFormat>) -> LayerSlot<R> {
+        LayerSlot {
+            normal: mat.normal.clone().into_tuple(),
+            albedo: mat.albedo.clone().into_tuple(),
+            knobs: mat.knobs.clone().into_tuple(),
+            mask: mask.clone().into_tuple(),
+        }
+    }
+}
+
+/// Pipeline data bound by `LayeredStyle`: the base as bound by `UberStyle`, and the layers
+pub struct LayeredBound<R: Resources> {
+    base: UberBound<R>,
+    layers: [LayerSlot<R>; MAX_MATERIAL_LAYERS],
+}
+
+/// Draws meshes like `UberStyle`, blending the layers of a `LayeredMaterial` over its base.
+/// It takes the same inputs as `UberStyle`, and only the layer textures are added to each
+/// draw, with the environment and lighting shared by every layer.
+pub struct LayeredStyle<R: Resources> {
+    // indexed by `ShaderVariantKey`
+    psos: Vec<PipelineState<R, pl::Meta>>,
+    // bound to unused layers, with an empty mask
+    blank: LayerSlot<R>,
+}
+
+impl<R: Resources> Style<R> for LayeredStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = UberInputs<R>;
+    type Material = LayeredMaterial<R>;
+    type Bound = LayeredBound<R>;
+
+    fn sampled(mat: &LayeredMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        let mut sampled = UberStyle::sampled(&mat.base);
+        for &(ref layer, ref mask) in mat.layers.iter().take(MAX_MATERIAL_LAYERS) {
+            sampled.extend(UberStyle::sampled(layer));
+            sampled.push(mask.buffer.raw().clone());
+        }
+        sampled
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut UberInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        let mut psos = vec![];
+        for key in ShaderVariantKey::all(1 << ShaderVariantKey::FEATURES) {
+            let shaders = i.shader_variant_with(f, key, &[("LAYERS", "")])?;
+            psos.push(f.create_pipeline_state(&shaders, p, r, pl::new())?);
+        }
+        let flat: Texture<R, LinearFormat> = Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?;
+        Ok(LayeredStyle {
+            psos: psos,
+            blank: LayerSlot {
+                normal: flat.clone().into_tuple(),
+                albedo: Texture::<R, (R8_G8_B8_A8, Srgb)>::uniform_value(f, [0xFF; 4])?.into_tuple(),
+                knobs: flat.into_tuple(),
+                mask: Texture::<R, MaskFormat>::uniform_value(f, 0)?.into_tuple(),
+            },
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<UberInputs<R>, Error> {
+        UberStyle::init(f)
+    }
+
+    fn bind(
+        &self,
+        inputs: &UberInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, VertNTT>,
+        mat: &LayeredMaterial<R>,
+    ) -> LayeredBound<R> {
+        if mat.layers.len() > MAX_MATERIAL_LAYERS {
+            warn!("only drawing {} of {} material layers", MAX_MATERIAL_LAYERS, mat.layers.len());
+        }
+        let layer = |i: usize| match mat.layers.get(i) {
+            Some(&(ref layer, ref mask)) => LayerSlot::new(layer, mask),
+            None => self.blank.clone(),
+        };
+        LayeredBound {
+            base: uber::bind_uber(inputs, color, depth, buf, &mat.base),
+            layers: [layer(0), layer(1)],
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut UberInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut LayeredBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let key = uber::prepare_uber(inputs, enc, scissor, &mut bound.base)?;
+        let base = &bound.base.data;
+        let layers = &bound.layers;
+        enc.draw(slice, &self.psos[key.0 as usize], &pl::Data {
+            verts: base.verts.clone(),
+            transform: base.transform.clone(),
+            previous: base.previous.clone(),
+            params: base.params.clone(),
+            surface: base.surface.clone(),
+            area_lights: base.area_lights.clone(),
+            scissor: base.scissor,
+            color: base.color.clone(),
+            depth: base.depth.clone(),
+            velocity: base.velocity.clone(),
+            normal: base.normal.clone(),
+            albedo: base.albedo.clone(),
+            knobs: base.knobs.clone(),
+            bent: base.bent.clone(),
+            irradiance: base.irradiance.clone(),
+            radiance: base.radiance.clone(),
+            irradiance_b: base.irradiance_b.clone(),
+            radiance_b: base.radiance_b.clone(),
+            integrated_brdf: base.integrated_brdf.clone(),
+            ltc_matrix: base.ltc_matrix.clone(),
+            ltc_norm: base.ltc_norm.clone(),
+            shadow_depth: base.shadow_depth.clone(),
+            sun_cookie: base.sun_cookie.clone(),
+            sky_occlusion: base.sky_occlusion.clone(),
+            voxel_x: base.voxel_x.clone(),
+            voxel_y: base.voxel_y.clone(),
+            voxel_z: base.voxel_z.clone(),
+            layer0_normal: layers[0].normal.clone(),
+            layer0_albedo: layers[0].albedo.clone(),
+            layer0_knobs: layers[0].knobs.clone(),
+            layer0_mask: layers[0].mask.clone(),
+            layer1_normal: layers[1].normal.clone(),
+            layer1_albedo: layers[1].albedo.clone(),
+            layer1_knobs: layers[1].knobs.clone(),
+            layer1_mask: layers[1].mask.clone(),
+        });
+        Ok(())
+    }
+}
+
+#[test]
+fn layer_slots() {
+    // the shader samples and blends one set of textures for each layer slot
+    let fragment = static_file!("shaders/uber.f.glsl").build().unwrap();
+    for i in 0..MAX_MATERIAL_LAYERS {
+        for role in &["normal", "albedo", "knobs", "mask"] {
+            assert!(fragment.contains(&format!("uniform sampler2D layer{}_{};", i, role)));
+        }
+        assert!(fragment.contains(&format!("blend_layer(layer{}_normal", i)));
+    }
+    assert!(!fragment.contains(&format!("layer{}_", MAX_MATERIAL_LAYERS)));
+}
@@ -0,0 +1,287 @@
+use gfx::{Resources, CommandBuffer};
+use nalgebra::{Transform3, Point3};
+
+use super::{Painter, Style, DrawParams, AlphaHashStyle, AlphaHashMaterial};
+use ::mesh::{Mesh, VertexData, VertNTT};
+use ::Error;
+
+/// The level of detail chosen for an object
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LodLevel {
+    /// The highest detail mesh
+    Full,
+    /// The medium detail mesh
+    Medium,
+    /// The lowest detail mesh
+    Low,
+    /// Too far away to be drawn at all
+    Culled,
+}
+
+/// Camera distances at which an object switches level of detail. Distances should
+/// satisfy `lod1 <= lod2 <= cull`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrawDistance {
+    /// Always use the highest detail within this distance, regardless of the other thresholds
+    pub full: f32,
+    /// Switch to the medium detail mesh beyond this distance
+    pub lod1: f32,
+    /// Switch to the low detail mesh beyond this distance
+    pub lod2: f32,
+    /// Skip drawing entirely beyond this distance
+    pub cull: f32,
+}
+
+impl DrawDistance {
+    /// Reasonable draw distances for an object whose bounding box has the given diagonal length
+    pub fn from_diagonal(aabb_diagonal: f32) -> DrawDistance {
+        let d = aabb_diagonal.abs().max(0.01);
+        DrawDistance {
+            full: d * 2.,
+            lod1: d * 10.,
+            lod2: d * 25.,
+            cull: d * 80.,
+        }
+    }
+
+    /// Draw at full detail, never culled
+    pub fn always() -> DrawDistance {
+        use std::f32::INFINITY;
+        DrawDistance {
+            full: INFINITY,
+            lod1: INFINITY,
+            lod2: INFINITY,
+            cull: INFINITY,
+        }
+    }
+
+    /// The level of detail to use at the given camera distance
+    pub fn select(&self, distance: f32) -> LodLevel {
+        if distance <= self.full {
+            LodLevel::Full
+        } else if distance > self.cull {
+            LodLevel::Culled
+        } else if distance > self.lod2 {
+            LodLevel::Low
+        } else if distance > self.lod1 {
+            LodLevel::Medium
+        } else {
+            LodLevel::Full
+        }
+    }
+}
+
+/// The distance from the point between the eyes to the origin of the given model
+pub fn camera_distance<R, C>(ctx: &DrawParams<R, C>, model: &Transform3<f32>) -> f32
+    where R: Resources, C: CommandBuffer<R>
+{
+    let center = Point3::from_coordinates((ctx.left.eye.coords + ctx.right.eye.coords) * 0.5);
+    let pos = model * Point3::origin();
+    (pos - center).norm()
+}
+
+impl<R: Resources, E: Style<R>> Painter<R, E> {
+    /// Draw one of three levels of detail of an object (`[full, medium, low]`) depending on
+    /// its distance from the viewer, or nothing if it is beyond the cull distance.
+    /// Returns the level that was chosen.
+    pub fn try_draw_lod<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        distance: &DrawDistance,
+        lods: [&Mesh<R, E::Vertex, E::Material>; 3],
+    )
+        -> Result<LodLevel, Error>
+        where C: CommandBuffer<R>
+    {
+        let level = distance.select(camera_distance(ctx, &model));
+        let mesh = match level {
+            LodLevel::Full => lods[0],
+            LodLevel::Medium => lods[1],
+            LodLevel::Low => lods[2],
+            LodLevel::Culled => return Ok(level),
+        };
+        self.try_draw(ctx, model, mesh)?;
+        Ok(level)
+    }
+
+    /// Draw one of three levels of detail of an object depending on its distance from the
+    /// viewer, logging any errors.
+    pub fn draw_lod<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        distance: &DrawDistance,
+        lods: [&Mesh<R, E::Vertex, E::Material>; 3],
+    )
+        where C: CommandBuffer<R>
+    {
+        if let Err(e) = self.try_draw_lod(ctx, model, distance, lods) {
+            error!("{}", e);
+        }
+    }
+}
+
+/// A cross-fade between two levels of detail of an object, so that it doesn't visibly pop
+/// from one to the other as the viewer moves. `t` runs from 0 (only `near` is drawn) to 1
+/// (only `far` is drawn), moving towards one end or the other at a set speed once the camera
+/// crosses the switch distance. The switch only happens `hysteresis / 2` past it either
+/// way, so standing at the switch distance doesn't keep fading back and forth.
+pub struct LodTransition<R: Resources, T: VertexData, M> {
+    /// The more detailed mesh, shown close up
+    pub near: Mesh<R, T, M>,
+    /// The less detailed mesh, shown far away
+    pub far: Mesh<R, T, M>,
+    /// The camera distance at which the meshes switch
+    pub switch: f32,
+    /// The width of the band around `switch` within which the meshes don't switch
+    pub hysteresis: f32,
+    fade: Fade,
+}
+
+/// How far a `LodTransition` has faded and which end it is heading for
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Fade {
+    t: f32,
+    target: f32,
+}
+
+impl Fade {
+    fn update(&mut self, camera_dist: f32, switch: f32, hysteresis: f32, dt: f32, transition_speed: f32) {
+        let band = hysteresis.abs() * 0.5;
+        if camera_dist > switch + band {
+            self.target = 1.;
+        } else if camera_dist < switch - band {
+            self.target = 0.;
+        }
+        let step = (dt * transition_speed).max(0.);
+        self.t = if self.target > self.t {
+            (self.t + step).min(self.target)
+        } else {
+            (self.t - step).max(self.target)
+        };
+    }
+
+    fn is_active(&self) -> bool {
+        self.t > 0. && self.t < 1.
+    }
+}
+
+impl<R: Resources, T: VertexData, M> LodTransition<R, T, M> {
+    /// Fade between two meshes at a camera distance, starting with the near one
+    pub fn new(near: Mesh<R, T, M>, far: Mesh<R, T, M>, switch: f32, hysteresis: f32) -> LodTransition<R, T, M> {
+        LodTransition {
+            near: near,
+            far: far,
+            switch: switch,
+            hysteresis: hysteresis,
+            fade: Fade::default(),
+        }
+    }
+
+    /// How far the fade is from `near` (0) to `far` (1)
+    pub fn t(&self) -> f32 {
+        self.fade.t
+    }
+
+    /// Move the fade towards the mesh for the camera distance by `transition_speed` (in
+    /// whole fades per second) times the `dt` seconds since the last update
+    pub fn update(&mut self, camera_dist: f32, dt: f32, transition_speed: f32) {
+        self.fade.update(camera_dist, self.switch, self.hysteresis, dt, transition_speed);
+    }
+
+    /// True while both meshes are drawn
+    pub fn is_active(&self) -> bool {
+        self.fade.is_active()
+    }
+
+    /// The near and far meshes with the opacity to draw each with, only while fading
+    pub fn both_meshes(&self) -> Option<(&Mesh<R, T, M>, f32, &Mesh<R, T, M>, f32)> {
+        if self.is_active() {
+            Some((&self.near, 1. - self.fade.t, &self.far, self.fade.t))
+        } else {
+            None
+        }
+    }
+
+    /// The one mesh drawn when not fading, the closer to the end of the fade otherwise
+    pub fn mesh(&self) -> &Mesh<R, T, M> {
+        if self.fade.t < 0.5 { &self.near } else { &self.far }
+    }
+}
+
+impl<R: Resources> Painter<R, AlphaHashStyle<R>> {
+    /// Draw a `LodTransition` of alpha hashed meshes, multiplying the opacity of their
+    /// materials by their weights while fading. The weights are given with
+    /// `AlphaHashInputs::set_fade`, so the meshes keep their cached bindings. The two hashes are independent, so mid fade
+    /// some pixels keep neither mesh until temporal anti-aliasing fills them in.
+    pub fn try_draw_lod_transition<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        lod: &LodTransition<R, VertNTT, AlphaHashMaterial<R>>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        match lod.both_meshes() {
+            Some((near, near_alpha, far, far_alpha)) => {
+                let mut result = Ok(());
+                for &(mesh, alpha) in &[(near, near_alpha), (far, far_alpha)] {
+                    self.cfg(|i| i.set_fade(alpha));
+                    result = self.try_draw(ctx, model, mesh);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                self.cfg(|i| i.set_fade(1.));
+                result
+            },
+            None => self.try_draw(ctx, model, lod.mesh()),
+        }
+    }
+}
+
+#[test]
+fn lod_selection() {
+    let d = DrawDistance { full: 1., lod1: 5., lod2: 10., cull: 20. };
+    assert_eq!(d.select(0.5), LodLevel::Full);
+    assert_eq!(d.select(3.), LodLevel::Full);
+    assert_eq!(d.select(7.), LodLevel::Medium);
+    assert_eq!(d.select(15.), LodLevel::Low);
+    assert_eq!(d.select(25.), LodLevel::Culled);
+
+    // objects held close stay detailed even with tiny lod thresholds
+    let d = DrawDistance { full: 2., lod1: 0., lod2: 0., cull: 0. };
+    assert_eq!(d.select(1.), LodLevel::Full);
+    assert_eq!(DrawDistance::always().select(1e30), LodLevel::Full);
+}
+
+#[test]
+fn lod_fade() {
+    let mut f = Fade::default();
+    // within the hysteresis band nothing starts
+    f.update(10.5, 10., 2., 0.1, 2.);
+    assert_eq!(f, Fade::default());
+    assert!(!f.is_active());
+
+    // past the band the fade moves towards far at the given speed
+    f.update(11.5, 10., 2., 0.1, 2.);
+    assert!(relative_eq!(f.t, 0.2));
+    assert!(f.is_active());
+    // coming back into the band keeps heading the same way
+    f.update(9.5, 10., 2., 0.1, 2.);
+    assert!(relative_eq!(f.t, 0.4));
+    // and the fade stops at the end
+    f.update(9.5, 10., 2., 1., 2.);
+    assert_eq!(f.t, 1.);
+    assert!(!f.is_active());
+
+    // crossing the far side of the band turns it around, and time never runs backwards
+    f.update(8.5, 10., 2., 0.25, 2.);
+    assert!(relative_eq!(f.t, 0.5));
+    f.update(8.5, 10., 2., -1., 2.);
+    assert!(relative_eq!(f.t, 0.5));
+    f.update(8.5, 10., 2., 1., 2.);
+    assert_eq!(f.t, 0.);
+}
@@ -1,20 +1,40 @@
-use gfx::{Resources, Encoder, Primitive, Rect, CommandBuffer, Slice, ShaderSet, Factory};
-use gfx::handle::Buffer;
+use gfx::{Resources, Encoder, Primitive, Rect, CommandBuffer, Slice, ShaderSet, Factory, IndexBuffer};
+use gfx::handle::{Buffer, RawShaderResourceView};
 use gfx::traits::FactoryExt;
 use gfx::state::Rasterizer;
 use nalgebra::{Transform3};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHasher};
 use failure::Fail;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::hash::{Hash, Hasher};
 
 use ::{DepthRef, TargetRef, Error, FlightError, NativeRepr};
-use ::mesh::{Mesh, Vertex};
+use ::mesh::{Mesh, MultiMesh, VertexData, Aabb};
 
 #[macro_use]
-mod shaders;
+pub(crate) mod shaders;
 mod context;
 pub use self::context::*;
 
+mod debug;
+pub use self::debug::DebugDraw;
+
+mod stats;
+pub use self::stats::{FrameStats, StatsOverlay, StatsFields};
+
+mod pass;
+pub use self::pass::{PassManager, PassDesc, TargetId};
+
+mod lod;
+pub use self::lod::{DrawDistance, LodLevel, LodTransition, camera_distance};
+
+mod target;
+pub use self::target::OffscreenTarget;
+
+mod present;
+pub use self::present::{Present, PresentTarget, SurfaceEncoding, LenticularCompositor, calibration_pattern};
+
 mod solid;
 pub use self::solid::{SolidStyle, SolidInputs};
 
@@ -25,7 +45,63 @@ mod pbr;
 pub use self::pbr::{PbrStyle, PbrMaterial, PbrInputs, LIGHT_COUNT};
 
 mod uber;
-pub use self::uber::{UberStyle, UberMaterial, UberInputs, UberEnv};
+pub use self::uber::{UberStyle, UberMaterial, UberInputs, UberEnv, SunCookie, LinearFormat, LinearChannel};
+pub use self::uber::{ShaderVariantKey, VelocityFormat, NormalEncoding, MAX_PRECOMPILE_VARIANTS};
+
+mod layered;
+pub use self::layered::{LayeredStyle, LayeredMaterial, LayeredBound, MaskFormat, MAX_MATERIAL_LAYERS};
+
+mod virtual_texture;
+pub use self::virtual_texture::{VirtualTextureStyle, VirtualMaterial, VirtualBound};
+
+mod unlit;
+pub use self::unlit::{UnlitStyle, UnlitMaterial, UnlitInputs};
+
+mod alpha_hash;
+pub use self::alpha_hash::{AlphaHashStyle, AlphaHashMaterial, AlphaHashInputs};
+
+mod impostor;
+pub use self::impostor::{ImpostorStyle, ImpostorInputs, ImpostorAtlas, ImpostorLayout, Impostor};
+pub use self::impostor::{ImpostorMaterial, ImpostorNormalStyle, ImpostorNormalInputs};
+
+/// Post-processing passes
+pub mod post;
+
+/// Sky rendering
+pub mod sky;
+
+mod probe;
+pub use self::probe::{ReflectionProbe, ProbeFilter, ProbeRefresh, ProbeFormat, IblBakeConfig};
+
+mod capture;
+pub use self::capture::{Stereo360Capture, EquirectFormat};
+
+mod gi;
+pub use self::gi::{VoxelGrid, VoxelStyle, VoxelInputs, VoxelFormat};
+
+mod particles;
+pub use self::particles::{ParticleSystem, ParticleSim, ParticlePainter, ParticleInstance, Emitter, EmitterShape};
+
+mod oit;
+pub use self::oit::{OitStyle, OitInputs, OitTargets, OitCompositor, TransparencyMode, TransparentPass, AccumFormat, RevealFormat};
+
+mod screen;
+pub use self::screen::{ScreenStyle, ScreenInputs};
+
+mod sdf;
+pub use self::sdf::{SdfStyle, SdfFontMaterial, SdfInputs};
+
+mod quantized;
+pub use self::quantized::{QuantizedStyle, QuantizedMaterial, QuantizedInputs};
+
+mod volume;
+pub use self::volume::{VolumeStyle, VolumeMaterial, VolumeData, VolumeInputs, VolumeMode, VOLUME_MODES, volume_box};
+
+mod water;
+pub use self::water::{WaterStyle, WaterMaterial, WaterInputs, WaterLayer, WaterTargets, ReflectionMode, reflect_eye};
+
+mod visibility;
+pub use self::visibility::{VisibilityBuffer, VisibilityMesh, VisibilityFormat, NO_TRIANGLE};
 
 /// The painter is responsible for drawing meshes. Painters
 /// are instantiated with an associated style which specifies
@@ -36,6 +112,24 @@ pub use self::uber::{UberStyle, UberMaterial, UberInputs, UberEnv};
 pub struct Painter<R: Resources, E: Style<R>> {
     inputs: RefCell<E::Inputs>,
     map: FnvHashMap<Primitive, E>,
+    biased: FnvHashMap<(Primitive, DepthBias), E>,
+    bindings: RefCell<FnvHashMap<u64, Vec<Binding<R, E>>>>,
+    swept: Cell<u64>,
+    debug: Option<Rc<RefCell<DebugDraw>>>,
+    lens: Cell<Option<LensShading>>,
+    layer_mask: Cell<u32>,
+}
+
+/// Pipeline data cached for a particular mesh and material, along with the
+/// handles it was bound to so that hash collisions and target changes are detected
+struct Binding<R: Resources, E: Style<R>> {
+    buf: Buffer<R, E::Vertex>,
+    mat: E::Material,
+    color: TargetRef<R>,
+    depth: DepthRef<R>,
+    bound: E::Bound,
+    /// The last frame the binding was drawn in
+    used: u64,
 }
 
 impl<R: Resources, E: Style<R>> Painter<R, E> {
@@ -44,6 +138,12 @@ impl<R: Resources, E: Style<R>> Painter<R, E> {
         Ok(Painter {
             inputs: RefCell::new(E::init(f)?),
             map: Default::default(),
+            biased: Default::default(),
+            bindings: Default::default(),
+            swept: Cell::new(0),
+            debug: None,
+            lens: Cell::new(None),
+            layer_mask: Cell::new(ALL_LAYERS),
         })
     }
 
@@ -61,6 +161,25 @@ impl<R: Resources, E: Style<R>> Painter<R, E> {
         Ok(())
     }
 
+    /// Add the ability to draw the given primitive with a depth bias, see `try_draw_biased`.
+    /// Each bias needs its own pipelines, so only `MAX_DEPTH_BIAS_VARIANTS` can be set up.
+    pub fn setup_depth_bias<F>(&mut self, f: &mut F, prim: Primitive, bias: DepthBias) -> Result<(), Error>
+        where F: Factory<R> + FactoryExt<R>, E: DepthBiasStyle<R>
+    {
+        if bias.is_none() {
+            return self.setup(f, prim);
+        }
+        if self.biased.contains_key(&(prim, bias)) {
+            return Ok(());
+        }
+        ensure!(self.biased.len() < MAX_DEPTH_BIAS_VARIANTS,
+            FlightError::DepthBiasVariants { max: MAX_DEPTH_BIAS_VARIANTS });
+        let mut inputs = self.inputs.borrow_mut();
+        let sty = E::new(f, &mut *inputs, prim, bias.rasterizer())?;
+        self.biased.insert((prim, bias), sty);
+        Ok(())
+    }
+
     /// Attempt to draw a mesh with the given parameters and model matrix,
     /// returning `Err` if something goes wrong.
     pub fn try_draw<C>(
@@ -72,51 +191,249 @@ impl<R: Resources, E: Style<R>> Painter<R, E> {
         -> Result<(), Error>
         where C: CommandBuffer<R>
     {
-        if let Some(ref sty) = self.map.get(&mesh.prim) {
-            let mut inputs = self.inputs.borrow_mut();
-            let mut trans = TransformBlock {
-                eye: ctx.left.eye.to_homogeneous().downgrade(),
-                model: model.downgrade(),
-                view: ctx.left.view.downgrade(),
-                proj: ctx.left.proj.downgrade(),
-                clip_offset: ctx.left.clip_offset,
-            };
-            inputs.transform(trans.clone());
-            sty.draw_raw(
-                &mut *inputs,
-                &mut ctx.encoder,
-                ctx.color.clone(),
-                ctx.depth.clone(),
-                ctx.left.clip,
-                &mesh.slice,
-                mesh.buf.clone(),
-                &mesh.mat,
-            )?;
+        self.try_draw_with(ctx, model, mesh, &mesh.mat)
+    }
 
-            trans.eye = ctx.right.eye.to_homogeneous().downgrade();
-            trans.view = ctx.right.view.downgrade();
-            trans.proj = ctx.right.proj.downgrade();
-            trans.clip_offset = ctx.right.clip_offset;
-            inputs.transform(trans);
-            sty.draw_raw(
-                &mut *inputs,
-                &mut ctx.encoder,
-                ctx.color.clone(),
-                ctx.depth.clone(),
-                ctx.right.clip,
-                &mesh.slice,
-                mesh.buf.clone(),
-                &mesh.mat,
-            )?;
+    /// Attempt to draw the geometry of a mesh using the given material in place of its own.
+    pub fn try_draw_with<C, M>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        mesh: &Mesh<R, E::Vertex, M>,
+        mat: &E::Material,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        self.debug_bounds(&mesh.bounds, &model);
+        let eyes = eye_transforms(ctx, model.downgrade(), &self.lens_shading(ctx));
+        self.draw_parts(ctx, &eyes, mesh.prim, &mesh.buf, &mesh.slice, mat)
+    }
 
-            Ok(())
-        } else {
-            Err(
-                FlightError::InvalidPrimitive { given: mesh.prim }
-                .context("setup has not been done for this primitive type".to_owned())
-                .into()
-            )
+    /// Attempt to draw a mesh lying on another surface, such as a floor marking or a
+    /// selection quad, offset in depth by `bias` so that it doesn't z-fight with the
+    /// surface. The bias must have been set up with `setup_depth_bias`.
+    pub fn try_draw_biased<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        mesh: &Mesh<R, E::Vertex, E::Material>,
+        bias: DepthBias,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, E: DepthBiasStyle<R>
+    {
+        self.debug_bounds(&mesh.bounds, &model);
+        let eyes = eye_transforms(ctx, model.downgrade(), &self.lens_shading(ctx));
+        self.draw_slices(ctx, &eyes, mesh.prim, bias, &mesh.buf, ::std::slice::from_ref(&mesh.slice), &mesh.mat)
+    }
+
+    /// Attempt to draw a mesh that stays fixed relative to the head, such as a HUD. The
+    /// model matrix places the mesh in view space and only the projection is applied, so
+    /// head rotation doesn't move it.
+    pub fn try_draw_head_locked<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        mesh: &Mesh<R, E::Vertex, E::Material>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let eyes = head_locked_transforms(ctx, model.downgrade(), &self.lens_shading(ctx));
+        self.draw_parts(ctx, &eyes, mesh.prim, &mesh.buf, &mesh.slice, &mesh.mat)
+    }
+
+    /// Attempt to draw every material group of a multi-material mesh, returning `Err`
+    /// if something goes wrong.
+    pub fn try_draw_multi<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        mesh: &MultiMesh<R, E::Vertex, E::Material>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        self.debug_bounds(&mesh.bounds, &model);
+        let eyes = eye_transforms(ctx, model.downgrade(), &self.lens_shading(ctx));
+        for g in &mesh.groups {
+            self.draw_parts(ctx, &eyes, mesh.prim, &mesh.buf, &g.slice, &g.mat)?;
+        }
+        Ok(())
+    }
+
+    /// Draw every material group of a multi-material mesh, logging any errors.
+    pub fn draw_multi<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        model: Transform3<f32>,
+        mesh: &MultiMesh<R, E::Vertex, E::Material>,
+    )
+        where C: CommandBuffer<R>
+    {
+        if let Err(e) = self.try_draw_multi(ctx, model, mesh) {
+            error!("{}", e);
+        }
+    }
+
+    /// Draw every command of a draw list from one shared vertex and index buffer, one draw
+    /// call per command and eye. The vertices are expected to already be in world space, so
+    /// no model matrix is applied, and the pipeline data is bound once for the whole list.
+    pub fn paint_draw_list<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        list: &DrawList,
+        verts: &Buffer<R, E::Vertex>,
+        inds: &IndexBuffer<R>,
+        mat: &E::Material,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if list.is_empty() {
+            return Ok(());
+        }
+        let slices: Vec<_> = list.commands().iter().map(|c| Slice {
+            start: c.first_index,
+            end: c.first_index + c.index_count,
+            base_vertex: c.base_vertex,
+            instances: if c.instance_count > 1 || c.base_instance > 0 {
+                Some((c.instance_count, c.base_instance))
+            } else {
+                None
+            },
+            buffer: inds.clone(),
+        }).collect();
+        let eyes = eye_transforms(ctx, Transform3::<f32>::identity().downgrade(), &self.lens_shading(ctx));
+        self.draw_slices(ctx, &eyes, list.prim, DepthBias::none(), verts, &slices, mat)
+    }
+
+    /// Record the bounds of every mesh drawn by this painter into the given debug drawer,
+    /// or stop recording if `None`. Nothing is recorded while the debug drawer is disabled.
+    pub fn set_debug(&mut self, debug: Option<Rc<RefCell<DebugDraw>>>) {
+        self.debug = debug;
+    }
+
+    fn debug_bounds(&self, bounds: &Aabb, model: &Transform3<f32>) {
+        if let Some(ref d) = self.debug {
+            let mut d = d.borrow_mut();
+            if !d.enabled { return }
+            let color = d.bounds_color;
+            d.add_aabb(bounds, model, color);
+            if d.bounds_spheres && !bounds.is_empty() {
+                let (center, _) = bounds.bounding_sphere();
+                let world = bounds.transform(model);
+                d.add_sphere(model * center, world.diagonal() / 2., color);
+            }
+        }
+    }
+
+    fn draw_parts<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        eyes: &[(TransformBlock, Rect); 2],
+        prim: Primitive,
+        buf: &Buffer<R, E::Vertex>,
+        slice: &Slice<R>,
+        mat: &E::Material,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        self.draw_slices(ctx, eyes, prim, DepthBias::none(), buf, ::std::slice::from_ref(slice), mat)
+    }
+
+    fn draw_slices<C>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        eyes: &[(TransformBlock, Rect); 2],
+        prim: Primitive,
+        bias: DepthBias,
+        buf: &Buffer<R, E::Vertex>,
+        slices: &[Slice<R>],
+        mat: &E::Material,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        profile_scope!("paint");
+        let sampled = E::sampled(mat);
+        if !ctx.resources.is_empty() {
+            use gfx::memory::Typed;
+            ctx.resources.check(ctx.color.raw(), &sampled)?;
+        }
+        let sty = self.biased_style(prim, bias)?;
+        let mut inputs = self.inputs.borrow_mut();
+        let mut bindings = self.bindings.borrow_mut();
+
+        // Bindings not drawn since the previous frame began are dropped, so the handles of
+        // short-lived meshes and materials aren't kept alive by the cache
+        let frame = ctx.frames.get();
+        if self.swept.get() != frame {
+            self.swept.set(frame);
+            bindings.retain(|_, list| {
+                list.retain(|b| b.used + 1 >= frame);
+                !list.is_empty()
+            });
+        }
+
+        // Pipeline data is only rebuilt when the mesh, material, or targets change. Bindings
+        // are found by the vertex buffer and textures, and told apart by the rest.
+        let key = {
+            let mut h = FnvHasher::default();
+            buf.hash(&mut h);
+            sampled.hash(&mut h);
+            h.finish()
+        };
+        let list = bindings.entry(key).or_insert_with(Vec::new);
+        let found = list.iter().position(|b| b.buf == *buf && b.mat == *mat
+            && b.color == ctx.color && b.depth == ctx.depth);
+        let binding = match found {
+            Some(i) => &mut list[i],
+            None => {
+                list.push(Binding {
+                    buf: buf.clone(),
+                    mat: mat.clone(),
+                    color: ctx.color.clone(),
+                    depth: ctx.depth.clone(),
+                    bound: sty.bind(
+                        &*inputs,
+                        ctx.color.clone(),
+                        ctx.depth.clone(),
+                        buf.clone(),
+                        mat,
+                    ),
+                    used: frame,
+                });
+                list.last_mut().unwrap()
+            },
+        };
+        binding.used = frame;
+
+        #[cfg(feature = "draw-inspector")]
+        {
+            use gfx::memory::Typed;
+            let pso_key = shader_set_key(prim, inputs.shader_set());
+            ctx.inspector.set_pipeline(::std::any::type_name::<E>(), prim, pso_key, Some(buf.raw().clone()), Some(E::sampled(mat)));
+        }
+        for &(trans, clip) in eyes {
+            inputs.transform(trans);
+            for slice in slices {
+                sty.draw_bound(&mut *inputs, &mut ctx.encoder, clip, slice, &mut binding.bound)?;
+                #[cfg(feature = "draw-inspector")]
+                ctx.inspect_draw(trans, clip, slice);
+                ctx.draw_calls += 1;
+            }
         }
+        Ok(())
+    }
+
+    /// Forget the pipeline data cached by `draw` and `try_draw`. The cache holds onto the
+    /// GPU handles of every mesh and material drawn in the current and previous frame (as
+    /// counted by `DrawParams::frames`), so call this after unloading assets mid-frame.
+    pub fn clear_cache(&self) {
+        self.bindings.borrow_mut().clear();
     }
 
     /// Draw a mesh with the given parameters and model matrix, logging any errors.
@@ -133,22 +450,233 @@ impl<R: Resources, E: Style<R>> Painter<R, E> {
         }
     }
 
+    /// Prepare the pipeline data for a fixed set of meshes and model matrices ahead of time.
+    /// Drawing the resulting scene with `submit` only updates the per-eye constants, so
+    /// static environments avoid rebuilding the same draw calls every frame. The scene
+    /// holds onto the color and depth targets of `ctx`, so it must be baked again if
+    /// those change.
+    pub fn bake<'a, C, I>(&self, ctx: &DrawParams<R, C>, meshes: I)
+        -> Result<BakedScene<R, E>, Error>
+        where
+            C: CommandBuffer<R>,
+            I: IntoIterator<Item = (Transform3<f32>, &'a Mesh<R, E::Vertex, E::Material>)>,
+            R: 'a,
+            E::Vertex: 'a,
+            E::Material: 'a,
+    {
+        let inputs = self.inputs.borrow();
+        let mut items = Vec::new();
+        for (model, mesh) in meshes {
+            let bound = self.style(mesh.prim)?.bind(
+                &*inputs,
+                ctx.color.clone(),
+                ctx.depth.clone(),
+                mesh.buf.clone(),
+                &mesh.mat,
+            );
+            items.push(BakedItem {
+                prim: mesh.prim,
+                layers: DEFAULT_LAYER,
+                model: model.downgrade(),
+                slice: mesh.slice.clone(),
+                bound: bound,
+            });
+        }
+        Ok(BakedScene { items: items })
+    }
+
+    /// Draw every mesh on a visible layer in a scene previously prepared by `bake`.
+    pub fn submit<C>(&self, ctx: &mut DrawParams<R, C>, scene: &mut BakedScene<R, E>)
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let mut inputs = self.inputs.borrow_mut();
+        for item in &mut scene.items {
+            if !self.draws_layers(ctx, item.layers) { continue }
+            let sty = self.style(item.prim)?;
+            #[cfg(feature = "draw-inspector")]
+            {
+                let pso_key = shader_set_key(item.prim, inputs.shader_set());
+                ctx.inspector.set_pipeline(::std::any::type_name::<E>(), item.prim, pso_key, None, None);
+            }
+            for &(trans, clip) in &eye_transforms(ctx, item.model, &self.lens_shading(ctx)) {
+                inputs.transform(trans);
+                sty.draw_bound(&mut *inputs, &mut ctx.encoder, clip, &item.slice, &mut item.bound)?;
+                #[cfg(feature = "draw-inspector")]
+                ctx.inspect_draw(trans, clip, &item.slice);
+                ctx.draw_calls += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// A key identifying the pipeline used to draw the given primitive, for grouping draws
+    /// that use the same shaders (see `BatchAccumulator`).
+    pub fn pso_key(&self, prim: Primitive) -> u64 {
+        shader_set_key(prim, self.inputs.borrow().shader_set())
+    }
+
+    fn biased_style(&self, prim: Primitive, bias: DepthBias) -> Result<&E, Error> {
+        if bias.is_none() {
+            return self.style(prim);
+        }
+        match self.biased.get(&(prim, bias)) {
+            Some(sty) => Ok(sty),
+            None => Err(
+                FlightError::InvalidPrimitive { given: prim }
+                .context(format!("setup_depth_bias has not been done for {:?}", bias))
+                .into()
+            ),
+        }
+    }
+
+    fn style(&self, prim: Primitive) -> Result<&E, Error> {
+        match self.map.get(&prim) {
+            Some(sty) => Ok(sty),
+            None => Err(
+                FlightError::InvalidPrimitive { given: prim }
+                .context("setup has not been done for this primitive type".to_owned())
+                .into()
+            ),
+        }
+    }
+
     /// Configure the draw style. For example, `cfg(|c| c.ambient([1., 0., 0., 1.]))`
     /// might set the ambient light color to red. The exact customization available
     /// depends on the style being used.
     pub fn cfg<F: FnOnce(&mut E::Inputs)>(&self, f: F) {
         f(&mut *self.inputs.borrow_mut())
     }
+
+    /// Shade the periphery of this painter's draws with the given setting instead of
+    /// `DrawParams::lens_shading`, or follow the draw parameters again with `None`
+    pub fn set_lens_shading(&self, lens: Option<LensShading>) {
+        self.lens.set(lens);
+    }
+
+    /// Only draw queued draws (from `BatchAccumulator` and `BakedScene`) on the given layers
+    /// with this painter, such as to hide editor helpers. This is combined with
+    /// `DrawParams::layer_mask`, and draws made directly are never filtered.
+    pub fn set_layer_mask(&self, mask: u32) {
+        self.layer_mask.set(mask);
+    }
+
+    /// True if a queued draw on the given layers passes the layer masks of this painter
+    /// and `ctx`
+    pub fn draws_layers<C: CommandBuffer<R>>(&self, ctx: &DrawParams<R, C>, layers: u32) -> bool {
+        layers & self.layer_mask.get() & ctx.layer_mask != 0
+    }
+
+    /// The peripheral shading setting used for draws into `ctx`
+    fn lens_shading<C: CommandBuffer<R>>(&self, ctx: &DrawParams<R, C>) -> LensShading {
+        self.lens.get().unwrap_or(ctx.lens_shading)
+    }
+}
+
+/// The `Painter::pso_key` of a primitive drawn with a shader set
+fn shader_set_key<R: Resources>(prim: Primitive, shaders: &ShaderSet<R>) -> u64 {
+    let mut h = FnvHasher::default();
+    prim.hash(&mut h);
+    match *shaders {
+        ShaderSet::Simple(ref v, ref p) => {
+            v.hash(&mut h);
+            p.hash(&mut h);
+        },
+        ShaderSet::Geometry(ref v, ref g, ref p) => {
+            v.hash(&mut h);
+            g.hash(&mut h);
+            p.hash(&mut h);
+        },
+        // tessellation is never used by the built-in styles
+        _ => (),
+    }
+    h.finish()
+}
+
+/// The projection of an eye for `DEPTH_CONVENTION`
+fn eye_proj(e: &EyeParams) -> [[f32; 4]; 4] {
+    DEPTH_CONVENTION.projection(e.proj.matrix()).downgrade()
+}
+
+/// The transform block and scissor rectangle of each eye
+fn eye_transforms<R, C>(ctx: &DrawParams<R, C>, model: [[f32; 4]; 4], lens: &LensShading) -> [(TransformBlock, Rect); 2]
+    where R: Resources, C: CommandBuffer<R>
+{
+    let eye = |e: &EyeParams| (TransformBlock {
+        eye: e.eye.to_homogeneous().downgrade(),
+        model: model,
+        view: e.view.downgrade(),
+        proj: eye_proj(e),
+        lens: lens.block(e.clip),
+        clip_offset: e.clip_offset,
+        log_depth: ctx.log_depth.block(),
+    }, e.clip);
+    let eyes = ctx.eyes();
+    [eye(&eyes[0]), eye(&eyes[1])]
+}
+
+/// The transform block and scissor rectangle of each eye, without the view transform
+fn head_locked_transforms<R, C>(ctx: &DrawParams<R, C>, model: [[f32; 4]; 4], lens: &LensShading) -> [(TransformBlock, Rect); 2]
+    where R: Resources, C: CommandBuffer<R>
+{
+    let eye = |e: &EyeParams| (TransformBlock {
+        eye: [0., 0., 0., 1.],
+        model: model,
+        view: Transform3::<f32>::identity().downgrade(),
+        proj: eye_proj(e),
+        lens: lens.block(e.clip),
+        clip_offset: e.clip_offset,
+        log_depth: ctx.log_depth.block(),
+    }, e.clip);
+    let eyes = ctx.eyes();
+    [eye(&eyes[0]), eye(&eyes[1])]
+}
+
+/// A set of meshes whose pipeline data was prepared by `Painter::bake`
+pub struct BakedScene<R: Resources, E: Style<R>> {
+    items: Vec<BakedItem<R, E>>,
+}
+
+struct BakedItem<R: Resources, E: Style<R>> {
+    prim: Primitive,
+    layers: u32,
+    model: [[f32; 4]; 4],
+    slice: Slice<R>,
+    bound: E::Bound,
+}
+
+impl<R: Resources, E: Style<R>> BakedScene<R, E> {
+    /// The number of meshes in this scene
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// True if this scene has no meshes
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Put a mesh, in the order given to `bake`, on the given layers instead of
+    /// `DEFAULT_LAYER`. Returns false if there is no such mesh.
+    pub fn set_layers(&mut self, index: usize, layers: u32) -> bool {
+        match self.items.get_mut(index) {
+            Some(item) => {
+                item.layers = layers;
+                true
+            },
+            None => false,
+        }
+    }
 }
 
 /// Implements a particular drawing process and visual style.
 pub trait Style<R: Resources>: Sized {
     /// The mesh vertex type required for drawing
-    type Vertex: Vertex;
+    type Vertex: VertexData;
     /// The configuration available for this style
     type Inputs: StyleInputs<R>;
-    /// The material type required on meshes
-    type Material;
+    /// The material type required on meshes, compared to find cached pipeline data
+    type Material: Clone + PartialEq;
 
     fn new<F: Factory<R> + FactoryExt<R>>(
         &mut F,
@@ -161,21 +689,61 @@ pub trait Style<R: Resources>: Sized {
         &mut F,
     ) -> Result<Self::Inputs, Error>;
 
-    fn draw_raw<C>(
+    /// Pipeline data for a single mesh, prepared by `bind` so that it can be drawn
+    /// repeatedly without cloning handles
+    type Bound;
+
+    fn bind(
         &self,
-        &mut Self::Inputs,
-        &mut Encoder<R, C>,
+        &Self::Inputs,
         TargetRef<R>,
         DepthRef<R>,
-        Rect,
-        &Slice<R>,
         Buffer<R, Self::Vertex>,
         &Self::Material,
+    ) -> Self::Bound;
+
+    fn draw_bound<C>(
+        &self,
+        &mut Self::Inputs,
+        &mut Encoder<R, C>,
+        Rect,
+        &Slice<R>,
+        &mut Self::Bound,
     )
         -> Result<(), Error>
         where C: CommandBuffer<R>;
+
+    /// The textures a material samples, checked against the color target by
+    /// `ResourceStateTracker`. Styles whose materials never show drawn targets can keep the
+    /// empty default.
+    fn sampled(_mat: &Self::Material) -> Vec<RawShaderResourceView<R>> {
+        Vec::new()
+    }
+
+    fn draw_raw<C>(
+        &self,
+        inputs: &mut Self::Inputs,
+        enc: &mut Encoder<R, C>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &Self::Material,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        profile_scope!("draw_raw");
+        let mut bound = self.bind(inputs, color, depth, buf, mat);
+        self.draw_bound(inputs, enc, scissor, slice, &mut bound)
+    }
 }
 
+/// A style whose pipelines can be created with a depth offset in their rasterizer, and so
+/// be drawn with `Painter::try_draw_biased`
+pub trait DepthBiasStyle<R: Resources>: Style<R> {}
+
 /// Required configuration options for a `Style`
 pub trait StyleInputs<R: Resources> {
     /// Transformation matrices and eye parameters
@@ -185,7 +753,9 @@ pub trait StyleInputs<R: Resources> {
 }
 
 mod defines {
+    use nalgebra::{Matrix4, Perspective3};
     use ::{Light, NativeRepr};
+    use super::DepthConvention;
 
     gfx_defines!{
         constant TransformBlock {
@@ -193,7 +763,9 @@ mod defines {
             view: [[f32; 4]; 4] = "view",
             proj: [[f32; 4]; 4] = "proj",
             eye: [f32; 4] = "eye_pos",
+            lens: [f32; 4] = "lens",
             clip_offset: f32 = "clip_offset",
+            log_depth: f32 = "log_depth",
         }
         constant LightBlock {
             pos: [f32; 4] = "pos",
@@ -201,6 +773,15 @@ mod defines {
         }
     }
 
+    impl TransformBlock {
+        /// A perspective projection with a vertical field of view of `fov` radians that draws
+        /// `near` at depth 1 and `far` at depth 0, for `DepthConvention::ReverseZ`
+        pub fn reversed_z_proj(fov: f32, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+            let proj = Perspective3::new(aspect, fov, near, far).to_homogeneous();
+            DepthConvention::ReverseZ.projection(&proj)
+        }
+    }
+
     impl From<Light> for LightBlock {
         fn from(l: Light) -> LightBlock {
             LightBlock {
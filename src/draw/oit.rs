@@ -0,0 +1,361 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder, IndexBuffer};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, RenderTargetView, ShaderResourceView};
+use gfx::state::{Rasterizer, Blend, BlendChannel, Equation, Factor, BlendValue};
+use gfx::format::*;
+use nalgebra::{Transform3, Point3};
+
+use super::{StyleInputs, Style, TransformBlock, Painter, DrawParams, UnlitStyle, UnlitMaterial};
+use ::mesh::{Primitive, Mesh, VertNTT};
+use ::{Error, FlightError, ColorFormat, DepthFormat, TargetRef, DepthRef};
+
+/// The pixel format of the weighted color accumulation target
+pub type AccumFormat = (R16_G16_B16_A16, Float);
+/// The pixel format of the revealage target
+pub type RevealFormat = (R16, Float);
+
+/// Multiplies the revealage by one minus each surface's opacity
+const REVEAL_BLEND: Blend = Blend {
+    color: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::Zero,
+        destination: Factor::OneMinus(BlendValue::SourceColor),
+    },
+    alpha: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::Zero,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+};
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        scissor: gfx::Scissor = (),
+        accum: gfx::BlendTarget<AccumFormat> = ("f_accum", gfx::state::ColorMask::all(), gfx::preset::blend::ADD),
+        reveal: gfx::BlendTarget<RevealFormat> = ("f_reveal", gfx::state::ColorMask::all(), REVEAL_BLEND),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+        texture: gfx::TextureSampler<[f32; 4]> = "color_tex",
+    }
+
+    pipeline composite {
+        scissor: gfx::Scissor = (),
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        accum: gfx::TextureSampler<[f32; 4]> = "accum_tex",
+        reveal: gfx::TextureSampler<f32> = "reveal_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX")
+        .define("TAN"),
+    fragment: static_file!("shaders/oit.f.glsl")
+        .define_to("I_TEX", "v_tex")
+});
+
+shader!(composite_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/oit_composite.f.glsl")
+});
+
+/// The accumulation and revealage targets of weighted blended transparency
+pub struct OitTargets<R: Resources> {
+    pub accum: RenderTargetView<R, AccumFormat>,
+    pub reveal: RenderTargetView<R, RevealFormat>,
+    accum_view: ShaderResourceView<R, [f32; 4]>,
+    reveal_view: ShaderResourceView<R, f32>,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl<R: Resources> OitTargets<R> {
+    /// Create targets with the given size in pixels, which should match the color target
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16) -> Result<OitTargets<R>, Error> {
+        let (_, accum_view, accum) = f.create_render_target::<AccumFormat>(width, height)?;
+        let (_, reveal_view, reveal) = f.create_render_target::<RevealFormat>(width, height)?;
+        Ok(OitTargets {
+            accum: accum,
+            reveal: reveal,
+            accum_view: accum_view,
+            reveal_view: reveal_view,
+            width: width,
+            height: height,
+        })
+    }
+
+    /// The GPU memory used by both targets, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        // 8 bytes of accumulation and 2 of revealage per pixel
+        self.width as usize * self.height as usize * 10
+    }
+}
+
+/// The configuration for accumulating transparent surfaces
+pub struct OitInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    targets: (RenderTargetView<R, AccumFormat>, RenderTargetView<R, RevealFormat>),
+}
+
+impl<R: Resources> OitInputs<R> {
+    /// Accumulate into the given targets
+    pub fn set_targets(&mut self, targets: &OitTargets<R>) {
+        self.targets = (targets.accum.clone(), targets.reveal.clone());
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for OitInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Accumulates textured transparent surfaces into `OitTargets` in any order. Depth is
+/// tested against the opaque scene but not written. The color target given by the draw
+/// parameters is ignored, the result reaches it through `OitCompositor`.
+pub struct OitStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for OitStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = OitInputs<R>;
+    type Material = UnlitMaterial<R>;
+    type Bound = pl::Data<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut OitInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(OitStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<OitInputs<R>, Error> {
+        // replaced by set_targets before anything is drawn
+        let (_, _, accum) = f.create_render_target::<AccumFormat>(1, 1)?;
+        let (_, _, reveal) = f.create_render_target::<RevealFormat>(1, 1)?;
+        Ok(OitInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            targets: (accum, reveal),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &OitInputs<R>,
+        _: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &UnlitMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            accum: inputs.targets.0.clone(),
+            reveal: inputs.targets.1.clone(),
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            texture: mat.color.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut OitInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        // targets may have been replaced since binding
+        data.accum = inputs.targets.0.clone();
+        data.reveal = inputs.targets.1.clone();
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
+
+/// Blends accumulated transparent surfaces over the opaque scene, one eye at a time
+pub struct OitCompositor<R: Resources> {
+    pso: PipelineState<R, composite::Meta>,
+    sampler: gfx::handle::Sampler<R>,
+}
+
+impl<R: Resources> OitCompositor<R> {
+    /// Build the composite pipeline
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<OitCompositor<R>, Error> {
+        let shaders = composite_shader(f)?;
+        Ok(OitCompositor {
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), composite::new())?,
+            sampler: f.create_sampler_linear(),
+        })
+    }
+
+    /// Blend the contents of the targets onto the color target of `ctx`
+    pub fn apply<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, targets: &OitTargets<R>) {
+        profile_scope!("oit_composite");
+        let slice = Slice {
+            start: 0,
+            end: 3,
+            base_vertex: 0,
+            instances: None,
+            buffer: IndexBuffer::Auto,
+        };
+        for eye in &ctx.eyes() {
+            ctx.encoder.draw(&slice, &self.pso, &composite::Data {
+                scissor: eye.clip,
+                color: ctx.color.clone(),
+                accum: (targets.accum_view.clone(), self.sampler.clone()),
+                reveal: (targets.reveal_view.clone(), self.sampler.clone()),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+}
+
+/// How transparent surfaces are combined
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransparencyMode {
+    /// Drawn back to front with alpha blending, exact for surfaces that don't intersect
+    Sorted,
+    /// Weighted blended order-independent transparency, approximate but unaffected by
+    /// intersecting surfaces and draw order
+    WeightedBlended,
+}
+
+impl Default for TransparencyMode {
+    fn default() -> TransparencyMode {
+        TransparencyMode::Sorted
+    }
+}
+
+/// A transparent draw waiting in a `TransparentPass`
+#[derive(Copy, Clone)]
+struct TransparentEntry {
+    mesh_index: usize,
+    material_index: usize,
+    model: Transform3<f32>,
+}
+
+/// Collects transparent draws over a frame and draws them after the opaque scene in the
+/// selected `TransparencyMode`. Submission is the same in every mode.
+pub struct TransparentPass<R: Resources> {
+    /// How surfaces are combined, `Sorted` by default
+    pub mode: TransparencyMode,
+    sorted: Painter<R, UnlitStyle<R>>,
+    oit: Painter<R, OitStyle<R>>,
+    targets: OitTargets<R>,
+    compositor: OitCompositor<R>,
+    queue: Vec<TransparentEntry>,
+}
+
+impl<R: Resources> TransparentPass<R> {
+    /// Create a pass for triangle meshes drawn into a target of the given size
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16) -> Result<TransparentPass<R>, Error> {
+        let mut sorted = Painter::new(f)?;
+        sorted.setup(f, Primitive::TriangleList)?;
+        let mut oit = Painter::new(f)?;
+        oit.setup(f, Primitive::TriangleList)?;
+        Ok(TransparentPass {
+            mode: TransparencyMode::Sorted,
+            sorted: sorted,
+            oit: oit,
+            targets: OitTargets::new(f, width, height)?,
+            compositor: OitCompositor::new(f)?,
+            queue: Vec::new(),
+        })
+    }
+
+    /// The extra GPU memory used by weighted blended transparency, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.targets.memory_bytes()
+    }
+
+    /// Record a transparent draw
+    pub fn push(&mut self, model: Transform3<f32>, mesh_index: usize, material_index: usize) {
+        self.queue.push(TransparentEntry {
+            mesh_index: mesh_index,
+            material_index: material_index,
+            model: model,
+        });
+    }
+
+    /// The number of draws recorded since the last flush
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// True if no draws have been recorded since the last flush
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Draw everything recorded over the opaque scene in `ctx`, then clear the queue
+    pub fn flush<C, M>(
+        &mut self,
+        ctx: &mut DrawParams<R, C>,
+        meshes: &[Mesh<R, VertNTT, M>],
+        materials: &[UnlitMaterial<R>],
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let mut queue = ::std::mem::replace(&mut self.queue, Vec::new());
+        for e in &queue {
+            ensure!(e.mesh_index < meshes.len(), FlightError::IndexOutOfRange {
+                what: "mesh",
+                index: e.mesh_index,
+                len: meshes.len(),
+            });
+            ensure!(e.material_index < materials.len(), FlightError::IndexOutOfRange {
+                what: "material",
+                index: e.material_index,
+                len: materials.len(),
+            });
+        }
+        match self.mode {
+            TransparencyMode::Sorted => {
+                let eyes = ctx.eyes();
+                let center = Point3::from_coordinates((eyes[0].eye.coords + eyes[1].eye.coords) * 0.5);
+                let dist = |e: &TransparentEntry| (e.model * Point3::origin() - center).norm_squared();
+                // furthest first
+                queue.sort_by(|a, b| dist(b).partial_cmp(&dist(a)).unwrap_or(::std::cmp::Ordering::Equal));
+                for e in &queue {
+                    self.sorted.try_draw_with(ctx, e.model, &meshes[e.mesh_index], &materials[e.material_index])?;
+                }
+            },
+            TransparencyMode::WeightedBlended => {
+                ctx.encoder.clear(&self.targets.accum, [0.; 4]);
+                ctx.encoder.clear(&self.targets.reveal, 1.);
+                let targets = &self.targets;
+                self.oit.cfg(|i| i.set_targets(targets));
+                for e in &queue {
+                    self.oit.try_draw_with(ctx, e.model, &meshes[e.mesh_index], &materials[e.material_index])?;
+                }
+                self.compositor.apply(ctx, &self.targets);
+            },
+        }
+        queue.clear();
+        self.queue = queue;
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Slice, IndexBuffer};
+use gfx::buffer::Role;
+use gfx::memory::{Bind, Usage};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, Sampler, ShaderResourceView};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+use nalgebra::{Point3, Vector3, Transform3};
+
+use super::{TransformBlock, DrawParams, eye_transforms};
+use super::uber::LumMapFormat;
+use ::mesh::Primitive;
+use ::{Error, ColorFormat, DepthFormat, Texture, NativeRepr};
+
+gfx_defines!{
+    vertex ParticleInstance {
+        pos_size: [f32; 4] = "i_pos_size",
+        color: [f32; 4] = "i_color",
+    }
+
+    constant ParticleBlock {
+        depth_params: [f32; 4] = "depth_params",
+        lit: f32 = "lit",
+    }
+
+    pipeline pl {
+        instances: gfx::InstanceBuffer<ParticleInstance> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<ParticleBlock> = "particles",
+        scissor: gfx::Scissor = (),
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+        scene_depth: gfx::TextureSampler<f32> = "scene_depth",
+        irradiance: gfx::TextureSampler<[f32; 3]> = "irradiance_map",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/particle.v.glsl"),
+    fragment: static_file!("shaders/particle.f.glsl")
+});
+
+/// The volume new particles appear in, centered on the emitter position
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EmitterShape {
+    Point,
+    /// A solid sphere with the given radius
+    Sphere(f32),
+    /// A solid box with the given half extents
+    Box(Vector3<f32>),
+    /// A flat disc in the XZ plane with the given radius, such as a patch of sky for snow
+    Disc(f32),
+}
+
+/// How an emitter spawns particles and how they change over their lifetime. Pairs of
+/// values are given as `[start, end]` and interpolated linearly over each particle's life.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    pub position: Point3<f32>,
+    /// Particles spawned per second
+    pub rate: f32,
+    /// The shortest and longest lifetime in seconds
+    pub lifetime: [f32; 2],
+    /// The initial velocity
+    pub velocity: Vector3<f32>,
+    /// The largest random change to each component of the initial velocity
+    pub velocity_spread: f32,
+    /// Acceleration applied to every particle
+    pub gravity: Vector3<f32>,
+    /// The width of each particle
+    pub size: [f32; 2],
+    /// Linear color and opacity
+    pub color: [[f32; 4]; 2],
+}
+
+impl Default for Emitter {
+    fn default() -> Emitter {
+        Emitter {
+            shape: EmitterShape::Point,
+            position: Point3::origin(),
+            rate: 100.,
+            lifetime: [1., 2.],
+            velocity: Vector3::new(0., 1., 0.),
+            velocity_spread: 0.5,
+            gravity: Vector3::new(0., -9.81, 0.),
+            size: [0.05, 0.02],
+            color: [[1., 1., 1., 1.], [1., 1., 1., 0.]],
+        }
+    }
+}
+
+/// A xorshift generator, so that runs with the same seed are identical on every platform
+#[derive(Copy, Clone, Debug)]
+struct Rng(u32);
+
+impl Rng {
+    fn new(seed: u32) -> Rng {
+        // zero is a fixed point of xorshift
+        Rng(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// Uniform in [0, 1)
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Uniform in [-1, 1)
+    fn signed(&mut self) -> f32 {
+        self.unit() * 2. - 1.
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    pos: Vector3<f32>,
+    vel: Vector3<f32>,
+    age: f32,
+    life: f32,
+}
+
+/// The CPU side of a particle system, with no GPU resources
+#[derive(Clone, Debug)]
+pub struct ParticleSim {
+    /// How particles are spawned and animated
+    pub emitter: Emitter,
+    budget: usize,
+    particles: Vec<Particle>,
+    rng: Rng,
+    pending: f32,
+}
+
+impl ParticleSim {
+    /// Create an empty simulation holding at most `budget` particles. Runs with the same
+    /// seed and time steps produce the same particles.
+    pub fn new(emitter: Emitter, budget: usize, seed: u32) -> ParticleSim {
+        ParticleSim {
+            emitter: emitter,
+            budget: budget,
+            particles: Vec::with_capacity(budget),
+            rng: Rng::new(seed),
+            pending: 0.,
+        }
+    }
+
+    /// The largest number of live particles
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The number of live particles
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// True if there are no live particles
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Remove every particle
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending = 0.;
+    }
+
+    /// Advance the simulation by `dt` seconds, retiring old particles and spawning new
+    /// ones. Spawning stops while the budget is used up.
+    pub fn update(&mut self, dt: f32) {
+        let gravity = self.emitter.gravity * dt;
+        let mut i = 0;
+        while i < self.particles.len() {
+            let p = &mut self.particles[i];
+            p.age += dt;
+            if p.age >= p.life {
+                self.particles.swap_remove(i);
+                continue;
+            }
+            p.vel += gravity;
+            p.pos += p.vel * dt;
+            i += 1;
+        }
+
+        self.pending += self.emitter.rate.max(0.) * dt;
+        let count = self.pending.floor();
+        self.pending -= count;
+        let count = (count as usize).min(self.budget - self.particles.len());
+        for _ in 0..count {
+            let p = self.spawn();
+            self.particles.push(p);
+        }
+    }
+
+    fn spawn(&mut self) -> Particle {
+        let e = &self.emitter;
+        let rng = &mut self.rng;
+        let offset = match e.shape {
+            EmitterShape::Point => Vector3::zeros(),
+            EmitterShape::Sphere(r) => loop {
+                let v = Vector3::new(rng.signed(), rng.signed(), rng.signed());
+                if v.norm_squared() <= 1. { break v * r }
+            },
+            EmitterShape::Box(h) => Vector3::new(rng.signed() * h.x, rng.signed() * h.y, rng.signed() * h.z),
+            EmitterShape::Disc(r) => loop {
+                let (x, z) = (rng.signed(), rng.signed());
+                if x * x + z * z <= 1. { break Vector3::new(x, 0., z) * r }
+            },
+        };
+        let spread = Vector3::new(rng.signed(), rng.signed(), rng.signed()) * e.velocity_spread;
+        let life = e.lifetime[0] + (e.lifetime[1] - e.lifetime[0]) * rng.unit();
+        Particle {
+            pos: e.position.coords + offset,
+            vel: e.velocity + spread,
+            age: 0.,
+            life: life.max(1e-3),
+        }
+    }
+
+    /// Write the instance data of every live particle to `out`, replacing its contents
+    pub fn write_instances(&self, out: &mut Vec<ParticleInstance>) {
+        let e = &self.emitter;
+        out.clear();
+        out.extend(self.particles.iter().map(|p| {
+            let t = p.age / p.life;
+            let size = e.size[0] + (e.size[1] - e.size[0]) * t;
+            let mut color = [0.; 4];
+            for c in 0..4 {
+                color[c] = e.color[0][c] + (e.color[1][c] - e.color[0][c]) * t;
+            }
+            ParticleInstance {
+                pos_size: [p.pos.x, p.pos.y, p.pos.z, size],
+                color: color,
+            }
+        }));
+    }
+}
+
+/// Simulated particles drawn as camera facing sprites. The simulation runs on the CPU and
+/// its results are streamed into a dynamic instance buffer sized for the whole budget.
+pub struct ParticleSystem<R: Resources> {
+    /// The simulation
+    pub sim: ParticleSim,
+    instances: Vec<ParticleInstance>,
+    buf: Buffer<R, ParticleInstance>,
+}
+
+impl<R: Resources> ParticleSystem<R> {
+    /// Create a system holding at most `budget` particles, seeded with `seed`
+    pub fn new<F: Factory<R>>(f: &mut F, emitter: Emitter, budget: usize, seed: u32) -> Result<ParticleSystem<R>, Error> {
+        Ok(ParticleSystem {
+            sim: ParticleSim::new(emitter, budget, seed),
+            instances: Vec::with_capacity(budget),
+            buf: f.create_buffer(budget.max(1), Role::Vertex, Usage::Dynamic, Bind::empty())?,
+        })
+    }
+
+    /// Advance the simulation by `dt` seconds
+    pub fn update(&mut self, dt: f32) {
+        self.sim.update(dt);
+    }
+}
+
+/// Draws `ParticleSystem`s. Sprites are alpha blended in no particular order, and depth
+/// tested against the scene without being written.
+pub struct ParticlePainter<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+    transform: Buffer<R, TransformBlock>,
+    params: Buffer<R, ParticleBlock>,
+    scene_depth: (ShaderResourceView<R, f32>, Sampler<R>),
+    irradiance: Texture<R, LumMapFormat>,
+    depth_params: [f32; 4],
+    /// How much the irradiance map tints particles, from 0 (unlit) to 1
+    pub lit: f32,
+}
+
+impl<R: Resources> ParticlePainter<R> {
+    /// Build the particle pipeline
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<ParticlePainter<R>, Error> {
+        use gfx::texture::*;
+        let shaders = shader(f)?;
+        // stands in for the scene depth until one is given
+        let depth = f.create_texture::<D32>(
+            Kind::D2(1, 1, AaMode::Single),
+            1,
+            Bind::SHADER_RESOURCE | Bind::DEPTH_STENCIL,
+            Usage::Data,
+            Some(ChannelType::Float),
+        )?;
+        let depth = f.view_texture_as_shader_resource::<(D32, Float)>(&depth, (0, 0), Swizzle::new())?;
+        let white = [1f32.to_bits(); 3];
+        Ok(ParticlePainter {
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), pl::new())?,
+            transform: f.create_constant_buffer(1),
+            params: f.create_constant_buffer(1),
+            scene_depth: (depth, f.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp))),
+            irradiance: Texture::uniform_value(f, white)?,
+            depth_params: [0.1, 100., 0.1, 0.],
+            lit: 0.,
+        })
+    }
+
+    /// Fade particles out where they come within `fade` units of the scene, using a
+    /// sampleable copy of the scene depth (the depth target of `DrawParams` can't be read
+    /// from, so this is usually filled by a depth prepass). `near` and `far` are the clip
+    /// planes of the eye projection.
+    pub fn set_scene_depth(&mut self, depth: Texture<R, (D32, Float)>, near: f32, far: f32, fade: f32) {
+        self.scene_depth = depth.into_tuple();
+        self.depth_params = [near, far, fade.max(1e-4), 1.];
+    }
+
+    /// Stop fading particles near the scene
+    pub fn clear_scene_depth(&mut self) {
+        self.depth_params[3] = 0.;
+    }
+
+    /// Light particles with the given irradiance map (usually `UberEnv::irradiance`), tinted
+    /// by `amount` from 0 to 1
+    pub fn set_irradiance(&mut self, irradiance: Texture<R, LumMapFormat>, amount: f32) {
+        self.irradiance = irradiance;
+        self.lit = amount;
+    }
+
+    /// Upload the particles of a system and draw them into both eyes
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, system: &mut ParticleSystem<R>) -> Result<(), Error> {
+        system.sim.write_instances(&mut system.instances);
+        if system.instances.is_empty() {
+            return Ok(());
+        }
+        ctx.encoder.update_buffer(&system.buf, &system.instances, 0)?;
+        ctx.encoder.update_constant_buffer(&self.params, &ParticleBlock {
+            depth_params: self.depth_params,
+            lit: self.lit,
+        });
+
+        let slice = Slice {
+            start: 0,
+            end: 6,
+            base_vertex: 0,
+            instances: Some((system.instances.len() as u32, 0)),
+            buffer: IndexBuffer::Auto,
+        };
+        let mut data = pl::Data {
+            instances: system.buf.clone(),
+            transform: self.transform.clone(),
+            params: self.params.clone(),
+            scissor: gfx::Rect { x: 0, y: 0, w: 0, h: 0 },
+            color: ctx.color.clone(),
+            depth: ctx.depth.clone(),
+            scene_depth: self.scene_depth.clone(),
+            irradiance: self.irradiance.clone().into_tuple(),
+        };
+        for &(ref block, clip) in &eye_transforms(ctx, Transform3::<f32>::identity().downgrade(), &ctx.lens_shading) {
+            ctx.encoder.update_constant_buffer(&self.transform, block);
+            data.scissor = clip;
+            ctx.encoder.draw(&slice, &self.pso, &data);
+            ctx.draw_calls += 1;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn particle_simulation() {
+    let emitter = Emitter {
+        shape: EmitterShape::Sphere(1.),
+        rate: 1000.,
+        lifetime: [0.5, 0.5],
+        .. Default::default()
+    };
+    let mut a = ParticleSim::new(emitter, 300, 7);
+    let mut b = ParticleSim::new(emitter, 300, 7);
+    a.update(0.1);
+    assert_eq!(a.len(), 100);
+    // the budget caps spawning
+    for _ in 0..3 { a.update(0.1) }
+    assert_eq!(a.len(), 300);
+    // the oldest particles retire, and the rate fills the budget again in the same step
+    a.update(0.2);
+    assert_eq!(a.len(), 300);
+    assert!(a.particles.iter().all(|p| p.age < p.life));
+    assert_eq!(a.particles.iter().filter(|p| p.age < 0.1).count(), 100);
+    // with no emission the rest retire as they age
+    let mut drained = a.clone();
+    drained.emitter.rate = 0.;
+    drained.update(0.2);
+    assert_eq!(drained.len(), 100);
+
+    // the same seed gives the same run
+    for _ in 0..4 { b.update(0.1) }
+    b.update(0.2);
+    assert_eq!(a.len(), b.len());
+    assert!(a.particles.iter().zip(&b.particles).all(|(p, q)| p.pos == q.pos && p.life == q.life));
+
+    let mut out = Vec::new();
+    a.write_instances(&mut out);
+    assert_eq!(out.len(), a.len());
+    assert!(out.iter().all(|i| i.color[3] <= 1. && i.pos_size[3] > 0.));
+}
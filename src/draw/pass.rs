@@ -0,0 +1,196 @@
+use gfx::{Resources, CommandBuffer};
+use fnv::FnvHashSet;
+
+use super::{DrawParams, EyeParams};
+use ::{Error, FlightError};
+
+/// The name of a drawing target (such as `"shadow_depth"` or `"hdr_color"`) that passes
+/// read from or write to
+pub type TargetId = &'static str;
+
+/// Describes the targets a rendering pass uses and the passes it depends on
+#[derive(Clone, Debug, Default)]
+pub struct PassDesc {
+    pub name: &'static str,
+    pub reads: Vec<TargetId>,
+    pub writes: Vec<TargetId>,
+    pub requires: Vec<&'static str>,
+    /// The layer mask drawn with while the pass runs, instead of `DrawParams::layer_mask`
+    pub layers: Option<u32>,
+}
+
+impl PassDesc {
+    /// Describe a pass with the given name that uses no targets
+    pub fn new(name: &'static str) -> PassDesc {
+        PassDesc {
+            name: name,
+            .. Default::default()
+        }
+    }
+
+    /// The pass samples from the given target
+    pub fn reads(mut self, target: TargetId) -> PassDesc {
+        self.reads.push(target);
+        self
+    }
+
+    /// The pass draws into the given target
+    pub fn writes(mut self, target: TargetId) -> PassDesc {
+        self.writes.push(target);
+        self
+    }
+
+    /// The given pass must run before this one every frame
+    pub fn requires(mut self, pass: &'static str) -> PassDesc {
+        self.requires.push(pass);
+        self
+    }
+
+    /// The pass only draws queued draws on the given layers, such as a shadow pass leaving
+    /// out layers that don't cast shadows
+    pub fn layers(mut self, mask: u32) -> PassDesc {
+        self.layers = Some(mask);
+        self
+    }
+}
+
+/// Checks that rendering passes run in a valid order. Passes are registered once with
+/// the targets they read and write, then run each frame through `run`, which also
+/// handles drawing both eyes.
+///
+/// Running a pass fails if it reads a target that it also writes, if one of its
+/// required passes has not yet run this frame, or if it reads a target that a
+/// different registered pass writes but that pass has not run yet this frame.
+#[derive(Clone, Debug, Default)]
+pub struct PassManager {
+    passes: Vec<PassDesc>,
+    ran: Vec<&'static str>,
+    written: FnvHashSet<TargetId>,
+}
+
+impl PassManager {
+    /// Create a manager without any passes
+    pub fn new() -> PassManager {
+        Default::default()
+    }
+
+    /// Register a pass, returning an error if it is invalid on its own
+    pub fn register(&mut self, pass: PassDesc) -> Result<(), Error> {
+        ensure!(
+            self.find(pass.name).is_none(),
+            FlightError::DuplicatePass { name: pass.name }
+        );
+        if let Some(t) = pass.reads.iter().find(|t| pass.writes.contains(t)) {
+            bail!(FlightError::PassHazard { pass: pass.name, target: *t });
+        }
+        self.passes.push(pass);
+        Ok(())
+    }
+
+    /// Forget which passes ran, call this at the start of each frame
+    pub fn begin_frame(&mut self) {
+        self.ran.clear();
+        self.written.clear();
+    }
+
+    /// The passes that have run so far this frame, in order
+    pub fn ran(&self) -> &[&'static str] {
+        &self.ran
+    }
+
+    /// Check if the given pass can run now, and if so, record that it ran
+    pub fn enter(&mut self, name: &'static str) -> Result<(), Error> {
+        let pass = match self.find(name) {
+            Some(p) => &self.passes[p],
+            None => bail!(FlightError::UnknownPass { name: name }),
+        };
+        for &r in &pass.requires {
+            ensure!(
+                self.ran.contains(&r),
+                FlightError::MissingPrerequisite { pass: name, requires: r }
+            );
+        }
+        for &t in &pass.reads {
+            if self.written.contains(t) { continue }
+            if let Some(w) = self.passes.iter().find(|p| p.name != name && p.writes.contains(&t)) {
+                bail!(FlightError::PassOutOfOrder { pass: name, target: t, writer: w.name });
+            }
+        }
+        self.ran.push(name);
+        self.written.extend(pass.writes.iter().cloned());
+        Ok(())
+    }
+
+    /// Check that the given pass can run now, then draw it once for each eye
+    pub fn run<R, C, F>(&mut self, name: &'static str, ctx: &mut DrawParams<R, C>, mut draw: F)
+        -> Result<(), Error>
+        where
+            R: Resources,
+            C: CommandBuffer<R>,
+            F: FnMut(&mut DrawParams<R, C>, &EyeParams) -> Result<(), Error>,
+    {
+        if let Err(e) = self.enter(name) {
+            error!("{}", e);
+            return Err(e);
+        }
+        let layers = self.find(name).and_then(|p| self.passes[p].layers);
+        let mask = ctx.layer_mask;
+        ctx.layer_mask = layers.unwrap_or(mask);
+        let eyes = ctx.eyes();
+        let result = eyes.iter().map(|eye| draw(ctx, eye)).collect();
+        ctx.layer_mask = mask;
+        result
+    }
+
+    /// Check that running the given passes in order would be valid, without running them
+    pub fn validate_order(&self, order: &[&'static str]) -> Result<(), Error> {
+        let mut check = PassManager {
+            passes: self.passes.clone(),
+            ran: Vec::new(),
+            written: Default::default(),
+        };
+        for &p in order {
+            check.enter(p)?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|p| p.name == name)
+    }
+}
+
+#[cfg(test)]
+fn example_passes() -> PassManager {
+    let mut passes = PassManager::new();
+    passes.register(PassDesc::new("shadow").writes("shadow_depth")).unwrap();
+    passes.register(PassDesc::new("uber")
+        .reads("shadow_depth")
+        .writes("hdr")
+        .requires("shadow")).unwrap();
+    passes.register(PassDesc::new("tonemap").reads("hdr").writes("color")).unwrap();
+    passes
+}
+
+#[test]
+fn pass_order() {
+    let passes = example_passes();
+    assert!(passes.validate_order(&["shadow", "uber", "tonemap"]).is_ok());
+    assert!(passes.validate_order(&["uber", "shadow", "tonemap"]).is_err());
+    assert!(passes.validate_order(&["shadow", "tonemap", "uber"]).is_err());
+    assert!(passes.validate_order(&["shadow", "blur"]).is_err());
+
+    let mut passes = passes;
+    passes.enter("shadow").unwrap();
+    passes.enter("uber").unwrap();
+    assert_eq!(passes.ran(), &["shadow", "uber"]);
+    passes.begin_frame();
+    assert!(passes.enter("uber").is_err());
+}
+
+#[test]
+fn pass_hazards() {
+    let mut passes = example_passes();
+    assert!(passes.register(PassDesc::new("blur").reads("hdr").writes("hdr")).is_err());
+    assert!(passes.register(PassDesc::new("shadow")).is_err());
+}
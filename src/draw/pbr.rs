@@ -13,7 +13,7 @@ use ::{Light, Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
 pub const LIGHT_COUNT: usize = 4;
 
 /// The collection of mesh textures used by physically based rendering
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct PbrMaterial<R: Resources> {
     /// Normal map
     pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
@@ -33,7 +33,7 @@ gfx_defines!{
         lights: gfx::ConstantBuffer<LightBlock> = "lights_layout",
         scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
         color: gfx::RenderTarget<ColorFormat> = "f_lum",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
         normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
         albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
         metalness: gfx::TextureSampler<f32> = "metalness_tex",
@@ -104,6 +104,7 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
     type Vertex = VertNTT;
     type Inputs = PbrInputs<R>;
     type Material = PbrMaterial<R>;
+    type Bound = pl::Data<R>;
 
     fn new<F: Factory<R> + FactoryExt<R>> (
         f: &mut F,
@@ -130,16 +131,36 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
         })
     }
 
-    fn draw_raw<C>(
+    fn bind(
         &self,
-        inputs: &mut PbrInputs<R>,
-        enc: &mut Encoder<R, C>,
+        inputs: &PbrInputs<R>,
         color: TargetRef<R>,
         depth: DepthRef<R>,
-        scissor: Rect,
-        slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         mat: &PbrMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            params: inputs.params_block.clone(),
+            lights: inputs.lights_block.clone(),
+            normal: mat.normal.clone().into_tuple(),
+            albedo: mat.albedo.clone().into_tuple(),
+            metalness: mat.metalness.clone().into_tuple(),
+            roughness: mat.roughness.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut PbrInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
     )
         -> Result<(), Error>
         where C: CommandBuffer<R>
@@ -153,19 +174,8 @@ impl<R: Resources> Style<R> for PbrStyle<R> {
         if let Some(p) = inputs.params.take() {
             enc.update_constant_buffer(&inputs.params_block, &p);
         }
-        enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
-            verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
-            params: inputs.params_block.clone(),
-            lights: inputs.lights_block.clone(),
-            normal: mat.normal.clone().into_tuple(),
-            albedo: mat.albedo.clone().into_tuple(),
-            metalness: mat.metalness.clone().into_tuple(),
-            roughness: mat.roughness.clone().into_tuple(),
-        });
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
         Ok(())
     }
 }
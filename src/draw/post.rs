@@ -0,0 +1,484 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect, Slice, IndexBuffer};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::state::Rasterizer;
+use gfx::format::{R8_G8_B8_A8, Srgb};
+use image::{Rgba, RgbaImage};
+use nalgebra::{Vector3, Vector4, Rotation3};
+
+use super::{DrawParams, EyeParams, OffscreenTarget, UberEnv};
+use ::mesh::Primitive;
+use ::{Error, ColorFormat, Texture};
+
+gfx_defines!{
+    constant GodRayBlock {
+        sun_pos: [f32; 4] = "sun_pos",
+        sun_color: [f32; 4] = "sun_color",
+        viewport: [f32; 4] = "viewport",
+        target_size: [f32; 4] = "target_size",
+        density: f32 = "density",
+        weight: f32 = "weight",
+        decay: f32 = "decay",
+        exposure: f32 = "exposure",
+        threshold: f32 = "threshold",
+    }
+
+    pipeline god_rays {
+        params: gfx::ConstantBuffer<GodRayBlock> = "god_rays",
+        scissor: gfx::Scissor = (),
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ADD),
+        occlusion: gfx::TextureSampler<[f32; 4]> = "occlusion_tex",
+    }
+
+    constant LensResolveBlock {
+        lens: [f32; 4] = "lens",
+    }
+
+    pipeline lens_resolve {
+        params: gfx::ConstantBuffer<LensResolveBlock> = "lens_resolve",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        scene: gfx::TextureSampler<[f32; 4]> = "scene_tex",
+    }
+
+    constant FlareSpotBlock {
+        viewport: [f32; 4] = "viewport",
+        spot_viewport: [f32; 4] = "spot_viewport",
+        target_size: [f32; 4] = "target_size",
+        threshold: f32 = "threshold",
+    }
+
+    pipeline flare_spots {
+        params: gfx::ConstantBuffer<FlareSpotBlock> = "flare_spots",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        scene: gfx::TextureSampler<[f32; 4]> = "scene_tex",
+    }
+
+    constant FlareBlock {
+        viewport: [f32; 4] = "viewport",
+        target_size: [f32; 4] = "target_size",
+        sprite: [f32; 4] = "sprite",
+        color: [f32; 4] = "color",
+        spot_column: i32 = "spot_column",
+        max_spots: i32 = "max_spots",
+    }
+
+    pipeline flare {
+        params: gfx::ConstantBuffer<FlareBlock> = "lens_flare",
+        scissor: gfx::Scissor = (),
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ADD),
+        spots: gfx::TextureSampler<[f32; 4]> = "spots_tex",
+        sprite: gfx::TextureSampler<[f32; 4]> = "sprite_tex",
+    }
+}
+
+shader!(god_ray_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/god_rays.f.glsl")
+});
+
+shader!(lens_resolve_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/lens_resolve.f.glsl")
+});
+
+shader!(flare_spot_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/flare_spots.f.glsl")
+});
+
+shader!(flare_shader {
+    vertex: static_file!("shaders/lens_flare.v.glsl")
+        .define_to("FLARE_GRID", FLARE_GRID),
+    fragment: static_file!("shaders/lens_flare.f.glsl")
+});
+
+/// A triangle covering the whole target, generated in the vertex shader
+pub(super) fn fullscreen_slice<R: Resources>() -> Slice<R> {
+    Slice {
+        start: 0,
+        end: 3,
+        base_vertex: 0,
+        instances: None,
+        buffer: IndexBuffer::Auto,
+    }
+}
+
+/// Where the sun appears in an eye viewport (0 to 1 across, +Y up), and how visible it is
+/// from 0 to 1. The visibility falls off as the sun leaves the viewport and is 0 behind the
+/// viewer.
+pub fn sun_screen_pos(eye: &EyeParams, sun_rotation: &Rotation3<f32>) -> [f32; 3] {
+    // the sun shines along -Z of its rotation, so it sits infinitely far along +Z
+    let dir = sun_rotation * Vector3::z();
+    let view = eye.view.matrix() * Vector4::new(dir.x, dir.y, dir.z, 0.);
+    let mut clip = eye.proj.matrix() * view;
+    if clip.w <= 1e-6 {
+        return [0.5, 0.5, 0.];
+    }
+    // undo the halving of x done by the transform shader
+    clip.x *= 0.5;
+    let (x, y) = (clip.x / clip.w * 0.5 + 0.5, clip.y / clip.w * 0.5 + 0.5);
+    let outside = (x - 0.5).abs().max((y - 0.5).abs()) - 0.5;
+    [x, y, (1. - outside.max(0.) * 2.).max(0.)]
+}
+
+/// Light shafts from the sun through openings such as a forest canopy or a window, using
+/// the screen space radial blur of Kenny Mitchell ("Volumetric Light Scattering as a
+/// Post-Process", GPU Gems 3). The scene is first drawn into `occlusion` between
+/// `begin_occlusion` and `end_occlusion`, where anything brighter than `threshold` (the sky
+/// and sun) emits light. Drawing occluders with a black unlit material gives the sharpest
+/// shafts. `apply` then blurs it outward from the sun's position in each eye and adds the
+/// result onto the scene color.
+pub struct GodRayPass<R: Resources> {
+    /// Where the occlusion of the sun is drawn
+    pub occlusion: OffscreenTarget<R>,
+    /// How far towards the sun samples reach, from 0 to 1
+    pub density: f32,
+    /// The brightness of each sample
+    pub weight: f32,
+    /// How much each sample fades compared to the one before
+    pub decay: f32,
+    /// The overall brightness of the shafts
+    pub exposure: f32,
+    /// Occlusion colors below this brightness let no light through
+    pub threshold: f32,
+    pso: PipelineState<R, god_rays::Meta>,
+    params: Buffer<R, GodRayBlock>,
+    saved: Option<(::TargetRef<R>, ::DepthRef<R>)>,
+}
+
+impl<R: Resources> GodRayPass<R> {
+    /// Create a pass drawing into targets `width` by `height` pixels, which should match
+    /// the color target it is applied to
+    pub fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        width: u16,
+        height: u16,
+        density: f32,
+        weight: f32,
+        decay: f32,
+        exposure: f32,
+    )
+        -> Result<GodRayPass<R>, Error>
+    {
+        let shaders = god_ray_shader(f)?;
+        Ok(GodRayPass {
+            occlusion: OffscreenTarget::new(f, width, height)?,
+            density: density,
+            weight: weight,
+            decay: decay,
+            exposure: exposure,
+            threshold: 0.8,
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), god_rays::new())?,
+            params: f.create_constant_buffer(1),
+            saved: None,
+        })
+    }
+
+    /// Redirect drawing to the occlusion target, cleared to black
+    pub fn begin_occlusion<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        ctx.encoder.clear(&self.occlusion.color, [0., 0., 0., 1.]);
+        ctx.encoder.clear_depth(&self.occlusion.depth, super::DEPTH_CONVENTION.far());
+        let color = ::std::mem::replace(&mut ctx.color, self.occlusion.color.clone());
+        let depth = ::std::mem::replace(&mut ctx.depth, self.occlusion.depth.clone());
+        if self.saved.is_none() {
+            self.saved = Some((color, depth));
+        }
+    }
+
+    /// Go back to drawing into the targets that were in use before `begin_occlusion`
+    pub fn end_occlusion<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        if let Some((color, depth)) = self.saved.take() {
+            ctx.color = color;
+            ctx.depth = depth;
+        }
+    }
+
+    /// Add light shafts from the sun of `env` onto the color target of `ctx`. Apply this
+    /// before drawing UI, which would be brightened by the shafts too.
+    pub fn apply<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, env: &UberEnv<R>) {
+        profile_scope!("god_rays");
+        let slice = fullscreen_slice();
+        let size = [self.occlusion.width as f32, self.occlusion.height as f32, 0., 0.];
+        let c = env.sun_color;
+        for eye in &ctx.eyes() {
+            let sun = sun_screen_pos(eye, &env.sun_rotation);
+            if sun[2] <= 0. || eye.clip.w == 0 || eye.clip.h == 0 {
+                continue;
+            }
+            let Rect { x, y, w, h } = eye.clip;
+            ctx.encoder.update_constant_buffer(&self.params, &GodRayBlock {
+                sun_pos: [sun[0], sun[1], sun[2], 0.],
+                sun_color: [c[0] * c[3], c[1] * c[3], c[2] * c[3], 1.],
+                viewport: [x as f32, y as f32, w as f32, h as f32],
+                target_size: size,
+                density: self.density,
+                weight: self.weight,
+                decay: self.decay,
+                exposure: self.exposure,
+                threshold: self.threshold,
+            });
+            ctx.encoder.draw(&slice, &self.pso, &god_rays::Data {
+                params: self.params.clone(),
+                scissor: eye.clip,
+                color: ctx.color.clone(),
+                occlusion: self.occlusion.texture.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+}
+
+/// Copies a scene drawn with `LensShading` to the color target, filling in the pixels that
+/// were skipped from their neighbors. With lens shading off this is a plain copy, so it can
+/// stay in the frame while the setting is toggled.
+pub struct LensResolve<R: Resources> {
+    pso: PipelineState<R, lens_resolve::Meta>,
+    params: Buffer<R, LensResolveBlock>,
+}
+
+impl<R: Resources> LensResolve<R> {
+    /// Build the resolve pipeline
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<LensResolve<R>, Error> {
+        let shaders = lens_resolve_shader(f)?;
+        Ok(LensResolve {
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), lens_resolve::new())?,
+            params: f.create_constant_buffer(1),
+        })
+    }
+
+    /// Copy `scene`, drawn with `ctx.lens_shading`, onto the color target of `ctx`
+    pub fn apply<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, scene: &OffscreenTarget<R>) {
+        profile_scope!("lens_resolve");
+        let slice = fullscreen_slice();
+        for eye in &ctx.eyes() {
+            ctx.encoder.update_constant_buffer(&self.params, &LensResolveBlock {
+                lens: ctx.lens_shading.block(eye.clip),
+            });
+            ctx.encoder.draw(&slice, &self.pso, &lens_resolve::Data {
+                params: self.params.clone(),
+                scissor: eye.clip,
+                color: ctx.color.clone(),
+                scene: scene.texture.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+}
+
+/// The number of cells along each side of an eye searched for bright spots by `LensFlare`
+pub const FLARE_GRID: u32 = 16;
+/// The most bright spots per eye that `LensFlare` draws flares for
+pub const MAX_FLARE_SPOTS: u32 = 10;
+
+/// A shape for lens flare sprites, see `flare_image`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FlareShape {
+    /// A soft disc with a brighter rim, like a ghost of a round aperture
+    Circle,
+    /// A soft hexagon, like the bokeh of a six bladed aperture
+    Hexagon,
+    /// A glow with thin rays, drawn over the light itself
+    Starburst {
+        /// The number of rays
+        rays: u32,
+    },
+}
+
+/// Draw a white flare sprite `size` pixels across, for `FlareSprite::texture` (loaded
+/// with `load::load_rgba8`). Brightness is stored in the color, which is added onto
+/// the scene.
+pub fn flare_image(shape: FlareShape, size: u32) -> RgbaImage {
+    let size = size.max(1);
+    RgbaImage::from_fn(size, size, |x, y| {
+        let px = (x as f32 + 0.5) / size as f32 * 2. - 1.;
+        let py = (y as f32 + 0.5) / size as f32 * 2. - 1.;
+        let r = (px * px + py * py).sqrt();
+        // soft edges over the outer tenth
+        let edge = |d: f32| ((1. - d) / 0.1).max(0.).min(1.);
+        let v = match shape {
+            FlareShape::Circle => edge(r) * (0.4 + 0.6 * r * r),
+            FlareShape::Hexagon => {
+                let (ax, ay) = (px.abs(), py.abs());
+                let d = ay.max(ax * 0.866_025 + ay * 0.5) / 0.866_025;
+                edge(d) * (0.5 + 0.5 * d * d)
+            },
+            FlareShape::Starburst { rays } => {
+                let angle = py.atan2(px);
+                let ray = (angle * rays as f32 * 0.5).cos().abs().powi(32);
+                let falloff = (1. - r).max(0.);
+                (falloff.powi(4) + ray * falloff.powi(2) * 0.5).min(1.)
+            },
+        };
+        let c = (v.max(0.).min(1.) * 255. + 0.5) as u8;
+        Rgba([c, c, c, 255])
+    })
+}
+
+/// One sprite of a lens flare, drawn for each bright spot along the line from the spot
+/// through the center of the view
+#[derive(Clone)]
+pub struct FlareSprite<R: Resources> {
+    /// The sprite, usually from `flare_image`
+    pub texture: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    /// Where along the line the sprite is drawn: 0 on the spot, 1 at the center of the view
+    /// and 2 mirrored across it
+    pub offset: f32,
+    /// The height of the sprite as a fraction of the view height
+    pub scale: f32,
+    /// The tint of the sprite, with w the strength
+    pub color: [f32; 4],
+}
+
+/// Lens flares from bright spots of the scene. `apply` searches a `FLARE_GRID` by
+/// `FLARE_GRID` grid over each eye of the scene for light above `threshold`, then adds
+/// every sprite for each of the `MAX_FLARE_SPOTS` brightest cells onto the color target.
+/// Everything stays on the GPU: the grid is a small target, and the sprites are drawn
+/// instanced once per cell with those outside the brightest collapsed in the vertex shader.
+pub struct LensFlare<R: Resources> {
+    /// The sprites drawn for each spot
+    pub sprites: Vec<FlareSprite<R>>,
+    /// The luminance from 0 to 1 above which the scene makes flares
+    pub threshold: f32,
+    /// The overall strength of the flares
+    pub intensity: f32,
+    spots: OffscreenTarget<R>,
+    spot_pso: PipelineState<R, flare_spots::Meta>,
+    spot_params: Buffer<R, FlareSpotBlock>,
+    pso: PipelineState<R, flare::Meta>,
+    params: Buffer<R, FlareBlock>,
+}
+
+impl<R: Resources> LensFlare<R> {
+    /// Build the flare pipelines drawing the given sprites
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, sprites: Vec<FlareSprite<R>>)
+        -> Result<LensFlare<R>, Error>
+    {
+        let spot_shaders = flare_spot_shader(f)?;
+        let shaders = flare_shader(f)?;
+        let grid = FLARE_GRID as u16;
+        Ok(LensFlare {
+            sprites: sprites,
+            threshold: 0.9,
+            intensity: 1.,
+            // a grid for each eye, side by side
+            spots: OffscreenTarget::new(f, grid * 2, grid)?,
+            spot_pso: f.create_pipeline_state(&spot_shaders, Primitive::TriangleList, Rasterizer::new_fill(), flare_spots::new())?,
+            spot_params: f.create_constant_buffer(1),
+            pso: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), flare::new())?,
+            params: f.create_constant_buffer(1),
+        })
+    }
+
+    /// Add flares from the bright spots of `scene` onto the color target of `ctx`. Apply
+    /// this before drawing UI, so that bright UI doesn't flare.
+    pub fn apply<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, scene: &OffscreenTarget<R>) {
+        profile_scope!("lens_flare");
+        let (width, height, _, _) = ctx.color.get_dimensions();
+        let target_size = [width as f32, height as f32, 0., 0.];
+        let grid = FLARE_GRID as u16;
+        let eyes = ctx.eyes();
+
+        ctx.encoder.clear(&self.spots.color, [0., 0., 0., 0.]);
+        for (column, eye) in eyes.iter().enumerate() {
+            if eye.clip.w == 0 || eye.clip.h == 0 { continue }
+            let Rect { x, y, w, h } = eye.clip;
+            let cells = Rect { x: column as u16 * grid, y: 0, w: grid, h: grid };
+            ctx.encoder.update_constant_buffer(&self.spot_params, &FlareSpotBlock {
+                viewport: [x as f32, y as f32, w as f32, h as f32],
+                spot_viewport: [cells.x as f32, 0., grid as f32, grid as f32],
+                target_size: [scene.width as f32, scene.height as f32, 0., 0.],
+                threshold: self.threshold,
+            });
+            ctx.encoder.draw(&fullscreen_slice(), &self.spot_pso, &flare_spots::Data {
+                params: self.spot_params.clone(),
+                scissor: cells,
+                color: self.spots.color.clone(),
+                scene: scene.texture.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+
+        // two triangles for each cell of the grid, without any vertex buffer
+        let slice = Slice {
+            start: 0,
+            end: 6,
+            base_vertex: 0,
+            instances: Some((FLARE_GRID * FLARE_GRID, 0)),
+            buffer: IndexBuffer::Auto,
+        };
+        for (column, eye) in eyes.iter().enumerate() {
+            if eye.clip.w == 0 || eye.clip.h == 0 { continue }
+            let Rect { x, y, w, h } = eye.clip;
+            for sprite in &self.sprites {
+                let c = sprite.color;
+                let strength = c[3] * self.intensity;
+                ctx.encoder.update_constant_buffer(&self.params, &FlareBlock {
+                    viewport: [x as f32, y as f32, w as f32, h as f32],
+                    target_size: target_size,
+                    sprite: [sprite.offset, sprite.scale, 0., 0.],
+                    color: [c[0] * strength, c[1] * strength, c[2] * strength, 1.],
+                    spot_column: column as i32,
+                    max_spots: MAX_FLARE_SPOTS as i32,
+                });
+                ctx.encoder.draw(&slice, &self.pso, &flare::Data {
+                    params: self.params.clone(),
+                    scissor: eye.clip,
+                    color: ctx.color.clone(),
+                    spots: self.spots.texture.clone().into_tuple(),
+                    sprite: sprite.texture.clone().into_tuple(),
+                });
+                ctx.draw_calls += 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn sun_position() {
+    use nalgebra::{self as na, Perspective3, Transform3, Matrix4};
+    use std::f32::consts::FRAC_PI_2;
+
+    let proj = Matrix4::new_nonuniform_scaling(&Vector3::new(2., 1., 1.))
+        * Perspective3::new(1., FRAC_PI_2, 0.1, 10.).to_homogeneous();
+    let eye = EyeParams {
+        proj: Transform3::from_matrix_unchecked(proj),
+        .. Default::default()
+    };
+    // straight ahead of a viewer looking down -Z
+    let ahead = Rotation3::from_axis_angle(&Vector3::y_axis(), ::std::f32::consts::PI);
+    let p = sun_screen_pos(&eye, &ahead);
+    assert!(relative_eq!(p[0], 0.5) && relative_eq!(p[1], 0.5) && relative_eq!(p[2], 1.));
+    // directly behind
+    let p = sun_screen_pos(&eye, &na::one());
+    assert_eq!(p[2], 0.);
+    // 45 degrees up is the top edge of a 90 degree field of view
+    let up = Rotation3::from_axis_angle(&Vector3::x_axis(), FRAC_PI_2 / 2.) * ahead;
+    let p = sun_screen_pos(&eye, &up);
+    assert!(relative_eq!(p[1], 1., epsilon = 1e-5) && relative_eq!(p[2], 1., epsilon = 1e-5));
+}
+
+#[test]
+fn flare_images() {
+    for &shape in &[FlareShape::Circle, FlareShape::Hexagon, FlareShape::Starburst { rays: 6 }] {
+        let image = flare_image(shape, 32);
+        assert_eq!(image.dimensions(), (32, 32));
+        // nothing in the corners, and mirrored across the center
+        assert_eq!(image.get_pixel(0, 0)[0], 0);
+        assert_eq!(image.get_pixel(31, 31)[0], 0);
+        for &(x, y) in &[(4, 16), (10, 3), (16, 20)] {
+            assert_eq!(image.get_pixel(x, y)[0], image.get_pixel(31 - x, 31 - y)[0]);
+        }
+    }
+    // the starburst is brightest in the middle
+    let star = flare_image(FlareShape::Starburst { rays: 6 }, 32);
+    assert!(star.get_pixel(16, 16)[0] > 200);
+    // the hexagon has corners along x and flat sides along y, unlike a circle
+    let hex = flare_image(FlareShape::Hexagon, 64);
+    let circle = flare_image(FlareShape::Circle, 64);
+    assert!(hex.get_pixel(1, 32)[0] > 0);
+    assert_eq!(hex.get_pixel(32, 1)[0], 0);
+    assert!(circle.get_pixel(32, 1)[0] > 0);
+}
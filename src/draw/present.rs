@@ -0,0 +1,426 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, RawRenderTargetView, RenderTargetView};
+use gfx::memory::Typed;
+use gfx::state::Rasterizer;
+use gfx::format::{Formatted, ChannelType, R8_G8_B8_A8, Unorm};
+use image::{Rgba, RgbaImage};
+
+use super::{DrawParams, OffscreenTarget};
+use super::post::fullscreen_slice;
+use ::mesh::Primitive;
+use ::load::load_rgba8;
+use ::{Error, ColorFormat, ColorFormatSrgb, Texture};
+
+gfx_defines!{
+    constant PresentBlock {
+        source: [f32; 4] = "source",
+        viewport: [f32; 4] = "viewport",
+        decode: i32 = "decode",
+        encode: i32 = "encode",
+    }
+
+    pipeline present_unorm {
+        params: gfx::ConstantBuffer<PresentBlock> = "present",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<(R8_G8_B8_A8, Unorm)> = "f_color",
+        frame: gfx::TextureSampler<[f32; 4]> = "frame_tex",
+    }
+
+    pipeline present_srgb {
+        params: gfx::ConstantBuffer<PresentBlock> = "present",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormatSrgb> = "f_color",
+        frame: gfx::TextureSampler<[f32; 4]> = "frame_tex",
+    }
+
+    constant LenticularBlock {
+        viewport: [f32; 4] = "viewport",
+        decode: i32 = "decode",
+        encode: i32 = "encode",
+    }
+
+    pipeline lenticular_unorm {
+        params: gfx::ConstantBuffer<LenticularBlock> = "lenticular",
+        color: gfx::RenderTarget<(R8_G8_B8_A8, Unorm)> = "f_color",
+        left: gfx::TextureSampler<[f32; 4]> = "left_tex",
+        right: gfx::TextureSampler<[f32; 4]> = "right_tex",
+    }
+
+    pipeline lenticular_srgb {
+        params: gfx::ConstantBuffer<LenticularBlock> = "lenticular",
+        color: gfx::RenderTarget<ColorFormatSrgb> = "f_color",
+        left: gfx::TextureSampler<[f32; 4]> = "left_tex",
+        right: gfx::TextureSampler<[f32; 4]> = "right_tex",
+    }
+}
+
+shader!(present_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/present.f.glsl")
+});
+
+shader!(lenticular_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/lenticular.f.glsl")
+});
+
+/// What the color values read or written by shaders mean for a surface
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SurfaceEncoding {
+    /// Values are stored as they are, so they must already be sRGB encoded for display
+    Display,
+    /// Values are linear, converted to and from sRGB by the hardware
+    Linear,
+}
+
+impl SurfaceEncoding {
+    /// The encoding of surfaces with the format `T`
+    pub fn of<T: Formatted>() -> SurfaceEncoding {
+        if T::get_format().1 == ChannelType::Srgb {
+            SurfaceEncoding::Linear
+        } else {
+            SurfaceEncoding::Display
+        }
+    }
+
+    /// The encoding of frames drawn into `ColorFormat`, which is `Linear` with the
+    /// `srgb-framebuffer` feature. Shaders write display values otherwise (see `OUTPUT_GAMMA`).
+    pub fn frame() -> SurfaceEncoding {
+        SurfaceEncoding::of::<ColorFormat>()
+    }
+
+    /// Whether a copy from a surface with this encoding into one with `target` must convert
+    /// from sRGB to linear, or from linear to sRGB
+    pub fn conversion(self, target: SurfaceEncoding) -> (bool, bool) {
+        use self::SurfaceEncoding::*;
+        (self == Display && target == Linear, self == Linear && target == Display)
+    }
+}
+
+/// A window or headset surface that finished frames are shown on. Which one a window gets
+/// depends on whether the surface it was given is sRGB capable (`glutin::PixelFormat::srgb`),
+/// see `from_raw`.
+#[derive(Clone)]
+pub enum PresentTarget<R: Resources> {
+    /// Shows the values written as they are
+    Unorm(RenderTargetView<R, (R8_G8_B8_A8, Unorm)>),
+    /// Encodes the linear values written to it as sRGB
+    Srgb(RenderTargetView<R, ColorFormatSrgb>),
+}
+
+impl<R: Resources> PresentTarget<R> {
+    /// Wrap the color view of a window, created without a type for its channel, as an sRGB
+    /// target if the window surface is sRGB capable
+    pub fn from_raw(view: RawRenderTargetView<R>, srgb: bool) -> PresentTarget<R> {
+        if srgb {
+            PresentTarget::Srgb(Typed::new(view))
+        } else {
+            PresentTarget::Unorm(Typed::new(view))
+        }
+    }
+
+    /// The encoding of the values drawn into the target
+    pub fn encoding(&self) -> SurfaceEncoding {
+        match *self {
+            PresentTarget::Unorm(_) => SurfaceEncoding::Display,
+            PresentTarget::Srgb(_) => SurfaceEncoding::Linear,
+        }
+    }
+
+    /// The size of the target in pixels
+    pub fn dimensions(&self) -> (u16, u16) {
+        let (w, h, _, _) = match *self {
+            PresentTarget::Unorm(ref v) => v.get_dimensions(),
+            PresentTarget::Srgb(ref v) => v.get_dimensions(),
+        };
+        (w, h)
+    }
+
+    /// The whole target
+    pub fn rect(&self) -> Rect {
+        let (w, h) = self.dimensions();
+        Rect { x: 0, y: 0, w: w, h: h }
+    }
+}
+
+impl<R: Resources> From<RenderTargetView<R, (R8_G8_B8_A8, Unorm)>> for PresentTarget<R> {
+    fn from(view: RenderTargetView<R, (R8_G8_B8_A8, Unorm)>) -> PresentTarget<R> {
+        PresentTarget::Unorm(view)
+    }
+}
+
+impl<R: Resources> From<RenderTargetView<R, ColorFormatSrgb>> for PresentTarget<R> {
+    fn from(view: RenderTargetView<R, ColorFormatSrgb>) -> PresentTarget<R> {
+        PresentTarget::Srgb(view)
+    }
+}
+
+/// Draw the calibration pattern shown by `Present::calibrate`, as display values. From the
+/// top, it has 16 gray steps, a smooth gray ramp, red, green and blue ramps, and then black
+/// and white lines beside a solid gray of the same brightness (188, half of white in linear
+/// light). When a target is presented correctly, the steps are evenly spaced, the darkest
+/// ones are told apart, and the lines and the solid gray match when seen from a distance.
+pub fn calibration_pattern(width: u32, height: u32) -> RgbaImage {
+    let (width, height) = (width.max(2), height.max(8));
+    let band = height / 4;
+    RgbaImage::from_fn(width, height, |x, y| {
+        let t = x as f32 / (width - 1) as f32;
+        let ramp = (t * 255. + 0.5) as u8;
+        let step = ((x * 16 / width) * 255 / 15) as u8;
+        let gray = |v| Rgba([v, v, v, 255]);
+        match (y / band).min(3) {
+            0 => gray(step),
+            1 => gray(ramp),
+            2 => match ((y - band * 2) * 3 / band).min(2) {
+                0 => Rgba([ramp, 0, 0, 255]),
+                1 => Rgba([0, ramp, 0, 255]),
+                _ => Rgba([0, 0, ramp, 255]),
+            },
+            _ if x < width / 2 => gray(if y % 2 == 0 { 255 } else { 0 }),
+            _ => gray(188),
+        }
+    })
+}
+
+/// The final copy of a frame onto the surfaces it is shown on, such as the headset and a
+/// desktop window mirroring it. Each copy converts between the encoding of the frame and of
+/// the target, so that the same frame looks the same on both whether or not their surfaces
+/// are sRGB.
+pub struct Present<R: Resources> {
+    unorm: PipelineState<R, present_unorm::Meta>,
+    srgb: PipelineState<R, present_srgb::Meta>,
+    params: Buffer<R, PresentBlock>,
+}
+
+impl<R: Resources> Present<R> {
+    /// Build the copy pipelines
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<Present<R>, Error> {
+        let shaders = present_shader(f)?;
+        Ok(Present {
+            unorm: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), present_unorm::new())?,
+            srgb: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), present_srgb::new())?,
+            params: f.create_constant_buffer(1),
+        })
+    }
+
+    /// Copy the `source` rectangle of `frame`, in pixels, onto the `viewport` rectangle of
+    /// `target`, scaling it to fit
+    pub fn blit<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        frame: &Texture<R, ColorFormat>,
+        source: Rect,
+        target: &PresentTarget<R>,
+        viewport: Rect,
+    ) {
+        profile_scope!("present");
+        let (decode, encode) = SurfaceEncoding::frame().conversion(target.encoding());
+        let rect = |r: Rect| [r.x as f32, r.y as f32, r.w as f32, r.h as f32];
+        ctx.encoder.update_constant_buffer(&self.params, &PresentBlock {
+            source: rect(source),
+            viewport: rect(viewport),
+            decode: decode as i32,
+            encode: encode as i32,
+        });
+        let slice = fullscreen_slice();
+        match *target {
+            PresentTarget::Unorm(ref color) => ctx.encoder.draw(&slice, &self.unorm, &present_unorm::Data {
+                params: self.params.clone(),
+                scissor: viewport,
+                color: color.clone(),
+                frame: frame.clone().into_tuple(),
+            }),
+            PresentTarget::Srgb(ref color) => ctx.encoder.draw(&slice, &self.srgb, &present_srgb::Data {
+                params: self.params.clone(),
+                scissor: viewport,
+                color: color.clone(),
+                frame: frame.clone().into_tuple(),
+            }),
+        }
+        ctx.draw_calls += 1;
+    }
+
+    /// Copy the whole of `frame` onto the whole of `target`
+    pub fn mirror<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        frame: &OffscreenTarget<R>,
+        target: &PresentTarget<R>,
+    ) {
+        let source = Rect { x: 0, y: 0, w: frame.width, h: frame.height };
+        self.blit(ctx, &frame.texture, source, target, target.rect());
+    }
+
+    /// Show `calibration_pattern` in each eye of the color target of `ctx` and across
+    /// `mirror`, each drawn pixel for pixel, so that they can be compared side by side
+    pub fn calibrate<F, C>(&self, f: &mut F, ctx: &mut DrawParams<R, C>, mirror: &PresentTarget<R>)
+        -> Result<(), Error>
+        where
+            F: Factory<R>,
+            C: CommandBuffer<R>,
+    {
+        let mut targets: Vec<(PresentTarget<R>, Rect)> = ctx.eyes().iter()
+            .filter(|eye| eye.clip.w > 0 && eye.clip.h > 0)
+            .map(|eye| (PresentTarget::from(ctx.color.clone()), eye.clip))
+            .collect();
+        targets.push((mirror.clone(), mirror.rect()));
+        let sampler = f.create_sampler_linear();
+        for (target, viewport) in targets {
+            let pattern = calibration_pattern(viewport.w as u32, viewport.h as u32);
+            let (w, h) = pattern.dimensions();
+            let texture: Texture<R, ColorFormat> = load_rgba8(f, pattern, sampler.clone())?;
+            let source = Rect { x: 0, y: 0, w: w as u16, h: h as u16 };
+            self.blit(ctx, &texture, source, &target, viewport);
+        }
+        Ok(())
+    }
+}
+
+/// The middle of a target `target` pixels in size, with pixels `pixel_pitch` wide and
+/// `row_pitch` tall, that shows an image `eye` pixels in size (with square pixels) at its
+/// own aspect ratio
+fn fit_viewport(eye: (u16, u16), target: (u16, u16), pixel_pitch: f32, row_pitch: f32) -> Rect {
+    let aspect = eye.0.max(1) as f32 / eye.1.max(1) as f32;
+    let (tw, th) = (target.0 as f32, target.1 as f32);
+    let (width, height) = (tw * pixel_pitch, th * row_pitch);
+    let (w, h) = if width > height * aspect {
+        ((height * aspect / pixel_pitch).round().min(tw), th)
+    } else {
+        (tw, (width / aspect / row_pitch).round().min(th))
+    };
+    Rect {
+        x: ((tw - w) / 2.) as u16,
+        y: ((th - h) / 2.) as u16,
+        w: w as u16,
+        h: h as u16,
+    }
+}
+
+/// Shows the two eyes on an autostereoscopic display, whose lenticular lens sheet sends
+/// alternate rows of pixels to each eye, by interleaving them: even rows (counted from the
+/// bottom of the target) come from the left eye and odd rows from the right. Each eye keeps
+/// its shape on the display, fitted in the middle of the target between black bars, for
+/// which the compositor needs to know how wide the pixels of the display are next to how
+/// tall its rows are.
+pub struct LenticularCompositor<R: Resources> {
+    unorm: PipelineState<R, lenticular_unorm::Meta>,
+    srgb: PipelineState<R, lenticular_srgb::Meta>,
+    params: Buffer<R, LenticularBlock>,
+    pixel_pitch: f32,
+    row_pitch: f32,
+}
+
+impl<R: Resources> LenticularCompositor<R> {
+    /// Build the pipelines, for a display with square pixels
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<LenticularCompositor<R>, Error> {
+        let shaders = lenticular_shader(f)?;
+        Ok(LenticularCompositor {
+            unorm: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), lenticular_unorm::new())?,
+            srgb: f.create_pipeline_state(&shaders, Primitive::TriangleList, Rasterizer::new_fill(), lenticular_srgb::new())?,
+            params: f.create_constant_buffer(1),
+            pixel_pitch: 0.1,
+            row_pitch: 0.1,
+        })
+    }
+
+    /// Set the horizontal distance between pixels of the display, in millimeters
+    pub fn set_pixel_pitch(&mut self, mm: f32) {
+        self.pixel_pitch = mm.max(1e-6);
+    }
+
+    /// Set the vertical distance between rows of the display, in millimeters
+    pub fn set_row_pitch(&mut self, mm: f32) {
+        self.row_pitch = mm.max(1e-6);
+    }
+
+    /// The rectangle of a target `target` pixels in size that eye images `eye` pixels in size
+    /// are fitted to
+    pub fn viewport(&self, eye: (u16, u16), target: (u16, u16)) -> Rect {
+        fit_viewport(eye, target, self.pixel_pitch, self.row_pitch)
+    }
+
+    /// Interleave the color of the `left` and `right` eye targets onto the whole of `target`
+    pub fn composite<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        left: &OffscreenTarget<R>,
+        right: &OffscreenTarget<R>,
+        target: &PresentTarget<R>,
+    ) {
+        profile_scope!("lenticular");
+        let (decode, encode) = SurfaceEncoding::frame().conversion(target.encoding());
+        let v = self.viewport((left.width, left.height), target.dimensions());
+        ctx.encoder.update_constant_buffer(&self.params, &LenticularBlock {
+            viewport: [v.x as f32, v.y as f32, v.w as f32, v.h as f32],
+            decode: decode as i32,
+            encode: encode as i32,
+        });
+        let slice = fullscreen_slice();
+        match *target {
+            PresentTarget::Unorm(ref color) => ctx.encoder.draw(&slice, &self.unorm, &lenticular_unorm::Data {
+                params: self.params.clone(),
+                color: color.clone(),
+                left: left.texture.clone().into_tuple(),
+                right: right.texture.clone().into_tuple(),
+            }),
+            PresentTarget::Srgb(ref color) => ctx.encoder.draw(&slice, &self.srgb, &lenticular_srgb::Data {
+                params: self.params.clone(),
+                color: color.clone(),
+                left: left.texture.clone().into_tuple(),
+                right: right.texture.clone().into_tuple(),
+            }),
+        }
+        ctx.draw_calls += 1;
+    }
+}
+
+#[test]
+fn present_encoding() {
+    use self::SurfaceEncoding::*;
+    assert_eq!(SurfaceEncoding::of::<(R8_G8_B8_A8, Unorm)>(), Display);
+    assert_eq!(SurfaceEncoding::of::<ColorFormatSrgb>(), Linear);
+    assert_eq!(Display.conversion(Display), (false, false));
+    assert_eq!(Linear.conversion(Linear), (false, false));
+    assert_eq!(Display.conversion(Linear), (true, false));
+    assert_eq!(Linear.conversion(Display), (false, true));
+    if cfg!(feature = "srgb-framebuffer") {
+        assert_eq!(SurfaceEncoding::frame(), Linear);
+    } else {
+        assert_eq!(SurfaceEncoding::frame(), Display);
+    }
+
+    let pattern = calibration_pattern(64, 32);
+    let value = |x, y| pattern.get_pixel(x, y).data;
+    // gray steps from black to white
+    assert_eq!(value(0, 0), [0, 0, 0, 255]);
+    assert_eq!(value(63, 0), [255, 255, 255, 255]);
+    assert_eq!(value(4, 0), [17, 17, 17, 255]);
+    for x in 1..64 {
+        assert!(value(x, 10)[0] >= value(x - 1, 10)[0]);
+    }
+    // one channel for each color ramp
+    assert_eq!(value(63, 16), [255, 0, 0, 255]);
+    assert_eq!(value(63, 19), [0, 255, 0, 255]);
+    assert_eq!(value(63, 22), [0, 0, 255, 255]);
+    // the lines average to the solid gray in linear light
+    let linear = |v: u8| {
+        let c = v as f32 / 255.;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let lines = (linear(value(0, 24)[0]) + linear(value(0, 25)[0])) * 0.5;
+    assert_ne!(value(0, 24), value(0, 25));
+    assert!((lines - linear(value(63, 24)[0])).abs() < 0.01);
+}
+
+#[test]
+fn lenticular_viewport() {
+    // square pixels: a square eye gets a square in the middle of a wide target
+    assert_eq!(fit_viewport((100, 100), (400, 200), 0.1, 0.1), Rect { x: 100, y: 0, w: 200, h: 200 });
+    // rows half as tall as pixels are wide take twice as many rows
+    assert_eq!(fit_viewport((100, 100), (400, 200), 0.2, 0.1), Rect { x: 150, y: 0, w: 100, h: 200 });
+    // a wide eye on a tall target gets bars above and below
+    assert_eq!(fit_viewport((200, 100), (100, 200), 0.1, 0.1), Rect { x: 0, y: 75, w: 100, h: 50 });
+    assert_eq!(fit_viewport((0, 0), (64, 64), 0.1, 0.1), Rect { x: 0, y: 0, w: 64, h: 64 });
+}
@@ -0,0 +1,426 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect, Slice, IndexBuffer};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, RenderTargetView};
+use gfx::memory::{Bind, Usage};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+use nalgebra::{Point3, Vector3, Matrix4, Isometry3, Perspective3, Transform3, Rotation3};
+use std::f32::consts::FRAC_PI_2;
+
+use super::{DrawParams, EyeParams, UberEnv, double_x};
+use super::uber::LumMapFormat;
+use ::environment::{ProbeBlendZone, ProbeBox};
+use ::mesh::Primitive;
+use ::{Error, ColorFormat, DepthFormat, Texture, NativeRepr};
+
+/// The pixel format of prefiltered probe maps, the format `UberEnv` samples its maps in
+/// so that a probe can light the scene directly. It keeps values above 1.
+pub type ProbeFormat = LumMapFormat;
+
+/// How carefully probes prefilter their captures into image based lighting. Few samples
+/// are enough to judge lighting while iterating, but leave visible noise and banding in
+/// rough reflections.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IblBakeConfig {
+    /// Samples of the capture per pixel of the irradiance map
+    pub irradiance_samples: u32,
+    /// Samples of the capture per pixel of each radiance map level
+    pub radiance_samples_per_level: u32,
+    /// The size of each face of the irradiance map, which holds no sharp detail
+    pub irradiance_resolution: u16,
+    /// The size of each captured face and of the sharpest radiance map level
+    pub radiance_resolution: u16,
+}
+
+impl IblBakeConfig {
+    /// Quick to bake, for debugging and editing lighting
+    pub fn preview() -> IblBakeConfig {
+        IblBakeConfig {
+            irradiance_samples: 128,
+            radiance_samples_per_level: 128,
+            irradiance_resolution: 16,
+            radiance_resolution: 64,
+        }
+    }
+
+    /// Smooth enough to ship, for probes that are captured once while loading
+    pub fn production() -> IblBakeConfig {
+        IblBakeConfig {
+            irradiance_samples: 4096,
+            radiance_samples_per_level: 4096,
+            irradiance_resolution: 32,
+            radiance_resolution: 256,
+        }
+    }
+}
+
+impl Default for IblBakeConfig {
+    /// Cheap enough to refresh probes while the scene runs
+    fn default() -> IblBakeConfig {
+        IblBakeConfig {
+            irradiance_samples: 64,
+            radiance_samples_per_level: 64,
+            irradiance_resolution: 16,
+            radiance_resolution: 128,
+        }
+    }
+}
+
+gfx_defines!{
+    constant FilterBlock {
+        forward: [f32; 4] = "face_forward",
+        right: [f32; 4] = "face_right",
+        up: [f32; 4] = "face_up",
+        size: f32 = "size",
+        roughness: f32 = "roughness",
+        source_gamma: f32 = "source_gamma",
+        samples: i32 = "sample_count",
+    }
+
+    pipeline filter {
+        params: gfx::ConstantBuffer<FilterBlock> = "filter_params",
+        color: gfx::RenderTarget<ProbeFormat> = "f_color",
+        source: gfx::TextureSampler<[f32; 4]> = "source_map",
+    }
+}
+
+shader!(radiance_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/probe_filter.f.glsl")
+});
+
+shader!(irradiance_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/probe_filter.f.glsl")
+        .define("IRRADIANCE")
+});
+
+/// The direction each cube face looks in and its up vector, in the order of
+/// `load::CUBE_SIDE_ORDER` and following the OpenGL cube map layout
+pub(super) fn cube_face(face: usize) -> (Vector3<f32>, Vector3<f32>) {
+    match face {
+        0 => (Vector3::x(), -Vector3::y()),
+        1 => (-Vector3::x(), -Vector3::y()),
+        2 => (Vector3::y(), Vector3::z()),
+        3 => (-Vector3::y(), -Vector3::z()),
+        4 => (Vector3::z(), -Vector3::y()),
+        _ => (-Vector3::z(), -Vector3::y()),
+    }
+}
+
+/// The camera axes of a cube face: forward, right and up
+fn face_basis(face: usize) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (forward, up) = cube_face(face);
+    let right = forward.cross(&up);
+    (forward, right, right.cross(&forward))
+}
+
+/// The view matrix looking out of a cube face from the given position
+pub(super) fn face_view(pos: &Point3<f32>, face: usize) -> Matrix4<f32> {
+    let (forward, up) = cube_face(face);
+    Isometry3::look_at_rh(pos, &(pos + forward), &up).to_homogeneous()
+}
+
+/// Blurs captured cube maps into the irradiance and radiance maps used for image based
+/// lighting, the same kind of maps `load::load_hdr_cubemap` loads from prefiltered files
+pub struct ProbeFilter<R: Resources> {
+    radiance: PipelineState<R, filter::Meta>,
+    irradiance: PipelineState<R, filter::Meta>,
+    params: Buffer<R, FilterBlock>,
+    sampler: gfx::handle::Sampler<R>,
+}
+
+impl<R: Resources> ProbeFilter<R> {
+    /// Build the filtering pipelines
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F) -> Result<ProbeFilter<R>, Error> {
+        let radiance = radiance_shader(f)?;
+        let irradiance = irradiance_shader(f)?;
+        let r = Rasterizer::new_fill();
+        Ok(ProbeFilter {
+            radiance: f.create_pipeline_state(&radiance, Primitive::TriangleList, r, filter::new())?,
+            irradiance: f.create_pipeline_state(&irradiance, Primitive::TriangleList, r, filter::new())?,
+            params: f.create_constant_buffer(1),
+            sampler: f.create_sampler_linear(),
+        })
+    }
+
+    fn apply<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        pso: &PipelineState<R, filter::Meta>,
+        source: &gfx::handle::ShaderResourceView<R, [f32; 4]>,
+        target: RenderTargetView<R, ProbeFormat>,
+        face: usize,
+        size: u16,
+        roughness: f32,
+        samples: u32,
+    ) {
+        profile_scope!("probe_filter");
+        let (forward, right, up) = face_basis(face);
+        ctx.encoder.update_constant_buffer(&self.params, &FilterBlock {
+            forward: forward.to_homogeneous().downgrade(),
+            right: right.to_homogeneous().downgrade(),
+            up: up.to_homogeneous().downgrade(),
+            size: size as f32,
+            roughness: roughness,
+            source_gamma: ::OUTPUT_GAMMA,
+            samples: samples.max(1) as i32,
+        });
+        let slice = Slice {
+            start: 0,
+            end: 3,
+            base_vertex: 0,
+            instances: None,
+            buffer: IndexBuffer::Auto,
+        };
+        ctx.encoder.draw(&slice, pso, &filter::Data {
+            params: self.params.clone(),
+            color: target,
+            source: (source.clone(), self.sampler.clone()),
+        });
+        ctx.draw_calls += 1;
+    }
+}
+
+/// When a reflection probe captures the scene
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProbeRefresh {
+    /// Capture the first time only, for static surroundings
+    Once,
+    /// Capture whenever `ReflectionProbe::request_refresh` is called
+    OnDemand,
+}
+
+/// A cube map of the scene captured from a fixed position, prefiltered into an environment
+/// for the uber style. Probes are lit through a `ProbeManager` (see `ReflectionProbe::zone`),
+/// which picks the nearest two for each draw and fades them into the global environment at
+/// the edges of their zones.
+pub struct ReflectionProbe<R: Resources> {
+    /// Where the scene is captured from
+    pub position: Point3<f32>,
+    /// When the scene is captured
+    pub refresh: ProbeRefresh,
+    /// The near and far clip planes used when capturing
+    pub clip: (f32, f32),
+    resolution: u16,
+    levels: u8,
+    config: IblBakeConfig,
+    dirty: bool,
+    faces: Vec<RenderTargetView<R, ColorFormat>>,
+    face_depth: gfx::handle::DepthStencilView<R, DepthFormat>,
+    capture: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    radiance_targets: Vec<RenderTargetView<R, ProbeFormat>>,
+    irradiance_targets: Vec<RenderTargetView<R, ProbeFormat>>,
+    env: UberEnv<R>,
+}
+
+impl<R: Resources> ReflectionProbe<R> {
+    /// Create a probe capturing faces `resolution` pixels across. The radiance map gets
+    /// mip levels down to 4 pixels for increasingly rough reflections.
+    pub fn new<F: Factory<R>>(f: &mut F, position: Point3<f32>, resolution: u16) -> Result<ReflectionProbe<R>, Error> {
+        let config = IblBakeConfig { radiance_resolution: resolution, .. Default::default() };
+        ReflectionProbe::with_config(f, position, &config)
+    }
+
+    /// Create a probe with the resolutions and sample counts of `config`
+    pub fn with_config<F: Factory<R>>(f: &mut F, position: Point3<f32>, config: &IblBakeConfig)
+        -> Result<ReflectionProbe<R>, Error>
+    {
+        use gfx::texture::*;
+        let resolution = config.radiance_resolution.max(8);
+        let irradiance_size = config.irradiance_resolution.max(1);
+        let levels = (resolution as f32).log2() as u8 - 1;
+
+        let capture = f.create_texture::<<ColorFormat as Formatted>::Surface>(
+            Kind::Cube(resolution),
+            1,
+            Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+            Usage::Data,
+            Some(<<ColorFormat as Formatted>::Channel as ChannelTyped>::get_channel_type()),
+        )?;
+        let faces = (0..6)
+            .map(|i| f.view_texture_as_render_target::<ColorFormat>(&capture, 0, Some(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let radiance = f.create_texture::<<ProbeFormat as Formatted>::Surface>(
+            Kind::Cube(resolution),
+            levels,
+            Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+            Usage::Data,
+            Some(ChannelType::Float),
+        )?;
+        let mut radiance_targets = Vec::with_capacity(levels as usize * 6);
+        for level in 0..levels {
+            for i in 0..6 {
+                radiance_targets.push(f.view_texture_as_render_target::<ProbeFormat>(&radiance, level, Some(i))?);
+            }
+        }
+        let irradiance = f.create_texture::<<ProbeFormat as Formatted>::Surface>(
+            Kind::Cube(irradiance_size),
+            1,
+            Bind::RENDER_TARGET | Bind::SHADER_RESOURCE,
+            Usage::Data,
+            Some(ChannelType::Float),
+        )?;
+        let irradiance_targets = (0..6)
+            .map(|i| f.view_texture_as_render_target::<ProbeFormat>(&irradiance, 0, Some(i)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Clamp));
+        Ok(ReflectionProbe {
+            position: position,
+            refresh: ProbeRefresh::Once,
+            clip: (0.05, 100.),
+            resolution: resolution,
+            levels: levels,
+            config: IblBakeConfig {
+                radiance_resolution: resolution,
+                irradiance_resolution: irradiance_size,
+                .. *config
+            },
+            dirty: true,
+            faces: faces,
+            face_depth: f.create_depth_stencil_view_only::<DepthFormat>(resolution, resolution)?,
+            capture: f.view_texture_as_shader_resource::<ColorFormat>(&capture, (0, 0), Swizzle::new())?,
+            radiance_targets: radiance_targets,
+            irradiance_targets: irradiance_targets,
+            env: UberEnv {
+                irradiance: Texture {
+                    buffer: f.view_texture_as_shader_resource::<ProbeFormat>(&irradiance, (0, 0), Swizzle::new())?,
+                    sampler: sampler.clone(),
+                },
+                radiance: Texture {
+                    buffer: f.view_texture_as_shader_resource::<ProbeFormat>(&radiance, (0, levels - 1), Swizzle::new())?,
+                    sampler: sampler,
+                },
+                sun_included: false,
+                sun_color: [0.; 4],
+                sun_rotation: Rotation3::identity(),
+                radiance_levels: levels,
+            },
+        })
+    }
+
+    /// The size of each captured face in pixels
+    pub fn resolution(&self) -> u16 {
+        self.resolution
+    }
+
+    /// The resolutions and sample counts of the probe
+    pub fn config(&self) -> IblBakeConfig {
+        self.config
+    }
+
+    /// Prefilter later captures with other sample counts, such as `production` ones for a
+    /// final capture after previewing. The resolutions are fixed when the probe is created.
+    pub fn set_samples(&mut self, irradiance_samples: u32, radiance_samples_per_level: u32) {
+        self.config.irradiance_samples = irradiance_samples;
+        self.config.radiance_samples_per_level = radiance_samples_per_level;
+    }
+
+    /// Capture the scene again on the next call to `capture`, if the probe refreshes
+    /// on demand
+    pub fn request_refresh(&mut self) {
+        if self.refresh == ProbeRefresh::OnDemand {
+            self.dirty = true;
+        }
+    }
+
+    /// True if the next call to `capture` will draw the scene
+    pub fn needs_capture(&self) -> bool {
+        self.dirty
+    }
+
+    /// Draw the scene six ways from the probe position with `draw_scene`, then prefilter it
+    /// into the probe environment. Does nothing unless `needs_capture` is true, returning
+    /// whether the scene was drawn. The scene is drawn through the draw parameters as
+    /// usual, redirected to the faces of the probe and restored afterwards.
+    pub fn capture<C, D>(&mut self, ctx: &mut DrawParams<R, C>, filter: &ProbeFilter<R>, mut draw_scene: D)
+        -> Result<bool, Error>
+        where C: CommandBuffer<R>, D: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        if !self.dirty {
+            return Ok(false);
+        }
+
+        let saved = (ctx.color.clone(), ctx.depth.clone());
+        let mut result = Ok(());
+        for face in 0..6 {
+            ctx.encoder.clear(&self.faces[face], [0., 0., 0., 1.]);
+            ctx.encoder.clear_depth(&self.face_depth, super::DEPTH_CONVENTION.far());
+            ctx.color = self.faces[face].clone();
+            ctx.depth = self.face_depth.clone();
+            ctx.push_eye(self.face_eye(face));
+            result = draw_scene(ctx);
+            ctx.pop_camera();
+            if result.is_err() { break }
+        }
+        ctx.color = saved.0;
+        ctx.depth = saved.1;
+        result?;
+
+        for level in 0..self.levels {
+            let size = self.resolution >> level;
+            let roughness = level as f32 / (self.levels - 1).max(1) as f32;
+            for face in 0..6 {
+                let target = self.radiance_targets[level as usize * 6 + face].clone();
+                filter.apply(ctx, &filter.radiance, &self.capture, target, face, size, roughness,
+                    self.config.radiance_samples_per_level);
+            }
+        }
+        for face in 0..6 {
+            let target = self.irradiance_targets[face].clone();
+            filter.apply(ctx, &filter.irradiance, &self.capture, target, face,
+                self.config.irradiance_resolution, 1., self.config.irradiance_samples);
+        }
+        self.dirty = false;
+        Ok(true)
+    }
+
+    /// An eye looking out of a cube face, covering the whole face
+    fn face_eye(&self, face: usize) -> EyeParams {
+        let proj = double_x(Perspective3::new(1., FRAC_PI_2, self.clip.0, self.clip.1).to_homogeneous());
+        EyeParams {
+            eye: self.position,
+            view: Transform3::from_matrix_unchecked(face_view(&self.position, face)),
+            proj: Transform3::from_matrix_unchecked(proj),
+            clip_offset: 0.,
+            clip: Rect { x: 0, y: 0, w: self.resolution, h: self.resolution },
+        }
+    }
+
+    /// The prefiltered environment, valid once the probe has been captured
+    pub fn env(&self) -> UberEnv<R> {
+        self.env.clone()
+    }
+
+    /// A zone for a `ProbeManager` lighting the area around the probe, with reflections
+    /// projected onto `parallax` if given
+    pub fn zone(&self, inner_radius: f32, outer_radius: f32, parallax: Option<ProbeBox>) -> ProbeBlendZone<R> {
+        ProbeBlendZone {
+            center: [self.position.x, self.position.y, self.position.z],
+            inner_radius: inner_radius,
+            outer_radius: outer_radius,
+            probe: self.env(),
+            parallax: parallax,
+        }
+    }
+}
+
+#[test]
+fn cube_faces() {
+    use nalgebra::Vector4;
+
+    let pos = Point3::new(1., 2., 3.);
+    for face in 0..6 {
+        let (forward, right, up) = face_basis(face);
+        // every face looks down its own axis
+        let ahead = face_view(&pos, face) * (pos + forward).to_homogeneous();
+        assert!(relative_eq!(ahead, Vector4::new(0., 0., -1., 1.), epsilon = 1e-5));
+        let side = face_view(&pos, face) * (pos + right).to_homogeneous();
+        assert!(relative_eq!(side, Vector4::new(1., 0., 0., 1.), epsilon = 1e-5));
+        assert!(relative_eq!(right.cross(&up), -forward, epsilon = 1e-5));
+    }
+    assert!(relative_eq!(face_basis(0).1, -Vector3::z()));
+}
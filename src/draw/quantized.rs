@@ -0,0 +1,160 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::state::Rasterizer;
+use gfx::format::*;
+
+use super::{StyleInputs, Style, TransformBlock};
+use ::mesh::{Primitive, VertQuantized};
+use ::mesh::quantize::QuantizationParams;
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The textures of a quantized mesh, along with the parameters it was quantized with
+#[derive(Clone, PartialEq)]
+pub struct QuantizedMaterial<R: Resources> {
+    /// Normal map
+    pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    /// Albedo map (base color)
+    pub albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    /// How the mesh positions map back to model space
+    pub quant: QuantizationParams,
+}
+
+gfx_defines!{
+    constant QuantParamsBlock {
+        scale: [f32; 4] = "scale",
+        bias: [f32; 4] = "bias",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertQuantized> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        quant: gfx::ConstantBuffer<QuantParamsBlock> = "quant",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+    }
+}
+
+impl From<QuantizationParams> for QuantParamsBlock {
+    fn from(q: QuantizationParams) -> QuantParamsBlock {
+        QuantParamsBlock {
+            scale: [q.scale[0], q.scale[1], q.scale[2], 0.],
+            bias: [q.bias[0], q.bias[1], q.bias[2], 0.],
+        }
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("QUANTIZED")
+        .define("NORM")
+        .define("TEX")
+        .define("TAN"),
+    fragment: static_file!("shaders/quantized.f.glsl")
+        .define_to("I_POS", "v_pos")
+        .define_to("I_NORM", "v_norm")
+        .define_to("I_TEX", "v_tex")
+        .define_to("I_TAN", "v_tan")
+        .define_to("I_BITAN", "v_bitan")
+});
+
+/// The configuration for quantized mesh rendering
+pub struct QuantizedInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    quant_block: Buffer<R, QuantParamsBlock>,
+}
+
+impl<R: Resources> StyleInputs<R> for QuantizedInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Pipeline data bound by `QuantizedStyle`, along with the mesh's quantization
+pub struct QuantizedBound<R: Resources> {
+    data: pl::Data<R>,
+    quant: QuantParamsBlock,
+}
+
+/// Draws meshes compressed with `mesh::quantize`, with a normal map and simple
+/// lighting from above
+pub struct QuantizedStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for QuantizedStyle<R> {
+    type Vertex = VertQuantized;
+    type Inputs = QuantizedInputs<R>;
+    type Material = QuantizedMaterial<R>;
+    type Bound = QuantizedBound<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut QuantizedInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(QuantizedStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<QuantizedInputs<R>, Error> {
+        Ok(QuantizedInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            quant_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &QuantizedInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &QuantizedMaterial<R>,
+    ) -> QuantizedBound<R> {
+        QuantizedBound {
+            data: pl::Data {
+                color: color,
+                depth: depth,
+                verts: buf,
+                scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+                transform: inputs.transform_block.clone(),
+                quant: inputs.quant_block.clone(),
+                normal: mat.normal.clone().into_tuple(),
+                albedo: mat.albedo.clone().into_tuple(),
+            },
+            quant: mat.quant.into(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut QuantizedInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut QuantizedBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        enc.update_constant_buffer(&inputs.quant_block, &bound.quant);
+        bound.data.scissor = scissor;
+        enc.draw(slice, &self.pso, &bound.data);
+        Ok(())
+    }
+}
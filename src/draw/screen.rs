@@ -0,0 +1,115 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+
+use super::{StyleInputs, Style, TransformBlock, UnlitMaterial};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef};
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        texture: gfx::TextureSampler<[f32; 4]> = "color_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl"),
+    fragment: static_file!("shaders/unlit.f.glsl")
+        .define("SCREEN_TEX")
+});
+
+/// The configuration for screen space texturing
+pub struct ScreenInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+}
+
+impl<R: Resources> StyleInputs<R> for ScreenInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Draws meshes showing whatever pixel of the material texture lies under each fragment,
+/// as if the texture were a window onto another target of the same size. Used to show
+/// views rendered offscreen, such as the scene through a portal.
+pub struct ScreenStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for ScreenStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = ScreenInputs<R>;
+    type Material = UnlitMaterial<R>;
+    type Bound = pl::Data<R>;
+
+    fn sampled(mat: &UnlitMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        vec![mat.color.buffer.raw().clone()]
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut ScreenInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(ScreenStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<ScreenInputs<R>, Error> {
+        Ok(ScreenInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &ScreenInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &UnlitMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            texture: mat.color.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut ScreenInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::state::Rasterizer;
+use gfx::format::*;
+
+use super::{StyleInputs, Style, TransformBlock};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The font atlas and color of a text mesh
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SdfFontMaterial<R: Resources> {
+    /// Distance field atlas, with the distance in the red channel (or the
+    /// red, green and blue channels for multi-channel fields)
+    pub atlas: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    /// The atlas is a multi-channel distance field
+    pub msdf: bool,
+    /// Text color and opacity
+    pub color: [u8; 4],
+}
+
+gfx_defines!{
+    constant SdfBlock {
+        color: [f32; 4] = "text_color",
+        edge_width: f32 = "edge_width",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<SdfBlock> = "params",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+
+        atlas: gfx::TextureSampler<[f32; 4]> = "font_tex",
+    }
+}
+
+fn shader<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F, msdf: bool)
+    -> Result<gfx::ShaderSet<R>, Error>
+{
+    let mut fragment = static_file!("shaders/sdf.f.glsl")
+        .define_to("I_TEX", "v_tex");
+    if msdf {
+        fragment = fragment.define("MSDF");
+    }
+    Ok(shader_set!(factory,
+        vertex: static_file!("shaders/transform.v.glsl")
+            .define("NORM")
+            .define("TEX")
+            .define("TAN"),
+        fragment: fragment,
+    ))
+}
+
+/// The configuration for distance field text rendering
+pub struct SdfInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    msdf_shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    edge_width: f32,
+    params_block: Buffer<R, SdfBlock>,
+}
+
+impl<R: Resources> SdfInputs<R> {
+    /// Set the minimum half width of the anti-aliased edge, in distance field units
+    /// (where 0.5 is the glyph outline). Larger values give softer text.
+    pub fn set_edge_width(&mut self, width: f32) {
+        self.edge_width = width;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for SdfInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Pipeline data bound by `SdfStyle`
+pub struct SdfBound<R: Resources> {
+    data: pl::Data<R>,
+    msdf: bool,
+    color: [f32; 4],
+}
+
+/// Draws text meshes (see `text::TextMesh`) from signed distance field font atlases,
+/// keeping glyph edges crisp at any size
+pub struct SdfStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+    msdf_pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for SdfStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = SdfInputs<R>;
+    type Material = SdfFontMaterial<R>;
+    type Bound = SdfBound<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut SdfInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(SdfStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            msdf_pso: f.create_pipeline_state(&i.msdf_shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<SdfInputs<R>, Error> {
+        Ok(SdfInputs {
+            shaders: shader(f, false)?,
+            msdf_shaders: shader(f, true)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            edge_width: 0.05,
+            params_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &SdfInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &SdfFontMaterial<R>,
+    ) -> SdfBound<R> {
+        let c = mat.color;
+        SdfBound {
+            data: pl::Data {
+                color: color,
+                depth: depth,
+                verts: buf,
+                scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+                transform: inputs.transform_block.clone(),
+                params: inputs.params_block.clone(),
+                atlas: mat.atlas.clone().into_tuple(),
+            },
+            msdf: mat.msdf,
+            color: [c[0] as f32 / 255., c[1] as f32 / 255., c[2] as f32 / 255., c[3] as f32 / 255.],
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut SdfInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut SdfBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        enc.update_constant_buffer(&inputs.params_block, &SdfBlock {
+            color: bound.color,
+            edge_width: inputs.edge_width,
+        });
+        bound.data.scissor = scissor;
+        let pso = if bound.msdf { &self.msdf_pso } else { &self.pso };
+        enc.draw(slice, pso, &bound.data);
+        Ok(())
+    }
+}
@@ -11,7 +11,7 @@ macro_rules! shader {
 
 macro_rules! single_shader {
     ($f:ident, $c:ident, $s:expr) => ({
-        $f.$c(&$s.build().into_bytes())?
+        $f.$c(&$s.build()?.into_bytes())?
     })
 }
 
@@ -48,6 +48,40 @@ macro_rules! shader_set {
     });
 }
 
+use ::Error;
+use ::load::ShaderPreprocessor;
+
+/// True for fragments skipped by `LensShading`, given the lens vector of the transform block
+/// and the fragment coordinate. Pixels of one checkerboard color are skipped once the
+/// ramp from the full rate circle passes their 2x2 ordered dither threshold.
+const LENS_SKIPPED: &str = "#define LENS_SKIPPED(lens, p) (lens.z > 0.0 \\
+    && ((int(p.x) + int(p.y)) & 1) == 1 \\
+    && clamp((length(p - lens.xy) - lens.z) / lens.w, 0.0, 1.0) \\
+        > fract(dot(floor(p * 0.5), vec2(0.5, 0.25)) + 0.125))
+";
+
+/// The depth of a clip position with `LogDepth`, and the view distance of a depth buffer
+/// value, given the `log_depth` value of the transform block
+const LOG_DEPTH: &str = "#define LOG_DEPTH_Z(c, k) (DEPTH_SIGN * (log2(max(1e-6, 1.0 + LOG_DEPTH_C * c.w)) * 2.0 / k - 1.0) * c.w)
+#define LOG_DEPTH_VIEW(d, k) ((exp2(STANDARD_DEPTH(d) * k) - 1.0) / LOG_DEPTH_C)
+";
+
+/// The sign of clip space depth at the far plane for `DEPTH_CONVENTION`, and a depth buffer
+/// value in the standard convention
+fn depth_convention() -> &'static str {
+    match super::DEPTH_CONVENTION {
+        super::DepthConvention::Standard => "#define DEPTH_SIGN 1.0
+#define STANDARD_DEPTH(d) (d)
+",
+        super::DepthConvention::ReverseZ => "#define DEPTH_SIGN -1.0
+#define STANDARD_DEPTH(d) (1.0 - (d))
+",
+    }
+}
+
+/// Where files included by the built-in shaders are read from
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/draw/shaders");
+
 pub struct BuildShader {
     prefix: String,
     source: String,
@@ -56,7 +90,8 @@ pub struct BuildShader {
 
 pub fn source(name: &str, source: &str) -> BuildShader {
     BuildShader {
-        prefix: String::new(),
+        prefix: format!("#define OUTPUT_GAMMA {:.1}\n#define LOG_DEPTH_C {:?}\n{}{}{}",
+            ::OUTPUT_GAMMA, super::LOG_DEPTH_C, depth_convention(), LENS_SKIPPED, LOG_DEPTH),
         source: source.to_owned(),
         name: name.to_owned(),
     }
@@ -75,12 +110,21 @@ impl BuildShader {
         self
     }
 
-    pub fn build(self) -> String {
-        if self.source.starts_with("#version") {
-            let (ver, src) = self.source.split_at(self.source.find('\n').unwrap_or(self.source.len()));
-            format!("{}\n{}#line 1\n{}", ver, self.prefix, src)
-        } else {
-            format!("{}#line 1\n{}", self.prefix, self.source)
-        }
+    /// The final source, with `#include` directives resolved from the shader directory and
+    /// the defines inserted
+    pub fn build(self) -> Result<String, Error> {
+        let source = ShaderPreprocessor::new(SHADER_DIR).process(&self.name, &self.source)?;
+        Ok(insert_prefix(&source, &self.prefix))
+    }
+}
+
+/// Insert lines at the start of GLSL source, after the `#version` directive if there is
+/// one, keeping the line numbers of the source in compile errors
+pub fn insert_prefix(source: &str, prefix: &str) -> String {
+    if source.starts_with("#version") {
+        let (ver, src) = source.split_at(source.find('\n').unwrap_or(source.len()));
+        format!("{}\n{}#line 1\n{}", ver, prefix, src)
+    } else {
+        format!("{}#line 1\n{}", prefix, source)
     }
 }
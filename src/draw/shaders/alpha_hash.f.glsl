@@ -0,0 +1,35 @@
+#version 410
+
+uniform sampler2D color_tex;
+
+layout(std140) uniform alpha_hash {
+    float alpha;
+    int frame;
+};
+
+in vec2 I_TEX;
+flat in vec4 v_lens;
+out vec4 f_color;
+
+// a threshold from 0 to 1 that looks random across pixels and frames
+float hash(ivec2 p) {
+    uint h = uint(p.x) * 0x8da6b343u ^ uint(p.y) * 0xd8163841u ^ uint(frame) * 0xcb1ab31fu;
+    h ^= h >> 16;
+    h *= 0x7feb352du;
+    h ^= h >> 15;
+    h *= 0x846ca68bu;
+    h ^= h >> 16;
+    return float(h >> 8) / 16777216.0;
+}
+
+void main() {
+    if (LENS_SKIPPED(v_lens, gl_FragCoord.xy)) {
+        discard;
+    }
+    vec4 c = texture(color_tex, I_TEX);
+    // keep the fraction of fragments given by the opacity, the rest show what's behind
+    if (c.a * alpha <= hash(ivec2(gl_FragCoord.xy))) {
+        discard;
+    }
+    f_color = vec4(c.rgb, 1.0);
+}
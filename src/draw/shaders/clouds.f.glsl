@@ -0,0 +1,108 @@
+#version 410
+
+#define STEPS 48
+#define LIGHT_STEPS 4
+
+const float PI = 3.14159265359;
+
+uniform sampler3D noise_tex;
+
+layout(std140) uniform clouds {
+    mat4 inv_view_proj;
+    vec4 eye_pos;
+    vec4 viewport; // x, y, width, height in pixels
+    vec4 sun_dir; // towards the sun
+    vec4 sun_color;
+    vec4 ambient;
+    vec4 layer; // bottom, top, coverage, density
+    vec4 wind; // offset of the shapes, 1 / noise scale
+    vec4 detail_wind; // offset of the detail, eccentricity
+    float exposure;
+    float gamma;
+};
+
+out vec4 f_color;
+
+float henyey_greenstein(float cos_theta, float g) {
+    float g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// cloud density at a world position
+float density_at(vec3 p) {
+    float h = (p.y - layer.x) / (layer.y - layer.x);
+    if (h < 0.0 || h > 1.0) {
+        return 0.0;
+    }
+    float shape = texture(noise_tex, (p + wind.xyz) * wind.w).r;
+    // rounded bottoms and thinning tops
+    shape *= smoothstep(0.0, 0.1, h) * smoothstep(1.0, 0.5, h);
+    float coverage = clamp(layer.z, 0.0, 1.0);
+    float d = clamp((shape - (1.0 - coverage)) / max(coverage, 1e-3), 0.0, 1.0);
+    // eat away at the edges with finer noise
+    float detail = texture(noise_tex, (p + detail_wind.xyz) * wind.w * 4.0).r;
+    d = max(d - (1.0 - detail) * 0.3 * (1.0 - d), 0.0);
+    return d * layer.w;
+}
+
+void main() {
+    vec2 uv = (gl_FragCoord.xy - viewport.xy) / viewport.zw;
+    vec4 far = inv_view_proj * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+    vec3 dir = normalize(far.xyz / far.w - eye_pos.xyz);
+
+    // where the view ray crosses the cloud layer
+    if (abs(dir.y) < 1e-4) {
+        f_color = vec4(0.0);
+        return;
+    }
+    float t0 = (layer.x - eye_pos.y) / dir.y;
+    float t1 = (layer.y - eye_pos.y) / dir.y;
+    if (t0 > t1) {
+        float t = t0; t0 = t1; t1 = t;
+    }
+    t0 = max(t0, 0.0);
+    // rays along the horizon would cross the layer for tens of kilometers
+    t1 = min(t1, t0 + 20.0 * (layer.y - layer.x));
+    if (t1 <= t0) {
+        f_color = vec4(0.0);
+        return;
+    }
+
+    float step_size = (t1 - t0) / STEPS;
+    float light_step = (layer.y - layer.x) / (2.0 * LIGHT_STEPS);
+    // offset each pixel's samples to turn banding into noise
+    float jitter = fract(sin(dot(gl_FragCoord.xy, vec2(12.9898, 78.233))) * 43758.5453);
+    float phase = henyey_greenstein(dot(dir, sun_dir.xyz), detail_wind.w) * 4.0 * PI;
+
+    float transmittance = 1.0;
+    vec3 light = vec3(0.0);
+    for (int i = 0; i < STEPS; i++) {
+        vec3 p = eye_pos.xyz + dir * (t0 + step_size * (float(i) + jitter));
+        float d = density_at(p);
+        if (d <= 0.0) {
+            continue;
+        }
+        // how much cloud lies between this point and the sun
+        float shadow = 0.0;
+        for (int j = 0; j < LIGHT_STEPS; j++) {
+            shadow += density_at(p + sun_dir.xyz * (float(j) + 0.5) * light_step);
+        }
+        vec3 lit = sun_color.rgb * exp(-shadow * light_step) * phase + ambient.rgb;
+        float a = exp(-d * step_size);
+        light += transmittance * (1.0 - a) * lit;
+        transmittance *= a;
+        if (transmittance < 0.01) {
+            break;
+        }
+    }
+
+    // tone map like the scene, then premultiply for compositing
+    float alpha = 1.0 - transmittance;
+    if (alpha <= 0.0) {
+        f_color = vec4(0.0);
+        return;
+    }
+    vec3 mapped = vec3(1.0) - exp(-light / alpha * exposure);
+    mapped = pow(mapped, vec3(1.0 / gamma));
+    f_color = vec4(mapped * alpha, alpha);
+}
@@ -0,0 +1,14 @@
+#version 410
+
+uniform sampler2D clouds_tex;
+
+layout(std140) uniform clouds_composite {
+    vec4 target_size;
+};
+
+out vec4 f_color;
+
+void main() {
+    // the clouds are at half resolution, so this fragment lands between their texels
+    f_color = texture(clouds_tex, gl_FragCoord.xy / target_size.xy);
+}
@@ -6,11 +6,25 @@ uniform samplerCube cube_map;
 layout(std140) uniform params {
     mat4 sun_matrix;
     vec4 sun_color;
+    vec4 probe_pos_a; // w = 1 if reflections are projected onto the probe box
+    vec4 probe_min_a;
+    vec4 probe_max_a;
+    vec4 probe_pos_b;
+    vec4 probe_min_b;
+    vec4 probe_max_b;
+    vec4 probe_levels; // radiance mip levels of both probes
+    vec4 sun_cookie; // 1 / extent, enabled, tiled, light outside the cookie
+    vec4 voxel_min; // grid corner, 1 / grid size
+    vec4 voxel_params; // enabled, diffuse strength, specular strength, mip levels
+    vec4 sky_occlusion_rect; // corner on the XZ plane, 1 / size
+    float sky_occlusion; // strength, 0 when there is no map
     float sun_in_env;
     int radiance_levels;
 
     float gamma;
     float exposure;
+    float probe_blend;
+    float shadow_depth_bias;
 };
 
 in vec3 I_POS;
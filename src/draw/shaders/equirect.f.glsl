@@ -0,0 +1,23 @@
+#version 410
+
+uniform samplerCube source_map;
+
+layout(std140) uniform equirect_params {
+    vec2 size; // of the target, in pixels
+    float source_gamma;
+};
+
+out vec4 f_color;
+
+const float PI = 3.14159265359;
+
+void main() {
+    // longitude from -pi on the left edge to pi on the right with -z in the middle,
+    // latitude from -pi/2 on the bottom row to pi/2 on the top
+    vec2 uv = gl_FragCoord.xy / size;
+    float lon = (uv.x - 0.5) * 2.0 * PI;
+    float lat = (uv.y - 0.5) * PI;
+    vec3 dir = vec3(sin(lon) * cos(lat), sin(lat), -cos(lon) * cos(lat));
+    // captured faces are display encoded
+    f_color = vec4(pow(texture(source_map, dir).rgb, vec3(source_gamma)), 1.0);
+}
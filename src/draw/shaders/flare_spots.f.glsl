@@ -0,0 +1,35 @@
+#version 410
+
+#define TAP_COUNT 8
+
+uniform sampler2D scene_tex;
+
+layout(std140) uniform flare_spots {
+    vec4 viewport; // the eye in the scene, x, y, width, height in pixels
+    vec4 spot_viewport; // the grid of this eye in the spot target
+    vec4 target_size; // of the scene
+    float threshold;
+};
+
+out vec4 f_color;
+
+// finds how much of a grid cell is above the threshold, and where
+void main() {
+    vec2 cell = floor(gl_FragCoord.xy - spot_viewport.xy);
+    vec2 cell_size = viewport.zw / spot_viewport.zw;
+    float total = 0.0;
+    vec2 center = vec2(0.0);
+    for (int j = 0; j < TAP_COUNT; j++) {
+        for (int i = 0; i < TAP_COUNT; i++) {
+            vec2 local = (vec2(i, j) + 0.5) / float(TAP_COUNT);
+            vec2 p = viewport.xy + (cell + local) * cell_size;
+            vec3 c = texture(scene_tex, p / target_size.xy).rgb;
+            float excess = max(dot(c, vec3(0.2126, 0.7152, 0.0722)) - threshold, 0.0);
+            total += excess;
+            center += local * excess;
+        }
+    }
+    float brightness = total / float(TAP_COUNT * TAP_COUNT) / max(1.0 - threshold, 1e-4);
+    // the bright center within the cell, and how bright it is
+    f_color = vec4(total > 0.0 ? center / total : vec2(0.5), brightness, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 410
+
+// a triangle covering the whole target, drawn without any vertex buffer
+void main() {
+    vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    #ifdef FAR_PLANE
+    // only passes a depth test where nothing has been drawn
+    gl_Position = vec4(p * 2.0 - 1.0, DEPTH_SIGN, 1.0);
+    #else
+    gl_Position = vec4(p * 2.0 - 1.0, 0.0, 1.0);
+    #endif
+}
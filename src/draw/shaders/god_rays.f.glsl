@@ -0,0 +1,43 @@
+#version 410
+
+#define SAMPLE_COUNT 64
+
+uniform sampler2D occlusion_tex;
+
+layout(std140) uniform god_rays {
+    vec4 sun_pos; // position in the eye viewport, visibility
+    vec4 sun_color;
+    vec4 viewport; // x, y, width, height in pixels
+    vec4 target_size;
+    float density;
+    float weight;
+    float decay;
+    float exposure;
+    float threshold;
+};
+
+out vec4 f_color;
+
+// light let through at a point of the eye viewport
+vec3 light(vec2 uv) {
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return vec3(0.0);
+    }
+    vec2 p = (viewport.xy + uv * viewport.zw) / target_size.xy;
+    vec3 c = texture(occlusion_tex, p).rgb;
+    return max(c - threshold, 0.0) / max(1.0 - threshold, 1e-4);
+}
+
+void main() {
+    vec2 uv = (gl_FragCoord.xy - viewport.xy) / viewport.zw;
+    // step from the pixel towards the sun, fading with each step
+    vec2 delta = (uv - sun_pos.xy) * density / float(SAMPLE_COUNT);
+    float illumination = 1.0;
+    vec3 sum = vec3(0.0);
+    for (int i = 0; i < SAMPLE_COUNT; i++) {
+        uv -= delta;
+        sum += light(uv) * illumination * weight;
+        illumination *= decay;
+    }
+    f_color = vec4(sum * exposure * sun_pos.z * sun_color.rgb, 1.0);
+}
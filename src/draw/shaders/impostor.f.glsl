@@ -0,0 +1,48 @@
+#version 410
+
+uniform sampler2D atlas_tex;
+uniform sampler2D normal_atlas_tex;
+
+layout(std140) uniform impostor {
+    vec4 cell_a;
+    vec4 cell_b;
+    vec4 sun_dir; // towards the sun in model space, w = 1 if lit by the normal atlas
+    vec4 sun_color;
+    vec4 ambient;
+    float blend;
+    float fade;
+    float alpha_cutoff;
+};
+
+in vec2 I_TEX;
+out vec4 f_color;
+
+// 4x4 ordered dither threshold, so partly faded impostors discard a stable pattern
+float dither() {
+    const float m[16] = float[](
+         0.0,  8.0,  2.0, 10.0,
+        12.0,  4.0, 14.0,  6.0,
+         3.0, 11.0,  1.0,  9.0,
+        15.0,  7.0, 13.0,  5.0);
+    ivec2 p = ivec2(gl_FragCoord.xy) % 4;
+    return (m[p.y * 4 + p.x] + 0.5) / 16.0;
+}
+
+void main() {
+    // undo the flip of the vertex shader, cells are stored with clip space up
+    vec2 uv = vec2(I_TEX.x, 1.0 - I_TEX.y);
+    vec4 a = texture(atlas_tex, cell_a.xy + uv * cell_a.zw);
+    vec4 b = texture(atlas_tex, cell_b.xy + uv * cell_b.zw);
+    vec4 c = mix(a, b, blend);
+    if (c.a < alpha_cutoff || fade < dither()) {
+        discard;
+    }
+    vec3 color = c.rgb;
+    if (sun_dir.w > 0.5) {
+        vec3 na = texture(normal_atlas_tex, cell_a.xy + uv * cell_a.zw).rgb;
+        vec3 nb = texture(normal_atlas_tex, cell_b.xy + uv * cell_b.zw).rgb;
+        vec3 n = normalize(mix(na, nb, blend) * 2 - 1);
+        color *= sun_color.rgb * max(dot(n, sun_dir.xyz), 0) + ambient.rgb;
+    }
+    f_color = vec4(color, 1.0);
+}
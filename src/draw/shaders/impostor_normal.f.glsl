@@ -0,0 +1,20 @@
+#version 410
+
+uniform sampler2D normal_tex;
+uniform sampler2D albedo_tex;
+
+in vec3 I_NORM;
+in vec2 I_TEX;
+in vec3 I_TAN;
+in vec3 I_BITAN;
+out vec4 f_color;
+
+void main() {
+    if (texture(albedo_tex, I_TEX).a < 0.5) {
+        discard;
+    }
+    vec3 normal_map = texture(normal_tex, I_TEX).rgb * 2 - 1;
+    vec3 N = normalize(mat3(I_TAN, I_BITAN, I_NORM) * normal_map);
+    // the view cells are in model space, so this is too
+    f_color = vec4(N * 0.5 + 0.5, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 410
+
+uniform sampler2D sprite_tex;
+
+in vec2 v_tex;
+in vec3 v_color;
+out vec4 f_color;
+
+void main() {
+    f_color = vec4(texture(sprite_tex, v_tex).rgb * v_color, 1.0);
+}
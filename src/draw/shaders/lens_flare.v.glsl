@@ -0,0 +1,54 @@
+#version 410
+
+uniform sampler2D spots_tex;
+
+layout(std140) uniform lens_flare {
+    vec4 viewport; // x, y, width, height in pixels
+    vec4 target_size;
+    vec4 sprite; // offset towards the center, height as a fraction of the view
+    vec4 color;
+    int spot_column; // which eye's grid of the spot target to read
+    int max_spots;
+};
+
+out vec2 v_tex;
+out vec3 v_color;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0));
+
+vec4 spot(int i) {
+    return texelFetch(spots_tex, ivec2(spot_column * FLARE_GRID + i % FLARE_GRID, i / FLARE_GRID), 0);
+}
+
+// one instance per cell of the grid, drawing a sprite only for the brightest cells
+void main() {
+    int cell = gl_InstanceID;
+    vec4 s = spot(cell);
+    // rank the cell by brightness, with ties going to the lower cell
+    int rank = 0;
+    for (int i = 0; i < FLARE_GRID * FLARE_GRID; i++) {
+        float b = spot(i).b;
+        if (b > s.b || (b == s.b && i < cell)) {
+            rank++;
+        }
+    }
+    if (s.b <= 0.0 || rank >= max_spots) {
+        // collapse the quad so nothing is drawn
+        gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+        v_tex = vec2(0.0);
+        v_color = vec3(0.0);
+        return;
+    }
+
+    // from 0 to 1 across the eye viewport
+    vec2 pos = (vec2(cell % FLARE_GRID, cell / FLARE_GRID) + s.rg) / float(FLARE_GRID);
+    vec2 at = mix(pos, vec2(0.5), sprite.x);
+    vec2 corner = CORNERS[gl_VertexID];
+    vec2 uv = at + corner * 0.5 * sprite.y * vec2(viewport.w / viewport.z, 1.0);
+    vec2 pixel = viewport.xy + uv * viewport.zw;
+    gl_Position = vec4(pixel / target_size.xy * 2.0 - 1.0, 0.0, 1.0);
+    v_tex = corner * 0.5 + 0.5;
+    v_color = color.rgb * s.b;
+}
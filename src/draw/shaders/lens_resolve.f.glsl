@@ -0,0 +1,24 @@
+#version 410
+
+uniform sampler2D scene_tex;
+
+layout(std140) uniform lens_resolve {
+    vec4 lens; // eye center, full rate radius and feather in pixels
+};
+
+out vec4 f_color;
+
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    if (LENS_SKIPPED(lens, gl_FragCoord.xy)) {
+        // the direct neighbors are the other checkerboard color, which is always shaded
+        ivec2 top = textureSize(scene_tex, 0) - 1;
+        f_color = 0.25 * (
+            texelFetch(scene_tex, clamp(p + ivec2(1, 0), ivec2(0), top), 0) +
+            texelFetch(scene_tex, clamp(p - ivec2(1, 0), ivec2(0), top), 0) +
+            texelFetch(scene_tex, clamp(p + ivec2(0, 1), ivec2(0), top), 0) +
+            texelFetch(scene_tex, clamp(p - ivec2(0, 1), ivec2(0), top), 0));
+    } else {
+        f_color = texelFetch(scene_tex, p, 0);
+    }
+}
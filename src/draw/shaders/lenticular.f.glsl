@@ -0,0 +1,38 @@
+#version 410
+
+uniform sampler2D left_tex;
+uniform sampler2D right_tex;
+
+layout(std140) uniform lenticular {
+    vec4 viewport; // corner and size of the region of the target the eyes are fitted to in pixels
+    int decode; // convert sampled values from sRGB to linear
+    int encode; // convert linear values to sRGB
+};
+
+out vec4 f_color;
+
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main() {
+    vec2 t = (gl_FragCoord.xy - viewport.xy) / viewport.zw;
+    if (any(lessThan(t, vec2(0.0))) || any(greaterThan(t, vec2(1.0)))) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+    // the lens sheet sends even rows (counted from the bottom) to the left eye
+    vec4 c = int(gl_FragCoord.y) % 2 == 0 ? texture(left_tex, t) : texture(right_tex, t);
+    vec3 rgb = clamp(c.rgb, 0.0, 1.0);
+    if (decode != 0) {
+        rgb = srgb_to_linear(rgb);
+    }
+    if (encode != 0) {
+        rgb = linear_to_srgb(rgb);
+    }
+    f_color = vec4(rgb, 1.0);
+}
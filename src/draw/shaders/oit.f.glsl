@@ -0,0 +1,17 @@
+#version 410
+
+uniform sampler2D color_tex;
+
+in vec2 I_TEX;
+out vec4 f_accum;
+out float f_reveal;
+
+void main() {
+    vec4 c = texture(color_tex, I_TEX);
+    // weighting function from McGuire and Bavoil, "Weighted Blended Order-Independent
+    // Transparency", favoring near and opaque surfaces
+    float z = STANDARD_DEPTH(gl_FragCoord.z);
+    float w = clamp(pow(min(1.0, c.a * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - z * 0.9, 3.0), 1e-2, 3e3);
+    f_accum = vec4(c.rgb * c.a, c.a) * w;
+    f_reveal = c.a;
+}
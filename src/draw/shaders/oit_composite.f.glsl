@@ -0,0 +1,18 @@
+#version 410
+
+uniform sampler2D accum_tex;
+uniform sampler2D reveal_tex;
+
+out vec4 f_color;
+
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    float reveal = texelFetch(reveal_tex, p, 0).r;
+    if (reveal >= 1.0) {
+        // nothing transparent covers this pixel
+        discard;
+    }
+    vec4 accum = texelFetch(accum_tex, p, 0);
+    vec3 avg = accum.rgb / max(accum.a, 1e-5);
+    f_color = vec4(avg, 1.0 - reveal);
+}
@@ -0,0 +1,57 @@
+#version 410
+
+layout(std140) uniform transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
+    float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
+};
+
+layout(std140) uniform particles {
+    // near plane, far plane, fade distance, 1 if the scene depth is bound
+    vec4 depth_params;
+    float lit;
+};
+
+uniform sampler2D scene_depth;
+uniform samplerCube irradiance_map;
+
+in vec3 v_pos;
+in vec2 v_uv;
+in vec4 v_color;
+in float v_depth;
+out vec4 f_color;
+
+float linear_depth(float d) {
+    if (log_depth > 0.0) {
+        return LOG_DEPTH_VIEW(d, log_depth);
+    }
+    float n = depth_params.x;
+    float f = depth_params.y;
+    return 2.0 * n * f / (f + n - (2.0 * STANDARD_DEPTH(d) - 1.0) * (f - n));
+}
+
+void main() {
+    float r = length(v_uv);
+    if (r > 1.0) {
+        discard;
+    }
+    float alpha = v_color.a * (1.0 - r * r);
+
+    // fade out where the sprite cuts into scene geometry
+    if (depth_params.w > 0.5) {
+        vec2 uv = gl_FragCoord.xy / vec2(textureSize(scene_depth, 0));
+        float scene = linear_depth(texture(scene_depth, uv).r);
+        alpha *= clamp((scene - v_depth) / depth_params.z, 0.0, 1.0);
+    }
+
+    vec3 color = v_color.rgb;
+    if (lit > 0.0) {
+        vec3 n = normalize(eye_pos.xyz - v_pos);
+        color *= mix(vec3(1.0), texture(irradiance_map, n).rgb, lit);
+    }
+    f_color = vec4(pow(color, vec3(1.0 / OUTPUT_GAMMA)), alpha);
+}
@@ -0,0 +1,47 @@
+#version 410
+
+layout(std140) uniform transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
+    float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
+};
+
+in vec4 i_pos_size;
+in vec4 i_color;
+
+out vec3 v_pos;
+out vec2 v_uv;
+out vec4 v_color;
+out float v_depth;
+
+const vec2 corners[6] = vec2[](
+    vec2(-1, -1), vec2(1, -1), vec2(1, 1),
+    vec2(-1, -1), vec2(1, 1), vec2(-1, 1));
+
+void main() {
+    vec2 c = corners[gl_VertexID % 6];
+    // the rows of the view rotation are the camera axes in world space
+    vec3 right = vec3(view[0][0], view[1][0], view[2][0]);
+    vec3 up = vec3(view[0][1], view[1][1], view[2][1]);
+    vec4 p = model * vec4(i_pos_size.xyz, 1);
+    p.xyz += (right * c.x + up * c.y) * i_pos_size.w * 0.5;
+    v_pos = p.xyz;
+    v_uv = c;
+    v_color = i_color;
+
+    vec4 vp = view * p;
+    v_depth = -vp.z;
+    vec4 clip = proj * vp;
+    // Fake an opengl viewport
+    clip.x /= 2 * clip.w;
+    clip.x += clip_offset;
+    clip.x *= clip.w;
+    if (log_depth > 0.0) {
+        clip.z = LOG_DEPTH_Z(clip, log_depth);
+    }
+    gl_Position = clip;
+}
@@ -18,7 +18,9 @@ layout(std140) uniform transform {
     mat4 view;
     mat4 proj;
     vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
     float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
 };
 
 struct Light {
@@ -35,6 +37,7 @@ in vec3 I_NORM;
 in vec2 I_TEX;
 in vec3 I_TAN;
 in vec3 I_BITAN;
+flat in vec4 v_lens;
 out vec4 f_lum;
 
 vec3 fresnelSchlick(float cosTheta, vec3 F0) {
@@ -72,6 +75,9 @@ float geometrySmith(vec3 N, vec3 V, vec3 L, float roughness) {
 }
 
 void main() {
+    if (LENS_SKIPPED(v_lens, gl_FragCoord.xy)) {
+        discard;
+    }
     vec3 normal_map = texture(normal_tex, I_TEX).rgb * 2 - 1;
     vec3 norm = mat3(I_TAN, I_BITAN, I_NORM) * normal_map;
 
@@ -116,5 +122,5 @@ void main() {
     }
 
     // OUT
-    f_lum = vec4(pow(lum, vec3(1 / 2.2)), 1);
+    f_lum = vec4(pow(lum, vec3(1.0 / OUTPUT_GAMMA)), 1);
 }
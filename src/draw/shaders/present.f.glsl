@@ -0,0 +1,34 @@
+#version 410
+
+uniform sampler2D frame_tex;
+
+layout(std140) uniform present {
+    vec4 source; // corner and size of the copied region of the frame in pixels
+    vec4 viewport; // corner and size of the region of the target drawn in pixels
+    int decode; // convert sampled values from sRGB to linear
+    int encode; // convert linear values to sRGB
+};
+
+out vec4 f_color;
+
+// the exact piecewise curves used by sRGB texture and framebuffer hardware
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main() {
+    vec2 t = (gl_FragCoord.xy - viewport.xy) / viewport.zw;
+    vec4 c = texture(frame_tex, (source.xy + t * source.zw) / vec2(textureSize(frame_tex, 0)));
+    vec3 rgb = clamp(c.rgb, 0.0, 1.0);
+    if (decode != 0) {
+        rgb = srgb_to_linear(rgb);
+    }
+    if (encode != 0) {
+        rgb = linear_to_srgb(rgb);
+    }
+    f_color = vec4(rgb, 1.0);
+}
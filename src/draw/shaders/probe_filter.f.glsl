@@ -0,0 +1,68 @@
+#version 410
+
+uniform samplerCube source_map;
+
+layout(std140) uniform filter_params {
+    vec4 face_forward;
+    vec4 face_right;
+    vec4 face_up;
+    float size; // of the face being drawn, in pixels
+    float roughness;
+    float source_gamma;
+    int sample_count;
+};
+
+out vec3 f_color;
+
+const float PI = 3.14159265359;
+
+vec2 hammersley(uint i) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(sample_count), float(bits) * 2.3283064365386963e-10);
+}
+
+// captured faces are display encoded, the maps hold linear light
+vec3 source(vec3 dir) {
+    return pow(texture(source_map, dir).rgb, vec3(source_gamma));
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / size * 2.0 - 1.0;
+    vec3 N = normalize(face_forward.xyz + uv.x * face_right.xyz + uv.y * face_up.xyz);
+    vec3 up = abs(N.y) < 0.999 ? vec3(0, 1, 0) : vec3(1, 0, 0);
+    vec3 T = normalize(cross(up, N));
+    vec3 B = cross(N, T);
+
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < uint(sample_count); i++) {
+        vec2 xi = hammersley(i);
+        #ifdef IRRADIANCE
+        // cosine weighted hemisphere
+        float phi = 2.0 * PI * xi.x;
+        float r = sqrt(xi.y);
+        vec3 L = T * r * cos(phi) + B * r * sin(phi) + N * sqrt(1.0 - xi.y);
+        sum += source(L);
+        weight += 1.0;
+        #else
+        // GGX importance sampling with the view along the normal
+        float a = roughness * roughness;
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 H = T * sin_theta * cos(phi) + B * sin_theta * sin(phi) + N * cos_theta;
+        vec3 L = 2.0 * dot(N, H) * H - N;
+        float NdotL = dot(N, L);
+        if (NdotL > 0.0) {
+            sum += source(L) * NdotL;
+            weight += NdotL;
+        }
+        #endif
+    }
+    f_color = sum / max(weight, 1e-4);
+}
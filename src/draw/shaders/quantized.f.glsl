@@ -0,0 +1,21 @@
+#version 410
+
+uniform sampler2D normal_tex;
+uniform sampler2D albedo_tex;
+
+in vec3 I_POS;
+in vec3 I_NORM;
+in vec2 I_TEX;
+in vec3 I_TAN;
+in vec3 I_BITAN;
+out vec4 f_color;
+
+void main() {
+    vec3 n = texture(normal_tex, I_TEX).rgb * 2.0 - 1.0;
+    n = normalize(mat3(normalize(I_TAN), normalize(I_BITAN), normalize(I_NORM)) * n);
+    vec4 albedo = texture(albedo_tex, I_TEX);
+
+    // light from above, with some ambient light from below
+    float lightness = mix(0.3, 1.0, max(dot(vec3(0, 1, 0), n), 0));
+    f_color = vec4(pow(albedo.rgb * lightness, vec3(1.0 / OUTPUT_GAMMA)), albedo.a);
+}
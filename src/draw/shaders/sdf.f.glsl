@@ -0,0 +1,30 @@
+#version 410
+
+uniform sampler2D font_tex;
+
+layout(std140) uniform params {
+    vec4 text_color;
+    float edge_width;
+};
+
+in vec2 I_TEX;
+out vec4 f_color;
+
+float median(float r, float g, float b) {
+    return max(min(r, g), min(max(r, g), b));
+}
+
+void main() {
+    vec4 s = texture(font_tex, I_TEX);
+    #ifdef MSDF
+    // the median of the three channels keeps corners sharp
+    float d = median(s.r, s.g, s.b);
+    #else
+    float d = s.r;
+    #endif
+    // widen the edge when the text is small on screen so it never aliases
+    float w = max(edge_width, 0.7 * fwidth(d));
+    float alpha = smoothstep(0.5 - w, 0.5 + w, d);
+    if (alpha <= 0.0) discard;
+    f_color = vec4(text_color.rgb, text_color.a * alpha);
+}
@@ -5,14 +5,54 @@ layout(std140) uniform transform {
     mat4 view;
     mat4 proj;
     vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
     float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
 };
 
+#ifdef VELOCITY
+// the transform of the same draw in the previous frame
+layout(std140) uniform previous_transform {
+    mat4 prev_model;
+    mat4 prev_view;
+    mat4 prev_proj;
+    vec4 prev_eye_pos;
+    vec4 prev_lens;
+    float prev_clip_offset;
+};
+// clip positions of this eye without the viewport offset, divided per pixel
+out vec4 v_clip;
+out vec4 v_prev_clip;
+#endif
+
+#ifdef QUANTIZED
+// positions are 16-bit fixed point, directions are octahedral encoded
+layout(std140) uniform quant {
+    vec4 scale;
+    vec4 bias;
+};
+in ivec4 a_pos;
+
+vec3 oct_decode(ivec2 q) {
+    vec2 e = vec2(q) / 127.0;
+    vec3 v = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+    if (v.z < 0) {
+        v.xy = (1.0 - abs(v.yx)) * vec2(v.x >= 0 ? 1.0 : -1.0, v.y >= 0 ? 1.0 : -1.0);
+    }
+    return normalize(v);
+}
+#else
 in vec3 a_pos;
+#endif
 out vec3 v_pos;
+flat out vec4 v_lens;
 
 #ifdef NORM
+#ifdef QUANTIZED
+in ivec4 a_norm;
+#else
 in vec3 a_norm;
+#endif
 out vec3 v_norm;
 #endif
 
@@ -27,9 +67,13 @@ out vec3 v_color;
 #endif
 
 #ifdef TAN
+#ifdef QUANTIZED
+in ivec4 a_tan;
+#else
 in vec3 a_tan;
-out vec3 v_tan;
 in vec3 a_bitan;
+#endif
+out vec3 v_tan;
 out vec3 v_bitan;
 #endif
 
@@ -38,11 +82,22 @@ out vec3 v_bitan;
 #endif
 
 void main() {
-    vec4 p = model * vec4(a_pos, W_COORD);
+    #ifdef QUANTIZED
+    vec3 pos = scale.xyz * vec3(a_pos.xyz) + bias.xyz;
+    #else
+    vec3 pos = a_pos;
+    #endif
+    vec4 p = model * vec4(pos, W_COORD);
     v_pos = p.xyz;
+    v_lens = lens;
 
     #ifdef NORM
-    v_norm = (model * vec4(a_norm, 0)).xyz;
+    #ifdef QUANTIZED
+    vec3 norm = oct_decode(a_norm.xy);
+    #else
+    vec3 norm = a_norm;
+    #endif
+    v_norm = (model * vec4(norm, 0)).xyz;
     #endif
 
     #ifdef TEX
@@ -55,15 +110,30 @@ void main() {
     #endif
 
     #ifdef TAN
-    v_tan = (model * vec4(a_tan, 0)).xyz;
-    v_bitan = (model * vec4(a_bitan, 0)).xyz;
+    #ifdef QUANTIZED
+    vec3 tan = oct_decode(a_tan.xy);
+    // only the side the bitangent is on is stored
+    vec3 bitan = cross(norm, tan) * sign(float(a_tan.z));
+    #else
+    vec3 tan = a_tan;
+    vec3 bitan = a_bitan;
+    #endif
+    v_tan = (model * vec4(tan, 0)).xyz;
+    v_bitan = (model * vec4(bitan, 0)).xyz;
     #endif
 
     vec4 c = proj * view * p;
+    #ifdef VELOCITY
+    v_clip = c;
+    v_prev_clip = prev_proj * prev_view * prev_model * vec4(pos, W_COORD);
+    #endif
     // Fake an opengl viewport
     // TODO: Submit a PR to GFX
     c.x /= 2 * c.w;
     c.x += clip_offset;
     c.x *= c.w;
+    if (log_depth > 0.0) {
+        c.z = LOG_DEPTH_Z(c, log_depth);
+    }
     gl_Position = c;
 }
@@ -6,29 +6,93 @@ const float F0_REFLECTIVITY = 0.0337;
 uniform sampler2D normal_tex;
 uniform sampler2D albedo_tex;
 uniform sampler2D knobs_tex;
+uniform sampler2D bent_tex;
+
+#ifdef LAYERS
+uniform sampler2D layer0_normal;
+uniform sampler2D layer0_albedo;
+uniform sampler2D layer0_knobs;
+uniform sampler2D layer0_mask;
+uniform sampler2D layer1_normal;
+uniform sampler2D layer1_albedo;
+uniform sampler2D layer1_knobs;
+uniform sampler2D layer1_mask;
+#endif
+
+#ifdef VIRTUAL_TEXTURE
+// for each page of each mip level, the atlas slot (xy) and mip level (z) of the finest
+// loaded page covering it
+uniform sampler2D page_table;
+uniform sampler2D page_atlas;
+#endif
 
 uniform samplerCube irradiance_map;
 uniform samplerCube radiance_map;
+uniform samplerCube irradiance_map_b;
+uniform samplerCube radiance_map_b;
+uniform sampler2D sun_cookie_tex;
+uniform sampler2D sky_occlusion_tex;
 uniform sampler2D integrated_brdf_map;
+uniform sampler2D ltc_matrix_map;
+uniform sampler2D ltc_norm_map;
 
 uniform sampler2DShadow shadow_depth;
 
+// the voxel grid, with slices along x, y and z
+uniform sampler3D voxel_x;
+uniform sampler3D voxel_y;
+uniform sampler3D voxel_z;
+
 layout(std140) uniform transform {
     mat4 model;
     mat4 view;
     mat4 proj;
     vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
     float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
 };
 
 layout(std140) uniform params {
     mat4 sun_matrix;
     vec4 sun_color;
+    vec4 probe_pos_a; // w = 1 if reflections are projected onto the probe box
+    vec4 probe_min_a;
+    vec4 probe_max_a;
+    vec4 probe_pos_b;
+    vec4 probe_min_b;
+    vec4 probe_max_b;
+    vec4 probe_levels; // radiance mip levels of both probes
+    vec4 sun_cookie; // 1 / extent, enabled, tiled, light outside the cookie
+    vec4 voxel_min; // grid corner, 1 / grid size
+    vec4 voxel_params; // enabled, diffuse strength, specular strength, mip levels
+    vec4 sky_occlusion_rect; // corner on the XZ plane, 1 / size
+    float sky_occlusion; // strength, 0 when there is no map
     float sun_in_env;
     int radiance_levels;
 
     float gamma;
     float exposure;
+    float probe_blend; // how far to blend the environment towards the second probe
+    float shadow_depth_bias; // subtracted from the depth compared with the shadow map
+};
+
+layout(std140) uniform surface {
+    mat4 surface_inv_model;
+    mat4 surface_normal_matrix; // transpose(inverse(model)), for the upper 3x3
+    vec4 surface_params; // radius, half height
+    int surface_kind; // 0 = mesh, 1 = sphere, 2 = cylinder, 3 = capsule
+};
+
+struct AreaLight {
+    vec4 center; // w = shape (0 = rectangle, 1 = disk)
+    vec4 axis_x; // center to edge along the shape's x axis
+    vec4 axis_y; // center to edge along the shape's y axis
+    vec4 color;
+};
+
+layout(std140) uniform area_lights_layout {
+    AreaLight area_lights[AREA_LIGHT_COUNT];
 };
 
 in vec3 I_POS;
@@ -36,7 +100,14 @@ in vec3 I_NORM;
 in vec2 I_TEX;
 in vec3 I_TAN;
 in vec3 I_BITAN;
+flat in vec4 v_lens;
 out vec4 f_color;
+#ifdef VELOCITY
+in vec4 I_CLIP;
+in vec4 I_PREV_CLIP;
+// NDC offset since the previous frame
+out vec2 f_velocity;
+#endif
 
 vec3 fresnel_schlick(float cos_theta, vec3 f_0) {
     return f_0 + (1.0 - f_0) * pow(1 - cos_theta, 5);
@@ -88,14 +159,225 @@ vec3 light_contrib(
     return (diffuse_brdf + specular_brdf) * radiance;
 }
 
+// Normal computed from the analytic shape of generated primitives, which hides the
+// shading seams of low-poly geometry.
+vec3 surface_normal() {
+    if (surface_kind == 0) return I_NORM;
+
+    vec3 p = (surface_inv_model * vec4(I_POS, 1.0)).xyz;
+    vec3 n;
+    if (surface_kind == 1) {
+        n = p;
+    } else {
+        vec3 axis = normalize((model * vec4(0.0, 1.0, 0.0, 0.0)).xyz);
+        // flat cylinder caps keep their own normals
+        if (surface_kind == 2 && abs(dot(normalize(I_NORM), axis)) > 0.5) return I_NORM;
+        float h = surface_params.y;
+        n = p - vec3(0.0, clamp(p.y, -h, h), 0.0);
+    }
+    return mat3(surface_normal_matrix) * n;
+}
+
+// Integral of the cosine over the arc between two directions on the unit sphere,
+// as a vector (see Heitz et al. 2016, "Real-Time Polygonal-Light Shading with
+// Linearly Transformed Cosines")
+vec3 ltc_edge(vec3 v1, vec3 v2) {
+    float x = dot(v1, v2);
+    float y = abs(x);
+    float a = 0.8543985 + (0.4965155 + 0.0145206 * y) * y;
+    float b = 3.4175940 + (4.1616724 + y) * y;
+    float v = a / b;
+    float theta_sintheta = (x > 0.0) ? v : 0.5 * inversesqrt(max(1.0 - x * x, 1e-7)) - v;
+    return cross(v1, v2) * theta_sintheta;
+}
+
+// Fraction of the lobe given by the inverse LTC matrix that is covered by an area light.
+// The polygon isn't clipped to the horizon; the parts below it subtract from the
+// vector form factor instead, which is close enough for lights in front of the surface.
+float ltc_evaluate(vec3 N, vec3 V, mat3 minv, AreaLight light) {
+    vec3 T1 = V - N * dot(V, N);
+    T1 = dot(T1, T1) > 1e-8 ? normalize(T1) : normalize(cross(N, abs(N.x) < 0.9 ? vec3(1, 0, 0) : vec3(0, 1, 0)));
+    vec3 T2 = cross(N, T1);
+    mat3 m = minv * transpose(mat3(T1, T2, N));
+
+    vec3 c = light.center.xyz - I_POS;
+    vec3 ax = light.axis_x.xyz;
+    vec3 ay = light.axis_y.xyz;
+    vec3 points[8];
+    int count;
+    if (light.center.w < 0.5) {
+        points[0] = c - ax - ay;
+        points[1] = c + ax - ay;
+        points[2] = c + ax + ay;
+        points[3] = c - ax + ay;
+        count = 4;
+    } else {
+        // an octagon with the same area as the disk
+        for (int i = 0; i < 8; i++) {
+            float a = float(i) * PI / 4.0;
+            points[i] = c + 1.0541 * (ax * cos(a) + ay * sin(a));
+        }
+        count = 8;
+    }
+
+    for (int i = 0; i < count; i++) {
+        points[i] = normalize(m * points[i]);
+    }
+    vec3 sum = vec3(0.0);
+    for (int i = 0; i < count; i++) {
+        sum += ltc_edge(points[i], points[(i + 1) % count]);
+    }
+    return abs(sum.z) / (2.0 * PI);
+}
+
+vec3 area_light_contrib(vec3 N, vec3 V, float NdotV, float roughness, vec3 albedo, float metalness) {
+    vec2 ltc = texture(ltc_matrix_map, vec2(NdotV, roughness)).rg;
+    float s = 1.0 / ltc.r;
+    float tilt = ltc.g * PI / 2.0;
+    // rotate the lobe onto the normal, then widen it into a cosine
+    mat3 minv = mat3(s, 0, 0, 0, s, 0, 0, 0, 1)
+        * mat3(cos(tilt), 0, -sin(tilt), 0, 1, 0, sin(tilt), 0, cos(tilt));
+    vec2 norm = texture(ltc_norm_map, vec2(NdotV, roughness)).rg;
+    vec3 F0 = mix(vec3(F0_REFLECTIVITY), albedo, metalness);
+    vec3 spec_scale = F0 * norm.x + norm.y;
+
+    vec3 lum = vec3(0.0);
+    for (int i = 0; i < AREA_LIGHT_COUNT; i++) {
+        AreaLight light = area_lights[i];
+        // lights only emit from their front side
+        vec3 front = cross(light.axis_x.xyz, light.axis_y.xyz);
+        if (dot(light.color.rgb, vec3(1.0)) <= 0.0 || dot(I_POS - light.center.xyz, front) <= 0.0) continue;
+
+        float spec = ltc_evaluate(N, V, minv, light);
+        float diff = ltc_evaluate(N, V, mat3(1.0), light);
+        lum += light.color.rgb * (spec * spec_scale + diff * albedo * (1.0 - metalness));
+    }
+    return lum;
+}
+
+// The fraction of sunlight let through by the cookie at a world position. The cookie is
+// projected along the sun direction, in the same light space as the shadow map.
+vec3 sun_cookie_light(vec3 pos) {
+    if (sun_cookie.y < 0.5) {
+        return vec3(1.0);
+    }
+    vec3 light_pos = transpose(mat3(sun_matrix)) * pos;
+    vec2 uv = light_pos.xy * sun_cookie.x + 0.5;
+    if (sun_cookie.z > 0.5) {
+        uv = fract(uv);
+    } else if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return vec3(sun_cookie.w);
+    }
+    return texture(sun_cookie_tex, uv).rgb;
+}
+
+// Find where a reflection ray leaves the probe box and return the direction from the probe
+// center to that point, so that nearby walls reflect where they are
+vec3 box_project(vec3 R, vec4 center, vec4 box_min, vec4 box_max) {
+    if (center.w < 0.5) {
+        return R;
+    }
+    vec3 to_max = (box_max.xyz - I_POS) / R;
+    vec3 to_min = (box_min.xyz - I_POS) / R;
+    vec3 far = max(to_max, to_min);
+    float d = min(min(far.x, far.y), far.z);
+    return I_POS + R * d - center.xyz;
+}
+
+// Sample the voxel grid at a world position and mip level. Each axis volume stores the
+// grid with its own axis as depth, so the lookups are swizzled back into place.
+vec4 sample_voxels(vec3 pos, float lod) {
+    vec3 p = (pos - voxel_min.xyz) * voxel_min.w;
+    vec4 x = textureLod(voxel_x, p.yzx, lod);
+    vec4 y = textureLod(voxel_y, p.zxy, lod);
+    vec4 z = textureLod(voxel_z, p.xyz, lod);
+    return max(max(x, y), z);
+}
+
+// March a cone through the voxel grid, widening the sampled mip level with the cone, and
+// return the light gathered (rgb) and how much of the cone is blocked (a)
+vec4 cone_trace(vec3 origin, vec3 dir, float aperture) {
+    float voxel = 1.0 / (voxel_min.w * exp2(voxel_params.w - 1));
+    vec4 acc = vec4(0.0);
+    float dist = voxel;
+    while (dist < 1.0 / voxel_min.w && acc.a < 0.95) {
+        float diameter = max(voxel, 2.0 * aperture * dist);
+        float lod = log2(diameter / voxel);
+        vec4 s = sample_voxels(origin + dir * dist, lod);
+        acc += (1.0 - acc.a) * s;
+        dist += diameter * 0.5;
+    }
+    return acc;
+}
+
+#ifdef LAYERS
+// blend a material layer over what is below it, where its mask is white
+void blend_layer(
+    sampler2D normal_layer,
+    sampler2D albedo_layer,
+    sampler2D knobs_layer,
+    sampler2D mask,
+    inout vec3 normal_map,
+    inout vec3 albedo,
+    inout vec3 knobs
+) {
+    float w = texture(mask, I_TEX).r;
+    normal_map = mix(normal_map, texture(normal_layer, I_TEX).rgb * 2 - 1, w);
+    albedo = mix(albedo, texture(albedo_layer, I_TEX).rgb, w);
+    knobs = mix(knobs, texture(knobs_layer, I_TEX).rgb, w);
+}
+#endif
+
+#ifdef VIRTUAL_TEXTURE
+// look up a page of the virtual texture at the mip level it is seen at, then sample it
+vec3 virtual_albedo(vec2 uv) {
+    uv = fract(uv);
+    ivec2 pages = textureSize(page_table, 0);
+    vec2 texels = uv * vec2(pages) * VT_PAGE_SIZE;
+    float lod = log2(max(length(dFdx(texels)), length(dFdy(texels))));
+    int levels = int(log2(float(max(pages.x, pages.y)))) + 1;
+    int level = clamp(int(floor(lod)), 0, levels - 1);
+    ivec2 page = min(ivec2(uv * vec2(pages)) >> level, textureSize(page_table, level) - 1);
+    vec3 entry = floor(texelFetch(page_table, page, level).xyz * 255.0 + 0.5);
+    // the page found may be coarser than the one wanted
+    vec2 in_page = fract(uv * vec2(pages) / exp2(entry.z));
+    float slot = VT_PAGE_SIZE + 2.0 * VT_BORDER;
+    vec2 p = entry.xy * slot + VT_BORDER + in_page * VT_PAGE_SIZE;
+    return textureLod(page_atlas, p / vec2(textureSize(page_atlas, 0)), 0.0).rgb;
+}
+#endif
+
 void main() {
+    if (LENS_SKIPPED(v_lens, gl_FragCoord.xy)) {
+        discard;
+    }
     // normal mapping
+#ifdef NORMAL_RG
+    // two channel map, z is always positive in tangent space
+    vec2 normal_xy = texture(normal_tex, I_TEX).rg * 2 - 1;
+    vec3 normal_map = vec3(normal_xy, sqrt(max(1 - dot(normal_xy, normal_xy), 0)));
+#else
     vec3 normal_map = texture(normal_tex, I_TEX).rgb * 2 - 1;
-    vec3 norm = mat3(I_TAN, I_BITAN, I_NORM) * normal_map;
+#endif
 
     // material params
     vec3 albedo = texture(albedo_tex, I_TEX).rgb;
     vec3 knobs = texture(knobs_tex, I_TEX).rgb;
+#ifdef VIRTUAL_TEXTURE
+    albedo *= virtual_albedo(I_TEX);
+#endif
+#ifdef LAYERS
+    blend_layer(layer0_normal, layer0_albedo, layer0_knobs, layer0_mask, normal_map, albedo, knobs);
+    blend_layer(layer1_normal, layer1_albedo, layer1_knobs, layer1_mask, normal_map, albedo, knobs);
+#endif
+    mat3 tbn = mat3(I_TAN, I_BITAN, normalize(surface_normal()) * length(I_NORM));
+    vec3 norm = tbn * normal_map;
+
+    // bent normal, tilted by the normal map the same way as the surface normal
+    vec4 bent_map = texture(bent_tex, I_TEX);
+    vec3 geometric = normalize(tbn[2]);
+    float occlusion = bent_map.a;
+
     float metalness = knobs.r;
     metalness = sqrt(metalness);
     float roughness = knobs.g;
@@ -112,17 +394,47 @@ void main() {
     // outgoing radiance
     vec3 lum = vec3(0.0);
 
+    // large scale occlusion of the sky from above
+    float sky = 1.0;
+    if (sky_occlusion > 0.0) {
+        vec2 sky_uv = (I_POS.xz - sky_occlusion_rect.xy) * sky_occlusion_rect.zw;
+        sky = mix(1.0, texture(sky_occlusion_tex, sky_uv).r, sky_occlusion);
+    }
+
     // IBL
-    // indirect diffuse
-    lum += texture(irradiance_map, N).rgb * albedo * (1.0 - metalness);
+    // indirect diffuse, from the least occluded direction
+    vec3 bent_N = normalize(N + normalize(tbn * (bent_map.rgb * 2 - 1)) - geometric);
+    vec3 irradiance = mix(texture(irradiance_map, bent_N).rgb, texture(irradiance_map_b, bent_N).rgb, probe_blend);
+    lum += irradiance * albedo * (1.0 - metalness) * occlusion * sky;
     vec2 env_brdf = texture(integrated_brdf_map, vec2(NdotV, roughness)).rg;
-    float lod = mix(0, radiance_levels - 1, roughness);
-    lum += textureLod(radiance_map, R, lod).rgb * (albedo * env_brdf.r + vec3(env_brdf.g));
+    vec3 R_a = box_project(R, probe_pos_a, probe_min_a, probe_max_a);
+    vec3 R_b = box_project(R, probe_pos_b, probe_min_b, probe_max_b);
+    float lod_a = mix(0, probe_levels.x - 1, roughness);
+    float lod_b = mix(0, probe_levels.y - 1, roughness);
+    vec3 radiance = mix(textureLod(radiance_map, R_a, lod_a).rgb, textureLod(radiance_map_b, R_b, lod_b).rgb, probe_blend);
+    lum += radiance * (albedo * env_brdf.r + vec3(env_brdf.g)) * occlusion;
+
+    // cone traced bounce light, from one specular and five diffuse cones
+    if (voxel_params.x > 0.5) {
+        vec3 origin = I_POS + geometric / (voxel_min.w * exp2(voxel_params.w - 1));
+        vec3 t = normalize(tbn[0] - N * dot(tbn[0], N));
+        vec3 b = cross(N, t);
+        vec4 diffuse = cone_trace(origin, N, 0.577);
+        for (int i = 0; i < 4; i++) {
+            float a = float(i) * PI / 2.0;
+            vec3 side = t * cos(a) + b * sin(a);
+            diffuse += cone_trace(origin, normalize(N + side), 0.577) * 0.75;
+        }
+        diffuse /= 4.0;
+        lum += diffuse.rgb * albedo * (1.0 - metalness) * occlusion * voxel_params.y;
+        vec4 specular = cone_trace(origin, R, max(alpha, 0.02));
+        lum += specular.rgb * (albedo * env_brdf.r + vec3(env_brdf.g)) * occlusion * voxel_params.z;
+    }
 
     // sun shadow
     vec4 sun_frag_pos = sun_matrix * vec4(I_POS, 1.0);
     vec3 sun_frag_uv = sun_frag_pos.xyz / sun_frag_pos.w * 0.5 + 0.5; // position in shadow buffer
-    float shadow_level = 1 /*texture(shadow_depth, sun_frag_uv)*/ - sun_in_env;
+    float shadow_level = 1 /*texture(shadow_depth, sun_frag_uv - vec3(0.0, 0.0, shadow_depth_bias))*/ - sun_in_env;
 
     // sun vectors
     vec3 sun_L = -(sun_matrix * vec4(0.0, 0.0, -1.0, 0.0)).xyz;
@@ -136,15 +448,21 @@ void main() {
         NdotV,
         sun_NdotH,
         sun_VdotH,
-        sun_color.rgb * sun_color.a,
+        sun_color.rgb * sun_color.a * sun_cookie_light(I_POS),
         albedo,
         max(alpha, 0.0025),
         metalness);
 
+    // area lights
+    lum += area_light_contrib(N, V, NdotV, roughness, albedo, metalness);
+
     // hdr to ldr  
     vec3 mapped = vec3(1.0) - exp(-lum * exposure);
     //mapped = mix(mapped, albedo, solidness); // make solid
     mapped = pow(mapped, vec3(1.0 / gamma));
 
     f_color = vec4(mapped, 1.0);
+    #ifdef VELOCITY
+    f_velocity = I_CLIP.xy / I_CLIP.w - I_PREV_CLIP.xy / I_PREV_CLIP.w;
+    #endif
 }
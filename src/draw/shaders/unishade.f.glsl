@@ -13,5 +13,5 @@ out vec4 f_color;
 void main() {
     float lightness = max(dot(vec3(0, 1, 0), I_NORM), 0);
     f_color = mix(dark, light, lightness);
-    f_color.xyz = pow(f_color.xyz, vec3(1 / 2.2));
+    f_color.xyz = pow(f_color.xyz, vec3(1.0 / OUTPUT_GAMMA));
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+#version 410
+
+uniform sampler2D color_tex;
+
+#ifndef SCREEN_TEX
+in vec2 I_TEX;
+#endif
+flat in vec4 v_lens;
+out vec4 f_color;
+
+void main() {
+    if (LENS_SKIPPED(v_lens, gl_FragCoord.xy)) {
+        discard;
+    }
+    #ifdef SCREEN_TEX
+    // the texture covers the whole target, so it is sampled where this fragment lands
+    f_color = texture(color_tex, gl_FragCoord.xy / vec2(textureSize(color_tex, 0)));
+    #else
+    // the texture is already in display space, so it is shown unchanged
+    f_color = texture(color_tex, I_TEX);
+    #endif
+}
@@ -0,0 +1,12 @@
+#version 410
+
+layout(std140) uniform visibility_draw {
+    uint first_triangle;
+};
+
+out uint f_id;
+
+void main() {
+    // triangles are numbered from the start of each draw
+    f_id = uint(gl_PrimitiveID) + first_triangle;
+}
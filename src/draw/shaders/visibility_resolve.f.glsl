@@ -0,0 +1,77 @@
+#version 410
+
+#define NO_TRIANGLE 0xFFFFFFFFu
+
+uniform usampler2D id_tex;
+// the index list and the vertices (position and u, then normal and v) row by row
+uniform usampler2D index_tex;
+uniform sampler2D vertex_tex;
+uniform sampler2D albedo_tex;
+
+layout(std140) uniform visibility_resolve {
+    mat4 inv_view_proj;
+    vec4 eye_pos;
+    vec4 viewport; // x, y, width, height in pixels
+    vec4 sun_dir; // towards the sun
+    vec4 sun_color;
+    vec4 ambient;
+    float gamma;
+};
+
+out vec4 f_color;
+
+ivec2 data_coord(int i, int width) {
+    return ivec2(i % width, i / width);
+}
+
+uint fetch_index(int i) {
+    return texelFetch(index_tex, data_coord(i, textureSize(index_tex, 0).x), 0).r;
+}
+
+vec4 fetch_vertex(uint v, int part) {
+    return texelFetch(vertex_tex, data_coord(int(v) * 2 + part, textureSize(vertex_tex, 0).x), 0);
+}
+
+void main() {
+    uint id = texelFetch(id_tex, ivec2(gl_FragCoord.xy), 0).r;
+    if (id == NO_TRIANGLE) {
+        discard;
+    }
+    int first = int(id) * 3;
+    uint i0 = fetch_index(first);
+    uint i1 = fetch_index(first + 1);
+    uint i2 = fetch_index(first + 2);
+    vec4 p0 = fetch_vertex(i0, 0);
+    vec4 p1 = fetch_vertex(i1, 0);
+    vec4 p2 = fetch_vertex(i2, 0);
+    vec4 n0 = fetch_vertex(i0, 1);
+    vec4 n1 = fetch_vertex(i1, 1);
+    vec4 n2 = fetch_vertex(i2, 1);
+
+    // the view ray through this pixel
+    vec2 uv = (gl_FragCoord.xy - viewport.xy) / viewport.zw;
+    vec4 far = inv_view_proj * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+    vec3 dir = far.xyz / far.w - eye_pos.xyz;
+
+    // barycentric weights where the ray crosses the plane of the triangle
+    vec3 e1 = p1.xyz - p0.xyz;
+    vec3 e2 = p2.xyz - p0.xyz;
+    vec3 p = cross(dir, e2);
+    float det = dot(e1, p);
+    if (abs(det) < 1e-12) {
+        det = 1e-12;
+    }
+    vec3 s = eye_pos.xyz - p0.xyz;
+    float b1 = dot(s, p) / det;
+    float b2 = dot(dir, cross(s, e1)) / det;
+    vec3 w = vec3(1.0 - b1 - b2, b1, b2);
+
+    vec3 norm = normalize(mat3(n0.xyz, n1.xyz, n2.xyz) * w);
+    vec2 tex = vec2(dot(vec3(p0.w, p1.w, p2.w), w), dot(vec3(n0.w, n1.w, n2.w), w));
+    tex.y = 1.0 - tex.y;
+    // no derivatives across triangles, so only the top mip level
+    vec3 albedo = textureLod(albedo_tex, tex, 0.0).rgb;
+
+    vec3 light = sun_color.rgb * max(dot(norm, sun_dir.xyz), 0.0) + ambient.rgb;
+    f_color = vec4(pow(albedo * light, vec3(1.0 / gamma)), 1.0);
+}
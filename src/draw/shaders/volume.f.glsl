@@ -0,0 +1,99 @@
+#version 410
+
+// Maximum number of samples taken along a single ray
+#define MAX_STEPS 512
+
+uniform sampler3D volume_tex;
+uniform sampler2D transfer_tex;
+
+layout(std140) uniform transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
+    float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
+};
+
+layout(std140) uniform params {
+    vec4 channel_weights;
+    int steps;
+    float opacity;
+    float brightness;
+};
+
+in vec3 I_POS;
+out vec4 f_color;
+
+void main() {
+    // work in texture space, where the volume box spans [0, 1]
+    mat4 inv_model = inverse(model);
+    vec3 origin = (inv_model * vec4(eye_pos.xyz, 1.0)).xyz + 0.5;
+    vec3 exit = (inv_model * vec4(I_POS, 1.0)).xyz + 0.5;
+    vec3 dir = exit - origin;
+
+    // find where the ray enters the box (or start at the eye if it is inside)
+    vec3 t0 = (vec3(0.0) - origin) / dir;
+    vec3 t1 = (vec3(1.0) - origin) / dir;
+    vec3 t_near = min(t0, t1);
+    float t_enter = clamp(max(max(t_near.x, t_near.y), t_near.z), 0.0, 1.0);
+    vec3 entry = origin + dir * t_enter;
+
+    int count = clamp(steps, 1, MAX_STEPS);
+    vec3 delta = (exit - entry) / float(count);
+    // step length relative to the box size, used for opacity correction
+    float step_len = length(delta);
+
+    #if defined(MODE_MIP)
+    float value = 0.0;
+    #elif defined(MODE_MINIP)
+    float value = 1.0;
+    #elif defined(MODE_AVERAGE)
+    float value = 0.0;
+    #else
+    vec4 acc = vec4(0.0);
+    #endif
+
+    vec3 p = entry + 0.5 * delta;
+    for (int i = 0; i < MAX_STEPS; i++) {
+        if (i >= count) break;
+        #ifdef CHANNELS
+        float density = dot(texture(volume_tex, p), channel_weights);
+        #else
+        float density = texture(volume_tex, p).r;
+        #endif
+
+        #if defined(MODE_MIP)
+        value = max(value, density);
+        #elif defined(MODE_MINIP)
+        value = min(value, density);
+        #elif defined(MODE_AVERAGE)
+        value += density;
+        #else
+        vec4 s = texture(transfer_tex, vec2(density, 0.5));
+        s.a = 1.0 - pow(1.0 - s.a, step_len * opacity);
+        // front to back compositing
+        acc.rgb += (1.0 - acc.a) * s.a * s.rgb;
+        acc.a += (1.0 - acc.a) * s.a;
+        if (acc.a > 0.99) break;
+        #endif
+
+        p += delta;
+    }
+
+    #if defined(MODE_AVERAGE)
+    value /= float(count);
+    #endif
+
+    #if defined(MODE_COMPOSITE)
+    vec3 lum = acc.rgb * brightness / max(acc.a, 0.0001);
+    float alpha = acc.a;
+    #else
+    // projections skip the transfer function, density maps straight to brightness
+    vec3 lum = vec3(value * brightness);
+    float alpha = clamp(value * brightness, 0.0, 1.0);
+    #endif
+
+    f_color = vec4(pow(lum, vec3(1.0 / OUTPUT_GAMMA)), alpha);
+}
@@ -0,0 +1,22 @@
+#version 410
+
+layout(std140) uniform voxelize {
+    vec4 sun_dir;
+    vec4 sun_color;
+    vec4 ambient;
+};
+
+uniform sampler2D albedo_tex;
+
+in vec3 I_NORM;
+in vec2 I_TEX;
+out vec4 f_color;
+
+void main() {
+    // voxels are not seen through a lens, so nothing is skipped here
+    vec3 albedo = texture(albedo_tex, I_TEX).rgb;
+    vec3 N = normalize(I_NORM);
+    // sun_dir points towards the sun
+    vec3 light = sun_color.rgb * max(dot(N, sun_dir.xyz), 0) + ambient.rgb;
+    f_color = vec4(albedo * light, 1);
+}
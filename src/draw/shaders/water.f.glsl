@@ -0,0 +1,94 @@
+#version 410
+
+const float F0_WATER = 0.02;
+
+uniform sampler2D normal_tex;
+uniform sampler2D reflection_tex;
+uniform sampler2D refraction_tex;
+uniform sampler2D depth_tex;
+
+layout(std140) uniform transform {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 eye_pos;
+    vec4 lens; // eye center, full rate radius and feather in pixels
+    float clip_offset;
+    float log_depth; // log2(LOG_DEPTH_C * far + 1), 0 for standard depth
+};
+
+layout(std140) uniform water {
+    mat4 reflect_left; // world to reflection target clip space
+    mat4 reflect_right;
+    vec4 layer_a; // offset, 1 / scale, strength
+    vec4 layer_b;
+    vec4 deep_color; // w = clarity in meters
+    vec4 sun_dir; // towards the sun
+    vec4 sun_color;
+    float distortion;
+    float shore_fade;
+};
+
+in vec3 I_POS;
+flat in vec4 v_lens;
+out vec4 f_color;
+
+// distance from the eye along the view axis of a depth buffer value
+float view_depth(float d) {
+    if (log_depth > 0.0) {
+        return LOG_DEPTH_VIEW(d, log_depth);
+    }
+    return proj[3][2] / (d * 2.0 - 1.0 + proj[2][2]);
+}
+
+// the ripples of one layer in tangent space
+vec2 ripples(vec4 layer) {
+    vec3 n = texture(normal_tex, I_POS.xz * layer.z + layer.xy).rgb * 2.0 - 1.0;
+    return n.xy * layer.w;
+}
+
+void main() {
+    if (LENS_SKIPPED(v_lens, gl_FragCoord.xy)) {
+        discard;
+    }
+    vec2 screen = gl_FragCoord.xy / vec2(textureSize(refraction_tex, 0));
+    float scene = texture(depth_tex, screen).r;
+    if (STANDARD_DEPTH(scene) < STANDARD_DEPTH(gl_FragCoord.z)) {
+        discard;
+    }
+
+    // the surface is horizontal, so tangent space x and y lie along world x and -z
+    vec2 tilt = ripples(layer_a) + ripples(layer_b);
+    vec3 n = normalize(vec3(tilt.x, 1.0, -tilt.y));
+    vec3 v = normalize(eye_pos.xyz - I_POS);
+    vec2 offset = n.xz * distortion;
+
+    // water depth along the view, for tinting and the shoreline
+    float surface = view_depth(gl_FragCoord.z);
+    float thickness = max(view_depth(scene) - surface, 0.0);
+    float shore = clamp(thickness / shore_fade, 0.0, 1.0);
+
+    // don't refract things in front of the water into it
+    vec2 bent = screen + offset * shore;
+    float bent_depth = view_depth(texture(depth_tex, bent).r);
+    if (bent_depth < surface) {
+        bent = screen;
+        bent_depth = view_depth(scene);
+    }
+    vec3 below = texture(refraction_tex, bent).rgb;
+    float absorbed = 1.0 - exp(-max(bent_depth - surface, 0.0) / deep_color.w);
+    vec3 refracted = mix(below, deep_color.rgb, absorbed);
+
+    vec4 r = (clip_offset > 0.0 ? reflect_right : reflect_left) * vec4(I_POS, 1.0);
+    vec2 reflect_uv = r.xy / r.w * 0.5 + 0.5 + offset;
+    vec3 reflected = texture(reflection_tex, reflect_uv).rgb;
+
+    float cos_v = max(dot(n, v), 0.0);
+    float fresnel = F0_WATER + (1.0 - F0_WATER) * pow(1.0 - cos_v, 5.0);
+    vec3 h = normalize(sun_dir.xyz + v);
+    vec3 highlight = sun_color.rgb * pow(max(dot(n, h), 0.0), 256.0) * fresnel;
+
+    vec3 color = mix(refracted, reflected, fresnel) + highlight;
+    // fade into the undistorted scene at the shore
+    f_color = vec4(mix(texture(refraction_tex, screen).rgb, color, shore), 1.0);
+}
@@ -0,0 +1,531 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect, Slice, IndexBuffer};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::state::{Rasterizer, Blend, BlendChannel, Equation, Factor, BlendValue};
+use gfx::format::{R8, Unorm};
+use nalgebra::{Vector3, Matrix4, Rotation3};
+use std::ops::{Add, Sub, Mul};
+
+use super::{DrawParams, EyeParams, RenderEvent, RenderEventBus, OffscreenTarget, UberEnv, UberInputs};
+use ::mesh::Primitive;
+use ::util::NativeRepr;
+use ::{Error, FlightError, ColorFormat, DepthFormat, Texture};
+
+/// Adds colors that are already multiplied by their opacity over the target
+const PREMULTIPLIED: Blend = Blend {
+    color: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::One,
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    },
+    alpha: BlendChannel {
+        equation: Equation::Add,
+        source: Factor::Zero,
+        destination: Factor::One,
+    },
+};
+
+gfx_defines!{
+    constant CloudBlock {
+        inv_view_proj: [[f32; 4]; 4] = "inv_view_proj",
+        eye_pos: [f32; 4] = "eye_pos",
+        viewport: [f32; 4] = "viewport",
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+        ambient: [f32; 4] = "ambient",
+        layer: [f32; 4] = "layer",
+        wind: [f32; 4] = "wind",
+        detail_wind: [f32; 4] = "detail_wind",
+        exposure: f32 = "exposure",
+        gamma: f32 = "gamma",
+    }
+
+    pipeline march {
+        params: gfx::ConstantBuffer<CloudBlock> = "clouds",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        noise: gfx::TextureSampler<f32> = "noise_tex",
+    }
+
+    constant CompositeBlock {
+        target_size: [f32; 4] = "target_size",
+    }
+
+    pipeline composite {
+        params: gfx::ConstantBuffer<CompositeBlock> = "clouds_composite",
+        scissor: gfx::Scissor = (),
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), PREMULTIPLIED),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+        clouds: gfx::TextureSampler<[f32; 4]> = "clouds_tex",
+    }
+}
+
+shader!(march_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/clouds.f.glsl")
+});
+
+shader!(composite_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl")
+        .define("FAR_PLANE"),
+    fragment: static_file!("shaders/clouds_composite.f.glsl")
+});
+
+/// The number of texels along each side of the cloud noise texture
+pub const CLOUD_NOISE_SIZE: u32 = 32;
+
+/// A well mixed hash of a lattice point
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed
+        .wrapping_add((x as u32).wrapping_mul(0x8da6_b343))
+        .wrapping_add((y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add((z as u32).wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+/// Billowy cellular noise from 0 to 1 that repeats every unit along each axis, with
+/// `cells` feature points across each repeat
+pub fn tiling_worley(p: [f32; 3], cells: u32, seed: u32) -> f32 {
+    let n = cells.max(1) as i32;
+    let q = [p[0] * n as f32, p[1] * n as f32, p[2] * n as f32];
+    let c = [q[0].floor() as i32, q[1].floor() as i32, q[2].floor() as i32];
+    let wrap = |i: i32| ((i % n) + n) % n;
+    let mut nearest = 1e9f32;
+    for dz in -1..2 {
+        for dy in -1..2 {
+            for dx in -1..2 {
+                let cell = [c[0] + dx, c[1] + dy, c[2] + dz];
+                let h = hash(wrap(cell[0]), wrap(cell[1]), wrap(cell[2]), seed);
+                let point = [
+                    cell[0] as f32 + (h & 0xff) as f32 / 255.,
+                    cell[1] as f32 + ((h >> 8) & 0xff) as f32 / 255.,
+                    cell[2] as f32 + ((h >> 16) & 0xff) as f32 / 255.,
+                ];
+                let d = (0..3).map(|i| (point[i] - q[i]).powi(2)).sum::<f32>();
+                nearest = nearest.min(d);
+            }
+        }
+    }
+    1. - nearest.sqrt().min(1.)
+}
+
+/// Cloud density noise for a texture `size` texels on each side, row by row and then layer
+/// by layer. Three octaves of cellular noise give puffy shapes that tile seamlessly.
+pub fn cloud_noise(size: u32, seed: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity((size * size * size) as usize);
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let p = [x as f32 / size as f32, y as f32 / size as f32, z as f32 / size as f32];
+                let v = tiling_worley(p, 4, seed) * 0.625
+                    + tiling_worley(p, 8, seed + 1) * 0.25
+                    + tiling_worley(p, 16, seed + 2) * 0.125;
+                data.push((v.max(0.).min(1.) * 255. + 0.5) as u8);
+            }
+        }
+    }
+    data
+}
+
+/// A layer of clouds drawn by ray marching through tiling noise, lit by the sun with single
+/// scattering (Henyey-Greenstein) and by an ambient sky color. The clouds are marched at half
+/// the width and height of the color target, then composited over the pixels where nothing
+/// has been drawn yet (still at the far plane of the depth buffer), so draw them after
+/// opaque geometry. Call `resize` with the size of the color target before drawing.
+pub struct VolumetricClouds<R: Resources> {
+    /// How much of the sky is covered, from 0 to 1
+    pub coverage: f32,
+    /// How thick the clouds are, as the fraction of light absorbed per meter
+    pub density: f32,
+    /// The width of one repeat of the noise in meters, larger for bigger clouds
+    pub noise_scale: f32,
+    /// The heights of the bottom and top of the cloud layer
+    pub altitude: [f32; 2],
+    /// The drift of the clouds in meters per second
+    pub wind: Vector3<f32>,
+    /// The light coming from the rest of the sky
+    pub ambient: [f32; 3],
+    /// How much the clouds scatter light forward, from 0 (evenly) to almost 1
+    pub eccentricity: f32,
+    /// The exposure scenes are drawn with, see `UberInputs::set_exposure`
+    pub exposure: f32,
+    time: f32,
+    noise: Texture<R, (R8, Unorm)>,
+    buffer: Option<OffscreenTarget<R>>,
+    march_pso: PipelineState<R, march::Meta>,
+    composite_pso: PipelineState<R, composite::Meta>,
+    params: Buffer<R, CloudBlock>,
+    composite_params: Buffer<R, CompositeBlock>,
+}
+
+/// The clip space to world space matrix of an eye, without the halving of x done by the
+/// transform shader
+pub(super) fn inverse_view_proj(eye: &EyeParams) -> Matrix4<f32> {
+    eye.view_proj()
+        .try_inverse()
+        .unwrap_or(Matrix4::identity())
+}
+
+impl<R: Resources> VolumetricClouds<R> {
+    /// Create a cloud layer with the given coverage, density and noise scale (see the fields
+    /// of the same names)
+    pub fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        coverage: f32,
+        density: f32,
+        noise_scale: f32,
+    )
+        -> Result<VolumetricClouds<R>, Error>
+    {
+        use gfx::texture::*;
+        let size = CLOUD_NOISE_SIZE;
+        let data = cloud_noise(size, 0);
+        let (_, view) = f.create_texture_immutable_u8::<(R8, Unorm)>(
+            Kind::D3(size as u16, size as u16, size as u16),
+            Mipmap::Provided,
+            &[&data[..]],
+        )?;
+        let noise = Texture {
+            buffer: view,
+            sampler: f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Tile)),
+        };
+        let march = march_shader(f)?;
+        let composite = composite_shader(f)?;
+        Ok(VolumetricClouds {
+            coverage: coverage,
+            density: density,
+            noise_scale: noise_scale,
+            altitude: [1500., 3000.],
+            wind: Vector3::new(10., 0., 3.),
+            ambient: [0.3, 0.35, 0.45],
+            eccentricity: 0.6,
+            exposure: 1.,
+            time: 0.,
+            noise: noise,
+            buffer: None,
+            march_pso: f.create_pipeline_state(&march, Primitive::TriangleList, Rasterizer::new_fill(), march::new())?,
+            composite_pso: f.create_pipeline_state(&composite, Primitive::TriangleList, Rasterizer::new_fill(), composite::new())?,
+            params: f.create_constant_buffer(1),
+            composite_params: f.create_constant_buffer(1),
+        })
+    }
+
+    /// Match the size of the color target the clouds are drawn over, and fire
+    /// `TargetsResized` on `events`
+    pub fn resize<F: Factory<R>>(&mut self, f: &mut F, events: &mut RenderEventBus, width: u16, height: u16) -> Result<(), Error> {
+        self.buffer = Some(OffscreenTarget::new(f, (width / 2).max(1), (height / 2).max(1))?);
+        events.fire(RenderEvent::TargetsResized(width, height));
+        Ok(())
+    }
+
+    /// Move the clouds along with the wind by `dt` seconds
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    /// Draw the clouds, lit by the sun of `env`, behind everything on the color target of
+    /// `ctx`. Nothing is drawn until `resize` has been called.
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, env: &UberEnv<R>) {
+        profile_scope!("clouds");
+        let buffer = match self.buffer {
+            Some(ref b) => b,
+            None => return,
+        };
+        let slice = Slice {
+            start: 0,
+            end: 3,
+            base_vertex: 0,
+            instances: None,
+            buffer: IndexBuffer::Auto,
+        };
+        let sun = env.sun_rotation * Vector3::z();
+        let c = env.sun_color;
+        let shape = self.wind * self.time;
+        // the detail drifts a little faster than the shapes, so they change as they move
+        let detail = self.wind * self.time * 1.5 + Vector3::new(0., self.time * 0.5, 0.);
+        let a = self.ambient;
+
+        ctx.encoder.clear(&buffer.color, [0., 0., 0., 0.]);
+        let eyes = ctx.eyes();
+        for eye in &eyes {
+            if eye.clip.w == 0 || eye.clip.h == 0 { continue }
+            let clip = Rect { x: eye.clip.x / 2, y: eye.clip.y / 2, w: (eye.clip.w / 2).max(1), h: (eye.clip.h / 2).max(1) };
+            ctx.encoder.update_constant_buffer(&self.params, &CloudBlock {
+                inv_view_proj: inverse_view_proj(eye).downgrade(),
+                eye_pos: eye.eye.to_homogeneous().downgrade(),
+                viewport: [clip.x as f32, clip.y as f32, clip.w as f32, clip.h as f32],
+                sun_dir: sun.to_homogeneous().downgrade(),
+                sun_color: [c[0] * c[3], c[1] * c[3], c[2] * c[3], 1.],
+                ambient: [a[0], a[1], a[2], 0.],
+                layer: [self.altitude[0], self.altitude[1], self.coverage, self.density],
+                wind: [shape.x, shape.y, shape.z, 1. / self.noise_scale.max(1e-3)],
+                detail_wind: [detail.x, detail.y, detail.z, self.eccentricity],
+                exposure: self.exposure,
+                gamma: ::OUTPUT_GAMMA,
+            });
+            ctx.encoder.draw(&slice, &self.march_pso, &march::Data {
+                params: self.params.clone(),
+                scissor: clip,
+                color: buffer.color.clone(),
+                noise: self.noise.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+
+        let full = [buffer.width as f32 * 2., buffer.height as f32 * 2., 0., 0.];
+        ctx.encoder.update_constant_buffer(&self.composite_params, &CompositeBlock {
+            target_size: full,
+        });
+        for eye in &eyes {
+            ctx.encoder.draw(&slice, &self.composite_pso, &composite::Data {
+                params: self.composite_params.clone(),
+                scissor: eye.clip,
+                color: ctx.color.clone(),
+                depth: ctx.depth.clone(),
+                clouds: buffer.texture.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+}
+
+/// A value keyed on the hour of day (0 to 24), following a Catmull-Rom spline through the
+/// keys that wraps around midnight
+#[derive(Clone, Debug, PartialEq)]
+pub struct HourCurve<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T> HourCurve<T>
+    where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{
+    /// Create a curve through `(hour, value)` keys, which must not be empty. Hours are
+    /// wrapped into a day and sorted.
+    pub fn new(mut keys: Vec<(f32, T)>) -> Result<HourCurve<T>, Error> {
+        ensure!(!keys.is_empty(), FlightError::InvalidShape { reason: "a curve needs at least one key" });
+        for k in &mut keys {
+            k.0 = wrap_hour(k.0);
+        }
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+        Ok(HourCurve { keys: keys })
+    }
+
+    /// A curve with the same value all day
+    pub fn constant(value: T) -> HourCurve<T> {
+        HourCurve { keys: vec![(0., value)] }
+    }
+
+    /// The keys of the curve, in order of the hour
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    /// The value at `hour`, which may be outside of 0 to 24
+    pub fn at(&self, hour: f32) -> T {
+        let n = self.keys.len();
+        if n == 1 {
+            return self.keys[0].1;
+        }
+        let h = wrap_hour(hour);
+        let (i, h) = match self.keys.iter().rposition(|k| k.0 <= h) {
+            Some(i) => (i as isize, h),
+            // before the first key, so after the last key of the day before
+            None => (n as isize - 1, h + 24.),
+        };
+        // keys past either end of the day come from the day before or after
+        let key = |j: isize| {
+            let wrapped = ((j % n as isize) + n as isize) % n as isize;
+            let days = (j - wrapped) / n as isize;
+            let (t, v) = self.keys[wrapped as usize];
+            (t + 24. * days as f32, v)
+        };
+        let (t0, p0) = key(i - 1);
+        let (t1, p1) = key(i);
+        let (t2, p2) = key(i + 1);
+        let (t3, p3) = key(i + 2);
+        let dt = (t2 - t1).max(1e-6);
+        let f = (h - t1) / dt;
+        // tangents scaled to the segment, for keys that aren't evenly spaced
+        let m1 = (p2 - p0) * (dt / (t2 - t0).max(1e-6));
+        let m2 = (p3 - p1) * (dt / (t3 - t1).max(1e-6));
+        let (f2, f3) = (f * f, f * f * f);
+        p1 * (2. * f3 - 3. * f2 + 1.)
+            + m1 * (f3 - 2. * f2 + f)
+            + p2 * (3. * f2 - 2. * f3)
+            + m2 * (f3 - f2)
+    }
+}
+
+fn wrap_hour(hour: f32) -> f32 {
+    ((hour % 24.) + 24.) % 24.
+}
+
+/// The color of a black body at `kelvin`, scaled so the brightest channel is 1. Daylight
+/// is about 5800 K at midday, and sunlight near the horizon about 2000 K.
+pub fn kelvin_color(kelvin: f32) -> Vector3<f32> {
+    // fit to the Planckian locus in sRGB, good from 1000 to 40000 K
+    let t = kelvin.max(1000.).min(40000.) / 100.;
+    let r = if t <= 66. { 255. } else { 329.699 * (t - 60.).powf(-0.133_205) };
+    let g = if t <= 66. {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122 * (t - 60.).powf(-0.075_514_846)
+    };
+    let b = if t >= 66. {
+        255.
+    } else if t <= 19. {
+        0.
+    } else {
+        138.517_73 * (t - 10.).ln() - 305.044_8
+    };
+    let c = Vector3::new(r, g, b).map(|c| (c / 255.).max(0.).min(1.).powf(::OUTPUT_GAMMA));
+    c / c.x.max(c.y).max(c.z)
+}
+
+/// The direction towards the sun at `hour` of local solar time, at a `latitude` (degrees,
+/// north positive) and solar `declination` (degrees, 0 at the equinoxes and +-23.44 at the
+/// solstices). East is +X, up is +Y and north is -Z.
+pub fn sun_direction(hour: f32, latitude: f32, declination: f32) -> Vector3<f32> {
+    let h = (hour - 12.) * 15f32.to_radians();
+    let (lat, dec) = (latitude.to_radians(), declination.to_radians());
+    let east = -dec.cos() * h.sin();
+    let north = dec.sin() * lat.cos() - dec.cos() * h.cos() * lat.sin();
+    let up = dec.sin() * lat.sin() + dec.cos() * h.cos() * lat.cos();
+    Vector3::new(east, up, -north)
+}
+
+/// Lighting that changes over a day, evaluated by the hour and given to the uber style with
+/// `apply`, so that time of day doesn't need the sun and exposure set by hand each frame.
+/// `fog_color` and `irradiance_tint` aren't used by the uber style and are for the caller,
+/// such as for the clear color or the ambient light of `VolumetricClouds`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeOfDayCurve {
+    /// Exposure compensation in stops, applied as `2^ev` with `UberInputs::set_exposure`
+    pub exposure_ev: HourCurve<f32>,
+    /// The color of distant haze
+    pub fog_color: HourCurve<Vector3<f32>>,
+    /// The color temperature of sunlight
+    pub sun_color_kelvin: HourCurve<f32>,
+    /// A color to multiply light from the sky by
+    pub irradiance_tint: HourCurve<Vector3<f32>>,
+    /// Where the sun is, see `sun_direction`
+    pub latitude: f32,
+    pub declination: f32,
+}
+
+impl TimeOfDayCurve {
+    /// Curves following the sun over a day at `latitude` and `declination` (see
+    /// `sun_direction`), keyed every hour from approximate measurements of daylight by sun
+    /// elevation: exposure rises about 4 stops from midday into night, sunlight reddens from
+    /// 5800 K to 2000 K at the horizon, and the sky turns blue through twilight.
+    pub fn realistic(latitude: f32, declination: f32) -> TimeOfDayCurve {
+        // (sun elevation in degrees, exposure, kelvin, fog, tint)
+        let table: [(f32, f32, f32, [f32; 3], [f32; 3]); 7] = [
+            (-18., 4., 2000., [0.02, 0.03, 0.06], [0.1, 0.13, 0.25]),
+            (-6., 3., 2000., [0.12, 0.14, 0.25], [0.45, 0.55, 0.9]),
+            (0., 1.5, 2200., [0.85, 0.55, 0.4], [1., 0.75, 0.6]),
+            (5., 1., 3000., [0.9, 0.7, 0.55], [1., 0.85, 0.72]),
+            (15., 0.5, 4300., [0.75, 0.78, 0.85], [1., 0.95, 0.9]),
+            (30., 0., 5300., [0.7, 0.78, 0.9], [1., 1., 1.]),
+            (60., 0., 5800., [0.65, 0.76, 0.92], [1., 1., 1.]),
+        ];
+        let lerp = |e: f32| {
+            let i = table.iter().rposition(|r| r.0 <= e).unwrap_or(0).min(table.len() - 2);
+            let (a, b) = (table[i], table[i + 1]);
+            let f = ((e - a.0) / (b.0 - a.0)).max(0.).min(1.);
+            let mix = |x: [f32; 3], y: [f32; 3]| Vector3::new(x[0], x[1], x[2]) * (1. - f) + Vector3::new(y[0], y[1], y[2]) * f;
+            (a.1 + (b.1 - a.1) * f, a.2 + (b.2 - a.2) * f, mix(a.3, b.3), mix(a.4, b.4))
+        };
+        let samples: Vec<(f32, (f32, f32, Vector3<f32>, Vector3<f32>))> = (0..24)
+            .map(|h| {
+                let h = h as f32;
+                let elevation = sun_direction(h, latitude, declination).y.max(-1.).min(1.).asin().to_degrees();
+                (h, lerp(elevation))
+            })
+            .collect();
+        fn curve<T>(keys: Vec<(f32, T)>) -> HourCurve<T> {
+            HourCurve { keys: keys }
+        }
+        TimeOfDayCurve {
+            exposure_ev: curve(samples.iter().map(|&(h, s)| (h, s.0)).collect()),
+            sun_color_kelvin: curve(samples.iter().map(|&(h, s)| (h, s.1)).collect()),
+            fog_color: curve(samples.iter().map(|&(h, s)| (h, s.2)).collect()),
+            irradiance_tint: curve(samples.iter().map(|&(h, s)| (h, s.3)).collect()),
+            latitude: latitude,
+            declination: declination,
+        }
+    }
+
+    /// The rotation of `UberEnv::sun_rotation` at `hour`, turning +Z towards the sun
+    pub fn sun_rotation(&self, hour: f32) -> Rotation3<f32> {
+        let to_sun = sun_direction(hour, self.latitude, self.declination);
+        Rotation3::rotation_between(&Vector3::z(), &to_sun)
+            .unwrap_or(Rotation3::from_axis_angle(&Vector3::x_axis(), ::std::f32::consts::PI))
+    }
+
+    /// Set the exposure, sun direction and sun color of `inputs` for `hour`, keeping the
+    /// brightness of the sun
+    pub fn apply<R: Resources>(&self, inputs: &mut UberInputs<R>, hour: f32) {
+        inputs.set_exposure(2f32.powf(self.exposure_ev.at(hour)));
+        let rotation = self.sun_rotation(hour);
+        let c = kelvin_color(self.sun_color_kelvin.at(hour));
+        let env = inputs.mut_env();
+        env.sun_rotation = rotation;
+        env.sun_color = [c.x, c.y, c.z, env.sun_color[3]];
+    }
+}
+
+#[test]
+fn time_of_day() {
+    // keys unevenly spaced and out of order, wrapping around midnight
+    let curve = HourCurve::new(vec![(18., 2.), (6., 0.), (12., 1.), (26., 4.)]).unwrap();
+    assert_eq!(curve.keys()[0], (2., 4.));
+    for &(h, v) in curve.keys() {
+        assert!(relative_eq!(curve.at(h), v, epsilon = 1e-5));
+        assert!(relative_eq!(curve.at(h + 24.), v, epsilon = 1e-5));
+    }
+    // continuous across midnight
+    assert!(relative_eq!(curve.at(23.999), curve.at(0.), epsilon = 1e-2));
+    assert_eq!(HourCurve::constant(3.).at(7.), 3.);
+    assert!(HourCurve::<f32>::new(vec![]).is_err());
+
+    let noon = sun_direction(12., 0., 0.);
+    assert!(relative_eq!(noon, Vector3::y(), epsilon = 1e-5));
+    // rises in the east, and stands to the south at midday in the north
+    assert!(sun_direction(6.5, 0., 0.).x > 0.);
+    assert!(sun_direction(12., 50., 0.).z > 0.);
+    assert!(sun_direction(0., 50., 0.).y < 0.);
+
+    let warm = kelvin_color(2000.);
+    let white = kelvin_color(6600.);
+    assert!(warm.x > warm.z && relative_eq!(warm.x, 1.));
+    assert!(white.z > 0.9 && white.x > 0.9);
+
+    let day = TimeOfDayCurve::realistic(45., 0.);
+    assert!(day.exposure_ev.at(12.) < day.exposure_ev.at(18.5));
+    assert!(day.exposure_ev.at(18.5) < day.exposure_ev.at(0.));
+    assert!(day.sun_color_kelvin.at(12.) > day.sun_color_kelvin.at(7.));
+    let to_sun = day.sun_rotation(12.) * Vector3::z();
+    assert!(relative_eq!(to_sun, sun_direction(12., 45., 0.), epsilon = 1e-5));
+}
+
+#[test]
+fn cloud_noise_tiles() {
+    // the noise wraps around every unit
+    for &p in &[[0.1, 0.2, 0.3], [0.95, 0.5, 0.02], [0.4, 0.99, 0.7]] {
+        let a = tiling_worley(p, 4, 7);
+        let b = tiling_worley([p[0] + 1., p[1] - 1., p[2] + 2.], 4, 7);
+        assert!(relative_eq!(a, b, epsilon = 1e-4));
+        assert!(a >= 0. && a <= 1.);
+    }
+    let noise = cloud_noise(8, 0);
+    assert_eq!(noise.len(), 8 * 8 * 8);
+    // not flat
+    let (lo, hi) = (noise.iter().min().unwrap(), noise.iter().max().unwrap());
+    assert!(hi - lo > 64);
+}
@@ -14,7 +14,7 @@ gfx_defines!{
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
         scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
         color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
     }
 }
 
@@ -47,6 +47,7 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
     type Vertex = VertC;
     type Inputs = SolidInputs<R>;
     type Material = ();
+    type Bound = pl::Data<R>;
 
     fn new<F: Factory<R> + FactoryExt<R>>(
         f: &mut F,
@@ -69,16 +70,30 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
         })
     }
 
-    fn draw_raw<C>(
+    fn bind(
         &self,
-        inputs: &mut SolidInputs<R>,
-        enc: &mut Encoder<R, C>,
+        inputs: &SolidInputs<R>,
         color: TargetRef<R>,
         depth: DepthRef<R>,
-        scissor: Rect,
-        slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         _: &(),
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut SolidInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
     )
         -> Result<(), Error>
         where C: CommandBuffer<R>
@@ -86,13 +101,8 @@ impl<R: Resources> Style<R> for SolidStyle<R> {
         if let Some(t) = inputs.transform.take() {
             enc.update_constant_buffer(&inputs.transform_block, &t);
         }
-        enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
-            verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
-        });
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
         Ok(())
     }
 }
\ No newline at end of file
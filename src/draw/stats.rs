@@ -0,0 +1,274 @@
+use gfx::{Resources, CommandBuffer, Primitive, Slice, IndexBuffer};
+use gfx::buffer::Role;
+use gfx::memory::{Bind, Usage};
+use gfx::handle::Buffer;
+use gfx::traits::FactoryExt;
+use nalgebra::{Vector3, Matrix4, Transform3};
+use std::time::Duration;
+
+use super::{Painter, SolidStyle, DrawParams};
+use ::mesh::{Mesh, VertC, Aabb};
+use ::Error;
+
+/// Timing and workload measurements of recent frames
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    /// Frames per second, smoothed over recent frames
+    pub fps: f32,
+    /// Time spent recording a frame on the CPU in milliseconds, smoothed over recent frames
+    pub cpu_ms: f32,
+    /// Time the GPU spent on a frame in milliseconds. gfx has no timer queries, so this
+    /// must be filled in from the compositor (e.g. OpenVR's frame timing).
+    pub gpu_ms: f32,
+    /// Draw calls in the last frame
+    pub draw_calls: usize,
+    /// Scale applied to the recommended render target size
+    pub resolution_scale: f32,
+    /// Frames that missed the display refresh
+    pub dropped_frames: u64,
+    /// The display refresh rate
+    pub target_hz: f32,
+    /// Frames are drawn every other refresh, see `vr::FrameGovernor`
+    pub half_rate: bool,
+}
+
+fn millis(d: Duration) -> f32 {
+    d.as_secs() as f32 * 1000. + d.subsec_nanos() as f32 / 1_000_000.
+}
+
+impl FrameStats {
+    /// Create empty statistics for a display with the given refresh rate
+    pub fn new(target_hz: f32) -> FrameStats {
+        FrameStats {
+            fps: 0.,
+            cpu_ms: 0.,
+            gpu_ms: 0.,
+            draw_calls: 0,
+            resolution_scale: 1.,
+            dropped_frames: 0,
+            target_hz: target_hz,
+            half_rate: false,
+        }
+    }
+
+    /// Record a finished frame. `interval` is the time since the previous frame began and
+    /// `cpu` is the time spent recording this one. Frames taking more than one and a half
+    /// refresh intervals count as dropped, or three and a half at half rate.
+    pub fn record(&mut self, interval: Duration, cpu: Duration, draw_calls: usize) {
+        let interval = millis(interval);
+        let fps = if interval > 0. { 1000. / interval } else { 0. };
+        if self.fps == 0. {
+            self.fps = fps;
+            self.cpu_ms = millis(cpu);
+        } else {
+            self.fps += (fps - self.fps) * 0.1;
+            self.cpu_ms += (millis(cpu) - self.cpu_ms) * 0.1;
+        }
+        let refreshes = if self.half_rate { 3.5 } else { 1.5 };
+        if interval > refreshes * 1000. / self.target_hz {
+            self.dropped_frames += 1;
+        }
+        self.draw_calls = draw_calls;
+    }
+}
+
+/// The measurements shown by a `StatsOverlay`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StatsFields {
+    pub fps: bool,
+    pub cpu: bool,
+    pub gpu: bool,
+    pub draw_calls: bool,
+    pub resolution_scale: bool,
+    pub dropped_frames: bool,
+    pub pacing: bool,
+}
+
+impl Default for StatsFields {
+    fn default() -> StatsFields {
+        StatsFields {
+            fps: true,
+            cpu: true,
+            gpu: true,
+            draw_calls: true,
+            resolution_scale: true,
+            dropped_frames: true,
+            pacing: true,
+        }
+    }
+}
+
+impl StatsFields {
+    fn format(&self, stats: &FrameStats) -> String {
+        let mut lines = Vec::new();
+        if self.fps { lines.push(format!("FPS {:.1}", stats.fps)) }
+        if self.cpu { lines.push(format!("CPU {:.2} MS", stats.cpu_ms)) }
+        if self.gpu { lines.push(format!("GPU {:.2} MS", stats.gpu_ms)) }
+        if self.draw_calls { lines.push(format!("DRAWS {}", stats.draw_calls)) }
+        if self.resolution_scale { lines.push(format!("RES {:.2}", stats.resolution_scale)) }
+        if self.dropped_frames { lines.push(format!("DROP {}", stats.dropped_frames)) }
+        if self.pacing { lines.push(format!("PACE {}", if stats.half_rate { "HALF" } else { "FULL" })) }
+        lines.join("\n")
+    }
+}
+
+/// Line segments of a glyph on a 4x8 grid, with the origin at the bottom left
+fn glyph(c: char) -> &'static [[u8; 4]] {
+    match c {
+        '0' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 8]],
+        '1' => &[[2, 0, 2, 8], [2, 8, 0, 6], [0, 0, 4, 0]],
+        '2' => &[[0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4], [0, 4, 0, 0], [0, 0, 4, 0]],
+        '3' => &[[0, 8, 4, 8], [4, 8, 4, 0], [4, 0, 0, 0], [0, 4, 4, 4]],
+        '4' => &[[0, 8, 0, 4], [0, 4, 4, 4], [4, 8, 4, 0]],
+        '5' => &[[4, 8, 0, 8], [0, 8, 0, 4], [0, 4, 4, 4], [4, 4, 4, 0], [4, 0, 0, 0]],
+        '6' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 4], [4, 4, 0, 4]],
+        '7' => &[[0, 8, 4, 8], [4, 8, 2, 0]],
+        '8' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0], [0, 4, 4, 4]],
+        '9' => &[[4, 4, 0, 4], [0, 4, 0, 8], [0, 8, 4, 8], [4, 8, 4, 0], [4, 0, 0, 0]],
+        'A' => &[[0, 0, 0, 6], [0, 6, 2, 8], [2, 8, 4, 6], [4, 6, 4, 0], [0, 4, 4, 4]],
+        'C' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0]],
+        'D' => &[[0, 0, 0, 8], [0, 8, 2, 8], [2, 8, 4, 6], [4, 6, 4, 2], [4, 2, 2, 0], [2, 0, 0, 0]],
+        'E' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [0, 4, 2, 4]],
+        'F' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 4, 2, 4]],
+        'G' => &[[4, 8, 0, 8], [0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 4], [4, 4, 2, 4]],
+        'H' => &[[0, 0, 0, 8], [4, 0, 4, 8], [0, 4, 4, 4]],
+        'L' => &[[0, 8, 0, 0], [0, 0, 4, 0]],
+        'M' => &[[0, 0, 0, 8], [0, 8, 2, 4], [2, 4, 4, 8], [4, 8, 4, 0]],
+        'O' => &[[0, 0, 4, 0], [4, 0, 4, 8], [4, 8, 0, 8], [0, 8, 0, 0]],
+        'P' => &[[0, 0, 0, 8], [0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4]],
+        'R' => &[[0, 0, 0, 8], [0, 8, 4, 8], [4, 8, 4, 4], [4, 4, 0, 4], [2, 4, 4, 0]],
+        'S' => &[[4, 8, 0, 8], [0, 8, 0, 4], [0, 4, 4, 4], [4, 4, 4, 0], [4, 0, 0, 0]],
+        'U' => &[[0, 8, 0, 0], [0, 0, 4, 0], [4, 0, 4, 8]],
+        'W' => &[[0, 8, 0, 0], [0, 0, 2, 4], [2, 4, 4, 0], [4, 0, 4, 8]],
+        '-' => &[[0, 4, 4, 4]],
+        '.' => &[[2, 0, 2, 1]],
+        ':' => &[[2, 2, 2, 3], [2, 5, 2, 6]],
+        _ => &[],
+    }
+}
+
+const GLYPH_ADVANCE: f32 = 6.;
+const LINE_HEIGHT: f32 = 12.;
+
+/// Lay out text as a line list in glyph grid units, with the first line below the origin
+fn layout_text(text: &str, color: [f32; 3], verts: &mut Vec<VertC>) {
+    for (row, line) in text.lines().enumerate() {
+        let y = -(row as f32 + 1.) * LINE_HEIGHT;
+        for (col, c) in line.chars().enumerate() {
+            let x = col as f32 * GLYPH_ADVANCE;
+            for s in glyph(c.to_ascii_uppercase()) {
+                verts.push(VertC { pos: [x + s[0] as f32, y + s[1] as f32, 0.], color: color });
+                verts.push(VertC { pos: [x + s[2] as f32, y + s[3] as f32, 0.], color: color });
+            }
+        }
+    }
+}
+
+/// A head-locked panel showing frame statistics, for performance work in the headset.
+/// The text is only rebuilt when it changes and is drawn with a single line list
+/// through a `SolidStyle` painter (which must be set up for `Primitive::LineList`).
+pub struct StatsOverlay<R: Resources> {
+    /// Position of the top left corner of the panel in view space, in meters
+    pub position: Vector3<f32>,
+    /// Height of a character in meters
+    pub scale: f32,
+    pub color: [f32; 3],
+    pub fields: StatsFields,
+    text: String,
+    verts: Vec<VertC>,
+    buf: Option<Buffer<R, VertC>>,
+}
+
+impl<R: Resources> StatsOverlay<R> {
+    /// Create an overlay in the lower left periphery of the view
+    pub fn new() -> StatsOverlay<R> {
+        StatsOverlay {
+            position: Vector3::new(-0.15, -0.12, -0.6),
+            scale: 0.008,
+            color: [0.2, 1., 0.2],
+            fields: Default::default(),
+            text: String::new(),
+            verts: Vec::new(),
+            buf: None,
+        }
+    }
+
+    /// Draw the given statistics
+    pub fn draw<F, C>(
+        &mut self,
+        f: &mut F,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, SolidStyle<R>>,
+        stats: &FrameStats,
+    )
+        -> Result<(), Error>
+        where F: FactoryExt<R>, C: CommandBuffer<R>
+    {
+        let text = self.fields.format(stats);
+        if self.buf.is_none() || text != self.text {
+            self.verts.clear();
+            layout_text(&text, self.color, &mut self.verts);
+            let capacity = self.buf.as_ref().map(|b| b.len()).unwrap_or(0);
+            if self.verts.len() > capacity {
+                let size = self.verts.len().next_power_of_two().max(256);
+                self.buf = Some(f.create_buffer(size, Role::Vertex, Usage::Dynamic, Bind::empty())?);
+            }
+            if let Some(ref buf) = self.buf {
+                ctx.encoder.update_buffer(buf, &self.verts, 0)?;
+            }
+            self.text = text;
+        }
+        let buf = match self.buf {
+            Some(ref b) if !self.verts.is_empty() => b.clone(),
+            _ => return Ok(()),
+        };
+
+        let mesh = Mesh {
+            slice: Slice {
+                start: 0,
+                end: self.verts.len() as u32,
+                base_vertex: 0,
+                instances: None,
+                buffer: IndexBuffer::Auto,
+            },
+            buf: buf,
+            prim: Primitive::LineList,
+            bounds: Aabb::empty(),
+            mat: (),
+        };
+        let model = Matrix4::new_translation(&self.position) * Matrix4::new_scaling(self.scale / 8.);
+        painter.try_draw_head_locked(ctx, Transform3::from_matrix_unchecked(model), &mesh)
+    }
+}
+
+impl<R: Resources> Default for StatsOverlay<R> {
+    fn default() -> StatsOverlay<R> {
+        StatsOverlay::new()
+    }
+}
+
+#[test]
+fn frame_stats() {
+    let mut stats = FrameStats::new(90.);
+    for _ in 0..3 {
+        stats.record(Duration::from_millis(11), Duration::from_millis(4), 20);
+    }
+    assert_eq!(stats.dropped_frames, 0);
+    assert!(stats.fps > 85. && stats.fps < 95.);
+    stats.record(Duration::from_millis(30), Duration::from_millis(4), 25);
+    assert_eq!(stats.dropped_frames, 1);
+    assert_eq!(stats.draw_calls, 25);
+
+    let fields = StatsFields { fps: false, cpu: false, gpu: false, ..Default::default() };
+    assert_eq!(fields.format(&stats), "DRAWS 25\nRES 1.00\nDROP 1\nPACE FULL");
+    // two refreshes apart is on time at half rate
+    stats.half_rate = true;
+    stats.record(Duration::from_millis(22), Duration::from_millis(4), 25);
+    assert_eq!(stats.dropped_frames, 1);
+
+    let mut verts = Vec::new();
+    layout_text("fps 1\n-", [1.; 3], &mut verts);
+    assert_eq!(verts.len(), (3 + 4 + 5 + 3 + 1) * 2);
+    // the second line sits below the first
+    assert_eq!(verts[verts.len() - 1].pos, [4., -24. + 4., 0.]);
+}
@@ -0,0 +1,88 @@
+use gfx::{Resources, Factory, Rect};
+use gfx::format::{Formatted, Swizzle};
+use gfx::traits::FactoryExt;
+use gfx::handle::Texture as RawTexture;
+use nalgebra::{self as na, Orthographic3, Point3, Transform3};
+
+use super::{EyeParams, double_x};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture, DEPTH_PRECISION};
+
+/// A color and depth target that can be drawn into and then sampled as a texture
+#[derive(Clone)]
+pub struct OffscreenTarget<R: Resources> {
+    pub color: TargetRef<R>,
+    pub depth: DepthRef<R>,
+    /// The contents of the color target
+    pub texture: Texture<R, ColorFormat>,
+    /// The texture behind `color`, for copies such as `capture_frame_rgba8`
+    pub color_texture: RawTexture<R, <ColorFormat as Formatted>::Surface>,
+    /// The contents of the depth target, if it was created with `with_depth_texture`
+    pub depth_texture: Option<Texture<R, DepthFormat>>,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl<R: Resources> OffscreenTarget<R> {
+    /// Create a target with the given size in pixels
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16) -> Result<OffscreenTarget<R>, Error> {
+        use gfx::format::ChannelTyped;
+        use gfx::texture::{AaMode, Kind};
+        use gfx::memory::{Bind, Usage};
+
+        // like `create_render_target`, but the color can also be copied (see `color_texture`)
+        let kind = Kind::D2(width, height, AaMode::Single);
+        let bind = Bind::SHADER_RESOURCE | Bind::RENDER_TARGET | Bind::TRANSFER_SRC | Bind::TRANSFER_DST;
+        let channel = <<ColorFormat as Formatted>::Channel as ChannelTyped>::get_channel_type();
+        let raw = f.create_texture(kind, 1, bind, Usage::Data, Some(channel))?;
+        let view = f.view_texture_as_shader_resource::<ColorFormat>(&raw, (0, 0), Swizzle::new())?;
+        let color = f.view_texture_as_render_target(&raw, 0, None)?;
+        let depth = f.create_depth_stencil_view_only::<DepthFormat>(width, height)?;
+        Ok(OffscreenTarget {
+            color: color,
+            depth: depth,
+            texture: Texture {
+                buffer: view,
+                sampler: f.create_sampler_linear(),
+            },
+            color_texture: raw,
+            depth_texture: None,
+            width: width,
+            height: height,
+        })
+    }
+
+    /// Create a target with the given size in pixels whose depth can also be sampled, for
+    /// effects that compare against the depth of what was drawn (such as soft shorelines)
+    pub fn with_depth_texture<F: Factory<R>>(f: &mut F, width: u16, height: u16) -> Result<OffscreenTarget<R>, Error> {
+        use gfx::texture::*;
+        use gfx::memory::{Bind, Usage};
+
+        let mut target = OffscreenTarget::new(f, width, height)?;
+        let kind = Kind::D2(width, height, AaMode::Single);
+        let bind = Bind::SHADER_RESOURCE | Bind::DEPTH_STENCIL;
+        let tex = f.create_texture(kind, 1, bind, Usage::Data, Some(DEPTH_PRECISION.channel_type()))?;
+        target.depth = f.view_texture_as_depth_stencil_trivial(&tex)?;
+        target.depth_texture = Some(Texture {
+            buffer: f.view_texture_as_shader_resource::<DepthFormat>(&tex, (0, 0), Swizzle::new())?,
+            sampler: f.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp)),
+        });
+        Ok(target)
+    }
+
+    /// Eye parameters for drawing 2D content in pixel coordinates (the origin at the top
+    /// left corner, +Y down) across the whole target. Depth runs from -1 to 1.
+    pub fn pixel_eye(&self) -> EyeParams {
+        let (w, h) = (self.width as f32, self.height as f32);
+        // Pixel row 0 goes to the bottom of clip space, which is the first row of the texture,
+        // so that meshes show the target the same way up as loaded images.
+        let ortho = Orthographic3::new(0., w, 0., h, -1., 1.);
+        let proj = double_x(ortho.to_homogeneous());
+        EyeParams {
+            eye: Point3::origin(),
+            view: na::one(),
+            proj: Transform3::from_matrix_unchecked(proj),
+            clip_offset: 0.,
+            clip: Rect { x: 0, y: 0, w: self.width, h: self.height },
+        }
+    }
+}
@@ -1,40 +1,138 @@
 use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
 use gfx::pso::PipelineState;
 use gfx::traits::FactoryExt;
-use gfx::handle::{Buffer, DepthStencilView};
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, DepthStencilView, RawShaderResourceView, RenderTargetView};
 use gfx::state::Rasterizer;
 use gfx::format::*;
 
 use nalgebra::{self as na, Rotation3, Vector3, Matrix4};
 
-use super::{StyleInputs, Style, TransformBlock};
+use super::{StyleInputs, Style, DepthBiasStyle, TransformBlock, FrameRingBuffer, FrameCounter};
+use super::gi::{self, VoxelGrid, VoxelVolumes, VoxelFormat};
 use ::mesh::{Primitive, MeshSource, Mesh, Indexing, Vert, VertNTT};
+use ::mesh::gen::Surface;
 use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+use ::light::{AreaLight, AreaShape};
+use ::environment::{ProbeManager, ProbeContributions, ProbeBox};
+use ::terrain::SkyOcclusion;
 use ::util::NativeRepr;
+use ::load::ShaderCache;
 use std::mem::transmute;
+use std::sync::Arc;
+use std::ops::BitOr;
 
 pub type LumMapFormat = (R32_G32_B32, Float);
 
+/// The maximum number of area lights that can be simulated
+pub const AREA_LIGHT_COUNT: usize = 4;
+
+/// The most shader variants `UberStyle::precompile_all_variants` compiles, so that adding
+/// features can't make loading take exponentially longer
+pub const MAX_PRECOMPILE_VARIANTS: usize = 64;
+
+/// The pixel format of textures holding data rather than color (normals, knobs), which
+/// must never be decoded from sRGB
+pub type LinearFormat = (R8_G8_B8_A8, Unorm);
+
+/// The format of per-pixel motion written by `UberStyle`: the screen space (NDC) offset
+/// of each pixel since the previous frame
+pub type VelocityFormat = (R16_G16, Float);
+
+/// Channel types that are sampled without any color space conversion
+pub trait LinearChannel: ChannelTyped {}
+impl LinearChannel for Unorm {}
+impl LinearChannel for Inorm {}
+impl LinearChannel for Float {}
+
+/// Fails to compile if `LinearFormat` is changed to an sRGB format
+#[allow(dead_code)]
+fn assert_linear_slots() {
+    fn linear<T: Formatted>() where T::Channel: LinearChannel {}
+    linear::<LinearFormat>();
+}
+
 /// The collection of mesh textures used by physically based rendering
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct UberMaterial<R: Resources> {
     /// normal map
-    pub normal: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub normal: Texture<R, LinearFormat>,
     /// albedo map (base color)
     pub albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
-    /// metalness (1=metal, 0=dielectric), roughness, flatness (0=PBR, 1=flat color) map
-    pub knobs: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    /// metalness (1=metal, 0=dielectric), roughness, flatness (0=PBR, 1=flat color) map,
+    /// with alpha unused (`load::pack_knobs` keeps ambient occlusion there)
+    pub knobs: Texture<R, LinearFormat>,
+    /// bent normal (tangent space like `normal`) and ambient occlusion (alpha) map, from
+    /// `mesh::bent_normal_image` or a flat `[0x80, 0x80, 0xFF, 0xFF]` for none
+    pub bent: Texture<R, LinearFormat>,
+    /// analytic shape used for smooth per-pixel normals (`Surface::Mesh` for imported meshes)
+    pub surface: Surface,
+    /// how `normal` stores its vectors
+    pub normal_encoding: NormalEncoding,
+}
+
+/// The channels a normal map stores its vectors in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NormalEncoding {
+    /// All three components in red, green and blue
+    Rgb,
+    /// Only x and y in red and green, with z rebuilt in the shader (BC5 and other two
+    /// channel formats)
+    Rg,
+}
+
+impl Default for NormalEncoding {
+    fn default() -> NormalEncoding {
+        NormalEncoding::Rgb
+    }
 }
 
 gfx_defines!{
     constant ParamsBlock {
         sun_matrix: [[f32; 4]; 4] = "sun_matrix",
         sun_color: [f32; 4] = "sun_color",
+        probe_pos_a: [f32; 4] = "probe_pos_a",
+        probe_min_a: [f32; 4] = "probe_min_a",
+        probe_max_a: [f32; 4] = "probe_max_a",
+        probe_pos_b: [f32; 4] = "probe_pos_b",
+        probe_min_b: [f32; 4] = "probe_min_b",
+        probe_max_b: [f32; 4] = "probe_max_b",
+        probe_levels: [f32; 4] = "probe_levels",
+        sun_cookie: [f32; 4] = "sun_cookie",
+        voxel_min: [f32; 4] = "voxel_min",
+        voxel_params: [f32; 4] = "voxel_params",
+        sky_occlusion_rect: [f32; 4] = "sky_occlusion_rect",
+        sky_occlusion: f32 = "sky_occlusion",
         sun_in_env: f32 = "sun_in_env",
         radiance_levels: i32 = "radiance_levels",
 
         gamma: f32 = "gamma",
         exposure: f32 = "exposure",
+        probe_blend: f32 = "probe_blend",
+        shadow_depth_bias: f32 = "shadow_depth_bias",
+    }
+
+    constant PreviousTransformBlock {
+        model: [[f32; 4]; 4] = "prev_model",
+        view: [[f32; 4]; 4] = "prev_view",
+        proj: [[f32; 4]; 4] = "prev_proj",
+        eye: [f32; 4] = "prev_eye_pos",
+        lens: [f32; 4] = "prev_lens",
+        clip_offset: f32 = "prev_clip_offset",
+    }
+
+    constant SurfaceBlock {
+        inv_model: [[f32; 4]; 4] = "surface_inv_model",
+        normal: [[f32; 4]; 4] = "surface_normal_matrix",
+        params: [f32; 4] = "surface_params",
+        kind: i32 = "surface_kind",
+    }
+
+    constant AreaLightBlock {
+        center: [f32; 4] = "center",
+        axis_x: [f32; 4] = "axis_x",
+        axis_y: [f32; 4] = "axis_y",
+        color: [f32; 4] = "color",
     }
 
     pipeline bg {
@@ -52,35 +150,126 @@ gfx_defines!{
     pipeline pl {
         verts: gfx::VertexBuffer<VertNTT> = (),
         transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        previous: gfx::ConstantBuffer<PreviousTransformBlock> = "previous_transform",
         params: gfx::ConstantBuffer<ParamsBlock> = "params",
+        surface: gfx::ConstantBuffer<SurfaceBlock> = "surface",
+        area_lights: gfx::ConstantBuffer<AreaLightBlock> = "area_lights_layout",
         scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
 
         color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        // only written by the motion shaders, other pipeline states leave it unbound
+        velocity: gfx::RenderTarget<VelocityFormat> = "f_velocity",
 
         normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
         albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
         knobs: gfx::TextureSampler<[f32; 4]> = "knobs_tex",
+        bent: gfx::TextureSampler<[f32; 4]> = "bent_tex",
         irradiance: gfx::TextureSampler<[f32; 3]> = "irradiance_map",
         radiance: gfx::TextureSampler<[f32; 3]> = "radiance_map",
+        irradiance_b: gfx::TextureSampler<[f32; 3]> = "irradiance_map_b",
+        radiance_b: gfx::TextureSampler<[f32; 3]> = "radiance_map_b",
         integrated_brdf: gfx::TextureSampler<[f32; 2]> = "integrated_brdf_map",
+        ltc_matrix: gfx::TextureSampler<[f32; 2]> = "ltc_matrix_map",
+        ltc_norm: gfx::TextureSampler<[f32; 2]> = "ltc_norm_map",
 
         shadow_depth: gfx::TextureSampler<f32> = "shadow_depth",
+        sun_cookie: gfx::TextureSampler<[f32; 4]> = "sun_cookie_tex",
+        sky_occlusion: gfx::TextureSampler<f32> = "sky_occlusion_tex",
+        voxel_x: gfx::TextureSampler<[f32; 4]> = "voxel_x",
+        voxel_y: gfx::TextureSampler<[f32; 4]> = "voxel_y",
+        voxel_z: gfx::TextureSampler<[f32; 4]> = "voxel_z",
     }
 }
 
-shader!(shader {
-    vertex: static_file!("shaders/transform.v.glsl")
-        .define("NORM")
-        .define("TEX")
-        .define("TAN"),
-    fragment: static_file!("shaders/uber.f.glsl")
-        .define_to("I_POS", "v_pos")
-        .define_to("I_NORM", "v_norm")
-        .define_to("I_TEX", "v_tex")
-        .define_to("I_TAN", "v_tan")
-        .define_to("I_BITAN", "v_bitan")
-});
+/// A combination of optional uber shader features, each compiled to its own shader set
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderVariantKey(pub u32);
+
+impl ShaderVariantKey {
+    /// Write the motion of each pixel to a velocity target
+    pub const VELOCITY: ShaderVariantKey = ShaderVariantKey(1);
+    /// Rebuild the z of two channel normal maps
+    pub const NORMAL_RG: ShaderVariantKey = ShaderVariantKey(2);
+    /// The number of feature bits
+    pub const FEATURES: u32 = 2;
+
+    /// Whether every feature of `other` is enabled
+    pub fn contains(self, other: ShaderVariantKey) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Every combination of features, in order of their bits, up to `limit` of them
+    pub fn all(limit: usize) -> Vec<ShaderVariantKey> {
+        (0..1u32 << ShaderVariantKey::FEATURES)
+            .take(limit)
+            .map(ShaderVariantKey)
+            .collect()
+    }
+
+    /// The vertex and fragment source of this variant
+    fn sources(self) -> Result<(String, String), Error> {
+        let mut vertex = static_file!("shaders/transform.v.glsl")
+            .define("NORM")
+            .define("TEX")
+            .define("TAN");
+        let mut fragment = static_file!("shaders/uber.f.glsl")
+            .define_to("I_POS", "v_pos")
+            .define_to("I_NORM", "v_norm")
+            .define_to("I_TEX", "v_tex")
+            .define_to("I_TAN", "v_tan")
+            .define_to("I_BITAN", "v_bitan")
+            .define_to("AREA_LIGHT_COUNT", AREA_LIGHT_COUNT);
+        if self.contains(ShaderVariantKey::VELOCITY) {
+            vertex = vertex.define("VELOCITY");
+            fragment = fragment
+                .define_to("I_CLIP", "v_clip")
+                .define_to("I_PREV_CLIP", "v_prev_clip")
+                .define("VELOCITY");
+        }
+        if self.contains(ShaderVariantKey::NORMAL_RG) {
+            fragment = fragment.define("NORMAL_RG");
+        }
+        Ok((vertex.build()?, fragment.build()?))
+    }
+
+    /// Get this variant's shader set from `cache`, compiling it if it isn't there
+    fn compile<R: Resources, F: Factory<R>>(self, cache: &mut ShaderCache<R>, f: &mut F)
+        -> Result<Arc<ShaderSet<R>>, Error>
+    {
+        self.compile_with(cache, f, &[])
+    }
+
+    /// Get this variant's shader set with extra defines, for styles built on the uber shader
+    fn compile_with<R: Resources, F: Factory<R>>(
+        self,
+        cache: &mut ShaderCache<R>,
+        f: &mut F,
+        defines: &[(&str, &str)],
+    )
+        -> Result<Arc<ShaderSet<R>>, Error>
+    {
+        let (vertex, fragment) = self.sources()?;
+        cache.get_or_compile(f, &vertex, &fragment, defines)
+    }
+}
+
+impl BitOr for ShaderVariantKey {
+    type Output = ShaderVariantKey;
+
+    fn bitor(self, other: ShaderVariantKey) -> ShaderVariantKey {
+        ShaderVariantKey(self.0 | other.0)
+    }
+}
+
+impl From<NormalEncoding> for ShaderVariantKey {
+    fn from(e: NormalEncoding) -> ShaderVariantKey {
+        match e {
+            NormalEncoding::Rgb => ShaderVariantKey::default(),
+            NormalEncoding::Rg => ShaderVariantKey::NORMAL_RG,
+        }
+    }
+}
 
 shader!(bg_shader {
     vertex: static_file!("shaders/transform.v.glsl")
@@ -89,7 +278,69 @@ shader!(bg_shader {
         .define_to("I_POS", "v_pos")
 });
 
+impl From<Surface> for SurfaceBlock {
+    fn from(s: Surface) -> SurfaceBlock {
+        use ::mesh::gen::Surface::*;
+        let (kind, radius, height) = match s {
+            Mesh => (0, 0., 0.),
+            Sphere { radius } => (1, radius, 0.),
+            Cylinder { radius, height } => (2, radius, height),
+            Capsule { radius, height } => (3, radius, height),
+        };
+        SurfaceBlock {
+            inv_model: Matrix4::identity().into(),
+            normal: Matrix4::identity().into(),
+            params: [radius, height / 2., 0., 0.],
+            kind: kind,
+        }
+    }
+}
+
+impl SurfaceBlock {
+    /// The block for the surface placed by the given model matrix. Meshes don't use the
+    /// matrices, so they are left alone and the block stays the same for every model.
+    fn placed(mut self, model: &Matrix4<f32>) -> SurfaceBlock {
+        if self.kind != 0 {
+            let inv = model.try_inverse().unwrap_or_else(Matrix4::identity);
+            self.inv_model = inv.into();
+            self.normal = inv.transpose().into();
+        }
+        self
+    }
+}
+
+impl From<TransformBlock> for PreviousTransformBlock {
+    fn from(t: TransformBlock) -> PreviousTransformBlock {
+        PreviousTransformBlock {
+            model: t.model,
+            view: t.view,
+            proj: t.proj,
+            eye: t.eye,
+            lens: t.lens,
+            clip_offset: t.clip_offset,
+        }
+    }
+}
+
+impl From<AreaLight> for AreaLightBlock {
+    fn from(l: AreaLight) -> AreaLightBlock {
+        let (center, x, y) = l.frame();
+        let shape = match l.shape {
+            AreaShape::Rect(..) => 0.,
+            AreaShape::Disk(_) => 1.,
+        };
+        let c = l.radiance();
+        AreaLightBlock {
+            center: [center.x, center.y, center.z, shape],
+            axis_x: x.to_homogeneous().downgrade(),
+            axis_y: y.to_homogeneous().downgrade(),
+            color: [c[0], c[1], c[2], 1.],
+        }
+    }
+}
+
 /// The scene environment
+#[derive(Clone)]
 pub struct UberEnv<R: Resources> {
     pub irradiance: Texture<R, LumMapFormat>,
     pub radiance: Texture<R, LumMapFormat>,
@@ -99,21 +350,88 @@ pub struct UberEnv<R: Resources> {
     pub radiance_levels: u8,
 }
 
+/// A texture projected along the sun direction and multiplied into its light, for
+/// patterned light such as sunlight through a stained glass window
+#[derive(Clone)]
+pub struct SunCookie<R: Resources> {
+    pub texture: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    /// The world space width covered by the texture, centered on the world origin
+    pub extent: f32,
+    /// Repeat the texture across the scene. The sampler should wrap when this is set.
+    pub tile: bool,
+    /// How much of the sunlight reaches surfaces outside the texture when not tiling
+    pub outside: f32,
+}
+
 /// The configuration for physically based rendering
 pub struct UberInputs<R: Resources> {
-    shaders: ShaderSet<R>,
+    shaders: Arc<ShaderSet<R>>,
+    shader_variant_cache: ShaderCache<R>,
     background: UberBackground<R>,
     transform: Option<TransformBlock>,
-    transform_block: Buffer<R, TransformBlock>,
+    transform_block: FrameRingBuffer<R, TransformBlock>,
+    previous_block: FrameRingBuffer<R, PreviousTransformBlock>,
+    velocity: Option<RenderTargetView<R, VelocityFormat>>,
+    no_velocity: RenderTargetView<R, VelocityFormat>,
+    motion_frame: u64,
+    // the counter selecting the ring buffers, advanced by `end_frame` unless
+    // `set_frame_counter` shared another one
+    own_frames: Option<FrameCounter>,
     env: UberEnv<R>,
+    env_version: usize,
+    probes: [Option<ProbeSlot<R>>; 2],
+    probe_blend: f32,
+    sun_cookie: Option<SunCookie<R>>,
+    no_cookie: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    voxels: Option<(VoxelVolumes<R>, [f32; 4], [f32; 4])>,
+    no_voxels: Texture<R, VoxelFormat>,
+    sky_occlusion: Option<(Texture<R, (R8, Unorm)>, [f32; 4], f32)>,
+    no_sky_occlusion: Texture<R, (R8, Unorm)>,
     exposure: f32,
     gamma: f32,
+    shadow_depth_bias: f32,
     params_update: bool,
-    params_block: Buffer<R, ParamsBlock>,
+    params_frame: u64,
+    params_block: FrameRingBuffer<R, ParamsBlock>,
+    surface_block: Buffer<R, SurfaceBlock>,
+    // the model matrix of the latest transform, and what `surface_block` holds
+    surface_model: Matrix4<f32>,
+    surface_current: Option<SurfaceBlock>,
+    area_lights: Option<[AreaLightBlock; AREA_LIGHT_COUNT]>,
+    area_lights_block: Buffer<R, AreaLightBlock>,
     integrated_brdf: Texture<R, (R8_G8, Unorm)>,
+    ltc_matrix: Texture<R, (R16_G16, Float)>,
+    ltc_norm: Texture<R, (R16_G16, Float)>,
     shadow_depth: Texture<R, (D32, Float)>,
 }
 
+/// The irradiance and radiance maps of an environment
+type EnvMaps<R> = (Texture<R, LumMapFormat>, Texture<R, LumMapFormat>);
+
+/// A reflection probe lighting draws in place of the global environment
+#[derive(Clone)]
+struct ProbeSlot<R: Resources> {
+    maps: EnvMaps<R>,
+    center: [f32; 3],
+    parallax: Option<ProbeBox>,
+    levels: u8,
+}
+
+impl<R: Resources> ProbeSlot<R> {
+    /// The position (w = 1 if parallax corrected) and box of the probe for the shader
+    fn blocks(&self) -> ([f32; 4], [f32; 4], [f32; 4]) {
+        let c = self.center;
+        match self.parallax {
+            Some(b) => (
+                [c[0], c[1], c[2], 1.],
+                [b.min[0], b.min[1], b.min[2], 0.],
+                [b.max[0], b.max[1], b.max[2], 0.],
+            ),
+            None => ([c[0], c[1], c[2], 0.], [0.; 4], [0.; 4]),
+        }
+    }
+}
+
 struct UberBackground<R: Resources> {
     pso: PipelineState<R, bg::Meta>,
     // shaders: ShaderSet<R>,
@@ -121,16 +439,139 @@ struct UberBackground<R: Resources> {
 }
 
 impl<R: Resources> UberInputs<R> {
+    /// The shader set of a combination of features, compiled the first time it's needed
+    pub fn shader_variant<F: Factory<R>>(&mut self, f: &mut F, key: ShaderVariantKey)
+        -> Result<Arc<ShaderSet<R>>, Error>
+    {
+        key.compile(&mut self.shader_variant_cache, f)
+    }
+
+    /// The shader set of a combination of features with extra defines, such as the layers
+    /// of `LayeredStyle`
+    pub(super) fn shader_variant_with<F: Factory<R>>(
+        &mut self,
+        f: &mut F,
+        key: ShaderVariantKey,
+        defines: &[(&str, &str)],
+    )
+        -> Result<Arc<ShaderSet<R>>, Error>
+    {
+        key.compile_with(&mut self.shader_variant_cache, f, defines)
+    }
+
     pub fn set_env(&mut self, env: UberEnv<R>) {
         self.env = env;
+        self.env_version += 1;
         self.params_update = true;
     }
 
     pub fn mut_env(&mut self) -> &mut UberEnv<R> {
+        self.env_version += 1;
         self.params_update = true;
         &mut self.env
     }
 
+    /// Light the following draws with reflection probes instead of the global environment,
+    /// usually from `ProbeManager::evaluate` at the position of the mesh being drawn.
+    pub fn set_probes(&mut self, probes: &ProbeManager<R>, c: &ProbeContributions) {
+        let slot = |i: Option<usize>| i
+            .and_then(|i| probes.zone(i))
+            .map(|z| ProbeSlot {
+                maps: (z.probe.irradiance.clone(), z.probe.radiance.clone()),
+                center: z.center,
+                parallax: z.parallax,
+                levels: z.probe.radiance_levels,
+            });
+        self.probes = [slot(c.primary), slot(c.secondary)];
+        self.probe_blend = c.blend;
+        self.env_version += 1;
+        self.params_update = true;
+    }
+
+    /// Go back to lighting with only the global environment
+    pub fn clear_probes(&mut self) {
+        self.probes = [None, None];
+        self.probe_blend = 0.;
+        self.env_version += 1;
+        self.params_update = true;
+    }
+
+    /// The maps of both blended environments, falling back to the global one
+    fn env_maps(&self, i: usize) -> EnvMaps<R> {
+        match self.probes[i] {
+            Some(ref p) => p.maps.clone(),
+            None => (self.env.irradiance.clone(), self.env.radiance.clone()),
+        }
+    }
+
+    /// Project a cookie texture onto the sunlight, or remove it with `None`
+    pub fn set_sun_cookie(&mut self, cookie: Option<SunCookie<R>>) {
+        self.sun_cookie = cookie;
+        self.env_version += 1;
+        self.params_update = true;
+    }
+
+    /// The cookie texture to bind, white if there is none
+    fn sun_cookie_texture(&self) -> Texture<R, (R8_G8_B8_A8, Srgb)> {
+        match self.sun_cookie {
+            Some(ref c) => c.texture.clone(),
+            None => self.no_cookie.clone(),
+        }
+    }
+
+    /// Add cone traced indirect light from a voxel grid, or remove it with `None`. The
+    /// grid's position and strengths are copied, so call this again after changing them.
+    pub fn set_voxel_grid(&mut self, grid: Option<&VoxelGrid<R>>) {
+        self.voxels = grid.map(|g| {
+            let (min, params) = g.blocks();
+            (g.volumes(), min, params)
+        });
+        self.env_version += 1;
+        self.params_update = true;
+    }
+
+    /// The voxel grid volumes to bind, empty if there is none
+    fn voxel_textures(&self) -> VoxelVolumes<R> {
+        match self.voxels {
+            Some((ref v, _, _)) => v.clone(),
+            None => (self.no_voxels.clone(), self.no_voxels.clone(), self.no_voxels.clone()),
+        }
+    }
+
+    /// Darken indirect diffuse light with large scale occlusion from above, or remove it with
+    /// `None`. The area and strength are copied, so call this again after changing them or
+    /// rebaking.
+    pub fn set_sky_occlusion(&mut self, sky: Option<&SkyOcclusion<R>>) {
+        self.sky_occlusion = sky.map(|s| (s.texture.clone(), s.rect(), s.strength));
+        self.env_version += 1;
+        self.params_update = true;
+    }
+
+    /// The sky occlusion map to bind, unoccluded if there is none
+    fn sky_occlusion_texture(&self) -> Texture<R, (R8, Unorm)> {
+        match self.sky_occlusion {
+            Some((ref t, _, _)) => t.clone(),
+            None => self.no_sky_occlusion.clone(),
+        }
+    }
+
+    /// Also write the motion of each pixel since the previous frame to `target`, which must
+    /// be the size of the color target, or stop with `None`. Call `end_frame` once all of a
+    /// frame's draws are done so that the next frame moves relative to this one.
+    pub fn set_velocity_target(&mut self, target: Option<RenderTargetView<R, VelocityFormat>>) {
+        self.velocity = target;
+    }
+
+    /// Make the transforms drawn this frame the previous transforms of the next frame, and
+    /// write the next frame's transforms and parameters to different buffers than this one's
+    /// (unless the frame count is shared with `set_frame_counter`)
+    pub fn end_frame(&mut self) {
+        self.motion_frame += 1;
+        if let Some(ref frames) = self.own_frames {
+            frames.advance();
+        }
+    }
+
     pub fn set_exposure(&mut self, exposure: f32) {
         self.exposure = exposure;
         self.params_update = true;
@@ -140,6 +581,68 @@ impl<R: Resources> UberInputs<R> {
         self.gamma = gamma;
         self.params_update = true;
     }
+
+    /// Set how much nearer the light surfaces must be than the shadow map depth to be lit.
+    /// This currently has no effect, since the shadow map lookup is disabled.
+    pub fn set_shadow_depth_bias(&mut self, bias: f32) {
+        self.shadow_depth_bias = bias;
+        self.params_update = true;
+    }
+
+    /// Share the frame count of the draw context, so that the buffers each frame writes its
+    /// transforms and parameters to move on with `DrawParams::begin_frame` instead of
+    /// `end_frame`. Usually given `DrawParams::frames`.
+    pub fn set_frame_counter(&mut self, frames: FrameCounter) {
+        self.own_frames = None;
+        self.transform_block.set_counter(frames.clone());
+        self.previous_block.set_counter(frames.clone());
+        self.params_block.set_counter(frames);
+        self.params_update = true;
+    }
+
+    fn params(&self) -> ParamsBlock {
+        let mat: Rotation3<f32> = na::convert(self.env.sun_rotation);
+        let global = ([0.; 4], [0.; 4], [0.; 4]);
+        let a = self.probes[0].as_ref().map(|p| p.blocks()).unwrap_or(global);
+        let b = self.probes[1].as_ref().map(|p| p.blocks()).unwrap_or(global);
+        let levels = |i: usize| self.probes[i].as_ref()
+            .map(|p| p.levels)
+            .unwrap_or(self.env.radiance_levels) as f32;
+        ParamsBlock {
+            sun_matrix: mat.to_homogeneous().downgrade(),
+            sun_color: self.env.sun_color,
+            probe_pos_a: a.0,
+            probe_min_a: a.1,
+            probe_max_a: a.2,
+            probe_pos_b: b.0,
+            probe_min_b: b.1,
+            probe_max_b: b.2,
+            probe_levels: [levels(0), levels(1), 0., 0.],
+            sun_cookie: match self.sun_cookie {
+                Some(ref c) => [1. / c.extent.max(1e-6), 1., if c.tile { 1. } else { 0. }, c.outside],
+                None => [0.; 4],
+            },
+            voxel_min: self.voxels.as_ref().map(|v| v.1).unwrap_or([0.; 4]),
+            voxel_params: self.voxels.as_ref().map(|v| v.2).unwrap_or([0.; 4]),
+            sky_occlusion_rect: self.sky_occlusion.as_ref().map(|s| s.1).unwrap_or([0.; 4]),
+            sky_occlusion: self.sky_occlusion.as_ref().map(|s| s.2).unwrap_or(0.),
+            sun_in_env: if self.env.sun_included { 1. } else { 0. },
+            exposure: self.exposure,
+            gamma: self.gamma,
+            radiance_levels: self.env.radiance_levels as i32,
+            probe_blend: self.probe_blend,
+            shadow_depth_bias: self.shadow_depth_bias,
+        }
+    }
+
+    /// Sets the area lights present in the scene. Only the first `AREA_LIGHT_COUNT` lights will be used.
+    pub fn set_area_lights(&mut self, lights: &[AreaLight]) {
+        let mut all = [AreaLightBlock::from(AreaLight::default()); AREA_LIGHT_COUNT];
+        for i in 0..lights.len().min(AREA_LIGHT_COUNT) {
+            all[i] = AreaLightBlock::from(lights[i]);
+        }
+        self.area_lights = Some(all);
+    }
 }
 
 impl<R: Resources> StyleInputs<R> for UberInputs<R> {
@@ -149,9 +652,50 @@ impl<R: Resources> StyleInputs<R> for UberInputs<R> {
     fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
 }
 
+/// The transforms a bound mesh was drawn with in the current and previous frame, for each
+/// eye. A mesh drawn more than once a frame shares its binding, so its previous transform
+/// is the last one it was drawn with.
+#[derive(Clone, Debug, Default)]
+struct MotionHistory {
+    frame: u64,
+    current: [Option<TransformBlock>; 2],
+    previous: [Option<TransformBlock>; 2],
+}
+
+impl MotionHistory {
+    /// Record a draw with `t` in `frame`, returning the transform to measure motion from.
+    /// The first draw of a mesh doesn't move.
+    fn record(&mut self, frame: u64, t: TransformBlock) -> TransformBlock {
+        if frame != self.frame {
+            self.previous = if frame == self.frame + 1 {
+                self.current
+            } else {
+                // skipped a frame, so the old transforms are too old to use
+                [None, None]
+            };
+            self.current = [None, None];
+            self.frame = frame;
+        }
+        // the eyes are told apart by which half of the target they draw to
+        let eye = if t.clip_offset > 0. { 1 } else { 0 };
+        self.current[eye] = Some(t);
+        self.previous[eye].unwrap_or(t)
+    }
+}
+
+/// Pipeline data bound by `UberStyle`, along with the environment it was bound to
+pub struct UberBound<R: Resources> {
+    pub(super) data: pl::Data<R>,
+    surface: SurfaceBlock,
+    env_version: usize,
+    motion: MotionHistory,
+    variant: ShaderVariantKey,
+}
+
 /// Draws meshes using a physically based rendering pipeline
 pub struct UberStyle<R: Resources> {
-    pso: PipelineState<R, pl::Meta>,
+    // indexed by `ShaderVariantKey`
+    psos: Vec<PipelineState<R, pl::Meta>>,
 }
 
 fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
@@ -159,7 +703,8 @@ fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
 {
     use gfx::texture::*;
     use gfx::memory::{Bind, Usage};
-    
+    profile_scope!("shadow_texture");
+
     let shadow_tex = {
         let kind = Kind::D2(512, 512, AaMode::Single);
         let bind = Bind::SHADER_RESOURCE | Bind::DEPTH_STENCIL;
@@ -177,7 +722,7 @@ fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
         FilterMethod::Bilinear,
         WrapMode::Clamp
     );
-    sampler_info.comparison = Some(gfx::state::Comparison::LessEqual);
+    sampler_info.comparison = Some(super::DEPTH_CONVENTION.comparison(gfx::state::Comparison::LessEqual));
     let sampler = factory.create_sampler(sampler_info);
 
     let shadow_depth_target = factory.view_texture_as_depth_stencil(
@@ -190,10 +735,22 @@ fn shadow_texture<R: Resources, F: Factory<R>>(factory: &mut F)
     })
 }
 
+impl<R: Resources> DepthBiasStyle<R> for UberStyle<R> {}
+
 impl<R: Resources> Style<R> for UberStyle<R> {
     type Vertex = VertNTT;
     type Inputs = UberInputs<R>;
     type Material = UberMaterial<R>;
+    type Bound = UberBound<R>;
+
+    fn sampled(mat: &UberMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        vec![
+            mat.normal.buffer.raw().clone(),
+            mat.albedo.buffer.raw().clone(),
+            mat.knobs.buffer.raw().clone(),
+            mat.bent.buffer.raw().clone(),
+        ]
+    }
 
     fn new<F: Factory<R> + FactoryExt<R>> (
         f: &mut F,
@@ -201,8 +758,13 @@ impl<R: Resources> Style<R> for UberStyle<R> {
         p: Primitive,
         r: Rasterizer,
     ) -> Result<Self, Error> {
+        let mut psos = vec![];
+        for key in ShaderVariantKey::all(1 << ShaderVariantKey::FEATURES) {
+            let shaders = i.shader_variant(f, key)?;
+            psos.push(f.create_pipeline_state(&shaders, p, r, pl::new())?);
+        }
         Ok(UberStyle {
-            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+            psos: psos,
         })
     }
 
@@ -215,6 +777,8 @@ impl<R: Resources> Style<R> for UberStyle<R> {
         };
         let (_, shadow_depth) = shadow_texture(f);
         let bg_shaders = bg_shader(f)?;
+        let (ltc_matrix, ltc_norm) = ::load::load_ltc_tables(f)?;
+        let frames = FrameCounter::new();
         let bg_verts = vec![
             Vert { pos: [-10., -10.,  10.] },
             Vert { pos: [-10.,  10.,  10.] },
@@ -239,8 +803,12 @@ impl<R: Resources> Style<R> for UberStyle<R> {
             1-1, 5-1, 7-1,
             6-1, 2-1, 4-1,
         ];
+        let (_, _, no_velocity) = f.create_render_target::<VelocityFormat>(1, 1)?;
+        let mut shader_variant_cache = ShaderCache::new();
+        let shaders = ShaderVariantKey::default().compile(&mut shader_variant_cache, f)?;
         Ok(UberInputs {
-            shaders: shader(f)?,
+            shaders: shaders,
+            shader_variant_cache: shader_variant_cache,
             background: UberBackground {
                 pso: f.create_pipeline_state(
                     &bg_shaders,
@@ -256,12 +824,26 @@ impl<R: Resources> Style<R> for UberStyle<R> {
                 }.upload(f),
             },
             transform: None,
-            transform_block: f.create_constant_buffer(1),
+            transform_block: FrameRingBuffer::new(f, frames.clone()),
+            previous_block: FrameRingBuffer::new(f, frames.clone()),
+            velocity: None,
+            no_velocity: no_velocity,
+            motion_frame: 0,
+            own_frames: Some(frames.clone()),
             params_update: true,
-            params_block: f.create_constant_buffer(1),
-            gamma: 2.2,
+            params_frame: 0,
+            params_block: FrameRingBuffer::new(f, frames),
+            surface_block: f.create_constant_buffer(1),
+            surface_model: Matrix4::identity(),
+            surface_current: None,
+            area_lights: Some([AreaLightBlock::from(AreaLight::default()); AREA_LIGHT_COUNT]),
+            area_lights_block: f.create_constant_buffer(AREA_LIGHT_COUNT),
+            gamma: ::OUTPUT_GAMMA,
             exposure: 1.0,
+            shadow_depth_bias: 0.001,
             integrated_brdf: ::load::load_integrated_brdf(f)?,
+            ltc_matrix: ltc_matrix,
+            ltc_norm: ltc_norm,
             env: UberEnv {
                 radiance: Texture::uniform_value(f, bg_bytes)?,
                 irradiance: Texture::uniform_value(f, bg_bytes)?,
@@ -273,91 +855,317 @@ impl<R: Resources> Style<R> for UberStyle<R> {
                 sun_included: false,
                 radiance_levels: 1,
             },
+            env_version: 0,
+            probes: [None, None],
+            probe_blend: 0.,
+            sun_cookie: None,
+            no_cookie: Texture::uniform_value(f, [255; 4])?,
+            voxels: None,
+            no_voxels: gi::empty_voxels(f)?,
+            sky_occlusion: None,
+            no_sky_occlusion: Texture::uniform_value(f, 255)?,
             shadow_depth: shadow_depth,
         })
     }
 
-    fn draw_raw<C>(
+    fn bind(
         &self,
-        inputs: &mut UberInputs<R>,
-        enc: &mut Encoder<R, C>,
+        inputs: &UberInputs<R>,
         color: TargetRef<R>,
         depth: DepthRef<R>,
-        scissor: Rect,
-        slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         mat: &UberMaterial<R>,
+    ) -> UberBound<R> {
+        bind_uber(inputs, color, depth, buf, mat)
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut UberInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut UberBound<R>,
     )
         -> Result<(), Error>
         where C: CommandBuffer<R>
     {
-        if let Some(t) = inputs.transform.take() {
-            enc.update_constant_buffer(&inputs.transform_block, &t);
+        let key = prepare_uber(inputs, enc, scissor, bound)?;
+        enc.draw(slice, &self.psos[key.0 as usize], &bound.data);
+        Ok(())
+    }
+}
+
+impl<R: Resources> UberStyle<R> {
+    /// Compile the shaders of every feature combination, up to `MAX_PRECOMPILE_VARIANTS` of
+    /// them, so that no variant is compiled in the middle of a frame. Call this while
+    /// loading, before the first VR frame.
+    pub fn precompile_all_variants<F: Factory<R>>(factory: &mut F, inputs: &mut UberInputs<R>)
+        -> Result<(), Error>
+    {
+        let variants = 1usize << ShaderVariantKey::FEATURES;
+        if variants > MAX_PRECOMPILE_VARIANTS {
+            warn!("only precompiling {} of {} uber shader variants", MAX_PRECOMPILE_VARIANTS, variants);
         }
-        if inputs.params_update {
-            let mat: Rotation3<f32> = na::convert(inputs.env.sun_rotation);
-            enc.update_constant_buffer(&inputs.params_block, &ParamsBlock { 
-                sun_matrix: mat.to_homogeneous().downgrade(),
-                sun_color: inputs.env.sun_color,
-                sun_in_env: if inputs.env.sun_included { 1. } else { 0. },
-                exposure: inputs.exposure,
-                gamma: inputs.gamma,
-                radiance_levels: inputs.env.radiance_levels as i32,
-            });
+        for key in ShaderVariantKey::all(MAX_PRECOMPILE_VARIANTS) {
+            inputs.shader_variant(factory, key)?;
         }
-        enc.draw(slice, &self.pso, &pl::Data {
+        Ok(())
+    }
+}
+
+/// Bind a mesh for the uber pipeline, also used by styles that extend it
+pub(super) fn bind_uber<R: Resources>(
+    inputs: &UberInputs<R>,
+    color: TargetRef<R>,
+    depth: DepthRef<R>,
+    buf: Buffer<R, VertNTT>,
+    mat: &UberMaterial<R>,
+) -> UberBound<R> {
+    let (irradiance, radiance) = inputs.env_maps(0);
+    let (irradiance_b, radiance_b) = inputs.env_maps(1);
+    let (voxel_x, voxel_y, voxel_z) = inputs.voxel_textures();
+    UberBound {
+        data: pl::Data {
             color: color,
             depth: depth,
+            velocity: inputs.no_velocity.clone(),
             verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
-            params: inputs.params_block.clone(),
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.current().clone(),
+            previous: inputs.previous_block.current().clone(),
+            params: inputs.params_block.current().clone(),
+            surface: inputs.surface_block.clone(),
+            area_lights: inputs.area_lights_block.clone(),
             normal: mat.normal.clone().into_tuple(),
             albedo: mat.albedo.clone().into_tuple(),
             knobs: mat.knobs.clone().into_tuple(),
+            bent: mat.bent.clone().into_tuple(),
             integrated_brdf: inputs.integrated_brdf.clone().into_tuple(),
-            irradiance: inputs.env.irradiance.clone().into_tuple(),
-            radiance: inputs.env.radiance.clone().into_tuple(),
+            ltc_matrix: inputs.ltc_matrix.clone().into_tuple(),
+            ltc_norm: inputs.ltc_norm.clone().into_tuple(),
+            irradiance: irradiance.into_tuple(),
+            radiance: radiance.into_tuple(),
+            irradiance_b: irradiance_b.into_tuple(),
+            radiance_b: radiance_b.into_tuple(),
             shadow_depth: inputs.shadow_depth.clone().into_tuple(),
-        });
-        Ok(())
+            sun_cookie: inputs.sun_cookie_texture().into_tuple(),
+            sky_occlusion: inputs.sky_occlusion_texture().into_tuple(),
+            voxel_x: voxel_x.into_tuple(),
+            voxel_y: voxel_y.into_tuple(),
+            voxel_z: voxel_z.into_tuple(),
+        },
+        surface: mat.surface.into(),
+        env_version: inputs.env_version,
+        motion: MotionHistory::default(),
+        variant: mat.normal_encoding.into(),
     }
 }
 
+/// Update the buffers and textures of a bound mesh before drawing it, returning the shader
+/// variant to draw it with
+pub(super) fn prepare_uber<R, C>(
+    inputs: &mut UberInputs<R>,
+    enc: &mut Encoder<R, C>,
+    scissor: Rect,
+    bound: &mut UberBound<R>,
+)
+    -> Result<ShaderVariantKey, Error>
+    where R: Resources, C: CommandBuffer<R>
+{
+    bound.data.transform = inputs.transform_block.current().clone();
+    bound.data.previous = inputs.previous_block.current().clone();
+    if let Some(t) = inputs.transform.take() {
+        enc.update_constant_buffer(&bound.data.transform, &t);
+        inputs.surface_model = t.model.into();
+        let previous = bound.motion.record(inputs.motion_frame, t);
+        if inputs.velocity.is_some() {
+            enc.update_constant_buffer(&bound.data.previous, &previous.into());
+        }
+    }
+    bound.data.params = inputs.params_block.current().clone();
+    if inputs.params_block.frame() != inputs.params_frame {
+        // this frame's buffer still holds the parameters of an earlier frame
+        inputs.params_frame = inputs.params_block.frame();
+        inputs.params_update = true;
+    }
+    if inputs.params_update {
+        enc.update_constant_buffer(&bound.data.params, &inputs.params());
+        inputs.params_update = false;
+    }
+    if let Some(l) = inputs.area_lights.take() {
+        enc.update_buffer(&inputs.area_lights_block, &l, 0)?;
+    }
+    if bound.env_version != inputs.env_version {
+        // the environment, probes, cookie, voxels or sky occlusion were replaced after this mesh was bound
+        let (irradiance, radiance) = inputs.env_maps(0);
+        let (irradiance_b, radiance_b) = inputs.env_maps(1);
+        bound.data.irradiance = irradiance.into_tuple();
+        bound.data.radiance = radiance.into_tuple();
+        bound.data.irradiance_b = irradiance_b.into_tuple();
+        bound.data.radiance_b = radiance_b.into_tuple();
+        bound.data.sun_cookie = inputs.sun_cookie_texture().into_tuple();
+        bound.data.sky_occlusion = inputs.sky_occlusion_texture().into_tuple();
+        let (voxel_x, voxel_y, voxel_z) = inputs.voxel_textures();
+        bound.data.voxel_x = voxel_x.into_tuple();
+        bound.data.voxel_y = voxel_y.into_tuple();
+        bound.data.voxel_z = voxel_z.into_tuple();
+        bound.env_version = inputs.env_version;
+    }
+    // both eyes and consecutive meshes of the same shape usually share the block
+    let surface = bound.surface.placed(&inputs.surface_model);
+    if inputs.surface_current != Some(surface) {
+        enc.update_constant_buffer(&inputs.surface_block, &surface);
+        inputs.surface_current = Some(surface);
+    }
+    bound.data.scissor = scissor;
+    Ok(match inputs.velocity {
+        Some(ref v) => {
+            bound.data.velocity = v.clone();
+            bound.variant | ShaderVariantKey::VELOCITY
+        }
+        None => bound.variant,
+    })
+}
+
 impl<R: Resources> super::Painter<R, UberStyle<R>> {
     pub fn clear_env<C: CommandBuffer<R>>(
         &self,
         ctx: &mut super::DrawParams<R, C>,
     ) {
         let inputs = self.inputs.borrow();
+        let transform = inputs.transform_block.current().clone();
+        let params = inputs.params_block.current().clone();
+        ctx.encoder.update_constant_buffer(&params, &inputs.params());
         let bgin = &inputs.background;
-        let mat: Rotation3<f32> = na::convert(inputs.env.sun_rotation);
-        ctx.encoder.update_constant_buffer(&inputs.params_block, &ParamsBlock { 
-            sun_matrix: mat.to_homogeneous().downgrade(),
-            sun_color: inputs.env.sun_color,
-            sun_in_env: if inputs.env.sun_included { 1. } else { 0. },
-            exposure: inputs.exposure,
-            gamma: inputs.gamma,
-            radiance_levels: inputs.env.radiance_levels as i32,
-        });
-        for eye in &[&ctx.left, &ctx.right] {
+        for eye in &ctx.eyes() {
             let trans = TransformBlock {
                 eye: eye.eye.to_homogeneous().downgrade(),
                 model: Matrix4::identity().downgrade(),
                 view: eye.view.downgrade(),
-                proj: eye.proj.downgrade(),
+                proj: super::eye_proj(eye),
+                lens: ctx.lens_shading.block(eye.clip),
                 clip_offset: eye.clip_offset,
+                log_depth: ctx.log_depth.block(),
             };
-            ctx.encoder.update_constant_buffer(&inputs.transform_block, &trans);
+            ctx.encoder.update_constant_buffer(&transform, &trans);
             ctx.encoder.draw(&bgin.mesh.slice, &bgin.pso, &bg::Data {
                 color: ctx.color.clone(),
                 depth: ctx.depth.clone(),
                 verts: bgin.mesh.buf.clone(),
                 scissor: eye.clip,
-                transform: inputs.transform_block.clone(),
-                params: inputs.params_block.clone(),
+                transform: transform.clone(),
+                params: params.clone(),
                 radiance: inputs.env.radiance.clone().into_tuple(),
             });
         }
     }
 }
+
+#[test]
+fn motion_history() {
+    let at = |x: f32, clip_offset: f32| {
+        let mut model = Matrix4::<f32>::identity();
+        model[(0, 3)] = x;
+        TransformBlock {
+            model: model.downgrade(),
+            view: Matrix4::identity().downgrade(),
+            proj: Matrix4::identity().downgrade(),
+            eye: [0.; 4],
+            lens: [0.; 4],
+            clip_offset: clip_offset,
+            log_depth: 0.,
+        }
+    };
+    let mut h = MotionHistory::default();
+    // nothing to move from on the first frame
+    assert_eq!(h.record(0, at(1., -0.5)), at(1., -0.5));
+    assert_eq!(h.record(0, at(1., 0.5)), at(1., 0.5));
+    // each eye moves from its own transform
+    assert_eq!(h.record(1, at(2., -0.5)), at(1., -0.5));
+    assert_eq!(h.record(1, at(2., 0.5)), at(1., 0.5));
+    assert_eq!(h.record(2, at(3., 0.5)), at(2., 0.5));
+    // after a frame without drawing the mesh appears without motion
+    assert_eq!(h.record(4, at(5., -0.5)), at(5., -0.5));
+}
+
+#[test]
+fn surface_placement() {
+    let model = Matrix4::new_translation(&Vector3::new(1., 2., 3.))
+        * Matrix4::new_nonuniform_scaling(&Vector3::new(2., 1., 1.));
+    let sphere = SurfaceBlock::from(Surface::Sphere { radius: 1. }).placed(&model);
+    let inv = Matrix4::from(sphere.inv_model);
+    assert!(relative_eq!(inv * model, Matrix4::identity(), epsilon = 1e-6));
+    assert_eq!(Matrix4::from(sphere.normal), inv.transpose());
+    // meshes keep their normals, so moving them doesn't change the block
+    let mesh = SurfaceBlock::from(Surface::Mesh);
+    assert_eq!(mesh.placed(&model), mesh);
+}
+
+#[test]
+fn shader_variants() {
+    let all = ShaderVariantKey::all(MAX_PRECOMPILE_VARIANTS);
+    assert_eq!(all.len(), 1 << ShaderVariantKey::FEATURES);
+    assert_eq!(all[0], ShaderVariantKey::default());
+    assert_eq!(ShaderVariantKey::all(1).len(), 1);
+    assert!(all.iter().any(|k| k.contains(ShaderVariantKey::VELOCITY)));
+
+    let (vertex, fragment) = ShaderVariantKey::VELOCITY.sources().unwrap();
+    assert!(vertex.contains("#define VELOCITY\n"));
+    assert!(fragment.contains("#define I_CLIP v_clip\n"));
+    let (vertex, fragment) = ShaderVariantKey::default().sources().unwrap();
+    assert!(!vertex.contains("#define VELOCITY"));
+    assert!(!fragment.contains("#define NORMAL_RG"));
+
+    let key = ShaderVariantKey::from(NormalEncoding::Rg) | ShaderVariantKey::VELOCITY;
+    assert!(key.contains(ShaderVariantKey::NORMAL_RG) && key.contains(ShaderVariantKey::VELOCITY));
+    assert!(all.contains(&key));
+    let (_, fragment) = key.sources().unwrap();
+    assert!(fragment.contains("#define NORMAL_RG\n"));
+}
+
+#[cfg(feature = "golden")]
+#[test]
+fn frame_buffers() {
+    use ::testing::Headless;
+    use super::{DrawParams, Painter, FRAME_RING_SIZE};
+
+    let mut context = Headless::new().unwrap();
+    let encoder = context.factory.create_command_buffer().into();
+    let mut ctx = DrawParams::new(encoder, context.target.color.clone(), context.target.depth.clone());
+    let f = &mut context.factory;
+    let mut painter: Painter<_, UberStyle<_>> = Painter::new(f).unwrap();
+    painter.setup(f, Primitive::TriangleList).unwrap();
+    let quad = ::mesh::gen::quad(1., 1.);
+    let flat = Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF]).unwrap();
+    let mat = UberMaterial {
+        albedo: Texture::uniform_value(f, [128, 128, 128, 255]).unwrap(),
+        normal: flat.clone(),
+        knobs: Texture::uniform_value(f, [0, 128, 0, 0]).unwrap(),
+        bent: flat,
+        surface: quad.mat,
+        normal_encoding: NormalEncoding::Rgb,
+    };
+    let mesh = quad.with_material(mat).upload(f);
+
+    let mut draw = |ctx: &mut DrawParams<_, _>| {
+        painter.try_draw(ctx, na::one(), &mesh).unwrap();
+        let bindings = painter.bindings.borrow();
+        let bound = &bindings.values().next().unwrap()[0].bound;
+        painter.cfg(|i| i.end_frame());
+        (bound.data.transform.clone(), bound.data.params.clone())
+    };
+    // each frame writes to buffers the GPU may still be reading from the previous frames
+    let frames: Vec<_> = (0..FRAME_RING_SIZE + 1).map(|_| draw(&mut ctx)).collect();
+    for (i, a) in frames[..FRAME_RING_SIZE].iter().enumerate() {
+        assert!(frames[i + 1..FRAME_RING_SIZE].iter().all(|b| a.0 != b.0 && a.1 != b.1));
+    }
+    assert_eq!(frames[0], frames[FRAME_RING_SIZE]);
+
+    // with the frame count of the context shared, only its frames move on
+    painter.cfg(|i| i.set_frame_counter(ctx.frames.clone()));
+    let first = draw(&mut ctx);
+    assert_eq!(draw(&mut ctx), first);
+    ctx.begin_frame();
+    assert!(draw(&mut ctx) != first);
+}
@@ -20,7 +20,7 @@ gfx_defines!{
         shade: gfx::ConstantBuffer<UnishadeBlock> = "shade",
         scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
         color: gfx::RenderTarget<ColorFormat> = "f_color",
-        depth: gfx::DepthTarget<DepthFormat> = gfx::preset::depth::LESS_EQUAL_WRITE,
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
     }
 }
 
@@ -65,6 +65,7 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
     type Vertex = VertN;
     type Inputs = UnishadeInputs<R>;
     type Material = ();
+    type Bound = pl::Data<R>;
 
     fn new<F: Factory<R> + FactoryExt<R>>(
         f: &mut F,
@@ -89,16 +90,31 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
         })
     }
 
-    fn draw_raw<C>(
+    fn bind(
         &self,
-        inputs: &mut UnishadeInputs<R>,
-        enc: &mut Encoder<R, C>,
+        inputs: &UnishadeInputs<R>,
         color: TargetRef<R>,
         depth: DepthRef<R>,
-        scissor: Rect,
-        slice: &Slice<R>,
         buf: Buffer<R, Self::Vertex>,
         _: &(),
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            shade: inputs.shade_block.clone(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut UnishadeInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
     )
         -> Result<(), Error>
         where C: CommandBuffer<R>
@@ -109,14 +125,8 @@ impl<R: Resources> Style<R> for UnishadeStyle<R> {
         if let Some(shade) = inputs.shade.take() {
             enc.update_constant_buffer(&inputs.shade_block, &shade);
         }
-        enc.draw(slice, &self.pso, &pl::Data {
-            color: color,
-            depth: depth,
-            verts: buf,
-            scissor: scissor,
-            transform: inputs.transform_block.clone(),
-            shade: inputs.shade_block.clone(),
-        });
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
         Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,139 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+
+use super::{StyleInputs, Style, DepthBiasStyle, TransformBlock};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The texture shown by `UnlitStyle`
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct UnlitMaterial<R: Resources> {
+    /// Color and opacity, in display space (like the contents of an `OffscreenTarget`)
+    pub color: Texture<R, ColorFormat>,
+}
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        texture: gfx::TextureSampler<[f32; 4]> = "color_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl")
+        .define("NORM")
+        .define("TEX")
+        .define("TAN"),
+    fragment: static_file!("shaders/unlit.f.glsl")
+        .define_to("I_TEX", "v_tex")
+});
+
+/// The configuration for unlit rendering
+pub struct UnlitInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+}
+
+impl<R: Resources> StyleInputs<R> for UnlitInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+/// Draws textured meshes without lighting, such as UI panels. Colors come out exactly as
+/// given whatever the scene's exposure, since exposure and tonemapping happen in the shaders
+/// of the lit styles (see `UberInputs::set_exposure`) rather than over the whole target.
+/// Post passes that add light onto the target (`post::GodRayPass`, `post::LensFlare`) are
+/// the exception, so draw UI after them, with the same depth target so the world still
+/// hides it.
+pub struct UnlitStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> DepthBiasStyle<R> for UnlitStyle<R> {}
+
+impl<R: Resources> Style<R> for UnlitStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = UnlitInputs<R>;
+    type Material = UnlitMaterial<R>;
+    type Bound = pl::Data<R>;
+
+    fn sampled(mat: &UnlitMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        vec![mat.color.buffer.raw().clone()]
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut UnlitInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(UnlitStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<UnlitInputs<R>, Error> {
+        Ok(UnlitInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &UnlitInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &UnlitMaterial<R>,
+    ) -> pl::Data<R> {
+        pl::Data {
+            color: color,
+            depth: depth,
+            verts: buf,
+            scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+            transform: inputs.transform_block.clone(),
+            texture: mat.color.clone().into_tuple(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut UnlitInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        data: &mut pl::Data<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        data.scissor = scissor;
+        enc.draw(slice, &self.pso, data);
+        Ok(())
+    }
+}
+
+#[test]
+fn unlit_ignores_exposure() {
+    // exposure and tonemapping belong to the lit shaders, so unlit UI keeps its colors
+    let unlit = static_file!("shaders/unlit.f.glsl").build().unwrap();
+    assert!(!unlit.contains("exposure") && !unlit.contains("gamma"));
+    let uber = static_file!("shaders/uber.f.glsl").build().unwrap();
+    assert!(uber.contains("exposure"));
+}
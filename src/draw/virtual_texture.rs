@@ -0,0 +1,191 @@
+use gfx::{self, Resources, CommandBuffer, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+
+use super::{Style, TransformBlock};
+use super::uber::{self, UberStyle, UberMaterial, UberInputs, UberBound, ShaderVariantKey};
+use super::uber::{ParamsBlock, PreviousTransformBlock, SurfaceBlock, AreaLightBlock, VelocityFormat};
+use ::mesh::{Primitive, VertNTT};
+use ::stream::{VirtualTexture, PAGE_SIZE, PAGE_BORDER};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// An uber material whose albedo comes from a `VirtualTexture`, tinted by the albedo map of
+/// the base (usually plain white)
+#[derive(Clone, PartialEq)]
+pub struct VirtualMaterial<R: Resources> {
+    pub base: UberMaterial<R>,
+    pub page_table: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub atlas: Texture<R, (R8_G8_B8_A8, Srgb)>,
+}
+
+impl<R: Resources> VirtualMaterial<R> {
+    /// Use the pages of `texture` over `base`
+    pub fn new(base: UberMaterial<R>, texture: &VirtualTexture<R>) -> VirtualMaterial<R> {
+        let (page_table, atlas) = texture.textures();
+        VirtualMaterial {
+            base: base,
+            page_table: page_table,
+            atlas: atlas,
+        }
+    }
+}
+
+gfx_defines!{
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        previous: gfx::ConstantBuffer<PreviousTransformBlock> = "previous_transform",
+        params: gfx::ConstantBuffer<ParamsBlock> = "params",
+        surface: gfx::ConstantBuffer<SurfaceBlock> = "surface",
+        area_lights: gfx::ConstantBuffer<AreaLightBlock> = "area_lights_layout",
+        scissor: gfx::Scissor = (),
+
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+        velocity: gfx::RenderTarget<VelocityFormat> = "f_velocity",
+
+        normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+        knobs: gfx::TextureSampler<[f32; 4]> = "knobs_tex",
+        bent: gfx::TextureSampler<[f32; 4]> = "bent_tex",
+        irradiance: gfx::TextureSampler<[f32; 3]> = "irradiance_map",
+        radiance: gfx::TextureSampler<[f32; 3]> = "radiance_map",
+        irradiance_b: gfx::TextureSampler<[f32; 3]> = "irradiance_map_b",
+        radiance_b: gfx::TextureSampler<[f32; 3]> = "radiance_map_b",
+        integrated_brdf: gfx::TextureSampler<[f32; 2]> = "integrated_brdf_map",
+        ltc_matrix: gfx::TextureSampler<[f32; 2]> = "ltc_matrix_map",
+        ltc_norm: gfx::TextureSampler<[f32; 2]> = "ltc_norm_map",
+
+        shadow_depth: gfx::TextureSampler<f32> = "shadow_depth",
+        sun_cookie: gfx::TextureSampler<[f32; 4]> = "sun_cookie_tex",
+        sky_occlusion: gfx::TextureSampler<f32> = "sky_occlusion_tex",
+        voxel_x: gfx::TextureSampler<[f32; 4]> = "voxel_x",
+        voxel_y: gfx::TextureSampler<[f32; 4]> = "voxel_y",
+        voxel_z: gfx::TextureSampler<[f32; 4]> = "voxel_z",
+
+        page_table: gfx::TextureSampler<[f32; 4]> = "page_table",
+        page_atlas: gfx::TextureSampler<[f32; 4]> = "page_atlas",
+    }
+}
+
+/// Pipeline data bound by `VirtualTextureStyle`: the base as bound by `UberStyle`, and the
+/// page table and atlas
+pub struct VirtualBound<R: Resources> {
+    base: UberBound<R>,
+    page_table: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    atlas: Texture<R, (R8_G8_B8_A8, Srgb)>,
+}
+
+/// Draws meshes like `UberStyle`, looking their albedo up in the page table of a
+/// `VirtualTexture` and then its atlas. It takes the same inputs as `UberStyle`. Surfaces
+/// only show the pages that were requested from the texture, so request the pages each mesh
+/// covers at the mip level it is seen at.
+pub struct VirtualTextureStyle<R: Resources> {
+    // indexed by `ShaderVariantKey`
+    psos: Vec<PipelineState<R, pl::Meta>>,
+}
+
+impl<R: Resources> Style<R> for VirtualTextureStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = UberInputs<R>;
+    type Material = VirtualMaterial<R>;
+    type Bound = VirtualBound<R>;
+
+    fn sampled(mat: &VirtualMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        let mut sampled = UberStyle::sampled(&mat.base);
+        sampled.push(mat.page_table.buffer.raw().clone());
+        sampled.push(mat.atlas.buffer.raw().clone());
+        sampled
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut UberInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        let page_size = format!("{}.0", PAGE_SIZE);
+        let border = format!("{}.0", PAGE_BORDER);
+        let defines = [("VIRTUAL_TEXTURE", ""), ("VT_PAGE_SIZE", &page_size[..]), ("VT_BORDER", &border[..])];
+        let mut psos = vec![];
+        for key in ShaderVariantKey::all(1 << ShaderVariantKey::FEATURES) {
+            let shaders = i.shader_variant_with(f, key, &defines)?;
+            psos.push(f.create_pipeline_state(&shaders, p, r, pl::new())?);
+        }
+        Ok(VirtualTextureStyle {
+            psos: psos,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<UberInputs<R>, Error> {
+        UberStyle::init(f)
+    }
+
+    fn bind(
+        &self,
+        inputs: &UberInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, VertNTT>,
+        mat: &VirtualMaterial<R>,
+    ) -> VirtualBound<R> {
+        VirtualBound {
+            base: uber::bind_uber(inputs, color, depth, buf, &mat.base),
+            page_table: mat.page_table.clone(),
+            atlas: mat.atlas.clone(),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut UberInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut VirtualBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        let key = uber::prepare_uber(inputs, enc, scissor, &mut bound.base)?;
+        let base = &bound.base.data;
+        enc.draw(slice, &self.psos[key.0 as usize], &pl::Data {
+            verts: base.verts.clone(),
+            transform: base.transform.clone(),
+            previous: base.previous.clone(),
+            params: base.params.clone(),
+            surface: base.surface.clone(),
+            area_lights: base.area_lights.clone(),
+            scissor: base.scissor,
+            color: base.color.clone(),
+            depth: base.depth.clone(),
+            velocity: base.velocity.clone(),
+            normal: base.normal.clone(),
+            albedo: base.albedo.clone(),
+            knobs: base.knobs.clone(),
+            bent: base.bent.clone(),
+            irradiance: base.irradiance.clone(),
+            radiance: base.radiance.clone(),
+            irradiance_b: base.irradiance_b.clone(),
+            radiance_b: base.radiance_b.clone(),
+            integrated_brdf: base.integrated_brdf.clone(),
+            ltc_matrix: base.ltc_matrix.clone(),
+            ltc_norm: base.ltc_norm.clone(),
+            shadow_depth: base.shadow_depth.clone(),
+            sun_cookie: base.sun_cookie.clone(),
+            sky_occlusion: base.sky_occlusion.clone(),
+            voxel_x: base.voxel_x.clone(),
+            voxel_y: base.voxel_y.clone(),
+            voxel_z: base.voxel_z.clone(),
+            page_table: bound.page_table.clone().into_tuple(),
+            page_atlas: bound.atlas.clone().into_tuple(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use std::ops::Range;
+
+use gfx::{self, Resources, CommandBuffer, Factory, Slice, IndexBuffer};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::{Buffer, RenderTargetView, ShaderResourceView, Sampler};
+use gfx::state::Rasterizer;
+use gfx::format::*;
+use nalgebra::{Vector3, Transform3};
+
+use super::{DrawParams, RenderEvent, RenderEventBus, TransformBlock, eye_transforms};
+use super::sky::inverse_view_proj;
+use ::mesh::{Primitive, VertNTT};
+use ::util::NativeRepr;
+use ::{Error, ColorFormat, DepthFormat, DepthRef, Texture};
+
+/// The pixel format of the triangle ID target
+pub type VisibilityFormat = (R32, Uint);
+
+/// The ID of pixels no triangle covers
+pub const NO_TRIANGLE: u32 = 0xFFFF_FFFF;
+
+/// The width in texels of the textures holding mesh data for the resolve pass
+const DATA_WIDTH: usize = 1024;
+
+gfx_defines!{
+    constant VisibilityDrawBlock {
+        first_triangle: u32 = "first_triangle",
+    }
+
+    pipeline ids {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        draw: gfx::ConstantBuffer<VisibilityDrawBlock> = "visibility_draw",
+        scissor: gfx::Scissor = (),
+        id: gfx::RenderTarget<VisibilityFormat> = "f_id",
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_WRITE,
+    }
+
+    constant ResolveBlock {
+        inv_view_proj: [[f32; 4]; 4] = "inv_view_proj",
+        eye_pos: [f32; 4] = "eye_pos",
+        viewport: [f32; 4] = "viewport",
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+        ambient: [f32; 4] = "ambient",
+        gamma: f32 = "gamma",
+    }
+
+    pipeline resolve {
+        params: gfx::ConstantBuffer<ResolveBlock> = "visibility_resolve",
+        scissor: gfx::Scissor = (),
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        ids: gfx::TextureSampler<u32> = "id_tex",
+        indices: gfx::TextureSampler<u32> = "index_tex",
+        vertices: gfx::TextureSampler<[f32; 4]> = "vertex_tex",
+        albedo: gfx::TextureSampler<[f32; 4]> = "albedo_tex",
+    }
+}
+
+shader!(id_shader {
+    vertex: static_file!("shaders/transform.v.glsl"),
+    fragment: static_file!("shaders/visibility_id.f.glsl")
+});
+
+shader!(resolve_shader {
+    vertex: static_file!("shaders/fullscreen.v.glsl"),
+    fragment: static_file!("shaders/visibility_resolve.f.glsl")
+});
+
+/// Pad `data` with defaults to whole rows of `DATA_WIDTH`, at least one, returning the rows
+fn pad_rows<T: Default + Clone>(data: &mut Vec<T>) -> usize {
+    let rows = ((data.len() + DATA_WIDTH - 1) / DATA_WIDTH).max(1);
+    data.resize(rows * DATA_WIDTH, Default::default());
+    rows
+}
+
+/// The texels of the vertex data texture: position and u in the first texel of each
+/// vertex, normal and v in the second
+fn pack_vertices(verts: &[VertNTT]) -> Vec<[u32; 4]> {
+    verts.iter()
+        .flat_map(|v| vec![
+            [v.pos[0].to_bits(), v.pos[1].to_bits(), v.pos[2].to_bits(), v.tex[0].to_bits()],
+            [v.norm[0].to_bits(), v.norm[1].to_bits(), v.norm[2].to_bits(), v.tex[1].to_bits()],
+        ])
+        .collect()
+}
+
+/// Pad `data` to whole rows of `DATA_WIDTH` and upload it as a texture
+fn data_texture<F, R, T>(f: &mut F, mut data: Vec<<T::Surface as SurfaceTyped>::DataType>)
+    -> Result<ShaderResourceView<R, T::View>, Error>
+    where F: Factory<R>, R: Resources, T: TextureFormat, <T::Surface as SurfaceTyped>::DataType: Default + Copy
+{
+    use gfx::texture::*;
+    let rows = pad_rows(&mut data);
+    let (_, view) = f.create_texture_immutable::<T>(
+        Kind::D2(DATA_WIDTH as u16, rows as u16, AaMode::Single),
+        Mipmap::Provided,
+        &[&data[..]],
+    )?;
+    Ok(view)
+}
+
+/// Triangles in world space drawn into a `VisibilityBuffer`. Their vertices and indices are
+/// also kept in textures, where the resolve pass looks them up by triangle ID. Merge the
+/// meshes of a scene into one and draw parts of it with `VisibilityBuffer::draw`.
+pub struct VisibilityMesh<R: Resources> {
+    verts: Buffer<R, VertNTT>,
+    slice: Slice<R>,
+    index_data: ShaderResourceView<R, u32>,
+    vertex_data: ShaderResourceView<R, [f32; 4]>,
+    /// The color of the surface, shared by every triangle
+    pub albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
+}
+
+impl<R: Resources> VisibilityMesh<R> {
+    /// Upload a triangle list
+    pub fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        verts: &[VertNTT],
+        inds: &[u32],
+        albedo: Texture<R, (R8_G8_B8_A8, Srgb)>,
+    )
+        -> Result<VisibilityMesh<R>, Error>
+    {
+        let (buf, slice) = f.create_vertex_buffer_with_slice(verts, inds);
+        let vertex_data = pack_vertices(verts);
+        Ok(VisibilityMesh {
+            verts: buf,
+            slice: slice,
+            index_data: data_texture::<_, _, (R32, Uint)>(f, inds.to_vec())?,
+            vertex_data: data_texture::<_, _, (R32_G32_B32_A32, Float)>(f, vertex_data)?,
+            albedo: albedo,
+        })
+    }
+
+    /// The number of triangles
+    pub fn triangles(&self) -> u32 {
+        (self.slice.end - self.slice.start) / 3
+    }
+}
+
+/// Deferred shading from a thin G-buffer holding only the ID of the triangle seen at each
+/// pixel. The ID pass writes nothing but depth and an integer per pixel, which keeps it
+/// cheap for scenes of many small triangles. The resolve pass then fetches the vertices of
+/// each pixel's triangle, finds the barycentric weights where the view ray crosses it and
+/// shades it with a sun and ambient light, so each pixel is shaded once.
+///
+/// `clear`, then `draw` the visible parts of a `VisibilityMesh`, then `resolve` onto the color
+/// target. The buffer must match the size of that target.
+pub struct VisibilityBuffer<R: Resources> {
+    /// The triangle IDs, `NO_TRIANGLE` where nothing was drawn
+    pub ids: RenderTargetView<R, VisibilityFormat>,
+    /// The depth tested by the ID pass
+    pub depth: DepthRef<R>,
+    /// Towards the sun
+    pub sun_dir: Vector3<f32>,
+    /// The light of the sun
+    pub sun_color: [f32; 3],
+    /// The light coming from everywhere else
+    pub ambient: [f32; 3],
+    pub width: u16,
+    pub height: u16,
+    id_view: ShaderResourceView<R, u32>,
+    nearest: Sampler<R>,
+    id_pso: PipelineState<R, ids::Meta>,
+    resolve_pso: PipelineState<R, resolve::Meta>,
+    transform: Buffer<R, TransformBlock>,
+    draw_params: Buffer<R, VisibilityDrawBlock>,
+    resolve_params: Buffer<R, ResolveBlock>,
+}
+
+impl<R: Resources> VisibilityBuffer<R> {
+    /// Create a buffer with the given size in pixels
+    pub fn new<F: Factory<R> + FactoryExt<R>>(f: &mut F, width: u16, height: u16) -> Result<VisibilityBuffer<R>, Error> {
+        use gfx::texture::*;
+        let id_shaders = id_shader(f)?;
+        let resolve_shaders = resolve_shader(f)?;
+        let (_, id_view, id) = f.create_render_target::<VisibilityFormat>(width, height)?;
+        Ok(VisibilityBuffer {
+            ids: id,
+            depth: f.create_depth_stencil_view_only::<DepthFormat>(width, height)?,
+            sun_dir: Vector3::y(),
+            sun_color: [1., 1., 1.],
+            ambient: [0.1, 0.1, 0.1],
+            width: width,
+            height: height,
+            id_view: id_view,
+            // integer textures can't be filtered
+            nearest: f.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp)),
+            id_pso: f.create_pipeline_state(&id_shaders, Primitive::TriangleList, Rasterizer::new_fill(), ids::new())?,
+            resolve_pso: f.create_pipeline_state(&resolve_shaders, Primitive::TriangleList, Rasterizer::new_fill(), resolve::new())?,
+            transform: f.create_constant_buffer(1),
+            draw_params: f.create_constant_buffer(1),
+            resolve_params: f.create_constant_buffer(1),
+        })
+    }
+
+    /// Match the size of the color target the buffer is resolved onto, and fire
+    /// `TargetsResized` on `events`
+    pub fn resize<F: Factory<R>>(&mut self, f: &mut F, events: &mut RenderEventBus, width: u16, height: u16) -> Result<(), Error> {
+        let (_, id_view, id) = f.create_render_target::<VisibilityFormat>(width, height)?;
+        self.ids = id;
+        self.id_view = id_view;
+        self.depth = f.create_depth_stencil_view_only::<DepthFormat>(width, height)?;
+        self.width = width;
+        self.height = height;
+        events.fire(RenderEvent::TargetsResized(width, height));
+        Ok(())
+    }
+
+    /// Forget everything drawn, before drawing a frame
+    pub fn clear<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>) {
+        ctx.encoder.clear(&self.ids, NO_TRIANGLE);
+        ctx.encoder.clear_depth(&self.depth, super::DEPTH_CONVENTION.far());
+    }
+
+    /// Write the IDs of a range of the triangles of `mesh` wherever they are closest to the
+    /// eyes. IDs count from the start of the mesh, not of the range.
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, mesh: &VisibilityMesh<R>, triangles: Range<u32>) {
+        profile_scope!("visibility_ids");
+        let end = triangles.end.min(mesh.triangles());
+        if triangles.start >= end { return }
+        let slice = Slice {
+            start: mesh.slice.start + triangles.start * 3,
+            end: mesh.slice.start + end * 3,
+            ..mesh.slice.clone()
+        };
+        ctx.encoder.update_constant_buffer(&self.draw_params, &VisibilityDrawBlock {
+            first_triangle: triangles.start,
+        });
+        let identity = Transform3::<f32>::identity().downgrade();
+        for &(ref block, clip) in &eye_transforms(ctx, identity, &ctx.lens_shading) {
+            if clip.w == 0 || clip.h == 0 { continue }
+            ctx.encoder.update_constant_buffer(&self.transform, block);
+            ctx.encoder.draw(&slice, &self.id_pso, &ids::Data {
+                verts: mesh.verts.clone(),
+                transform: self.transform.clone(),
+                draw: self.draw_params.clone(),
+                scissor: clip,
+                id: self.ids.clone(),
+                depth: self.depth.clone(),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+
+    /// Shade every pixel that has a triangle of `mesh` onto the color target of `ctx`
+    pub fn resolve<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, mesh: &VisibilityMesh<R>) {
+        profile_scope!("visibility_resolve");
+        let slice = Slice {
+            start: 0,
+            end: 3,
+            base_vertex: 0,
+            instances: None,
+            buffer: IndexBuffer::Auto,
+        };
+        let (s, a) = (self.sun_color, self.ambient);
+        let sun = self.sun_dir.try_normalize(1e-6).unwrap_or(Vector3::y());
+        for eye in &ctx.eyes() {
+            if eye.clip.w == 0 || eye.clip.h == 0 { continue }
+            let clip = eye.clip;
+            ctx.encoder.update_constant_buffer(&self.resolve_params, &ResolveBlock {
+                inv_view_proj: inverse_view_proj(eye).downgrade(),
+                eye_pos: eye.eye.to_homogeneous().downgrade(),
+                viewport: [clip.x as f32, clip.y as f32, clip.w as f32, clip.h as f32],
+                sun_dir: sun.to_homogeneous().downgrade(),
+                sun_color: [s[0], s[1], s[2], 1.],
+                ambient: [a[0], a[1], a[2], 0.],
+                gamma: ::OUTPUT_GAMMA,
+            });
+            ctx.encoder.draw(&slice, &self.resolve_pso, &resolve::Data {
+                params: self.resolve_params.clone(),
+                scissor: clip,
+                color: ctx.color.clone(),
+                ids: (self.id_view.clone(), self.nearest.clone()),
+                indices: (mesh.index_data.clone(), self.nearest.clone()),
+                vertices: (mesh.vertex_data.clone(), self.nearest.clone()),
+                albedo: mesh.albedo.clone().into_tuple(),
+            });
+            ctx.draw_calls += 1;
+        }
+    }
+}
+
+#[test]
+fn visibility_data() {
+    let v = VertNTT {
+        pos: [1., 2., 3.],
+        norm: [0., 1., 0.],
+        tan: [1., 0., 0.],
+        bitan: [0., 0., 1.],
+        tex: [0.25, 0.75],
+    };
+    let packed = pack_vertices(&[v, v]);
+    assert_eq!(packed.len(), 4);
+    assert_eq!(packed[2], [1f32.to_bits(), 2f32.to_bits(), 3f32.to_bits(), 0.25f32.to_bits()]);
+    assert_eq!(packed[3], [0, 1f32.to_bits(), 0, 0.75f32.to_bits()]);
+
+    // data is padded to whole rows, with a row even for nothing
+    let mut data = vec![7u32; DATA_WIDTH + 1];
+    assert_eq!(pad_rows(&mut data), 2);
+    assert_eq!(data.len(), 2 * DATA_WIDTH);
+    assert_eq!(data[DATA_WIDTH], 7);
+    assert_eq!(data[DATA_WIDTH + 1], 0);
+    assert_eq!(pad_rows(&mut Vec::<u32>::new()), 1);
+}
@@ -0,0 +1,338 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::handle::Buffer;
+use gfx::state::{Rasterizer, CullFace};
+use gfx::format::*;
+
+use super::{StyleInputs, Style, TransformBlock};
+use ::mesh::{Primitive, MeshSource, Indexing, Vert};
+use ::{Error, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+
+/// The way samples along each ray are combined into a pixel
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VolumeMode {
+    /// Accumulate color and opacity from the transfer function
+    Composite,
+    /// Maximum intensity projection (brightest sample along each ray)
+    Mip,
+    /// Minimum intensity projection (darkest sample along each ray)
+    MinIp,
+    /// Average intensity projection (mean of all samples along each ray)
+    AverageProjn,
+}
+
+/// All volume modes, in the order their pipelines are stored
+pub const VOLUME_MODES: [VolumeMode; 4] = [
+    VolumeMode::Composite,
+    VolumeMode::Mip,
+    VolumeMode::MinIp,
+    VolumeMode::AverageProjn,
+];
+
+impl VolumeMode {
+    fn index(&self) -> usize {
+        use self::VolumeMode::*;
+        match *self {
+            Composite => 0,
+            Mip => 1,
+            MinIp => 2,
+            AverageProjn => 3,
+        }
+    }
+
+    fn define(&self) -> &'static str {
+        use self::VolumeMode::*;
+        match *self {
+            Composite => "MODE_COMPOSITE",
+            Mip => "MODE_MIP",
+            MinIp => "MODE_MINIP",
+            AverageProjn => "MODE_AVERAGE",
+        }
+    }
+}
+
+/// The voxel data of a volumetric dataset
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum VolumeData<R: Resources> {
+    /// Single-channel 3D density texture (e.g. a CT scan)
+    Scalar(Texture<R, (R8, Unorm)>),
+    /// Up to four channels (e.g. fused PET/CT) blended by `VolumeInputs::set_channel_weights`
+    Channels(Texture<R, (R8_G8_B8_A8, Unorm)>),
+}
+
+/// The textures describing a volumetric dataset
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct VolumeMaterial<R: Resources> {
+    /// Voxel data
+    pub data: VolumeData<R>,
+    /// Maps density (u coordinate) to color and opacity, only used by `VolumeMode::Composite`
+    pub transfer: Texture<R, (R8_G8_B8_A8, Srgb)>,
+}
+
+gfx_defines!{
+    constant VolumeBlock {
+        channel_weights: [f32; 4] = "channel_weights",
+        steps: i32 = "steps",
+        opacity: f32 = "opacity",
+        brightness: f32 = "brightness",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<Vert> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<VolumeBlock> = "params",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+
+        density: gfx::TextureSampler<f32> = "volume_tex",
+        transfer: gfx::TextureSampler<[f32; 4]> = "transfer_tex",
+    }
+
+    pipeline multi {
+        verts: gfx::VertexBuffer<Vert> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<VolumeBlock> = "params",
+        scissor: gfx::Scissor = (), // TODO: Replace scissoring with viewport
+
+        color: gfx::BlendTarget<ColorFormat> = ("f_color", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+        depth: gfx::DepthTarget<DepthFormat> = ::draw::DEPTH_TEST,
+
+        channels: gfx::TextureSampler<[f32; 4]> = "volume_tex",
+        transfer: gfx::TextureSampler<[f32; 4]> = "transfer_tex",
+    }
+}
+
+fn shader<R: gfx::Resources, F: gfx::Factory<R>>(factory: &mut F, mode: VolumeMode, channels: bool)
+    -> Result<gfx::ShaderSet<R>, Error>
+{
+    let mut fragment = static_file!("shaders/volume.f.glsl")
+        .define_to("I_POS", "v_pos")
+        .define(mode.define());
+    if channels {
+        fragment = fragment.define("CHANNELS");
+    }
+    Ok(shader_set!(factory,
+        vertex: static_file!("shaders/transform.v.glsl"),
+        fragment: fragment,
+    ))
+}
+
+/// Build a unit cube centered on the origin. Volumes are drawn by rendering this box
+/// (scaled and placed by the model matrix) with a `VolumeStyle` painter.
+pub fn volume_box() -> MeshSource<Vert, ()> {
+    let verts = (0..8).map(|i| Vert { pos: [
+        (i >> 2 & 1) as f32 - 0.5,
+        (i >> 1 & 1) as f32 - 0.5,
+        (i & 1) as f32 - 0.5,
+    ] }).collect();
+    MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(vec![
+            1, 3, 2, 1, 2, 0,
+            4, 6, 7, 4, 7, 5,
+            0, 4, 5, 0, 5, 1,
+            3, 7, 6, 3, 6, 2,
+            2, 6, 4, 2, 4, 0,
+            1, 5, 7, 1, 7, 3,
+        ]),
+        prim: Primitive::TriangleList,
+        mat: (),
+    }
+}
+
+/// The configuration for volume rendering
+pub struct VolumeInputs<R: Resources> {
+    shaders: Vec<ShaderSet<R>>,
+    multi_shaders: Vec<ShaderSet<R>>,
+    mode: VolumeMode,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    channel_weights: [f32; 4],
+    steps: u32,
+    opacity: f32,
+    brightness: f32,
+    params_update: bool,
+    params_block: Buffer<R, VolumeBlock>,
+}
+
+impl<R: Resources> VolumeInputs<R> {
+    /// Set how samples along each ray are combined
+    pub fn set_mode(&mut self, mode: VolumeMode) {
+        self.mode = mode;
+    }
+
+    /// Get the current volume mode
+    pub fn mode(&self) -> VolumeMode {
+        self.mode
+    }
+
+    /// Set how much each channel of a multi-channel volume contributes to the
+    /// sampled density (for example `[1., 0., 0., 0.]` shows only the first channel)
+    pub fn set_channel_weights(&mut self, weights: [f32; 4]) {
+        self.channel_weights = weights;
+        self.params_update = true;
+    }
+
+    /// Set the number of samples taken across the volume box
+    pub fn set_steps(&mut self, steps: u32) {
+        self.steps = steps;
+        self.params_update = true;
+    }
+
+    /// Set the opacity multiplier applied to the transfer function (composite mode only)
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+        self.params_update = true;
+    }
+
+    /// Set the brightness multiplier applied to the final color
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+        self.params_update = true;
+    }
+}
+
+impl<R: Resources> StyleInputs<R> for VolumeInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders[self.mode.index()] }
+}
+
+/// Pipeline data bound by `VolumeStyle`
+pub enum VolumeBound<R: Resources> {
+    Scalar(pl::Data<R>),
+    Channels(multi::Data<R>),
+}
+
+/// Draws 3D textures by ray marching through a box, either compositing through a
+/// transfer function or projecting the density directly (MIP, MinIP, average).
+/// Every mode has its own pipeline, so switching modes never recompiles shaders.
+pub struct VolumeStyle<R: Resources> {
+    psos: Vec<PipelineState<R, pl::Meta>>,
+    multi_psos: Vec<PipelineState<R, multi::Meta>>,
+}
+
+impl<R: Resources> Style<R> for VolumeStyle<R> {
+    type Vertex = Vert;
+    type Inputs = VolumeInputs<R>;
+    type Material = VolumeMaterial<R>;
+    type Bound = VolumeBound<R>;
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut VolumeInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        // Only the back faces are drawn, so that rays still start correctly when
+        // the eye is inside of the volume.
+        let r = Rasterizer { cull_face: CullFace::Front, .. r };
+        let mut psos = Vec::with_capacity(VOLUME_MODES.len());
+        for s in &i.shaders {
+            psos.push(f.create_pipeline_state(s, p, r, pl::new())?);
+        }
+        let mut multi_psos = Vec::with_capacity(VOLUME_MODES.len());
+        for s in &i.multi_shaders {
+            multi_psos.push(f.create_pipeline_state(s, p, r, multi::new())?);
+        }
+        Ok(VolumeStyle {
+            psos: psos,
+            multi_psos: multi_psos,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<VolumeInputs<R>, Error> {
+        let mut shaders = Vec::with_capacity(VOLUME_MODES.len());
+        let mut multi_shaders = Vec::with_capacity(VOLUME_MODES.len());
+        for &m in &VOLUME_MODES {
+            shaders.push(shader(f, m, false)?);
+            multi_shaders.push(shader(f, m, true)?);
+        }
+        Ok(VolumeInputs {
+            shaders: shaders,
+            multi_shaders: multi_shaders,
+            mode: VolumeMode::Composite,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            channel_weights: [1., 0., 0., 0.],
+            steps: 128,
+            opacity: 64.,
+            brightness: 1.,
+            params_update: true,
+            params_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &VolumeInputs<R>,
+        color: TargetRef<R>,
+        depth: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &VolumeMaterial<R>,
+    ) -> VolumeBound<R> {
+        let scissor = Rect { x: 0, y: 0, w: 0, h: 0 };
+        match mat.data {
+            VolumeData::Scalar(ref density) => VolumeBound::Scalar(pl::Data {
+                color: color,
+                depth: depth,
+                verts: buf,
+                scissor: scissor,
+                transform: inputs.transform_block.clone(),
+                params: inputs.params_block.clone(),
+                density: density.clone().into_tuple(),
+                transfer: mat.transfer.clone().into_tuple(),
+            }),
+            VolumeData::Channels(ref channels) => VolumeBound::Channels(multi::Data {
+                color: color,
+                depth: depth,
+                verts: buf,
+                scissor: scissor,
+                transform: inputs.transform_block.clone(),
+                params: inputs.params_block.clone(),
+                channels: channels.clone().into_tuple(),
+                transfer: mat.transfer.clone().into_tuple(),
+            }),
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut VolumeInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut VolumeBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        if inputs.params_update {
+            enc.update_constant_buffer(&inputs.params_block, &VolumeBlock {
+                channel_weights: inputs.channel_weights,
+                steps: inputs.steps as i32,
+                opacity: inputs.opacity,
+                brightness: inputs.brightness,
+            });
+            inputs.params_update = false;
+        }
+        match *bound {
+            VolumeBound::Scalar(ref mut data) => {
+                data.scissor = scissor;
+                enc.draw(slice, &self.psos[inputs.mode.index()], data);
+            },
+            VolumeBound::Channels(ref mut data) => {
+                data.scissor = scissor;
+                enc.draw(slice, &self.multi_psos[inputs.mode.index()], data);
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,534 @@
+use gfx::{self, Resources, CommandBuffer, ShaderSet, Factory, Rect, Slice, Encoder};
+use gfx::pso::PipelineState;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use gfx::handle::{Buffer, RawShaderResourceView};
+use gfx::state::Rasterizer;
+use nalgebra::{Point3, Vector3, Vector4, Matrix4, Transform3};
+
+use super::{StyleInputs, Style, TransformBlock, DrawParams, EyeParams, OffscreenTarget, LinearFormat, LogDepth};
+use ::mesh::{Primitive, VertNTT};
+use ::{Error, FlightError, ColorFormat, DepthFormat, TargetRef, DepthRef, Texture};
+use ::util::NativeRepr;
+
+/// The ripples drawn by `WaterStyle`
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct WaterMaterial<R: Resources> {
+    /// Tiling normal map (tangent space, +Z out of the surface), used by both layers
+    pub normal: Texture<R, LinearFormat>,
+}
+
+/// One of the two layers of ripples blended on the water surface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WaterLayer {
+    /// The width in meters of one repeat of the normal map
+    pub scale: f32,
+    /// How fast the layer scrolls across the surface, in meters per second along X and Z
+    pub velocity: [f32; 2],
+    /// How much the layer tilts the surface
+    pub strength: f32,
+}
+
+gfx_defines!{
+    constant WaterBlock {
+        reflect_left: [[f32; 4]; 4] = "reflect_left",
+        reflect_right: [[f32; 4]; 4] = "reflect_right",
+        layer_a: [f32; 4] = "layer_a",
+        layer_b: [f32; 4] = "layer_b",
+        deep_color: [f32; 4] = "deep_color",
+        sun_dir: [f32; 4] = "sun_dir",
+        sun_color: [f32; 4] = "sun_color",
+        distortion: f32 = "distortion",
+        shore_fade: f32 = "shore_fade",
+    }
+
+    pipeline pl {
+        verts: gfx::VertexBuffer<VertNTT> = (),
+        transform: gfx::ConstantBuffer<TransformBlock> = "transform",
+        params: gfx::ConstantBuffer<WaterBlock> = "water",
+        scissor: gfx::Scissor = (),
+        // the scene depth is tested in the shader, since it is sampled for the shoreline
+        color: gfx::RenderTarget<ColorFormat> = "f_color",
+        normal: gfx::TextureSampler<[f32; 4]> = "normal_tex",
+        reflection: gfx::TextureSampler<[f32; 4]> = "reflection_tex",
+        refraction: gfx::TextureSampler<[f32; 4]> = "refraction_tex",
+        scene_depth: gfx::TextureSampler<f32> = "depth_tex",
+    }
+}
+
+shader!(shader {
+    vertex: static_file!("shaders/transform.v.glsl"),
+    fragment: static_file!("shaders/water.f.glsl")
+        .define_to("I_POS", "v_pos")
+});
+
+/// How planar reflections are shared between the eyes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ReflectionMode {
+    /// Each eye gets its own reflection, at half its resolution
+    PerEye,
+    /// One reflection is drawn from between the eyes, at half the resolution of one eye.
+    /// This halves the cost again, but both eyes see the same reflected image projected
+    /// onto the water, so reflections lose their stereo depth: nearby reflected objects
+    /// look painted onto the surface instead of sitting below it. Far away scenery, which
+    /// has little parallax anyway, looks the same either way.
+    Shared,
+}
+
+/// Mirror an eye through the horizontal plane at height `level`, for drawing what a water
+/// surface at that height reflects. The near plane of the projection is bent onto the
+/// water (Lengyel, "Oblique View Frustum Depth Projection and Clipping") so that nothing
+/// under the surface ends up in the reflection. An eye under the water is mirrored without
+/// clipping.
+pub fn reflect_eye(eye: &EyeParams, level: f32) -> EyeParams {
+    let mut mirror = Matrix4::new_nonuniform_scaling(&Vector3::new(1., -1., 1.));
+    mirror[(1, 3)] = 2. * level;
+    let view = eye.view.matrix() * mirror;
+    let mut proj = *eye.proj.matrix();
+
+    let inverses = view.try_inverse().and_then(|v| (half_x() * proj).try_inverse().map(|p| (v, p)));
+    match inverses {
+        Some((inv_view, inv_proj)) if eye.eye.y >= level => {
+            // the plane in view space, facing away from the mirrored eye
+            let plane = inv_view.transpose() * Vector4::new(0., 1., 0., -level);
+            // the far corner on the kept side, found without the doubling of x for the
+            // transform shader (which doesn't touch the depth row)
+            let corner = inv_proj * Vector4::new(plane.x.signum(), plane.y.signum(), 1., 1.);
+            let c = plane * (2. / plane.dot(&corner));
+            for i in 0..4 {
+                proj[(2, i)] = c[i] - proj[(3, i)];
+            }
+        },
+        _ => (),
+    }
+
+    EyeParams {
+        eye: Point3::new(eye.eye.x, 2. * level - eye.eye.y, eye.eye.z),
+        view: Transform3::from_matrix_unchecked(view),
+        proj: Transform3::from_matrix_unchecked(proj),
+        .. *eye
+    }
+}
+
+/// Halves x, undoing the doubling done for the transform shader
+fn half_x() -> Matrix4<f32> {
+    Matrix4::new_nonuniform_scaling(&Vector3::new(0.5, 1., 1.))
+}
+
+/// An eye halfway between two eyes, looking the same way and seeing about as much as both
+fn center_eye(left: &EyeParams, right: &EyeParams) -> EyeParams {
+    let center = Point3::from_coordinates((left.eye.coords + right.eye.coords) * 0.5);
+    let shift = Matrix4::new_translation(&(left.eye - center));
+    EyeParams {
+        eye: center,
+        view: Transform3::from_matrix_unchecked(left.view.matrix() * shift),
+        proj: Transform3::from_matrix_unchecked((left.proj.matrix() + right.proj.matrix()) * 0.5),
+        clip_offset: 0.,
+        .. *left
+    }
+}
+
+/// The matrix taking world positions to the clip space of the whole target that an eye
+/// draws into, including the halving and offset of x done by the transform shader
+fn target_matrix(eye: &EyeParams) -> Matrix4<f32> {
+    let mut fit = half_x();
+    fit[(0, 3)] = eye.clip_offset;
+    fit * eye.proj.matrix() * eye.view.matrix()
+}
+
+/// The offscreen targets a water surface samples: the planar reflection, drawn between
+/// `begin_reflection` and `end_reflection`, and a copy of the opaque scene under the water
+/// taken by `grab`. A frame usually goes:
+///
+/// 1. `begin_reflection`, draw the scene (mirrored), `end_reflection`
+/// 2. draw the opaque scene into an `OffscreenTarget::with_depth_texture`
+/// 3. `grab` that target, then `WaterInputs::set_targets`
+/// 4. draw the water surface into the same target
+pub struct WaterTargets<R: Resources> {
+    /// The height of the water surface, which must be horizontal
+    pub level: f32,
+    /// Where the mirrored scene is drawn, at half resolution
+    pub reflection: OffscreenTarget<R>,
+    /// The opaque scene color copied by `grab`
+    pub refraction: OffscreenTarget<R>,
+    mode: ReflectionMode,
+    scene_depth: Option<Texture<R, DepthFormat>>,
+    matrices: [Matrix4<f32>; 2],
+    saved: Option<(TargetRef<R>, DepthRef<R>, LogDepth)>,
+}
+
+impl<R: Resources> WaterTargets<R> {
+    /// Create targets for water at height `level` drawn over a color target `width` by
+    /// `height` pixels holding both eyes side by side
+    pub fn new<F: Factory<R>>(f: &mut F, width: u16, height: u16, level: f32, mode: ReflectionMode)
+        -> Result<WaterTargets<R>, Error>
+    {
+        let reflect_width = match mode {
+            ReflectionMode::PerEye => width / 2,
+            ReflectionMode::Shared => width / 4,
+        };
+        Ok(WaterTargets {
+            level: level,
+            reflection: OffscreenTarget::new(f, reflect_width.max(1), (height / 2).max(1))?,
+            refraction: OffscreenTarget::new(f, width, height)?,
+            mode: mode,
+            scene_depth: None,
+            matrices: [Matrix4::identity(); 2],
+            saved: None,
+        })
+    }
+
+    /// How the reflection is shared between the eyes
+    pub fn mode(&self) -> ReflectionMode {
+        self.mode
+    }
+
+    /// Redirect drawing to the reflection target, cleared to black, seen from the eyes of
+    /// `ctx` mirrored through the water. Depth is standard until `end_reflection`, even with
+    /// `DrawParams::log_depth` set.
+    pub fn begin_reflection<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        let half = |e: &EyeParams| Rect { x: e.clip.x / 2, y: e.clip.y / 2, w: e.clip.w / 2, h: e.clip.h / 2 };
+        let (left, right) = match self.mode {
+            ReflectionMode::PerEye => (
+                EyeParams { clip: half(&ctx.left), .. reflect_eye(&ctx.left, self.level) },
+                EyeParams { clip: half(&ctx.right), .. reflect_eye(&ctx.right, self.level) },
+            ),
+            ReflectionMode::Shared => {
+                let all = Rect { x: 0, y: 0, w: self.reflection.width, h: self.reflection.height };
+                let eye = EyeParams { clip: all, .. reflect_eye(&center_eye(&ctx.left, &ctx.right), self.level) };
+                // the right eye draws nothing
+                (eye, EyeParams { clip: Rect { x: 0, y: 0, w: 0, h: 0 }, .. eye })
+            },
+        };
+        self.matrices = match self.mode {
+            ReflectionMode::PerEye => [target_matrix(&left), target_matrix(&right)],
+            ReflectionMode::Shared => [target_matrix(&left); 2],
+        };
+
+        ctx.encoder.clear(&self.reflection.color, [0., 0., 0., 1.]);
+        ctx.encoder.clear_depth(&self.reflection.depth, super::DEPTH_CONVENTION.far());
+        let color = ::std::mem::replace(&mut ctx.color, self.reflection.color.clone());
+        let depth = ::std::mem::replace(&mut ctx.depth, self.reflection.depth.clone());
+        self.saved = Some((color, depth, ctx.log_depth));
+        ctx.push_eyes(left, right);
+        // logarithmic depth would undo the bent near plane
+        ctx.log_depth = LogDepth::off();
+    }
+
+    /// Go back to drawing into the targets and eyes from before `begin_reflection`
+    pub fn end_reflection<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        if let Some((color, depth, log_depth)) = self.saved.take() {
+            ctx.color = color;
+            ctx.depth = depth;
+            ctx.pop_camera();
+            ctx.log_depth = log_depth;
+        }
+    }
+
+    /// Copy the color of the opaque scene for refraction, and keep its depth for the
+    /// shoreline. The scene must be the size given to `new` and have a depth texture.
+    pub fn grab<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>, scene: &OffscreenTarget<R>)
+        -> Result<(), Error>
+    {
+        use gfx::format::Formatted;
+        let depth = match scene.depth_texture {
+            Some(ref d) => d.clone(),
+            None => bail!(FlightError::NoDepthTexture { target: "water scene" }),
+        };
+        let info = scene.color_texture.get_info().to_raw_image_info(ColorFormat::get_format().1, 0);
+        ctx.encoder.copy_texture_to_texture_raw(
+            scene.color_texture.raw(),
+            None,
+            info,
+            self.refraction.color_texture.raw(),
+            None,
+            info,
+        ).map_err(|e| FlightError::TextureCopy { reason: format!("{:?}", e) })?;
+        self.scene_depth = Some(depth);
+        Ok(())
+    }
+}
+
+/// The textures and reflection matrices copied from `WaterTargets`
+struct WaterSources<R: Resources> {
+    reflection: Texture<R, ColorFormat>,
+    refraction: Texture<R, ColorFormat>,
+    scene_depth: Texture<R, DepthFormat>,
+    matrices: [Matrix4<f32>; 2],
+}
+
+/// The configuration for water rendering
+pub struct WaterInputs<R: Resources> {
+    shaders: ShaderSet<R>,
+    transform: Option<TransformBlock>,
+    transform_block: Buffer<R, TransformBlock>,
+    layers: [WaterLayer; 2],
+    time: f32,
+    distortion: f32,
+    deep_color: [f32; 3],
+    clarity: f32,
+    shore_fade: f32,
+    sun_dir: Vector3<f32>,
+    sun_color: [f32; 3],
+    sources: Option<WaterSources<R>>,
+    sources_version: usize,
+    placeholder: OffscreenTarget<R>,
+    params_update: bool,
+    params_block: Buffer<R, WaterBlock>,
+}
+
+impl<R: Resources> StyleInputs<R> for WaterInputs<R> {
+    fn transform(&mut self, block: TransformBlock) { self.transform = Some(block); }
+    fn shader_set(&self) -> &ShaderSet<R> { &self.shaders }
+}
+
+impl<R: Resources> WaterInputs<R> {
+    /// Sample the reflection and scene of `targets`, once they have grabbed a scene. The
+    /// reflection matrices are copied, so call this every frame after drawing the reflection.
+    pub fn set_targets(&mut self, targets: &WaterTargets<R>) {
+        self.sources = targets.scene_depth.clone().map(|depth| WaterSources {
+            reflection: targets.reflection.texture.clone(),
+            refraction: targets.refraction.texture.clone(),
+            scene_depth: depth,
+            matrices: targets.matrices,
+        });
+        self.sources_version += 1;
+        self.params_update = true;
+    }
+
+    /// Set both layers of ripples
+    pub fn set_layers(&mut self, a: WaterLayer, b: WaterLayer) {
+        self.layers = [a, b];
+        self.params_update = true;
+    }
+
+    /// Scroll the ripples along by `dt` seconds
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+        self.params_update = true;
+    }
+
+    /// How far the ripples bend the reflection and refraction, as a fraction of the view
+    pub fn set_distortion(&mut self, distortion: f32) {
+        self.distortion = distortion;
+        self.params_update = true;
+    }
+
+    /// The color of deep water, and the depth in meters through which about two thirds of
+    /// the light from below is replaced by it
+    pub fn set_deep_color(&mut self, color: [f32; 3], clarity: f32) {
+        self.deep_color = color;
+        self.clarity = clarity;
+        self.params_update = true;
+    }
+
+    /// The depth of water in meters over which the surface fades in from the shore
+    pub fn set_shore_fade(&mut self, distance: f32) {
+        self.shore_fade = distance;
+        self.params_update = true;
+    }
+
+    /// Add a highlight for a sun shining towards `dir` with the given color (w intensity)
+    pub fn set_sun(&mut self, dir: Vector3<f32>, color: [f32; 4]) {
+        self.sun_dir = -dir.try_normalize(1e-6).unwrap_or(-Vector3::y());
+        self.sun_color = [color[0] * color[3], color[1] * color[3], color[2] * color[3]];
+        self.params_update = true;
+    }
+
+    fn params(&self) -> WaterBlock {
+        let layer = |l: &WaterLayer| {
+            let scale = l.scale.max(1e-3);
+            // the offset is in repeats of the normal map, wrapped to keep precision
+            let offset = |v: f32| (v * self.time / scale).fract();
+            [offset(l.velocity[0]), offset(l.velocity[1]), 1. / scale, l.strength]
+        };
+        let matrices = self.sources.as_ref()
+            .map(|s| s.matrices)
+            .unwrap_or([Matrix4::identity(); 2]);
+        let (c, s) = (self.deep_color, self.sun_color);
+        WaterBlock {
+            reflect_left: matrices[0].downgrade(),
+            reflect_right: matrices[1].downgrade(),
+            layer_a: layer(&self.layers[0]),
+            layer_b: layer(&self.layers[1]),
+            deep_color: [c[0], c[1], c[2], self.clarity.max(1e-3)],
+            sun_dir: self.sun_dir.to_homogeneous().downgrade(),
+            sun_color: [s[0], s[1], s[2], 0.],
+            distortion: self.distortion,
+            shore_fade: self.shore_fade.max(1e-3),
+        }
+    }
+
+    /// The reflection, refraction and scene depth to bind, from the placeholder target if
+    /// there are none
+    fn textures(&self) -> (Texture<R, ColorFormat>, Texture<R, ColorFormat>, Texture<R, DepthFormat>) {
+        match self.sources {
+            Some(ref s) => (s.reflection.clone(), s.refraction.clone(), s.scene_depth.clone()),
+            None => (
+                self.placeholder.texture.clone(),
+                self.placeholder.texture.clone(),
+                self.placeholder.depth_texture.clone().expect("placeholder has a depth texture"),
+            ),
+        }
+    }
+}
+
+/// Pipeline data and the version of the water targets it samples
+pub struct WaterBound<R: Resources> {
+    data: pl::Data<R>,
+    sources_version: usize,
+}
+
+/// Draws a horizontal water surface (such as a lake or a wet floor) at the level of its
+/// `WaterTargets`, blending the planar reflection with the scene under the water by the
+/// Fresnel term. Two layers of ripples scroll across the surface and bend both, the water
+/// tints with depth and fades out towards the shore. The water is drawn into the target
+/// holding the scene it covers, testing against the scene depth in the shader, and doesn't
+/// write depth. Nothing is drawn until `WaterInputs::set_targets` is called with grabbed
+/// targets.
+pub struct WaterStyle<R: Resources> {
+    pso: PipelineState<R, pl::Meta>,
+}
+
+impl<R: Resources> Style<R> for WaterStyle<R> {
+    type Vertex = VertNTT;
+    type Inputs = WaterInputs<R>;
+    type Material = WaterMaterial<R>;
+    type Bound = WaterBound<R>;
+
+    fn sampled(mat: &WaterMaterial<R>) -> Vec<RawShaderResourceView<R>> {
+        vec![mat.normal.buffer.raw().clone()]
+    }
+
+    fn new<F: Factory<R> + FactoryExt<R>>(
+        f: &mut F,
+        i: &mut WaterInputs<R>,
+        p: Primitive,
+        r: Rasterizer,
+    ) -> Result<Self, Error> {
+        Ok(WaterStyle {
+            pso: f.create_pipeline_state(&i.shaders, p, r, pl::new())?,
+        })
+    }
+
+    fn init<F: Factory<R>>(
+        f: &mut F,
+    ) -> Result<WaterInputs<R>, Error> {
+        let ripple = |scale: f32, x: f32, z: f32| WaterLayer {
+            scale: scale,
+            velocity: [x, z],
+            strength: 1.,
+        };
+        Ok(WaterInputs {
+            shaders: shader(f)?,
+            transform: None,
+            transform_block: f.create_constant_buffer(1),
+            layers: [ripple(4., 0.05, 0.03), ripple(1.5, -0.04, 0.06)],
+            time: 0.,
+            distortion: 0.02,
+            deep_color: [0.02, 0.08, 0.1],
+            clarity: 3.,
+            shore_fade: 0.3,
+            sun_dir: Vector3::y(),
+            sun_color: [0.; 3],
+            sources: None,
+            sources_version: 0,
+            placeholder: OffscreenTarget::with_depth_texture(f, 1, 1)?,
+            params_update: true,
+            params_block: f.create_constant_buffer(1),
+        })
+    }
+
+    fn bind(
+        &self,
+        inputs: &WaterInputs<R>,
+        color: TargetRef<R>,
+        _: DepthRef<R>,
+        buf: Buffer<R, Self::Vertex>,
+        mat: &WaterMaterial<R>,
+    ) -> WaterBound<R> {
+        let (reflection, refraction, scene_depth) = inputs.textures();
+        WaterBound {
+            data: pl::Data {
+                color: color,
+                verts: buf,
+                scissor: Rect { x: 0, y: 0, w: 0, h: 0 },
+                transform: inputs.transform_block.clone(),
+                params: inputs.params_block.clone(),
+                normal: mat.normal.clone().into_tuple(),
+                reflection: reflection.into_tuple(),
+                refraction: refraction.into_tuple(),
+                scene_depth: scene_depth.into_tuple(),
+            },
+            sources_version: inputs.sources_version,
+        }
+    }
+
+    fn draw_bound<C>(
+        &self,
+        inputs: &mut WaterInputs<R>,
+        enc: &mut Encoder<R, C>,
+        scissor: Rect,
+        slice: &Slice<R>,
+        bound: &mut WaterBound<R>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>
+    {
+        if let Some(t) = inputs.transform.take() {
+            enc.update_constant_buffer(&inputs.transform_block, &t);
+        }
+        if inputs.sources.is_none() { return Ok(()) }
+        if inputs.params_update {
+            enc.update_constant_buffer(&inputs.params_block, &inputs.params());
+            inputs.params_update = false;
+        }
+        if bound.sources_version != inputs.sources_version {
+            // the targets were replaced after this mesh was bound
+            let (reflection, refraction, scene_depth) = inputs.textures();
+            bound.data.reflection = reflection.into_tuple();
+            bound.data.refraction = refraction.into_tuple();
+            bound.data.scene_depth = scene_depth.into_tuple();
+            bound.sources_version = inputs.sources_version;
+        }
+        bound.data.scissor = scissor;
+        enc.draw(slice, &self.pso, &bound.data);
+        Ok(())
+    }
+}
+
+#[test]
+fn water_reflection() {
+    use nalgebra::{Isometry3, Perspective3};
+
+    let level = 1.;
+    let pos = Point3::new(0., 3., 0.);
+    let view = Isometry3::look_at_rh(&pos, &Point3::new(0., 0., -6.), &Vector3::y());
+    let persp = Perspective3::new(1., 1.5, 0.1, 100.);
+    let eye = EyeParams {
+        eye: pos,
+        view: Transform3::from_matrix_unchecked(view.to_homogeneous()),
+        proj: Transform3::from_matrix_unchecked(
+            Matrix4::new_nonuniform_scaling(&Vector3::new(2., 1., 1.)) * persp.to_homogeneous()),
+        clip_offset: -0.5,
+        clip: Rect { x: 0, y: 0, w: 100, h: 100 },
+    };
+    let mirrored = reflect_eye(&eye, level);
+    assert!(relative_eq!(mirrored.eye, Point3::new(0., -1., 0.)));
+
+    let clip = |e: &EyeParams, p: Point3<f32>| {
+        let c = e.proj.matrix() * e.view.matrix() * p.to_homogeneous();
+        c / c.w
+    };
+    // the surface is seen in the same place from both
+    let surface = Point3::new(0.5, level, -4.);
+    let (a, b) = (clip(&eye, surface), clip(&mirrored, surface));
+    assert!(relative_eq!(a.x, b.x, epsilon = 1e-4) && relative_eq!(a.y, b.y, epsilon = 1e-4));
+    // a point above the water is reflected in front of the bent near plane, one below isn't
+    assert!(clip(&mirrored, Point3::new(0., level + 0.5, -4.)).z > -1.);
+    assert!(clip(&mirrored, Point3::new(0., level - 0.5, -4.)).z < -1.);
+
+    // the target matrix lands the surface on the left half of the target
+    let t = target_matrix(&eye) * surface.to_homogeneous();
+    assert!(t.x / t.w < 0. && t.x / t.w > -1.);
+}
@@ -0,0 +1,157 @@
+use gfx::Resources;
+
+use ::draw::UberEnv;
+
+/// An axis aligned box (such as the walls of a room) that reflections from a probe are
+/// projected onto, so that they line up with the surroundings away from the probe center
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProbeBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+/// An area around a reflection probe where it lights the scene. Inside `inner_radius` only
+/// this probe is used, and its influence fades out towards `outer_radius`.
+pub struct ProbeBlendZone<R: Resources> {
+    /// Where the probe was captured
+    pub center: [f32; 3],
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    /// The environment captured by the probe
+    pub probe: UberEnv<R>,
+    /// Correct reflections for parallax against this box, or treat the environment as
+    /// infinitely far away if `None`
+    pub parallax: Option<ProbeBox>,
+}
+
+impl<R: Resources> ProbeBlendZone<R> {
+    /// The influence of this zone at the given position, from 0 outside to 1 inside
+    pub fn weight(&self, world_pos: [f32; 3]) -> f32 {
+        zone_weight(self.center, self.inner_radius, self.outer_radius, world_pos)
+    }
+}
+
+fn zone_weight(center: [f32; 3], inner: f32, outer: f32, pos: [f32; 3]) -> f32 {
+    let d = (0..3).map(|i| (pos[i] - center[i]) * (pos[i] - center[i])).sum::<f32>().sqrt();
+    if d <= inner {
+        1.
+    } else if d >= outer {
+        0.
+    } else {
+        1. - (d - inner) / (outer - inner)
+    }
+}
+
+/// The probes lighting a position: the result is `primary` blended towards `secondary` by
+/// `blend`. `None` stands for the global environment set on the uber painter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProbeContributions {
+    pub primary: Option<usize>,
+    pub secondary: Option<usize>,
+    pub blend: f32,
+}
+
+impl ProbeContributions {
+    /// Only the global environment
+    pub fn global() -> ProbeContributions {
+        ProbeContributions {
+            primary: None,
+            secondary: None,
+            blend: 0.,
+        }
+    }
+}
+
+/// Pick the two most influential zones given the weight of every zone
+fn contributions<I: IntoIterator<Item = f32>>(weights: I) -> ProbeContributions {
+    let mut best: [Option<(usize, f32)>; 2] = [None, None];
+    for (i, w) in weights.into_iter().enumerate() {
+        if w <= 0. { continue }
+        match best {
+            [Some((_, a)), _] if w <= a => {
+                if best[1].map(|(_, b)| w > b).unwrap_or(true) {
+                    best[1] = Some((i, w));
+                }
+            },
+            _ => {
+                best[1] = best[0];
+                best[0] = Some((i, w));
+            },
+        }
+    }
+    match best {
+        [None, _] => ProbeContributions::global(),
+        [Some((i, w)), None] => ProbeContributions {
+            primary: Some(i),
+            secondary: None,
+            blend: 1. - w,
+        },
+        [Some((i, a)), Some((j, b))] => ProbeContributions {
+            primary: Some(i),
+            secondary: Some(j),
+            blend: b / (a + b),
+        },
+    }
+}
+
+/// A set of reflection probes with overlapping zones of influence, so that reflections
+/// change smoothly when moving between (for example) indoor and outdoor areas
+pub struct ProbeManager<R: Resources> {
+    zones: Vec<ProbeBlendZone<R>>,
+}
+
+impl<R: Resources> ProbeManager<R> {
+    /// Create a manager without any probes
+    pub fn new() -> ProbeManager<R> {
+        ProbeManager {
+            zones: Vec::new(),
+        }
+    }
+
+    /// Add a zone, returning its index
+    pub fn add(&mut self, zone: ProbeBlendZone<R>) -> usize {
+        self.zones.push(zone);
+        self.zones.len() - 1
+    }
+
+    /// The zone with the given index
+    pub fn zone(&self, index: usize) -> Option<&ProbeBlendZone<R>> {
+        self.zones.get(index)
+    }
+
+    /// Every zone, in the order they were added
+    pub fn zones(&self) -> &[ProbeBlendZone<R>] {
+        &self.zones
+    }
+
+    /// Find the (up to two) probes lighting the given position and how to blend them.
+    /// Where only one zone reaches, it is blended with the global environment instead.
+    pub fn evaluate(&self, world_pos: [f32; 3]) -> ProbeContributions {
+        contributions(self.zones.iter().map(|z| z.weight(world_pos)))
+    }
+}
+
+impl<R: Resources> Default for ProbeManager<R> {
+    fn default() -> ProbeManager<R> {
+        ProbeManager::new()
+    }
+}
+
+#[test]
+fn probe_blending() {
+    assert_eq!(zone_weight([0.; 3], 1., 3., [0.5, 0., 0.]), 1.);
+    assert!(relative_eq!(zone_weight([0.; 3], 1., 3., [0., 2., 0.]), 0.5));
+    assert_eq!(zone_weight([0.; 3], 1., 3., [0., 0., 4.]), 0.);
+
+    assert_eq!(contributions(vec![0., 0.]), ProbeContributions::global());
+    // half way out of a single zone, half the global environment
+    let c = contributions(vec![0., 0.5]);
+    assert_eq!((c.primary, c.secondary), (Some(1), None));
+    assert!(relative_eq!(c.blend, 0.5));
+    // the strongest two zones win
+    let c = contributions(vec![0.2, 1., 0.6]);
+    assert_eq!((c.primary, c.secondary), (Some(1), Some(2)));
+    assert!(relative_eq!(c.blend, 0.375));
+    let c = contributions(vec![0.6, 0.2, 1.]);
+    assert_eq!((c.primary, c.secondary), (Some(2), Some(0)));
+}
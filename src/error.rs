@@ -10,4 +10,125 @@ pub enum FlightError {
     CubemapSizeMismatch {
         expected: u32,
     },
+    #[fail(display = "Expected {} bytes of texture data but {} were given", expected, given)]
+    TextureSizeMismatch {
+        expected: usize,
+        given: usize,
+    },
+    #[fail(display = "Invalid NIfTI volume: {}", reason)]
+    InvalidNifti {
+        reason: &'static str,
+    },
+    #[fail(display = "Invalid vmesh data: {}", reason)]
+    InvalidVmesh {
+        reason: &'static str,
+    },
+    #[fail(display = "Invalid shape parameters: {}", reason)]
+    InvalidShape {
+        reason: &'static str,
+    },
+    #[fail(display = "Font metrics are missing the {} field", field)]
+    InvalidFontMetrics {
+        field: &'static str,
+    },
+    #[fail(display = "The {} index {} is out of range for length {}", what, index, len)]
+    IndexOutOfRange {
+        what: &'static str,
+        index: usize,
+        len: usize,
+    },
+    #[fail(display = "No pass named {} has been registered", name)]
+    UnknownPass {
+        name: &'static str,
+    },
+    #[fail(display = "A pass named {} is already registered", name)]
+    DuplicatePass {
+        name: &'static str,
+    },
+    #[fail(display = "The {} pass both reads and writes the {} target", pass, target)]
+    PassHazard {
+        pass: &'static str,
+        target: &'static str,
+    },
+    #[fail(display = "The {} pass requires the {} pass to run first this frame", pass, requires)]
+    MissingPrerequisite {
+        pass: &'static str,
+        requires: &'static str,
+    },
+    #[fail(display = "The {} pass reads the {} target before the {} pass has written it", pass, target, writer)]
+    PassOutOfOrder {
+        pass: &'static str,
+        target: &'static str,
+        writer: &'static str,
+    },
+    #[fail(display = "Circular shader include: {:?}", chain)]
+    CircularInclude {
+        chain: Vec<String>,
+    },
+    #[fail(display = "Malformed include directive on line {} of {}", line, file)]
+    MalformedInclude {
+        file: String,
+        line: usize,
+    },
+    #[fail(display = "Could not create a headless rendering context: {}", reason)]
+    Headless {
+        reason: String,
+    },
+    #[fail(display = "There is no golden image named {}", name)]
+    MissingGolden {
+        name: String,
+    },
+    #[fail(display = "{} of {} pixels differ from the golden image {}", differing, total, name)]
+    GoldenMismatch {
+        name: String,
+        differing: usize,
+        total: usize,
+    },
+    #[fail(display = "A draw samples the {} target while drawing into it", target)]
+    ResourceStateConflict {
+        target: &'static str,
+    },
+    #[fail(display = "The {} target has no depth texture to sample", target)]
+    NoDepthTexture {
+        target: &'static str,
+    },
+    #[fail(display = "The texture set in {} has no {} map", dir, role)]
+    MissingTexture {
+        dir: String,
+        role: &'static str,
+    },
+    #[fail(display = "Several files could be the {} map: {:?}", role, candidates)]
+    AmbiguousTexture {
+        role: &'static str,
+        candidates: Vec<String>,
+    },
+    #[fail(display = "Could not update a texture: {}", reason)]
+    TextureUpdate {
+        reason: String,
+    },
+    #[fail(display = "Could not copy a texture: {}", reason)]
+    TextureCopy {
+        reason: String,
+    },
+    #[fail(display = "The images don't fit in a {} pixel wide atlas", max_size)]
+    AtlasFull {
+        max_size: u32,
+    },
+    #[fail(display = "No more than {} depth biases can be set up", max)]
+    DepthBiasVariants {
+        max: usize,
+    },
+    #[fail(display = "Invalid config on line {}: {}", line, reason)]
+    InvalidConfig {
+        line: usize,
+        reason: &'static str,
+    },
+    #[fail(display = "Invalid virtual texture: {}", reason)]
+    InvalidVirtualTexture {
+        reason: &'static str,
+    },
+    #[fail(display = "Nothing has been captured by the {} yet", what)]
+    NotCaptured {
+        what: &'static str,
+    },
 }
@@ -0,0 +1,410 @@
+use gfx::{Resources, Factory, CommandBuffer};
+use nalgebra::{self as na, Point3, Vector3, UnitQuaternion, Similarity3, Translation3, Isometry3, Transform3, Unit};
+use std::f32::consts::PI;
+
+use ::{Ray, Error, Texture, ColorFormat};
+use ::draw::{DrawParams, Painter, UnlitStyle, UnlitMaterial};
+use ::mesh::{Mesh, VertNTT, gen};
+
+/// What dragging a gizmo handle does
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GizmoMode {
+    /// Move along one of the object's axes
+    Translate,
+    /// Rotate about one of the object's axes
+    Rotate,
+    /// Scale uniformly
+    Scale,
+}
+
+/// A part of the gizmo that can be grabbed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GizmoHandle {
+    /// The arrow or ring of the given axis (0 = X, 1 = Y, 2 = Z)
+    Axis(usize),
+    /// The box in the middle
+    Center,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Drag {
+    handle: GizmoHandle,
+    start: Similarity3<f32>,
+    scale: f32,
+    value: f32,
+}
+
+/// The interaction state of a gizmo, separate from its meshes. Handles are picked with a
+/// controller ray and dragged while the trigger is held.
+#[derive(Clone, Debug)]
+pub struct GizmoControl {
+    /// What dragging a handle does
+    pub mode: GizmoMode,
+    /// Length of the axes as a fraction of the distance to the eye, which keeps the
+    /// apparent size of the gizmo the same at any distance
+    pub size: f32,
+    /// Translation increment in world units (0 disables snapping)
+    pub translate_snap: f32,
+    /// Rotation increment in radians (0 disables snapping)
+    pub rotate_snap: f32,
+    /// Scale factor increment (0 disables snapping)
+    pub scale_snap: f32,
+    hovered: Option<GizmoHandle>,
+    drag: Option<Drag>,
+    pressed: bool,
+    frame: Similarity3<f32>,
+}
+
+/// Thickness of the pickable area around handles, relative to the axis length
+const PICK_RADIUS: f32 = 0.08;
+/// Half size of the center box, relative to the axis length
+const CENTER_SIZE: f32 = 0.12;
+
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0. { (value / step).round() * step } else { value }
+}
+
+/// Parameter along the line `origin + t * axis` closest to the ray, or `None` when they
+/// are parallel
+fn closest_on_axis(origin: Point3<f32>, axis: Vector3<f32>, ray: &Ray) -> Option<f32> {
+    let dir = ray.dir.normalize();
+    let w = origin - ray.origin;
+    let b = axis.dot(&dir);
+    let denom = 1. - b * b;
+    if denom < 1e-6 {
+        return None;
+    }
+    Some((b * dir.dot(&w) - axis.dot(&w)) / denom)
+}
+
+/// Where the ray hits the plane through `origin` with the given normal
+fn hit_plane(origin: Point3<f32>, normal: Vector3<f32>, ray: &Ray) -> Option<(f32, Point3<f32>)> {
+    let d = ray.dir.dot(&normal);
+    if d.abs() < 1e-6 {
+        return None;
+    }
+    let t = (origin - ray.origin).dot(&normal) / d;
+    if t < 0. { None } else { Some((t, ray.at(t))) }
+}
+
+/// The unit vector along axis `i` (0 for X, 1 for Y, 2 for Z)
+fn unit_axis(i: usize) -> Vector3<f32> {
+    let mut v = Vector3::zeros();
+    v[i] = 1.;
+    v
+}
+
+/// The angle of a point around an axis, measured from the next axis over
+fn angle_about(frame: &Similarity3<f32>, axis: usize, p: Point3<f32>) -> f32 {
+    let rot = frame.isometry.rotation;
+    let u = rot * unit_axis((axis + 1) % 3);
+    let w = rot * unit_axis((axis + 2) % 3);
+    let v = p.coords - frame.isometry.translation.vector;
+    v.dot(&w).atan2(v.dot(&u))
+}
+
+impl GizmoControl {
+    /// Create a translation gizmo without snapping
+    pub fn new() -> GizmoControl {
+        GizmoControl {
+            mode: GizmoMode::Translate,
+            size: 0.15,
+            translate_snap: 0.,
+            rotate_snap: 0.,
+            scale_snap: 0.,
+            hovered: None,
+            drag: None,
+            pressed: false,
+            frame: Similarity3::identity(),
+        }
+    }
+
+    /// The handle under the ray (or being dragged) as of the last update
+    pub fn hovered(&self) -> Option<GizmoHandle> {
+        self.drag.map(|d| d.handle).or(self.hovered)
+    }
+
+    /// True while a handle is being dragged
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Where the gizmo is drawn, at the object's position and orientation and scaled by
+    /// its distance to the eye
+    pub fn frame(&self) -> Similarity3<f32> {
+        self.frame
+    }
+
+    fn axis(&self, frame: &Similarity3<f32>, i: usize) -> Vector3<f32> {
+        frame.isometry.rotation * unit_axis(i)
+    }
+
+    /// Find the closest handle hit by the ray, if any
+    fn pick(&self, frame: &Similarity3<f32>, ray: &Ray) -> Option<GizmoHandle> {
+        let center = Point3::from_coordinates(frame.isometry.translation.vector);
+        let s = frame.scaling();
+        let dir = ray.dir.normalize();
+        let mut best: Option<(f32, GizmoHandle)> = None;
+        let mut offer = |t: f32, h: GizmoHandle| {
+            if t >= 0. && best.map_or(true, |(bt, _)| t < bt) {
+                best = Some((t, h));
+            }
+        };
+        match self.mode {
+            GizmoMode::Translate => for i in 0..3 {
+                let axis = self.axis(frame, i);
+                if let Some(a) = closest_on_axis(center, axis, ray) {
+                    let a = a.max(0.).min(s);
+                    let p = center + axis * a;
+                    let t = (p - ray.origin).dot(&dir);
+                    if (ray.origin + dir * t - p).norm() < PICK_RADIUS * s {
+                        offer(t, GizmoHandle::Axis(i));
+                    }
+                }
+            },
+            GizmoMode::Rotate => for i in 0..3 {
+                if let Some((t, p)) = hit_plane(center, self.axis(frame, i), ray) {
+                    if ((p - center).norm() - s).abs() < PICK_RADIUS * s {
+                        offer(t * ray.dir.norm(), GizmoHandle::Axis(i));
+                    }
+                }
+            },
+            GizmoMode::Scale => {
+                let t = (center - ray.origin).dot(&dir);
+                if (ray.origin + dir * t - center).norm() < CENTER_SIZE * s * 3f32.sqrt() {
+                    offer(t, GizmoHandle::Center);
+                }
+            },
+        }
+        best.map(|(_, h)| h)
+    }
+
+    /// The scalar a drag of the given handle tracks: distance along the axis, angle about
+    /// the axis, or height across the view.
+    fn measure(&self, handle: GizmoHandle, frame: &Similarity3<f32>, eye: Point3<f32>, ray: &Ray) -> Option<f32> {
+        let center = Point3::from_coordinates(frame.isometry.translation.vector);
+        match (self.mode, handle) {
+            (GizmoMode::Translate, GizmoHandle::Axis(i)) => closest_on_axis(center, self.axis(frame, i), ray),
+            (GizmoMode::Rotate, GizmoHandle::Axis(i)) => hit_plane(center, self.axis(frame, i), ray)
+                .map(|(_, p)| angle_about(frame, i, p)),
+            (GizmoMode::Scale, GizmoHandle::Center) => {
+                // drag on the plane through the center facing the eye, growing upward
+                let normal = (center - eye).normalize();
+                let up = Vector3::y() - normal * normal.y;
+                if up.norm() < 1e-3 {
+                    return None;
+                }
+                hit_plane(center, normal, ray).map(|(_, p)| (p - center).dot(&up.normalize()))
+            },
+            _ => None,
+        }
+    }
+
+    /// Update the gizmo from a controller ray and trigger state, returning the new object
+    /// transform. This should be called once per frame with the transform it last returned.
+    /// Pressing the trigger over a handle starts a drag, and the object follows the ray
+    /// until the trigger is released.
+    pub fn update(&mut self, target: Similarity3<f32>, eye: Point3<f32>, ray: &Ray, pressed: bool)
+        -> Similarity3<f32>
+    {
+        let center = Point3::from_coordinates(target.isometry.translation.vector);
+        let scale = self.size * (center - eye).norm();
+        let mut result = target;
+
+        if !pressed {
+            self.drag = None;
+        }
+        if let Some(drag) = self.drag {
+            let start = Similarity3::from_isometry(drag.start.isometry, drag.scale);
+            if let Some(value) = self.measure(drag.handle, &start, eye, ray) {
+                let iso = drag.start.isometry;
+                match (self.mode, drag.handle) {
+                    (GizmoMode::Translate, GizmoHandle::Axis(i)) => {
+                        let offset = snap(value - drag.value, self.translate_snap);
+                        let axis = self.axis(&start, i);
+                        result.isometry.translation = Translation3::from_vector(iso.translation.vector + axis * offset);
+                    },
+                    (GizmoMode::Rotate, GizmoHandle::Axis(i)) => {
+                        // keep the turn within half a revolution either way
+                        let mut angle = value - drag.value;
+                        if angle > PI { angle -= 2. * PI }
+                        if angle < -PI { angle += 2. * PI }
+                        let angle = snap(angle, self.rotate_snap);
+                        let axis = Unit::new_normalize(self.axis(&start, i));
+                        result.isometry.rotation = UnitQuaternion::from_axis_angle(&axis, angle) * iso.rotation;
+                    },
+                    (GizmoMode::Scale, GizmoHandle::Center) => {
+                        let factor = snap(1. + (value - drag.value) / drag.scale, self.scale_snap).max(0.01);
+                        result = Similarity3::from_isometry(iso, drag.start.scaling() * factor);
+                    },
+                    _ => (),
+                }
+            }
+        }
+        if self.drag.is_none() {
+            let frame = Similarity3::from_isometry(target.isometry, scale);
+            self.hovered = self.pick(&frame, ray);
+            // only a fresh press can start a drag
+            if pressed && !self.pressed {
+                if let Some(handle) = self.hovered {
+                    if let Some(value) = self.measure(handle, &frame, eye, ray) {
+                        self.drag = Some(Drag {
+                            handle: handle,
+                            start: target,
+                            scale: scale,
+                            value: value,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.pressed = pressed;
+        let center = Point3::from_coordinates(result.isometry.translation.vector);
+        let scale = self.size * (center - eye).norm();
+        self.frame = Similarity3::from_isometry(result.isometry, scale);
+        result
+    }
+}
+
+impl Default for GizmoControl {
+    fn default() -> GizmoControl {
+        GizmoControl::new()
+    }
+}
+
+/// A translation, rotation and scale gizmo for editing objects in VR. The gizmo is drawn
+/// unlit on top of the scene at the object's transform, with the hovered handle highlighted.
+pub struct Gizmo<R: Resources> {
+    /// The interaction state
+    pub control: GizmoControl,
+    shaft: Mesh<R, VertNTT, ()>,
+    tip: Mesh<R, VertNTT, ()>,
+    ring: Mesh<R, VertNTT, ()>,
+    center: Mesh<R, VertNTT, ()>,
+    axis_colors: [UnlitMaterial<R>; 3],
+    center_color: UnlitMaterial<R>,
+    highlight: UnlitMaterial<R>,
+}
+
+fn color<R: Resources, F: Factory<R>>(f: &mut F, rgba: [u8; 4]) -> Result<UnlitMaterial<R>, Error> {
+    Ok(UnlitMaterial { color: Texture::<R, ColorFormat>::uniform_value(f, rgba)? })
+}
+
+impl<R: Resources> Gizmo<R> {
+    /// Create the gizmo meshes
+    pub fn new<F: Factory<R>>(f: &mut F) -> Result<Gizmo<R>, Error> {
+        let tip = 2. * PICK_RADIUS;
+        Ok(Gizmo {
+            control: GizmoControl::new(),
+            shaft: gen::cylinder(0.015, 1., 8).map_material(|_| ()).upload(f),
+            tip: gen::rounded_box(Vector3::repeat(tip), 0., 1)?.map_material(|_| ()).upload(f),
+            ring: gen::torus(1., 0.015, 48, 6)?.map_material(|_| ()).upload(f),
+            center: gen::rounded_box(Vector3::repeat(2. * CENTER_SIZE), 0.03, 2)?.map_material(|_| ()).upload(f),
+            axis_colors: [
+                color(f, [230, 60, 60, 255])?,
+                color(f, [60, 210, 60, 255])?,
+                color(f, [70, 100, 240, 255])?,
+            ],
+            center_color: color(f, [220, 220, 220, 255])?,
+            highlight: color(f, [255, 220, 40, 255])?,
+        })
+    }
+
+    /// Update the interaction state, see `GizmoControl::update`
+    pub fn update(&mut self, target: Similarity3<f32>, eye: Point3<f32>, ray: &Ray, pressed: bool)
+        -> Similarity3<f32>
+    {
+        self.control.update(target, eye, ray, pressed)
+    }
+
+    /// Draw the gizmo where it was last updated. This clears the depth buffer so the gizmo
+    /// is never hidden by the scene, so it belongs in an overlay pass after everything else.
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, painter: &Painter<R, UnlitStyle<R>>)
+        -> Result<(), Error>
+    {
+        ctx.encoder.clear_depth(&ctx.depth, ::draw::DEPTH_CONVENTION.far());
+        let frame = self.control.frame().to_homogeneous();
+        let hovered = self.control.hovered();
+        let mat = |handle: GizmoHandle, normal: &UnlitMaterial<R>| {
+            if hovered == Some(handle) { self.highlight.clone() } else { normal.clone() }
+        };
+        let place = |local: Isometry3<f32>| -> Transform3<f32> {
+            Transform3::from_matrix_unchecked(frame * local.to_homogeneous())
+        };
+        // meshes are modelled along +Y
+        let along = |i: usize| UnitQuaternion::rotation_between(&Vector3::y(), &unit_axis(i))
+            .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), PI));
+
+        match self.control.mode {
+            GizmoMode::Translate => for i in 0..3 {
+                let m = mat(GizmoHandle::Axis(i), &self.axis_colors[i]);
+                let rot = along(i);
+                painter.try_draw_with(ctx, place(Isometry3::from_parts(Translation3::from_vector(rot * Vector3::y() * 0.5), rot)), &self.shaft, &m)?;
+                painter.try_draw_with(ctx, place(Isometry3::from_parts(Translation3::from_vector(rot * Vector3::y()), rot)), &self.tip, &m)?;
+            },
+            GizmoMode::Rotate => for i in 0..3 {
+                let m = mat(GizmoHandle::Axis(i), &self.axis_colors[i]);
+                painter.try_draw_with(ctx, place(Isometry3::from_parts(na::one(), along(i))), &self.ring, &m)?;
+            },
+            GizmoMode::Scale => {
+                for i in 0..3 {
+                    let rot = along(i);
+                    painter.try_draw_with(ctx, place(Isometry3::from_parts(Translation3::from_vector(rot * Vector3::y() * 0.5), rot)), &self.shaft, &self.axis_colors[i])?;
+                }
+                let m = mat(GizmoHandle::Center, &self.center_color);
+                painter.try_draw_with(ctx, place(na::one()), &self.center, &m)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn gizmo_drag() {
+    let eye = Point3::new(0., 0., 10.);
+    let toward = |x: f32, y: f32, z: f32| Ray::new(eye, Point3::new(x, y, z) - eye);
+    let mut g = GizmoControl::new();
+    g.size = 0.1;
+    let start = Similarity3::identity();
+
+    // the axes are a tenth of the eye distance long
+    let t = g.update(start, eye, &toward(0.5, 0., 0.), false);
+    assert_eq!(g.hovered(), Some(GizmoHandle::Axis(0)));
+    assert!(relative_eq!(g.frame().scaling(), 1.));
+    assert!(g.update(t, eye, &toward(0., 5., 0.), false) == start);
+    assert_eq!(g.hovered(), None);
+
+    // drag the X arrow by 2.3 units, snapped to whole units
+    g.translate_snap = 1.;
+    let t = g.update(start, eye, &toward(0.5, 0., 0.), true);
+    assert!(g.dragging());
+    let t = g.update(t, eye, &toward(2.8, 1., 0.), true);
+    assert!(relative_eq!(t.isometry.translation.vector, Vector3::new(2., 0., 0.), epsilon = 1e-5));
+    let t = g.update(t, eye, &toward(2.8, 1., 0.), false);
+    assert!(!g.dragging());
+
+    // a press that starts off the gizmo never grabs it
+    assert!(g.update(t, eye, &toward(9., 9., 0.), true) == t);
+    assert!(g.update(t, eye, &toward(2.5, 0., 0.), true) == t);
+    assert!(!g.dragging());
+    g.update(t, eye, &toward(2.5, 0., 0.), false);
+
+    // turn a quarter revolution about Z with 15 degree snapping
+    g.mode = GizmoMode::Rotate;
+    g.rotate_snap = 15f32.to_radians();
+    let t = g.update(start, eye, &toward(1., 0., 0.), true);
+    assert_eq!(g.hovered(), Some(GizmoHandle::Axis(2)));
+    let t = g.update(t, eye, &toward(0.05, 1., 0.), true);
+    let (axis, angle) = t.isometry.rotation.axis_angle().unwrap();
+    assert!(relative_eq!(angle, PI / 2., epsilon = 1e-4));
+    assert!(relative_eq!(axis.unwrap(), Vector3::z(), epsilon = 1e-4));
+    g.update(t, eye, &toward(0., 1., 0.), false);
+
+    // scaling doubles when dragged up by the gizmo size
+    g.mode = GizmoMode::Scale;
+    let t = g.update(start, eye, &toward(0., 0., 0.), true);
+    assert_eq!(g.hovered(), Some(GizmoHandle::Center));
+    let t = g.update(t, eye, &toward(0., 1., 0.), true);
+    assert!(relative_eq!(t.scaling(), 2., epsilon = 1e-4));
+}
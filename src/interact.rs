@@ -0,0 +1,193 @@
+use nalgebra::{Isometry3, Similarity3, Vector3, Matrix3, Rotation3, UnitQuaternion, Translation3};
+
+#[derive(Copy, Clone, Debug)]
+enum Grab {
+    Free,
+    /// The object relative to the hand holding it
+    One(usize, Similarity3<f32>),
+    /// The object relative to the frame between both hands
+    Two(Similarity3<f32>),
+}
+
+/// Moves, rotates and scales an object held with one or both controllers. With one hand
+/// the object follows that hand rigidly. With both, it follows the midpoint of the hands,
+/// turns with the line between them (and their average roll about it) and scales with
+/// their distance apart. Grabbing or letting go with either hand never moves the object.
+#[derive(Clone, Debug)]
+pub struct TwoHandManipulator {
+    /// Keep the object's scale fixed
+    pub lock_scale: bool,
+    /// Ignore the hands' roll about the line between them, keeping the object upright
+    pub lock_roll: bool,
+    /// The smallest scale the object can be given
+    pub min_scale: f32,
+    /// The largest scale the object can be given
+    pub max_scale: f32,
+    grab: Grab,
+    last_frame: Option<Similarity3<f32>>,
+}
+
+impl TwoHandManipulator {
+    /// Create a manipulator with no constraints, not holding anything
+    pub fn new() -> TwoHandManipulator {
+        TwoHandManipulator {
+            lock_scale: false,
+            lock_roll: false,
+            min_scale: 0.,
+            max_scale: ::std::f32::INFINITY,
+            grab: Grab::Free,
+            last_frame: None,
+        }
+    }
+
+    /// The number of hands holding the object
+    pub fn hands(&self) -> usize {
+        match self.grab {
+            Grab::Free => 0,
+            Grab::One(..) => 1,
+            Grab::Two(..) => 2,
+        }
+    }
+
+    /// The frame between both hands: at their midpoint, with X along the line from the first
+    /// hand to the second, and scaled by their distance apart. `None` if the hands coincide.
+    fn two_hand_frame(&self, a: &Isometry3<f32>, b: &Isometry3<f32>) -> Option<Similarity3<f32>> {
+        let (pa, pb) = (a.translation.vector, b.translation.vector);
+        let d = pb - pa;
+        let dist = d.norm();
+        if dist < 1e-4 {
+            return None;
+        }
+        let x = d / dist;
+        let hands_up = a.rotation * Vector3::y() + b.rotation * Vector3::y();
+        let mut up = if self.lock_roll { Vector3::y() } else { hands_up };
+        if (up - x * x.dot(&up)).norm() < 1e-3 {
+            // pointing straight along the up direction, fall back to the hands' roll
+            up = hands_up;
+        }
+        let y = up - x * x.dot(&up);
+        if y.norm() < 1e-3 {
+            return None;
+        }
+        let y = y.normalize();
+        let rot = Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[x, y, x.cross(&y)]));
+        let scale = if self.lock_scale { 1. } else { dist };
+        Some(Similarity3::from_parts(
+            Translation3::from_vector((pa + pb) / 2.),
+            UnitQuaternion::from_rotation_matrix(&rot),
+            scale,
+        ))
+    }
+
+    /// Update from the poses of the hands holding the object (`None` for a hand that isn't)
+    /// and return the object's new world transform. This should be called every frame with
+    /// the transform it last returned. Which hands hold the object is up to the caller,
+    /// usually the grip buttons of controllers pointing at it.
+    pub fn update(&mut self, object: Similarity3<f32>, hands: [Option<Isometry3<f32>>; 2])
+        -> Similarity3<f32>
+    {
+        let frame = match (hands[0], hands[1]) {
+            (Some(a), Some(b)) => self.two_hand_frame(&a, &b).or(self.last_frame),
+            _ => None,
+        };
+        let mut result = object;
+        match (self.grab, hands[0], hands[1], frame) {
+            (Grab::Two(offset), Some(_), Some(_), Some(f)) => result = f * offset,
+            (Grab::One(i, offset), _, _, _) if hands[i].is_some() && hands[1 - i].is_none() => {
+                result = Similarity3::from_isometry(hands[i].unwrap(), 1.) * offset;
+            },
+            (_, Some(_), Some(_), Some(f)) => {
+                // both hands just took hold, or the second one joined
+                self.grab = Grab::Two(f.inverse() * object);
+            },
+            (_, Some(h), None, _) => self.grab = Grab::One(0, Similarity3::from_isometry(h.inverse(), 1.) * object),
+            (_, None, Some(h), _) => self.grab = Grab::One(1, Similarity3::from_isometry(h.inverse(), 1.) * object),
+            _ => self.grab = Grab::Free,
+        }
+        self.last_frame = frame;
+
+        let scale = result.scaling();
+        let clamped = scale.max(self.min_scale).min(self.max_scale);
+        if (clamped - scale).abs() > 1e-6 * scale.abs() && clamped > 0. {
+            result.set_scaling(clamped);
+            // keep holding the object where it is now, so shrinking back isn't delayed
+            if let Grab::Two(_) = self.grab {
+                if let Some(f) = frame {
+                    self.grab = Grab::Two(f.inverse() * result);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Default for TwoHandManipulator {
+    fn default() -> TwoHandManipulator {
+        TwoHandManipulator::new()
+    }
+}
+
+#[test]
+fn two_hand_manipulation() {
+    use nalgebra::{self as na, Point3};
+    use std::f32::consts::PI;
+
+    let at = |x: f32, y: f32, z: f32| Some(Isometry3::new(Vector3::new(x, y, z), na::zero()));
+    let close = |a: &Similarity3<f32>, b: &Similarity3<f32>| {
+        relative_eq!(a.to_homogeneous(), b.to_homogeneous(), epsilon = 1e-4)
+    };
+    let mut m = TwoHandManipulator::new();
+    let start = Similarity3::from_parts(Translation3::new(0., 1., 0.), na::one(), 1.);
+
+    // one hand drags the object along
+    let t = m.update(start, [at(0.5, 1., 0.), None]);
+    assert!(close(&t, &start));
+    let t = m.update(t, [at(0.5, 2., 0.), None]);
+    assert!(relative_eq!(t.isometry.translation.vector, Vector3::new(0., 2., 0.), epsilon = 1e-5));
+
+    // the second hand joining doesn't move it
+    let joined = m.update(t, [at(0.5, 2., 0.), at(-0.5, 2., 0.)]);
+    assert_eq!(m.hands(), 2);
+    assert!(close(&joined, &t));
+
+    // pulling the hands apart doubles the scale about their midpoint
+    let t = m.update(joined, [at(1., 2., 0.), at(-1., 2., 0.)]);
+    assert!(relative_eq!(t.scaling(), 2., epsilon = 1e-4));
+    assert!(relative_eq!(t.isometry.translation.vector, Vector3::new(0., 2., 0.), epsilon = 1e-4));
+
+    // turning the hands a quarter turn about the vertical turns the object too
+    let t = m.update(t, [at(0., 2., -1.), at(0., 2., 1.)]);
+    let turned = t.isometry.rotation * Vector3::x();
+    assert!(relative_eq!(turned, -Vector3::z(), epsilon = 1e-4));
+
+    // letting go with one hand doesn't move it either
+    let released = m.update(t, [None, at(0., 2., 1.)]);
+    assert_eq!(m.hands(), 1);
+    assert!(close(&released, &t));
+    let t = m.update(released, [None, at(0., 3., 1.)]);
+    assert!(relative_eq!(t * Point3::origin(), Point3::new(0., 3., 0.), epsilon = 1e-4));
+    assert_eq!(m.update(t, [None, None]), t);
+    assert_eq!(m.hands(), 0);
+
+    // constrained scaling
+    let mut m = TwoHandManipulator::new();
+    m.max_scale = 1.5;
+    let t = m.update(start, [at(0.5, 1., 0.), at(-0.5, 1., 0.)]);
+    let t = m.update(t, [at(2., 1., 0.), at(-2., 1., 0.)]);
+    assert!(relative_eq!(t.scaling(), 1.5));
+    let t = m.update(t, [at(0.5, 1., 0.), at(-0.5, 1., 0.)]);
+    assert!(t.scaling() < 1.);
+    let mut m = TwoHandManipulator::new();
+    m.lock_scale = true;
+    let t = m.update(start, [at(0.5, 1., 0.), at(-0.5, 1., 0.)]);
+    let t = m.update(t, [at(2., 1., 0.), at(-2., 1., 0.)]);
+    assert!(close(&t, &start));
+
+    // with roll locked, twisting both hands about the line between them does nothing
+    let mut m = TwoHandManipulator::new();
+    m.lock_roll = true;
+    let twist = |x: f32| Some(Isometry3::new(Vector3::new(x, 1., 0.), Vector3::x() * PI / 4.));
+    let t = m.update(start, [at(0.5, 1., 0.), at(-0.5, 1., 0.)]);
+    let t = m.update(t, [twist(0.5), twist(-0.5)]);
+    assert!(close(&t, &start));
+}
@@ -12,9 +12,20 @@ extern crate rust_webvr as webvr;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "profiling")]
+extern crate puffin;
 #[cfg(test)]
 #[macro_use]
 extern crate approx;
+#[cfg(feature = "golden")]
+extern crate gfx_device_gl;
+#[cfg(feature = "golden")]
+extern crate glutin;
+
+#[macro_use]
+mod profile;
 
 /// Mesh drawing
 pub mod draw;
@@ -24,6 +35,35 @@ pub mod load;
 pub mod mesh;
 /// VR hardware interface
 pub mod vr;
+/// World-space user interface panels
+pub mod ui;
+/// Area light sources
+pub mod light;
+/// Distance field text
+pub mod text;
+/// Texture streaming
+pub mod stream;
+/// Object manipulation gizmos
+pub mod gizmo;
+/// Labels pointing at controller buttons
+pub mod tooltip;
+/// Grabbing and moving objects with controllers
+pub mod interact;
+/// Views between linked regions of a scene
+pub mod portal;
+/// Large world coordinates
+pub mod world;
+/// Outdoor terrain lighting
+pub mod terrain;
+/// Reflection probe placement and blending
+pub mod environment;
+/// Vectorized math on plain arrays
+pub mod math;
+/// Render settings tweaked while running
+pub mod config;
+/// Golden image regression testing
+#[cfg(feature = "golden")]
+pub mod testing;
 
 mod error;
 pub use error::FlightError;
@@ -34,14 +74,79 @@ pub use self::util::*;
 use gfx::shade::core::CreateShaderError;
 use gfx::handle::*;
 use gfx::format::*;
-use nalgebra::{Point3, UnitQuaternion, Point2};
+use nalgebra::{Point3, Vector3, UnitQuaternion, Point2};
 pub use failure::Error;
 
-/// The pixel format of color drawing targets
+/// The pixel format of color drawing targets, `ColorFormatSrgb` with the
+/// `srgb-framebuffer` feature
+#[cfg(not(feature = "srgb-framebuffer"))]
 pub type ColorFormat = (R8_G8_B8_A8, Unorm);
-/// The pixel format of depth drawing targets
+#[cfg(feature = "srgb-framebuffer")]
+pub type ColorFormat = ColorFormatSrgb;
+/// The pixel format of color targets that encode linear shader output to sRGB on write
+pub type ColorFormatSrgb = (R8_G8_B8_A8, Srgb);
+/// The gamma shaders encode their output with, 1 when the framebuffer does it instead
+#[cfg(not(feature = "srgb-framebuffer"))]
+pub const OUTPUT_GAMMA: f32 = 2.2;
+#[cfg(feature = "srgb-framebuffer")]
+pub const OUTPUT_GAMMA: f32 = 1.0;
+/// How precise depth drawing targets are, picked when building with the `depth-16` and
+/// `depth-32f` features
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DepthPrecision {
+    /// 16 bit integer depth, saving bandwidth on mobile headsets
+    D16,
+    /// 24 bit integer depth with an 8 bit stencil, the default
+    D24Stencil8,
+    /// 32 bit float depth, for large scenes that need precision far from the camera
+    D32Float,
+}
+
+impl DepthPrecision {
+    /// The number of bits of depth
+    pub fn depth_bits(self) -> u8 {
+        match self {
+            DepthPrecision::D16 => 16,
+            DepthPrecision::D24Stencil8 => 24,
+            DepthPrecision::D32Float => 32,
+        }
+    }
+
+    /// The number of bits of stencil
+    pub fn stencil_bits(self) -> u8 {
+        match self {
+            DepthPrecision::D24Stencil8 => 8,
+            _ => 0,
+        }
+    }
+
+    /// How depth values are stored, for creating raw depth textures
+    pub fn channel_type(self) -> ChannelType {
+        match self {
+            DepthPrecision::D32Float => ChannelType::Float,
+            _ => ChannelType::Unorm,
+        }
+    }
+}
+
+#[cfg(all(feature = "depth-16", feature = "depth-32f"))]
+compile_error!("the depth-16 and depth-32f features can't be used together");
+
+/// The precision of `DepthFormat`
+#[cfg(not(any(feature = "depth-16", feature = "depth-32f")))]
+pub const DEPTH_PRECISION: DepthPrecision = DepthPrecision::D24Stencil8;
+#[cfg(feature = "depth-16")]
+pub const DEPTH_PRECISION: DepthPrecision = DepthPrecision::D16;
+#[cfg(feature = "depth-32f")]
+pub const DEPTH_PRECISION: DepthPrecision = DepthPrecision::D32Float;
+/// The pixel format of depth drawing targets, see `DEPTH_PRECISION`
+#[cfg(not(any(feature = "depth-16", feature = "depth-32f")))]
 pub type DepthFormat = (D24_S8, Unorm);
-/// The pixel format of shadow depth buffers
+#[cfg(feature = "depth-16")]
+pub type DepthFormat = (D16, Unorm);
+#[cfg(feature = "depth-32f")]
+pub type DepthFormat = (D32, Float);
+/// The pixel format of shadow depth buffers, whatever the precision of `DepthFormat`
 pub type ShadowDepthFormat = (D32, Float);
 /// Reference to a GPU color target
 pub type TargetRef<R> = RenderTargetView<R, ColorFormat>;
@@ -87,6 +192,33 @@ impl Default for Sun {
     }
 }
 
+/// A half-line in world space, such as the pointing direction of a controller
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub dir: Vector3<f32>,
+}
+
+impl Ray {
+    /// Create a ray starting at `origin` and heading in the direction `dir`
+    pub fn new(origin: Point3<f32>, dir: Vector3<f32>) -> Ray {
+        Ray {
+            origin: origin,
+            dir: dir,
+        }
+    }
+
+    /// The ray a tracked device is pointing along
+    pub fn from_trackable<T: vr::Trackable>(device: &T) -> Ray {
+        Ray::new(device.origin(), device.pointing())
+    }
+
+    /// The point at the given distance along the ray (in units of `dir`)
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.dir * t
+    }
+}
+
 /// GPU-allocated texture object. Since this is just a reference to assets stored on the GPU,
 /// its memory footprint is negligible and it can be cloned freely.
 #[derive(Clone)]
@@ -97,6 +229,22 @@ pub struct Texture<R, T>
     pub buffer: ShaderResourceView<R, <T as Formatted>::View>,
 }
 
+impl<R: gfx::Resources, T: TextureFormat> PartialEq for Texture<R, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.buffer == other.buffer && self.sampler == other.sampler
+    }
+}
+
+impl<R: gfx::Resources, T: TextureFormat> Eq for Texture<R, T> { }
+
+/// Textures are hashed by the identity of their GPU handles, not by their contents
+impl<R: gfx::Resources, T: TextureFormat> ::std::hash::Hash for Texture<R, T> {
+    fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+        self.buffer.hash(state);
+        self.sampler.hash(state);
+    }
+}
+
 impl<R: gfx::Resources, T: TextureFormat> Texture<R, T> {
     /// Convert this texture reference to an internally recognized tuple form
     pub fn into_tuple(self) -> (ShaderResourceView<R, T::View>, Sampler<R>) {
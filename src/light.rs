@@ -0,0 +1,70 @@
+use nalgebra::{Matrix4, Point3, Vector3, U3};
+
+/// The outline of an area light, in the XY plane of the light's transform
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AreaShape {
+    /// A rectangle with the given width (along X) and height (along Y)
+    Rect(f32, f32),
+    /// A disk with the given radius
+    Disk(f32),
+}
+
+/// A flat light source that emits from one side of its shape (towards +Z of its transform).
+/// Area lights give softer, more realistic highlights than point lights.
+#[derive(Copy, Clone, Debug)]
+pub struct AreaLight {
+    pub shape: AreaShape,
+    /// Placement of the shape in world space
+    pub transform: Matrix4<f32>,
+    /// Emitted color, with the alpha channel as a brightness multiplier (like `Light`)
+    pub color: [f32; 4],
+    pub intensity: f32,
+}
+
+impl AreaLight {
+    /// The world space center of the light and the vectors from the center to the
+    /// edge of the shape along its X and Y axes
+    pub fn frame(&self) -> (Point3<f32>, Vector3<f32>, Vector3<f32>) {
+        let (x, y) = match self.shape {
+            AreaShape::Rect(w, h) => (w / 2., h / 2.),
+            AreaShape::Disk(r) => (r, r),
+        };
+        let center = Point3::from_homogeneous(self.transform * Point3::origin().to_homogeneous())
+            .unwrap_or(Point3::origin());
+        let linear = self.transform.fixed_slice::<U3, U3>(0, 0);
+        (center, linear * Vector3::x() * x, linear * Vector3::y() * y)
+    }
+
+    /// The emitted radiance, combining color and intensity
+    pub fn radiance(&self) -> [f32; 3] {
+        let k = self.color[3] * self.intensity;
+        [self.color[0] * k, self.color[1] * k, self.color[2] * k]
+    }
+}
+
+impl Default for AreaLight {
+    fn default() -> AreaLight {
+        AreaLight {
+            shape: AreaShape::Rect(1., 1.),
+            transform: Matrix4::identity(),
+            color: [0.; 4],
+            intensity: 0.,
+        }
+    }
+}
+
+#[test]
+fn area_light_frame() {
+    use nalgebra::Translation3;
+    let light = AreaLight {
+        shape: AreaShape::Rect(2., 4.),
+        transform: Translation3::new(0., 3., 0.).to_homogeneous(),
+        color: [1., 0.5, 0., 2.],
+        intensity: 3.,
+    };
+    let (center, x, y) = light.frame();
+    assert!(relative_eq!(center, Point3::new(0., 3., 0.)));
+    assert!(relative_eq!(x, Vector3::new(1., 0., 0.)));
+    assert!(relative_eq!(y, Vector3::new(0., 2., 0.)));
+    assert_eq!(light.radiance(), [6., 3., 0.]);
+}
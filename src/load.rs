@@ -9,13 +9,45 @@ use std::io;
 use std::path::Path;
 use std::fmt;
 use std::mem;
+use std::sync::Arc;
 
 use ::{Error, FlightError, Texture};
-use ::mesh::{Mesh, MeshSource, Indexing, VertNT, VertNTT, Primitive};
+use ::mesh::{Mesh, MeshSource, MultiMeshSource, MeshGroup, Indexing, VertNT, VertNTT, Primitive, Aabb, Vertex};
+use ::mesh::gen::Surface;
 use ::draw;
 
-/// Load wavefront OBJ data into an internal mesh object
+/// Compressed binary meshes
+pub mod vmesh;
+/// Writing meshes back out as OBJ and binary glTF
+pub mod export;
+
+mod validate;
+pub use self::validate::{ValidationKind, ValidationWarning, MAX_EXAMPLES};
+pub use self::validate::{validate, validate_groups, validate_material, set_strict, strict};
+
+mod units;
+pub use self::units::{ImportOptions, Units, Axis, MIN_PLAUSIBLE_SIZE, MAX_PLAUSIBLE_SIZE};
+pub use self::units::{suggest_units, check_scale};
+
+mod normals;
+pub use self::normals::{NormalConvention, detect_normal_convention, convert_normal_map, flip_green};
+
+mod texture_set;
+pub use self::texture_set::{pack_knobs, pack_knob_images, find_texture_set, load_material_dir, TextureSet, TextureRole};
+pub use self::texture_set::TextureCache;
+pub use ::stream::{MipmapStream, STREAM_INITIAL_MIPS};
+pub use self::texture_set::{DEFAULT_ROUGHNESS, DEFAULT_METALNESS, DEFAULT_OCCLUSION};
+
+mod atlas;
+pub use self::atlas::{AtlasPacker, DEFAULT_MAX_ATLAS_SIZE};
+
+/// Load wavefront OBJ data into an internal mesh object, assuming it is in meters and Y-up
 pub fn load_wavefront(obj: &Obj<SimplePolygon>) -> Result<MeshSource<VertNT, ()>, Error> {
+    load_wavefront_with(obj, &ImportOptions::default())
+}
+
+/// Load wavefront OBJ data into an internal mesh object, converted with `options`
+pub fn load_wavefront_with(obj: &Obj<SimplePolygon>, options: &ImportOptions) -> Result<MeshSource<VertNT, ()>, Error> {
     let mut verts = Vec::new();
     let mut ind_look = FnvHashMap::default();
     let mut inds = Vec::new();
@@ -30,19 +62,96 @@ pub fn load_wavefront(obj: &Obj<SimplePolygon>) -> Result<MeshSource<VertNT, ()>
         }));
         inds.extend(poly);
     }
-    Ok(MeshSource {
+    options.apply(&mut verts);
+    let mesh = MeshSource {
         verts: verts,
         inds: Indexing::Inds(inds),
         prim: Primitive::TriangleList,
         mat: (),
-    })
+    };
+    check_scale("wavefront mesh", &mesh.bounds());
+    if strict() {
+        validate::report("wavefront mesh", &validate(&mesh));
+    }
+    Ok(mesh)
 }
 
-/// Load a wavefront obj file into an internal mesh object
+/// Load a wavefront obj file into an internal mesh object, assuming it is in meters and Y-up
 pub fn open_wavefront<P: AsRef<Path>>(path: P) -> Result<MeshSource<VertNT, ()>, Error> {
     load_wavefront(&Obj::load(path.as_ref())?)
 }
 
+/// Load a wavefront obj file into an internal mesh object, converted with `options`
+pub fn open_wavefront_with<P: AsRef<Path>>(path: P, options: &ImportOptions) -> Result<MeshSource<VertNT, ()>, Error> {
+    load_wavefront_with(&Obj::load(path.as_ref())?, options)
+}
+
+/// Load wavefront OBJ data into a single mesh with one group per OBJ group, where each
+/// group's material is the name of the OBJ group. Use `MultiMeshSource::map_materials`
+/// to turn the names into real materials. The data is assumed to be in meters and Y-up.
+pub fn load_wavefront_groups(obj: &Obj<SimplePolygon>) -> Result<MultiMeshSource<VertNT, String>, Error> {
+    load_wavefront_groups_with(obj, &ImportOptions::default())
+}
+
+/// Load wavefront OBJ data into a single mesh with one group per OBJ group, converted with
+/// `options`
+pub fn load_wavefront_groups_with(obj: &Obj<SimplePolygon>, options: &ImportOptions)
+    -> Result<MultiMeshSource<VertNT, String>, Error>
+{
+    let mut verts = Vec::new();
+    let mut ind_look = FnvHashMap::default();
+    let mut inds = Vec::new();
+    let mut groups = Vec::new();
+    for g in obj.objects.iter().flat_map(|o| &o.groups) {
+        let start = inds.len() as u32;
+        for p in &g.polys {
+            let poly = p.iter().map(|i| *ind_look.entry((i.0, i.1, i.2)).or_insert_with(|| {
+                verts.push(VertNT {
+                    pos: obj.position[i.0],
+                    norm: match i.2 { Some(i) => obj.normal[i], None => [0.; 3] },
+                    tex: match i.1 { Some(i) => obj.texture[i], None => [0.; 2] },
+                });
+                verts.len() as u32 - 1
+            }));
+            inds.extend(poly);
+        }
+        let end = inds.len() as u32;
+        if end > start {
+            groups.push(MeshGroup {
+                start: start,
+                end: end,
+                mat: g.name.clone(),
+            });
+        }
+    }
+    options.apply(&mut verts);
+    check_scale("wavefront mesh", &Aabb::from_points(verts.iter().map(|v| v.pos())));
+    let mesh = MultiMeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        groups: groups,
+    };
+    if strict() {
+        validate::report("wavefront mesh", &validate_groups(&mesh));
+    }
+    Ok(mesh)
+}
+
+/// Load a wavefront obj file into a mesh with one group per OBJ group, assuming it is in
+/// meters and Y-up
+pub fn open_wavefront_groups<P: AsRef<Path>>(path: P) -> Result<MultiMeshSource<VertNT, String>, Error> {
+    load_wavefront_groups(&Obj::load(path.as_ref())?)
+}
+
+/// Load a wavefront obj file into a mesh with one group per OBJ group, converted with
+/// `options`
+pub fn open_wavefront_groups_with<P: AsRef<Path>>(path: P, options: &ImportOptions)
+    -> Result<MultiMeshSource<VertNT, String>, Error>
+{
+    load_wavefront_groups_with(&Obj::load(path.as_ref())?, options)
+}
+
 pub fn load_integrated_brdf<R, F>(f: &mut F)
     -> Result<Texture<R, (R8_G8, Unorm)>, Error>
     where
@@ -74,6 +183,135 @@ pub fn load_integrated_brdf<R, F>(f: &mut F)
     })
 }
 
+/// The width and height of the area light lookup tables
+pub const LTC_SIZE: usize = 64;
+
+/// Compute the area light lookup tables, indexed by (NdotV, roughness) like the integrated
+/// BRDF. The first table holds, for each cell, the scale of the cosine lobe in the tangent
+/// plane and the tilt of the lobe towards the reflection direction (as a fraction of 90°),
+/// which together give the inverse linearly transformed cosine (LTC) matrix. The tilt and
+/// scale come from an analytic approximation of the GGX lobe rather than a fitted table.
+/// The second table holds the scale and bias applied to F0 to get the total specular
+/// reflectance, integrated numerically with the same GGX terms as the uber shader.
+pub fn ltc_tables() -> (Vec<[f32; 2]>, Vec<[f32; 2]>) {
+    use std::f32::consts::PI;
+    const SAMPLES: u32 = 128;
+
+    let mut inv = Vec::with_capacity(LTC_SIZE * LTC_SIZE);
+    let mut norm = Vec::with_capacity(LTC_SIZE * LTC_SIZE);
+    for row in 0..LTC_SIZE {
+        let roughness = ((row as f32 + 0.5) / LTC_SIZE as f32).max(0.05);
+        let alpha = roughness * roughness;
+        for col in 0..LTC_SIZE {
+            let n_dot_v = ((col as f32 + 0.5) / LTC_SIZE as f32).max(0.01);
+            let sin_v = (1. - n_dot_v * n_dot_v).sqrt();
+
+            // the dominant direction moves from the reflection vector to the normal
+            // as the surface gets rougher
+            let s = (1. - alpha) * ((1. - alpha).sqrt() + alpha);
+            let tilt = (s * sin_v).atan2(1. - s + s * n_dot_v);
+            inv.push([alpha * (2. - alpha), tilt / (PI / 2.)]);
+
+            // importance sample GGX around the normal
+            let k = alpha / 2.;
+            let g1 = |c: f32| c / (c * (1. - k) + k);
+            let (mut scale, mut bias) = (0., 0.);
+            for i in 0..SAMPLES {
+                let u = i as f32 / SAMPLES as f32;
+                let v = radical_inverse(i);
+                let phi = 2. * PI * u;
+                let cos_h = ((1. - v) / (1. + (alpha * alpha - 1.) * v)).sqrt();
+                let sin_h = (1. - cos_h * cos_h).sqrt();
+                let h = [sin_h * phi.cos(), sin_h * phi.sin(), cos_h];
+                let v_dot_h = sin_v * h[0] + n_dot_v * h[2];
+                let n_dot_l = 2. * v_dot_h * h[2] - n_dot_v;
+                if n_dot_l > 0. && v_dot_h > 0. {
+                    let vis = g1(n_dot_v) * g1(n_dot_l) * v_dot_h / (cos_h * n_dot_v);
+                    let fres = (1. - v_dot_h).powi(5);
+                    scale += (1. - fres) * vis;
+                    bias += fres * vis;
+                }
+            }
+            norm.push([scale / SAMPLES as f32, bias / SAMPLES as f32]);
+        }
+    }
+    (inv, norm)
+}
+
+/// The van der Corput sequence, used to spread samples evenly
+fn radical_inverse(mut i: u32) -> f32 {
+    let mut r = 0.;
+    let mut scale = 0.5;
+    while i > 0 {
+        r += (i & 1) as f32 * scale;
+        i >>= 1;
+        scale /= 2.;
+    }
+    r
+}
+
+/// Truncate a float to half precision, flushing values too small for a normal half to zero
+fn f16_bits(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = (bits >> 16 & 0x8000) as u16;
+    let exp = (bits >> 23 & 0xff) as i32 - 127 + 15;
+    if exp <= 0 {
+        sign
+    } else if exp >= 31 {
+        sign | 0x7c00
+    } else {
+        sign | (exp as u16) << 10 | (bits >> 13 & 0x3ff) as u16
+    }
+}
+
+/// Compute and upload the area light lookup tables (see `ltc_tables`)
+pub fn load_ltc_tables<R, F>(f: &mut F)
+    -> Result<(Texture<R, (R16_G16, Float)>, Texture<R, (R16_G16, Float)>), Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+{
+    use gfx::texture::*;
+    let (inv, norm) = ltc_tables();
+    let sampler = f.create_sampler(SamplerInfo::new(
+        FilterMethod::Bilinear,
+        WrapMode::Clamp));
+    let mut upload = |data: Vec<[f32; 2]>| -> Result<Texture<R, (R16_G16, Float)>, Error> {
+        let data: Vec<[u16; 2]> = data.iter().map(|v| [f16_bits(v[0]), f16_bits(v[1])]).collect();
+        let (_, shader_resource) = f.create_texture_immutable
+            ::<(R16_G16, Float)>(
+            Kind::D2(LTC_SIZE as u16, LTC_SIZE as u16, AaMode::Single),
+            Mipmap::Provided,
+            &[&data[..]],
+        )?;
+        Ok(Texture {
+            sampler: sampler.clone(),
+            buffer: shader_resource,
+        })
+    };
+    Ok((upload(inv)?, upload(norm)?))
+}
+
+#[test]
+fn ltc_table_values() {
+    assert_eq!(f16_bits(1.), 0x3c00);
+    assert_eq!(f16_bits(-2.), 0xc000);
+    assert_eq!(f16_bits(0.5), 0x3800);
+    assert_eq!(f16_bits(1e-9), 0);
+
+    let (inv, norm) = ltc_tables();
+    assert_eq!(inv.len(), LTC_SIZE * LTC_SIZE);
+    for (i, n) in inv.iter().zip(&norm) {
+        assert!(i[0] > 0. && i[0] <= 1.);
+        assert!(i[1] >= 0. && i[1] <= 1.);
+        // a surface can't reflect more light than it receives
+        assert!(n[0] >= 0. && n[1] >= 0. && n[0] + n[1] <= 1.01);
+    }
+    // smooth surfaces tilt the lobe all the way to the reflection direction
+    assert!(inv[0][1] > 0.9);
+    assert!(inv[0][1] > inv[(LTC_SIZE - 1) * LTC_SIZE][1]);
+}
+
 pub fn load_rgba8<R, F, T>(f: &mut F, image: RgbaImage, sampler: Sampler<R>)
     -> Result<Texture<R, (R8_G8_B8_A8, T)>, Error>
     where
@@ -110,6 +348,22 @@ pub fn open_rgba8<R, F, T, P>(f: &mut F, path: P, sampler: Sampler<R>)
     load_rgba8(f, open_image(path)?.to_rgba(), sampler)
 }
 
+/// Open a tangent space normal map, flipping its green channel into the OpenGL
+/// convention if `convention` says it's in the DirectX one or detects that it is
+pub fn open_normal_map<R, F, P>(f: &mut F, path: P, convention: NormalConvention, sampler: Sampler<R>)
+    -> Result<Texture<R, draw::LinearFormat>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+        P: AsRef<Path>,
+{
+    let mut image = open_image(path.as_ref())?.to_rgba();
+    convert_normal_map(&mut image, convention, &path.as_ref().display().to_string());
+    load_rgba8(f, image, sampler)
+}
+
+/// Open a mesh with its albedo, normal and knobs textures. The normal map's convention is
+/// detected and converted to OpenGL.
 pub fn open_uber_mesh<R, F, P1, P2, P3, P4>(
     f: &mut F,
     wavefront: P1,
@@ -130,12 +384,22 @@ pub fn open_uber_mesh<R, F, P1, P2, P3, P4>(
     let sampler = f.create_sampler(SamplerInfo::new(
         FilterMethod::Bilinear,
         WrapMode::Tile));
+    let albedo_image = open_image(albedo.as_ref())?.to_rgba();
+    let mut normal_image = open_image(normal.as_ref())?.to_rgba();
+    convert_normal_map(&mut normal_image, NormalConvention::AutoDetect, &normal.as_ref().display().to_string());
+    if strict() {
+        let path = format!("{} and {}", albedo.as_ref().display(), normal.as_ref().display());
+        validate::report(&path, &validate_material(Some(&albedo_image), Some(&normal_image)));
+    }
     Ok(open_wavefront(wavefront)?
     .compute_tan()
     .with_material(draw::UberMaterial {
-        albedo: open_rgba8(f, albedo, sampler.clone())?,
-        normal: open_rgba8(f, normal, sampler.clone())?,
+        albedo: load_rgba8(f, albedo_image, sampler.clone())?,
+        normal: load_rgba8(f, normal_image, sampler.clone())?,
         knobs: open_rgba8(f, knobs, sampler)?,
+        bent: Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+        surface: Surface::Mesh,
+        normal_encoding: draw::NormalEncoding::Rgb,
     }).upload(f))
 }
 
@@ -238,3 +502,449 @@ pub fn load_hdr_cubemap<R, F, B, S>(f: &mut F, levels: u8, source: S)
         buffer: shader_resource,
     })
 }
+
+/// Upload single-channel volume data (e.g. CT density) as a 3D texture.
+/// The data is ordered x fastest, then y, then z.
+pub fn load_volume<R, F>(f: &mut F, width: u16, height: u16, depth: u16, data: &[u8])
+    -> Result<Texture<R, (R8, Unorm)>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+{
+    let expected = width as usize * height as usize * depth as usize;
+    ensure!(
+        data.len() == expected,
+        FlightError::TextureSizeMismatch { expected: expected, given: data.len() }
+    );
+
+    use gfx::texture::*;
+    let (_, shader_resource) = f.create_texture_immutable_u8
+        ::<(R8, Unorm)>(
+        Kind::D3(width, height, depth),
+        Mipmap::Provided,
+        &[data],
+    )?;
+    let sampler = f.create_sampler(SamplerInfo::new(
+        FilterMethod::Trilinear,
+        WrapMode::Clamp));
+    Ok(Texture {
+        sampler: sampler,
+        buffer: shader_resource,
+    })
+}
+
+/// Upload a volume transfer function, mapping density (from 0 to 1) to color and opacity.
+/// The given colors are spread evenly over the density range.
+pub fn load_transfer_function<R, F>(f: &mut F, colors: &[[u8; 4]])
+    -> Result<Texture<R, (R8_G8_B8_A8, Srgb)>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+{
+    use gfx::texture::*;
+    let data: Vec<u8> = colors.iter().flat_map(|c| c.iter().cloned()).collect();
+    let (_, shader_resource) = f.create_texture_immutable_u8
+        ::<(R8_G8_B8_A8, Srgb)>(
+        Kind::D2(colors.len() as u16, 1, AaMode::Single),
+        Mipmap::Provided,
+        &[&data[..]],
+    )?;
+    let sampler = f.create_sampler(SamplerInfo::new(
+        FilterMethod::Bilinear,
+        WrapMode::Clamp));
+    Ok(Texture {
+        sampler: sampler,
+        buffer: shader_resource,
+    })
+}
+
+/// Volume data decoded from a NIfTI-1 file, normalized to bytes and
+/// interleaved as RGBA voxels (one color channel per volume in the time/channel axis).
+pub struct NiftiChannels {
+    /// Size of the volume in voxels (x, y, z)
+    pub size: (u16, u16, u16),
+    /// Number of channels present in the file (1 to 4)
+    pub channels: usize,
+    /// RGBA voxel data, x fastest, then y, then z
+    pub data: Vec<u8>,
+}
+
+/// Parse a single-file NIfTI-1 (`.nii`) volume whose 4th dimension holds up to four
+/// channels. Every channel is rescaled so that its own minimum and maximum map to 0 and 255.
+pub fn parse_multi_channel_nifti(bytes: &[u8]) -> Result<NiftiChannels, Error> {
+    ensure!(bytes.len() >= 352, FlightError::InvalidNifti { reason: "file is too short" });
+
+    // The header size doubles as an endianness check
+    let le = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let be = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let little = if le == 348 {
+        true
+    } else if be == 348 {
+        false
+    } else {
+        bail!(FlightError::InvalidNifti { reason: "header size is not 348" })
+    };
+    ensure!(
+        &bytes[344..347] == b"n+1",
+        FlightError::InvalidNifti { reason: "not a single-file NIfTI-1 volume" }
+    );
+
+    let read_i16 = |o: usize| {
+        let b = [bytes[o], bytes[o + 1]];
+        if little { i16::from_le_bytes(b) } else { i16::from_be_bytes(b) }
+    };
+    let read_f32 = |o: usize| {
+        let b = [bytes[o], bytes[o + 1], bytes[o + 2], bytes[o + 3]];
+        if little { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+    };
+
+    let ndim = read_i16(40);
+    ensure!(ndim >= 3 && ndim <= 7, FlightError::InvalidNifti { reason: "unsupported dimension count" });
+    let mut dims = [1usize; 4];
+    for i in 0..(ndim.min(4) as usize) {
+        let d = read_i16(42 + 2 * i);
+        ensure!(d > 0, FlightError::InvalidNifti { reason: "non-positive dimension" });
+        dims[i] = d as usize;
+    }
+    for i in 4..(ndim as usize) {
+        ensure!(read_i16(42 + 2 * i) <= 1, FlightError::InvalidNifti { reason: "more than four dimensions" });
+    }
+    ensure!(dims[3] <= 4, FlightError::InvalidNifti { reason: "more than four channels" });
+    ensure!(
+        dims[0] <= ::std::u16::MAX as usize
+            && dims[1] <= ::std::u16::MAX as usize
+            && dims[2] <= ::std::u16::MAX as usize,
+        FlightError::InvalidNifti { reason: "volume is too large for a 3D texture" }
+    );
+
+    let datatype = read_i16(70);
+    let bytes_per = match datatype {
+        2 | 256 => 1,
+        4 | 512 => 2,
+        8 | 16 | 768 => 4,
+        64 => 8,
+        _ => bail!(FlightError::InvalidNifti { reason: "unsupported data type" }),
+    };
+    let offset = read_f32(108).max(352.) as usize;
+    let slope = match read_f32(112) { s if s == 0. || !s.is_finite() => 1., s => s };
+    let inter = match read_f32(116) { i if i.is_finite() => i, _ => 0. };
+
+    let voxels = dims[0] * dims[1] * dims[2];
+    let total = voxels * dims[3];
+    ensure!(
+        bytes.len() >= offset + total * bytes_per,
+        FlightError::InvalidNifti { reason: "voxel data is truncated" }
+    );
+
+    let value = |i: usize| -> f32 {
+        let o = offset + i * bytes_per;
+        let b = &bytes[o..o + bytes_per];
+        let raw = match (datatype, little) {
+            (2, _) => b[0] as f32,
+            (256, _) => b[0] as i8 as f32,
+            (4, true) => i16::from_le_bytes([b[0], b[1]]) as f32,
+            (4, false) => i16::from_be_bytes([b[0], b[1]]) as f32,
+            (512, true) => u16::from_le_bytes([b[0], b[1]]) as f32,
+            (512, false) => u16::from_be_bytes([b[0], b[1]]) as f32,
+            (8, true) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (8, false) => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (768, true) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (768, false) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (16, true) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            (16, false) => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            (_, true) => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+            (_, false) => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+        };
+        raw * slope + inter
+    };
+
+    let mut data = vec![0u8; voxels * 4];
+    for c in 0..dims[3] {
+        let base = c * voxels;
+        let (mut min, mut max) = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+        for i in 0..voxels {
+            let v = value(base + i);
+            if v.is_finite() {
+                min = min.min(v);
+                max = max.max(v);
+            }
+        }
+        let range = if max > min { max - min } else { 1. };
+        for i in 0..voxels {
+            let v = (value(base + i) - min) / range;
+            data[i * 4 + c] = (v * 255.).round().max(0.).min(255.) as u8;
+        }
+    }
+
+    Ok(NiftiChannels {
+        size: (dims[0] as u16, dims[1] as u16, dims[2] as u16),
+        channels: dims[3],
+        data: data,
+    })
+}
+
+/// Load a single-file NIfTI-1 volume as a 3D RGBA texture, where the time/channel axis
+/// maps to the RGBA channels. This is meant for multi-channel modalities such as
+/// PET/CT fusion, drawn with `draw::VolumeData::Channels`.
+pub fn load_multi_channel_nifti<R, F, P>(f: &mut F, path: P)
+    -> Result<Texture<R, (R8_G8_B8_A8, Unorm)>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+        P: AsRef<Path>,
+{
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    ::std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+    let vol = parse_multi_channel_nifti(&bytes)?;
+
+    use gfx::texture::*;
+    let (w, h, d) = vol.size;
+    let (_, shader_resource) = f.create_texture_immutable_u8
+        ::<(R8_G8_B8_A8, Unorm)>(
+        Kind::D3(w, h, d),
+        Mipmap::Provided,
+        &[&vol.data[..]],
+    )?;
+    let sampler = f.create_sampler(SamplerInfo::new(
+        FilterMethod::Trilinear,
+        WrapMode::Clamp));
+    Ok(Texture {
+        sampler: sampler,
+        buffer: shader_resource,
+    })
+}
+
+#[test]
+fn parse_nifti_channels() {
+    // 2x1x1 volume with 2 channels of little endian int16
+    let mut bytes = vec![0u8; 352];
+    bytes[0..4].copy_from_slice(&348i32.to_le_bytes());
+    for (i, &d) in [4i16, 2, 1, 1, 2].iter().enumerate() {
+        bytes[40 + 2 * i..42 + 2 * i].copy_from_slice(&d.to_le_bytes());
+    }
+    bytes[70..72].copy_from_slice(&4i16.to_le_bytes());
+    bytes[72..74].copy_from_slice(&16i16.to_le_bytes());
+    bytes[108..112].copy_from_slice(&352f32.to_le_bytes());
+    bytes[344..348].copy_from_slice(b"n+1\0");
+    for &v in &[-100i16, 300, 7, 5] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let vol = parse_multi_channel_nifti(&bytes).unwrap();
+    assert_eq!(vol.size, (2, 1, 1));
+    assert_eq!(vol.channels, 2);
+    assert_eq!(vol.data, vec![0, 255, 0, 0, 255, 0, 0, 0]);
+
+    bytes[0..4].copy_from_slice(&0i32.to_le_bytes());
+    assert!(parse_multi_channel_nifti(&bytes).is_err());
+}
+
+/// Resolves `#include "file.glsl"` directives in GLSL source. Included files are looked up
+/// first among the sources added with `add_source`, then in the include directory.
+#[derive(Clone, Debug)]
+pub struct ShaderPreprocessor {
+    /// Directory that included file names are relative to
+    pub include_dir: ::std::path::PathBuf,
+    sources: FnvHashMap<String, String>,
+    included: Vec<String>,
+}
+
+impl ShaderPreprocessor {
+    /// Create a preprocessor that reads included files from the given directory
+    pub fn new<P: AsRef<Path>>(include_dir: P) -> ShaderPreprocessor {
+        ShaderPreprocessor {
+            include_dir: include_dir.as_ref().to_owned(),
+            sources: Default::default(),
+            included: Vec::new(),
+        }
+    }
+
+    /// Make a source file available for inclusion without reading it from disk
+    pub fn add_source<N: Into<String>, S: Into<String>>(&mut self, name: N, source: S) {
+        self.sources.insert(name.into(), source.into());
+    }
+
+    /// Every file included by the most recent call to `process` or `process_file`. A shader
+    /// should be rebuilt if any of these change.
+    pub fn included(&self) -> &[String] {
+        &self.included
+    }
+
+    /// Read a file from the include directory and resolve its includes
+    pub fn process_file(&mut self, name: &str) -> Result<String, Error> {
+        let source = self.read(name)?;
+        self.process(name, &source)
+    }
+
+    /// Resolve the includes in the given source, where `name` identifies the source in errors
+    pub fn process(&mut self, name: &str, source: &str) -> Result<String, Error> {
+        self.included.clear();
+        let mut chain = vec![name.to_owned()];
+        let mut out = String::with_capacity(source.len());
+        self.expand(source, &mut chain, &mut out)?;
+        Ok(out)
+    }
+
+    fn read(&self, name: &str) -> Result<String, Error> {
+        if let Some(s) = self.sources.get(name) {
+            return Ok(s.clone());
+        }
+        use std::io::Read;
+        let mut s = String::new();
+        ::std::fs::File::open(self.include_dir.join(name))?.read_to_string(&mut s)?;
+        Ok(s)
+    }
+
+    fn expand(&mut self, source: &str, chain: &mut Vec<String>, out: &mut String) -> Result<(), Error> {
+        for (i, line) in source.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if !trimmed.starts_with("#include") {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            }
+
+            let malformed = || FlightError::MalformedInclude {
+                file: chain[chain.len() - 1].clone(),
+                line: i + 1,
+            };
+            let rest = trimmed["#include".len()..].trim();
+            ensure!(rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"'), malformed());
+            let file = &rest[1..rest.len() - 1];
+            if chain.iter().any(|c| c == file) {
+                let mut chain = chain.clone();
+                chain.push(file.to_owned());
+                return Err(FlightError::CircularInclude { chain: chain }.into());
+            }
+
+            let included = self.read(file)?;
+            if !self.included.iter().any(|f| f == file) {
+                self.included.push(file.to_owned());
+            }
+            chain.push(file.to_owned());
+            out.push_str("#line 1\n");
+            self.expand(&included, chain, out)?;
+            chain.pop();
+            // restore line numbers of the including file
+            out.push_str(&format!("#line {}\n", i + 2));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn shader_includes() {
+    let mut pre = ShaderPreprocessor::new("shaders");
+    pre.add_source("consts.glsl", "const float PI = 3.14;");
+    pre.add_source("brdf.glsl", "#include \"consts.glsl\"\nfloat brdf() { return PI; }");
+    let out = pre.process("main.f.glsl", "#version 410\n  #include \"brdf.glsl\"\nvoid main() {}").unwrap();
+    assert_eq!(out, "#version 410\n#line 1\n#line 1\nconst float PI = 3.14;\n#line 2\n\
+        float brdf() { return PI; }\n#line 3\nvoid main() {}\n");
+    assert_eq!(pre.included(), &["brdf.glsl".to_owned(), "consts.glsl".to_owned()]);
+
+    pre.add_source("a.glsl", "#include \"b.glsl\"");
+    pre.add_source("b.glsl", "#include \"a.glsl\"");
+    let err = pre.process("main.f.glsl", "#include \"a.glsl\"").unwrap_err();
+    match err.downcast::<FlightError>() {
+        Ok(FlightError::CircularInclude { chain }) => assert_eq!(chain, vec!["main.f.glsl", "a.glsl", "b.glsl", "a.glsl"]),
+        _ => panic!("expected a circular include error"),
+    }
+    assert!(pre.process("main.f.glsl", "#include brdf.glsl").is_err());
+}
+
+/// Insert `#define` lines into GLSL source, after the `#version` directive if there is one
+fn with_defines(source: &str, defines: &[(&str, &str)]) -> String {
+    if defines.is_empty() {
+        return source.to_owned();
+    }
+    let mut prefix = String::new();
+    for &(name, val) in defines {
+        prefix += &format!("#define {} {}\n", name, val);
+    }
+    ::draw::shaders::insert_prefix(source, &prefix)
+}
+
+/// Compiled shader sets keyed by a hash of their final source text, so that styles built
+/// from identical sources share one set of driver shaders instead of recompiling them.
+pub struct ShaderCache<R: gfx::Resources> {
+    sets: FnvHashMap<u64, Arc<gfx::ShaderSet<R>>>,
+}
+
+impl<R: gfx::Resources> ShaderCache<R> {
+    /// Create an empty cache
+    pub fn new() -> ShaderCache<R> {
+        ShaderCache {
+            sets: Default::default(),
+        }
+    }
+
+    /// The number of distinct shader sets compiled
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    /// Whether nothing has been compiled yet
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// Forget every cached shader set. Sets still held elsewhere stay alive.
+    pub fn clear(&mut self) {
+        self.sets.clear();
+    }
+
+    /// Get the shader set built from the given vertex and fragment sources with `defines`
+    /// (name, value) applied to both, compiling and caching it if it hasn't been seen.
+    pub fn get_or_compile<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        vertex: &str,
+        fragment: &str,
+        defines: &[(&str, &str)],
+    )
+        -> Result<Arc<gfx::ShaderSet<R>>, Error>
+    {
+        let vertex = with_defines(vertex, defines);
+        let fragment = with_defines(fragment, defines);
+        let key = source_hash(&vertex, &fragment);
+        if let Some(set) = self.sets.get(&key) {
+            return Ok(set.clone());
+        }
+        let set = Arc::new(gfx::ShaderSet::Simple(
+            factory.create_shader_vertex(vertex.as_bytes())?,
+            factory.create_shader_pixel(fragment.as_bytes())?,
+        ));
+        self.sets.insert(key, set.clone());
+        Ok(set)
+    }
+}
+
+impl<R: gfx::Resources> Default for ShaderCache<R> {
+    fn default() -> ShaderCache<R> {
+        ShaderCache::new()
+    }
+}
+
+fn source_hash(vertex: &str, fragment: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = ::fnv::FnvHasher::default();
+    vertex.hash(&mut hasher);
+    fragment.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn shader_cache_keys() {
+    let src = "#version 410\nvoid main() {}";
+    assert_eq!(with_defines(src, &[]), src);
+    assert_eq!(with_defines(src, &[("TEX", ""), ("STEPS", "8")]),
+        "#version 410\n#define TEX \n#define STEPS 8\n#line 1\n\nvoid main() {}");
+    assert_eq!(with_defines("void main() {}", &[("TEX", "")]), "#define TEX \n#line 1\nvoid main() {}");
+
+    let a = source_hash(src, &with_defines(src, &[("TEX", "")]));
+    assert_eq!(a, source_hash(src, &with_defines(src, &[("TEX", "")])));
+    assert!(a != source_hash(src, &with_defines(src, &[("NORM", "")])));
+    // the stage a source belongs to is part of the key
+    assert!(source_hash("a", "b") != source_hash("b", "a"));
+}
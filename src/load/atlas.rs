@@ -0,0 +1,196 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use gfx;
+use gfx::format::*;
+use fnv::FnvHashMap;
+
+use ::{Error, FlightError, Texture};
+use super::load_rgba8;
+
+/// The largest width or height `AtlasPacker` grows atlases to by default
+pub const DEFAULT_MAX_ATLAS_SIZE: u32 = 4096;
+
+/// Packs many small images, such as decals and particle sprites, into one power of two
+/// atlas so that they can be drawn without switching textures. Images are placed on
+/// shelves, tallest first, in the smallest atlas they fit in.
+pub struct AtlasPacker {
+    /// Pixels around each image filled by repeating its edge, so that filtering doesn't
+    /// bleed neighbouring images into it
+    pub padding: u32,
+    /// The largest width or height the atlas may grow to
+    pub max_size: u32,
+    images: Vec<(String, RgbaImage)>,
+}
+
+impl AtlasPacker {
+    /// Create an empty packer leaving `padding` pixels around each image
+    pub fn new(padding: u32) -> AtlasPacker {
+        AtlasPacker {
+            padding: padding,
+            max_size: DEFAULT_MAX_ATLAS_SIZE,
+            images: Vec::new(),
+        }
+    }
+
+    /// Add an image, replacing any earlier one with the same name
+    pub fn add(&mut self, name: String, img: DynamicImage) {
+        let img = img.to_rgba();
+        match self.images.iter_mut().find(|&&mut (ref n, _)| *n == name) {
+            Some(entry) => entry.1 = img,
+            None => self.images.push((name, img)),
+        }
+    }
+
+    /// The number of images added
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// True if no images have been added
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// The size of an image with its padding
+    fn cell(&self, img: &RgbaImage) -> (u32, u32) {
+        let (w, h) = img.dimensions();
+        (w + 2 * self.padding, h + 2 * self.padding)
+    }
+
+    /// The corner of each padded image on shelves in an atlas of the given size, or `None`
+    /// if they don't fit
+    fn shelves(&self, width: u32, height: u32) -> Option<Vec<(u32, u32)>> {
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|&i| {
+            let (w, h) = self.cell(&self.images[i].1);
+            (!h, !w)
+        });
+        let mut corners = vec![(0, 0); self.images.len()];
+        let (mut x, mut y, mut shelf) = (0, 0, 0);
+        for i in order {
+            let (w, h) = self.cell(&self.images[i].1);
+            if x + w > width {
+                // start a new shelf above the tallest image of this one
+                x = 0;
+                y += shelf;
+                shelf = 0;
+            }
+            if x + w > width || y + h > height {
+                return None;
+            }
+            corners[i] = (x, y);
+            x += w;
+            shelf = shelf.max(h);
+        }
+        Some(corners)
+    }
+
+    /// Pack the images into an atlas image, returning it with the texture coordinates of
+    /// each image by name as (left, bottom, right, top). Like `text::Glyph::uv`, these are
+    /// mesh texture coordinates, with their origin at the bottom of the image.
+    pub fn pack_image(&self) -> Result<(RgbaImage, FnvHashMap<String, [f32; 4]>), Error> {
+        let area: u64 = self.images.iter()
+            .map(|&(_, ref img)| {
+                let (w, h) = self.cell(img);
+                w as u64 * h as u64
+            })
+            .sum();
+        let widest = self.images.iter().map(|&(_, ref img)| self.cell(img).0).max().unwrap_or(1);
+        let tallest = self.images.iter().map(|&(_, ref img)| self.cell(img).1).max().unwrap_or(1);
+        let side = ((area as f64).sqrt().ceil() as u32).max(1).next_power_of_two();
+        let (mut width, mut height) = (side.max(widest.next_power_of_two()), side.max(tallest.next_power_of_two()));
+        let corners = loop {
+            ensure!(width <= self.max_size && height <= self.max_size,
+                FlightError::AtlasFull { max_size: self.max_size });
+            if let Some(c) = self.shelves(width, height) {
+                break c;
+            }
+            if width <= height {
+                width *= 2;
+            } else {
+                height *= 2;
+            }
+        };
+
+        let mut atlas = RgbaImage::from_pixel(width, height, Rgba([0; 4]));
+        let mut rects = FnvHashMap::default();
+        let p = self.padding;
+        for (&(ref name, ref img), &(x, y)) in self.images.iter().zip(&corners) {
+            let (w, h) = img.dimensions();
+            let (cw, ch) = self.cell(img);
+            for cy in 0..ch {
+                for cx in 0..cw {
+                    // the padding repeats the nearest edge pixel
+                    let sx = cx.max(p).min(p + w - 1) - p;
+                    let sy = cy.max(p).min(p + h - 1) - p;
+                    atlas.put_pixel(x + cx, y + cy, *img.get_pixel(sx, sy));
+                }
+            }
+            let (fw, fh) = (width as f32, height as f32);
+            rects.insert(name.clone(), [
+                (x + p) as f32 / fw,
+                1. - (y + p + h) as f32 / fh,
+                (x + p + w) as f32 / fw,
+                1. - (y + p) as f32 / fh,
+            ]);
+        }
+        Ok((atlas, rects))
+    }
+
+    /// Pack the images into an atlas texture, see `pack_image`
+    pub fn pack<R, F>(&self, f: &mut F)
+        -> Result<(Texture<R, (R8_G8_B8_A8, Srgb)>, FnvHashMap<String, [f32; 4]>), Error>
+        where
+            R: gfx::Resources,
+            F: gfx::Factory<R>,
+    {
+        use gfx::texture::*;
+        let (atlas, rects) = self.pack_image()?;
+        let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp));
+        Ok((load_rgba8(f, atlas, sampler)?, rects))
+    }
+}
+
+#[test]
+fn atlas_packing() {
+    let solid = |w, h, v| DynamicImage::ImageRgba8(RgbaImage::from_pixel(w, h, Rgba([v, v, v, 255])));
+    let mut packer = AtlasPacker::new(2);
+    packer.add("wide".to_string(), solid(20, 4, 10));
+    packer.add("tall".to_string(), solid(4, 12, 20));
+    packer.add("dot".to_string(), solid(1, 1, 30));
+    packer.add("dot".to_string(), solid(3, 3, 40));
+    assert_eq!(packer.len(), 3);
+    let (atlas, rects) = packer.pack_image().unwrap();
+    let (aw, ah) = atlas.dimensions();
+    assert!(aw.is_power_of_two() && ah.is_power_of_two());
+
+    // back to pixel rows, which run down the image
+    let pixels = |r: &[f32; 4]| (
+        (r[0] * aw as f32).round() as u32,
+        ((1. - r[3]) * ah as f32).round() as u32,
+        (r[2] * aw as f32).round() as u32,
+        ((1. - r[1]) * ah as f32).round() as u32,
+    );
+    let mut cells = vec![];
+    for &(name, w, h, v) in &[("wide", 20, 4, 10), ("tall", 4, 12, 20), ("dot", 3, 3, 40)] {
+        let (x0, y0, x1, y1) = pixels(&rects[name]);
+        assert_eq!((x1 - x0, y1 - y0), (w, h));
+        // the image and its padding hold its color
+        for y in (y0 - 2)..(y1 + 2) {
+            for x in (x0 - 2)..(x1 + 2) {
+                assert_eq!(atlas.get_pixel(x, y).data[0], v);
+            }
+        }
+        cells.push((x0 - 2, y0 - 2, x1 + 2, y1 + 2));
+    }
+    for (i, a) in cells.iter().enumerate() {
+        for b in &cells[i + 1..] {
+            assert!(a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1);
+        }
+    }
+
+    let mut full = AtlasPacker::new(0);
+    full.max_size = 16;
+    full.add("big".to_string(), solid(17, 1, 0));
+    assert!(full.pack_image().is_err());
+    assert_eq!(AtlasPacker::new(1).pack_image().unwrap().0.dimensions(), (1, 1));
+}
@@ -0,0 +1,311 @@
+use std::io::Write;
+use nalgebra::{Vector3, Point2};
+
+use ::{Error, FlightError};
+use ::mesh::{MeshSource, Indexing, Primitive, Vertex, Vert, VertN, VertC, VertNC, VertNT, VertNTT};
+use ::mesh::{HasNorm, HasTex};
+
+/// A vertex whose normal and texture coordinates, where it has them, can be exported
+pub trait ExportVertex: Vertex {
+    /// The normal, if the vertex has one
+    fn export_norm(&self) -> Option<Vector3<f32>> { None }
+    /// The texture coordinates, with their origin at the bottom of the image, if the
+    /// vertex has them
+    fn export_tex(&self) -> Option<Point2<f32>> { None }
+}
+
+impl ExportVertex for Vert {}
+
+impl ExportVertex for VertC {}
+
+impl ExportVertex for VertN {
+    fn export_norm(&self) -> Option<Vector3<f32>> { Some(*self.norm()) }
+}
+
+impl ExportVertex for VertNC {
+    fn export_norm(&self) -> Option<Vector3<f32>> { Some(*self.norm()) }
+}
+
+impl ExportVertex for VertNT {
+    fn export_norm(&self) -> Option<Vector3<f32>> { Some(*self.norm()) }
+    fn export_tex(&self) -> Option<Point2<f32>> { Some(*self.tex()) }
+}
+
+impl ExportVertex for VertNTT {
+    fn export_norm(&self) -> Option<Vector3<f32>> { Some(*self.norm()) }
+    fn export_tex(&self) -> Option<Point2<f32>> { Some(*self.tex()) }
+}
+
+/// Surface values written into the material of a glTF export
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportMaterial {
+    pub name: String,
+    /// Linear color and opacity
+    pub base_color: [f32; 4],
+    pub metalness: f32,
+    pub roughness: f32,
+    /// Linear emitted color
+    pub emissive: [f32; 3],
+}
+
+impl Default for ExportMaterial {
+    fn default() -> ExportMaterial {
+        ExportMaterial {
+            name: "material".to_string(),
+            base_color: [1.; 4],
+            metalness: 0.,
+            roughness: 0.5,
+            emissive: [0.; 3],
+        }
+    }
+}
+
+/// The triangles of a mesh as vertex indices. Strips are unrolled with the winding kept,
+/// skipping the degenerate triangles that join their pieces.
+pub fn triangles<V, M>(mesh: &MeshSource<V, M>) -> Result<Vec<[u32; 3]>, Error> {
+    let inds: Vec<u32> = match mesh.inds {
+        Indexing::Inds(ref i) => i.clone(),
+        Indexing::Range(a, b) => (a..b).collect(),
+        Indexing::All => (0..mesh.verts.len() as u32).collect(),
+    };
+    ensure!(inds.iter().all(|&i| (i as usize) < mesh.verts.len()),
+        FlightError::InvalidShape { reason: "an index is past the end of the vertices" });
+    Ok(match mesh.prim {
+        Primitive::TriangleList => inds.chunks(3)
+            .filter(|t| t.len() == 3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        Primitive::TriangleStrip => inds.windows(3)
+            .enumerate()
+            .filter(|&(_, t)| t[0] != t[1] && t[1] != t[2] && t[0] != t[2])
+            .map(|(n, t)| if n % 2 == 0 { [t[0], t[1], t[2]] } else { [t[1], t[0], t[2]] })
+            .collect(),
+        _ => bail!(FlightError::InvalidShape { reason: "only triangle meshes can be exported" }),
+    })
+}
+
+/// Write a mesh as a wavefront OBJ file, with normals and texture coordinates if its
+/// vertices have them
+pub fn write_obj<W: Write, V: ExportVertex, M>(w: &mut W, mesh: &MeshSource<V, M>) -> Result<(), Error> {
+    let tris = triangles(mesh)?;
+    let has_norm = mesh.verts.first().map_or(false, |v| v.export_norm().is_some());
+    let has_tex = mesh.verts.first().map_or(false, |v| v.export_tex().is_some());
+    writeln!(w, "# exported by flight")?;
+    for v in &mesh.verts {
+        let p = v.pos();
+        writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for t in mesh.verts.iter().filter_map(|v| v.export_tex()) {
+        writeln!(w, "vt {} {}", t.x, t.y)?;
+    }
+    for n in mesh.verts.iter().filter_map(|v| v.export_norm()) {
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    // each vertex has the same index in all three lists, counting from 1
+    let corner = |i: u32| match (has_tex, has_norm) {
+        (true, true) => format!("{0}/{0}/{0}", i + 1),
+        (true, false) => format!("{0}/{0}", i + 1),
+        (false, true) => format!("{0}//{0}", i + 1),
+        (false, false) => format!("{}", i + 1),
+    };
+    for t in &tris {
+        writeln!(w, "f {} {} {}", corner(t[0]), corner(t[1]), corner(t[2]))?;
+    }
+    Ok(())
+}
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+const GL_FLOAT: u32 = 5126;
+const GL_UNSIGNED_INT: u32 = 5125;
+const GL_ARRAY_BUFFER: u32 = 34962;
+const GL_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+fn push_f32s(bin: &mut Vec<u8>, values: &[f32]) {
+    for v in values {
+        bin.extend_from_slice(&v.to_bits().to_le_bytes());
+    }
+}
+
+/// Write a mesh as a binary glTF (GLB) file holding one node, with normals and texture
+/// coordinates if its vertices have them and `material` if given. glTF puts the origin of
+/// texture coordinates at the top of the image, so they are flipped.
+pub fn write_glb<W: Write, V: ExportVertex, M>(w: &mut W, mesh: &MeshSource<V, M>, material: Option<&ExportMaterial>)
+    -> Result<(), Error>
+{
+    let tris = triangles(mesh)?;
+    let count = mesh.verts.len();
+    let mut bin = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut attributes = json!({});
+
+    // positions, with the bounds glTF requires
+    let bounds = mesh.bounds();
+    let start = bin.len();
+    for v in &mesh.verts {
+        let p = v.pos();
+        push_f32s(&mut bin, &[p.x, p.y, p.z]);
+    }
+    views.push(json!({ "buffer": 0, "byteOffset": start, "byteLength": bin.len() - start, "target": GL_ARRAY_BUFFER }));
+    accessors.push(json!({
+        "bufferView": views.len() - 1, "componentType": GL_FLOAT, "count": count, "type": "VEC3",
+        "min": [bounds.min.x, bounds.min.y, bounds.min.z],
+        "max": [bounds.max.x, bounds.max.y, bounds.max.z],
+    }));
+    attributes["POSITION"] = json!(accessors.len() - 1);
+
+    let norms: Vec<_> = mesh.verts.iter().filter_map(|v| v.export_norm()).collect();
+    if norms.len() == count && count > 0 {
+        let start = bin.len();
+        for n in &norms {
+            push_f32s(&mut bin, &[n.x, n.y, n.z]);
+        }
+        views.push(json!({ "buffer": 0, "byteOffset": start, "byteLength": bin.len() - start, "target": GL_ARRAY_BUFFER }));
+        accessors.push(json!({ "bufferView": views.len() - 1, "componentType": GL_FLOAT, "count": count, "type": "VEC3" }));
+        attributes["NORMAL"] = json!(accessors.len() - 1);
+    }
+
+    let texs: Vec<_> = mesh.verts.iter().filter_map(|v| v.export_tex()).collect();
+    if texs.len() == count && count > 0 {
+        let start = bin.len();
+        for t in &texs {
+            push_f32s(&mut bin, &[t.x, 1. - t.y]);
+        }
+        views.push(json!({ "buffer": 0, "byteOffset": start, "byteLength": bin.len() - start, "target": GL_ARRAY_BUFFER }));
+        accessors.push(json!({ "bufferView": views.len() - 1, "componentType": GL_FLOAT, "count": count, "type": "VEC2" }));
+        attributes["TEXCOORD_0"] = json!(accessors.len() - 1);
+    }
+
+    let start = bin.len();
+    for i in tris.iter().flat_map(|t| t.iter()) {
+        bin.extend_from_slice(&i.to_le_bytes());
+    }
+    views.push(json!({ "buffer": 0, "byteOffset": start, "byteLength": bin.len() - start, "target": GL_ELEMENT_ARRAY_BUFFER }));
+    accessors.push(json!({ "bufferView": views.len() - 1, "componentType": GL_UNSIGNED_INT, "count": tris.len() * 3, "type": "SCALAR" }));
+
+    let mut primitive = json!({ "attributes": attributes, "indices": accessors.len() - 1, "mode": 4 });
+    let mut doc = json!({
+        "asset": { "version": "2.0", "generator": "flight" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "buffers": [{ "byteLength": bin.len() }],
+        "bufferViews": views,
+        "accessors": accessors,
+    });
+    if let Some(m) = material {
+        primitive["material"] = json!(0);
+        doc["materials"] = json!([{
+            "name": m.name,
+            "pbrMetallicRoughness": {
+                "baseColorFactor": m.base_color,
+                "metallicFactor": m.metalness,
+                "roughnessFactor": m.roughness,
+            },
+            "emissiveFactor": m.emissive,
+        }]);
+    }
+    doc["meshes"] = json!([{ "primitives": [primitive] }]);
+
+    // chunks are padded to 4 bytes, JSON with spaces and binary with zeros
+    let mut text = ::serde_json::to_vec(&doc)?;
+    while text.len() % 4 != 0 {
+        text.push(b' ');
+    }
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+    let total = 12 + 8 + text.len() + 8 + bin.len();
+    for word in &[GLB_MAGIC, 2, total as u32, text.len() as u32, GLB_JSON] {
+        w.write_all(&word.to_le_bytes())?;
+    }
+    w.write_all(&text)?;
+    for word in &[bin.len() as u32, GLB_BIN] {
+        w.write_all(&word.to_le_bytes())?;
+    }
+    w.write_all(&bin)?;
+    Ok(())
+}
+
+impl<V: ExportVertex, M> MeshSource<V, M> {
+    /// Write the mesh as a wavefront OBJ file, see `write_obj`
+    pub fn export_obj<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        write_obj(w, self)
+    }
+
+    /// Write the mesh as a binary glTF file, see `write_glb`
+    pub fn export_glb<W: Write>(&self, w: &mut W, material: Option<&ExportMaterial>) -> Result<(), Error> {
+        write_glb(w, self, material)
+    }
+}
+
+#[test]
+fn export_round_trip() {
+    use wavefront::Obj;
+    use ::mesh::{gen, Aabb};
+
+    let sphere = gen::sphere(0.5, 12, 8);
+    let strip = MeshSource {
+        verts: vec![
+            VertN { pos: [0., 0., 0.], norm: [0., 0., 1.] },
+            VertN { pos: [1., 0., 0.], norm: [0., 0., 1.] },
+            VertN { pos: [0., 2., 0.], norm: [0., 0., 1.] },
+            VertN { pos: [1., 2., 0.], norm: [0., 0., 1.] },
+        ],
+        inds: Indexing::All,
+        prim: Primitive::TriangleStrip,
+        mat: (),
+    };
+    let same_bounds = |a: &Aabb, b: &Aabb| relative_eq!(a.min, b.min) && relative_eq!(a.max, b.max);
+
+    // OBJ comes back through the wavefront importer
+    for (bounds, count, obj) in vec![
+        (sphere.bounds(), triangles(&sphere).unwrap().len(), { let mut o = Vec::new(); sphere.export_obj(&mut o).unwrap(); o }),
+        (strip.bounds(), 2, { let mut o = Vec::new(); strip.export_obj(&mut o).unwrap(); o }),
+    ] {
+        let back = super::load_wavefront(&Obj::load_buf(&mut &obj[..]).unwrap()).unwrap();
+        assert!(same_bounds(&back.bounds(), &bounds));
+        assert_eq!(triangles(&back).unwrap().len(), count);
+    }
+    // strips keep their winding
+    let tris = triangles(&strip).unwrap();
+    assert_eq!(tris, vec![[0, 1, 2], [2, 1, 3]]);
+
+    // GLB holds the same bounds, triangles and material
+    let mut glb = Vec::new();
+    let mat = ExportMaterial { roughness: 0.25, ..Default::default() };
+    sphere.export_glb(&mut glb, Some(&mat)).unwrap();
+    let word = |at: usize| {
+        let mut b = [0; 4];
+        b.copy_from_slice(&glb[at..at + 4]);
+        u32::from_le_bytes(b)
+    };
+    assert_eq!((word(0), word(4), word(8) as usize), (GLB_MAGIC, 2, glb.len()));
+    let json_len = word(12) as usize;
+    let doc: ::serde_json::Value = ::serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+    let bin = &glb[20 + json_len + 8..];
+    let prim = &doc["meshes"][0]["primitives"][0];
+    let position = &doc["accessors"][prim["attributes"]["POSITION"].as_u64().unwrap() as usize];
+    assert_eq!(position["count"].as_u64().unwrap() as usize, sphere.verts.len());
+    let indices = &doc["accessors"][prim["indices"].as_u64().unwrap() as usize];
+    assert_eq!(indices["count"].as_u64().unwrap() as usize, triangles(&sphere).unwrap().len() * 3);
+    // bounds from the position data itself
+    let view = &doc["bufferViews"][position["bufferView"].as_u64().unwrap() as usize];
+    let start = view["byteOffset"].as_u64().unwrap() as usize;
+    let mut read = Aabb::empty();
+    for v in bin[start..start + 12 * sphere.verts.len()].chunks(12) {
+        let f = |i: usize| {
+            let mut b = [0; 4];
+            b.copy_from_slice(&v[i * 4..i * 4 + 4]);
+            f32::from_bits(u32::from_le_bytes(b))
+        };
+        read.extend(&::nalgebra::Point3::new(f(0), f(1), f(2)));
+    }
+    assert!(same_bounds(&read, &sphere.bounds()));
+    assert_eq!(doc["materials"][0]["pbrMetallicRoughness"]["roughnessFactor"].as_f64(), Some(0.25));
+
+    let lines = MeshSource { prim: Primitive::LineList, ..strip };
+    assert!(lines.export_obj(&mut Vec::new()).is_err());
+}
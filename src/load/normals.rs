@@ -0,0 +1,141 @@
+use image::RgbaImage;
+
+/// Which way the green channel of a tangent space normal map points
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NormalConvention {
+    /// Green points up the image, as the uber shader expects (Blender, Maya)
+    OpenGL,
+    /// Green points down the image (3ds Max, Unreal, Substance's DirectX export)
+    DirectX,
+    /// Decide from the contents of the map, see `detect_normal_convention`
+    AutoDetect,
+}
+
+impl Default for NormalConvention {
+    fn default() -> NormalConvention {
+        NormalConvention::OpenGL
+    }
+}
+
+/// How much more consistent one convention has to be before `detect_normal_convention`
+/// picks it
+const DETECT_MARGIN: f32 = 0.8;
+
+/// The slopes of the surface along x and y at a texel, decoded from red and green with z
+/// rebuilt so that two channel maps work as well
+fn slopes(image: &RgbaImage, x: u32, y: u32) -> (f32, f32) {
+    let p = image.get_pixel(x, y).data;
+    let nx = p[0] as f32 / 127.5 - 1.;
+    let ny = p[1] as f32 / 127.5 - 1.;
+    let nz = (1. - nx * nx - ny * ny).max(0.01).sqrt();
+    (nx / nz, ny / nz)
+}
+
+/// Guess the convention of a normal map, or `None` if the map is too flat or too noisy to
+/// tell. A normal map stores the slopes of a height field, whose mixed derivatives must
+/// agree. That only holds with the green channel the right way up, so the convention that
+/// keeps them closest wins.
+pub fn detect_normal_convention(image: &RgbaImage) -> Option<NormalConvention> {
+    let (width, height) = image.dimensions();
+    if width < 2 || height < 2 {
+        return None;
+    }
+    let (mut opengl, mut directx) = (0., 0.);
+    for y in 0..(height - 1) {
+        for x in 0..(width - 1) {
+            let (a, b) = slopes(image, x, y);
+            let a_down = slopes(image, x, y + 1).0;
+            let b_right = slopes(image, x + 1, y).1;
+            // with green up the image, red changes down the rows as much as green changes
+            // across the columns, but the other way
+            let (da, db) = (a_down - a, b_right - b);
+            opengl += (da + db).abs();
+            directx += (da - db).abs();
+        }
+    }
+    if opengl < directx * DETECT_MARGIN {
+        Some(NormalConvention::OpenGL)
+    } else if directx < opengl * DETECT_MARGIN {
+        Some(NormalConvention::DirectX)
+    } else {
+        None
+    }
+}
+
+/// Flip the green channel of every texel
+pub fn flip_green(image: &mut RgbaImage) {
+    for p in image.pixels_mut() {
+        p.data[1] = 255 - p.data[1];
+    }
+}
+
+/// Bring a normal map named `what` into the OpenGL convention, detecting its convention
+/// first if asked to and logging the decision. Returns the convention the map was in,
+/// which is OpenGL for maps that can't be told apart.
+pub fn convert_normal_map(image: &mut RgbaImage, convention: NormalConvention, what: &str) -> NormalConvention {
+    let found = match convention {
+        NormalConvention::AutoDetect => match detect_normal_convention(image) {
+            Some(c) => {
+                info!("{} looks like a {:?} normal map", what, c);
+                c
+            }
+            None => {
+                info!("could not tell the convention of normal map {}, assuming OpenGL", what);
+                NormalConvention::OpenGL
+            }
+        },
+        c => c,
+    };
+    if found == NormalConvention::DirectX {
+        info!("flipping the green channel of {} to the OpenGL convention", what);
+        flip_green(image);
+    }
+    found
+}
+
+#[test]
+fn normal_conventions() {
+    use image::Rgba;
+
+    // bumps of different sizes, encoded with green up the image
+    let size = 64;
+    let height = |x: f32, y: f32| {
+        let bump = |cx: f32, cy: f32, r: f32| (-((x - cx).powi(2) + (y - cy).powi(2)) / (r * r)).exp();
+        4. * bump(20., 24., 6.) + 3. * bump(44., 40., 9.) - 2. * bump(40., 14., 4.)
+    };
+    let opengl = RgbaImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x as f32, y as f32);
+        let dx = height(x + 0.5, y) - height(x - 0.5, y);
+        let dy = height(x, y + 0.5) - height(x, y - 0.5);
+        // rows run down the image, green runs up it
+        let n = [-dx, dy, 1.];
+        let l = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        let enc = |v: f32| ((v / l * 0.5 + 0.5) * 255.).round() as u8;
+        Rgba([enc(n[0]), enc(n[1]), enc(n[2]), 255])
+    });
+    let mut directx = opengl.clone();
+    flip_green(&mut directx);
+
+    assert_eq!(detect_normal_convention(&opengl), Some(NormalConvention::OpenGL));
+    assert_eq!(detect_normal_convention(&directx), Some(NormalConvention::DirectX));
+    let flat = RgbaImage::from_pixel(8, 8, Rgba([128, 128, 255, 255]));
+    assert_eq!(detect_normal_convention(&flat), None);
+
+    // both maps light the same once loaded
+    let mut a = opengl.clone();
+    assert_eq!(convert_normal_map(&mut a, NormalConvention::AutoDetect, "opengl"), NormalConvention::OpenGL);
+    let mut b = directx.clone();
+    assert_eq!(convert_normal_map(&mut b, NormalConvention::AutoDetect, "directx"), NormalConvention::DirectX);
+    assert_eq!(a.into_raw(), opengl.clone().into_raw());
+    assert_eq!(b.into_raw(), opengl.clone().into_raw());
+    let mut c = directx.clone();
+    convert_normal_map(&mut c, NormalConvention::DirectX, "directx");
+    assert_eq!(c.into_raw(), opengl.into_raw());
+
+    // two channel maps decode the same without blue
+    let mut rg = directx.clone();
+    for p in rg.pixels_mut() {
+        p.data[2] = 0;
+    }
+    assert_eq!(detect_normal_convention(&rg), Some(NormalConvention::DirectX));
+}
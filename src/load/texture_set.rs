@@ -0,0 +1,394 @@
+use image::{imageops, FilterType, GrayImage, Rgba, RgbaImage, open as open_image};
+use gfx;
+use gfx::format::*;
+use fnv::FnvHashMap;
+
+use std::path::{Path, PathBuf};
+
+use ::{Error, FlightError, Texture};
+use ::draw::{UberMaterial, NormalEncoding, LinearFormat};
+use ::mesh::gen::Surface;
+use super::{load_rgba8, convert_normal_map, NormalConvention};
+
+/// The roughness of surfaces without a roughness map, between polished and matte
+pub const DEFAULT_ROUGHNESS: u8 = 0x80;
+/// The metalness of surfaces without a metalness map (dielectric)
+pub const DEFAULT_METALNESS: u8 = 0x00;
+/// The ambient occlusion of surfaces without an occlusion map (unoccluded)
+pub const DEFAULT_OCCLUSION: u8 = 0xFF;
+
+/// Image file extensions `find_texture_set` looks at
+const TEXTURE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tga", "bmp", "tif", "tiff"];
+
+/// What a texture in a texture set folder is for, going by its file name
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureRole {
+    Albedo,
+    Normal(NormalConvention),
+    Roughness,
+    Metalness,
+    Occlusion,
+    Emissive,
+}
+
+impl TextureRole {
+    /// Guess the role of a texture from the words of its file name, such as
+    /// `rock_basecolor.png`, `Rock-Roughness.jpg` or `rock_nor_gl_4k.png`
+    pub fn from_file_name(name: &str) -> Option<TextureRole> {
+        use self::TextureRole::*;
+        let stem = Path::new(name).file_stem()?.to_str()?.to_lowercase();
+        let words: Vec<_> = stem.split(|c: char| !c.is_alphanumeric()).collect();
+        // the last matching word wins, since names tend to end with the role
+        let role = words.iter()
+            .filter_map(|&word| Some(match word {
+                "albedo" | "basecolor" | "base" | "diffuse" | "diff" | "color" | "col" => Albedo,
+                "normal" | "nrm" | "nor" => Normal(NormalConvention::AutoDetect),
+                "normalgl" => Normal(NormalConvention::OpenGL),
+                "normaldx" => Normal(NormalConvention::DirectX),
+                "roughness" | "rough" | "rgh" => Roughness,
+                "metalness" | "metallic" | "metal" | "mtl" => Metalness,
+                "ao" | "occlusion" | "ambientocclusion" => Occlusion,
+                "emissive" | "emission" | "emit" => Emissive,
+                _ => return None,
+            }))
+            .last()?;
+        // the convention can also be a word of its own, such as `nor_gl`
+        Some(match role {
+            Normal(NormalConvention::AutoDetect) if words.contains(&"gl") => Normal(NormalConvention::OpenGL),
+            Normal(NormalConvention::AutoDetect) if words.contains(&"dx") => Normal(NormalConvention::DirectX),
+            r => r,
+        })
+    }
+}
+
+/// The textures found in a texture set folder, see `find_texture_set`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureSet {
+    pub albedo: Option<PathBuf>,
+    pub normal: Option<(PathBuf, NormalConvention)>,
+    pub roughness: Option<PathBuf>,
+    pub metalness: Option<PathBuf>,
+    pub occlusion: Option<PathBuf>,
+    pub emissive: Option<PathBuf>,
+}
+
+/// The one file for a role, or `FlightError::AmbiguousTexture` listing the candidates
+fn single<T>(role: &'static str, mut candidates: Vec<(PathBuf, T)>) -> Result<Option<(PathBuf, T)>, Error> {
+    ensure!(candidates.len() <= 1, FlightError::AmbiguousTexture {
+        role: role,
+        candidates: candidates.iter().map(|&(ref p, _)| p.display().to_string()).collect(),
+    });
+    Ok(candidates.pop())
+}
+
+impl TextureSet {
+    /// Sort files into a texture set by name. Files with no role are ignored, and several
+    /// files with the same role are an error, except that an OpenGL normal map is picked
+    /// over other normal maps (many sets come with both conventions).
+    pub fn from_files<I: IntoIterator<Item = PathBuf>>(files: I) -> Result<TextureSet, Error> {
+        let mut found: Vec<(PathBuf, TextureRole)> = files.into_iter()
+            .filter_map(|path| {
+                let role = path.file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(TextureRole::from_file_name)?;
+                Some((path, role))
+            })
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        let with = |role: TextureRole| found.iter()
+            .filter(|&&(_, r)| r == role)
+            .map(|&(ref p, _)| (p.clone(), ()))
+            .collect::<Vec<_>>();
+        let path = |c: Option<(PathBuf, ())>| c.map(|(p, _)| p);
+
+        let mut normals: Vec<_> = found.iter()
+            .filter_map(|&(ref p, r)| match r {
+                TextureRole::Normal(c) => Some((p.clone(), c)),
+                _ => None,
+            })
+            .collect();
+        if normals.iter().filter(|&&(_, c)| c == NormalConvention::OpenGL).count() == 1 {
+            normals.retain(|&(_, c)| c == NormalConvention::OpenGL);
+        }
+
+        Ok(TextureSet {
+            albedo: path(single("albedo", with(TextureRole::Albedo))?),
+            normal: single("normal", normals)?,
+            roughness: path(single("roughness", with(TextureRole::Roughness))?),
+            metalness: path(single("metalness", with(TextureRole::Metalness))?),
+            occlusion: path(single("ambient occlusion", with(TextureRole::Occlusion))?),
+            emissive: path(single("emissive", with(TextureRole::Emissive))?),
+        })
+    }
+}
+
+/// Find the textures of a texture set, as exported by Substance or downloaded from Quixel
+/// or Poly Haven, among the images in a folder
+pub fn find_texture_set<P: AsRef<Path>>(dir: P) -> Result<TextureSet, Error> {
+    let mut files = vec![];
+    for entry in dir.as_ref().read_dir()? {
+        let path = entry?.path();
+        let image = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| TEXTURE_EXTENSIONS.contains(&&e.to_lowercase()[..]))
+            .unwrap_or(false);
+        if image {
+            files.push(path);
+        }
+    }
+    TextureSet::from_files(files)
+}
+
+/// Textures loaded by `load_material_dir`, keyed by the files they were made from, so that
+/// materials sharing files share textures
+pub struct TextureCache<R: gfx::Resources> {
+    srgb: FnvHashMap<PathBuf, Texture<R, (R8_G8_B8_A8, Srgb)>>,
+    linear: FnvHashMap<Vec<PathBuf>, Texture<R, LinearFormat>>,
+}
+
+impl<R: gfx::Resources> TextureCache<R> {
+    /// Create an empty cache
+    pub fn new() -> TextureCache<R> {
+        TextureCache {
+            srgb: Default::default(),
+            linear: Default::default(),
+        }
+    }
+
+    /// The number of textures loaded
+    pub fn len(&self) -> usize {
+        self.srgb.len() + self.linear.len()
+    }
+
+    /// Whether nothing has been loaded yet
+    pub fn is_empty(&self) -> bool {
+        self.srgb.is_empty() && self.linear.is_empty()
+    }
+
+    /// Forget every cached texture. Textures still held by materials stay alive.
+    pub fn clear(&mut self) {
+        self.srgb.clear();
+        self.linear.clear();
+    }
+
+    fn srgb<F>(&mut self, path: &Path, load: F) -> Result<Texture<R, (R8_G8_B8_A8, Srgb)>, Error>
+        where F: FnOnce() -> Result<Texture<R, (R8_G8_B8_A8, Srgb)>, Error>
+    {
+        if let Some(t) = self.srgb.get(path) {
+            return Ok(t.clone());
+        }
+        let t = load()?;
+        self.srgb.insert(path.to_owned(), t.clone());
+        Ok(t)
+    }
+
+    fn linear<F>(&mut self, key: Vec<PathBuf>, load: F) -> Result<Texture<R, LinearFormat>, Error>
+        where F: FnOnce() -> Result<Texture<R, LinearFormat>, Error>
+    {
+        if let Some(t) = self.linear.get(&key) {
+            return Ok(t.clone());
+        }
+        let t = load()?;
+        self.linear.insert(key, t.clone());
+        Ok(t)
+    }
+}
+
+impl<R: gfx::Resources> Default for TextureCache<R> {
+    fn default() -> TextureCache<R> {
+        TextureCache::new()
+    }
+}
+
+/// Pack grayscale maps into the knobs layout of `UberMaterial`, with ambient occlusion in
+/// alpha. The maps are resized to the largest of them, and missing ones are filled with the
+/// `DEFAULT_*` constants.
+pub fn pack_knob_images(
+    roughness: Option<GrayImage>,
+    metalness: Option<GrayImage>,
+    occlusion: Option<GrayImage>,
+    flatness: f32,
+) -> RgbaImage {
+    let (width, height) = [&roughness, &metalness, &occlusion].iter()
+        .filter_map(|&i| i.as_ref())
+        .map(|i| i.dimensions())
+        .max_by_key(|&(w, h)| w as u64 * h as u64)
+        .unwrap_or((1, 1));
+    let fit = |image: Option<GrayImage>| image.map(|i| if i.dimensions() == (width, height) {
+        i
+    } else {
+        imageops::resize(&i, width, height, FilterType::Triangle)
+    });
+    let (roughness, metalness, occlusion) = (fit(roughness), fit(metalness), fit(occlusion));
+    let flatness = (flatness.max(0.).min(1.) * 255.).round() as u8;
+    let texel = |image: &Option<GrayImage>, x, y, default| match *image {
+        Some(ref i) => i.get_pixel(x, y).data[0],
+        None => default,
+    };
+    RgbaImage::from_fn(width, height, |x, y| Rgba([
+        texel(&metalness, x, y, DEFAULT_METALNESS),
+        texel(&roughness, x, y, DEFAULT_ROUGHNESS),
+        flatness,
+        texel(&occlusion, x, y, DEFAULT_OCCLUSION),
+    ]))
+}
+
+fn open_gray(path: Option<&Path>) -> Result<Option<GrayImage>, Error> {
+    Ok(match path {
+        Some(p) => Some(open_image(p)?.to_luma()),
+        None => None,
+    })
+}
+
+fn open_knob_image(roughness: Option<&Path>, metalness: Option<&Path>, occlusion: Option<&Path>, flatness: f32)
+    -> Result<RgbaImage, Error>
+{
+    Ok(pack_knob_images(open_gray(roughness)?, open_gray(metalness)?, open_gray(occlusion)?, flatness))
+}
+
+fn texture_sampler<R: gfx::Resources, F: gfx::Factory<R>>(f: &mut F) -> gfx::handle::Sampler<R> {
+    use gfx::texture::*;
+    f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Tile))
+}
+
+/// Load whichever of the separate roughness, metalness and ambient occlusion maps exist
+/// into one knobs texture for `UberMaterial`, see `pack_knob_images`
+pub fn pack_knobs<R, F>(
+    f: &mut F,
+    roughness: Option<&Path>,
+    metalness: Option<&Path>,
+    ao: Option<&Path>,
+    flatness: f32,
+)
+    -> Result<Texture<R, (R8_G8_B8_A8, Unorm)>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+{
+    let image = open_knob_image(roughness, metalness, ao, flatness)?;
+    let sampler = texture_sampler(f);
+    load_rgba8(f, image, sampler)
+}
+
+/// Load the material in a texture set folder (see `find_texture_set`), which needs at
+/// least an albedo map. Albedo is read as sRGB and everything else as linear. Missing normal
+/// and knobs maps are filled in with a flat normal and the `DEFAULT_*` constants, and the
+/// normal map is converted to the OpenGL convention. Ambient occlusion goes in both the
+/// knobs and the bent normal map, where the uber shader reads it. Emissive maps are found
+/// but not drawn, since the uber style has no emission.
+pub fn load_material_dir<R, F>(f: &mut F, cache: &mut TextureCache<R>, dir: &Path) -> Result<UberMaterial<R>, Error>
+    where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+{
+    let set = find_texture_set(dir)?;
+    let albedo_path = match set.albedo {
+        Some(ref p) => p,
+        None => return Err(FlightError::MissingTexture {
+            dir: dir.display().to_string(),
+            role: "albedo",
+        }.into()),
+    };
+    if let Some(ref p) = set.emissive {
+        warn!("ignoring emissive map {}, the uber style has no emission", p.display());
+    }
+    let sampler = texture_sampler(f);
+
+    let albedo = cache.srgb(albedo_path, || {
+        load_rgba8(f, open_image(albedo_path)?.to_rgba(), sampler.clone())
+    })?;
+    let normal = match set.normal {
+        Some((ref p, convention)) => cache.linear(vec![p.clone()], || {
+            let mut image = open_image(p)?.to_rgba();
+            convert_normal_map(&mut image, convention, &p.display().to_string());
+            load_rgba8(f, image, sampler.clone())
+        })?,
+        None => Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+    };
+    // missing maps are left as empty paths in the key
+    let knob_paths: Vec<PathBuf> = [&set.roughness, &set.metalness, &set.occlusion].iter()
+        .map(|p| p.as_ref().cloned().unwrap_or_default())
+        .collect();
+    let mut knob_image = None;
+    let knobs = cache.linear(knob_paths.clone(), || {
+        let image = open_knob_image(
+            set.roughness.as_ref().map(PathBuf::as_path),
+            set.metalness.as_ref().map(PathBuf::as_path),
+            set.occlusion.as_ref().map(PathBuf::as_path),
+            0.,
+        )?;
+        knob_image = Some(image.clone());
+        load_rgba8(f, image, sampler.clone())
+    })?;
+    let bent = match set.occlusion {
+        Some(ref ao) => cache.linear(vec![PathBuf::new(), ao.clone()], || {
+            let mut bent = match knob_image {
+                Some(image) => image,
+                None => pack_knob_images(None, None, Some(open_image(ao)?.to_luma()), 0.),
+            };
+            for p in bent.pixels_mut() {
+                p.data = [0x80, 0x80, 0xFF, p.data[3]];
+            }
+            load_rgba8(f, bent, sampler)
+        })?,
+        None => Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+    };
+
+    Ok(UberMaterial {
+        albedo: albedo,
+        normal: normal,
+        knobs: knobs,
+        bent: bent,
+        surface: Surface::Mesh,
+        normal_encoding: NormalEncoding::Rgb,
+    })
+}
+
+#[test]
+fn knob_packing() {
+    use image::Luma;
+
+    let rough = GrayImage::from_fn(4, 4, |x, _| Luma([x as u8 * 60]));
+    let metal = GrayImage::from_pixel(2, 2, Luma([200]));
+    let knobs = pack_knob_images(Some(rough), Some(metal), None, 1.);
+    assert_eq!(knobs.dimensions(), (4, 4));
+    for (x, _, p) in knobs.enumerate_pixels() {
+        assert_eq!(p.data, [200, x as u8 * 60, 0xFF, DEFAULT_OCCLUSION]);
+    }
+
+    let empty = pack_knob_images(None, None, None, 0.5);
+    assert_eq!(empty.dimensions(), (1, 1));
+    assert_eq!(empty.get_pixel(0, 0).data, [DEFAULT_METALNESS, DEFAULT_ROUGHNESS, 0x80, DEFAULT_OCCLUSION]);
+
+    let set = TextureSet::from_files(vec![
+        PathBuf::from("rock/Rock_Roughness.png"),
+        PathBuf::from("rock/Rock_BaseColor.png"),
+        PathBuf::from("rock/rock-normal.jpg"),
+        PathBuf::from("rock/Rock_AO.png"),
+        PathBuf::from("rock/preview.png"),
+    ]).unwrap();
+    assert_eq!(set.albedo, Some(PathBuf::from("rock/Rock_BaseColor.png")));
+    assert_eq!(set.normal, Some((PathBuf::from("rock/rock-normal.jpg"), NormalConvention::AutoDetect)));
+    assert_eq!(set.roughness, Some(PathBuf::from("rock/Rock_Roughness.png")));
+    assert_eq!(set.metalness, None);
+    assert_eq!(set.occlusion, Some(PathBuf::from("rock/Rock_AO.png")));
+    assert_eq!(set.emissive, None);
+    assert_eq!(TextureRole::from_file_name("rock_NormalDX.png"), Some(TextureRole::Normal(NormalConvention::DirectX)));
+
+    // Poly Haven names, with both normal conventions
+    let set = TextureSet::from_files(vec![
+        PathBuf::from("brick_diff_4k.jpg"),
+        PathBuf::from("brick_nor_dx_4k.png"),
+        PathBuf::from("brick_nor_gl_4k.png"),
+        PathBuf::from("brick_rough_4k.jpg"),
+    ]).unwrap();
+    assert_eq!(set.albedo, Some(PathBuf::from("brick_diff_4k.jpg")));
+    assert_eq!(set.normal, Some((PathBuf::from("brick_nor_gl_4k.png"), NormalConvention::OpenGL)));
+
+    let ambiguous = TextureSet::from_files(vec![
+        PathBuf::from("metal_albedo.png"),
+        PathBuf::from("Metal_BaseColor.png"),
+        PathBuf::from("metal_metallic.png"),
+    ]);
+    assert!(ambiguous.unwrap_err().to_string().contains("metal_albedo.png"));
+}
@@ -0,0 +1,176 @@
+use nalgebra::Matrix3;
+
+use ::mesh::{Aabb, HasNorm};
+
+/// The smallest extent, in meters, of a mesh that doesn't look mis-scaled
+pub const MIN_PLAUSIBLE_SIZE: f32 = 0.005;
+/// The largest extent, in meters, of a mesh that doesn't look mis-scaled
+pub const MAX_PLAUSIBLE_SIZE: f32 = 100.;
+
+/// The length of one unit in a source file
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Units {
+    Meters,
+    Centimeters,
+    Millimeters,
+    Inches,
+    Feet,
+}
+
+impl Units {
+    /// The length of one unit in meters
+    pub fn meters(&self) -> f32 {
+        use self::Units::*;
+        match *self {
+            Meters => 1.,
+            Centimeters => 0.01,
+            Millimeters => 0.001,
+            Inches => 0.0254,
+            Feet => 0.3048,
+        }
+    }
+}
+
+/// The axis pointing up in a source file. Files are assumed to be right handed, so in Z-up
+/// files (most CAD tools and Blender's native space) +Y becomes forward.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// How to bring a source file into the world convention: one unit is one meter, +Y is up
+/// and coordinates are right handed, so -Z is forward. The importers apply these to every
+/// mesh they load, and warn about meshes whose size in meters is implausible since scale
+/// mistakes are obvious right away in VR.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImportOptions {
+    /// The length of one unit in the file
+    pub source_units: Units,
+    /// The axis pointing up in the file
+    pub up_axis: Axis,
+}
+
+impl Default for ImportOptions {
+    /// A file that already follows the world convention
+    fn default() -> ImportOptions {
+        ImportOptions {
+            source_units: Units::Meters,
+            up_axis: Axis::Y,
+        }
+    }
+}
+
+impl ImportOptions {
+    /// Options for a file in the given units that is otherwise in the world convention
+    pub fn units(source_units: Units) -> ImportOptions {
+        ImportOptions {
+            source_units: source_units,
+            .. Default::default()
+        }
+    }
+
+    /// The rotation taking the file's up axis to +Y
+    pub fn rotation(&self) -> Matrix3<f32> {
+        match self.up_axis {
+            Axis::X => Matrix3::new(
+                0., -1., 0.,
+                1., 0., 0.,
+                0., 0., 1.,
+            ),
+            Axis::Y => Matrix3::identity(),
+            Axis::Z => Matrix3::new(
+                1., 0., 0.,
+                0., 0., 1.,
+                0., -1., 0.,
+            ),
+        }
+    }
+
+    /// Whether these options change anything
+    pub fn is_identity(&self) -> bool {
+        self.source_units == Units::Meters && self.up_axis == Axis::Y
+    }
+
+    /// Scale and rotate vertices from the file into the world convention
+    pub fn apply<V: HasNorm>(&self, verts: &mut [V]) {
+        if self.is_identity() { return }
+        let rot = self.rotation();
+        let scale = self.source_units.meters();
+        for v in verts {
+            let p = rot * v.pos().coords * scale;
+            v.mut_pos().coords = p;
+            let n = rot * *v.norm();
+            *v.mut_norm() = n;
+        }
+    }
+}
+
+/// The units that would give a mesh of this size a plausible size in meters, if its size in
+/// meters isn't already plausible. `None` means it looks right (or nothing fits).
+pub fn suggest_units(bounds: &Aabb) -> Option<Units> {
+    use self::Units::*;
+    if bounds.is_empty() { return None }
+    let size = bounds.max - bounds.min;
+    let extent = size.x.max(size.y).max(size.z);
+    let plausible = |e: f32| e >= MIN_PLAUSIBLE_SIZE && e <= MAX_PLAUSIBLE_SIZE;
+    if extent <= 0. || plausible(extent) { return None }
+    if extent > MAX_PLAUSIBLE_SIZE {
+        [Millimeters, Centimeters, Inches].iter()
+            .cloned()
+            .find(|u| plausible(extent * u.meters()))
+    } else {
+        // tiny meshes are usually exported in meters but scaled down, there's nothing to
+        // suggest besides checking the export
+        Some(Meters)
+    }
+}
+
+/// Warn with `warn!` if a mesh imported as `what` has an implausible size in meters
+pub fn check_scale(what: &str, bounds: &Aabb) {
+    if let Some(units) = suggest_units(bounds) {
+        let size = bounds.max - bounds.min;
+        let extent = size.x.max(size.y).max(size.z);
+        if units == Units::Meters {
+            warn!("{} is {} m across, check that it was exported at full size", what, extent);
+        } else {
+            warn!("{} is {} m across, it was likely exported in {:?}", what, extent, units);
+        }
+    }
+}
+
+#[test]
+fn import_units() {
+    use nalgebra::{Point3, Vector3};
+    use ::mesh::{VertNT, Vertex};
+
+    let mut verts = vec![
+        VertNT { pos: [0., 0., 100.], norm: [0., 0., 1.], tex: [0., 0.] },
+        VertNT { pos: [0., -200., 0.], norm: [0., -1., 0.], tex: [0., 0.] },
+    ];
+    // a Z-up file in centimeters
+    let options = ImportOptions {
+        source_units: Units::Centimeters,
+        up_axis: Axis::Z,
+    };
+    options.apply(&mut verts);
+    assert!(relative_eq!(*verts[0].pos(), Point3::new(0., 1., 0.)));
+    assert!(relative_eq!(*verts[0].norm(), Vector3::y()));
+    // Z-up +Y is forward, so -Y is back
+    assert!(relative_eq!(*verts[1].pos(), Point3::new(0., 0., 2.)));
+    assert!(relative_eq!(*verts[1].norm(), Vector3::z()));
+    // X-up
+    let up = ImportOptions { up_axis: Axis::X, .. Default::default() }.rotation();
+    assert!(relative_eq!(up * Vector3::x(), Vector3::y()));
+    assert!(relative_eq!(up.determinant(), 1.));
+
+    // a 200 meter coffee cup
+    let cup = Aabb { min: Point3::new(-40., 0., -40.), max: Point3::new(40., 200., 40.) };
+    assert_eq!(suggest_units(&cup), Some(Units::Millimeters));
+    let tiny = Aabb { min: Point3::new(0., 0., 0.), max: Point3::new(0.001, 0.001, 0.001) };
+    assert_eq!(suggest_units(&tiny), Some(Units::Meters));
+    let room = Aabb { min: Point3::new(0., 0., 0.), max: Point3::new(4., 3., 5.) };
+    assert_eq!(suggest_units(&room), None);
+    assert!(suggest_units(&Aabb::empty()).is_none());
+}
@@ -0,0 +1,304 @@
+use image::RgbaImage;
+use nalgebra::Vector3;
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ::mesh::{MeshSource, MultiMeshSource, Indexing, Primitive, HasNorm, HasTex};
+
+/// How many example indices a warning keeps
+pub const MAX_EXAMPLES: usize = 8;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Turn strict loading on or off. In strict mode the mesh and texture loaders in this module
+/// validate everything they load and log any problems with `warn!`. It is off by default,
+/// since the checks visit every vertex and texel.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Whether strict loading is on, see `set_strict`
+pub fn strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// A kind of bad asset data
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValidationKind {
+    /// Vertex positions that are NaN or infinite (examples are vertex indices)
+    NonFinitePosition,
+    /// Vertex normals that are zero length or not finite (examples are vertex indices)
+    ZeroLengthNormal,
+    /// Texture coordinates that are NaN or infinite (examples are vertex indices)
+    NonFiniteUv,
+    /// Indices past the end of the vertex list (examples are positions in the index list)
+    IndexOutOfRange,
+    /// Triangles with no area or a repeated vertex (examples are triangle numbers)
+    DegenerateTriangle,
+    /// Triangles with area but all of their texture coordinates on a line, so their
+    /// tangents are undefined (examples are triangle numbers)
+    DegenerateUv,
+    /// Normal map texels that do not decode to a unit vector (examples are texel indices)
+    NormalMapNotNormalized,
+    /// Normal map texels pointing into the surface (examples are texel indices)
+    NormalMapBackfacing,
+    /// An albedo texture so dark it was likely saved with linear instead of sRGB values
+    /// (examples are texel indices of dark texels)
+    AlbedoLooksLinear,
+}
+
+impl ValidationKind {
+    /// What to do about this kind of problem
+    pub fn advice(&self) -> &'static str {
+        use self::ValidationKind::*;
+        match *self {
+            NonFinitePosition => "re-export the mesh, the exporter wrote invalid positions",
+            ZeroLengthNormal => "recalculate normals in the modeling tool",
+            NonFiniteUv => "re-unwrap or re-export the UVs",
+            IndexOutOfRange => "the index list does not match the vertices, re-export the mesh",
+            DegenerateTriangle => "merge by distance or remove zero area faces",
+            DegenerateUv => "unwrap these faces, normal mapping needs UVs with area",
+            NormalMapNotNormalized => "re-bake the normal map or check it is not gamma corrected",
+            NormalMapBackfacing => "check the normal map is tangent space with +Z out of the surface",
+            AlbedoLooksLinear => "save the albedo texture with sRGB values",
+        }
+    }
+}
+
+impl fmt::Display for ValidationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationKind::*;
+        f.write_str(match *self {
+            NonFinitePosition => "non-finite vertex positions",
+            ZeroLengthNormal => "zero length vertex normals",
+            NonFiniteUv => "non-finite texture coordinates",
+            IndexOutOfRange => "indices out of range",
+            DegenerateTriangle => "degenerate triangles",
+            DegenerateUv => "triangles with degenerate texture coordinates",
+            NormalMapNotNormalized => "non-normalized normal map texels",
+            NormalMapBackfacing => "normal map texels facing into the surface",
+            AlbedoLooksLinear => "dark albedo texels, the texture looks linear",
+        })
+    }
+}
+
+/// A problem found in an asset, with how often it happens and where
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationWarning {
+    pub kind: ValidationKind,
+    /// How many vertices, indices, triangles or texels have the problem
+    pub count: usize,
+    /// The first few places it happens (at most `MAX_EXAMPLES`), see `ValidationKind`
+    pub examples: Vec<usize>,
+}
+
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} (e.g. {:?}): {}", self.count, self.kind, self.examples, self.kind.advice())
+    }
+}
+
+/// Counts the failures of a single check
+struct Check {
+    kind: ValidationKind,
+    count: usize,
+    examples: Vec<usize>,
+}
+
+impl Check {
+    fn new(kind: ValidationKind) -> Check {
+        Check {
+            kind: kind,
+            count: 0,
+            examples: Vec::new(),
+        }
+    }
+
+    fn fail(&mut self, index: usize) {
+        self.count += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(index);
+        }
+    }
+
+    fn finish(self, out: &mut Vec<ValidationWarning>) {
+        if self.count > 0 {
+            out.push(ValidationWarning {
+                kind: self.kind,
+                count: self.count,
+                examples: self.examples,
+            });
+        }
+    }
+}
+
+fn finite(v: &[f32]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+fn check_mesh<V: HasNorm + HasTex>(verts: &[V], inds: &Indexing, prim: Primitive) -> Vec<ValidationWarning> {
+    use self::ValidationKind::*;
+    let mut pos = Check::new(NonFinitePosition);
+    let mut norm = Check::new(ZeroLengthNormal);
+    let mut uv = Check::new(NonFiniteUv);
+    for (i, v) in verts.iter().enumerate() {
+        if !finite(v.pos().coords.as_slice()) { pos.fail(i) }
+        // written so that NaN fails too
+        if !(v.norm().norm_squared() > 1e-12) || !finite(v.norm().as_slice()) { norm.fail(i) }
+        if !finite(v.tex().coords.as_slice()) { uv.fail(i) }
+    }
+
+    let inds: Vec<usize> = match *inds {
+        Indexing::Inds(ref inds) => inds.iter().map(|&i| i as usize).collect(),
+        Indexing::Range(a, b) => (a as usize..b as usize).collect(),
+        Indexing::All => (0..verts.len()).collect(),
+    };
+    let mut range = Check::new(IndexOutOfRange);
+    for (n, &i) in inds.iter().enumerate() {
+        if i >= verts.len() { range.fail(n) }
+    }
+
+    let mut tri = Check::new(DegenerateTriangle);
+    let mut tex = Check::new(DegenerateUv);
+    {
+        let mut visit = |n: usize, a: usize, b: usize, c: usize| {
+            if a >= verts.len() || b >= verts.len() || c >= verts.len() { return }
+            let (a, b, c) = (&verts[a], &verts[b], &verts[c]);
+            let area: Vector3<f32> = (b.pos() - a.pos()).cross(&(c.pos() - a.pos()));
+            if !(area.norm_squared() > 1e-20) {
+                tri.fail(n);
+                return;
+            }
+            let (e1, e2) = (b.tex() - a.tex(), c.tex() - a.tex());
+            if !((e1.x * e2.y - e2.x * e1.y).abs() > 1e-12) {
+                tex.fail(n);
+            }
+        };
+        match prim {
+            Primitive::TriangleList => for (n, t) in inds.chunks(3).filter(|t| t.len() == 3).enumerate() {
+                visit(n, t[0], t[1], t[2]);
+            },
+            Primitive::TriangleStrip => for (n, t) in inds.windows(3).enumerate() {
+                visit(n, t[0], t[1], t[2]);
+            },
+            _ => (),
+        }
+    }
+
+    let mut out = Vec::new();
+    for c in vec![pos, norm, uv, range, tri, tex] {
+        c.finish(&mut out);
+    }
+    out
+}
+
+/// Check a mesh for bad vertex and index data. Triangle checks only happen for triangle
+/// lists and strips. An empty result means nothing was found.
+pub fn validate<V: HasNorm + HasTex, M>(mesh: &MeshSource<V, M>) -> Vec<ValidationWarning> {
+    check_mesh(&mesh.verts, &mesh.inds, mesh.prim)
+}
+
+/// Check a grouped mesh for bad vertex and index data, see `validate`
+pub fn validate_groups<V: HasNorm + HasTex, M>(mesh: &MultiMeshSource<V, M>) -> Vec<ValidationWarning> {
+    check_mesh(&mesh.verts, &mesh.inds, mesh.prim)
+}
+
+/// Check the images of a material before they are uploaded. The albedo check is a
+/// heuristic: a texture that is mostly very dark in sRGB was probably written with linear
+/// values, though it may also just be a dark material.
+pub fn validate_material(albedo: Option<&RgbaImage>, normal: Option<&RgbaImage>) -> Vec<ValidationWarning> {
+    use self::ValidationKind::*;
+    let mut out = Vec::new();
+    if let Some(albedo) = albedo {
+        // 0.2 in sRGB is 0.033 linear, darker than coal
+        let mut dark = Check::new(AlbedoLooksLinear);
+        let mut opaque = 0;
+        for (i, p) in albedo.pixels().enumerate() {
+            if p.data[3] == 0 { continue }
+            opaque += 1;
+            if p.data[..3].iter().all(|&c| c < 51) { dark.fail(i) }
+        }
+        if opaque > 0 && dark.count * 4 > opaque * 3 {
+            dark.finish(&mut out);
+        }
+    }
+    if let Some(normal) = normal {
+        let mut length = Check::new(NormalMapNotNormalized);
+        let mut facing = Check::new(NormalMapBackfacing);
+        for (i, p) in normal.pixels().enumerate() {
+            let n = Vector3::new(p.data[0], p.data[1], p.data[2]).map(|c| c as f32 / 127.5 - 1.);
+            // 8 bit quantization alone can be off by about 0.01
+            if (n.norm() - 1.).abs() > 0.1 { length.fail(i) }
+            if n.z < 0. { facing.fail(i) }
+        }
+        length.finish(&mut out);
+        facing.finish(&mut out);
+    }
+    out
+}
+
+/// Log each warning about `what` with `warn!`
+pub fn report(what: &str, warnings: &[ValidationWarning]) {
+    for w in warnings {
+        warn!("{}: {}", what, w);
+    }
+}
+
+#[test]
+fn broken_assets() {
+    use image::Rgba;
+    use ::mesh::VertNT;
+    use self::ValidationKind::*;
+
+    let v = |pos: [f32; 3], norm: [f32; 3], tex: [f32; 2]| VertNT { pos: pos, norm: norm, tex: tex };
+    let up = [0., 1., 0.];
+    let mesh = MeshSource {
+        verts: vec![
+            v([0., 0., 0.], up, [0., 0.]),
+            v([1., 0., 0.], up, [1., 0.]),
+            v([0., 0., 1.], up, [0., 1.]),
+            v([::std::f32::NAN, 0., 0.], up, [0., 0.]),
+            v([1., 0., 1.], [0.; 3], [1., 1.]),
+            v([2., 0., 1.], up, [1., 1.]),
+        ],
+        // a good triangle, a repeated vertex, flat UVs, and an index past the end
+        inds: Indexing::Inds(vec![0, 1, 2, 0, 0, 1, 1, 4, 5, 0, 1, 9]),
+        prim: Primitive::TriangleList,
+        mat: (),
+    };
+    let found = validate(&mesh);
+    let get = |k| found.iter().find(|w| w.kind == k).cloned();
+    assert_eq!(get(NonFinitePosition).map(|w| w.examples), Some(vec![3]));
+    assert_eq!(get(ZeroLengthNormal).map(|w| w.examples), Some(vec![4]));
+    assert_eq!(get(IndexOutOfRange).map(|w| w.examples), Some(vec![11]));
+    assert_eq!(get(DegenerateTriangle).map(|w| w.examples), Some(vec![1]));
+    assert_eq!(get(DegenerateUv).map(|w| w.examples), Some(vec![2]));
+    assert_eq!(get(NonFiniteUv), None);
+    assert_eq!(found.len(), 5);
+
+    // a clean mesh has nothing to say
+    let mut clean = mesh.clone();
+    clean.verts.truncate(3);
+    clean.inds = Indexing::All;
+    assert!(validate(&clean).is_empty());
+
+    let normal = RgbaImage::from_fn(4, 1, |x, _| match x {
+        0 => Rgba([128, 128, 255, 255]),
+        1 => Rgba([128, 128, 128, 255]),
+        2 => Rgba([128, 128, 0, 255]),
+        _ => Rgba([255, 128, 128, 255]),
+    });
+    let found = validate_material(None, Some(&normal));
+    assert_eq!(found, vec![
+        ValidationWarning { kind: NormalMapNotNormalized, count: 1, examples: vec![1] },
+        ValidationWarning { kind: NormalMapBackfacing, count: 1, examples: vec![2] },
+    ]);
+
+    let linear = RgbaImage::from_fn(2, 2, |x, y| Rgba([10 * (x + y) as u8, 20, 5, 255]));
+    let found = validate_material(Some(&linear), None);
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].kind, found[0].count), (AlbedoLooksLinear, 4));
+    let srgb = RgbaImage::from_pixel(2, 2, Rgba([180, 120, 90, 255]));
+    assert!(validate_material(Some(&srgb), None).is_empty());
+}
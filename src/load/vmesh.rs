@@ -0,0 +1,408 @@
+use nalgebra::{Point3, Vector3};
+
+use ::{Error, FlightError};
+use ::mesh::{MeshSource, Indexing, VertNTT, Primitive, Aabb};
+use ::mesh::quantize::{oct_project, oct_unproject};
+
+const MAGIC: &[u8; 4] = b"VMSH";
+const VERSION: u8 = 1;
+const POS_BITS: u32 = 20;
+const DIR_RANGE: f32 = 32767.;
+const TEX_RANGE: f32 = 65536.;
+
+fn invalid(reason: &'static str) -> Error {
+    FlightError::InvalidVmesh { reason: reason }.into()
+}
+
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.data.extend_from_slice(&v.to_bits().to_le_bytes());
+    }
+
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.data.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.data.push(v as u8);
+    }
+
+    /// Zigzag encode, so small negative deltas are small too
+    fn signed(&mut self, v: i64) {
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, Error> {
+        let v = *self.data.get(self.at).ok_or_else(|| invalid("unexpected end of data"))?;
+        self.at += 1;
+        Ok(v)
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        let mut b = [0; 4];
+        for i in 0..4 {
+            b[i] = self.u8()?;
+        }
+        Ok(f32::from_bits(u32::from_le_bytes(b)))
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(invalid("variable-length integer is too long"))
+    }
+
+    fn signed(&mut self) -> Result<i64, Error> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    /// Add a zigzag encoded delta to `prev`, failing instead of overflowing
+    fn delta(&mut self, prev: i64) -> Result<i64, Error> {
+        let d = self.signed()?;
+        prev.checked_add(d).ok_or_else(|| invalid("value out of range"))
+    }
+
+    /// Read a count, which can't exceed the number of bytes left since each item takes
+    /// at least one byte
+    fn count(&mut self) -> Result<usize, Error> {
+        let n = self.varint()?;
+        ensure!(n <= (self.data.len() - self.at) as u64, FlightError::InvalidVmesh {
+            reason: "count is larger than the data",
+        });
+        Ok(n as usize)
+    }
+}
+
+fn prim_code(prim: Primitive) -> (u8, u8) {
+    use self::Primitive::*;
+    match prim {
+        PointList => (0, 0),
+        LineList => (1, 0),
+        LineStrip => (2, 0),
+        TriangleList => (3, 0),
+        TriangleStrip => (4, 0),
+        LineListAdjacency => (5, 0),
+        LineStripAdjacency => (6, 0),
+        TriangleListAdjacency => (7, 0),
+        TriangleStripAdjacency => (8, 0),
+        PatchList(n) => (9, n),
+    }
+}
+
+fn code_prim(code: u8, patches: u8) -> Result<Primitive, Error> {
+    use self::Primitive::*;
+    Ok(match code {
+        0 => PointList,
+        1 => LineList,
+        2 => LineStrip,
+        3 => TriangleList,
+        4 => TriangleStrip,
+        5 => LineListAdjacency,
+        6 => LineStripAdjacency,
+        7 => TriangleListAdjacency,
+        8 => TriangleStripAdjacency,
+        9 => PatchList(patches),
+        _ => return Err(invalid("unknown primitive type")),
+    })
+}
+
+/// Spread the low 10 bits of `v` out to every third bit
+fn spread(v: u32) -> u32 {
+    let mut v = v & 0x3ff;
+    v = (v | (v << 16)) & 0x030000ff;
+    v = (v | (v << 8)) & 0x0300f00f;
+    v = (v | (v << 4)) & 0x030c30c3;
+    v = (v | (v << 2)) & 0x09249249;
+    v
+}
+
+fn morton(q: [u32; 3]) -> u32 {
+    let s = POS_BITS - 10;
+    spread(q[0] >> s) << 2 | spread(q[1] >> s) << 1 | spread(q[2] >> s)
+}
+
+fn write_dir(w: &mut Writer, prev: &mut [i64; 2], v: &[f32; 3]) {
+    let (x, y) = oct_project(&Vector3::from_row_slice(v));
+    for (p, c) in prev.iter_mut().zip(&[x, y]) {
+        let q = (c * DIR_RANGE).round() as i64;
+        w.signed(q - *p);
+        *p = q;
+    }
+}
+
+fn read_dir(r: &mut Reader, prev: &mut [i64; 2]) -> Result<[f32; 3], Error> {
+    prev[0] = r.delta(prev[0])?;
+    prev[1] = r.delta(prev[1])?;
+    let v = oct_unproject(prev[0] as f32 / DIR_RANGE, prev[1] as f32 / DIR_RANGE);
+    Ok([v.x, v.y, v.z])
+}
+
+/// Compress a mesh. Attributes are quantized and stored as variable-length deltas, so
+/// similar neighbouring vertices cost few bytes. Positions keep 20 bits of precision across
+/// the mesh bounds, directions 16 bits per octahedral coordinate and texture coordinates 16
+/// fractional bits. Indexed meshes have their vertices sorted along a Morton curve, which
+/// doesn't change what is drawn.
+pub fn encode(src: &MeshSource<VertNTT, ()>) -> Vec<u8> {
+    let mut w = Writer { data: MAGIC.to_vec() };
+    w.u8(VERSION);
+    let (prim, patches) = prim_code(src.prim);
+    w.u8(prim);
+    w.u8(patches);
+
+    let bounds = if src.verts.is_empty() {
+        Aabb { min: Point3::origin(), max: Point3::origin() }
+    } else {
+        src.bounds()
+    };
+    let steps = ((1 << POS_BITS) - 1) as f32;
+    let step = bounds.extents() / steps;
+    let quantize = |v: &VertNTT| {
+        let mut q = [0; 3];
+        for i in 0..3 {
+            if step[i] > 0. {
+                q[i] = ((v.pos[i] - bounds.min[i]) / step[i]).round().max(0.).min(steps) as u32;
+            }
+        }
+        q
+    };
+
+    // reordering vertices only keeps the primitives the same if they are indexed
+    let mut order: Vec<usize> = (0..src.verts.len()).collect();
+    if let Indexing::Inds(_) = src.inds {
+        order.sort_by_key(|&i| morton(quantize(&src.verts[i])));
+    }
+    let mut remap = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        remap[old] = new as i64;
+    }
+
+    w.varint(src.verts.len() as u64);
+    for i in 0..3 {
+        w.f32(bounds.min[i]);
+        w.f32(step[i]);
+    }
+    let mut prev = [0i64; 3];
+    for &i in &order {
+        let q = quantize(&src.verts[i]);
+        for c in 0..3 {
+            w.signed(q[c] as i64 - prev[c]);
+            prev[c] = q[c] as i64;
+        }
+    }
+    let (mut norm, mut tan, mut bitan) = ([0; 2], [0; 2], [0; 2]);
+    for &i in &order {
+        let v = &src.verts[i];
+        write_dir(&mut w, &mut norm, &v.norm);
+        write_dir(&mut w, &mut tan, &v.tan);
+        write_dir(&mut w, &mut bitan, &v.bitan);
+    }
+    let mut prev = [0i64; 2];
+    for &i in &order {
+        for c in 0..2 {
+            let q = (src.verts[i].tex[c] * TEX_RANGE).round() as i64;
+            w.signed(q - prev[c]);
+            prev[c] = q;
+        }
+    }
+
+    match src.inds {
+        Indexing::All => w.u8(0),
+        Indexing::Range(a, b) => {
+            w.u8(1);
+            w.varint(a as u64);
+            w.varint(b as u64);
+        },
+        Indexing::Inds(ref inds) => {
+            w.u8(2);
+            w.varint(inds.len() as u64);
+            let mut prev = 0;
+            for &i in inds {
+                let i = remap[i as usize];
+                w.signed(i - prev);
+                prev = i;
+            }
+        },
+    }
+    w.data
+}
+
+/// Decompress a mesh made by `encode`
+pub fn decode(data: &[u8]) -> Result<MeshSource<VertNTT, ()>, Error> {
+    ensure!(data.starts_with(MAGIC), FlightError::InvalidVmesh { reason: "not a vmesh file" });
+    let mut r = Reader { data: data, at: MAGIC.len() };
+    ensure!(r.u8()? == VERSION, FlightError::InvalidVmesh { reason: "unsupported version" });
+    let code = r.u8()?;
+    let prim = code_prim(code, r.u8()?)?;
+
+    let n = r.count()?;
+    let (mut min, mut step) = ([0.; 3], [0.; 3]);
+    for i in 0..3 {
+        min[i] = r.f32()?;
+        step[i] = r.f32()?;
+    }
+    let mut verts = vec![VertNTT {
+        pos: [0.; 3],
+        norm: [0.; 3],
+        tan: [0.; 3],
+        bitan: [0.; 3],
+        tex: [0.; 2],
+    }; n];
+    let mut prev = [0i64; 3];
+    for v in &mut verts {
+        for c in 0..3 {
+            prev[c] = r.delta(prev[c])?;
+            v.pos[c] = min[c] + step[c] * prev[c] as f32;
+        }
+    }
+    let (mut norm, mut tan, mut bitan) = ([0; 2], [0; 2], [0; 2]);
+    for v in &mut verts {
+        v.norm = read_dir(&mut r, &mut norm)?;
+        v.tan = read_dir(&mut r, &mut tan)?;
+        v.bitan = read_dir(&mut r, &mut bitan)?;
+    }
+    let mut prev = [0i64; 2];
+    for v in &mut verts {
+        for c in 0..2 {
+            prev[c] = r.delta(prev[c])?;
+            v.tex[c] = prev[c] as f32 / TEX_RANGE;
+        }
+    }
+
+    let inds = match r.u8()? {
+        0 => Indexing::All,
+        1 => {
+            let (a, b) = (r.varint()?, r.varint()?);
+            ensure!(a <= b && b <= n as u64, FlightError::InvalidVmesh {
+                reason: "index range is out of bounds",
+            });
+            Indexing::Range(a as u32, b as u32)
+        },
+        2 => {
+            let count = r.count()?;
+            let mut inds = Vec::with_capacity(count);
+            let mut prev = 0;
+            for _ in 0..count {
+                prev = r.delta(prev)?;
+                ensure!(prev >= 0 && (prev as usize) < n, FlightError::InvalidVmesh {
+                    reason: "index is out of range",
+                });
+                inds.push(prev as u32);
+            }
+            Indexing::Inds(inds)
+        },
+        _ => bail!(FlightError::InvalidVmesh { reason: "unknown indexing scheme" }),
+    };
+    let mesh = MeshSource {
+        verts: verts,
+        inds: inds,
+        prim: prim,
+        mat: (),
+    };
+    if super::strict() {
+        super::validate::report("vmesh", &super::validate(&mesh));
+    }
+    Ok(mesh)
+}
+
+impl MeshSource<VertNTT, ()> {
+    /// Compress this mesh into the vmesh format, see `load::vmesh::encode`
+    pub fn to_vmesh_bytes(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    /// Read a mesh in the vmesh format, see `load::vmesh::decode`
+    pub fn from_vmesh_bytes(data: &[u8]) -> Result<MeshSource<VertNTT, ()>, Error> {
+        decode(data)
+    }
+}
+
+#[test]
+fn vmesh_round_trip() {
+    use ::mesh::gen;
+
+    let src = gen::rounded_box(Vector3::new(2., 1., 0.5), 0.1, 6).unwrap().map_material(|_| ());
+    let data = src.to_vmesh_bytes();
+    // a raw vertex is 56 bytes and an index 4
+    let raw = src.verts.len() * 56 + match src.inds { Indexing::Inds(ref i) => i.len() * 4, _ => 0 };
+    assert!(data.len() * 4 < raw * 3, "{} bytes compressed from {}", data.len(), raw);
+
+    let out = MeshSource::from_vmesh_bytes(&data).unwrap();
+    assert_eq!(out.verts.len(), src.verts.len());
+    assert_eq!(out.prim, src.prim);
+    let (a, b) = match (&src.inds, &out.inds) {
+        (&Indexing::Inds(ref a), &Indexing::Inds(ref b)) => (a, b),
+        _ => panic!("indexing changed"),
+    };
+    assert_eq!(a.len(), b.len());
+    // the vertices are reordered, but every index still refers to the same vertex
+    for (&i, &j) in a.iter().zip(b) {
+        let (v, w) = (&src.verts[i as usize], &out.verts[j as usize]);
+        let d = |x: &[f32; 3], y: &[f32; 3]| (Vector3::from_row_slice(x) - Vector3::from_row_slice(y)).norm();
+        assert!(d(&v.pos, &w.pos) < 3e-6);
+        assert!(d(&v.norm, &w.norm) < 2e-4);
+        assert!(d(&v.tan, &w.tan) < 2e-4);
+        assert!(d(&v.bitan, &w.bitan) < 2e-4);
+        assert!((v.tex[0] - w.tex[0]).abs() < 1e-5 && (v.tex[1] - w.tex[1]).abs() < 1e-5);
+    }
+
+    // unindexed meshes keep their order
+    let strip = MeshSource {
+        verts: src.verts[..5].to_vec(),
+        inds: Indexing::Range(1, 4),
+        prim: Primitive::TriangleStrip,
+        mat: (),
+    };
+    let out = decode(&encode(&strip)).unwrap();
+    assert_eq!(out.prim, Primitive::TriangleStrip);
+    match out.inds {
+        Indexing::Range(1, 4) => (),
+        _ => panic!("range changed"),
+    }
+    for (v, w) in strip.verts.iter().zip(&out.verts) {
+        assert!((Vector3::from_row_slice(&v.pos) - Vector3::from_row_slice(&w.pos)).norm() < 3e-6);
+    }
+
+    assert!(decode(b"VMSX").is_err());
+    assert!(decode(&data[..data.len() / 2]).is_err());
+
+    // ranges past the vertices or backwards
+    for &(a, b) in &[(2, 6), (4, 1)] {
+        let bad = MeshSource { inds: Indexing::Range(a, b), .. strip.clone() };
+        assert!(decode(&encode(&bad)).is_err());
+    }
+    // position deltas adding up past the range of i64
+    let mut w = Writer { data: MAGIC.to_vec() };
+    w.u8(VERSION);
+    w.u8(0);
+    w.u8(0);
+    w.varint(2);
+    for _ in 0..6 {
+        w.f32(1.);
+    }
+    for _ in 0..6 {
+        w.signed(::std::i64::MAX);
+    }
+    assert!(decode(&w.data).is_err());
+}
@@ -0,0 +1,4 @@
+/// Batch vertex transforms, using AVX2 when compiled for it
+pub mod simd;
+/// Frustum planes of view-projection matrices, for culling
+pub mod frustum;
@@ -0,0 +1,106 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+/// The six planes bounding the volume seen through a view-projection matrix, in the order
+/// left, right, bottom, top, near, far (Gribb-Hartmann). Each plane is `(normal, distance)`
+/// with a unit normal facing inward, so a point `p` is inside when
+/// `plane.dot(&p.to_homogeneous()) >= 0` for every plane.
+///
+/// The matrix is taken the way nalgebra and the shaders use it: it multiplies column vectors
+/// on its right (`clip = view_proj * world`), so the planes come from its rows, read with
+/// `view_proj[(row, column)]`. That nalgebra stores matrices column-major doesn't matter here,
+/// but a matrix built for row vectors (`world * view_proj`, as in Direct3D samples) must be
+/// transposed first. Clip z is taken to run from -w to w, as OpenGL and OpenVR projections
+/// give it.
+pub fn extract_frustum_planes(view_proj: Matrix4<f32>) -> [Vector4<f32>; 6] {
+    let row = |i: usize| Vector4::new(view_proj[(i, 0)], view_proj[(i, 1)], view_proj[(i, 2)], view_proj[(i, 3)]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    let mut planes = [w + x, w - x, w + y, w - y, w + z, w - z];
+    for p in planes.iter_mut() {
+        let len = Vector3::new(p.x, p.y, p.z).norm();
+        if len > 0. {
+            *p /= len;
+        }
+    }
+    planes
+}
+
+/// The world space corners of the volume seen through a view-projection matrix, or `None`
+/// if it has no inverse or a corner is at infinity
+fn frustum_corners(view_proj: &Matrix4<f32>) -> Option<[Point3<f32>; 8]> {
+    let inv = view_proj.try_inverse()?;
+    let mut corners = [Point3::origin(); 8];
+    for (i, c) in corners.iter_mut().enumerate() {
+        let ndc = Vector4::new(
+            if i & 4 != 0 { 1. } else { -1. },
+            if i & 2 != 0 { 1. } else { -1. },
+            if i & 1 != 0 { 1. } else { -1. },
+            1.,
+        );
+        let h = inv * ndc;
+        if h.w.abs() < 1e-12 { return None }
+        *c = Point3::from_homogeneous(h)?;
+    }
+    Some(corners)
+}
+
+/// Planes (as from `extract_frustum_planes`) of one convex volume covering the volumes seen
+/// through two view-projection matrices, such as those of the two eyes, so a scene can be
+/// culled once for both. Each plane is the one of the two eyes that needs the least moving
+/// out to take in every corner of both volumes, moved that far. For eyes looking the same
+/// way this keeps the outer side plane of each eye and gives the frustum of a single
+/// camera just behind them, as wide apart as the eyes.
+///
+/// Both matrices need a finite far plane. Planes that can't be placed let everything through.
+pub fn merge_frustum_planes(a: Matrix4<f32>, b: Matrix4<f32>) -> [Vector4<f32>; 6] {
+    let (pa, pb) = (extract_frustum_planes(a), extract_frustum_planes(b));
+    let corners = match (frustum_corners(&a), frustum_corners(&b)) {
+        (Some(ca), Some(cb)) => ca.iter().chain(cb.iter()).cloned().collect::<Vec<_>>(),
+        _ => return [Vector4::w(); 6],
+    };
+    // how far a plane must move out for every corner to be inside it
+    let push = |p: &Vector4<f32>| corners.iter()
+        .map(|c| -p.dot(&c.to_homogeneous()))
+        .fold(0f32, f32::max);
+    let mut planes = [Vector4::w(); 6];
+    for (i, out) in planes.iter_mut().enumerate() {
+        let (da, db) = (push(&pa[i]), push(&pb[i]));
+        *out = if da <= db { pa[i] + Vector4::w() * da } else { pb[i] + Vector4::w() * db };
+    }
+    planes
+}
+
+#[test]
+fn frustum_planes() {
+    use nalgebra::{Isometry3, Perspective3};
+
+    let proj = Perspective3::new(1., ::std::f32::consts::PI / 2., 0.1, 100.).to_homogeneous();
+    let inside = |planes: &[Vector4<f32>; 6], p: Point3<f32>| {
+        planes.iter().all(|q| q.dot(&p.to_homogeneous()) >= -1e-4)
+    };
+
+    let planes = extract_frustum_planes(proj);
+    // a 90 degree view down -z
+    assert!(relative_eq!(planes[0], Vector4::new(1., 0., -1., 0.) / 2f32.sqrt(), epsilon = 1e-5));
+    assert!(relative_eq!(planes[4].w, -0.1, epsilon = 1e-5));
+    assert!(relative_eq!(planes[5].w, 100., epsilon = 1e-3));
+    assert!(inside(&planes, Point3::new(0., 0., -50.)));
+    assert!(inside(&planes, Point3::new(4.9, 4.9, -5.)));
+    assert!(!inside(&planes, Point3::new(5.1, 0., -5.)));
+    assert!(!inside(&planes, Point3::new(0., 0., 1.)));
+
+    // eyes 0.064 apart, both looking down -z
+    let eye = |x: f32| proj * Isometry3::new(Vector3::new(-x, 0., 0.), Vector3::zeros()).to_homogeneous();
+    let (left, right) = (eye(-0.032), eye(0.032));
+    let merged = merge_frustum_planes(left, right);
+    // the outer side planes are those of the eyes
+    assert!(relative_eq!(merged[0], extract_frustum_planes(left)[0], epsilon = 1e-4));
+    assert!(relative_eq!(merged[1], extract_frustum_planes(right)[1], epsilon = 1e-4));
+    // points each eye sees only at the edge of its view
+    assert!(inside(&merged, Point3::new(-5.032, 0., -5.)));
+    assert!(inside(&merged, Point3::new(5.032, 0., -5.)));
+    assert!(!inside(&extract_frustum_planes(right), Point3::new(-5.032, 0., -5.)));
+    assert!(!inside(&merged, Point3::new(5.2, 0., -5.)));
+    for c in frustum_corners(&left).unwrap().iter().chain(frustum_corners(&right).unwrap().iter()) {
+        assert!(inside(&merged, *c));
+    }
+}
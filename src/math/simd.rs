@@ -0,0 +1,107 @@
+use nalgebra::{Matrix4, Point3};
+
+/// The number of vertices transformed per AVX2 iteration
+pub const LANES: usize = 8;
+
+/// Transform points by a matrix (dividing by w) one at a time. This is the fallback of
+/// `batch_transform_vertices`, and panics the same way.
+pub fn scalar_transform_vertices(verts: &[[f32; 3]], matrix: &Matrix4<f32>, out: &mut [[f32; 3]]) {
+    assert_eq!(verts.len(), out.len(), "transform output must match the input length");
+    for (v, o) in verts.iter().zip(out.iter_mut()) {
+        let p = Point3::from_homogeneous(matrix * Point3::new(v[0], v[1], v[2]).to_homogeneous())
+            .unwrap_or(Point3::origin());
+        *o = [p.x, p.y, p.z];
+    }
+}
+
+/// Transform points by a matrix (dividing by w), `LANES` at a time with AVX2 when the crate
+/// is compiled with the `avx2` target feature (such as with `-C target-cpu=native`), and
+/// one at a time otherwise. Points with w = 0 end up at the origin. Panics if `out` isn't
+/// as long as `verts`.
+pub fn batch_transform_vertices(verts: &[[f32; 3]], matrix: &Matrix4<f32>, out: &mut [[f32; 3]]) {
+    assert_eq!(verts.len(), out.len(), "transform output must match the input length");
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    {
+        let whole = verts.len() / LANES * LANES;
+        // the avx2 target feature is enabled for the whole crate, so the intrinsics are
+        // always available here
+        unsafe { avx2::transform(&verts[..whole], matrix, &mut out[..whole]) };
+        scalar_transform_vertices(&verts[whole..], matrix, &mut out[whole..]);
+    }
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
+    scalar_transform_vertices(verts, matrix, out);
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+    use nalgebra::Matrix4;
+    use super::LANES;
+
+    /// Transform a multiple of `LANES` points, loading each group as separate x, y and z
+    /// registers and storing them back interleaved
+    pub unsafe fn transform(verts: &[[f32; 3]], m: &Matrix4<f32>, out: &mut [[f32; 3]]) {
+        debug_assert!(verts.len() % LANES == 0 && verts.len() == out.len());
+        let row = |r: usize| [
+            _mm256_set1_ps(m[(r, 0)]),
+            _mm256_set1_ps(m[(r, 1)]),
+            _mm256_set1_ps(m[(r, 2)]),
+            _mm256_set1_ps(m[(r, 3)]),
+        ];
+        let rows = [row(0), row(1), row(2), row(3)];
+        // offsets of the x of each point in a group, in floats
+        let stride = _mm256_setr_epi32(0, 3, 6, 9, 12, 15, 18, 21);
+        let zero = _mm256_setzero_ps();
+        let one = _mm256_set1_ps(1.);
+
+        for (group, dest) in verts.chunks(LANES).zip(out.chunks_mut(LANES)) {
+            let base = group.as_ptr() as *const f32;
+            let x = _mm256_i32gather_ps(base, stride, 4);
+            let y = _mm256_i32gather_ps(base.offset(1), stride, 4);
+            let z = _mm256_i32gather_ps(base.offset(2), stride, 4);
+            let dot = |r: &[__m256; 4]| _mm256_add_ps(
+                _mm256_add_ps(_mm256_mul_ps(r[0], x), _mm256_mul_ps(r[1], y)),
+                _mm256_add_ps(_mm256_mul_ps(r[2], z), r[3]),
+            );
+            let w = dot(&rows[3]);
+            // points at infinity go to the origin, like the scalar path
+            let w_zero = _mm256_cmp_ps(w, zero, _CMP_EQ_OQ);
+            let inv_w = _mm256_andnot_ps(w_zero, _mm256_div_ps(one, w));
+
+            let mut lanes = [[0f32; LANES]; 3];
+            for (i, lane) in lanes.iter_mut().enumerate() {
+                _mm256_storeu_ps(lane.as_mut_ptr(), _mm256_mul_ps(dot(&rows[i]), inv_w));
+            }
+            for (i, o) in dest.iter_mut().enumerate() {
+                *o = [lanes[0][i], lanes[1][i], lanes[2][i]];
+            }
+        }
+    }
+}
+
+#[test]
+fn batch_transforms() {
+    use nalgebra::{Vector3, Isometry3, Perspective3};
+
+    // 8 points per group and a remainder
+    let verts: Vec<[f32; 3]> = (0..21)
+        .map(|i| [i as f32 * 0.5 - 3., (i % 5) as f32, -(i as f32) - 1.])
+        .collect();
+    let matrices = [
+        Matrix4::identity(),
+        Isometry3::new(Vector3::new(1., -2., 3.), Vector3::new(0.3, 0.5, -0.1)).to_homogeneous()
+            * Matrix4::new_scaling(2.),
+        Perspective3::new(1.5, 1.2, 0.1, 100.).to_homogeneous(),
+    ];
+    for m in &matrices {
+        let mut scalar = vec![[0.; 3]; verts.len()];
+        let mut batch = vec![[0.; 3]; verts.len()];
+        scalar_transform_vertices(&verts, m, &mut scalar);
+        batch_transform_vertices(&verts, m, &mut batch);
+        for ((v, s), b) in verts.iter().zip(&scalar).zip(&batch) {
+            let p = Point3::from_homogeneous(m * Point3::new(v[0], v[1], v[2]).to_homogeneous()).unwrap();
+            assert!(relative_eq!(Point3::new(s[0], s[1], s[2]), p, epsilon = 1e-4));
+            assert!(relative_eq!(Point3::new(b[0], b[1], b[2]), p, epsilon = 1e-4));
+        }
+    }
+}
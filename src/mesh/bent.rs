@@ -0,0 +1,439 @@
+use image::{Rgba, RgbaImage};
+use nalgebra::{Point3, Vector3};
+
+use super::{VertNTT, Vertex, HasNorm, HasTan, HasTex, Aabb, Bvh};
+
+/// Cosine weighted directions around +Z, spread with the golden angle so that any ray count
+/// covers the hemisphere evenly
+fn hemisphere(count: u32) -> Vec<Vector3<f32>> {
+    (0..count).map(|i| {
+        let u = (i as f32 + 0.5) / count as f32;
+        let phi = i as f32 * 2.399_963;
+        let r = u.sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), (1. - u).sqrt())
+    }).collect()
+}
+
+/// Whether a ray hits a triangle closer than `max` (Möller-Trumbore)
+fn hits(origin: &Point3<f32>, dir: &Vector3<f32>, tri: [&Point3<f32>; 3], max: f32) -> bool {
+    let e1 = tri[1] - tri[0];
+    let e2 = tri[2] - tri[0];
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 { return false }
+    let inv = 1. / det;
+    let s = origin - tri[0];
+    let u = s.dot(&p) * inv;
+    if u < 0. || u > 1. { return false }
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) * inv;
+    if v < 0. || u + v > 1. { return false }
+    let t = e2.dot(&q) * inv;
+    t > 0. && t < max
+}
+
+/// Bake the bent normal (xyz) and ambient occlusion (w) of every vertex of a triangle list.
+/// Each vertex casts `ray_count` cosine weighted rays over its normal's hemisphere against
+/// every triangle; the bent normal is the average of the rays that travel `max_dist` without
+/// hitting anything, and the occlusion is the fraction of them that do. Vertices with no
+/// way out keep their normal. This is a brute force offline bake, so it is slow for large
+/// meshes.
+pub fn bake_bent_occlusion(verts: &[VertNTT], inds: &[u32], ray_count: u32, max_dist: f32) -> Vec<[f32; 4]> {
+    let rays = hemisphere(ray_count.max(1));
+    let tris: Vec<[usize; 3]> = inds.chunks(3)
+        .filter(|t| t.len() == 3 && t.iter().all(|&i| (i as usize) < verts.len()))
+        .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+        .collect();
+    let bias = max_dist * 1e-3;
+    verts.iter().enumerate().map(|(i, v)| {
+        let n = match v.norm().try_normalize(1e-12) {
+            Some(n) => n,
+            None => return [0., 0., 0., 1.],
+        };
+        let (t, b) = basis(&n);
+        let origin = v.pos() + n * bias;
+
+        let mut sum = Vector3::zeros();
+        let mut open = 0;
+        for r in &rays {
+            let dir = t * r.x + b * r.y + n * r.z;
+            let blocked = tris.iter()
+                .filter(|tri| !tri.contains(&i))
+                .any(|tri| hits(&origin, &dir, [verts[tri[0]].pos(), verts[tri[1]].pos(), verts[tri[2]].pos()], max_dist));
+            if !blocked {
+                sum += dir;
+                open += 1;
+            }
+        }
+        let bent = sum.try_normalize(1e-6).unwrap_or(n);
+        [bent.x, bent.y, bent.z, open as f32 / rays.len() as f32]
+    }).collect()
+}
+
+/// Bake the bent normal of every vertex of a triangle list, see `bake_bent_occlusion`
+pub fn bake_bent_normals(verts: &[VertNTT], inds: &[u32], ray_count: u32, max_dist: f32) -> Vec<[f32; 3]> {
+    bake_bent_occlusion(verts, inds, ray_count, max_dist).into_iter()
+        .map(|b| [b[0], b[1], b[2]])
+        .collect()
+}
+
+/// A unit vector perpendicular to `n`, and a third completing the basis
+fn basis(n: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let t = if n.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+    let t = (t - n * n.dot(&t)).normalize();
+    (t, n.cross(&t))
+}
+
+/// The mesh and settings shared by the rays of `bake_multibounce_ao`
+struct Bounces<'a> {
+    bvh: Bvh,
+    verts: &'a [VertNTT],
+    inds: &'a [u32],
+    secondary: Vec<Vector3<f32>>,
+    albedo: f32,
+    reach: f32,
+    bias: f32,
+}
+
+impl<'a> Bounces<'a> {
+    fn corner(&self, tri: usize, k: usize) -> &Point3<f32> {
+        self.verts[self.inds[tri * 3 + k] as usize].pos()
+    }
+
+    /// The light reaching a point with normal `n` along `rays`, with `left` bounces to go,
+    /// ignoring the triangles for which `skip` is true
+    fn gather(&self, rays: &[Vector3<f32>], pos: &Point3<f32>, n: &Vector3<f32>, left: u32, skip: &dyn Fn(usize) -> bool) -> f32 {
+        let (t, b) = basis(n);
+        let origin = pos + n * self.bias;
+        let mut sum = 0.;
+        for r in rays {
+            let dir = t * r.x + b * r.y + n * r.z;
+            let hit = match self.bvh.cast_filtered(&origin, &dir, self.reach, skip) {
+                Some(hit) => hit,
+                None => {
+                    sum += 1.;
+                    continue
+                },
+            };
+            if left <= 1 || self.albedo <= 0. { continue }
+            let tri = hit.triangle;
+            let a = self.corner(tri, 0);
+            let (e1, e2) = (self.corner(tri, 1) - a, self.corner(tri, 2) - a);
+            let face = match e1.cross(&e2).try_normalize(1e-12) {
+                // the side of the triangle facing the ray
+                Some(f) => if f.dot(&dir) > 0. { -f } else { f },
+                None => continue,
+            };
+            let at = a + e1 * hit.uv[0] + e2 * hit.uv[1];
+            sum += self.albedo * self.gather(&self.secondary, &at, &face, left - 1, &|n| n == tri);
+        }
+        sum / rays.len() as f32
+    }
+}
+
+/// Bake the ambient light reaching every vertex of a triangle list, from 0 to 1, counting
+/// light bounced off the mesh itself. Like the occlusion of `bake_bent_occlusion`, each vertex
+/// casts `ray_count` cosine weighted rays and rays that escape bring in 1. A ray that hits the
+/// mesh brings in `albedo` times the light at the hit point, which casts `ray_count / bounces`
+/// rays of its own, for up to `bounces` bounces. With 1 bounce this is the single bounce
+/// occlusion, while more let light into crevices, which single bounce occlusion leaves too
+/// dark. Rays are cast through a `Bvh`, so large meshes are practical, but the rays per vertex
+/// grow exponentially with `bounces`.
+pub fn bake_multibounce_ao(verts: &[VertNTT], inds: &[u32], bounces: u8, ray_count: u32, albedo: f32) -> Vec<f32> {
+    let bounces = bounces.max(1) as u32;
+    let primary = hemisphere(ray_count.max(1));
+    let reach = Aabb::from_points(verts.iter().map(|v| v.pos())).diagonal().max(1e-6);
+    let mesh = Bounces {
+        bvh: Bvh::new(verts, inds),
+        verts: verts,
+        inds: inds,
+        secondary: hemisphere((ray_count / bounces).max(1)),
+        albedo: albedo.max(0.).min(1.),
+        reach: reach,
+        bias: reach * 1e-4,
+    };
+    verts.iter().enumerate().map(|(i, v)| {
+        let n = match v.norm().try_normalize(1e-12) {
+            Some(n) => n,
+            None => return 1.,
+        };
+        let own = |tri: usize| inds[tri * 3..tri * 3 + 3].contains(&(i as u32));
+        mesh.gather(&primary, v.pos(), &n, bounces, &own).min(1.)
+    }).collect()
+}
+
+/// Point `i` of the first two dimensions of the Sobol sequence, each from 0 to 1
+fn sobol(i: u32) -> (f32, f32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    // the second dimension's direction numbers, from the polynomial x + 1
+    let mut v = 1u32 << 31;
+    for k in 0..32 {
+        if i >> k & 1 == 1 {
+            x ^= 1 << (31 - k);
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    let scale = 1. / 4_294_967_296.;
+    (x as f32 * scale, y as f32 * scale)
+}
+
+/// A pseudo random offset from 0 to 1 for each vertex, so that neighbouring vertices don't
+/// all sample the same directions
+fn jitter(i: usize, salt: u32) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9e37_79b9) ^ salt;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    (h >> 8) as f32 / 16_777_216.
+}
+
+/// Bake the ambient occlusion of every vertex of a triangle list for high quality bakes,
+/// as the share of cosine weighted light reaching the vertex through rays of length `radius`,
+/// like the occlusion of `bake_bent_occlusion`.
+///
+/// A quarter as many rays first find the bent normal, the average open direction, and then
+/// `sample_count` rays are importance sampled with a cosine around it, since that is where
+/// the light comes from. Rays below the surface are rejected, counting as dark, and the rest
+/// are weighted to keep the estimate unbiased. The directions follow a Sobol sequence shifted
+/// by a random offset per vertex, which is stratified without lining up between vertices, so
+/// the result has much less noise than evenly spread rays at the same count.
+pub fn bake_gtao(verts: &[VertNTT], inds: &[u32], sample_count: u32, radius: f32, bvh: &Bvh) -> Vec<f32> {
+    use std::f32::consts::PI;
+    let count = sample_count.max(1);
+    let probes = (count / 4).max(4);
+    let bias = radius * 1e-4;
+    // a cosine weighted direction around +Z from a point of the unit square, shifted by `offset`
+    let direction = |i: u32, offset: (f32, f32)| {
+        let (u, v) = sobol(i);
+        let (u, v) = ((u + offset.0) % 1., (v + offset.1) % 1.);
+        let (r, phi) = (u.sqrt(), v * 2. * PI);
+        Vector3::new(r * phi.cos(), r * phi.sin(), (1. - u).sqrt())
+    };
+    verts.iter().enumerate().map(|(i, v)| {
+        let n = match v.norm().try_normalize(1e-12) {
+            Some(n) => n,
+            None => return 1.,
+        };
+        let own = |tri: usize| inds[tri * 3..tri * 3 + 3].contains(&(i as u32));
+        let origin = v.pos() + n * bias;
+        let offset = (jitter(i, 0x68e3_1da4), jitter(i, 0xb529_7a4d));
+
+        let (t, b) = basis(&n);
+        let mut open = Vector3::zeros();
+        let mut blocked = 0;
+        for k in 0..probes {
+            let r = direction(k, offset);
+            let dir = t * r.x + b * r.y + n * r.z;
+            if bvh.occluded(&origin, &dir, radius, &own) {
+                blocked += 1;
+            } else {
+                open += dir;
+            }
+        }
+        let bent = match open.try_normalize(1e-6) {
+            // with nothing in the way the light is centered on the normal
+            Some(bent) if blocked > 0 => bent,
+            _ => n,
+        };
+
+        let (t, b) = basis(&bent);
+        let mut sum = 0.;
+        for k in 0..count {
+            let r = direction(k, offset);
+            let dir = t * r.x + b * r.y + bent * r.z;
+            let cos_n = dir.dot(&n);
+            if cos_n <= 0. || r.z <= 1e-6 { continue }
+            if !bvh.occluded(&origin, &dir, radius, &own) {
+                // the cosine around the normal over the density around the bent normal
+                sum += cos_n / r.z;
+            }
+        }
+        (sum / count as f32).min(1.)
+    }).collect()
+}
+
+/// Draw the baked bent normals and occlusion of a mesh into its UV space, for the `bent`
+/// map of `UberMaterial`. The bent normal is stored in tangent space like a normal map
+/// (RGB) with the occlusion in alpha. Texels outside every triangle are left unoccluded.
+pub fn bent_normal_image(verts: &[VertNTT], inds: &[u32], baked: &[[f32; 4]], width: u32, height: u32) -> RgbaImage {
+    let flat = Rgba([128, 128, 255, 255]);
+    let mut image = RgbaImage::from_pixel(width, height, flat);
+    let mut covered = vec![false; (width * height) as usize];
+    let tangent: Vec<[f32; 4]> = verts.iter().zip(baked).map(|(v, b)| {
+        let d = Vector3::new(b[0], b[1], b[2]);
+        let n = v.norm().try_normalize(1e-12).unwrap_or(Vector3::z());
+        let local = Vector3::new(d.dot(v.tan()), d.dot(v.bitan()), d.dot(&n))
+            .try_normalize(1e-6)
+            .unwrap_or(Vector3::z());
+        [local.x, local.y, local.z, b[3]]
+    }).collect();
+    let unorm = |x: f32| (x * 255.).round().max(0.).min(255.) as u8;
+
+    for t in inds.chunks(3).filter(|t| t.len() == 3 && t.iter().all(|&i| (i as usize) < tangent.len())) {
+        let (a, b, c) = (t[0] as usize, t[1] as usize, t[2] as usize);
+        let uv = |i: usize| {
+            // the transform shader flips v, so the first image row is sampled at v = 1
+            let tex = verts[i].tex();
+            (tex.x * width as f32, (1. - tex.y) * height as f32)
+        };
+        let (pa, pb, pc) = (uv(a), uv(b), uv(c));
+        let area = (pb.0 - pa.0) * (pc.1 - pa.1) - (pc.0 - pa.0) * (pb.1 - pa.1);
+        if area.abs() < 1e-12 { continue }
+        let x0 = pa.0.min(pb.0).min(pc.0).floor().max(0.) as u32;
+        let y0 = pa.1.min(pb.1).min(pc.1).floor().max(0.) as u32;
+        let x1 = (pa.0.max(pb.0).max(pc.0).ceil().max(0.) as u32).min(width);
+        let y1 = (pa.1.max(pb.1).max(pc.1).ceil().max(0.) as u32).min(height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let wa = ((pb.0 - px) * (pc.1 - py) - (pc.0 - px) * (pb.1 - py)) / area;
+                let wb = ((pc.0 - px) * (pa.1 - py) - (pa.0 - px) * (pc.1 - py)) / area;
+                let wc = 1. - wa - wb;
+                if wa < -1e-4 || wb < -1e-4 || wc < -1e-4 { continue }
+                let mut v = [0.; 4];
+                for k in 0..4 {
+                    v[k] = tangent[a][k] * wa + tangent[b][k] * wb + tangent[c][k] * wc;
+                }
+                let d = Vector3::new(v[0], v[1], v[2]).try_normalize(1e-6).unwrap_or(Vector3::z());
+                image.put_pixel(x, y, Rgba([
+                    unorm(d.x * 0.5 + 0.5),
+                    unorm(d.y * 0.5 + 0.5),
+                    unorm(d.z * 0.5 + 0.5),
+                    unorm(v[3]),
+                ]));
+                covered[(y * width + x) as usize] = true;
+            }
+        }
+    }
+
+    // pad the edges of UV islands by a texel so filtering doesn't pull in the flat value
+    let original = image.clone();
+    for y in 0..height {
+        for x in 0..width {
+            if covered[(y * width + x) as usize] { continue }
+            let neighbor = [(0i32, -1i32), (-1, 0), (1, 0), (0, 1)].iter()
+                .map(|&(dx, dy)| (x as i32 + dx, y as i32 + dy))
+                .find(|&(nx, ny)| nx >= 0 && ny >= 0 && (nx as u32) < width && (ny as u32) < height
+                    && covered[(ny as u32 * width + nx as u32) as usize]);
+            if let Some((nx, ny)) = neighbor {
+                image.put_pixel(x, y, *original.get_pixel(nx as u32, ny as u32));
+            }
+        }
+    }
+    image
+}
+
+#[test]
+fn bent_normals() {
+    use super::gen;
+
+    // a floor with a low wall standing along x = 0
+    let v = |pos: [f32; 3], norm: [f32; 3]| VertNTT {
+        pos: pos,
+        norm: norm,
+        tan: [1., 0., 0.],
+        bitan: [0., 0., 1.],
+        tex: [pos[0] * 0.5 + 0.5, pos[2] * 0.5 + 0.5],
+    };
+    let up = [0., 1., 0.];
+    let side = [1., 0., 0.];
+    let verts = vec![
+        v([-1., 0., -1.], up), v([1., 0., -1.], up), v([1., 0., 1.], up), v([-1., 0., 1.], up),
+        v([0., 0., -1.], side), v([0., 0.2, -1.], side), v([0., 0.2, 1.], side), v([0., 0., 1.], side),
+        v([5., 0., 0.], up), v([0.05, 0., 0.], up),
+    ];
+    let inds = vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7];
+    let baked = bake_bent_occlusion(&verts, &inds, 256, 10.);
+    // far from the wall, the sky is mostly open and straight up
+    assert!(baked[8][3] > 0.9 && baked[8][1] > 0.99);
+    // next to the wall about half the sky is gone, and what's left is away from the wall
+    assert!(baked[9][3] < 0.7 && baked[9][3] > 0.3);
+    assert!(baked[9][0] > 0.3);
+    let normals = bake_bent_normals(&verts, &inds, 256, 10.);
+    assert!(relative_eq!(normals[9][0], baked[9][0]));
+
+    // an unoccluded sphere keeps its normals
+    let sphere = gen::sphere(1., 8, 4);
+    let inds = match sphere.inds {
+        super::Indexing::Inds(ref i) => i.clone(),
+        _ => unreachable!(),
+    };
+    let verts = sphere.verts;
+    let baked = bake_bent_occlusion(&verts, &inds, 32, 10.);
+    for (v, b) in verts.iter().zip(&baked) {
+        assert!(relative_eq!(b[3], 1.));
+        assert!(v.norm().normalize().dot(&Vector3::new(b[0], b[1], b[2])) > 0.95);
+    }
+
+    let image = bent_normal_image(&verts, &inds, &baked, 8, 8);
+    assert_eq!(image.dimensions(), (8, 8));
+}
+
+#[test]
+fn multibounce_ao() {
+    // a narrow trench, open at the top and ends
+    let v = |pos: [f32; 3], norm: [f32; 3]| VertNTT {
+        pos: pos,
+        norm: norm,
+        tan: [1., 0., 0.],
+        bitan: [0., 0., 1.],
+        tex: [0., 0.],
+    };
+    let (up, east, west) = ([0., 1., 0.], [1., 0., 0.], [-1., 0., 0.]);
+    let verts = vec![
+        v([-0.2, 0., -2.], up), v([0.2, 0., -2.], up), v([0.2, 0., 2.], up), v([-0.2, 0., 2.], up),
+        v([-0.2, 0., -2.], east), v([-0.2, 1., -2.], east), v([-0.2, 1., 2.], east), v([-0.2, 0., 2.], east),
+        v([0.2, 0., -2.], west), v([0.2, 1., -2.], west), v([0.2, 1., 2.], west), v([0.2, 0., 2.], west),
+        v([0., 0.01, 0.], up), v([0., 3., 0.], up),
+    ];
+    let inds = vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7, 8, 9, 10, 8, 10, 11];
+    let single = bake_multibounce_ao(&verts, &inds, 1, 128, 0.8);
+    let bounced = bake_multibounce_ao(&verts, &inds, 3, 128, 0.8);
+    let black = bake_multibounce_ao(&verts, &inds, 3, 128, 0.);
+
+    // the bottom of the trench sees little sky, and a point above it all of it
+    assert!(single[12] < 0.4);
+    assert!(relative_eq!(single[13], 1.));
+    let bent = bake_bent_occlusion(&verts, &inds, 128, 10.);
+    assert!((single[12] - bent[12][3]).abs() < 0.05);
+    // light bounced off the walls brightens it, unless they are black
+    assert!(bounced[12] > single[12] + 0.1 && bounced[12] <= 1.);
+    assert!(relative_eq!(black[12], single[12]));
+    assert!(relative_eq!(bounced[13], 1.));
+}
+
+#[test]
+fn gtao() {
+    let v = |pos: [f32; 3], norm: [f32; 3]| VertNTT {
+        pos: pos,
+        norm: norm,
+        tan: [1., 0., 0.],
+        bitan: [0., 0., 1.],
+        tex: [0., 0.],
+    };
+    // a corner where a floor meets a wall, with points in front of the wall
+    let (up, back) = ([0., 1., 0.], [0., 0., 1.]);
+    let verts = vec![
+        v([-2., 0., 0.], up), v([2., 0., 0.], up), v([2., 0., 2.], up), v([-2., 0., 2.], up),
+        v([-2., 0., 0.], back), v([2., 0., 0.], back), v([2., 2., 0.], back), v([-2., 2., 0.], back),
+        v([0., 0.01, 0.05], up), v([0.5, 0.01, 0.3], up), v([0., 1., 0.05], back), v([0., 5., 5.], up),
+    ];
+    let inds = vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7];
+    let bvh = Bvh::new(&verts, &inds);
+    let reference = bake_bent_occlusion(&verts, &inds, 4096, 10.);
+    let ao = bake_gtao(&verts, &inds, 64, 10., &bvh);
+    for i in 8..11 {
+        assert!((ao[i] - reference[i][3]).abs() < 0.05, "{}: {} vs {}", i, ao[i], reference[i][3]);
+    }
+    assert!(ao[8] < ao[9]);
+    assert!(relative_eq!(ao[11], 1., epsilon = 1e-3));
+
+    assert_eq!(sobol(0), (0., 0.));
+    assert_eq!(sobol(1), (0.5, 0.5));
+    // every quarter of the square gets one of the first four points
+    let quarters: Vec<(bool, bool)> = (0..4).map(|i| { let (x, y) = sobol(i); (x < 0.5, y < 0.5) }).collect();
+    for &q in &[(true, true), (true, false), (false, true), (false, false)] {
+        assert!(quarters.contains(&q));
+    }
+}
@@ -0,0 +1,106 @@
+use nalgebra::{Point3, Vector3, Transform3};
+use std::f32::{INFINITY, NEG_INFINITY};
+
+/// An axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// A box that contains nothing, extending it by any point gives a box around that point
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new(INFINITY, INFINITY, INFINITY),
+            max: Point3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY),
+        }
+    }
+
+    /// The smallest box containing all of the given points
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point3<f32>>>(points: I) -> Aabb {
+        let mut b = Aabb::empty();
+        for p in points {
+            b.extend(p);
+        }
+        b
+    }
+
+    /// True if this box contains nothing
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Grow this box to contain the given point
+    pub fn extend(&mut self, p: &Point3<f32>) {
+        for i in 0..3 {
+            self.min[i] = self.min[i].min(p[i]);
+            self.max[i] = self.max[i].max(p[i]);
+        }
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut b = *self;
+        b.extend(&other.min);
+        b.extend(&other.max);
+        b
+    }
+
+    /// The center of the box
+    pub fn center(&self) -> Point3<f32> {
+        Point3::from_coordinates((self.min.coords + self.max.coords) * 0.5)
+    }
+
+    /// The size of the box along each axis
+    pub fn extents(&self) -> Vector3<f32> {
+        if self.is_empty() { Vector3::new(0., 0., 0.) } else { self.max - self.min }
+    }
+
+    /// The length of the diagonal of the box
+    pub fn diagonal(&self) -> f32 {
+        self.extents().norm()
+    }
+
+    /// The eight corners of the box, where corner `i` takes its x from `max` if bit 2 of `i`
+    /// is set, its y from `max` if bit 1 is set, and its z from `max` if bit 0 is set
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let mut c = [self.min; 8];
+        for (i, p) in c.iter_mut().enumerate() {
+            if i & 4 != 0 { p.x = self.max.x }
+            if i & 2 != 0 { p.y = self.max.y }
+            if i & 1 != 0 { p.z = self.max.z }
+        }
+        c
+    }
+
+    /// The box containing this box after it has been transformed
+    pub fn transform(&self, t: &Transform3<f32>) -> Aabb {
+        if self.is_empty() { return *self }
+        let corners = self.corners();
+        let moved: Vec<_> = corners.iter().map(|p| t * p).collect();
+        Aabb::from_points(&moved)
+    }
+
+    /// The center and radius of a sphere containing the box
+    pub fn bounding_sphere(&self) -> (Point3<f32>, f32) {
+        (self.center(), self.diagonal() / 2.)
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
+#[test]
+fn aabb_ops() {
+    let b = Aabb::from_points(&[Point3::new(1., 0., -1.), Point3::new(-1., 2., 1.)]);
+    assert_eq!(b.min, Point3::new(-1., 0., -1.));
+    assert_eq!(b.max, Point3::new(1., 2., 1.));
+    assert_eq!(b.center(), Point3::new(0., 1., 0.));
+    assert_eq!(b.corners()[5], Point3::new(1., 0., 1.));
+    assert!(Aabb::empty().is_empty());
+    assert_eq!(Aabb::empty().union(&b), b);
+}
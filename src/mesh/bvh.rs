@@ -0,0 +1,265 @@
+use nalgebra::{Point3, Vector3};
+
+use super::{Aabb, Vertex};
+
+/// The most triangles kept in a leaf of a `Bvh`
+const LEAF_SIZE: usize = 4;
+
+/// A node of a `Bvh`. Leaves hold `count` triangles from `start`, while other nodes have their
+/// first child right after them and their second child at `start`.
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    bounds: Aabb,
+    start: u32,
+    count: u32,
+}
+
+/// Where a ray hit a triangle of a `Bvh`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhHit {
+    /// The distance along the ray, in multiples of its direction
+    pub t: f32,
+    /// The index of the triangle in the index list the tree was built from, divided by 3
+    pub triangle: usize,
+    /// The barycentric weights of the second and third corners of the triangle
+    pub uv: [f32; 2],
+}
+
+/// A bounding volume hierarchy over the triangles of a triangle list, for casting rays
+/// against large meshes when baking. The tree is stored flat, depth first, and split at the
+/// median of the longest axis.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    tris: Vec<[u32; 3]>,
+    ids: Vec<usize>,
+    points: Vec<Point3<f32>>,
+}
+
+impl Bvh {
+    /// Build a tree over a triangle list, skipping triangles with indices out of range
+    pub fn new<V: Vertex>(verts: &[V], inds: &[u32]) -> Bvh {
+        let points: Vec<Point3<f32>> = verts.iter().map(|v| *v.pos()).collect();
+        let mut order: Vec<(usize, [u32; 3])> = inds.chunks(3).enumerate()
+            .filter(|&(_, t)| t.len() == 3 && t.iter().all(|&i| (i as usize) < points.len()))
+            .map(|(n, t)| (n, [t[0], t[1], t[2]]))
+            .collect();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(order.len() * 2 / LEAF_SIZE + 1),
+            tris: Vec::new(),
+            ids: Vec::new(),
+            points: points,
+        };
+        let len = order.len();
+        // a node for an empty range would look like an interior node, so an empty tree has none
+        if len > 0 {
+            bvh.build(&mut order, 0, len);
+        }
+        bvh.ids = order.iter().map(|&(n, _)| n).collect();
+        bvh.tris = order.into_iter().map(|(_, t)| t).collect();
+        bvh
+    }
+
+    /// The number of triangles in the tree
+    pub fn len(&self) -> usize {
+        self.tris.len()
+    }
+
+    /// True if the tree has no triangles
+    pub fn is_empty(&self) -> bool {
+        self.tris.is_empty()
+    }
+
+    fn corners(&self, t: &[u32; 3]) -> [&Point3<f32>; 3] {
+        [&self.points[t[0] as usize], &self.points[t[1] as usize], &self.points[t[2] as usize]]
+    }
+
+    fn centroid(&self, t: &[u32; 3]) -> Point3<f32> {
+        let c = self.corners(t);
+        Point3::from_coordinates((c[0].coords + c[1].coords + c[2].coords) / 3.)
+    }
+
+    fn build(&mut self, tris: &mut [(usize, [u32; 3])], offset: usize, len: usize) {
+        let mut bounds = Aabb::empty();
+        let mut centers = Aabb::empty();
+        for &(_, ref t) in tris.iter() {
+            for p in &self.corners(t) {
+                bounds.extend(p);
+            }
+            centers.extend(&self.centroid(t));
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node { bounds: bounds, start: offset as u32, count: len as u32 });
+        if len <= LEAF_SIZE {
+            return;
+        }
+
+        let size = centers.extents();
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        tris.sort_by(|a, b| {
+            let (ca, cb) = (self.centroid(&a.1)[axis], self.centroid(&b.1)[axis]);
+            ca.partial_cmp(&cb).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+        let half = len / 2;
+        let (left, right) = tris.split_at_mut(half);
+        self.build(left, offset, half);
+        let second = self.nodes.len();
+        self.build(right, offset + half, len - half);
+        self.nodes[node].start = second as u32;
+        self.nodes[node].count = 0;
+    }
+
+    /// The closest hit of a ray nearer than `max`, ignoring triangles (by their index in the
+    /// original list) for which `skip` is true
+    pub fn cast_filtered<F>(&self, origin: &Point3<f32>, dir: &Vector3<f32>, max: f32, skip: F) -> Option<BvhHit>
+        where F: Fn(usize) -> bool
+    {
+        let mut best: Option<BvhHit> = None;
+        self.walk(origin, dir, max, |bvh, i, limit| {
+            if skip(bvh.ids[i]) { return limit }
+            match ray_triangle(origin, dir, bvh.corners(&bvh.tris[i])) {
+                Some((t, u, v)) if t < limit => {
+                    best = Some(BvhHit { t: t, triangle: bvh.ids[i], uv: [u, v] });
+                    t
+                },
+                _ => limit,
+            }
+        });
+        best
+    }
+
+    /// The closest hit of a ray nearer than `max`
+    pub fn cast(&self, origin: &Point3<f32>, dir: &Vector3<f32>, max: f32) -> Option<BvhHit> {
+        self.cast_filtered(origin, dir, max, |_| false)
+    }
+
+    /// Whether a ray hits anything nearer than `max`, ignoring triangles for which `skip` is
+    /// true. This stops at the first hit found.
+    pub fn occluded<F>(&self, origin: &Point3<f32>, dir: &Vector3<f32>, max: f32, skip: F) -> bool
+        where F: Fn(usize) -> bool
+    {
+        let mut hit = false;
+        self.walk(origin, dir, max, |bvh, i, limit| {
+            if skip(bvh.ids[i]) { return limit }
+            match ray_triangle(origin, dir, bvh.corners(&bvh.tris[i])) {
+                Some((t, _, _)) if t < limit => {
+                    hit = true;
+                    // nothing is nearer than 0, which ends the walk
+                    0.
+                },
+                _ => limit,
+            }
+        });
+        hit
+    }
+
+    /// Visit the triangles of leaves the ray passes through nearer than the limit, which
+    /// `visit` returns for the rest of the walk
+    fn walk<F>(&self, origin: &Point3<f32>, dir: &Vector3<f32>, max: f32, mut visit: F)
+        where F: FnMut(&Bvh, usize, f32) -> f32
+    {
+        if self.nodes.is_empty() { return }
+        let inv = Vector3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let mut limit = max;
+        let mut stack = vec![0usize];
+        while let Some(n) = stack.pop() {
+            let node = self.nodes[n];
+            if !slab(origin, &inv, &node.bounds, limit) { continue }
+            if node.count > 0 {
+                for i in node.start..node.start + node.count {
+                    limit = visit(self, i as usize, limit);
+                    if limit <= 0. { return }
+                }
+            } else {
+                stack.push(node.start as usize);
+                stack.push(n + 1);
+            }
+        }
+    }
+}
+
+/// Whether a ray enters a box nearer than `max`, given the reciprocal of its direction
+fn slab(origin: &Point3<f32>, inv: &Vector3<f32>, b: &Aabb, max: f32) -> bool {
+    let (mut near, mut far) = (0f32, max);
+    for i in 0..3 {
+        let mut t0 = (b.min[i] - origin[i]) * inv[i];
+        let mut t1 = (b.max[i] - origin[i]) * inv[i];
+        if t0 > t1 { ::std::mem::swap(&mut t0, &mut t1) }
+        // NaN from 0 * inf (a ray in the plane of a face) leaves the range alone
+        if t0 > near { near = t0 }
+        if t1 < far { far = t1 }
+        if near > far { return false }
+    }
+    true
+}
+
+/// The distance and barycentric weights of the second and third corners where a ray hits a
+/// triangle in front of it (Möller-Trumbore)
+pub(super) fn ray_triangle(origin: &Point3<f32>, dir: &Vector3<f32>, tri: [&Point3<f32>; 3]) -> Option<(f32, f32, f32)> {
+    let e1 = tri[1] - tri[0];
+    let e2 = tri[2] - tri[0];
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 { return None }
+    let inv = 1. / det;
+    let s = origin - tri[0];
+    let u = s.dot(&p) * inv;
+    if u < 0. || u > 1. { return None }
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) * inv;
+    if v < 0. || u + v > 1. { return None }
+    let t = e2.dot(&q) * inv;
+    if t > 0. { Some((t, u, v)) } else { None }
+}
+
+impl Default for Bvh {
+    fn default() -> Bvh {
+        Bvh::new::<super::Vert>(&[], &[])
+    }
+}
+
+#[test]
+fn bvh_casts() {
+    use std::f32::INFINITY;
+    use super::{gen, Indexing};
+
+    let sphere = gen::sphere(1., 32, 16);
+    let inds = match sphere.inds {
+        Indexing::Inds(ref i) => i.clone(),
+        _ => unreachable!(),
+    };
+    let verts = sphere.verts;
+    let bvh = Bvh::new(&verts, &inds);
+    assert_eq!(bvh.len(), inds.len() / 3);
+
+    let brute = |origin: &Point3<f32>, dir: &Vector3<f32>| {
+        inds.chunks(3).enumerate()
+            .filter_map(|(n, t)| {
+                let c = [verts[t[0] as usize].pos(), verts[t[1] as usize].pos(), verts[t[2] as usize].pos()];
+                ray_triangle(origin, dir, c).map(|(t, _, _)| (t, n))
+            })
+            .fold(None, |best: Option<(f32, usize)>, h| match best {
+                Some(b) if b.0 <= h.0 => Some(b),
+                _ => Some(h),
+            })
+    };
+    for i in 0..50 {
+        let a = i as f32 * 2.399_963;
+        let origin = Point3::new(a.cos() * 3., (i as f32 * 0.37).sin() * 2., a.sin() * 3.);
+        let target = Point3::new((a * 3.).sin() * 0.8, (a * 5.).cos() * 0.8, 0.);
+        let dir = target - origin;
+        let expected = brute(&origin, &dir);
+        let hit = bvh.cast(&origin, &dir, INFINITY);
+        assert_eq!(hit.map(|h| h.triangle), expected.map(|e| e.1));
+        if let (Some(h), Some(e)) = (hit, expected) {
+            assert!(relative_eq!(h.t, e.0));
+            assert!(bvh.occluded(&origin, &dir, INFINITY, |_| false));
+            // skipping the front face finds the back of the sphere
+            let back = bvh.cast_filtered(&origin, &dir, INFINITY, |n| n == h.triangle).unwrap();
+            assert!(back.t > h.t);
+        }
+    }
+    // rays too short to reach the sphere
+    assert!(bvh.cast(&Point3::new(0., 0., 3.), &-Vector3::z(), 1.5).is_none());
+    assert!(!bvh.occluded(&Point3::new(0., 0., 3.), &Vector3::z(), INFINITY, |_| false));
+    assert!(Bvh::default().cast(&Point3::origin(), &Vector3::z(), INFINITY).is_none());
+}
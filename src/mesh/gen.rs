@@ -0,0 +1,677 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Point3, Vector3};
+
+use super::{MeshSource, Indexing, Primitive, VertNTT, Path};
+use ::{Error, FlightError, NativeRepr};
+
+/// The analytic shape of a generated mesh. Styles that support it (see `draw::UberMaterial`)
+/// compute normals per-pixel from the object-space position, so that low-poly primitives
+/// shade perfectly smoothly. Shapes are centered on the origin with their axis along +Y.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Surface {
+    /// Arbitrary geometry, the interpolated vertex normals are used
+    Mesh,
+    /// A sphere
+    Sphere {
+        radius: f32,
+    },
+    /// A capped cylinder
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+    /// A cylinder with hemispherical caps, where `height` excludes the caps
+    Capsule {
+        radius: f32,
+        height: f32,
+    },
+}
+
+impl Default for Surface {
+    fn default() -> Surface {
+        Surface::Mesh
+    }
+}
+
+/// Push a grid of `rows` by `cols` quads onto the index list, where vertex `(i, j)` is at
+/// `base + i * (cols + 1) + j`. Rows run downward and columns run counterclockwise around
+/// the +Y axis, which makes the triangles face outward.
+fn push_grid(inds: &mut Vec<u32>, base: u32, rows: u32, cols: u32, skip_top: bool, skip_bottom: bool) {
+    for i in 0..rows {
+        for j in 0..cols {
+            let a = base + i * (cols + 1) + j;
+            let b = a + 1;
+            let c = a + cols + 1;
+            let d = c + 1;
+            if !(skip_top && i == 0) {
+                inds.extend(&[a, b, d]);
+            }
+            if !(skip_bottom && i == rows - 1) {
+                inds.extend(&[a, d, c]);
+            }
+        }
+    }
+}
+
+/// Push a flat disk at height `y` onto the mesh, facing +Y if `up` and -Y otherwise
+fn push_cap(verts: &mut Vec<VertNTT>, inds: &mut Vec<u32>, radius: f32, y: f32, slices: u32, up: bool) {
+    let base = verts.len() as u32;
+    let ny = if up { 1. } else { -1. };
+    verts.push(VertNTT {
+        pos: [0., y, 0.],
+        norm: [0., ny, 0.],
+        tan: [1., 0., 0.],
+        bitan: [0., 0., ny],
+        tex: [0.5, 0.5],
+    });
+    for j in 0..(slices + 1) {
+        let phi = 2. * PI * j as f32 / slices as f32;
+        let (s, c) = phi.sin_cos();
+        verts.push(VertNTT {
+            pos: [radius * c, y, radius * s],
+            norm: [0., ny, 0.],
+            tan: [1., 0., 0.],
+            bitan: [0., 0., ny],
+            tex: [0.5 + 0.5 * c, 0.5 + 0.5 * ny * s],
+        });
+    }
+    for j in 0..slices {
+        let a = base + 1 + j;
+        if up {
+            inds.extend(&[base, a + 1, a]);
+        } else {
+            inds.extend(&[base, a, a + 1]);
+        }
+    }
+}
+
+/// Generate a UV sphere with the given number of slices (around the axis) and stacks
+/// (from pole to pole).
+pub fn sphere(radius: f32, slices: u32, stacks: u32) -> MeshSource<VertNTT, Surface> {
+    let slices = slices.max(3);
+    let stacks = stacks.max(2);
+    let mut verts = Vec::with_capacity(((slices + 1) * (stacks + 1)) as usize);
+    for i in 0..(stacks + 1) {
+        let theta = PI * i as f32 / stacks as f32;
+        let (st, ct) = theta.sin_cos();
+        for j in 0..(slices + 1) {
+            let phi = 2. * PI * j as f32 / slices as f32;
+            let (sp, cp) = phi.sin_cos();
+            verts.push(VertNTT {
+                pos: [radius * st * cp, radius * ct, radius * st * sp],
+                norm: [st * cp, ct, st * sp],
+                tan: [-sp, 0., cp],
+                bitan: [-ct * cp, st, -ct * sp],
+                tex: [j as f32 / slices as f32, 1. - i as f32 / stacks as f32],
+            });
+        }
+    }
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, stacks, slices, true, true);
+    MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Sphere { radius: radius },
+    }
+}
+
+/// Generate a capped cylinder with the given number of slices around its axis
+pub fn cylinder(radius: f32, height: f32, slices: u32) -> MeshSource<VertNTT, Surface> {
+    let slices = slices.max(3);
+    let half = height / 2.;
+    let mut verts = Vec::new();
+    for i in 0..2 {
+        let y = if i == 0 { half } else { -half };
+        for j in 0..(slices + 1) {
+            let phi = 2. * PI * j as f32 / slices as f32;
+            let (s, c) = phi.sin_cos();
+            verts.push(VertNTT {
+                pos: [radius * c, y, radius * s],
+                norm: [c, 0., s],
+                tan: [-s, 0., c],
+                bitan: [0., 1., 0.],
+                tex: [j as f32 / slices as f32, 1. - i as f32],
+            });
+        }
+    }
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, 1, slices, false, false);
+    push_cap(&mut verts, &mut inds, radius, half, slices, true);
+    push_cap(&mut verts, &mut inds, radius, -half, slices, false);
+    MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Cylinder { radius: radius, height: height },
+    }
+}
+
+/// Generate a capsule (a cylinder with hemispherical ends) along +Y, where `height` is the
+/// length of the cylindrical part. `rings` is the number of stacks in each hemisphere.
+pub fn capsule(radius: f32, height: f32, slices: u32, rings: u32) -> Result<MeshSource<VertNTT, Surface>, Error> {
+    ensure!(radius > 0., FlightError::InvalidShape { reason: "capsule radius must be positive" });
+    ensure!(height >= 0., FlightError::InvalidShape { reason: "capsule height must not be negative" });
+    let slices = slices.max(3);
+    let rings = rings.max(1);
+    let half = height / 2.;
+    // texture coordinates run evenly along the outline from pole to pole
+    let length = PI * radius + height;
+    let mut verts = Vec::with_capacity(((slices + 1) * (2 * rings + 2)) as usize);
+    for i in 0..(2 * rings + 2) {
+        let (theta, y, along) = if i <= rings {
+            let theta = PI / 2. * i as f32 / rings as f32;
+            (theta, half, radius * theta)
+        } else {
+            let theta = PI / 2. * (1. + (i - rings - 1) as f32 / rings as f32);
+            (theta, -half, height + radius * theta)
+        };
+        let (st, ct) = theta.sin_cos();
+        for j in 0..(slices + 1) {
+            let phi = 2. * PI * j as f32 / slices as f32;
+            let (sp, cp) = phi.sin_cos();
+            verts.push(VertNTT {
+                pos: [radius * st * cp, radius * ct + y, radius * st * sp],
+                norm: [st * cp, ct, st * sp],
+                tan: [-sp, 0., cp],
+                bitan: [-ct * cp, st, -ct * sp],
+                tex: [j as f32 / slices as f32, 1. - along / length],
+            });
+        }
+    }
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, 2 * rings + 1, slices, true, true);
+    Ok(MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Capsule { radius: radius, height: height },
+    })
+}
+
+/// Generate a torus around +Y. `major` is the distance from the center to the middle of
+/// the tube and `minor` is the radius of the tube.
+pub fn torus(major: f32, minor: f32, major_segments: u32, minor_segments: u32)
+    -> Result<MeshSource<VertNTT, Surface>, Error>
+{
+    ensure!(minor > 0., FlightError::InvalidShape { reason: "torus tube radius must be positive" });
+    ensure!(major > minor, FlightError::InvalidShape { reason: "torus tube must be thinner than the ring" });
+    let cols = major_segments.max(3);
+    let rows = minor_segments.max(3);
+    let mut verts = Vec::with_capacity(((cols + 1) * (rows + 1)) as usize);
+    for i in 0..(rows + 1) {
+        // rows run downward on the outside of the ring
+        let theta = -2. * PI * i as f32 / rows as f32;
+        let (st, ct) = theta.sin_cos();
+        for j in 0..(cols + 1) {
+            let phi = 2. * PI * j as f32 / cols as f32;
+            let (sp, cp) = phi.sin_cos();
+            let r = major + minor * ct;
+            verts.push(VertNTT {
+                pos: [r * cp, minor * st, r * sp],
+                norm: [ct * cp, st, ct * sp],
+                tan: [-sp, 0., cp],
+                bitan: [-st * cp, ct, -st * sp],
+                tex: [j as f32 / cols as f32, 1. - i as f32 / rows as f32],
+            });
+        }
+    }
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, rows, cols, false, false);
+    Ok(MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Mesh,
+    })
+}
+
+/// Generate a box centered on the origin with the given size and its edges rounded with
+/// the given radius. Each rounded edge is split into `segments` pieces. Every face gets
+/// its own texture coordinates from 0 to 1.
+pub fn rounded_box(size: Vector3<f32>, radius: f32, segments: u32) -> Result<MeshSource<VertNTT, Surface>, Error> {
+    ensure!(size.x > 0. && size.y > 0. && size.z > 0.,
+        FlightError::InvalidShape { reason: "box size must be positive" });
+    ensure!(radius >= 0. && 2. * radius <= size.x.min(size.y).min(size.z),
+        FlightError::InvalidShape { reason: "box corner radius must fit within the box" });
+    let outer = size / 2.;
+    let inner = outer - Vector3::repeat(radius);
+    // each face covers half of the rounded edges it borders
+    let steps = if radius > 0. { (segments + 1) / 2 } else { 0 };
+
+    // sample coordinates along an axis, spaced so normals turn evenly around the edges
+    let samples = |inner: f32| {
+        let mut out = Vec::new();
+        for k in (1..(steps + 1)).rev() {
+            out.push(-inner - radius * (PI / 4. * k as f32 / steps as f32).tan());
+        }
+        out.push(-inner);
+        if inner > 0. {
+            out.push(inner);
+        }
+        for k in 1..(steps + 1) {
+            out.push(inner + radius * (PI / 4. * k as f32 / steps as f32).tan());
+        }
+        out
+    };
+    let axes = [samples(inner.x), samples(inner.y), samples(inner.z)];
+
+    // (normal axis, normal sign, u axis, u sign, v axis, v sign) with u cross v = normal
+    let faces = [
+        (2, 1., 0, 1., 1, 1.),
+        (2, -1., 0, -1., 1, 1.),
+        (0, 1., 2, -1., 1, 1.),
+        (0, -1., 2, 1., 1, 1.),
+        (1, 1., 0, 1., 2, -1.),
+        (1, -1., 0, 1., 2, 1.),
+    ];
+    let mut verts = Vec::new();
+    let mut inds = Vec::new();
+    for &(n, ns, u, us, v, vs) in &faces {
+        let base = verts.len() as u32;
+        let (us_list, vs_list) = (&axes[u], &axes[v]);
+        let (cols, rows) = (us_list.len() - 1, vs_list.len() - 1);
+        let (mut u_dir, mut v_dir) = (Vector3::zeros(), Vector3::zeros());
+        u_dir[u] = us;
+        v_dir[v] = vs;
+        for i in 0..(rows + 1) {
+            for j in 0..(cols + 1) {
+                // walk the samples in the direction of the face's axes
+                let a = us_list[if us > 0. { j } else { cols - j }];
+                let b = vs_list[if vs > 0. { i } else { rows - i }];
+                let mut p = Vector3::zeros();
+                p[n] = ns * outer[n];
+                p[u] = a;
+                p[v] = b;
+                let q = Vector3::new(
+                    p.x.max(-inner.x).min(inner.x),
+                    p.y.max(-inner.y).min(inner.y),
+                    p.z.max(-inner.z).min(inner.z),
+                );
+                let mut norm = p - q;
+                let len = norm.norm();
+                norm = if len > 1e-6 { norm / len } else {
+                    let mut f = Vector3::zeros();
+                    f[n] = ns;
+                    f
+                };
+                let pos = q + norm * radius;
+                let tan = (u_dir - norm * norm.dot(&u_dir)).normalize();
+                let bitan = (v_dir - norm * norm.dot(&v_dir)).normalize();
+                verts.push(VertNTT {
+                    pos: [pos.x, pos.y, pos.z],
+                    norm: [norm.x, norm.y, norm.z],
+                    tan: [tan.x, tan.y, tan.z],
+                    bitan: [bitan.x, bitan.y, bitan.z],
+                    tex: [j as f32 / cols as f32, i as f32 / rows as f32],
+                });
+            }
+        }
+        // rows run along v here, so the grid faces along the normal
+        push_grid(&mut inds, base, rows as u32, cols as u32, false, false);
+    }
+    Ok(MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Mesh,
+    })
+}
+
+/// Generate a flat rectangle in the XY plane, facing +Z
+pub fn quad(width: f32, height: f32) -> MeshSource<VertNTT, Surface> {
+    let (x, y) = (width / 2., height / 2.);
+    let vert = |px: f32, py: f32, u: f32, v: f32| VertNTT {
+        pos: [px, py, 0.],
+        norm: [0., 0., 1.],
+        tan: [1., 0., 0.],
+        bitan: [0., 1., 0.],
+        tex: [u, v],
+    };
+    MeshSource {
+        verts: vec![vert(-x, -y, 0., 0.), vert(x, -y, 1., 0.), vert(x, y, 1., 1.), vert(-x, y, 0., 1.)],
+        inds: Indexing::Inds(vec![0, 1, 2, 0, 2, 3]),
+        prim: Primitive::TriangleList,
+        mat: Surface::Mesh,
+    }
+}
+
+/// Generate the inside of a section of a cylinder, spanning `arc` radians and facing its
+/// axis. The middle of the section is at the origin facing +Z, with the axis `radius`
+/// units along +Z. Texture coordinates run left to right across the arc.
+pub fn cylinder_section(radius: f32, arc: f32, height: f32, segments: u32) -> MeshSource<VertNTT, Surface> {
+    let segments = segments.max(1);
+    let half = height / 2.;
+    let mut verts = Vec::with_capacity(2 * (segments + 1) as usize);
+    for i in 0..2 {
+        let y = if i == 0 { -half } else { half };
+        for j in 0..(segments + 1) {
+            let u = j as f32 / segments as f32;
+            let (s, c) = ((u - 0.5) * arc).sin_cos();
+            verts.push(VertNTT {
+                pos: [radius * s, y, radius - radius * c],
+                norm: [-s, 0., c],
+                tan: [c, 0., s],
+                bitan: [0., 1., 0.],
+                tex: [u, i as f32],
+            });
+        }
+    }
+    // rows run upward here, so the grid faces the axis
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, 1, segments, false, false);
+    MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Mesh,
+    }
+}
+
+/// A unit vector perpendicular to `t`, as close to +Y as possible
+fn frame_up(t: &Vector3<f32>) -> Vector3<f32> {
+    let up = if t.y.abs() < 0.99 { Vector3::y() } else { Vector3::x() };
+    (up - t * t.dot(&up)).normalize()
+}
+
+/// The up direction of the frame at each sample along a path. The first is as close to +Y
+/// as possible and the rest turn with the path by double reflection, which keeps them from
+/// twisting around it.
+fn rotation_minimizing(samples: &[(Point3<f32>, Vector3<f32>)]) -> Vec<Vector3<f32>> {
+    let mut ups: Vec<Vector3<f32>> = Vec::with_capacity(samples.len());
+    for (i, &(p, t)) in samples.iter().enumerate() {
+        if i == 0 {
+            ups.push(frame_up(&t));
+            continue;
+        }
+        let (q, s) = samples[i - 1];
+        let r = ups[i - 1];
+        // reflect across the plane between the two points, then across the plane that
+        // brings the reflected tangent onto the new one
+        let v1 = p - q;
+        let c1 = v1.norm_squared();
+        let (rl, tl) = if c1 > 1e-12 {
+            (r - v1 * (2. / c1 * v1.dot(&r)), s - v1 * (2. / c1 * v1.dot(&s)))
+        } else {
+            (r, s)
+        };
+        let v2 = t - tl;
+        let c2 = v2.norm_squared();
+        let up = if c2 > 1e-12 { rl - v2 * (2. / c2 * v2.dot(&rl)) } else { rl };
+        // keep rounding errors from building up
+        let up = up - t * t.dot(&up);
+        ups.push(if up.norm_squared() > 1e-12 { up.normalize() } else { frame_up(&t) });
+    }
+    ups
+}
+
+/// Sweep a closed `profile` along a path with `segments` pieces, for cables, pipes and
+/// rails. At each point of the path the profile's y axis is the frame's up direction,
+/// which starts as close to +Y as possible and turns with the path without twisting, and
+/// x points to the right looking along the path. The ends are capped with fans from the
+/// middle of the profile, so it should be convex or close to it. Texture coordinates run
+/// around the profile in u and along the path in v, both in units of the profile's
+/// perimeter so that tiling textures keep their aspect ratio.
+pub fn extrude_along_path<P: Path>(profile: &[[f32; 2]], path: &P, segments: u32)
+    -> Result<MeshSource<VertNTT, Surface>, Error>
+{
+    // repeated points would leave edges without a direction
+    let same = |a: &[f32; 2], b: &[f32; 2]| (a[0] - b[0]).abs() <= 1e-6 && (a[1] - b[1]).abs() <= 1e-6;
+    let mut outline: Vec<[f32; 2]> = Vec::with_capacity(profile.len());
+    for p in profile {
+        if outline.last().map(|l| !same(l, p)).unwrap_or(true) {
+            outline.push(*p);
+        }
+    }
+    while outline.len() > 1 && same(&outline[0], &outline[outline.len() - 1]) {
+        outline.pop();
+    }
+    let n = outline.len();
+    let area = (0..n).map(|j| {
+        let (a, b) = (outline[j], outline[(j + 1) % n]);
+        a[0] * b[1] - b[0] * a[1]
+    }).sum::<f32>() / 2.;
+    ensure!(n >= 3 && area.abs() > 1e-12,
+        FlightError::InvalidShape { reason: "extrusion profile must enclose an area" });
+    // with x to the right, clockwise profiles face outward
+    if area > 0. {
+        outline.reverse();
+    }
+
+    // smoothed normals and the distance around the profile at each point
+    let mut normals = Vec::with_capacity(n);
+    let mut around = Vec::with_capacity(n + 1);
+    let mut perimeter = 0.;
+    for j in 0..n {
+        let (prev, p, next) = (outline[(j + n - 1) % n], outline[j], outline[(j + 1) % n]);
+        let edge = |a: [f32; 2], b: [f32; 2]| {
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            let l = (dx * dx + dy * dy).sqrt();
+            [-dy / l, dx / l]
+        };
+        let (a, b) = (edge(prev, p), edge(p, next));
+        let (nx, ny) = (a[0] + b[0], a[1] + b[1]);
+        let l = (nx * nx + ny * ny).sqrt().max(1e-12);
+        normals.push([nx / l, ny / l]);
+        around.push(perimeter);
+        perimeter += ((next[0] - p[0]).powi(2) + (next[1] - p[1]).powi(2)).sqrt();
+    }
+    around.push(perimeter);
+
+    let segments = segments.max(1);
+    let samples: Vec<_> = (0..(segments + 1))
+        .map(|i| path.sample(i as f32 / segments as f32))
+        .collect();
+    let ups = rotation_minimizing(&samples);
+    let length = path.length();
+    let mut verts = Vec::with_capacity(((segments + 1) * (n as u32 + 1)) as usize + 2 * (n + 1));
+    for (i, (&(p, t), &y)) in samples.iter().zip(&ups).enumerate() {
+        let x = t.cross(&y);
+        let along = length * i as f32 / segments as f32;
+        for j in 0..(n + 1) {
+            let (q, nm) = (outline[j % n], normals[j % n]);
+            let norm = x * nm[0] + y * nm[1];
+            let tan = x * nm[1] - y * nm[0];
+            verts.push(VertNTT {
+                pos: (p + x * q[0] + y * q[1]).downgrade(),
+                norm: norm.downgrade(),
+                tan: tan.downgrade(),
+                bitan: t.downgrade(),
+                tex: [around[j] / perimeter, along / perimeter],
+            });
+        }
+    }
+    let mut inds = Vec::new();
+    push_grid(&mut inds, 0, segments, n as u32, false, false);
+
+    // caps, textured by the profile's bounds
+    let (mut min, mut max) = ([::std::f32::INFINITY; 2], [::std::f32::NEG_INFINITY; 2]);
+    for q in &outline {
+        for k in 0..2 {
+            min[k] = min[k].min(q[k]);
+            max[k] = max[k].max(q[k]);
+        }
+    }
+    let center = [
+        outline.iter().map(|q| q[0]).sum::<f32>() / n as f32,
+        outline.iter().map(|q| q[1]).sum::<f32>() / n as f32,
+    ];
+    for &(i, end) in &[(0, false), (segments as usize, true)] {
+        let ((p, t), y) = (samples[i], ups[i]);
+        let x = t.cross(&y);
+        let norm = if end { t } else { -t };
+        let base = verts.len() as u32;
+        for q in Some(center).iter().chain(&outline) {
+            verts.push(VertNTT {
+                pos: (p + x * q[0] + y * q[1]).downgrade(),
+                norm: norm.downgrade(),
+                tan: x.downgrade(),
+                bitan: y.downgrade(),
+                tex: [(q[0] - min[0]) / (max[0] - min[0]), (q[1] - min[1]) / (max[1] - min[1])],
+            });
+        }
+        for j in 0..n as u32 {
+            let (a, b) = (base + 1 + j, base + 1 + (j + 1) % n as u32);
+            if end {
+                inds.extend(&[base, a, b]);
+            } else {
+                inds.extend(&[base, b, a]);
+            }
+        }
+    }
+
+    Ok(MeshSource {
+        verts: verts,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: Surface::Mesh,
+    })
+}
+
+#[test]
+fn generated_winding() {
+    use super::CatmullRom;
+    // every triangle of a convex shape centered on the origin should face outward
+    let square = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]];
+    let straight = CatmullRom::new(vec![Point3::new(0., 0., 1.), Point3::new(0., 0., -1.)]).unwrap();
+    let convex = [
+        sphere(1., 12, 6),
+        cylinder(0.5, 2., 10),
+        capsule(0.5, 1., 10, 3).unwrap(),
+        rounded_box(Vector3::new(1., 2., 3.), 0.25, 4).unwrap(),
+        rounded_box(Vector3::new(1., 1., 1.), 0., 4).unwrap(),
+        extrude_along_path(&square, &straight, 3).unwrap(),
+    ];
+    for mesh in &convex {
+        let inds = match mesh.inds {
+            Indexing::Inds(ref i) => i,
+            _ => unreachable!(),
+        };
+        for t in inds.chunks(3) {
+            let p = |i: u32| {
+                let v = mesh.verts[i as usize].pos;
+                Point3::new(v[0], v[1], v[2])
+            };
+            let (a, b, c) = (p(t[0]), p(t[1]), p(t[2]));
+            let n: Vector3<f32> = (b - a).cross(&(c - a));
+            let center = (a.coords + b.coords + c.coords) / 3.;
+            assert!(n.norm() > 1e-6, "degenerate triangle");
+            assert!(n.dot(&center) > 0., "triangle faces inward");
+        }
+    }
+
+    // panels face +Z, or their axis when curved
+    for mesh in &[quad(2., 1.), cylinder_section(1., PI / 2., 1., 4)] {
+        let inds = match mesh.inds {
+            Indexing::Inds(ref i) => i,
+            _ => unreachable!(),
+        };
+        for t in inds.chunks(3) {
+            let p = |i: u32| {
+                let v = mesh.verts[i as usize].pos;
+                Point3::new(v[0], v[1], v[2])
+            };
+            let (a, b, c) = (p(t[0]), p(t[1]), p(t[2]));
+            let n: Vector3<f32> = (b - a).cross(&(c - a));
+            let center = (a.coords + b.coords + c.coords) / 3.;
+            assert!(n.dot(&(Vector3::new(0., center.y, 1.) - center)) > 0., "panel faces away");
+        }
+    }
+}
+
+#[test]
+fn generated_closed() {
+    use fnv::FnvHashMap;
+    use super::CatmullRom;
+
+    let donut = torus(1., 0.25, 16, 8).unwrap();
+    // a cable bending around in the XZ plane, over a repeated point
+    let cable = CatmullRom::new(vec![
+        Point3::new(0., 0., 0.),
+        Point3::new(0., 0., -1.),
+        Point3::new(0., 0., -1.),
+        Point3::new(1., 0., -2.),
+        Point3::new(2., 0., -1.),
+    ]).unwrap();
+    let circle: Vec<[f32; 2]> = (0..8).map(|i| {
+        let (s, c) = (i as f32 * PI / 4.).sin_cos();
+        [0.1 * c, 0.1 * s]
+    }).collect();
+    let extruded = extrude_along_path(&circle, &cable, 16).unwrap();
+    let shapes = [
+        capsule(0.5, 1., 10, 3).unwrap(),
+        donut.clone(),
+        rounded_box(Vector3::new(1., 2., 3.), 0.25, 4).unwrap(),
+        rounded_box(Vector3::new(1., 1., 1.), 0.5, 3).unwrap(),
+        extruded.clone(),
+    ];
+    for mesh in &shapes {
+        // seams duplicate vertices, so edges are matched by position
+        let mut ids = FnvHashMap::default();
+        let id: Vec<usize> = mesh.verts.iter().map(|v| {
+            let key = (
+                (v.pos[0] * 1e4).round() as i64,
+                (v.pos[1] * 1e4).round() as i64,
+                (v.pos[2] * 1e4).round() as i64,
+            );
+            let next = ids.len();
+            *ids.entry(key).or_insert(next)
+        }).collect();
+        let inds = match mesh.inds {
+            Indexing::Inds(ref i) => i,
+            _ => unreachable!(),
+        };
+        let mut edges = FnvHashMap::default();
+        for t in inds.chunks(3) {
+            let t = [id[t[0] as usize], id[t[1] as usize], id[t[2] as usize]];
+            assert!(t[0] != t[1] && t[1] != t[2] && t[2] != t[0], "degenerate triangle");
+            for k in 0..3 {
+                let (a, b) = (t[k], t[(k + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|&c| c == 2), "mesh is not closed");
+
+        for v in &mesh.verts {
+            let (n, t) = (Vector3::from_row_slice(&v.norm), Vector3::from_row_slice(&v.tan));
+            assert!((n.norm() - 1.).abs() < 1e-4 && (t.norm() - 1.).abs() < 1e-4);
+            assert!(n.dot(&t).abs() < 1e-4, "tangent is not perpendicular to the normal");
+            assert!(v.pos.iter().chain(&v.tex).all(|x| x.is_finite()));
+        }
+    }
+
+    // the torus faces away from the middle of its tube
+    let inds = match donut.inds {
+        Indexing::Inds(ref i) => i,
+        _ => unreachable!(),
+    };
+    for t in inds.chunks(3) {
+        let p = |i: u32| Point3::from_coordinates(Vector3::from_row_slice(&donut.verts[i as usize].pos));
+        let (a, b, c) = (p(t[0]), p(t[1]), p(t[2]));
+        let center = (a.coords + b.coords + c.coords) / 3.;
+        let ring = Vector3::new(center.x, 0., center.z).normalize();
+        assert!((b - a).cross(&(c - a)).dot(&(center - ring)) > 0., "torus faces inward");
+    }
+
+    // the frames don't twist away from up on a level path
+    for ring in extruded.verts.chunks(circle.len() + 1).take(17) {
+        let top = ring.iter().map(|v| v.pos[1]).fold(0., f32::max);
+        assert!(relative_eq!(top, 0.1, epsilon = 1e-4));
+        assert!(ring.iter().any(|v| relative_eq!(v.norm[1], 1., epsilon = 1e-4)));
+    }
+    assert!(extrude_along_path(&[[0., 0.], [1., 0.], [2., 0.]], &cable, 4).is_err());
+    assert!(extrude_along_path(&circle[..2], &cable, 4).is_err());
+    // points repeated up to rounding, including closing the loop, are dropped
+    let mut repeated = circle.clone();
+    repeated.insert(3, [circle[2][0] + 1e-7, circle[2][1]]);
+    repeated.push(circle[0]);
+    assert_eq!(extrude_along_path(&repeated, &cable, 16).unwrap().verts.len(), extruded.verts.len());
+
+    assert!(capsule(0., 1., 8, 2).is_err());
+    assert!(capsule(1., -1., 8, 2).is_err());
+    assert!(torus(0.5, 1., 8, 8).is_err());
+    assert!(rounded_box(Vector3::new(1., 0., 1.), 0.1, 4).is_err());
+    assert!(rounded_box(Vector3::new(1., 1., 1.), 0.6, 4).is_err());
+    assert!(rounded_box(Vector3::new(1., 1., 1.), ::std::f32::NAN, 4).is_err());
+}
@@ -0,0 +1,221 @@
+use nalgebra::{Point3, Vector3};
+use fnv::FnvHashSet;
+
+use super::{Aabb, MeshSource, Indexing, Primitive, Vert, VertNTT, Vertex};
+
+/// A triangle of a hull under construction, counter-clockwise seen from outside
+struct Face {
+    corners: [usize; 3],
+    normal: Vector3<f32>,
+    offset: f32,
+}
+
+impl Face {
+    fn new(points: &[Point3<f32>], corners: [usize; 3]) -> Face {
+        let (a, b, c) = (corners[0], corners[1], corners[2]);
+        let normal = (points[b] - points[a]).cross(&(points[c] - points[a]))
+            .try_normalize(1e-20)
+            .unwrap_or(Vector3::zeros());
+        Face {
+            corners: corners,
+            normal: normal,
+            offset: normal.dot(&points[a].coords),
+        }
+    }
+
+    /// The signed distance of a point in front of the face
+    fn distance(&self, p: &Point3<f32>) -> f32 {
+        self.normal.dot(&p.coords) - self.offset
+    }
+}
+
+/// The triangles of the convex hull of a set of points (with no duplicates) as corner
+/// indices, or `None` if the points are all in one plane. Points are added one at a time,
+/// replacing the faces each can see with a fan from it to their horizon.
+fn convex_hull(points: &[Point3<f32>], eps: f32) -> Option<Vec<[usize; 3]>> {
+    if points.len() < 4 { return None }
+
+    // a starting tetrahedron from extreme points
+    let a = (0..points.len()).min_by(|&i, &j| points[i].x.partial_cmp(&points[j].x).unwrap()).unwrap();
+    let b = (0..points.len()).max_by(|&i, &j| {
+        let (di, dj) = ((points[i] - points[a]).norm(), (points[j] - points[a]).norm());
+        di.partial_cmp(&dj).unwrap()
+    }).unwrap();
+    let ab = points[b] - points[a];
+    let line = |i: usize| (points[i] - points[a]).cross(&ab).norm();
+    let c = (0..points.len()).max_by(|&i, &j| line(i).partial_cmp(&line(j)).unwrap()).unwrap();
+    if line(c) <= eps * ab.norm() { return None }
+    let plane = Face::new(points, [a, b, c]);
+    let d = (0..points.len()).max_by(|&i, &j| {
+        plane.distance(&points[i]).abs().partial_cmp(&plane.distance(&points[j]).abs()).unwrap()
+    }).unwrap();
+    if plane.distance(&points[d]).abs() <= eps { return None }
+
+    let mut faces = if plane.distance(&points[d]) > 0. {
+        vec![[a, c, b], [a, b, d], [b, c, d], [c, a, d]]
+    } else {
+        vec![[a, b, c], [a, d, b], [b, d, c], [c, d, a]]
+    }.into_iter().map(|f| Face::new(points, f)).collect::<Vec<_>>();
+
+    for (p, point) in points.iter().enumerate() {
+        if p == a || p == b || p == c || p == d { continue }
+        let dist: Vec<f32> = faces.iter().map(|f| f.distance(point)).collect();
+        // points within the tolerance of every face are inside or on the hull
+        if !dist.iter().any(|&d| d > eps) { continue }
+        // faces in the same plane as the point are replaced too, or their corners could end
+        // up inside the hull's faces once the point widens them
+        let visible: Vec<bool> = dist.iter().map(|&d| d > -eps).collect();
+
+        let edges: FnvHashSet<(usize, usize)> = faces.iter().zip(&visible)
+            .filter(|&(_, &v)| v)
+            .flat_map(|(f, _)| {
+                let c = f.corners;
+                vec![(c[0], c[1]), (c[1], c[2]), (c[2], c[0])]
+            })
+            .collect();
+        // edges whose neighbor across them stays are on the horizon, and keep their direction
+        let horizon: Vec<(usize, usize)> = edges.iter()
+            .filter(|&&(x, y)| !edges.contains(&(y, x)))
+            .cloned()
+            .collect();
+        let mut keep = visible.iter().map(|&v| !v);
+        faces.retain(|_| keep.next().unwrap());
+        faces.extend(horizon.into_iter().map(|(x, y)| Face::new(points, [x, y, p])));
+    }
+    Some(faces.into_iter().map(|f| f.corners).collect())
+}
+
+/// Build a mesh of the convex hull of the vertex positions, for drawing in hardware occlusion
+/// queries in place of a detailed mesh. It is drawn with few triangles and fits concave shapes
+/// such as an L much more tightly than their bounding box, while still covering everything the
+/// mesh can cover. Flat meshes, whose hull has no volume, get their bounding box. Vertices
+/// with NaN or infinite coordinates are left out.
+pub fn generate_occlusion_proxy(verts: &[VertNTT]) -> MeshSource<Vert, ()> {
+    let finite: Vec<&Point3<f32>> = verts.iter()
+        .map(|v| v.pos())
+        .filter(|p| p.coords.iter().all(|c| c.is_finite()))
+        .collect();
+    let bounds = Aabb::from_points(finite.iter().cloned());
+    let eps = bounds.diagonal() * 1e-5;
+
+    // merge positions closer than the tolerance so the hull doesn't see slivers
+    let mut points: Vec<Point3<f32>> = Vec::new();
+    let mut seen = FnvHashSet::default();
+    for p in finite {
+        let key = if eps > 0. {
+            ((p.x / eps).round() as i64, (p.y / eps).round() as i64, (p.z / eps).round() as i64)
+        } else {
+            (0, 0, 0)
+        };
+        if seen.insert(key) {
+            points.push(*p);
+        }
+    }
+
+    let (points, tris) = match convex_hull(&points, eps) {
+        Some(tris) => (points, tris),
+        None if bounds.is_empty() => (Vec::new(), Vec::new()),
+        None => {
+            let corners = bounds.corners().to_vec();
+            // corner i takes x from max with bit 2, y with bit 1 and z with bit 0
+            let tris = vec![
+                [0, 1, 3], [0, 3, 2], [4, 6, 7], [4, 7, 5],
+                [0, 4, 5], [0, 5, 1], [2, 3, 7], [2, 7, 6],
+                [0, 2, 6], [0, 6, 4], [1, 5, 7], [1, 7, 3],
+            ];
+            (corners, tris)
+        },
+    };
+
+    // keep only the points on the hull
+    let mut remap = vec![None; points.len()];
+    let mut out = Vec::new();
+    let mut inds = Vec::with_capacity(tris.len() * 3);
+    for tri in &tris {
+        for &i in tri {
+            let n = *remap[i].get_or_insert_with(|| {
+                out.push(Vert { pos: [points[i].x, points[i].y, points[i].z] });
+                out.len() as u32 - 1
+            });
+            inds.push(n);
+        }
+    }
+    MeshSource {
+        verts: out,
+        inds: Indexing::Inds(inds),
+        prim: Primitive::TriangleList,
+        mat: (),
+    }
+}
+
+#[test]
+fn occlusion_proxy() {
+    use super::gen;
+
+    let check = |proxy: &MeshSource<Vert, ()>, inside: &[Point3<f32>]| {
+        let inds = match proxy.inds {
+            Indexing::Inds(ref i) => i.clone(),
+            _ => unreachable!(),
+        };
+        let pos = |i: u32| { let p = proxy.verts[i as usize].pos; Point3::new(p[0], p[1], p[2]) };
+        for t in inds.chunks(3) {
+            let (a, b, c) = (pos(t[0]), pos(t[1]), pos(t[2]));
+            let n = (b - a).cross(&(c - a));
+            // every point is behind every face, so the faces point out
+            for p in inside {
+                assert!(n.dot(&(p - a)) <= 1e-4);
+            }
+        }
+        inds.len() / 3
+    };
+
+    // an L, whose bounding box would also cover the corner it leaves out
+    let v = |x: f32, y: f32, z: f32| VertNTT {
+        pos: [x, y, z],
+        norm: [0., 1., 0.],
+        tan: [1., 0., 0.],
+        bitan: [0., 0., 1.],
+        tex: [0., 0.],
+    };
+    let mut verts = Vec::new();
+    for &(x, y) in &[(0., 0.), (2., 0.), (2., 1.), (1., 1.), (1., 2.), (0., 2.), (0.5, 0.5)] {
+        verts.push(v(x, y, 0.));
+        verts.push(v(x, y, 1.));
+        // duplicates are merged
+        verts.push(v(x, y, 1.));
+    }
+    let proxy = generate_occlusion_proxy(&verts);
+    let points: Vec<Point3<f32>> = verts.iter().map(|v| *v.pos()).collect();
+    // a pentagonal prism: the inner corner and the middle point aren't on the hull
+    assert_eq!(proxy.verts.len(), 10);
+    assert_eq!(check(&proxy, &points), 16);
+    // the missing corner of the L is left out
+    let box_corner = Point3::new(1.9, 1.9, 0.5);
+    let inds = match proxy.inds { Indexing::Inds(ref i) => i.clone(), _ => unreachable!() };
+    assert!(inds.chunks(3).any(|t| {
+        let pos = |i: u32| { let p = proxy.verts[i as usize].pos; Point3::new(p[0], p[1], p[2]) };
+        let (a, b, c) = (pos(t[0]), pos(t[1]), pos(t[2]));
+        (b - a).cross(&(c - a)).dot(&(box_corner - a)) > 0.
+    }));
+
+    let sphere = gen::sphere(1., 16, 8);
+    let proxy = generate_occlusion_proxy(&sphere.verts);
+    let points: Vec<Point3<f32>> = sphere.verts.iter().map(|v| *v.pos()).collect();
+    assert!(proxy.verts.len() <= points.len());
+    check(&proxy, &points);
+
+    // a flat quad gets its (flat) bounding box
+    let flat = vec![v(0., 0., 0.), v(1., 0., 0.), v(1., 1., 0.), v(0., 1., 0.)];
+    let proxy = generate_occlusion_proxy(&flat);
+    assert_eq!(proxy.verts.len(), 8);
+    assert_eq!(check(&proxy, &[]), 12);
+    assert!(generate_occlusion_proxy(&[]).verts.is_empty());
+
+    // broken vertices are skipped
+    let mut broken = verts.clone();
+    broken.push(v(::std::f32::NAN, 0., 0.));
+    broken.push(v(0., ::std::f32::INFINITY, 0.));
+    let proxy = generate_occlusion_proxy(&broken);
+    assert_eq!(proxy.verts.len(), 10);
+    assert!(proxy.verts.iter().all(|v| v.pos.iter().all(|c| c.is_finite())));
+}
@@ -1,12 +1,35 @@
 pub use gfx::Primitive;
-use gfx::{Resources, Slice, traits, pso};
+use gfx::{Resources, Slice, Encoder, CommandBuffer, traits, pso};
 use gfx::format::Format;
 use gfx::traits::FactoryExt;
 use gfx::handle::Buffer;
 use nalgebra::{self as na, Point3, Point2, Vector3};
-use ::NativeRepr;
+use ::{NativeRepr, Error};
 use std::f32::EPSILON;
 
+/// Procedurally generated meshes
+pub mod gen;
+/// Compact vertex formats
+pub mod quantize;
+
+mod bounds;
+pub use self::bounds::Aabb;
+
+mod bent;
+pub use self::bent::{bake_bent_normals, bake_bent_occlusion, bent_normal_image, bake_multibounce_ao, bake_gtao};
+
+mod bvh;
+pub use self::bvh::{Bvh, BvhHit};
+
+mod hull;
+pub use self::hull::generate_occlusion_proxy;
+
+mod spline;
+pub use self::spline::{Path, ArcLength, CatmullRom, Bezier, ARC_SAMPLES_PER_SEGMENT};
+
+mod winding;
+pub use self::winding::{validate_winding, flip_winding, flip_normals};
+
 gfx_defines!{
     /// A vertex that includes pos only.
     vertex Vert {
@@ -47,10 +70,25 @@ gfx_defines!{
         bitan: [f32; 3] = "a_bitan",
         tex: [f32; 2] = "a_tex",
     }
+
+    /// A compressed vertex with a 16-bit fixed point position (see `quantize`) and
+    /// octahedral encoded normal and tangent. The tangent's z holds the bitangent sign.
+    vertex VertQuantized {
+        pos: [i16; 4] = "a_pos",
+        norm: [i8; 4] = "a_norm",
+        tan: [i8; 4] = "a_tan",
+        tex: [f32; 2] = "a_tex",
+    }
 }
 
+/// A type that can be stored in a vertex buffer, including compressed vertices whose
+/// positions are not directly readable.
+pub trait VertexData: traits::Pod + pso::buffer::Structure<Format> {}
+
+impl<T: traits::Pod + pso::buffer::Structure<Format>> VertexData for T {}
+
 /// A type that can be used as a vertex.
-pub trait Vertex: traits::Pod + pso::buffer::Structure<Format> {
+pub trait Vertex: VertexData {
     /// Get the vertex's position
     fn pos(&self) -> &Point3<f32>;
     /// Change the vertex's position
@@ -265,32 +303,45 @@ pub struct MeshSource<V, M> {
 /// of this object is negligible, since  (unlike `MeshSource`) it is a cloneable 
 /// reference to GPU allocated resources.
 #[derive(Clone)]
-pub struct Mesh<R: Resources, T: Vertex, M> {
+pub struct Mesh<R: Resources, T: VertexData, M> {
     /// Reference to slice object (index buffer or range)
     pub slice: Slice<R>,
     /// Reference to VBO
     pub buf: Buffer<R, T>,
     /// Primitive type
     pub prim: Primitive,
+    /// Bounding box of the vertices in model space
+    pub bounds: Aabb,
     /// Material/texture data
     pub mat: M,
 }
 
-impl<R: Resources, T: Vertex, M> Mesh<R, T, M> {
+impl<R: Resources, T: VertexData, M> Mesh<R, T, M> {
     /// Set the material of this mesh (usually just textures)
     pub fn with_material<N>(self, mat: N) -> Mesh<R, T, N> {
         Mesh {
             slice: self.slice,
             buf: self.buf,
             prim: self.prim,
+            bounds: self.bounds,
             mat: mat,
         }
     }
+
+    /// Overwrite vertices from `offset` on, for meshes made with `upload_dynamic`. The
+    /// bounds are left alone.
+    pub fn update_verts<C: CommandBuffer<R>>(&self, enc: &mut Encoder<R, C>, verts: &[T], offset: usize)
+        -> Result<(), Error>
+    {
+        enc.update_buffer(&self.buf, verts, offset)?;
+        Ok(())
+    }
 }
 
-impl<T: Vertex, M> MeshSource<T, M> {
-    /// Upload this mesh to the GPU.
-    pub fn upload<R: Resources, F: FactoryExt<R>>(self, f: &mut F) -> Mesh<R, T, M> {
+impl<T: VertexData, M> MeshSource<T, M> {
+    /// Upload this mesh to the GPU with the given bounding box, for vertices that
+    /// don't store their positions directly.
+    pub fn upload_with_bounds<R: Resources, F: FactoryExt<R>>(self, f: &mut F, bounds: Aabb) -> Mesh<R, T, M> {
         use self::Indexing::*;
 
         let (buf, slice) = match self.inds {
@@ -314,9 +365,58 @@ impl<T: Vertex, M> MeshSource<T, M> {
             buf: buf,
             slice: slice,
             prim: self.prim,
+            bounds: bounds,
             mat: self.mat,
         }
     }
+}
+
+impl<T: Vertex, M> MeshSource<T, M> {
+    /// Upload this mesh to the GPU.
+    pub fn upload<R: Resources, F: FactoryExt<R>>(self, f: &mut F) -> Mesh<R, T, M> {
+        let bounds = self.bounds();
+        self.upload_with_bounds(f, bounds)
+    }
+
+    /// Upload this mesh to the GPU with vertices that can be changed afterwards with
+    /// `Mesh::update_verts`. The vertices are written by `enc`, so they are there for
+    /// draws recorded after this.
+    pub fn upload_dynamic<R, F, C>(self, f: &mut F, enc: &mut Encoder<R, C>) -> Result<Mesh<R, T, M>, Error>
+        where R: Resources, F: FactoryExt<R>, C: CommandBuffer<R>
+    {
+        use gfx::buffer::Role;
+        use gfx::memory::{Bind, Usage};
+        use self::Indexing::*;
+
+        let bounds = self.bounds();
+        let buf = f.create_buffer(self.verts.len().max(1), Role::Vertex, Usage::Dynamic, Bind::empty())?;
+        enc.update_buffer(&buf, &self.verts, 0)?;
+        let mut slice = Slice::new_match_vertex_buffer(&buf);
+        slice.end = self.verts.len() as u32;
+        match self.inds {
+            All => (),
+            Range(a, b) => {
+                slice.start = a;
+                slice.end = b;
+            },
+            Inds(ref i) => {
+                slice.end = i.len() as u32;
+                slice.buffer = f.create_index_buffer(&i[..]);
+            },
+        }
+        Ok(Mesh {
+            buf: buf,
+            slice: slice,
+            prim: self.prim,
+            bounds: bounds,
+            mat: self.mat,
+        })
+    }
+
+    /// Compute the bounding box of the vertices
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.verts.iter().map(|v| v.pos()))
+    }
 
     /// Set the material of this mesh (usually just textures)
     pub fn with_material<N>(self, mat: N) -> MeshSource<T, N> {
@@ -327,6 +427,125 @@ impl<T: Vertex, M> MeshSource<T, M> {
             mat: mat,
         }
     }
+
+    /// Replace the material of this mesh with one built from the current material
+    pub fn map_material<N, F: FnOnce(M) -> N>(self, f: F) -> MeshSource<T, N> {
+        MeshSource {
+            verts: self.verts,
+            inds: self.inds,
+            prim: self.prim,
+            mat: f(self.mat),
+        }
+    }
+}
+
+/// One material group of a `MultiMeshSource`
+#[derive(Clone)]
+pub struct MeshGroup<M> {
+    /// The first index (or vertex, if not indexed) of the group
+    pub start: u32,
+    /// One past the last index (or vertex, if not indexed) of the group
+    pub end: u32,
+    /// Material/texture data
+    pub mat: M,
+}
+
+/// Mesh storage where several ranges of the same vertices are drawn with different
+/// materials. This is uploaded as a single vertex buffer, so it is preferable to
+/// many separate meshes when loading files that assign materials per face group.
+#[derive(Clone)]
+pub struct MultiMeshSource<V, M> {
+    /// Vertices
+    pub verts: Vec<V>,
+    /// Indexing scheme shared by all groups
+    pub inds: Indexing,
+    /// Primitive type
+    pub prim: Primitive,
+    /// Ranges of indices and their materials
+    pub groups: Vec<MeshGroup<M>>,
+}
+
+/// A range of a `MultiMesh` drawn with a single material
+#[derive(Clone)]
+pub struct SubMesh<R: Resources, M> {
+    /// Reference to slice object (index buffer or range)
+    pub slice: Slice<R>,
+    /// Material/texture data
+    pub mat: M,
+}
+
+/// A GPU mesh with several material groups sharing one vertex buffer
+#[derive(Clone)]
+pub struct MultiMesh<R: Resources, T: VertexData, M> {
+    /// Reference to VBO
+    pub buf: Buffer<R, T>,
+    /// Primitive type
+    pub prim: Primitive,
+    /// Bounding box of the vertices in model space
+    pub bounds: Aabb,
+    /// Material groups
+    pub groups: Vec<SubMesh<R, M>>,
+}
+
+impl<T: Vertex, M> MultiMeshSource<T, M> {
+    /// Upload this mesh to the GPU.
+    pub fn upload<R: Resources, F: FactoryExt<R>>(self, f: &mut F) -> MultiMesh<R, T, M> {
+        let base = MeshSource {
+            verts: self.verts,
+            inds: self.inds,
+            prim: self.prim,
+            mat: (),
+        }.upload(f);
+        let groups = self.groups.into_iter().map(|g| {
+            let mut slice = base.slice.clone();
+            slice.end = slice.start + g.end;
+            slice.start += g.start;
+            SubMesh {
+                slice: slice,
+                mat: g.mat,
+            }
+        }).collect();
+        MultiMesh {
+            buf: base.buf,
+            prim: base.prim,
+            bounds: base.bounds,
+            groups: groups,
+        }
+    }
+
+    /// Replace the material of every group
+    pub fn map_materials<N, F: FnMut(M) -> N>(self, mut f: F) -> MultiMeshSource<T, N> {
+        MultiMeshSource {
+            verts: self.verts,
+            inds: self.inds,
+            prim: self.prim,
+            groups: self.groups.into_iter().map(|g| MeshGroup {
+                start: g.start,
+                end: g.end,
+                mat: f(g.mat),
+            }).collect(),
+        }
+    }
+}
+
+impl<V, M> MultiMeshSource<V, M>
+    where V: WithTan + HasTex, V::With: HasTex
+{
+    /// Computes tangents and bitangents across all groups, see `MeshSource::compute_tan`.
+    pub fn compute_tan(self) -> MultiMeshSource<V::With, M> {
+        let whole = MeshSource {
+            verts: self.verts,
+            inds: self.inds,
+            prim: self.prim,
+            mat: (),
+        }.compute_tan();
+        MultiMeshSource {
+            verts: whole.verts,
+            inds: whole.inds,
+            prim: whole.prim,
+            groups: self.groups,
+        }
+    }
 }
 
 impl<V: WithNorm, M> MeshSource<V, M> {
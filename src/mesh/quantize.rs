@@ -0,0 +1,154 @@
+use gfx::Resources;
+use gfx::traits::FactoryExt;
+use nalgebra::{Point3, Vector3};
+
+use super::{Aabb, Mesh, MeshSource, VertNTT, VertQuantized};
+
+/// The largest magnitude of a quantized position component
+const POS_RANGE: f32 = 32767.;
+/// The largest magnitude of a quantized direction component
+const DIR_RANGE: f32 = 127.;
+
+/// How to turn quantized positions back into model space, as `scale * pos + bias`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QuantizationParams {
+    pub scale: [f32; 3],
+    pub bias: [f32; 3],
+}
+
+impl QuantizationParams {
+    /// The parameters that map the full 16-bit range onto the given box
+    pub fn for_bounds(bounds: &Aabb) -> QuantizationParams {
+        let center = bounds.center();
+        let half = bounds.extents() / 2.;
+        QuantizationParams {
+            scale: [half.x / POS_RANGE, half.y / POS_RANGE, half.z / POS_RANGE],
+            bias: [center.x, center.y, center.z],
+        }
+    }
+
+    /// Compress a position within the bounds these parameters were made for
+    pub fn quantize(&self, p: &Point3<f32>) -> [i16; 4] {
+        let mut q = [0, 0, 0, 1];
+        for i in 0..3 {
+            if self.scale[i] > 0. {
+                let v = ((p[i] - self.bias[i]) / self.scale[i]).round();
+                q[i] = v.max(-POS_RANGE).min(POS_RANGE) as i16;
+            }
+        }
+        q
+    }
+
+    /// Recover a position, as the vertex shader does
+    pub fn dequantize(&self, q: [i16; 4]) -> Point3<f32> {
+        Point3::new(
+            self.scale[0] * q[0] as f32 + self.bias[0],
+            self.scale[1] * q[1] as f32 + self.bias[1],
+            self.scale[2] * q[2] as f32 + self.bias[2],
+        )
+    }
+}
+
+fn sign_not_zero(v: f32) -> f32 {
+    if v >= 0. { 1. } else { -1. }
+}
+
+/// Map a unit vector onto an octahedron unfolded into the square from -1 to 1
+pub fn oct_project(v: &Vector3<f32>) -> (f32, f32) {
+    let n = v / (v.x.abs() + v.y.abs() + v.z.abs());
+    if n.z >= 0. {
+        (n.x, n.y)
+    } else {
+        // fold the lower half over the diagonals
+        ((1. - n.y.abs()) * sign_not_zero(n.x), (1. - n.x.abs()) * sign_not_zero(n.y))
+    }
+}
+
+/// Recover a unit vector from a point made by `oct_project`
+pub fn oct_unproject(x: f32, y: f32) -> Vector3<f32> {
+    let z = 1. - x.abs() - y.abs();
+    let (x, y) = if z < 0. {
+        ((1. - y.abs()) * sign_not_zero(x), (1. - x.abs()) * sign_not_zero(y))
+    } else {
+        (x, y)
+    };
+    Vector3::new(x, y, z).normalize()
+}
+
+/// Encode a unit vector as a point on an octahedron unfolded onto a square
+pub fn oct_encode(v: &Vector3<f32>) -> [i8; 2] {
+    let (x, y) = oct_project(v);
+    [(x * DIR_RANGE).round() as i8, (y * DIR_RANGE).round() as i8]
+}
+
+/// Decode a unit vector encoded with `oct_encode`, as the vertex shader does
+pub fn oct_decode(e: [i8; 2]) -> Vector3<f32> {
+    oct_unproject(e[0] as f32 / DIR_RANGE, e[1] as f32 / DIR_RANGE)
+}
+
+/// Compress the positions, normals and tangents of vertices lying within `aabb`. The
+/// bitangent is not stored, only which side of the normal and tangent it lies on.
+pub fn quantize_positions(verts: &[VertNTT], aabb: &Aabb) -> (Vec<VertQuantized>, QuantizationParams) {
+    let params = QuantizationParams::for_bounds(aabb);
+    let out = verts.iter().map(|v| {
+        let norm = Vector3::from_row_slice(&v.norm);
+        let tan = Vector3::from_row_slice(&v.tan);
+        let bitan = Vector3::from_row_slice(&v.bitan);
+        let n = oct_encode(&norm);
+        let t = oct_encode(&tan);
+        let sign = if bitan.dot(&norm.cross(&tan)) >= 0. { 127 } else { -127 };
+        VertQuantized {
+            pos: params.quantize(&Point3::from_coordinates(Vector3::from_row_slice(&v.pos))),
+            norm: [n[0], n[1], 0, 0],
+            tan: [t[0], t[1], sign, 0],
+            tex: v.tex,
+        }
+    }).collect();
+    (out, params)
+}
+
+/// Quantize a mesh and upload it, returning the parameters needed to draw it
+pub fn quantize_mesh<R, F, M>(f: &mut F, source: MeshSource<VertNTT, M>)
+    -> (Mesh<R, VertQuantized, M>, QuantizationParams)
+    where R: Resources, F: FactoryExt<R>
+{
+    let bounds = source.bounds();
+    let (verts, params) = quantize_positions(&source.verts, &bounds);
+    let mesh = MeshSource {
+        verts: verts,
+        inds: source.inds,
+        prim: source.prim,
+        mat: source.mat,
+    }.upload_with_bounds(f, bounds);
+    (mesh, params)
+}
+
+#[test]
+fn quantize_round_trip() {
+    let source = super::gen::capsule(0.5, 2., 12, 4).unwrap();
+    let bounds = source.bounds();
+    let (verts, params) = quantize_positions(&source.verts, &bounds);
+    assert_eq!(verts.len(), source.verts.len());
+    // half a step of the largest axis
+    let tolerance = 1.5 / POS_RANGE;
+    for (q, v) in verts.iter().zip(&source.verts) {
+        assert_eq!(q.pos[3], 1);
+        let p = params.dequantize(q.pos);
+        assert!((p.coords - Vector3::from_row_slice(&v.pos)).norm() < tolerance, "position error");
+
+        let n = oct_decode([q.norm[0], q.norm[1]]);
+        assert!(n.dot(&Vector3::from_row_slice(&v.norm)) > 0.9995, "normal error");
+        let t = oct_decode([q.tan[0], q.tan[1]]);
+        assert!(t.dot(&Vector3::from_row_slice(&v.tan)) > 0.9995, "tangent error");
+        let bitan = n.cross(&t) * q.tan[2] as f32 / DIR_RANGE;
+        assert!(bitan.dot(&Vector3::from_row_slice(&v.bitan)) > 0.99, "bitangent side");
+    }
+
+    // flat boxes quantize without dividing by zero
+    let flat = Aabb::from_points(&[Point3::new(0., 1., 0.), Point3::new(2., 1., 0.)]);
+    let params = QuantizationParams::for_bounds(&flat);
+    assert_eq!(params.quantize(&Point3::new(2., 1., 0.)), [32767, 0, 0, 1]);
+    assert!(relative_eq!(params.dequantize([32767, 0, 0, 1]), Point3::new(2., 1., 0.)));
+    // directions on the lower half fold back correctly
+    assert!(oct_decode(oct_encode(&-Vector3::z())).dot(&-Vector3::z()) > 0.9999);
+}
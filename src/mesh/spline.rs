@@ -0,0 +1,294 @@
+use nalgebra::{Point3, Vector3};
+
+use ::{Error, FlightError};
+
+/// The number of pieces each curve segment is split into when measuring its length
+pub const ARC_SAMPLES_PER_SEGMENT: usize = 32;
+
+/// A smooth curve through space, such as a cable or rail, that things can be placed along
+pub trait Path {
+    /// The position at curve parameter `u` from 0 to 1. Equal steps of `u` aren't equal
+    /// distances, see `sample` for that.
+    fn position(&self, u: f32) -> Point3<f32>;
+
+    /// The derivative of `position` at `u`, zero where the curve stops
+    fn derivative(&self, u: f32) -> Vector3<f32>;
+
+    /// The arc length table of the curve
+    fn arc(&self) -> &ArcLength;
+
+    /// The length of the curve
+    fn length(&self) -> f32 {
+        self.arc().total()
+    }
+
+    /// The position and unit tangent at `t` of the way along the curve by distance, for
+    /// moving things along it at an even speed
+    fn sample(&self, t: f32) -> (Point3<f32>, Vector3<f32>) {
+        let u = self.arc().param(t);
+        (self.position(u), self.tangent(u))
+    }
+
+    /// The unit direction of the curve at `u`. Where the curve stops (such as at repeated
+    /// points) the direction comes from nearby, then from the whole curve, and finally -Z
+    /// for a curve that doesn't go anywhere.
+    fn tangent(&self, u: f32) -> Vector3<f32> {
+        let d = self.derivative(u);
+        if d.norm_squared() > 1e-12 {
+            return d.normalize();
+        }
+        let h = 1e-3;
+        let chord = self.position((u + h).min(1.)) - self.position((u - h).max(0.));
+        if chord.norm_squared() > 1e-12 {
+            return chord.normalize();
+        }
+        let whole = self.position(1.) - self.position(0.);
+        if whole.norm_squared() > 1e-12 {
+            return whole.normalize();
+        }
+        -Vector3::z()
+    }
+}
+
+/// Distances along a curve at evenly spaced curve parameters, for converting between the
+/// two
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArcLength {
+    lengths: Vec<f32>,
+}
+
+impl ArcLength {
+    /// Measure a curve given by `position` with `samples` straight pieces
+    pub fn measure<F: Fn(f32) -> Point3<f32>>(position: F, samples: usize) -> ArcLength {
+        let samples = samples.max(1);
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut total = 0.;
+        let mut last = position(0.);
+        lengths.push(0.);
+        for i in 1..(samples + 1) {
+            let p = position(i as f32 / samples as f32);
+            total += (p - last).norm();
+            lengths.push(total);
+            last = p;
+        }
+        ArcLength {
+            lengths: lengths,
+        }
+    }
+
+    /// The length of the curve
+    pub fn total(&self) -> f32 {
+        *self.lengths.last().unwrap_or(&0.)
+    }
+
+    /// The curve parameter `t` of the way along the curve by distance. NaN is taken as the
+    /// start of the curve.
+    pub fn param(&self, t: f32) -> f32 {
+        let t = if t.is_nan() { 0. } else { t.max(0.).min(1.) };
+        let total = self.total();
+        if !(total > 0. && total.is_finite()) {
+            return t;
+        }
+        let target = t * total;
+        // the first sample at or past the target distance
+        let i = match self.lengths.binary_search_by(|l| l.partial_cmp(&target).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i,
+        }.max(1).min(self.lengths.len() - 1);
+        let (a, b) = (self.lengths[i - 1], self.lengths[i]);
+        let f = if b > a { (target - a) / (b - a) } else { 0. };
+        let samples = (self.lengths.len() - 1) as f32;
+        (i as f32 - 1. + f) / samples
+    }
+}
+
+/// Whether every coordinate of the points is a number and finite, so the arc length is too
+fn all_finite(points: &[Point3<f32>]) -> bool {
+    points.iter().all(|p| p.coords.iter().all(|c| c.is_finite()))
+}
+
+/// The segment of a curve with `segments` equal parameter ranges that `u` falls in, and
+/// how far along that segment it is
+fn segment(u: f32, segments: usize) -> (usize, f32) {
+    let s = u.max(0.).min(1.) * segments as f32;
+    let i = (s.floor() as usize).min(segments - 1);
+    (i, s - i as f32)
+}
+
+/// A curve passing through every one of its points, with the tangent at each point
+/// parallel to the line between its neighbours
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatmullRom {
+    points: Vec<Point3<f32>>,
+    arc: ArcLength,
+}
+
+impl CatmullRom {
+    /// Create a curve through `points`, which must not be empty. Repeated points are
+    /// merged, since the curve would turn back on itself at them.
+    pub fn new(mut points: Vec<Point3<f32>>) -> Result<CatmullRom, Error> {
+        ensure!(!points.is_empty(), FlightError::InvalidShape { reason: "a path needs at least one point" });
+        ensure!(all_finite(&points), FlightError::InvalidShape { reason: "path points must be finite" });
+        points.dedup_by(|a, b| (*a - *b).norm_squared() < 1e-12);
+        let mut c = CatmullRom {
+            points: points,
+            arc: ArcLength { lengths: vec![] },
+        };
+        let samples = ARC_SAMPLES_PER_SEGMENT * c.segments().max(1);
+        let arc = ArcLength::measure(|u| c.position(u), samples);
+        c.arc = arc;
+        Ok(c)
+    }
+
+    /// The points the curve passes through, without repeats
+    pub fn points(&self) -> &[Point3<f32>] {
+        &self.points
+    }
+
+    fn segments(&self) -> usize {
+        self.points.len() - 1
+    }
+
+    /// The four points shaping the segment containing `u`, with the ends repeated, and how
+    /// far along the segment `u` is
+    fn controls(&self, u: f32) -> ([Vector3<f32>; 4], f32) {
+        let (i, f) = segment(u, self.segments());
+        let last = self.points.len() - 1;
+        let p = |j: isize| self.points[(j.max(0) as usize).min(last)].coords;
+        let i = i as isize;
+        ([p(i - 1), p(i), p(i + 1), p(i + 2)], f)
+    }
+}
+
+impl Path for CatmullRom {
+    fn position(&self, u: f32) -> Point3<f32> {
+        if self.points.len() == 1 {
+            return self.points[0];
+        }
+        let ([p0, p1, p2, p3], f) = self.controls(u);
+        let (f2, f3) = (f * f, f * f * f);
+        Point3::from_coordinates((p1 * 2.
+            + (p2 - p0) * f
+            + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * f2
+            + (p1 * 3. - p0 - p2 * 3. + p3) * f3) * 0.5)
+    }
+
+    fn derivative(&self, u: f32) -> Vector3<f32> {
+        if self.points.len() == 1 {
+            return Vector3::zeros();
+        }
+        let ([p0, p1, p2, p3], f) = self.controls(u);
+        let d = ((p2 - p0)
+            + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * (2. * f)
+            + (p1 * 3. - p0 - p2 * 3. + p3) * (3. * f * f)) * 0.5;
+        d * self.segments() as f32
+    }
+
+    fn arc(&self) -> &ArcLength {
+        &self.arc
+    }
+}
+
+/// A chain of cubic Bézier curves. The points are the start, then two control points and
+/// an end point for each curve, with every curve starting where the last one ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bezier {
+    points: Vec<Point3<f32>>,
+    arc: ArcLength,
+}
+
+impl Bezier {
+    /// Create a chain of curves from `3 * n + 1` points, for `n` of at least one
+    pub fn new(points: Vec<Point3<f32>>) -> Result<Bezier, Error> {
+        ensure!(points.len() >= 4 && points.len() % 3 == 1,
+            FlightError::InvalidShape { reason: "a Bézier path needs three points per curve after the first" });
+        ensure!(all_finite(&points), FlightError::InvalidShape { reason: "path points must be finite" });
+        let mut b = Bezier {
+            points: points,
+            arc: ArcLength { lengths: vec![] },
+        };
+        let samples = ARC_SAMPLES_PER_SEGMENT * b.segments();
+        let arc = ArcLength::measure(|u| b.position(u), samples);
+        b.arc = arc;
+        Ok(b)
+    }
+
+    /// The start, control and end points of the curves
+    pub fn points(&self) -> &[Point3<f32>] {
+        &self.points
+    }
+
+    fn segments(&self) -> usize {
+        self.points.len() / 3
+    }
+
+    fn controls(&self, u: f32) -> ([Vector3<f32>; 4], f32) {
+        let (i, f) = segment(u, self.segments());
+        let p = |j: usize| self.points[3 * i + j].coords;
+        ([p(0), p(1), p(2), p(3)], f)
+    }
+}
+
+impl Path for Bezier {
+    fn position(&self, u: f32) -> Point3<f32> {
+        let ([p0, p1, p2, p3], f) = self.controls(u);
+        let g = 1. - f;
+        Point3::from_coordinates(p0 * (g * g * g) + p1 * (3. * g * g * f) + p2 * (3. * g * f * f) + p3 * (f * f * f))
+    }
+
+    fn derivative(&self, u: f32) -> Vector3<f32> {
+        let ([p0, p1, p2, p3], f) = self.controls(u);
+        let g = 1. - f;
+        let d = (p1 - p0) * (3. * g * g) + (p2 - p1) * (6. * g * f) + (p3 - p2) * (3. * f * f);
+        d * self.segments() as f32
+    }
+
+    fn arc(&self) -> &ArcLength {
+        &self.arc
+    }
+}
+
+#[test]
+fn spline_sampling() {
+    let line = CatmullRom::new(vec![
+        Point3::new(0., 0., 0.),
+        Point3::new(1., 0., 0.),
+        Point3::new(1., 0., 0.),
+        Point3::new(4., 0., 0.),
+    ]).unwrap();
+    assert!(relative_eq!(line.length(), 4., epsilon = 1e-3));
+    assert!(relative_eq!(line.position(0.), Point3::origin()));
+    assert!(relative_eq!(line.position(1.), Point3::new(4., 0., 0.)));
+    // even steps by distance, even though the points aren't evenly spaced
+    for i in 0..9 {
+        let t = i as f32 / 8.;
+        let (p, d) = line.sample(t);
+        assert!(relative_eq!(p.x, 4. * t, epsilon = 1e-2));
+        assert!(relative_eq!(d, Vector3::x(), epsilon = 1e-3));
+    }
+
+    let still = CatmullRom::new(vec![Point3::new(1., 2., 3.); 3]).unwrap();
+    assert_eq!(still.length(), 0.);
+    assert_eq!(still.sample(0.5), (Point3::new(1., 2., 3.), -Vector3::z()));
+    assert!(CatmullRom::new(vec![]).is_err());
+    assert!(CatmullRom::new(vec![Point3::origin(), Point3::new(::std::f32::NAN, 0., 0.)]).is_err());
+    // NaN stays at the start rather than reaching the arc length table
+    assert_eq!(line.sample(::std::f32::NAN).0, Point3::origin());
+    assert!(relative_eq!(line.sample(::std::f32::INFINITY).0, Point3::new(4., 0., 0.)));
+
+    // a quarter circle
+    let k = 0.5523;
+    let arc = Bezier::new(vec![
+        Point3::new(1., 0., 0.),
+        Point3::new(1., k, 0.),
+        Point3::new(k, 1., 0.),
+        Point3::new(0., 1., 0.),
+    ]).unwrap();
+    assert!(relative_eq!(arc.length(), ::std::f32::consts::PI / 2., epsilon = 1e-3));
+    let (p, d) = arc.sample(0.5);
+    let half = 0.5f32.sqrt();
+    assert!(relative_eq!(p, Point3::new(half, half, 0.), epsilon = 1e-3));
+    assert!(relative_eq!(d, Vector3::new(-half, half, 0.), epsilon = 1e-3));
+    assert!(Bezier::new(vec![Point3::origin(); 5]).is_err());
+    assert!(Bezier::new(vec![Point3::new(0., ::std::f32::INFINITY, 0.); 4]).is_err());
+}
@@ -0,0 +1,83 @@
+use nalgebra::Vector3;
+
+use super::{MeshSource, Indexing, Primitive, VertNTT, Vertex};
+
+/// The total area of a triangle list, with each triangle counted negative where its
+/// winding disagrees with its vertex normals. Counter-clockwise triangles (seen from the
+/// side the normals point to) count positive, so a negative total means most of the mesh
+/// is wound clockwise, as some exporters with the wrong axis settings produce.
+pub fn validate_winding(verts: &[VertNTT], inds: &[u32]) -> f64 {
+    let mut total = 0f64;
+    for t in inds.chunks(3) {
+        if t.len() < 3 || t.iter().any(|&i| i as usize >= verts.len()) { continue }
+        let (a, b, c) = (&verts[t[0] as usize], &verts[t[1] as usize], &verts[t[2] as usize]);
+        let (pa, pb, pc) = (a.pos(), b.pos(), c.pos());
+        let face = (pb - pa).cross(&(pc - pa));
+        let norm = Vector3::from(a.norm) + Vector3::from(b.norm) + Vector3::from(c.norm);
+        if let Some(n) = norm.try_normalize(1e-12) {
+            total += 0.5 * face.dot(&n) as f64;
+        }
+    }
+    total
+}
+
+/// Reverse the winding of every triangle of a triangle list by swapping its second and
+/// third corners
+pub fn flip_winding(inds: &mut Vec<u32>) {
+    for t in inds.chunks_mut(3) {
+        if t.len() == 3 {
+            t.swap(1, 2);
+        }
+    }
+}
+
+/// Point every normal the other way
+pub fn flip_normals(verts: &mut Vec<VertNTT>) {
+    for v in verts.iter_mut() {
+        v.norm = [-v.norm[0], -v.norm[1], -v.norm[2]];
+    }
+}
+
+impl<M> MeshSource<VertNTT, M> {
+    /// Reverse the winding of a triangle list if most of it disagrees with its normals
+    /// (see `validate_winding`), so back face culling removes the right side. Other
+    /// primitives are left alone.
+    pub fn with_corrected_winding(mut self) -> MeshSource<VertNTT, M> {
+        if self.prim != Primitive::TriangleList { return self }
+        let mut inds = match self.inds {
+            Indexing::Inds(ref i) => i.clone(),
+            Indexing::Range(a, b) => (a..b).collect(),
+            Indexing::All => (0..self.verts.len() as u32).collect(),
+        };
+        if validate_winding(&self.verts, &inds) < 0. {
+            flip_winding(&mut inds);
+            self.inds = Indexing::Inds(inds);
+        }
+        self
+    }
+}
+
+#[test]
+fn winding() {
+    use super::gen;
+
+    let quad = gen::quad(2., 1.);
+    let inds = match quad.inds { Indexing::Inds(ref i) => i.clone(), _ => unreachable!() };
+    assert!(relative_eq!(validate_winding(&quad.verts, &inds), 2.));
+    let mut flipped = inds.clone();
+    flip_winding(&mut flipped);
+    assert_eq!(flipped, vec![0, 2, 1, 0, 3, 2]);
+    assert!(relative_eq!(validate_winding(&quad.verts, &flipped), -2.));
+    // flipping the normals as well agrees again
+    let mut verts = quad.verts.clone();
+    flip_normals(&mut verts);
+    assert_eq!(verts[0].norm, [0., 0., -1.]);
+    assert!(relative_eq!(validate_winding(&verts, &flipped), 2.));
+
+    let fixed = gen::quad(2., 1.).with_corrected_winding();
+    assert!(match fixed.inds { Indexing::Inds(ref i) => i == &inds, _ => false });
+    let mut backwards = gen::quad(2., 1.);
+    backwards.inds = Indexing::Inds(flipped);
+    let fixed = backwards.with_corrected_winding();
+    assert!(match fixed.inds { Indexing::Inds(ref i) => i == &inds, _ => false });
+}
@@ -0,0 +1,315 @@
+use gfx::{Resources, Factory, CommandBuffer, Rect};
+use nalgebra::{Isometry3, Point3, Vector3, Vector4, Matrix4, Transform3, UnitQuaternion};
+use std::f32::consts::PI;
+
+use ::Error;
+use ::draw::{DrawParams, EyeParams, OffscreenTarget, Painter, ScreenStyle, UnlitMaterial};
+use ::mesh::{Mesh, VertNTT, gen};
+
+/// One of the two openings of a portal
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PortalSide {
+    A,
+    B,
+}
+
+impl PortalSide {
+    /// The opening at the other end
+    pub fn other(self) -> PortalSide {
+        match self {
+            PortalSide::A => PortalSide::B,
+            PortalSide::B => PortalSide::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            PortalSide::A => 0,
+            PortalSide::B => 1,
+        }
+    }
+}
+
+/// A pair of linked rectangular openings. Looking into the front of one opening shows the
+/// scene in front of the other, and walking through one comes out of the other. Each
+/// opening is centered on its pose, facing +Z with +Y up.
+#[derive(Copy, Clone, Debug)]
+pub struct Portal {
+    /// Placement of the first opening
+    pub a: Isometry3<f32>,
+    /// Placement of the second opening
+    pub b: Isometry3<f32>,
+    /// Width of both openings in world units
+    pub width: f32,
+    /// Height of both openings in world units
+    pub height: f32,
+    /// The number of times a view can pass through the portal, so portals seen through
+    /// portals are also drawn when this is above 1
+    pub depth: u32,
+}
+
+impl Portal {
+    /// Create a portal between two openings of the given size, drawn one level deep
+    pub fn new(a: Isometry3<f32>, b: Isometry3<f32>, width: f32, height: f32) -> Portal {
+        Portal {
+            a: a,
+            b: b,
+            width: width,
+            height: height,
+            depth: 1,
+        }
+    }
+
+    /// The placement of the given opening
+    pub fn pose(&self, side: PortalSide) -> Isometry3<f32> {
+        match side {
+            PortalSide::A => self.a,
+            PortalSide::B => self.b,
+        }
+    }
+
+    /// The transform taking things that enter the given opening to where they come out of
+    /// the other. Entering the front of one opening leaves out of the front of the other,
+    /// so the exit is turned half way around.
+    pub fn transform(&self, from: PortalSide) -> Isometry3<f32> {
+        let turn = Isometry3::from_parts(
+            ::nalgebra::Translation3::identity(),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), PI),
+        );
+        self.pose(from.other()) * turn * self.pose(from).inverse()
+    }
+
+    /// True if the point is on the front side of the plane of the given opening
+    pub fn in_front(&self, side: PortalSide, p: &Point3<f32>) -> bool {
+        (self.pose(side).inverse() * p).z > 0.
+    }
+
+    /// If moving from `from` to `to` (usually the head position on consecutive frames)
+    /// passes into the front of either opening, the transform to apply to the player so
+    /// they come out of the other one.
+    pub fn teleport(&self, from: &Point3<f32>, to: &Point3<f32>) -> Option<Isometry3<f32>> {
+        for &side in &[PortalSide::A, PortalSide::B] {
+            let inv = self.pose(side).inverse();
+            let (p, q) = (inv * from, inv * to);
+            if p.z <= 0. || q.z > 0. { continue }
+            let t = p.z / (p.z - q.z);
+            let hit = p + (q - p) * t;
+            if hit.x.abs() <= self.width / 2. && hit.y.abs() <= self.height / 2. {
+                return Some(self.transform(side));
+            }
+        }
+        None
+    }
+
+    /// The parameters of an eye looking into the given opening, moved to see the scene
+    /// beyond the other one. Everything between the moved eye and the other opening is
+    /// clipped away by tilting the near plane onto the opening. `None` if the eye is behind
+    /// the opening and so can't see through it.
+    pub fn eye_through(&self, side: PortalSide, eye: &EyeParams) -> Option<EyeParams> {
+        if !self.in_front(side, &eye.eye) {
+            return None;
+        }
+        let trans = self.transform(side);
+        let view = eye.view.to_homogeneous() * trans.inverse().to_homogeneous();
+        let exit = self.pose(side.other());
+        let normal = exit.rotation * Vector3::z();
+        let world_plane = Vector4::new(normal.x, normal.y, normal.z, -normal.dot(&exit.translation.vector));
+        let view_plane = view.try_inverse()?.transpose() * world_plane;
+        Some(EyeParams {
+            eye: trans * eye.eye,
+            view: Transform3::from_matrix_unchecked(view),
+            proj: Transform3::from_matrix_unchecked(oblique_clip(&eye.proj.to_homogeneous(), &view_plane)),
+            .. *eye
+        })
+    }
+}
+
+/// Replace the near plane of a projection with the given view space plane, keeping the
+/// side the plane faces (Lengyel, "Oblique View Frustum Depth Projection and Clipping").
+/// The projection is unchanged if the eye is on the kept side of the plane.
+pub fn oblique_clip(proj: &Matrix4<f32>, plane: &Vector4<f32>) -> Matrix4<f32> {
+    if plane.w >= 0. {
+        return *proj;
+    }
+    let inv = match proj.try_inverse() {
+        Some(i) => i,
+        None => return *proj,
+    };
+    // the corner of the frustum opposite the plane
+    let q = inv * Vector4::new(plane.x.signum(), plane.y.signum(), 1., 1.);
+    let c = plane * (2. / plane.dot(&q));
+    let mut m = *proj;
+    for i in 0..4 {
+        m[(2, i)] = c[i] - m[(3, i)];
+    }
+    m
+}
+
+/// Draws the views through a portal. Each view is rendered into an offscreen target the
+/// same size as the main target, with both eyes moved through the portal separately, and
+/// the openings are then drawn showing the pixels of those targets where they land on
+/// screen. Pixels outside an opening never show the view, so the openings act as masks.
+pub struct PortalView<R: Resources> {
+    /// The openings and how deep to draw
+    pub portal: Portal,
+    /// The color views are cleared to, seen where a view can't continue through a portal
+    pub background: [f32; 4],
+    quad: Mesh<R, VertNTT, ()>,
+    /// Targets for each level of recursion, for views into A then views into B
+    levels: Vec<[OffscreenTarget<R>; 2]>,
+}
+
+impl<R: Resources> PortalView<R> {
+    /// Create the targets for drawing the given portal. The size must match the target
+    /// the portal is seen in, and enough targets are made for `portal.depth` levels.
+    pub fn new<F: Factory<R>>(f: &mut F, portal: Portal, width: u16, height: u16)
+        -> Result<PortalView<R>, Error>
+    {
+        let mut levels = Vec::new();
+        for _ in 0..portal.depth.max(1) {
+            levels.push([
+                OffscreenTarget::new(f, width, height)?,
+                OffscreenTarget::new(f, width, height)?,
+            ]);
+        }
+        Ok(PortalView {
+            portal: portal,
+            background: [0., 0., 0., 1.],
+            quad: gen::quad(portal.width, portal.height).with_material(()).upload(f),
+            levels: levels,
+        })
+    }
+
+    /// Render the scene through both openings with the given closure, which should draw
+    /// everything except the portal itself. Deeper levels follow the view back into the
+    /// same opening, which covers openings facing each other. Call this before drawing
+    /// the openings with `draw`. The targets and eyes of `ctx` are restored afterwards.
+    pub fn render<C, D>(&self, ctx: &mut DrawParams<R, C>, painter: &Painter<R, ScreenStyle<R>>, mut scene: D)
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, D: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        let saved = (ctx.color.clone(), ctx.depth.clone());
+        let (left, right) = (ctx.left, ctx.right);
+        let mut result = Ok(());
+        for &side in &[PortalSide::A, PortalSide::B] {
+            result = self.render_side(ctx, painter, &mut scene, side, left, right);
+            if result.is_err() { break }
+        }
+        ctx.color = saved.0;
+        ctx.depth = saved.1;
+        result
+    }
+
+    fn render_side<C, D>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, ScreenStyle<R>>,
+        scene: &mut D,
+        side: PortalSide,
+        left: EyeParams,
+        right: EyeParams,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, D: FnMut(&mut DrawParams<R, C>) -> Result<(), Error>
+    {
+        let depth = (self.portal.depth.max(1) as usize).min(self.levels.len());
+        // the eyes at each level, or None once an eye can no longer see through
+        let mut eyes = Vec::with_capacity(depth);
+        let (mut l, mut r) = (Some(left), Some(right));
+        for _ in 0..depth {
+            l = l.and_then(|e| self.portal.eye_through(side, &e));
+            r = r.and_then(|e| self.portal.eye_through(side, &e));
+            eyes.push((l, r));
+        }
+
+        // innermost first, so each level can show the one behind it
+        for level in (0..depth).rev() {
+            let target = &self.levels[level][side.index()];
+            ctx.encoder.clear(&target.color, self.background);
+            ctx.encoder.clear_depth(&target.depth, ::draw::DEPTH_CONVENTION.far());
+            let (l, r) = eyes[level];
+            if l.is_none() && r.is_none() { continue }
+            let hidden = |e: EyeParams| EyeParams { clip: Rect { x: 0, y: 0, w: 0, h: 0 }, .. e };
+            ctx.color = target.color.clone();
+            ctx.depth = target.depth.clone();
+            ctx.push_eyes(l.unwrap_or_else(|| hidden(left)), r.unwrap_or_else(|| hidden(right)));
+            let drawn = scene(ctx).and_then(|_| if level + 1 < depth {
+                self.draw_opening(ctx, painter, side, &self.levels[level + 1][side.index()])
+            } else {
+                Ok(())
+            });
+            ctx.pop_camera();
+            drawn?;
+        }
+        Ok(())
+    }
+
+    fn draw_opening<C: CommandBuffer<R>>(
+        &self,
+        ctx: &mut DrawParams<R, C>,
+        painter: &Painter<R, ScreenStyle<R>>,
+        side: PortalSide,
+        target: &OffscreenTarget<R>,
+    )
+        -> Result<(), Error>
+    {
+        let mat = UnlitMaterial { color: target.texture.clone() };
+        let model = Transform3::from_matrix_unchecked(self.portal.pose(side).to_homogeneous());
+        painter.try_draw_with(ctx, model, &self.quad, &mat)
+    }
+
+    /// Draw both openings showing the views rendered by `render`, as part of the scene
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, painter: &Painter<R, ScreenStyle<R>>)
+        -> Result<(), Error>
+    {
+        for &side in &[PortalSide::A, PortalSide::B] {
+            self.draw_opening(ctx, painter, side, &self.levels[0][side.index()])?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn portal_transforms() {
+    use nalgebra::{Perspective3, Point2};
+
+    let a = Isometry3::new(Vector3::new(0., 1., 0.), Vector3::zeros());
+    let b = Isometry3::new(Vector3::new(10., 1., 0.), Vector3::y() * PI / 2.);
+    let portal = Portal::new(a, b, 1., 2.);
+
+    // a point one unit in front of A ends up one unit behind B
+    let p = portal.transform(PortalSide::A) * Point3::new(0., 1., 1.);
+    assert!(relative_eq!(p, Point3::new(9., 1., 0.), epsilon = 1e-5));
+    let back = portal.transform(PortalSide::B) * p;
+    assert!(relative_eq!(back, Point3::new(0., 1., 1.), epsilon = 1e-5));
+
+    // walking into A teleports, walking out of it or past it does not
+    let t = portal.teleport(&Point3::new(0., 1., 0.1), &Point3::new(0., 1., -0.1)).unwrap();
+    assert!(relative_eq!(t * Point3::new(0., 1., 1.), p, epsilon = 1e-5));
+    assert!(portal.teleport(&Point3::new(0., 1., -0.1), &Point3::new(0., 1., 0.1)).is_none());
+    assert!(portal.teleport(&Point3::new(2., 1., 0.1), &Point3::new(2., 1., -0.1)).is_none());
+
+    // each eye is moved separately and the near plane lies on B
+    let proj = Perspective3::new(1., PI / 2., 0.1, 100.).to_homogeneous();
+    let eye = |x: f32| {
+        let pos = Point3::new(x, 1., 2.);
+        EyeParams {
+            eye: pos,
+            view: Transform3::from_matrix_unchecked(Isometry3::new(-pos.coords, Vector3::zeros()).to_homogeneous()),
+            proj: Transform3::from_matrix_unchecked(proj),
+            clip_offset: 0.,
+            clip: Rect { x: 0, y: 0, w: 100, h: 100 },
+        }
+    };
+    let left = portal.eye_through(PortalSide::A, &eye(-0.03)).unwrap();
+    let right = portal.eye_through(PortalSide::A, &eye(0.03)).unwrap();
+    assert!(relative_eq!(left.eye, Point3::new(8., 1., -0.03), epsilon = 1e-5));
+    assert!(relative_eq!(right.eye, Point3::new(8., 1., 0.03), epsilon = 1e-5));
+    let on_b = (left.proj * left.view).to_homogeneous() * Point3::new(10., 1.5, 0.2).to_homogeneous();
+    assert!(relative_eq!(on_b.z / on_b.w, -1., epsilon = 1e-4));
+    let clip = Point2::new(on_b.x / on_b.w, on_b.y / on_b.w);
+    assert!(clip.x.abs() < 1. && clip.y.abs() < 1.);
+    // behind A there is nothing to see
+    let behind = EyeParams { eye: Point3::new(0., 1., -2.), .. eye(0.) };
+    assert!(portal.eye_through(PortalSide::A, &behind).is_none());
+}
@@ -0,0 +1,9 @@
+/// Record the rest of the enclosing block as a named puffin scope when the `profiling`
+/// feature is enabled, and do nothing otherwise. Scopes only show up in the profiler if
+/// the application calls `puffin::GlobalProfiler::lock().new_frame()` once per frame.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        ::puffin::profile_scope!($name);
+    };
+}
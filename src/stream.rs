@@ -0,0 +1,885 @@
+use gfx::{self, Resources, Factory, Encoder, CommandBuffer};
+use gfx::format::*;
+use gfx::handle::{Sampler, ShaderResourceView};
+use nalgebra::{Point3, Transform3};
+use image::{self, FilterType, RgbaImage};
+use image::imageops::resize;
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+
+use ::{Error, FlightError, Texture};
+use ::mesh::Aabb;
+
+/// The color space of a streamed texture
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamChannel {
+    /// Colors such as albedo
+    Srgb,
+    /// Data such as normals and material knobs
+    Unorm,
+}
+
+/// Which version of a streamed texture is on the GPU
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamTier {
+    /// Only the low resolution version is available
+    Low,
+    /// The full resolution version is being loaded
+    Loading,
+    /// The full resolution version is available
+    Full,
+}
+
+/// Identifies a texture added to a `TextureStreamer`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StreamId(usize);
+
+/// An image and its mipmaps, largest first
+struct MipChain {
+    width: u16,
+    height: u16,
+    levels: Vec<Vec<u8>>,
+}
+
+impl MipChain {
+    /// Build the mip chain of an image, first shrinking it to fit within `max_size`
+    fn new(mut img: RgbaImage, max_size: u32) -> MipChain {
+        let (w, h) = img.dimensions();
+        if w.max(h) > max_size {
+            let scale = max_size as f32 / w.max(h) as f32;
+            let (w, h) = (((w as f32 * scale) as u32).max(1), ((h as f32 * scale) as u32).max(1));
+            img = resize(&img, w, h, FilterType::Triangle);
+        }
+        let (width, height) = img.dimensions();
+        let mut levels = vec![];
+        let (mut w, mut h) = (width, height);
+        loop {
+            levels.push(if w == width { img.clone().into_raw() } else { resize(&img, w, h, FilterType::Triangle).into_raw() });
+            if w == 1 && h == 1 { break }
+            w = (w / 2).max(1);
+            h = (h / 2).max(1);
+        }
+        MipChain {
+            width: width as u16,
+            height: height as u16,
+            levels: levels,
+        }
+    }
+
+    fn upload<R, F>(&self, f: &mut F, channel: StreamChannel)
+        -> Result<(ShaderResourceView<R, [f32; 4]>, Sampler<R>), Error>
+        where R: Resources, F: Factory<R>
+    {
+        use gfx::texture::*;
+        let kind = Kind::D2(self.width, self.height, AaMode::Single);
+        let levels: Vec<&[u8]> = self.levels.iter().map(|l| &l[..]).collect();
+        let view = match channel {
+            StreamChannel::Srgb => f.create_texture_immutable_u8
+                ::<(R8_G8_B8_A8, Srgb)>(kind, Mipmap::Provided, &levels)?.1,
+            StreamChannel::Unorm => f.create_texture_immutable_u8
+                ::<(R8_G8_B8_A8, Unorm)>(kind, Mipmap::Provided, &levels)?.1,
+        };
+        let mut info = SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Tile);
+        // never sample levels that were not uploaded
+        info.lod_range = (Lod::from(0.), Lod::from((self.levels.len() - 1) as f32));
+        Ok((view, f.create_sampler(info)))
+    }
+}
+
+struct Streamed<R: Resources> {
+    path: PathBuf,
+    channel: StreamChannel,
+    full_bytes: usize,
+    low: (ShaderResourceView<R, [f32; 4]>, Sampler<R>),
+    full: Option<(ShaderResourceView<R, [f32; 4]>, Sampler<R>)>,
+    pending: bool,
+    wanted: bool,
+    coverage: f32,
+}
+
+/// Keeps large textures within a GPU memory budget. Every texture has a small version
+/// that is always resident; full resolution versions are loaded on a background thread
+/// for the textures covering the most of the view (as reported each frame) and dropped
+/// again when other textures need the memory.
+///
+/// Because materials hold texture handles, fetch the current handle with `texture` when
+/// building materials each frame, otherwise evicted textures stay alive.
+pub struct TextureStreamer<R: Resources> {
+    /// The number of bytes full resolution textures may use
+    pub budget: usize,
+    /// The largest width or height of the always-resident versions
+    pub low_size: u32,
+    textures: Vec<Streamed<R>>,
+    used: usize,
+    requests: Sender<(usize, PathBuf)>,
+    results: Receiver<(usize, Result<MipChain, Error>)>,
+}
+
+impl<R: Resources> TextureStreamer<R> {
+    /// Create a streamer with the given budget (in bytes) for full resolution textures
+    pub fn new(budget: usize) -> TextureStreamer<R> {
+        let (requests, incoming) = channel::<(usize, PathBuf)>();
+        let (outgoing, results) = channel();
+        thread::spawn(move || {
+            for (id, path) in incoming {
+                let chain = image::open(&path)
+                    .map(|img| MipChain::new(img.to_rgba(), u32::max_value()))
+                    .map_err(Error::from);
+                if outgoing.send((id, chain)).is_err() { break }
+            }
+        });
+        TextureStreamer {
+            budget: budget,
+            low_size: 128,
+            textures: Vec::new(),
+            used: 0,
+            requests: requests,
+            results: results,
+        }
+    }
+
+    /// Add a texture, loading the low resolution version immediately
+    pub fn add<F, P>(&mut self, f: &mut F, path: P, channel: StreamChannel) -> Result<StreamId, Error>
+        where F: Factory<R>, P: AsRef<Path>
+    {
+        let img = image::open(path.as_ref())?.to_rgba();
+        let (w, h) = img.dimensions();
+        // the size of the full chain is about 4/3 of the base level
+        let full_bytes = w as usize * h as usize * 4 * 4 / 3;
+        let low = MipChain::new(img, self.low_size).upload(f, channel)?;
+        self.textures.push(Streamed {
+            path: path.as_ref().to_owned(),
+            channel: channel,
+            full_bytes: full_bytes,
+            low: low,
+            full: None,
+            pending: false,
+            wanted: false,
+            coverage: 0.,
+        });
+        Ok(StreamId(self.textures.len() - 1))
+    }
+
+    /// Report that the given textures are used by a mesh with the given bounds, model
+    /// matrix, and viewer position this frame. The textures covering the most of the view
+    /// get full resolution first.
+    pub fn report(&mut self, ids: &[StreamId], bounds: &Aabb, model: &Transform3<f32>, eye: &Point3<f32>) {
+        let (center, radius) = bounds.transform(model).bounding_sphere();
+        let dist = (center - eye).norm().max(radius).max(0.001);
+        // approximate fraction of the view covered by the bounding sphere
+        let coverage = (radius / dist) * (radius / dist);
+        for &StreamId(i) in ids {
+            let t = &mut self.textures[i];
+            t.coverage = t.coverage.max(coverage);
+        }
+    }
+
+    /// Upload finished loads, evict textures that lost their place in the budget, and start
+    /// loading newly wanted ones. Call once per frame, after reporting coverage.
+    pub fn update<F: Factory<R>>(&mut self, f: &mut F) -> Result<(), Error> {
+        let candidates: Vec<_> = self.textures.iter()
+            .map(|t| {
+                // resident textures are favored slightly so that similar ones don't swap back and forth
+                let bias = if t.full.is_some() { 1.25 } else { 1. };
+                (t.coverage * bias, t.full_bytes)
+            })
+            .collect();
+        let wanted = select(&candidates, self.budget);
+
+        for (t, wanted) in self.textures.iter_mut().zip(wanted) {
+            t.wanted = wanted;
+            t.coverage = 0.;
+            if !wanted && t.full.take().is_some() {
+                self.used -= t.full_bytes;
+            }
+        }
+
+        let mut result = Ok(());
+        while let Ok((i, chain)) = self.results.try_recv() {
+            let t = &mut self.textures[i];
+            t.pending = false;
+            match chain {
+                Ok(chain) => if t.wanted && t.full.is_none() && self.used + t.full_bytes <= self.budget {
+                    t.full = Some(chain.upload(f, t.channel)?);
+                    self.used += t.full_bytes;
+                },
+                Err(e) => result = Err(e),
+            }
+        }
+
+        for (i, t) in self.textures.iter_mut().enumerate() {
+            if t.wanted && t.full.is_none() && !t.pending {
+                t.pending = self.requests.send((i, t.path.clone())).is_ok();
+            }
+        }
+        result
+    }
+
+    /// The best version of a texture currently on the GPU. The channel type must match
+    /// the `StreamChannel` the texture was added with.
+    pub fn texture<C>(&self, id: StreamId) -> Texture<R, (R8_G8_B8_A8, C)>
+        where (R8_G8_B8_A8, C): TextureFormat + Formatted<View = [f32; 4]>
+    {
+        let t = &self.textures[id.0];
+        let &(ref view, ref sampler) = t.full.as_ref().unwrap_or(&t.low);
+        Texture {
+            buffer: view.clone(),
+            sampler: sampler.clone(),
+        }
+    }
+
+    /// Which version of a texture is available
+    pub fn tier(&self, id: StreamId) -> StreamTier {
+        let t = &self.textures[id.0];
+        match (&t.full, t.pending) {
+            (&Some(_), _) => StreamTier::Full,
+            (&None, true) => StreamTier::Loading,
+            (&None, false) => StreamTier::Low,
+        }
+    }
+
+    /// The number of bytes used by full resolution textures
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+/// The number of smallest mip levels `MipmapStream::add` uploads before returning
+pub const STREAM_INITIAL_MIPS: u8 = 2;
+
+/// The number of mip levels down to 1x1 of an image with the given size
+fn mip_count(width: u32, height: u32) -> u8 {
+    (32 - width.max(height).max(1).leading_zeros()) as u8
+}
+
+/// The size of a mip level of an image with the given size
+fn mip_size(width: u32, height: u32, level: u8) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// Write one mip level of a texture
+fn upload_level<R, C>(enc: &mut Encoder<R, C>, texture: &gfx::handle::Texture<R, R8_G8_B8_A8>, level: u8, data: &[u8])
+    -> Result<(), Error>
+    where R: Resources, C: CommandBuffer<R>
+{
+    let info = texture.get_info().to_image_info(level);
+    // the data is laid out the same for every 8 bit channel type
+    enc.update_texture::<R8_G8_B8_A8, (R8_G8_B8_A8, Unorm)>(texture, None, info, gfx::memory::cast_slice(data))
+        .map_err(|e| FlightError::TextureUpdate { reason: format!("{:?}", e) }.into())
+}
+
+/// A texture whose larger mip levels are still loading
+struct Growing<R: Resources> {
+    texture: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    remaining: u8,
+}
+
+/// Loads large textures without a hitch by uploading their mip levels a few at a time.
+/// `add` uploads the smallest levels and returns the texture right away, while the larger
+/// levels are built on a background thread and uploaded by `poll` as they finish, smallest
+/// first. Until a level arrives it has undefined contents (black on most drivers), so
+/// surfaces seen up close can show that for a few frames.
+///
+/// Unlike `TextureStreamer`, the texture handle never changes, so materials can be built
+/// once.
+pub struct MipmapStream<R: Resources> {
+    /// The most bytes `poll` uploads in one call, apart from always uploading at least one
+    /// level
+    pub upload_budget: usize,
+    textures: Vec<Option<Growing<R>>>,
+    arrived: VecDeque<(usize, u8, Vec<u8>)>,
+    requests: Sender<(usize, RgbaImage, u8)>,
+    results: Receiver<(usize, u8, Vec<u8>)>,
+}
+
+impl<R: Resources> MipmapStream<R> {
+    /// Create a stream with its background thread
+    pub fn new() -> MipmapStream<R> {
+        let (requests, incoming) = channel::<(usize, RgbaImage, u8)>();
+        let (outgoing, results) = channel();
+        thread::spawn(move || {
+            for (id, img, loaded) in incoming {
+                let mut levels = MipChain::new(img, u32::max_value()).levels;
+                let missing = levels.len() - loaded as usize;
+                levels.truncate(missing);
+                for (level, data) in levels.into_iter().enumerate().rev() {
+                    if outgoing.send((id, level as u8, data)).is_err() { return }
+                }
+            }
+        });
+        MipmapStream {
+            upload_budget: 16 << 20,
+            textures: Vec::new(),
+            arrived: VecDeque::new(),
+            requests: requests,
+            results: results,
+        }
+    }
+
+    /// Open an image and return it as a texture with only its smallest
+    /// `STREAM_INITIAL_MIPS` levels filled in, queueing the rest
+    pub fn add<F, C, T, P>(&mut self, f: &mut F, enc: &mut Encoder<R, C>, path: P)
+        -> Result<Texture<R, (R8_G8_B8_A8, T)>, Error>
+        where
+            F: Factory<R>,
+            C: CommandBuffer<R>,
+            P: AsRef<Path>,
+            (R8_G8_B8_A8, T): TextureFormat + Formatted<Surface = R8_G8_B8_A8, View = [f32; 4]>,
+    {
+        use gfx::texture::*;
+        use gfx::memory::{Bind, Usage};
+        let img = image::open(path.as_ref())?.to_rgba();
+        let (width, height) = img.dimensions();
+        let levels = mip_count(width, height);
+        let kind = Kind::D2(width as u16, height as u16, AaMode::Single);
+        let channel = <(R8_G8_B8_A8, T) as Formatted>::get_format().1;
+        let texture = f.create_texture::<R8_G8_B8_A8>(kind, levels, Bind::SHADER_RESOURCE, Usage::Dynamic, Some(channel))?;
+        let view = f.view_texture_as_shader_resource::<(R8_G8_B8_A8, T)>(&texture, (0, levels - 1), Swizzle::new())?;
+
+        let loaded = STREAM_INITIAL_MIPS.min(levels);
+        for level in (levels - loaded)..levels {
+            let (w, h) = mip_size(width, height, level);
+            upload_level(enc, &texture, level, &resize(&img, w, h, FilterType::Triangle).into_raw())?;
+        }
+        if loaded < levels {
+            let id = self.textures.len();
+            self.textures.push(Some(Growing {
+                texture: texture,
+                remaining: levels - loaded,
+            }));
+            if self.requests.send((id, img, loaded)).is_err() {
+                warn!("the mipmap stream thread has stopped, {} will stay blurry", path.as_ref().display());
+            }
+        }
+        Ok(Texture {
+            buffer: view,
+            sampler: f.create_sampler(SamplerInfo::new(FilterMethod::Trilinear, WrapMode::Tile)),
+        })
+    }
+
+    /// Upload the mip levels that have finished loading, up to `upload_budget` bytes of
+    /// them. Call once per frame. Returns the number of levels uploaded.
+    pub fn poll<C: CommandBuffer<R>>(&mut self, enc: &mut Encoder<R, C>) -> Result<usize, Error> {
+        while let Ok(level) = self.results.try_recv() {
+            self.arrived.push_back(level);
+        }
+        let (mut bytes, mut uploaded) = (0, 0);
+        while let Some((id, level, data)) = self.arrived.pop_front() {
+            if uploaded > 0 && bytes + data.len() > self.upload_budget {
+                self.arrived.push_front((id, level, data));
+                break;
+            }
+            bytes += data.len();
+            uploaded += 1;
+            let done = match self.textures[id] {
+                Some(ref mut g) => {
+                    upload_level(enc, &g.texture, level, &data)?;
+                    g.remaining -= 1;
+                    g.remaining == 0
+                },
+                None => false,
+            };
+            if done {
+                self.textures[id] = None;
+            }
+        }
+        Ok(uploaded)
+    }
+
+    /// The number of mip levels not uploaded yet, over every texture
+    pub fn pending(&self) -> usize {
+        self.textures.iter()
+            .filter_map(|t| t.as_ref())
+            .map(|g| g.remaining as usize)
+            .sum()
+    }
+}
+
+impl<R: Resources> Default for MipmapStream<R> {
+    fn default() -> MipmapStream<R> {
+        MipmapStream::new()
+    }
+}
+
+/// The width and height in texels of a page of a `VirtualTexture`
+pub const PAGE_SIZE: u32 = 128;
+
+/// The texels around each page copied from its neighbours, so filtering doesn't bleed in
+/// from whatever is next to it in the atlas
+pub const PAGE_BORDER: u32 = 2;
+
+/// The width and height in texels of a page with its border, as `PageIo` provides it
+pub const PAGE_SLOT: u32 = PAGE_SIZE + 2 * PAGE_BORDER;
+
+/// A page of a `VirtualTexture`: its column and row (from the top left) in a mip level
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PageId {
+    pub x: u16,
+    pub y: u16,
+    pub mip: u8,
+}
+
+impl PageId {
+    /// The page covering this one in the next coarser mip level
+    pub fn parent(&self) -> PageId {
+        PageId { x: self.x / 2, y: self.y / 2, mip: self.mip + 1 }
+    }
+}
+
+/// Where the pages of a `VirtualTexture` come from, usually tiles on disk loaded on a
+/// background thread. Pages are `PAGE_SLOT` texels square in RGBA8, top row first,
+/// including their border.
+pub trait PageIo {
+    /// Start loading a page
+    fn request(&mut self, page: PageId);
+    /// A page that has finished loading, or `None` if none has
+    fn poll(&mut self) -> Option<(PageId, Vec<u8>)>;
+}
+
+/// Pages cut from an image in memory, for textures small enough to load whole (and tests)
+pub struct ImagePages {
+    chain: MipChain,
+    queue: VecDeque<PageId>,
+}
+
+impl ImagePages {
+    /// Resize the image to exactly fill a virtual texture of the given size in pages
+    pub fn new(img: RgbaImage, pages: (u16, u16)) -> ImagePages {
+        let (w, h) = (pages.0 as u32 * PAGE_SIZE, pages.1 as u32 * PAGE_SIZE);
+        let img = if img.dimensions() == (w, h) { img } else { resize(&img, w, h, FilterType::Triangle) };
+        ImagePages {
+            chain: MipChain::new(img, u32::max_value()),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Copy a page out of its mip level, clamping the border at the edges
+    fn cut(&self, page: PageId) -> Vec<u8> {
+        let level = (page.mip as usize).min(self.chain.levels.len() - 1);
+        let (w, h) = mip_size(self.chain.width as u32, self.chain.height as u32, level as u8);
+        let data = &self.chain.levels[level];
+        let mut out = Vec::with_capacity((PAGE_SLOT * PAGE_SLOT * 4) as usize);
+        for row in 0..PAGE_SLOT {
+            let y = (page.y as i64 * PAGE_SIZE as i64 + row as i64 - PAGE_BORDER as i64).max(0).min(h as i64 - 1);
+            for col in 0..PAGE_SLOT {
+                let x = (page.x as i64 * PAGE_SIZE as i64 + col as i64 - PAGE_BORDER as i64).max(0).min(w as i64 - 1);
+                let i = (y as usize * w as usize + x as usize) * 4;
+                out.extend_from_slice(&data[i..i + 4]);
+            }
+        }
+        out
+    }
+}
+
+impl PageIo for ImagePages {
+    fn request(&mut self, page: PageId) {
+        self.queue.push_back(page);
+    }
+
+    fn poll(&mut self) -> Option<(PageId, Vec<u8>)> {
+        self.queue.pop_front().map(|page| (page, self.cut(page)))
+    }
+}
+
+/// Which page is in each slot of the atlas and when it was last requested, along with the
+/// pages requested this frame and those still loading. This is the part of a
+/// `VirtualTexture` that doesn't touch the GPU.
+struct PageCache {
+    pages: (u16, u16),
+    levels: u8,
+    slots_wide: u16,
+    slots: Vec<Option<(PageId, u64)>>,
+    resident: FnvHashMap<PageId, usize>,
+    requested: FnvHashSet<PageId>,
+    pending: FnvHashSet<PageId>,
+    frame: u64,
+}
+
+impl PageCache {
+    fn new(pages: (u16, u16), slots_wide: u16) -> PageCache {
+        PageCache {
+            pages: pages,
+            levels: mip_count(pages.0 as u32, pages.1 as u32),
+            slots_wide: slots_wide,
+            slots: vec![None; slots_wide as usize * slots_wide as usize],
+            resident: FnvHashMap::default(),
+            requested: FnvHashSet::default(),
+            pending: FnvHashSet::default(),
+            frame: 0,
+        }
+    }
+
+    /// Mark the page at the given texture coordinates (+V up) and mip level as needed
+    fn request(&mut self, u: f32, v: f32, mip: u8) {
+        let mip = mip.min(self.levels - 1);
+        let (w, h) = self.level_size(mip);
+        let col = (u.max(0.).min(1.) * w as f32) as u16;
+        let row = ((1. - v.max(0.).min(1.)) * h as f32) as u16;
+        self.requested.insert(PageId { x: col.min(w - 1), y: row.min(h - 1), mip: mip });
+    }
+
+    /// Start a new frame, marking the requested pages that are resident as used in it and
+    /// asking `io` for the others unless they are already loading
+    fn begin_frame<P: PageIo>(&mut self, io: &mut P) {
+        self.frame += 1;
+        let top = self.top();
+        self.requested.insert(top);
+        let requested: Vec<PageId> = self.requested.drain().collect();
+        for page in requested {
+            if !self.touch(page, self.frame) && self.pending.insert(page) {
+                io.request(page);
+            }
+        }
+    }
+
+    /// Take a page that has finished loading, returning the slot to upload it to, or `None`
+    /// if it is already resident or every slot is in use this frame. In the latter case
+    /// the page waits to be requested again.
+    fn receive(&mut self, page: PageId, data: &[u8]) -> Result<Option<usize>, Error> {
+        self.pending.remove(&page);
+        ensure!(data.len() == (PAGE_SLOT * PAGE_SLOT * 4) as usize, FlightError::TextureSizeMismatch {
+            expected: (PAGE_SLOT * PAGE_SLOT * 4) as usize,
+            given: data.len(),
+        });
+        if self.resident.contains_key(&page) {
+            return Ok(None);
+        }
+        let frame = self.frame;
+        Ok(self.insert(page, frame))
+    }
+
+    /// The single page covering the whole texture, which is never evicted
+    fn top(&self) -> PageId {
+        PageId { x: 0, y: 0, mip: self.levels - 1 }
+    }
+
+    /// The number of pages across and down a mip level
+    fn level_size(&self, mip: u8) -> (u16, u16) {
+        ((self.pages.0 >> mip).max(1), (self.pages.1 >> mip).max(1))
+    }
+
+    /// The column and row of a slot in the atlas
+    fn slot_position(&self, slot: usize) -> (u16, u16) {
+        ((slot % self.slots_wide as usize) as u16, (slot / self.slots_wide as usize) as u16)
+    }
+
+    /// Mark a resident page as used in the given frame, returning false if it isn't resident
+    fn touch(&mut self, page: PageId, frame: u64) -> bool {
+        match self.resident.get(&page) {
+            Some(&slot) => {
+                self.slots[slot] = Some((page, frame));
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Find a slot for a page, evicting the least recently used page that wasn't used this
+    /// frame if there are no free slots. Returns `None` if every slot is in use.
+    fn insert(&mut self, page: PageId, frame: u64) -> Option<usize> {
+        let top = self.top();
+        let slot = match self.slots.iter().position(|s| s.is_none()) {
+            Some(free) => free,
+            None => {
+                let (slot, old) = self.slots.iter().enumerate()
+                    .filter_map(|(i, s)| s.map(|(p, used)| (i, p, used)))
+                    .filter(|&(_, p, used)| used < frame && p != top)
+                    .min_by_key(|&(_, _, used)| used)
+                    .map(|(i, p, _)| (i, p))?;
+                self.resident.remove(&old);
+                slot
+            },
+        };
+        self.slots[slot] = Some((page, frame));
+        self.resident.insert(page, slot);
+        Some(slot)
+    }
+
+    /// The page table of a mip level, row by row: the atlas slot of the finest resident page
+    /// covering each page, and the mip level of that page
+    fn entries(&self, mip: u8) -> Vec<[u8; 4]> {
+        let (w, h) = self.level_size(mip);
+        let mut out = Vec::with_capacity(w as usize * h as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let mut page = PageId { x: x, y: y, mip: mip };
+                let entry = loop {
+                    if let Some(&slot) = self.resident.get(&page) {
+                        let (sx, sy) = self.slot_position(slot);
+                        break [sx as u8, sy as u8, page.mip, 255];
+                    }
+                    if page.mip + 1 >= self.levels {
+                        // nothing is resident yet
+                        break [0, 0, page.mip, 0];
+                    }
+                    page = page.parent();
+                };
+                out.push(entry);
+            }
+        }
+        out
+    }
+}
+
+/// A texture far larger than fits in GPU memory, split into pages of `PAGE_SIZE` texels
+/// of which only the ones in use are kept. The pages live in slots of a physical atlas, and
+/// a page table with a mip level per mip level of the texture says which slot holds each
+/// page. Where a page isn't loaded, the table points at the finest loaded page covering it,
+/// so surfaces are blurry for a moment instead of missing. The single page of the coarsest
+/// level is always loaded.
+///
+/// Each frame, `request_page` the pages surfaces need (at the mip level they're seen at),
+/// then `tick` to load them, evicting the pages unused for longest. Draw with
+/// `draw::VirtualTextureStyle` and a `draw::VirtualMaterial`.
+pub struct VirtualTexture<R: Resources> {
+    /// The most pages `tick` uploads in one call
+    pub upload_budget: usize,
+    cache: PageCache,
+    dirty: bool,
+    atlas: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    table: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    atlas_view: ShaderResourceView<R, [f32; 4]>,
+    table_view: ShaderResourceView<R, [f32; 4]>,
+    linear: Sampler<R>,
+    nearest: Sampler<R>,
+}
+
+impl<R: Resources> VirtualTexture<R> {
+    /// Create a texture `pages` pages wide and high, each a power of two, with an atlas of
+    /// `slots` pages on each side (at most 255)
+    pub fn new<F: Factory<R>>(f: &mut F, pages: (u16, u16), slots: u16) -> Result<VirtualTexture<R>, Error> {
+        use gfx::texture::*;
+        use gfx::memory::{Bind, Usage};
+        ensure!(pages.0.is_power_of_two() && pages.1.is_power_of_two(),
+            FlightError::InvalidVirtualTexture { reason: "the page counts must be powers of two" });
+        ensure!(slots > 0 && slots <= 255,
+            FlightError::InvalidVirtualTexture { reason: "the atlas must be 1 to 255 slots wide" });
+        let cache = PageCache::new(pages, slots);
+        let side = slots * PAGE_SLOT as u16;
+        let atlas = f.create_texture::<R8_G8_B8_A8>(
+            Kind::D2(side, side, AaMode::Single),
+            1,
+            Bind::SHADER_RESOURCE,
+            Usage::Dynamic,
+            Some(ChannelType::Srgb),
+        )?;
+        let table = f.create_texture::<R8_G8_B8_A8>(
+            Kind::D2(pages.0, pages.1, AaMode::Single),
+            cache.levels,
+            Bind::SHADER_RESOURCE,
+            Usage::Dynamic,
+            Some(ChannelType::Unorm),
+        )?;
+        let atlas_view = f.view_texture_as_shader_resource::<(R8_G8_B8_A8, Srgb)>(&atlas, (0, 0), Swizzle::new())?;
+        let table_view = f.view_texture_as_shader_resource::<(R8_G8_B8_A8, Unorm)>(&table, (0, cache.levels - 1), Swizzle::new())?;
+        Ok(VirtualTexture {
+            upload_budget: 8,
+            cache: cache,
+            dirty: true,
+            atlas: atlas,
+            table: table,
+            atlas_view: atlas_view,
+            table_view: table_view,
+            linear: f.create_sampler(SamplerInfo::new(FilterMethod::Bilinear, WrapMode::Clamp)),
+            nearest: f.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp)),
+        })
+    }
+
+    /// The number of mip levels, down to a single page
+    pub fn levels(&self) -> u8 {
+        self.cache.levels
+    }
+
+    /// Mark the page at the given texture coordinates (+V up, as meshes have them) and mip
+    /// level as needed this frame
+    pub fn request_page(&mut self, u: f32, v: f32, mip: u8) {
+        self.cache.request(u, v, mip);
+    }
+
+    /// True if a page is in the atlas
+    pub fn is_resident(&self, page: PageId) -> bool {
+        self.cache.resident.contains_key(&page)
+    }
+
+    /// Ask `io` for the requested pages that aren't loaded, upload up to `upload_budget`
+    /// pages it has finished, evicting the least recently requested pages to make room,
+    /// and update the page table. Call once per frame after requesting pages. Returns the
+    /// number of pages uploaded.
+    pub fn tick<C, P>(&mut self, enc: &mut Encoder<R, C>, io: &mut P) -> Result<usize, Error>
+        where C: CommandBuffer<R>, P: PageIo
+    {
+        self.cache.begin_frame(io);
+
+        let mut uploaded = 0;
+        while uploaded < self.upload_budget {
+            let (page, data) = match io.poll() {
+                Some(p) => p,
+                None => break,
+            };
+            let slot = match self.cache.receive(page, &data)? {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let (sx, sy) = self.cache.slot_position(slot);
+            let mut info = self.atlas.get_info().to_image_info(0);
+            info.xoffset = sx * PAGE_SLOT as u16;
+            info.yoffset = sy * PAGE_SLOT as u16;
+            info.width = PAGE_SLOT as u16;
+            info.height = PAGE_SLOT as u16;
+            enc.update_texture::<R8_G8_B8_A8, (R8_G8_B8_A8, Srgb)>(&self.atlas, None, info, gfx::memory::cast_slice(&data))
+                .map_err(|e| FlightError::TextureUpdate { reason: format!("{:?}", e) })?;
+            uploaded += 1;
+            self.dirty = true;
+        }
+
+        if self.dirty {
+            for mip in 0..self.cache.levels {
+                let entries = self.cache.entries(mip);
+                let info = self.table.get_info().to_image_info(mip);
+                enc.update_texture::<R8_G8_B8_A8, (R8_G8_B8_A8, Unorm)>(&self.table, None, info, &entries[..])
+                    .map_err(|e| FlightError::TextureUpdate { reason: format!("{:?}", e) })?;
+            }
+            self.dirty = false;
+        }
+        Ok(uploaded)
+    }
+
+    /// The page table and atlas, for a `draw::VirtualMaterial`
+    pub fn textures(&self) -> (Texture<R, (R8_G8_B8_A8, Unorm)>, Texture<R, (R8_G8_B8_A8, Srgb)>) {
+        (
+            Texture { buffer: self.table_view.clone(), sampler: self.nearest.clone() },
+            Texture { buffer: self.atlas_view.clone(), sampler: self.linear.clone() },
+        )
+    }
+}
+
+/// Greedily pick the (coverage, size) candidates to keep at full resolution, largest
+/// coverage first, until the budget is spent. Unreported candidates are never picked.
+fn select(candidates: &[(f32, usize)], budget: usize) -> Vec<bool> {
+    let mut order: Vec<usize> = (0..candidates.len()).filter(|&i| candidates[i].0 > 0.).collect();
+    order.sort_by(|&a, &b| candidates[b].0.partial_cmp(&candidates[a].0).unwrap_or(::std::cmp::Ordering::Equal));
+    let mut wanted = vec![false; candidates.len()];
+    let mut total = 0;
+    for i in order {
+        if total + candidates[i].1 <= budget {
+            total += candidates[i].1;
+            wanted[i] = true;
+        }
+    }
+    wanted
+}
+
+#[test]
+fn stream_selection() {
+    let chain = MipChain::new(RgbaImage::new(8, 2), 4);
+    assert_eq!((chain.width, chain.height), (4, 1));
+    assert_eq!(chain.levels.iter().map(|l| l.len() / 4).collect::<Vec<_>>(), vec![4, 2, 1]);
+
+    assert_eq!(mip_count(8, 2), 4);
+    assert_eq!(mip_count(1, 1), 1);
+    assert_eq!(mip_count(4096, 4096), 13);
+    let big = MipChain::new(RgbaImage::new(13, 6), u32::max_value());
+    assert_eq!(big.levels.len(), mip_count(13, 6) as usize);
+    for (level, data) in big.levels.iter().enumerate() {
+        let (w, h) = mip_size(13, 6, level as u8);
+        assert_eq!(data.len(), (w * h * 4) as usize);
+    }
+
+    let candidates = [(0.1, 40), (0.5, 60), (0., 10), (0.2, 30)];
+    assert_eq!(select(&candidates, 100), vec![false, true, false, true]);
+    assert_eq!(select(&candidates, 200), vec![true, true, false, true]);
+    assert_eq!(select(&candidates, 0), vec![false; 4]);
+}
+
+#[test]
+fn virtual_pages() {
+    // levels of 4 by 2, 2 by 1 and 1 page, in an atlas of 2 by 2 slots
+    let mut cache = PageCache::new((4, 2), 2);
+    assert_eq!(cache.levels, 3);
+    let top = cache.top();
+    assert_eq!(top, PageId { x: 0, y: 0, mip: 2 });
+    assert_eq!(cache.insert(top, 1), Some(0));
+    assert!(cache.entries(0).iter().all(|e| e == &[0, 0, 2, 255]));
+
+    let a = PageId { x: 3, y: 1, mip: 0 };
+    let b = PageId { x: 0, y: 0, mip: 1 };
+    assert_eq!(cache.insert(a, 1), Some(1));
+    assert_eq!(cache.insert(b, 1), Some(2));
+    let level0 = cache.entries(0);
+    assert_eq!(level0[7], [1, 0, 0, 255]);
+    // pages that aren't resident use the finest page covering them
+    assert_eq!(level0[4], [0, 1, 1, 255]);
+    assert_eq!(level0[2], [0, 0, 2, 255]);
+    assert_eq!(cache.entries(1), vec![[0, 1, 1, 255], [0, 0, 2, 255]]);
+
+    // once the atlas is full the page unused for longest goes, but never the top page
+    let c = PageId { x: 1, y: 0, mip: 1 };
+    assert_eq!(cache.insert(c, 2), Some(3));
+    assert!(cache.touch(a, 3));
+    let d = PageId { x: 0, y: 1, mip: 0 };
+    assert_eq!(cache.insert(d, 3), Some(2));
+    assert!(!cache.touch(b, 3));
+    // nor pages used this frame
+    for &p in &[a, c, d] {
+        assert!(cache.touch(p, 4));
+    }
+    assert_eq!(cache.insert(b, 4), None);
+
+    let img = RgbaImage::from_fn(PAGE_SIZE, PAGE_SIZE, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+    let mut io = ImagePages::new(img, (1, 1));
+    io.request(PageId { x: 0, y: 0, mip: 0 });
+    let (page, data) = io.poll().unwrap();
+    assert_eq!(page, PageId { x: 0, y: 0, mip: 0 });
+    assert_eq!(data.len(), (PAGE_SLOT * PAGE_SLOT * 4) as usize);
+    let texel = |x: u32, y: u32| { let i = ((y * PAGE_SLOT + x) * 4) as usize; [data[i], data[i + 1]] };
+    // the border repeats the edge
+    assert_eq!(texel(0, 0), [0, 0]);
+    assert_eq!(texel(PAGE_BORDER + 5, PAGE_BORDER + 7), [5, 7]);
+    assert_eq!(texel(PAGE_SLOT - 1, PAGE_SLOT - 1), [PAGE_SIZE as u8 - 1, PAGE_SIZE as u8 - 1]);
+    assert!(io.poll().is_none());
+}
+
+#[test]
+fn virtual_requests() {
+    /// Loads nothing, only records what was asked for
+    struct Requests(Vec<PageId>);
+    impl PageIo for Requests {
+        fn request(&mut self, page: PageId) { self.0.push(page) }
+        fn poll(&mut self) -> Option<(PageId, Vec<u8>)> { None }
+    }
+    let data = vec![0; (PAGE_SLOT * PAGE_SLOT * 4) as usize];
+
+    let mut cache = PageCache::new((4, 2), 2);
+    let mut io = Requests(Vec::new());
+    // the top page is always requested, and coordinates have +V up
+    cache.request(0.9, 0.9, 0);
+    cache.request(0.9, 0.8, 0);
+    cache.request(0.1, 0.1, 7);
+    cache.begin_frame(&mut io);
+    let top = cache.top();
+    let corner = PageId { x: 3, y: 0, mip: 0 };
+    io.0.sort_by_key(|p| p.mip);
+    assert_eq!(io.0, vec![corner, top]);
+
+    // pages still loading aren't asked for again
+    io.0.clear();
+    cache.request(0.9, 0.9, 0);
+    cache.begin_frame(&mut io);
+    assert!(io.0.is_empty());
+
+    assert!(cache.receive(corner, &data[1..]).is_err());
+    assert_eq!(cache.receive(top, &data).unwrap(), Some(0));
+    assert_eq!(cache.receive(corner, &data).unwrap(), Some(1));
+    // a page loaded twice only takes one slot
+    assert_eq!(cache.receive(corner, &data).unwrap(), None);
+    assert!(cache.resident.contains_key(&corner));
+
+    // resident pages are marked as used instead of loaded again
+    cache.request(0.9, 0.9, 0);
+    cache.begin_frame(&mut io);
+    assert!(io.0.is_empty());
+    assert_eq!(cache.slots[1], Some((corner, 3)));
+}
@@ -0,0 +1,551 @@
+use gfx::{self, Factory, CommandBuffer, Encoder};
+use gfx::format::{R8, Unorm};
+use gfx::traits::FactoryExt;
+use nalgebra::{Point3, Transform3, Vector3};
+use std::f32::consts::PI;
+
+use ::{Error, FlightError, Texture};
+use ::draw::UberMaterial;
+use ::mesh::{Mesh, MeshSource, Indexing, Primitive, Vertex, VertNTT, Aabb};
+
+/// Bilinearly sample a heightfield, clamping at the edges
+fn sample(heights: &[f32], width: u32, depth: u32, x: f32, z: f32) -> f32 {
+    let x = x.max(0.).min((width - 1) as f32);
+    let z = z.max(0.).min((depth - 1) as f32);
+    let (x0, z0) = (x.floor() as u32, z.floor() as u32);
+    let (x1, z1) = ((x0 + 1).min(width - 1), (z0 + 1).min(depth - 1));
+    let (fx, fz) = (x - x0 as f32, z - z0 as f32);
+    let h = |x: u32, z: u32| heights[(z * width + x) as usize];
+    let top = h(x0, z0) * (1. - fx) + h(x1, z0) * fx;
+    let bottom = h(x0, z1) * (1. - fx) + h(x1, z1) * fx;
+    top * (1. - fz) + bottom * fz
+}
+
+/// Bake ambient occlusion for a heightfield of `width` by `depth` samples spaced one unit
+/// apart, stored row by row (`heights[z * width + x]`). For each sample, `ray_count`
+/// directions around the vertical are marched across the terrain to find the highest
+/// horizon angle. The part of the sky below a horizon at angle `h` covers `sin(h)` of the
+/// hemisphere, so the result is 1 minus the average of that over every direction: 1 where
+/// the whole sky is visible and lower in valleys and at the foot of cliffs.
+pub fn bake_horizon_ao(heights: &[f32], width: u32, depth: u32, ray_count: u32) -> Vec<f32> {
+    let count = width as usize * depth as usize;
+    if count == 0 || heights.len() < count {
+        return vec![1.; count];
+    }
+    let ray_count = ray_count.max(1);
+    let max_steps = width.max(depth) as usize;
+    let dirs: Vec<(f32, f32)> = (0..ray_count)
+        .map(|i| {
+            let a = 2. * PI * i as f32 / ray_count as f32;
+            (a.cos(), a.sin())
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(count);
+    for z in 0..depth {
+        for x in 0..width {
+            let h0 = heights[(z * width + x) as usize];
+            let mut obscured = 0.;
+            for &(dx, dz) in &dirs {
+                let mut max_tan = 0f32;
+                for step in 1..max_steps {
+                    let t = step as f32;
+                    let (sx, sz) = (x as f32 + dx * t, z as f32 + dz * t);
+                    if sx < 0. || sz < 0. || sx > (width - 1) as f32 || sz > (depth - 1) as f32 {
+                        break;
+                    }
+                    max_tan = max_tan.max((sample(heights, width, depth, sx, sz) - h0) / t);
+                }
+                // sin(atan(x)) without the trigonometry
+                obscured += max_tan / (1. + max_tan * max_tan).sqrt();
+            }
+            out.push(1. - obscured / ray_count as f32);
+        }
+    }
+    out
+}
+
+/// Upload baked ambient occlusion (see `bake_horizon_ao`) as a single channel texture
+pub fn load_ao_map<R, F>(f: &mut F, ao: &[f32], width: u32, depth: u32)
+    -> Result<Texture<R, (R8, Unorm)>, Error>
+    where
+        R: gfx::Resources,
+        F: Factory<R>,
+{
+    let expected = width as usize * depth as usize;
+    ensure!(
+        ao.len() == expected,
+        FlightError::TextureSizeMismatch { expected: expected, given: ao.len() }
+    );
+    let data: Vec<u8> = ao.iter().map(|&v| (v.max(0.).min(1.) * 255. + 0.5) as u8).collect();
+
+    use gfx::texture::*;
+    let (_, shader_resource) = f.create_texture_immutable_u8::<(R8, Unorm)>(
+        Kind::D2(width as u16, depth as u16, AaMode::Single),
+        Mipmap::Provided,
+        &[&data[..]],
+    )?;
+    let sampler = f.create_sampler(SamplerInfo::new(
+        FilterMethod::Bilinear,
+        WrapMode::Clamp));
+    Ok(Texture {
+        sampler: sampler,
+        buffer: shader_resource,
+    })
+}
+
+/// Blur baked ambient occlusion with a box filter `radius` samples wide, applied along x
+/// and then z, to soften the steps between samples
+pub fn blur_ao(ao: &[f32], width: u32, depth: u32, radius: u32) -> Vec<f32> {
+    let count = width as usize * depth as usize;
+    if radius == 0 || count == 0 || ao.len() < count {
+        return ao.to_vec();
+    }
+    let r = radius as i32;
+    let pass = |src: &[f32], along_x: bool| -> Vec<f32> {
+        let mut out = Vec::with_capacity(count);
+        for z in 0..depth as i32 {
+            for x in 0..width as i32 {
+                let (mut sum, mut n) = (0., 0.);
+                for o in -r..r + 1 {
+                    let (sx, sz) = if along_x { (x + o, z) } else { (x, z + o) };
+                    if sx < 0 || sz < 0 || sx >= width as i32 || sz >= depth as i32 { continue }
+                    sum += src[(sz as u32 * width + sx as u32) as usize];
+                    n += 1.;
+                }
+                out.push(sum / n);
+            }
+        }
+        out
+    };
+    let once = pass(ao, true);
+    pass(&once, false)
+}
+
+/// The height of the highest surface above each point of a `width` by `depth` grid spanning
+/// `min` to `min + size` on the XZ plane, found by rasterizing triangle list meshes from
+/// above. Points with nothing above them are at `floor`. This is the CPU equivalent of
+/// rendering static geometry top-down, for scenes without a terrain heightmap.
+pub fn rasterize_heights<V: Vertex, M>(
+    meshes: &[(&MeshSource<V, M>, Transform3<f32>)],
+    min: [f32; 2],
+    size: [f32; 2],
+    width: u32,
+    depth: u32,
+    floor: f32,
+)
+    -> Vec<f32>
+{
+    let mut heights = vec![floor; width as usize * depth as usize];
+    if width < 2 || depth < 2 { return heights }
+    let step = [size[0] / (width - 1) as f32, size[1] / (depth - 1) as f32];
+    for &(mesh, ref model) in meshes {
+        if mesh.prim != Primitive::TriangleList { continue }
+        let points: Vec<Point3<f32>> = mesh.verts.iter().map(|v| model * v.pos()).collect();
+        let inds: Vec<usize> = match mesh.inds {
+            Indexing::Inds(ref i) => i.iter().map(|&i| i as usize).collect(),
+            Indexing::Range(a, b) => (a as usize..b as usize).collect(),
+            Indexing::All => (0..points.len()).collect(),
+        };
+        for t in inds.chunks(3).filter(|t| t.len() == 3 && t.iter().all(|&i| i < points.len())) {
+            // grid coordinates of the corners
+            let g = |p: &Point3<f32>| ((p.x - min[0]) / step[0], (p.z - min[1]) / step[1], p.y);
+            let (a, b, c) = (g(&points[t[0]]), g(&points[t[1]]), g(&points[t[2]]));
+            let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+            if area.abs() < 1e-12 { continue }
+            let x0 = a.0.min(b.0).min(c.0).ceil().max(0.) as u32;
+            let z0 = a.1.min(b.1).min(c.1).ceil().max(0.) as u32;
+            let x1 = (a.0.max(b.0).max(c.0).floor().max(-1.) + 1.) as u32;
+            let z1 = (a.1.max(b.1).max(c.1).floor().max(-1.) + 1.) as u32;
+            for z in z0..z1.min(depth) {
+                for x in x0..x1.min(width) {
+                    let (px, pz) = (x as f32, z as f32);
+                    let wa = ((b.0 - px) * (c.1 - pz) - (c.0 - px) * (b.1 - pz)) / area;
+                    let wb = ((c.0 - px) * (a.1 - pz) - (a.0 - px) * (c.1 - pz)) / area;
+                    let wc = 1. - wa - wb;
+                    if wa < -1e-5 || wb < -1e-5 || wc < -1e-5 { continue }
+                    let h = &mut heights[(z * width + x) as usize];
+                    *h = h.max(a.2 * wa + b.2 * wb + c.2 * wc);
+                }
+            }
+        }
+    }
+    heights
+}
+
+/// Large scale ambient occlusion over an area of the XZ plane, seen from above, which the
+/// uber style multiplies into its irradiance (see `UberInputs::set_sky_occlusion`). Valleys
+/// and the space under overhangs are darker than ridges at a scale that screen space
+/// techniques can't reach. Bake it from a terrain heightmap or `rasterize_heights`, and
+/// bake again when static geometry changes.
+pub struct SkyOcclusion<R: gfx::Resources> {
+    /// The occlusion, 1 where the whole sky is visible
+    pub texture: Texture<R, (R8, Unorm)>,
+    /// The corner of the covered area with the smallest coordinates
+    pub min: [f32; 2],
+    /// The width and depth of the covered area
+    pub size: [f32; 2],
+    /// How much of the occlusion is applied, from 0 (none) to 1
+    pub strength: f32,
+}
+
+impl<R: gfx::Resources> SkyOcclusion<R> {
+    /// Bake occlusion from a grid of world space heights spanning `min` to `min + size`
+    /// (see `bake_horizon_ao`), blurred by `blur` samples. Samples should be as far apart
+    /// along x as along z.
+    pub fn bake<F: Factory<R>>(
+        f: &mut F,
+        heights: &[f32],
+        width: u32,
+        depth: u32,
+        min: [f32; 2],
+        size: [f32; 2],
+        ray_count: u32,
+        blur: u32,
+    )
+        -> Result<SkyOcclusion<R>, Error>
+    {
+        let mut sky = SkyOcclusion {
+            texture: Texture::uniform_value(f, 255)?,
+            min: min,
+            size: size,
+            strength: 1.,
+        };
+        sky.rebake(f, heights, width, depth, ray_count, blur)?;
+        Ok(sky)
+    }
+
+    /// Replace the occlusion with a new bake over the same area. Pass the result to the uber
+    /// painter again, since meshes keep the texture they were bound with.
+    pub fn rebake<F: Factory<R>>(
+        &mut self,
+        f: &mut F,
+        heights: &[f32],
+        width: u32,
+        depth: u32,
+        ray_count: u32,
+        blur: u32,
+    )
+        -> Result<(), Error>
+    {
+        // the horizon search works in units of samples
+        let spacing = self.size[0] / (width.max(2) - 1) as f32;
+        let scaled: Vec<f32> = heights.iter().map(|h| h / spacing.max(1e-6)).collect();
+        let ao = bake_horizon_ao(&scaled, width, depth, ray_count);
+        self.texture = load_ao_map(f, &blur_ao(&ao, width, depth, blur), width, depth)?;
+        Ok(())
+    }
+
+    /// The area for the uber shader: the minimum corner and the inverse of the size
+    pub fn rect(&self) -> [f32; 4] {
+        [self.min[0], self.min[1], 1. / self.size[0].max(1e-6), 1. / self.size[1].max(1e-6)]
+    }
+}
+
+/// The height of a sample of a heightfield stored row by row, clamped to the edges
+fn height_at(heights: &[f32], width: u32, depth: u32, x: i64, z: i64) -> f32 {
+    let x = x.max(0).min(width as i64 - 1);
+    let z = z.max(0).min(depth as i64 - 1);
+    heights[(z * width as i64 + x) as usize]
+}
+
+/// The vertex of a heightfield sample, with the normal and tangents of the slope found by
+/// central differences (one sided at the edges)
+fn terrain_vertex(heights: &[f32], width: u32, depth: u32, spacing: f32, x: u32, z: u32) -> VertNTT {
+    let h = |dx: i64, dz: i64| height_at(heights, width, depth, x as i64 + dx, z as i64 + dz);
+    // how many samples apart the neighbors are
+    let span = |i: u32, len: u32| ((i > 0) as u32 + (i + 1 < len) as u32).max(1) as f32 * spacing;
+    let dx = (h(1, 0) - h(-1, 0)) / span(x, width);
+    let dz = (h(0, 1) - h(0, -1)) / span(z, depth);
+    let norm = Vector3::new(-dx, 1., -dz).normalize();
+    let tan = Vector3::new(1., dx, 0.).normalize();
+    let bitan = Vector3::new(0., dz, 1.).normalize();
+    VertNTT {
+        pos: [x as f32 * spacing, h(0, 0), z as f32 * spacing],
+        norm: [norm.x, norm.y, norm.z],
+        tan: [tan.x, tan.y, tan.z],
+        bitan: [bitan.x, bitan.y, bitan.z],
+        tex: [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+    }
+}
+
+/// How `DeformableTerrain::deform` changes the heights under the brush
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DeformMode {
+    /// Push the ground up by the strength
+    Raise,
+    /// Dig the ground down by the strength
+    Lower,
+    /// Pull each height towards the average of its neighbors, fully at a strength of 1
+    Smooth,
+}
+
+/// A heightfield that can be dug into and sculpted while it is shown, such as sand in a
+/// sandbox or tissue in a surgery simulator. The heights are kept on the CPU, with
+/// `width` by `depth` samples `spacing` apart on the XZ plane starting at the origin, and
+/// deforming them only rebuilds the vertices around the brush. Call `upload` once a frame
+/// to send the changed vertices to the GPU in one contiguous write.
+pub struct DeformableTerrain<R: gfx::Resources> {
+    /// The mesh to draw with an uber painter
+    pub mesh: Mesh<R, VertNTT, UberMaterial<R>>,
+    field: Heightfield,
+}
+
+impl<R: gfx::Resources> DeformableTerrain<R> {
+    /// Build a terrain from heights stored row by row (`heights[z * width + x]`)
+    pub fn new<F, C>(
+        f: &mut F,
+        enc: &mut Encoder<R, C>,
+        heights: Vec<f32>,
+        width: u32,
+        depth: u32,
+        spacing: f32,
+        mat: UberMaterial<R>,
+    )
+        -> Result<DeformableTerrain<R>, Error>
+        where F: FactoryExt<R>, C: CommandBuffer<R>
+    {
+        let field = Heightfield::new(heights, width, depth, spacing)?;
+        let mut inds = Vec::with_capacity((width as usize - 1) * (depth as usize - 1) * 6);
+        for z in 0..depth - 1 {
+            for x in 0..width - 1 {
+                let (a, b) = (z * width + x, z * width + x + 1);
+                let (c, d) = (a + width, b + width);
+                inds.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        let mesh = MeshSource {
+            verts: field.verts.clone(),
+            inds: Indexing::Inds(inds),
+            prim: Primitive::TriangleList,
+            mat: mat,
+        }.upload_dynamic(f, enc)?;
+        Ok(DeformableTerrain {
+            mesh: mesh,
+            field: field,
+        })
+    }
+
+    /// The heights, row by row
+    pub fn heights(&self) -> &[f32] {
+        &self.field.heights
+    }
+
+    /// Change the heights within `radius` of `center` (whose height is ignored), by up to
+    /// `strength` at the center and less towards the edge of the brush
+    pub fn deform(&mut self, center: [f32; 3], radius: f32, strength: f32, mode: DeformMode) {
+        self.field.deform(&mut self.mesh.bounds, center, radius, strength, mode);
+    }
+
+    /// The first and last vertex changed since the last `upload`
+    pub fn dirty_range(&self) -> Option<(usize, usize)> {
+        self.field.dirty
+    }
+
+    /// Send the vertices changed since the last upload to the GPU
+    pub fn upload<C: CommandBuffer<R>>(&mut self, enc: &mut Encoder<R, C>) -> Result<(), Error> {
+        if let Some((first, last)) = self.field.dirty.take() {
+            self.mesh.update_verts(enc, &self.field.verts[first..last + 1], first)?;
+        }
+        Ok(())
+    }
+}
+
+/// The CPU side of a `DeformableTerrain`: the heights, their vertices and which vertices
+/// have changed
+struct Heightfield {
+    heights: Vec<f32>,
+    verts: Vec<VertNTT>,
+    width: u32,
+    depth: u32,
+    spacing: f32,
+    /// The first and last changed vertex not yet uploaded
+    dirty: Option<(usize, usize)>,
+}
+
+impl Heightfield {
+    fn new(heights: Vec<f32>, width: u32, depth: u32, spacing: f32) -> Result<Heightfield, Error> {
+        ensure!(width >= 2 && depth >= 2, FlightError::InvalidShape {
+            reason: "a terrain needs at least 2 by 2 heights",
+        });
+        ensure!(heights.len() == width as usize * depth as usize, FlightError::InvalidShape {
+            reason: "the number of heights must be the terrain width times its depth",
+        });
+        let verts = (0..depth)
+            .flat_map(|z| (0..width).map(move |x| (x, z)))
+            .map(|(x, z)| terrain_vertex(&heights, width, depth, spacing, x, z))
+            .collect::<Vec<_>>();
+        Ok(Heightfield {
+            heights: heights,
+            verts: verts,
+            width: width,
+            depth: depth,
+            spacing: spacing,
+            dirty: None,
+        })
+    }
+
+    /// Apply a brush stroke (see `DeformableTerrain::deform`), growing `bounds` to cover
+    /// the rebuilt vertices
+    fn deform(&mut self, bounds: &mut Aabb, center: [f32; 3], radius: f32, strength: f32, mode: DeformMode) {
+        if radius <= 0. { return }
+        let (cx, cz) = (center[0] / self.spacing, center[2] / self.spacing);
+        let r = radius / self.spacing;
+        let x0 = (cx - r).floor().max(0.) as i64;
+        let z0 = (cz - r).floor().max(0.) as i64;
+        let x1 = ((cx + r).ceil() as i64).min(self.width as i64 - 1);
+        let z1 = ((cz + r).ceil() as i64).min(self.depth as i64 - 1);
+        if x0 > x1 || z0 > z1 { return }
+
+        // smoothing reads the heights from before this stroke
+        let before = self.heights.clone();
+        let (w, d) = (self.width, self.depth);
+        let old = |x: i64, z: i64| height_at(&before, w, d, x, z);
+        for z in z0..z1 + 1 {
+            for x in x0..x1 + 1 {
+                let t = ((x as f32 - cx).powi(2) + (z as f32 - cz).powi(2)).sqrt() / r;
+                if t >= 1. { continue }
+                let falloff = (1. - t * t) * (1. - t * t);
+                let h = &mut self.heights[(z * self.width as i64 + x) as usize];
+                match mode {
+                    DeformMode::Raise => *h += strength * falloff,
+                    DeformMode::Lower => *h -= strength * falloff,
+                    DeformMode::Smooth => {
+                        let avg = (old(x - 1, z) + old(x + 1, z) + old(x, z - 1) + old(x, z + 1)) * 0.25;
+                        *h += (avg - *h) * (strength * falloff).max(0.).min(1.);
+                    },
+                }
+            }
+        }
+        // the normals one sample outside the brush see the changed heights too
+        let grow = |v: i64, max: u32| v.max(0).min(max as i64 - 1) as u32;
+        self.rebuild(bounds, grow(x0 - 1, w), grow(z0 - 1, d), grow(x1 + 1, w), grow(z1 + 1, d));
+    }
+
+    /// Recompute the vertices of a rectangle of samples, and mark them for upload
+    fn rebuild(&mut self, bounds: &mut Aabb, x0: u32, z0: u32, x1: u32, z1: u32) {
+        for z in z0..z1 + 1 {
+            for x in x0..x1 + 1 {
+                let v = terrain_vertex(&self.heights, self.width, self.depth, self.spacing, x, z);
+                // the bounds only grow, which keeps them covering the terrain
+                bounds.extend(&Point3::new(v.pos[0], v.pos[1], v.pos[2]));
+                self.verts[(z * self.width + x) as usize] = v;
+            }
+        }
+        let (first, last) = ((z0 * self.width + x0) as usize, (z1 * self.width + x1) as usize);
+        self.dirty = Some(match self.dirty {
+            Some((a, b)) => (a.min(first), b.max(last)),
+            None => (first, last),
+        });
+    }
+}
+
+#[test]
+fn horizon_ao() {
+    // flat ground sees the whole sky
+    let flat = bake_horizon_ao(&[0.; 16], 4, 4, 8);
+    assert!(flat.iter().all(|&v| relative_eq!(v, 1.)));
+
+    // a pit surrounded by tall walls is mostly dark, the wall tops are not
+    let (w, d) = (9, 9);
+    let mut heights = vec![0.; w * d];
+    for z in 0..d {
+        for x in 0..w {
+            if x == 0 || z == 0 || x == w - 1 || z == d - 1 {
+                heights[z * w + x] = 20.;
+            }
+        }
+    }
+    let ao = bake_horizon_ao(&heights, w as u32, d as u32, 16);
+    let center = ao[4 * w + 4];
+    assert!(center < 0.2);
+    assert!(ao[0] > 0.99);
+    assert!(ao.iter().all(|&v| v >= 0. && v <= 1.));
+    // lower walls hide less of the sky
+    let low: Vec<f32> = heights.iter().map(|h| h / 10.).collect();
+    let ao = bake_horizon_ao(&low, w as u32, d as u32, 16);
+    assert!(ao[4 * w + 4] > center);
+}
+
+#[test]
+fn top_down_heights() {
+    use ::mesh::VertN;
+    use nalgebra as na;
+
+    // a 2 unit wide roof 3 units up over the middle of a 4 unit square
+    let v = |x: f32, z: f32| VertN { pos: [x, 3., z], norm: [0., 1., 0.] };
+    let roof = MeshSource {
+        verts: vec![v(-1., -1.), v(1., -1.), v(1., 1.), v(-1., 1.)],
+        inds: Indexing::Inds(vec![0, 1, 2, 0, 2, 3]),
+        prim: Primitive::TriangleList,
+        mat: (),
+    };
+    let heights = rasterize_heights(&[(&roof, na::one())], [-2., -2.], [4., 4.], 5, 5, 0.);
+    assert_eq!(heights[2 * 5 + 2], 3.);
+    assert_eq!(heights[1 * 5 + 1], 3.);
+    assert_eq!(heights[0], 0.);
+    assert_eq!(heights[4 * 5 + 2], 0.);
+    // lifted by the model transform
+    let up = Transform3::from_matrix_unchecked(na::Matrix4::new_translation(&na::Vector3::new(0., 1., 0.)));
+    let heights = rasterize_heights(&[(&roof, up)], [-2., -2.], [4., 4.], 5, 5, 0.);
+    assert_eq!(heights[2 * 5 + 2], 4.);
+
+    // blurring keeps flat areas flat and softens edges
+    let ao = vec![1., 1., 0., 0.];
+    let soft = blur_ao(&ao, 4, 1, 1);
+    assert!(relative_eq!(soft[0], 1.) && relative_eq!(soft[3], 0.));
+    assert!(soft[1] < 1. && soft[2] > 0.);
+}
+
+#[test]
+fn terrain_normals() {
+    // a ramp rising one unit for every two along x
+    let (w, d) = (4, 3);
+    let heights: Vec<f32> = (0..w * d).map(|i| (i % w) as f32).collect();
+    for &(x, z) in &[(0, 0), (1, 1), (3, 2)] {
+        let v = terrain_vertex(&heights, w, d, 2., x, z);
+        assert_eq!(v.pos, [x as f32 * 2., x as f32, z as f32 * 2.]);
+        let n = Vector3::new(-1., 2., 0.).normalize();
+        let vec = |a: [f32; 3]| Vector3::new(a[0], a[1], a[2]);
+        assert!(relative_eq!(vec(v.norm), n, epsilon = 1e-6));
+        assert!(relative_eq!(vec(v.tan).dot(&n), 0., epsilon = 1e-6));
+    }
+    assert_eq!(terrain_vertex(&heights, w, d, 2., 3, 2).tex, [1., 1.]);
+}
+
+#[test]
+fn deform_brush() {
+    let (w, d) = (8, 8);
+    assert!(Heightfield::new(vec![0.; 10], w, d, 1.).is_err());
+    let mut field = Heightfield::new(vec![0.; (w * d) as usize], w, d, 1.).unwrap();
+    let mut bounds = Aabb::empty();
+    assert_eq!(field.dirty, None);
+
+    // raise around (3, 4) with a radius of 2 samples
+    field.deform(&mut bounds, [3., 10., 4.], 2., 1., DeformMode::Raise);
+    let h = |f: &Heightfield, x: u32, z: u32| f.heights[(z * w + x) as usize];
+    assert!(relative_eq!(h(&field, 3, 4), 1.));
+    assert!(h(&field, 4, 4) > 0. && h(&field, 4, 4) < 1.);
+    // the edge of the brush and beyond are untouched
+    assert_eq!(h(&field, 5, 4), 0.);
+    assert_eq!(h(&field, 7, 7), 0.);
+    // the vertices follow the heights, and the bounds grew to hold them
+    assert!(relative_eq!(field.verts[(4 * w + 3) as usize].pos[1], 1.));
+    assert!(relative_eq!(bounds.max.y, 1.));
+
+    // the brush covers x from 1 to 5 and z from 2 to 6, and the normals one sample further out
+    assert_eq!(field.dirty, Some(((1 * w) as usize, (7 * w + 6) as usize)));
+    // lowering elsewhere widens the range rather than replacing it
+    field.deform(&mut bounds, [7., 0., 0.], 1., 1., DeformMode::Lower);
+    assert!(relative_eq!(h(&field, 7, 0), -1.));
+    assert_eq!(field.dirty, Some((5, (7 * w + 6) as usize)));
+
+    // smoothing pulls the peak down towards its neighbors
+    let peak = h(&field, 3, 4);
+    field.deform(&mut bounds, [3., 0., 4.], 1., 1., DeformMode::Smooth);
+    assert!(h(&field, 3, 4) < peak);
+    // a brush outside the terrain changes nothing
+    let heights = field.heights.clone();
+    field.deform(&mut bounds, [-10., 0., -10.], 1., 1., DeformMode::Raise);
+    assert_eq!(field.heights, heights);
+}
@@ -0,0 +1,778 @@
+use gfx::{Factory as FactoryTrait, Device as DeviceTrait, Rect};
+use gfx::texture::{SamplerInfo, FilterMethod, WrapMode};
+use gfx_device_gl::{self, Device, Factory, Resources, CommandBuffer};
+use glutin::{self, GlContext};
+use image::{self, Rgba, RgbaImage};
+use nalgebra::{self as na, Isometry3, Perspective3, Point3, Vector3, Rotation3, Translation3, Transform3};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3};
+
+use ::draw::{self, DrawParams, Painter, OffscreenTarget, UberStyle, UberMaterial, NormalEncoding, SunCookie};
+use ::draw::{VolumeStyle, VolumeMaterial, VolumeData, VolumeMode, volume_box};
+use ::draw::{UnlitStyle, UnlitMaterial, DepthBias, LayeredStyle, LayeredMaterial};
+use ::draw::{VisibilityBuffer, VisibilityMesh, TextureDownload, VirtualTextureStyle, VirtualMaterial};
+use ::draw::{TemporalHistory, ResourceStateTracker, LodTransition, AlphaHashStyle, AlphaHashMaterial};
+use ::draw::Stereo360Capture;
+use ::mesh::{gen, Primitive, Indexing};
+use ::stream::{VirtualTexture, ImagePages, PAGE_SIZE};
+use ::terrain::{DeformableTerrain, DeformMode};
+use ::{Error, FlightError, Texture};
+
+/// The size in pixels of every golden image
+pub const GOLDEN_WIDTH: u16 = 192;
+pub const GOLDEN_HEIGHT: u16 = 128;
+
+/// Set this environment variable to overwrite the golden images with what is rendered now,
+/// after checking that a change in the output is intended
+pub const BLESS_VAR: &str = "FLIGHT_BLESS_GOLDENS";
+
+/// The built in reference scenes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GoldenScene {
+    /// Uber spheres from smooth to rough, dielectric in front and metal behind
+    MaterialLadder,
+    /// A floor and sphere lit through a checkered sun cookie. This covers the cookie, not
+    /// shadows, since the uber style doesn't sample its shadow map yet.
+    SunCookieFloor,
+    /// The environment background with the sun disc drawn on top
+    BackgroundSun,
+    /// The environment background when the sun is already part of the environment map
+    BackgroundEnv,
+    /// A maximum intensity projection of a synthetic volume
+    VolumeSlice,
+}
+
+/// Every built in scene, in the order they are checked
+pub const GOLDEN_SCENES: [GoldenScene; 5] = [
+    GoldenScene::MaterialLadder,
+    GoldenScene::SunCookieFloor,
+    GoldenScene::BackgroundSun,
+    GoldenScene::BackgroundEnv,
+    GoldenScene::VolumeSlice,
+];
+
+impl GoldenScene {
+    /// The file name (without extension) of the scene's golden image
+    pub fn name(&self) -> &'static str {
+        use self::GoldenScene::*;
+        match *self {
+            MaterialLadder => "material_ladder",
+            SunCookieFloor => "sun_cookie_floor",
+            BackgroundSun => "background_sun",
+            BackgroundEnv => "background_env",
+            VolumeSlice => "volume_slice",
+        }
+    }
+}
+
+/// An OpenGL context without a window, drawing into an offscreen target of
+/// `GOLDEN_WIDTH` by `GOLDEN_HEIGHT` pixels
+pub struct Headless {
+    pub device: Device,
+    pub factory: Factory,
+    pub target: OffscreenTarget<Resources>,
+    // dropped last, the device and factory need it
+    _context: glutin::HeadlessContext,
+}
+
+impl Headless {
+    /// Create the context, failing if the platform can't make one without a window
+    pub fn new() -> Result<Headless, Error> {
+        let context = glutin::HeadlessRendererBuilder::new(GOLDEN_WIDTH as u32, GOLDEN_HEIGHT as u32)
+            .build()
+            .map_err(|e| FlightError::Headless { reason: e.to_string() })?;
+        let current = unsafe { context.make_current() };
+        current.map_err(|e| FlightError::Headless { reason: e.to_string() })?;
+        let (device, mut factory) = gfx_device_gl::create(|s| context.get_proc_address(s) as *const _);
+        let target = OffscreenTarget::new(&mut factory, GOLDEN_WIDTH, GOLDEN_HEIGHT)?;
+        Ok(Headless {
+            device: device,
+            factory: factory,
+            target: target,
+            _context: context,
+        })
+    }
+
+    /// Clear the target, let `draw` fill it from the fixed golden camera, and read it back.
+    /// The camera sits at (0, 1.5, 4) looking at (0, 0.5, 0) with a 60 degree field of view.
+    pub fn render<F>(&mut self, draw: F) -> Result<RgbaImage, Error>
+        where F: FnOnce(&mut Factory, &mut DrawParams<Resources, CommandBuffer>) -> Result<(), Error>
+    {
+        let encoder = self.factory.create_command_buffer().into();
+        let mut ctx = DrawParams::new(encoder, self.target.color.clone(), self.target.depth.clone());
+        ctx.encoder.clear(&ctx.color, [0., 0., 0., 1.]);
+        ctx.encoder.clear_depth(&ctx.depth, ::draw::DEPTH_CONVENTION.far());
+        let (w, h) = (GOLDEN_WIDTH, GOLDEN_HEIGHT);
+        let view = Isometry3::look_at_rh(&Point3::new(0., 1.5, 4.), &Point3::new(0., 0.5, 0.), &Vector3::y());
+        let proj = Perspective3::new(w as f32 / h as f32, FRAC_PI_3, 0.1, 100.);
+        ctx.push_camera(view.to_homogeneous(), proj.to_homogeneous(), Rect { x: 0, y: 0, w: w, h: h });
+        draw(&mut self.factory, &mut ctx)?;
+        ctx.pop_camera();
+        let image = draw::capture_frame_rgba8(
+            &mut self.factory,
+            &mut ctx.encoder,
+            &mut self.device,
+            &self.target.color_texture,
+        )?;
+        self.device.cleanup();
+        Ok(image)
+    }
+}
+
+fn uber_material<F: FactoryTrait<Resources>>(
+    f: &mut F,
+    albedo: [u8; 4],
+    knobs: [u8; 4],
+    surface: gen::Surface,
+)
+    -> Result<UberMaterial<Resources>, Error>
+{
+    Ok(UberMaterial {
+        albedo: Texture::uniform_value(f, albedo)?,
+        normal: Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+        knobs: Texture::uniform_value(f, knobs)?,
+        bent: Texture::uniform_value(f, [0x80, 0x80, 0xFF, 0xFF])?,
+        surface: surface,
+        normal_encoding: NormalEncoding::Rgb,
+    })
+}
+
+/// A sun shining from the given direction, for the background and cookie scenes
+fn sun_towards(dir: Vector3<f32>) -> Rotation3<f32> {
+    Rotation3::rotation_between(&Vector3::z(), &dir).unwrap_or(na::one())
+}
+
+fn draw_scene(
+    scene: GoldenScene,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    use self::GoldenScene::*;
+    match scene {
+        MaterialLadder => {
+            let mut painter: Painter<_, UberStyle<_>> = Painter::new(f)?;
+            painter.setup(f, Primitive::TriangleList)?;
+            painter.clear_env(ctx);
+            for metal in 0..2 {
+                for rough in 0..5 {
+                    let knobs = [metal * 255, 25 + rough * 50, 0, 0];
+                    let sphere = gen::sphere(0.3, 24, 12);
+                    let surface = sphere.mat;
+                    let mat = uber_material(f, [200, 60, 40, 255], knobs, surface)?;
+                    let mesh = sphere.with_material(mat).upload(f);
+                    let pos = Translation3::new(rough as f32 * 0.7 - 1.4, 0.5, metal as f32 * -0.8);
+                    painter.try_draw(ctx, na::convert(pos), &mesh)?;
+                }
+            }
+        },
+        SunCookieFloor => {
+            let mut painter: Painter<_, UberStyle<_>> = Painter::new(f)?;
+            painter.setup(f, Primitive::TriangleList)?;
+            let checker = RgbaImage::from_fn(8, 8, |x, y| if (x + y) % 2 == 0 {
+                Rgba([255; 4])
+            } else {
+                Rgba([40, 40, 40, 255])
+            });
+            let sampler = f.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Tile));
+            painter.cfg(|i| {
+                i.mut_env().sun_rotation = sun_towards(Vector3::new(0.3, 1., 0.2));
+                i.mut_env().sun_color = [1., 0.95, 0.9, 3.];
+            });
+            let cookie = ::load::load_rgba8(f, checker, sampler)?;
+            painter.cfg(|i| i.set_sun_cookie(Some(SunCookie {
+                texture: cookie,
+                extent: 2.,
+                tile: true,
+                outside: 1.,
+            })));
+            painter.clear_env(ctx);
+            let floor = gen::quad(4., 4.);
+            let mat = uber_material(f, [180, 180, 180, 255], [0, 200, 0, 0], floor.mat)?;
+            let floor = floor.with_material(mat).upload(f);
+            let flat: Transform3<f32> = na::convert(Rotation3::from_axis_angle(&Vector3::x_axis(), -::std::f32::consts::FRAC_PI_2));
+            painter.try_draw(ctx, flat, &floor)?;
+            let ball = gen::sphere(0.5, 24, 12);
+            let mat = uber_material(f, [60, 120, 200, 255], [0, 120, 0, 0], ball.mat)?;
+            let ball = ball.with_material(mat).upload(f);
+            painter.try_draw(ctx, na::convert(Translation3::new(0., 0.5, 0.)), &ball)?;
+        },
+        BackgroundSun | BackgroundEnv => {
+            let painter: Painter<_, UberStyle<_>> = Painter::new(f)?;
+            painter.cfg(|i| {
+                let env = i.mut_env();
+                env.sun_rotation = sun_towards(Vector3::new(0.3, 0.3, -1.));
+                env.sun_color = [1., 0.9, 0.8, 3.];
+                env.sun_included = scene == BackgroundEnv;
+            });
+            painter.clear_env(ctx);
+        },
+        VolumeSlice => {
+            let mut painter: Painter<_, VolumeStyle<_>> = Painter::new(f)?;
+            painter.setup(f, Primitive::TriangleList)?;
+            painter.cfg(|i| i.set_mode(VolumeMode::Mip));
+            let n = 32;
+            let mut data = Vec::with_capacity(n * n * n);
+            for z in 0..n {
+                for y in 0..n {
+                    for x in 0..n {
+                        let c = |v: usize| v as f32 / (n - 1) as f32 - 0.5;
+                        let r = (c(x) * c(x) + c(y) * c(y) + c(z) * c(z)).sqrt();
+                        data.push(((1. - r * 2.).max(0.) * 255.) as u8);
+                    }
+                }
+            }
+            let mat = VolumeMaterial {
+                data: VolumeData::Scalar(::load::load_volume(f, n as u16, n as u16, n as u16, &data)?),
+                transfer: ::load::load_transfer_function(f, &[[0, 0, 0, 0], [255, 128, 0, 128], [255, 255, 255, 255]])?,
+            };
+            let cube = volume_box().upload(f);
+            painter.try_draw_with(ctx, na::convert(Translation3::new(0., 0.5, 0.)), &cube, &mat)?;
+        },
+    }
+    Ok(())
+}
+
+/// Render one of the built in reference scenes with a fresh headless context
+pub fn render_golden(scene: GoldenScene) -> Result<RgbaImage, Error> {
+    Headless::new()?.render(|f, ctx| draw_scene(scene, f, ctx))
+}
+
+/// Compare two images, returning how many pixels are noticeably different along with an
+/// image showing them in red over a faded copy of `expected`. The difference of each pixel
+/// is measured in YIQ space, weighted towards brightness the way eyes are (Kotsarenko and
+/// Ramos, "Measuring perceived color difference using YIQ NTSC transmission color space"),
+/// and `threshold` runs from 0 (any change) to 1 (black against white).
+pub fn perceptual_diff(expected: &RgbaImage, actual: &RgbaImage, threshold: f32) -> (usize, RgbaImage) {
+    fn yiq(p: &Rgba<u8>) -> [f32; 3] {
+        // blend onto white so transparent pixels compare by what they show
+        let a = p.data[3] as f32 / 255.;
+        let c = |i: usize| 255. + (p.data[i] as f32 - 255.) * a;
+        let (r, g, b) = (c(0), c(1), c(2));
+        [
+            0.298_895_31 * r + 0.586_622_47 * g + 0.114_482_23 * b,
+            0.595_977_99 * r - 0.274_176_10 * g - 0.321_801_89 * b,
+            0.211_470_17 * r - 0.522_617_11 * g + 0.311_146_94 * b,
+        ]
+    }
+    // the largest possible delta, between black and white
+    const MAX_DELTA: f32 = 35215.;
+
+    let (w, h) = expected.dimensions();
+    let mut diff = RgbaImage::new(w, h);
+    let mut count = 0;
+    for (x, y, e) in expected.enumerate_pixels() {
+        let differs = if x < actual.width() && y < actual.height() {
+            let (a, b) = (yiq(e), yiq(actual.get_pixel(x, y)));
+            let (dy, di, dq) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+            0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq > MAX_DELTA * threshold * threshold
+        } else {
+            true
+        };
+        let out = if differs {
+            count += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let l = (255. - (255. - yiq(e)[0]) * 0.1) as u8;
+            Rgba([l, l, l, 255])
+        };
+        diff.put_pixel(x, y, out);
+    }
+    (count, diff)
+}
+
+/// Checks rendered images against golden PNGs kept in a directory
+pub struct GoldenCheck {
+    /// Where the goldens are (`<name>.png`), and where failures write `<name>.actual.png`
+    /// and `<name>.diff.png`
+    pub dir: PathBuf,
+    /// The per-pixel threshold of `perceptual_diff`
+    pub threshold: f32,
+    /// The fraction of pixels allowed to differ, for driver differences in rasterization
+    pub max_fraction: f32,
+}
+
+impl GoldenCheck {
+    /// Check against the goldens in `dir`, allowing small differences
+    pub fn new<P: Into<PathBuf>>(dir: P) -> GoldenCheck {
+        GoldenCheck {
+            dir: dir.into(),
+            threshold: 0.1,
+            max_fraction: 0.002,
+        }
+    }
+
+    /// The goldens of the built in scenes, kept with this crate
+    pub fn builtin() -> GoldenCheck {
+        GoldenCheck::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden"))
+    }
+
+    /// Compare `image` with the golden of the given name. If `BLESS_VAR` is set the golden
+    /// is replaced instead.
+    pub fn check(&self, name: &str, image: &RgbaImage) -> Result<(), Error> {
+        let golden = self.dir.join(format!("{}.png", name));
+        if env::var_os(BLESS_VAR).is_some() {
+            fs::create_dir_all(&self.dir)?;
+            image.save(&golden)?;
+            return Ok(());
+        }
+        let expected = match image::open(&golden) {
+            Ok(i) => i.to_rgba(),
+            Err(_) => {
+                image.save(self.dir.join(format!("{}.actual.png", name)))?;
+                bail!(FlightError::MissingGolden { name: name.to_string() });
+            },
+        };
+        let (count, diff) = perceptual_diff(&expected, image, self.threshold);
+        let total = (expected.width() * expected.height()) as usize;
+        if expected.dimensions() != image.dimensions() || count as f32 > total as f32 * self.max_fraction {
+            image.save(self.dir.join(format!("{}.actual.png", name)))?;
+            diff.save(self.dir.join(format!("{}.diff.png", name)))?;
+            bail!(FlightError::GoldenMismatch {
+                name: name.to_string(),
+                differing: count,
+                total: total,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Draw a red uber sphere with the given exposure partly in front of an unlit panel of a
+/// reference gray. No shade of the sphere can be mistaken for the gray.
+fn draw_ui_over_scene(
+    exposure: f32,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    let mut uber: Painter<_, UberStyle<_>> = Painter::new(f)?;
+    uber.setup(f, Primitive::TriangleList)?;
+    uber.cfg(|i| i.set_exposure(exposure));
+    let ball = gen::sphere(0.4, 24, 12);
+    let mat = uber_material(f, [200, 60, 40, 255], [0, 120, 0, 0], ball.mat)?;
+    let ball = ball.with_material(mat).upload(f);
+    uber.try_draw(ctx, na::convert(Translation3::new(0.8, 0.5, 1.)), &ball)?;
+
+    let mut unlit: Painter<_, UnlitStyle<_>> = Painter::new(f)?;
+    unlit.setup(f, Primitive::TriangleList)?;
+    let panel = gen::quad(2., 1.).with_material(UnlitMaterial {
+        color: Texture::uniform_value(f, [128, 128, 128, 255])?,
+    }).upload(f);
+    unlit.try_draw(ctx, na::convert(Translation3::new(0., 0.5, 0.)), &panel)?;
+    Ok(())
+}
+
+#[test]
+fn ui_ignores_exposure() {
+    let mut context = Headless::new().unwrap();
+    let dark = context.render(|f, ctx| draw_ui_over_scene(0.1, f, ctx)).unwrap();
+    let bright = context.render(|f, ctx| draw_ui_over_scene(10., f, ctx)).unwrap();
+    let gray = Rgba([128, 128, 128, 255]);
+    let center = (GOLDEN_WIDTH as u32 / 2, GOLDEN_HEIGHT as u32 / 2);
+    assert_eq!(dark.get_pixel(center.0, center.1), &gray);
+    assert_eq!(bright.get_pixel(center.0, center.1), &gray);
+    // the exposure changed the sphere, which hid the same part of the panel both times
+    assert!(dark.pixels().zip(bright.pixels()).any(|(a, b)| a != b));
+    let panel = |i: &RgbaImage| i.pixels().filter(|&p| *p == gray).count();
+    assert_eq!(panel(&dark), panel(&bright));
+}
+
+/// Draw a gray floor with a white marking in exactly the same plane, seen by two eyes side by
+/// side with their heads at `head` looking at the marking
+fn draw_floor_marking(
+    head: Point3<f32>,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    let (w, h) = (GOLDEN_WIDTH / 2, GOLDEN_HEIGHT);
+    let proj = Perspective3::new(w as f32 / h as f32, FRAC_PI_3, 0.1, 100.).to_homogeneous();
+    let eye = |offset: f32| {
+        let pos = head + Vector3::x() * offset;
+        Isometry3::look_at_rh(&pos, &Point3::new(offset, 0., 0.), &Vector3::y()).to_homogeneous()
+    };
+    ctx.push_camera(eye(0.032), proj, Rect { x: w, y: 0, w: w, h: h });
+    let right = ctx.left;
+    ctx.pop_camera();
+    ctx.push_camera(eye(-0.032), proj, Rect { x: 0, y: 0, w: w, h: h });
+    let left = ctx.left;
+    ctx.pop_camera();
+    ctx.push_eyes(left, right);
+
+    let mut unlit: Painter<_, UnlitStyle<_>> = Painter::new(f)?;
+    let bias = DepthBias::new(2, 2);
+    unlit.setup(f, Primitive::TriangleList)?;
+    unlit.setup_depth_bias(f, Primitive::TriangleList, bias)?;
+    let flat: Transform3<f32> = na::convert(Isometry3::new(Vector3::zeros(), Vector3::x() * -FRAC_PI_2));
+    let floor = gen::quad(20., 20.).with_material(UnlitMaterial {
+        color: Texture::uniform_value(f, [100, 100, 100, 255])?,
+    }).upload(f);
+    let marking = gen::quad(1., 1.).with_material(UnlitMaterial {
+        color: Texture::uniform_value(f, [255, 255, 255, 255])?,
+    }).upload(f);
+    unlit.try_draw(ctx, flat, &floor)?;
+    unlit.try_draw_biased(ctx, flat, &marking, bias)?;
+    ctx.pop_camera();
+    Ok(())
+}
+
+#[test]
+fn coplanar_marking() {
+    let mut context = Headless::new().unwrap();
+    let white = Rgba([255, 255, 255, 255]);
+    // the head moves around and further away, seeing the floor at ever more grazing angles
+    for i in 0..8 {
+        let a = i as f32 * 0.8;
+        let d = 1.5 + i as f32 * 0.5;
+        let head = Point3::new(a.sin() * d, 1.6, a.cos() * d);
+        let image = context.render(|f, ctx| draw_floor_marking(head, f, ctx)).unwrap();
+        let (w, h) = (GOLDEN_WIDTH as u32 / 2, GOLDEN_HEIGHT as u32);
+        for &x in &[w / 2, w + w / 2] {
+            for dx in 0..3 {
+                for dy in 0..3 {
+                    assert_eq!(image.get_pixel(x + dx - 1, h / 2 + dy - 1), &white, "head at {}", head);
+                }
+            }
+        }
+    }
+}
+
+/// Draw a sphere with a layered material of `layer` over `base`, masked by `mask`, or with
+/// only `base` through the uber style if there is no layer
+fn draw_layered_sphere(
+    base: [u8; 4],
+    layer: Option<([u8; 4], u8)>,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    let ball = gen::sphere(0.8, 24, 12);
+    let model = na::convert(Translation3::new(0., 0.5, 0.));
+    let base = uber_material(f, base, [0, 120, 0, 0], ball.mat)?;
+    match layer {
+        Some((albedo, mask)) => {
+            let mut layered: Painter<_, LayeredStyle<_>> = Painter::new(f)?;
+            layered.setup(f, Primitive::TriangleList)?;
+            let mat = LayeredMaterial {
+                base: base,
+                layers: vec![(uber_material(f, albedo, [0, 200, 0, 0], ball.mat)?, Texture::uniform_value(f, mask)?)],
+            };
+            layered.try_draw(ctx, model, &ball.with_material(mat).upload(f))
+        },
+        None => {
+            let mut uber: Painter<_, UberStyle<_>> = Painter::new(f)?;
+            uber.setup(f, Primitive::TriangleList)?;
+            uber.try_draw(ctx, model, &ball.with_material(base).upload(f))
+        },
+    }
+}
+
+#[test]
+fn layered_masks() {
+    let mut context = Headless::new().unwrap();
+    let (red, white) = ([200, 60, 40, 255], [240, 240, 240, 255]);
+    let plain = context.render(|f, ctx| draw_layered_sphere(red, None, f, ctx)).unwrap();
+    // an empty mask leaves the base alone
+    let hidden = context.render(|f, ctx| draw_layered_sphere(red, Some((white, 0)), f, ctx)).unwrap();
+    assert_eq!(perceptual_diff(&plain, &hidden, 0.).0, 0);
+    // a full mask covers the base, roughness included
+    let snow = context.render(|f, ctx| draw_layered_sphere(red, Some((white, 255)), f, ctx)).unwrap();
+    assert!(perceptual_diff(&plain, &snow, 0.1).0 > 0);
+    let covered = context.render(|f, ctx| draw_layered_sphere(white, Some((white, 255)), f, ctx)).unwrap();
+    assert_eq!(perceptual_diff(&snow, &covered, 0.).0, 0);
+}
+
+/// Draw a gray quad facing the camera through a visibility buffer, lit straight on by the
+/// sun, keeping only the given triangles of its two
+fn draw_visibility_quad(
+    triangles: ::std::ops::Range<u32>,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    let quad = gen::quad(2., 2.);
+    let inds = match quad.inds {
+        Indexing::Inds(ref i) => i.clone(),
+        _ => unreachable!(),
+    };
+    let gray = Texture::uniform_value(f, [128, 128, 128, 255])?;
+    let mesh = VisibilityMesh::new(f, &quad.verts, &inds, gray)?;
+    let mut vis = VisibilityBuffer::new(f, GOLDEN_WIDTH, GOLDEN_HEIGHT)?;
+    vis.sun_dir = Vector3::z();
+    vis.ambient = [0., 0., 0.];
+    vis.clear(ctx);
+    vis.draw(ctx, &mesh, triangles);
+    vis.resolve(ctx, &mesh);
+    Ok(())
+}
+
+#[test]
+fn visibility_buffer() {
+    let mut context = Headless::new().unwrap();
+    let full = context.render(|f, ctx| draw_visibility_quad(0..2, f, ctx)).unwrap();
+    let (w, h) = (GOLDEN_WIDTH as u32, GOLDEN_HEIGHT as u32);
+    let center = *full.get_pixel(w / 2, h / 2);
+    assert!(center.data[0] > 64);
+    assert_eq!(center.data[0], center.data[1]);
+    assert_eq!(center.data[1], center.data[2]);
+    // nothing was drawn in the corners
+    assert_eq!(full.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+    // the shading is the same across the quad
+    let lit = full.pixels().filter(|p| p.data[0] > 0).collect::<Vec<_>>();
+    assert!(lit.iter().all(|p| (0..3).all(|i| (p.data[i] as i32 - center.data[i] as i32).abs() <= 1)));
+
+    // the center is on the second triangle, which keeps its ID when drawn alone
+    let half = context.render(|f, ctx| draw_visibility_quad(1..2, f, ctx)).unwrap();
+    assert_eq!(half.get_pixel(w / 2, h / 2), &center);
+    let half_lit = half.pixels().filter(|p| p.data[0] > 0).count();
+    assert!(half_lit > 0 && half_lit < lit.len());
+}
+
+/// Draw a quad colored `color`, either through a virtual texture or a plain albedo map
+fn draw_virtual_quad(
+    color: [u8; 4],
+    virtual_texture: bool,
+    f: &mut Factory,
+    ctx: &mut DrawParams<Resources, CommandBuffer>,
+)
+    -> Result<(), Error>
+{
+    let quad = gen::quad(2., 2.);
+    let model = na::convert(Translation3::new(0., 0.5, 0.));
+    let knobs = [0, 200, 0, 0];
+    if virtual_texture {
+        let img = RgbaImage::from_pixel(PAGE_SIZE * 2, PAGE_SIZE * 2, Rgba(color));
+        let mut io = ImagePages::new(img, (2, 2));
+        let mut texture = VirtualTexture::new(f, (2, 2), 2)?;
+        for &(u, v) in &[(0.25, 0.25), (0.75, 0.25), (0.25, 0.75)] {
+            texture.request_page(u, v, 0);
+        }
+        texture.tick(&mut ctx.encoder, &mut io)?;
+        let mat = VirtualMaterial::new(uber_material(f, [0xFF; 4], knobs, quad.mat)?, &texture);
+        let mut painter: Painter<_, VirtualTextureStyle<_>> = Painter::new(f)?;
+        painter.setup(f, Primitive::TriangleList)?;
+        painter.try_draw(ctx, model, &quad.with_material(mat).upload(f))
+    } else {
+        let mat = uber_material(f, color, knobs, quad.mat)?;
+        let mut painter: Painter<_, UberStyle<_>> = Painter::new(f)?;
+        painter.setup(f, Primitive::TriangleList)?;
+        painter.try_draw(ctx, model, &quad.with_material(mat).upload(f))
+    }
+}
+
+#[test]
+fn virtual_texture() {
+    let mut context = Headless::new().unwrap();
+    let color = [60, 140, 200, 255];
+    let plain = context.render(|f, ctx| draw_virtual_quad(color, false, f, ctx)).unwrap();
+    let paged = context.render(|f, ctx| draw_virtual_quad(color, true, f, ctx)).unwrap();
+    let black = RgbaImage::from_pixel(GOLDEN_WIDTH as u32, GOLDEN_HEIGHT as u32, Rgba([0, 0, 0, 255]));
+    assert!(perceptual_diff(&black, &paged, 0.1).0 > 0);
+    // loaded and missing pages alike show the same uniform color
+    assert_eq!(perceptual_diff(&plain, &paged, 0.02).0, 0);
+}
+
+#[test]
+fn texture_download() {
+    let mut context = Headless::new().unwrap();
+    let image = context.render(|f, ctx| draw_scene(GoldenScene::MaterialLadder, f, ctx)).unwrap();
+
+    let encoder = context.factory.create_command_buffer().into();
+    let mut ctx = DrawParams::new(encoder, context.target.color.clone(), context.target.depth.clone());
+    let download: TextureDownload<_, [u8; 4]> = TextureDownload::queue::<_, _, ::ColorFormat>(
+        &mut context.factory,
+        &mut ctx,
+        &context.target.color_texture,
+    ).unwrap();
+    ctx.encoder.flush(&mut context.device);
+    assert_eq!(download.size(), (GOLDEN_WIDTH as usize, GOLDEN_HEIGHT as usize));
+    // the copy isn't read until enough frames have begun for the GPU to finish it
+    for _ in 0..draw::FRAME_RING_SIZE {
+        assert!(download.poll(&mut context.factory).is_none());
+        ctx.begin_frame();
+    }
+    let data = download.poll(&mut context.factory).unwrap().unwrap();
+    let pixels: Vec<u8> = data.iter().flat_map(|p| p.iter().cloned()).collect();
+    assert_eq!(pixels, image.into_raw());
+}
+
+#[test]
+fn temporal_history() {
+    use gfx::memory::Typed;
+
+    let mut context = Headless::new().unwrap();
+    let mut history = TemporalHistory::new(&mut context.factory, 4, 4).unwrap();
+    let mut tracker = ResourceStateTracker::new();
+    history.register(&mut tracker);
+    let mut encoder: ::gfx::Encoder<_, _> = context.factory.create_command_buffer().into();
+    assert_eq!(history.frames(), 0);
+    for &color in &[[1., 0., 0., 1.], [0., 1., 0., 1.], [0., 0., 1., 1.]] {
+        encoder.clear(&history.current.color, color);
+        // sampling the last frame while drawing is fine, sampling the frame being drawn isn't
+        let previous = history.previous_as_texture();
+        assert!(tracker.check(history.current.color.raw(), &[previous.raw().clone()]).is_ok());
+        let current = history.current.texture.buffer.raw().clone();
+        assert!(tracker.check(history.current.color.raw(), &[current]).is_err());
+        history.swap();
+    }
+    assert_eq!(history.frames(), 2);
+    let mut read = |t: &OffscreenTarget<Resources>| {
+        let image = draw::capture_frame_rgba8(&mut context.factory, &mut encoder, &mut context.device, &t.color_texture).unwrap();
+        *image.get_pixel(1, 1)
+    };
+    assert_eq!(read(&history.previous), Rgba([0, 0, 255, 255]));
+    assert_eq!(read(&history.penultimate), Rgba([0, 255, 0, 255]));
+}
+
+#[test]
+fn lod_transition() {
+    let mut draws = Vec::new();
+    Headless::new().unwrap().render(|f, ctx| {
+        let mat = AlphaHashMaterial { color: Texture::uniform_value(f, [255, 255, 255, 255])?, alpha: 1. };
+        let near = gen::sphere(0.5, 16, 8).with_material(mat.clone()).upload(f);
+        let far = gen::sphere(0.5, 6, 3).with_material(mat).upload(f);
+        let mut painter: Painter<_, AlphaHashStyle<_>> = Painter::new(f)?;
+        painter.setup(f, Primitive::TriangleList)?;
+        let mut lod = LodTransition::new(near, far, 10., 2.);
+        let step = |lod: &mut LodTransition<_, _, _>, dist: f32, ctx: &mut DrawParams<_, _>| {
+            lod.update(dist, 0.1, 2.);
+            let before = ctx.draw_calls;
+            painter.try_draw_lod_transition(ctx, na::one(), lod).map(|_| (lod.t(), ctx.draw_calls - before))
+        };
+        // inside the band around the switch distance nothing starts
+        draws.push(step(&mut lod, 10.5, ctx)?);
+        draws.push(step(&mut lod, 11.5, ctx)?);
+        // coming back inside the band keeps fading out
+        draws.push(step(&mut lod, 10.5, ctx)?);
+        for _ in 0..3 {
+            draws.push(step(&mut lod, 10.5, ctx)?);
+        }
+        draws.push(step(&mut lod, 8., ctx)?);
+        Ok(())
+    }).unwrap();
+    let t: Vec<f32> = draws.iter().map(|d| d.0).collect();
+    for (a, b) in t.iter().zip(&[0., 0.2, 0.4, 0.6, 0.8, 1., 0.8]) {
+        assert!(relative_eq!(*a, *b, epsilon = 1e-5));
+    }
+    // both meshes are drawn only while fading
+    let single = draws[0].1;
+    assert!(single > 0);
+    assert_eq!(draws[1].1, single * 2);
+    assert_eq!(draws[5].1, single);
+    assert_eq!(draws[6].1, single * 2);
+}
+
+#[test]
+fn deformable_terrain() {
+    let mut context = Headless::new().unwrap();
+    let f = &mut context.factory;
+    let mut encoder: ::gfx::Encoder<_, _> = f.create_command_buffer().into();
+    let mat = uber_material(f, [128, 128, 128, 255], [0, 200, 0, 0], gen::Surface::Mesh).unwrap();
+    assert!(DeformableTerrain::new(f, &mut encoder, vec![0.; 10], 4, 4, 1., mat.clone()).is_err());
+    let mut terrain = DeformableTerrain::new(f, &mut encoder, vec![0.; 64 * 64], 64, 64, 0.5, mat).unwrap();
+    assert!(terrain.dirty_range().is_none());
+
+    terrain.deform([10., 5., 10.], 2., 1., DeformMode::Raise);
+    // the middle of the brush goes all the way up, the edge and outside stay
+    assert!(relative_eq!(terrain.heights()[20 * 64 + 20], 1.));
+    assert!(terrain.heights()[20 * 64 + 22] > 0. && terrain.heights()[20 * 64 + 22] < 1.);
+    assert_eq!(terrain.heights()[20 * 64 + 24], 0.);
+    assert_eq!(terrain.heights()[30 * 64 + 20], 0.);
+    // only the rows under the brush, and one more each way for the normals, are uploaded
+    let (first, last) = terrain.dirty_range().unwrap();
+    assert_eq!((first, last), (15 * 64 + 15, 25 * 64 + 25));
+    assert!(terrain.mesh.bounds.max.y >= 1.);
+    terrain.upload(&mut encoder).unwrap();
+    assert!(terrain.dirty_range().is_none());
+
+    // smoothing lowers the peak, digging below the ground is fine
+    terrain.deform([10., 0., 10.], 1., 1., DeformMode::Smooth);
+    assert!(terrain.heights()[20 * 64 + 20] < 1.);
+    terrain.deform([0., 0., 0.], 1., 2., DeformMode::Lower);
+    assert!(relative_eq!(terrain.heights()[0], -2.));
+    assert_eq!(terrain.dirty_range().unwrap().0, 0);
+    terrain.upload(&mut encoder).unwrap();
+    encoder.flush(&mut context.device);
+}
+
+#[test]
+fn stereo_360_capture() {
+    let mut context = Headless::new().unwrap();
+    let mut capture = Stereo360Capture::with_size(&mut context.factory, 64, 32).unwrap();
+    let save_base = env::temp_dir().join(format!("flight_stereo_{}", ::std::process::id()));
+    assert!(capture.save_pair(&save_base).is_err());
+
+    let encoder = context.factory.create_command_buffer().into();
+    let mut ctx = DrawParams::new(encoder, context.target.color.clone(), context.target.depth.clone());
+    let mut eyes = Vec::new();
+    // each face is filled with the direction it looks in
+    let (left, right) = capture.capture(&mut context.factory, &mut ctx, &mut context.device, Point3::new(0., 1., 0.), |ctx| {
+        let forward = ctx.left.view.try_inverse().unwrap() * -Vector3::z();
+        eyes.push((forward, ctx.left.eye));
+        let c = forward * 0.5 + Vector3::new(0.5, 0.5, 0.5);
+        ctx.encoder.clear(&ctx.color, [c.x, c.y, c.z, 1.]);
+        Ok(())
+    }).unwrap();
+    assert_eq!((left.width, left.height), (64, 32));
+    // looking down -z from the middle of the image, and +x three quarters across
+    let linear = |v: f32| v.powf(::OUTPUT_GAMMA);
+    let expect = |image: &::draw::Hdr32Image, x: usize, c: [f32; 3]| {
+        let p = image.data[16 * 64 + x];
+        for i in 0..3 {
+            assert!((p[i] - linear(c[i])).abs() < 0.02, "{:?} isn't {:?}", p, c);
+        }
+    };
+    expect(left, 32, [0.5, 0.5, 0.]);
+    expect(right, 32, [0.5, 0.5, 0.]);
+    expect(left, 48, [1., 0.5, 0.5]);
+
+    // the eyes are apart across the horizontal faces, and together looking up and down
+    assert_eq!(eyes.len(), 12);
+    for (l, r) in eyes[..6].iter().zip(&eyes[6..]) {
+        let apart = (r.1 - l.1).norm();
+        if l.0.y.abs() > 0.5 {
+            assert!(relative_eq!(apart, 0., epsilon = 1e-6));
+        } else {
+            assert!(relative_eq!(apart, 0.065, epsilon = 1e-5));
+            // the right eye is on the right
+            assert!(l.0.cross(&Vector3::y()).dot(&(r.1 - l.1)) > 0.);
+        }
+    }
+
+    capture.save_pair(&save_base).unwrap();
+    let (left_path, right_path) = Stereo360Capture::<Resources>::pair_paths(&save_base);
+    assert!(left_path.to_string_lossy().ends_with("_left.hdr"));
+    fs::remove_file(left_path).unwrap();
+    fs::remove_file(right_path).unwrap();
+}
+
+#[test]
+fn golden_images() {
+    let a = RgbaImage::from_pixel(4, 4, Rgba([100, 150, 200, 255]));
+    let mut b = a.clone();
+    b.put_pixel(1, 1, Rgba([102, 151, 199, 255]));
+    b.put_pixel(2, 3, Rgba([255, 0, 0, 255]));
+    let (count, diff) = perceptual_diff(&a, &b, 0.1);
+    assert_eq!(count, 1);
+    assert_eq!(diff.get_pixel(2, 3), &Rgba([255, 0, 0, 255]));
+    assert_eq!(perceptual_diff(&a, &a, 0.).0, 0);
+
+    let check = GoldenCheck::builtin();
+    let mut context = Headless::new().unwrap();
+    for &scene in &GOLDEN_SCENES {
+        let image = context.render(|f, ctx| draw_scene(scene, f, ctx)).unwrap();
+        assert_eq!(image.dimensions(), (GOLDEN_WIDTH as u32, GOLDEN_HEIGHT as u32));
+        if let Err(e) = check.check(scene.name(), &image) {
+            panic!("{} (run with {}=1 to accept the new output)", e, BLESS_VAR);
+        }
+    }
+}
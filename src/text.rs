@@ -0,0 +1,216 @@
+use gfx::{Resources, Factory};
+use gfx::format::*;
+use fnv::FnvHashMap;
+use serde_json::{self, Value};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use ::{Error, FlightError, Texture};
+use ::draw::SdfFontMaterial;
+use ::mesh::{MeshSource, Indexing, Primitive, VertNTT};
+
+/// Placement of a single character in a font atlas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Glyph {
+    /// Distance from this glyph's pen position to the next, in ems
+    pub advance: f32,
+    /// Bounds of the glyph quad relative to the pen position in ems (left, bottom,
+    /// right, top), or `None` for blank characters such as space
+    pub plane: Option<[f32; 4]>,
+    /// Texture coordinates of the glyph quad (left, bottom, right, top)
+    pub uv: [f32; 4],
+}
+
+/// Glyph metrics of a distance field font atlas
+#[derive(Clone, Debug)]
+pub struct FontMetrics {
+    pub glyphs: FnvHashMap<char, Glyph>,
+    /// Distance between baselines, in ems
+    pub line_height: f32,
+    /// The range of distances stored in the atlas, in pixels
+    pub distance_range: f32,
+    /// The atlas is a multi-channel distance field
+    pub msdf: bool,
+}
+
+fn field<'a>(v: &'a Value, key: &'static str) -> Result<&'a Value, Error> {
+    v.get(key).ok_or_else(|| FlightError::InvalidFontMetrics { field: key }.into())
+}
+
+fn number(v: &Value, key: &'static str) -> Result<f32, Error> {
+    match field(v, key)?.as_f64() {
+        Some(n) => Ok(n as f32),
+        None => Err(FlightError::InvalidFontMetrics { field: key }.into()),
+    }
+}
+
+fn bounds(v: &Value) -> Result<[f32; 4], Error> {
+    Ok([number(v, "left")?, number(v, "bottom")?, number(v, "right")?, number(v, "top")?])
+}
+
+/// Parse the JSON glyph metrics written by msdf-atlas-gen
+pub fn parse_font_metrics(json: &str) -> Result<FontMetrics, Error> {
+    let root: Value = serde_json::from_str(json)?;
+    let atlas = field(&root, "atlas")?;
+    let width = number(atlas, "width")?;
+    let height = number(atlas, "height")?;
+    let top_origin = atlas.get("yOrigin").and_then(Value::as_str) == Some("top");
+    let msdf = match atlas.get("type").and_then(Value::as_str) {
+        Some("msdf") | Some("mtsdf") => true,
+        _ => false,
+    };
+
+    let mut glyphs = FnvHashMap::default();
+    let list = field(&root, "glyphs")?.as_array()
+        .ok_or(FlightError::InvalidFontMetrics { field: "glyphs" })?;
+    for g in list {
+        let code = number(g, "unicode")? as u32;
+        let c = match ::std::char::from_u32(code) {
+            Some(c) => c,
+            None => continue,
+        };
+        let (plane, uv) = match (g.get("planeBounds"), g.get("atlasBounds")) {
+            (Some(p), Some(a)) => {
+                let a = bounds(a)?;
+                // texture coordinates have their origin at the bottom of the image
+                let (b, t) = if top_origin {
+                    (1. - a[1] / height, 1. - a[3] / height)
+                } else {
+                    (a[1] / height, a[3] / height)
+                };
+                (Some(bounds(p)?), [a[0] / width, b, a[2] / width, t])
+            },
+            _ => (None, [0.; 4]),
+        };
+        glyphs.insert(c, Glyph {
+            advance: number(g, "advance")?,
+            plane: plane,
+            uv: uv,
+        });
+    }
+
+    let line_height = match root.get("metrics") {
+        Some(m) => number(m, "lineHeight")?,
+        None => 1.,
+    };
+    Ok(FontMetrics {
+        glyphs: glyphs,
+        line_height: line_height,
+        distance_range: number(atlas, "distanceRange")?,
+        msdf: msdf,
+    })
+}
+
+/// A signed distance field font: an atlas of glyph distance fields and their metrics
+pub struct SdfFontAtlas<R: Resources> {
+    pub atlas: Texture<R, (R8_G8_B8_A8, Unorm)>,
+    pub metrics: FontMetrics,
+}
+
+impl<R: Resources> SdfFontAtlas<R> {
+    /// Load an atlas image and the JSON metrics generated with it (e.g. by msdf-atlas-gen)
+    pub fn open<F, P1, P2>(f: &mut F, image: P1, metrics: P2) -> Result<SdfFontAtlas<R>, Error>
+        where F: Factory<R>, P1: AsRef<Path>, P2: AsRef<Path>
+    {
+        use gfx::texture::*;
+        let mut json = String::new();
+        File::open(metrics)?.read_to_string(&mut json)?;
+        let sampler = f.create_sampler(SamplerInfo::new(
+            FilterMethod::Bilinear,
+            WrapMode::Clamp));
+        Ok(SdfFontAtlas {
+            atlas: ::load::open_rgba8(f, image, sampler)?,
+            metrics: parse_font_metrics(&json)?,
+        })
+    }
+
+    /// A material drawing this font in the given color
+    pub fn material(&self, color: [u8; 4]) -> SdfFontMaterial<R> {
+        SdfFontMaterial {
+            atlas: self.atlas.clone(),
+            msdf: self.metrics.msdf,
+            color: color,
+        }
+    }
+}
+
+/// Builds meshes of text for drawing with `draw::SdfStyle`
+pub struct TextMesh;
+
+impl TextMesh {
+    /// Lay out a string of text in the XY plane facing +Z, with the first baseline at
+    /// the origin. `size` is the height of an em. The text is white; replace the
+    /// material to change the color.
+    pub fn from_string<R: Resources>(font: &SdfFontAtlas<R>, text: &str, size: f32)
+        -> MeshSource<VertNTT, SdfFontMaterial<R>>
+    {
+        let (verts, inds) = layout(&font.metrics, text, size);
+        MeshSource {
+            verts: verts,
+            inds: Indexing::Inds(inds),
+            prim: Primitive::TriangleList,
+            mat: font.material([255; 4]),
+        }
+    }
+}
+
+fn layout(metrics: &FontMetrics, text: &str, size: f32) -> (Vec<VertNTT>, Vec<u32>) {
+    let mut verts = Vec::new();
+    let mut inds = Vec::new();
+    let (mut x, mut y) = (0., 0.);
+    for c in text.chars() {
+        if c == '\n' {
+            x = 0.;
+            y -= metrics.line_height * size;
+            continue;
+        }
+        let g = match metrics.glyphs.get(&c).or_else(|| metrics.glyphs.get(&'?')) {
+            Some(g) => g,
+            None => continue,
+        };
+        if let Some(p) = g.plane {
+            let base = verts.len() as u32;
+            for &(i, j) in &[(0, 1), (2, 1), (2, 3), (0, 3)] {
+                verts.push(VertNTT {
+                    pos: [x + p[i] * size, y + p[j] * size, 0.],
+                    norm: [0., 0., 1.],
+                    tan: [1., 0., 0.],
+                    bitan: [0., 1., 0.],
+                    tex: [g.uv[i], g.uv[j]],
+                });
+            }
+            inds.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        x += g.advance * size;
+    }
+    (verts, inds)
+}
+
+#[test]
+fn text_layout() {
+    let metrics = parse_font_metrics(r#"{
+        "atlas": { "type": "msdf", "distanceRange": 4, "size": 32, "width": 64, "height": 32, "yOrigin": "top" },
+        "metrics": { "emSize": 1, "lineHeight": 1.25 },
+        "glyphs": [
+            { "unicode": 32, "advance": 0.25 },
+            { "unicode": 65, "advance": 0.5,
+              "planeBounds": { "left": 0, "bottom": -0.25, "right": 0.5, "top": 0.75 },
+              "atlasBounds": { "left": 0, "bottom": 32, "right": 16, "top": 0 } }
+        ]
+    }"#).unwrap();
+    assert!(metrics.msdf);
+    assert_eq!(metrics.glyphs[&'A'].uv, [0., 0., 0.25, 1.]);
+    assert_eq!(metrics.glyphs[&' '].plane, None);
+
+    let (verts, inds) = layout(&metrics, "A A\nA", 2.);
+    assert_eq!(verts.len(), 12);
+    assert_eq!(inds.len(), 18);
+    // the second glyph is past the first and a space
+    assert_eq!(verts[4].pos, [1.5, -0.5, 0.]);
+    // the third glyph starts a new line
+    assert_eq!(verts[8].pos, [0., -2.5 - 0.5, 0.]);
+    assert_eq!(verts[10].tex, [0.25, 1.]);
+
+    assert!(parse_font_metrics(r#"{ "glyphs": [] }"#).is_err());
+}
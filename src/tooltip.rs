@@ -0,0 +1,343 @@
+use gfx::{Resources, CommandBuffer, Slice, Primitive};
+use gfx::traits::FactoryExt;
+use nalgebra::{self as na, Point3, Vector3, Isometry3, Transform3, Translation3};
+use fnv::FnvHashMap;
+
+use ::Error;
+use ::draw::{DrawParams, Painter, SdfStyle, SdfFontMaterial, SolidStyle};
+use ::mesh::{Mesh, VertC, VertNTT, Aabb};
+use ::text::{SdfFontAtlas, TextMesh};
+use ::vr::MappedController;
+
+/// Which hand holds a controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+/// A button of a controller that a tooltip can point at
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ControllerButton {
+    Trigger,
+    Grip,
+    Menu,
+    /// The circular touch pad
+    Pad,
+}
+
+/// How far the trigger has to be pulled to count as used
+const TRIGGER_USED: f64 = 0.5;
+
+impl ControllerButton {
+    /// Is the button being used on the controller. The pad counts as used when touched.
+    pub fn used(self, controller: &MappedController) -> bool {
+        match self {
+            ControllerButton::Trigger => controller.trigger > TRIGGER_USED,
+            ControllerButton::Grip => controller.grip,
+            ControllerButton::Menu => controller.menu,
+            ControllerButton::Pad => controller.pad_touched,
+        }
+    }
+
+    /// Where the button is on the default controller mesh (a Vive wand), in controller
+    /// space with -Z pointing forward
+    pub fn default_anchor(self) -> Point3<f32> {
+        match self {
+            ControllerButton::Trigger => Point3::new(0., -0.035, -0.055),
+            ControllerButton::Grip => Point3::new(0.022, -0.012, 0.),
+            ControllerButton::Menu => Point3::new(0., 0.008, -0.08),
+            ControllerButton::Pad => Point3::new(0., 0.01, -0.05),
+        }
+    }
+}
+
+/// A label pointing at a controller button
+#[derive(Clone, Debug)]
+pub struct Tooltip {
+    pub hand: Hand,
+    pub button: ControllerButton,
+    pub text: String,
+    /// The button position in world space, where the leader line ends
+    pub anchor: Point3<f32>,
+    /// The center of the label in world space
+    pub position: Point3<f32>,
+    /// 1 while the label is shown, falling to 0 as it fades out
+    pub opacity: f32,
+    age: f32,
+    fading: Option<f32>,
+}
+
+/// Text labels attached to controller buttons for teaching the controls ("press trigger to
+/// select"). Labels sit a little above their buttons, are pushed apart so labels on one
+/// controller don't overlap, and fade out once their button is used or after `lifetime`.
+/// This only keeps the layout, see `TooltipDraw` for drawing it.
+#[derive(Clone, Debug)]
+pub struct ControllerTooltips {
+    /// Seconds a label is shown before it fades out on its own (infinite to wait for the
+    /// button to be used)
+    pub lifetime: f32,
+    /// Seconds a label takes to fade out
+    pub fade_time: f32,
+    /// Distance from a button to its label
+    pub offset: f32,
+    /// The closest two labels on one controller may be, as seen from the eye
+    pub spacing: f32,
+    tips: Vec<Tooltip>,
+    anchors: FnvHashMap<(Hand, ControllerButton), Point3<f32>>,
+}
+
+/// Rounds of pushing overlapping labels apart per update
+const SEPARATION_ROUNDS: usize = 8;
+
+impl ControllerTooltips {
+    /// Create an empty set of tooltips
+    pub fn new() -> ControllerTooltips {
+        ControllerTooltips {
+            lifetime: 10.,
+            fade_time: 0.5,
+            offset: 0.06,
+            spacing: 0.04,
+            tips: Vec::new(),
+            anchors: FnvHashMap::default(),
+        }
+    }
+
+    /// Show a label on a button, replacing any label it had and restarting its lifetime
+    pub fn set(&mut self, hand: Hand, button: ControllerButton, text: &str) {
+        self.remove(hand, button);
+        self.tips.push(Tooltip {
+            hand: hand,
+            button: button,
+            text: text.to_string(),
+            anchor: Point3::origin(),
+            position: Point3::origin(),
+            opacity: 1.,
+            age: 0.,
+            fading: None,
+        });
+    }
+
+    /// Remove the label from a button at once, without fading
+    pub fn remove(&mut self, hand: Hand, button: ControllerButton) {
+        self.tips.retain(|t| t.hand != hand || t.button != button);
+    }
+
+    /// Move where a button's label points, in controller space, for controller meshes other
+    /// than the default one
+    pub fn set_anchor(&mut self, hand: Hand, button: ControllerButton, anchor: Point3<f32>) {
+        self.anchors.insert((hand, button), anchor);
+    }
+
+    /// Go back to the default anchors of every button
+    pub fn reset_anchors(&mut self) {
+        self.anchors.clear();
+    }
+
+    /// Where a button's label points, in controller space
+    pub fn anchor(&self, hand: Hand, button: ControllerButton) -> Point3<f32> {
+        self.anchors.get(&(hand, button)).cloned().unwrap_or_else(|| button.default_anchor())
+    }
+
+    /// The labels still showing or fading out
+    pub fn tooltips(&self) -> &[Tooltip] {
+        &self.tips
+    }
+
+    /// Advance the labels of the controller in `hand` by `dt` seconds and place them
+    /// around it as seen from `eye`
+    pub fn update(&mut self, hand: Hand, controller: &MappedController, eye: Point3<f32>, dt: f32) {
+        let anchors: Vec<_> = self.tips.iter().map(|t| self.anchor(t.hand, t.button)).collect();
+        let (lifetime, fade_time, offset) = (self.lifetime, self.fade_time, self.offset);
+        for (t, local) in self.tips.iter_mut().zip(anchors) {
+            if t.hand != hand {
+                continue;
+            }
+            t.age += dt;
+            t.fading = match t.fading {
+                Some(f) => Some(f + dt),
+                None if t.age >= lifetime || t.button.used(controller) => Some(0.),
+                None => None,
+            };
+            t.opacity = match t.fading {
+                Some(f) if fade_time > 0. => (1. - f / fade_time).max(0.),
+                Some(_) => 0.,
+                None => 1.,
+            };
+            // labels rise above their buttons, and out to the side for buttons on the side
+            let side = if local.x.abs() > 1e-3 { local.x.signum() } else { 0. };
+            let out = Vector3::new(side, 1., 0.).normalize();
+            t.anchor = controller.pose * local;
+            t.position = t.anchor + controller.pose.rotation * out * offset;
+        }
+        self.tips.retain(|t| t.opacity > 0.);
+        self.separate(hand, eye);
+    }
+
+    /// Push the labels of one hand apart across the view until they are `spacing` apart
+    fn separate(&mut self, hand: Hand, eye: Point3<f32>) {
+        let mine: Vec<usize> = (0..self.tips.len()).filter(|&i| self.tips[i].hand == hand).collect();
+        for _ in 0..SEPARATION_ROUNDS {
+            let mut moved = false;
+            for (n, &i) in mine.iter().enumerate() {
+                for &j in &mine[n + 1..] {
+                    let (a, b) = (self.tips[i].position, self.tips[j].position);
+                    let view = (eye - na::center(&a, &b)).normalize();
+                    let apart = b - a;
+                    let across = apart - view * apart.dot(&view);
+                    let dist = across.norm();
+                    if dist >= self.spacing {
+                        continue;
+                    }
+                    // labels in line with each other split vertically
+                    let up = Vector3::y() - view * view.y;
+                    let dir = if dist > 1e-6 {
+                        across / dist
+                    } else if up.norm() > 1e-6 {
+                        up.normalize()
+                    } else {
+                        Vector3::x()
+                    };
+                    let push = dir * (self.spacing - dist) * 0.5;
+                    self.tips[i].position -= push;
+                    self.tips[j].position += push;
+                    moved = true;
+                }
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for ControllerTooltips {
+    fn default() -> ControllerTooltips {
+        ControllerTooltips::new()
+    }
+}
+
+/// Draws `ControllerTooltips` as distance field text turned toward the eye, with leader
+/// lines to the buttons. Leader lines are dropped once a label is half faded.
+pub struct TooltipDraw<R: Resources> {
+    /// Height of an em of label text
+    pub size: f32,
+    /// Label text color
+    pub color: [u8; 4],
+    /// Leader line color
+    pub line_color: [f32; 3],
+    font: SdfFontAtlas<R>,
+    meshes: FnvHashMap<String, Mesh<R, VertNTT, SdfFontMaterial<R>>>,
+}
+
+impl<R: Resources> TooltipDraw<R> {
+    /// Draw labels in the given font
+    pub fn new(font: SdfFontAtlas<R>) -> TooltipDraw<R> {
+        TooltipDraw {
+            size: 0.015,
+            color: [255; 4],
+            line_color: [1., 1., 1.],
+            font: font,
+            meshes: FnvHashMap::<String, Mesh<R, VertNTT, SdfFontMaterial<R>>>::default(),
+        }
+    }
+
+    /// Draw the labels where they were last updated. Text meshes are kept for labels that
+    /// are still showing and rebuilt when their text changes.
+    pub fn draw<C, F>(
+        &mut self,
+        f: &mut F,
+        ctx: &mut DrawParams<R, C>,
+        tooltips: &ControllerTooltips,
+        eye: Point3<f32>,
+        text: &Painter<R, SdfStyle<R>>,
+        lines: &Painter<R, SolidStyle<R>>,
+    )
+        -> Result<(), Error>
+        where C: CommandBuffer<R>, F: FactoryExt<R>
+    {
+        let (font, size) = (&self.font, self.size);
+        self.meshes.retain(|s, _| tooltips.tips.iter().any(|t| t.text == *s));
+        let mut leaders = Vec::new();
+        for t in &tooltips.tips {
+            let mesh = self.meshes.entry(t.text.clone())
+                .or_insert_with(|| TextMesh::from_string(font, &t.text, size).upload(f));
+            // center the text on the label position, facing the eye
+            let center = mesh.bounds.center();
+            let face = Isometry3::new_observer_frame(&t.position, &eye, &Vector3::y());
+            let model = face.to_homogeneous()
+                * Translation3::new(-center.x, -center.y, 0.).to_homogeneous();
+            let mut mat = mesh.mat.clone();
+            mat.color = self.color;
+            mat.color[3] = (self.color[3] as f32 * t.opacity) as u8;
+            text.try_draw_with(ctx, Transform3::from_matrix_unchecked(model), mesh, &mat)?;
+            if t.opacity > 0.5 {
+                for p in &[t.anchor, t.position] {
+                    leaders.push(VertC { pos: [p.x, p.y, p.z], color: self.line_color });
+                }
+            }
+        }
+        if leaders.is_empty() { return Ok(()) }
+        let buf = f.create_vertex_buffer(&leaders);
+        let mesh = Mesh {
+            slice: Slice::new_match_vertex_buffer(&buf),
+            buf: buf,
+            prim: Primitive::LineList,
+            bounds: Aabb::empty(),
+            mat: (),
+        };
+        lines.try_draw(ctx, na::one(), &mesh)
+    }
+}
+
+#[test]
+fn tooltip_layout() {
+    let mut c = MappedController::default();
+    c.pose = Isometry3::new(Vector3::new(0.2, 1., -0.3), na::zero());
+    let eye = Point3::new(0., 1.6, 0.5);
+    let mut tips = ControllerTooltips::new();
+    tips.lifetime = 2.;
+    tips.set(Hand::Right, ControllerButton::Trigger, "Select");
+    tips.set(Hand::Right, ControllerButton::Menu, "Menu");
+    tips.set(Hand::Right, ControllerButton::Pad, "Move");
+    tips.set(Hand::Left, ControllerButton::Pad, "Turn");
+    tips.set(Hand::Right, ControllerButton::Pad, "Move around");
+    assert_eq!(tips.tooltips().len(), 4);
+
+    // labels on one controller keep apart, and lines run from their buttons
+    tips.update(Hand::Right, &c, eye, 0.1);
+    let right: Vec<_> = tips.tooltips().iter().filter(|t| t.hand == Hand::Right).collect();
+    for (i, a) in right.iter().enumerate() {
+        assert!(relative_eq!(a.anchor, c.pose * ControllerButton::default_anchor(a.button)));
+        for b in &right[i + 1..] {
+            let view = (eye - na::center(&a.position, &b.position)).normalize();
+            let apart = b.position - a.position;
+            assert!((apart - view * apart.dot(&view)).norm() > tips.spacing * 0.9);
+        }
+    }
+
+    // custom anchors move the labels
+    tips.set_anchor(Hand::Right, ControllerButton::Trigger, Point3::new(0., -0.1, 0.));
+    tips.update(Hand::Right, &c, eye, 0.1);
+    let trigger = tips.tooltips().iter().find(|t| t.button == ControllerButton::Trigger).unwrap();
+    assert!(relative_eq!(trigger.anchor, Point3::new(0.2, 0.9, -0.3)));
+
+    // using a button fades its label out
+    c.trigger = 1.;
+    tips.update(Hand::Right, &c, eye, 0.25);
+    let trigger = tips.tooltips().iter().find(|t| t.button == ControllerButton::Trigger).unwrap();
+    assert_eq!(trigger.opacity, 1.);
+    tips.update(Hand::Right, &c, eye, 0.25);
+    assert_eq!(tips.tooltips().len(), 4);
+    tips.update(Hand::Right, &c, eye, 0.3);
+    assert!(tips.tooltips().iter().all(|t| t.button != ControllerButton::Trigger));
+
+    // the rest fade after their lifetime, but only updates of their own hand age them
+    c.trigger = 0.;
+    tips.update(Hand::Right, &c, eye, 2.);
+    assert_eq!(tips.tooltips().len(), 3);
+    tips.update(Hand::Right, &c, eye, 1.);
+    assert_eq!(tips.tooltips().len(), 1);
+    assert_eq!(tips.tooltips()[0].text, "Turn");
+    assert_eq!(tips.tooltips()[0].opacity, 1.);
+}
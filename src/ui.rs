@@ -0,0 +1,370 @@
+use gfx::{Resources, Factory, CommandBuffer};
+use nalgebra::{Isometry3, Point2, Vector2, Matrix4, Transform3};
+
+use ::{Ray, Error, TargetRef, DepthRef};
+use ::draw::{DrawParams, OffscreenTarget, Painter, UnlitStyle, UnlitMaterial};
+use ::mesh::{Mesh, VertNTT, gen};
+
+/// The kind of pointer interaction with a panel
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PanelEventKind {
+    /// The pointer moved over the panel
+    Move,
+    /// The pointer was pressed on the panel
+    Down,
+    /// The pointer was released after being pressed on the panel
+    Up,
+}
+
+/// A pointer interaction with a panel. Coordinates have their origin at the top left corner.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PanelEvent {
+    /// Location on the panel from (0, 0) to (1, 1)
+    pub uv: Point2<f32>,
+    /// Location on the panel in pixels
+    pub pixel: Point2<f32>,
+    /// What happened
+    pub kind: PanelEventKind,
+}
+
+/// The shape of a panel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Curvature {
+    /// A flat rectangle
+    Flat,
+    /// A section of a cylinder around a vertical axis `radius` units in front of the
+    /// panel, spanning `arc` radians. The panel width becomes the arc length.
+    Cylinder {
+        radius: f32,
+        arc: f32,
+    },
+}
+
+/// A rectangle in the world that 2D user interfaces are drawn onto, optionally curved
+/// around the viewer. This handles
+/// routing a controller's pointer into the panel; how the panel contents are drawn is
+/// up to the application.
+#[derive(Clone, Debug)]
+pub struct UiPanel {
+    /// Location of the panel center, the panel faces along +Z with +Y up
+    pub pose: Isometry3<f32>,
+    /// Width and height of the panel in world units
+    pub size: Vector2<f32>,
+    /// Width and height of the panel contents in pixels
+    pub resolution: (u32, u32),
+    /// Number of calls to `route_pointer` after a press or release during which
+    /// further changes of the trigger state are ignored
+    pub debounce: u32,
+    curvature: Curvature,
+    pressed: bool,
+    captured: bool,
+    lockout: u32,
+    last_uv: Point2<f32>,
+}
+
+impl UiPanel {
+    /// Create a panel with the given pose, size in world units, and resolution in pixels
+    pub fn new(pose: Isometry3<f32>, size: Vector2<f32>, resolution: (u32, u32)) -> UiPanel {
+        UiPanel {
+            pose: pose,
+            size: size,
+            resolution: resolution,
+            debounce: 3,
+            curvature: Curvature::Flat,
+            pressed: false,
+            captured: false,
+            lockout: 0,
+            last_uv: Point2::origin(),
+        }
+    }
+
+    /// The shape of the panel
+    pub fn curvature(&self) -> Curvature {
+        self.curvature
+    }
+
+    /// Change the shape of the panel. Curving the panel sets its width to the arc length.
+    pub fn set_curvature(&mut self, curvature: Curvature) {
+        if let Curvature::Cylinder { radius, arc } = curvature {
+            self.size.x = radius * arc;
+        }
+        self.curvature = curvature;
+    }
+
+    /// Project a ray onto the surface of the panel (extended past its edges), returning
+    /// the distance along the ray and the (possibly out of bounds) panel coordinates of
+    /// the intersection
+    pub fn project(&self, ray: &Ray) -> Option<(f32, Point2<f32>)> {
+        let inv = self.pose.inverse();
+        let origin = inv * ray.origin;
+        let dir = inv * ray.dir;
+        match self.curvature {
+            Curvature::Flat => {
+                if dir.z.abs() <= ::std::f32::EPSILON { return None }
+                let t = -origin.z / dir.z;
+                if t < 0. { return None }
+                let hit = origin + dir * t;
+                Some((t, Point2::new(hit.x / self.size.x + 0.5, 0.5 - hit.y / self.size.y)))
+            },
+            Curvature::Cylinder { radius, arc } => {
+                // intersect with the cylinder in the XZ plane, around the axis at z = radius
+                let (ox, oz) = (origin.x, origin.z - radius);
+                let a = dir.x * dir.x + dir.z * dir.z;
+                let b = 2. * (ox * dir.x + oz * dir.z);
+                let c = ox * ox + oz * oz - radius * radius;
+                let disc = b * b - 4. * a * c;
+                if a <= ::std::f32::EPSILON || disc < 0. { return None }
+                let uv = |t: f32| {
+                    let hit = origin + dir * t;
+                    let phi = hit.x.atan2(radius - hit.z);
+                    (Point2::new(phi / arc + 0.5, 0.5 - hit.y / self.size.y), radius - hit.z > 0.)
+                };
+                let (near, far) = ((-b - disc.sqrt()) / (2. * a), (-b + disc.sqrt()) / (2. * a));
+                // take the first hit within the panel, or else the first on the panel's half
+                // of the cylinder
+                let mut best = None;
+                for &t in &[near, far] {
+                    if t < 0. { continue }
+                    let (p, front) = uv(t);
+                    if p.x >= 0. && p.x <= 1. && p.y >= 0. && p.y <= 1. {
+                        return Some((t, p));
+                    }
+                    if front && best.is_none() {
+                        best = Some((t, p));
+                    }
+                }
+                best
+            },
+        }
+    }
+
+    /// Find where a ray hits the panel, if it does
+    pub fn hit(&self, ray: &Ray) -> Option<(f32, Point2<f32>)> {
+        self.project(ray).and_then(|(t, uv)| {
+            if uv.x >= 0. && uv.x <= 1. && uv.y >= 0. && uv.y <= 1. {
+                Some((t, uv))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// True while a press that started on the panel is held
+    pub fn captured(&self) -> bool {
+        self.captured
+    }
+
+    /// Route a controller pointer and trigger state into the panel. This should be called
+    /// once per frame. While a press that started on the panel is held, the pointer stays
+    /// captured: events keep coming (clamped to the panel edges) even when the ray slips
+    /// off, and the release is always reported.
+    pub fn route_pointer(&mut self, ray: Ray, pressed: bool) -> Option<PanelEvent> {
+        // debounce the trigger edges
+        let was_pressed = self.pressed;
+        if self.lockout > 0 {
+            self.lockout -= 1;
+        } else if pressed != self.pressed {
+            self.pressed = pressed;
+            self.lockout = self.debounce;
+        }
+        let down = self.pressed && !was_pressed;
+        let up = !self.pressed && was_pressed;
+
+        let uv = if self.captured {
+            match self.project(&ray) {
+                Some((_, uv)) => Point2::new(uv.x.max(0.).min(1.), uv.y.max(0.).min(1.)),
+                None => self.last_uv,
+            }
+        } else {
+            match self.hit(&ray) {
+                Some((_, uv)) => uv,
+                None => return None,
+            }
+        };
+        self.last_uv = uv;
+
+        let kind = if down {
+            self.captured = true;
+            PanelEventKind::Down
+        } else if up && self.captured {
+            self.captured = false;
+            PanelEventKind::Up
+        } else {
+            PanelEventKind::Move
+        };
+        Some(PanelEvent {
+            uv: uv,
+            pixel: Point2::new(uv.x * self.resolution.0 as f32, uv.y * self.resolution.1 as f32),
+            kind: kind,
+        })
+    }
+}
+
+/// The GPU mesh of a panel, regenerated only when the panel's shape or size changes.
+/// Replacing the mesh frees the old buffers.
+pub struct PanelMesh<R: Resources> {
+    shape: Option<(Curvature, Vector2<f32>)>,
+    mesh: Option<Mesh<R, VertNTT, ()>>,
+}
+
+impl<R: Resources> PanelMesh<R> {
+    /// Create an empty panel mesh
+    pub fn new() -> PanelMesh<R> {
+        PanelMesh {
+            shape: None,
+            mesh: None,
+        }
+    }
+
+    /// Get the mesh for the panel's current shape, centered on the panel origin and
+    /// textured with the panel contents (uv origin at the bottom left, as for other meshes)
+    pub fn get<F: Factory<R>>(&mut self, f: &mut F, panel: &UiPanel) -> &Mesh<R, VertNTT, ()> {
+        let shape = (panel.curvature, panel.size);
+        if self.shape != Some(shape) || self.mesh.is_none() {
+            let source = match panel.curvature {
+                Curvature::Flat => gen::quad(panel.size.x, panel.size.y),
+                Curvature::Cylinder { radius, arc } => {
+                    // a segment every 5 degrees keeps the curve smooth
+                    let segments = (arc.abs() / 5f32.to_radians()).ceil() as u32;
+                    gen::cylinder_section(radius, arc, panel.size.y, segments)
+                },
+            };
+            self.mesh = Some(source.map_material(|_| ()).upload(f));
+            self.shape = Some(shape);
+        }
+        self.mesh.as_ref().unwrap()
+    }
+}
+
+impl<R: Resources> Default for PanelMesh<R> {
+    fn default() -> PanelMesh<R> {
+        PanelMesh::new()
+    }
+}
+
+/// A world-locked panel showing 2D content. The content is drawn into an offscreen canvas
+/// between `begin_2d_draw` and `end_2d_draw` with any painter, then `draw` shows the
+/// canvas on a flat quad.
+pub struct WorldPanel<R: Resources> {
+    /// The panel quad, textured with the canvas
+    pub mesh: Mesh<R, VertNTT, UnlitMaterial<R>>,
+    /// The target 2D content is drawn into
+    pub canvas: OffscreenTarget<R>,
+    /// The color the canvas is cleared to when 2D drawing begins
+    pub background: [f32; 4],
+    transform: Matrix4<f32>,
+    saved: Option<(TargetRef<R>, DepthRef<R>)>,
+}
+
+impl<R: Resources> WorldPanel<R> {
+    /// Create a panel with the given size in world units and canvas resolution in pixels
+    pub fn new<F: Factory<R>>(f: &mut F, size: Vector2<f32>, resolution: (u16, u16))
+        -> Result<WorldPanel<R>, Error>
+    {
+        let canvas = OffscreenTarget::new(f, resolution.0, resolution.1)?;
+        let mat = UnlitMaterial { color: canvas.texture.clone() };
+        Ok(WorldPanel {
+            mesh: gen::quad(size.x, size.y).map_material(|_| mat).upload(f),
+            canvas: canvas,
+            background: [0., 0., 0., 0.],
+            transform: Matrix4::identity(),
+            saved: None,
+        })
+    }
+
+    /// Place the panel in the world. The quad faces +Z with +Y up before transformation.
+    pub fn set_transform(&mut self, mat: Matrix4<f32>) {
+        self.transform = mat;
+    }
+
+    /// The placement of the panel in the world
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    /// Clear the canvas and redirect `ctx` to draw into it. Until `end_2d_draw`, both
+    /// eyes are replaced by an orthographic view in canvas pixels (origin at the top left)
+    /// and everything drawn with `ctx` ends up on the panel.
+    pub fn begin_2d_draw<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        if self.saved.is_none() {
+            self.saved = Some((ctx.color.clone(), ctx.depth.clone()));
+            ctx.push_eye(self.canvas.pixel_eye());
+        }
+        ctx.encoder.clear(&self.canvas.color, self.background);
+        ctx.encoder.clear_depth(&self.canvas.depth, ::draw::DEPTH_CONVENTION.far());
+        ctx.color = self.canvas.color.clone();
+        ctx.depth = self.canvas.depth.clone();
+    }
+
+    /// Restore the targets and eyes `ctx` had before `begin_2d_draw`. The canvas now
+    /// holds the new panel contents.
+    pub fn end_2d_draw<C: CommandBuffer<R>>(&mut self, ctx: &mut DrawParams<R, C>) {
+        if let Some((color, depth)) = self.saved.take() {
+            ctx.color = color;
+            ctx.depth = depth;
+            ctx.pop_camera();
+        }
+    }
+
+    /// Draw the panel in the world
+    pub fn draw<C: CommandBuffer<R>>(&self, ctx: &mut DrawParams<R, C>, painter: &Painter<R, UnlitStyle<R>>)
+        -> Result<(), Error>
+    {
+        painter.try_draw(ctx, Transform3::from_matrix_unchecked(self.transform), &self.mesh)
+    }
+}
+
+#[test]
+fn panel_pointer_capture() {
+    use nalgebra::{self as na, Point3, Vector3};
+
+    let mut panel = UiPanel::new(na::one(), Vector2::new(2., 1.), (200, 100));
+    panel.debounce = 1;
+    let at = |x: f32, y: f32| Ray::new(Point3::new(x, y, 1.), Vector3::new(0., 0., -1.));
+
+    let e = panel.route_pointer(at(0.5, 0.25), false).unwrap();
+    assert_eq!(e.kind, PanelEventKind::Move);
+    assert!(relative_eq!(e.uv, Point2::new(0.75, 0.25)));
+    assert!(relative_eq!(e.pixel, Point2::new(150., 25.)));
+
+    assert_eq!(panel.route_pointer(at(0., 0.), true).unwrap().kind, PanelEventKind::Down);
+    assert!(panel.captured());
+    // a bouncing trigger is ignored
+    assert_eq!(panel.route_pointer(at(0., 0.), false).unwrap().kind, PanelEventKind::Move);
+    // the ray slips off the panel, but it is still captured
+    let e = panel.route_pointer(at(5., 0.), true).unwrap();
+    assert_eq!(e.kind, PanelEventKind::Move);
+    assert!(relative_eq!(e.uv, Point2::new(1., 0.5)));
+    assert_eq!(panel.route_pointer(at(5., 0.), false).unwrap().kind, PanelEventKind::Up);
+    assert!(!panel.captured());
+    assert!(panel.route_pointer(at(5., 0.), false).is_none());
+    // pointing away from the panel never hits
+    assert!(panel.route_pointer(Ray::new(Point3::new(0., 0., 1.), Vector3::z()), false).is_none());
+}
+
+#[test]
+fn curved_panel_mapping() {
+    use nalgebra::{self as na, Point3, Vector3};
+    use std::f32::consts::PI;
+
+    let mut panel = UiPanel::new(na::one(), Vector2::new(2., 1.), (200, 100));
+    panel.set_curvature(Curvature::Cylinder { radius: 1., arc: PI / 2. });
+    assert!(relative_eq!(panel.size.x, PI / 2.));
+
+    // from the axis, every direction hits the panel at its own angle
+    let axis = Point3::new(0., 0., 1.);
+    let (t, uv) = panel.hit(&Ray::new(axis, Vector3::new((PI / 8.).sin(), 0.25, -(PI / 8.).cos()))).unwrap();
+    assert!(relative_eq!(uv, Point2::new(0.75, 0.25), epsilon = 1e-5));
+    assert!(relative_eq!(t, 1., epsilon = 1e-5));
+    // past the edge of the arc the projection continues around the cylinder
+    let (_, uv) = panel.project(&Ray::new(axis, Vector3::new(1., 0., -0.1))).unwrap();
+    assert!(uv.x > 1.);
+    assert!(panel.hit(&Ray::new(axis, Vector3::new(1., 0., -0.1))).is_none());
+    // a ray from the front still hits the middle of the panel
+    let (_, uv) = panel.hit(&Ray::new(Point3::new(0., 0., 3.), Vector3::new(0., 0., -1.))).unwrap();
+    assert!(relative_eq!(uv, Point2::new(0.5, 0.5), epsilon = 1e-5));
+
+    panel.set_curvature(Curvature::Flat);
+    let (_, uv) = panel.hit(&Ray::new(Point3::new(0.5, 0., 1.), Vector3::new(0., 0., -1.))).unwrap();
+    assert!(relative_eq!(uv, Point2::new(0.5 / (PI / 2.) + 0.5, 0.5)));
+}
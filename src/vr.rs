@@ -1,10 +1,12 @@
 use nalgebra::{self as na, Similarity3, Transform3, Matrix4, Vector3, Point3, Vector2, Point2, Isometry3, Quaternion, Translation3, Unit};
 use webvr::*;
-use draw::EyeParams;
+use draw::{EyeParams, FrameStats, UberInputs};
 use fnv::FnvHashMap;
-use gfx::{Rect};
+use gfx::{Rect, Resources};
 use ::NativeRepr;
 
+pub use draw::LenticularCompositor;
+
 const VEL_SMOOTHING: f64 = 1e-90;
 
 /// Provides access to VR hardware.
@@ -515,3 +517,245 @@ impl Trackable for MappedController {
         self.pose
     }
 }
+
+/// Whether frames are drawn every refresh of the display or every other one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacingMode {
+    /// A new frame every refresh
+    Full,
+    /// A new frame every other refresh, which the compositor reprojects in between
+    Half,
+}
+
+/// Drops to half rate when frames keep overrunning the refresh interval, so that the
+/// compositor reprojects every other frame steadily instead of juddering whenever a frame is
+/// late. It returns to full rate once frames have fit comfortably for a while.
+///
+/// Call `update` once per refresh after `FrameStats::record`, then `render` to find whether to
+/// draw a new frame. On refreshes that aren't drawn, sync and submit the moment anyway: the eye
+/// texture still holds the previous frame, which is submitted again. Callbacks added with
+/// `on_change` can lower quality as well while at half rate, such as the resolution scale or
+/// the `DrawDistance` of distant objects.
+pub struct FrameGovernor {
+    /// The share of a refresh interval that the CPU or GPU time of a frame may take before it
+    /// counts as an overrun
+    pub overrun: f32,
+    /// Overrunning refreshes in a row before dropping to half rate
+    pub drop_after: u32,
+    /// The share of a full rate refresh interval frames must fit in to return to full rate
+    pub recover: f32,
+    /// Refreshes in a row fitting in `recover` before returning to full rate
+    pub recover_after: u32,
+    mode: PacingMode,
+    overruns: u32,
+    fits: u32,
+    skip: bool,
+    callbacks: Vec<Box<dyn FnMut(PacingMode, &FrameStats)>>,
+}
+
+impl FrameGovernor {
+    /// Start at full rate, dropping after a sixth of a second of overruns at 90 Hz and
+    /// recovering after two seconds with a third of the interval to spare
+    pub fn new() -> FrameGovernor {
+        FrameGovernor {
+            overrun: 0.95,
+            drop_after: 15,
+            recover: 0.65,
+            recover_after: 180,
+            mode: PacingMode::Full,
+            overruns: 0,
+            fits: 0,
+            skip: false,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// The current rate
+    pub fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    /// Call `f` with the new mode and the latest statistics whenever the rate changes
+    pub fn on_change<F: FnMut(PacingMode, &FrameStats) + 'static>(&mut self, f: F) {
+        self.callbacks.push(Box::new(f));
+    }
+
+    /// Judge the latest statistics, switching rate if needed, and mark the rate on `stats` for
+    /// the `StatsOverlay`
+    pub fn update(&mut self, stats: &mut FrameStats) -> PacingMode {
+        let interval = 1000. / stats.target_hz.max(1.);
+        let work = stats.cpu_ms.max(stats.gpu_ms);
+        match self.mode {
+            PacingMode::Full => {
+                self.overruns = if work > interval * self.overrun { self.overruns + 1 } else { 0 };
+                if self.overruns >= self.drop_after {
+                    self.switch(PacingMode::Half, stats);
+                }
+            },
+            PacingMode::Half => {
+                self.fits = if work < interval * self.recover { self.fits + 1 } else { 0 };
+                if self.fits >= self.recover_after {
+                    self.switch(PacingMode::Full, stats);
+                }
+            },
+        }
+        stats.half_rate = self.mode == PacingMode::Half;
+        self.mode
+    }
+
+    /// Whether to draw a new frame this refresh, once per refresh
+    pub fn render(&mut self) -> bool {
+        match self.mode {
+            PacingMode::Full => true,
+            PacingMode::Half => {
+                self.skip = !self.skip;
+                self.skip
+            },
+        }
+    }
+
+    fn switch(&mut self, mode: PacingMode, stats: &FrameStats) {
+        info!("Frame pacing switched to {:?} rate", mode);
+        self.mode = mode;
+        self.overruns = 0;
+        self.fits = 0;
+        self.skip = false;
+        for f in &mut self.callbacks {
+            f(mode, stats);
+        }
+    }
+}
+
+impl Default for FrameGovernor {
+    fn default() -> FrameGovernor {
+        FrameGovernor::new()
+    }
+}
+
+/// Rendering settings known to suit a model of headset, see `detect_hmd_profile`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HmdProfile {
+    /// The name of the model, or "Generic" if the headset isn't known
+    pub name: String,
+    /// Samples per pixel worth their cost at the recommended resolution
+    pub recommended_msaa: u8,
+    /// Scale for the render size given by the runtime (`VrContext::retrieve_size`)
+    pub recommended_resolution_scale: f32,
+    /// The gamma of the displays
+    pub display_gamma: f32,
+    /// The white point of the displays in kelvin
+    pub color_temperature_k: f32,
+}
+
+/// Known headsets, matched by a lowercase part of the display name. The Pimax headsets
+/// render far more pixels than the others, so they trade resolution and samples for rate.
+const HMD_PROFILES: [(&str, &str, u8, f32, f32, f32); 4] = [
+    ("rift s", "Oculus Rift S", 4, 1., 2.2, 6500.),
+    ("index", "Valve Index", 2, 1., 2.2, 6500.),
+    ("vive", "HTC Vive", 4, 1.2, 2.2, 6800.),
+    ("pimax", "Pimax", 2, 0.75, 2.2, 6500.),
+];
+
+impl HmdProfile {
+    /// Settings for an unknown headset, which leave rendering as it is
+    pub fn generic() -> HmdProfile {
+        HmdProfile {
+            name: "Generic".into(),
+            recommended_msaa: 4,
+            recommended_resolution_scale: 1.,
+            display_gamma: 2.2,
+            color_temperature_k: 6500.,
+        }
+    }
+
+    /// The profile of the headset with the given display name
+    pub fn from_name(display_name: &str) -> HmdProfile {
+        let lower = display_name.to_lowercase();
+        HMD_PROFILES.iter()
+            .find(|p| lower.contains(p.0))
+            .map(|&(_, name, msaa, scale, gamma, kelvin)| HmdProfile {
+                name: name.into(),
+                recommended_msaa: msaa,
+                recommended_resolution_scale: scale,
+                display_gamma: gamma,
+                color_temperature_k: kelvin,
+            })
+            .unwrap_or_else(HmdProfile::generic)
+    }
+
+    /// The render size to use for the size recommended by the runtime
+    pub fn resolution(&self, (w, h): (u32, u32)) -> (u32, u32) {
+        let scale = |x: u32| ((x as f32 * self.recommended_resolution_scale).round() as u32).max(1);
+        (scale(w), scale(h))
+    }
+
+    /// Encode the output of the uber style for the gamma of the displays. Output is encoded
+    /// for a gamma of 2.2 (by the shader, or an sRGB framebuffer), so only the difference
+    /// is applied. Exposure is left alone, since the displays don't change scene brightness,
+    /// and the render size is for the caller to apply with `resolution`.
+    pub fn apply<R: Resources>(&self, inputs: &mut UberInputs<R>) {
+        inputs.set_gamma(::OUTPUT_GAMMA * self.display_gamma / 2.2);
+    }
+}
+
+/// Find the profile of a headset by its display name, falling back to `HmdProfile::generic`
+pub fn detect_hmd_profile(display: &dyn VRDisplay) -> HmdProfile {
+    let profile = HmdProfile::from_name(&display.data().display_name);
+    info!("Using the {} headset profile", profile.name);
+    profile
+}
+
+#[test]
+fn frame_governor() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let mut stats = FrameStats::new(90.);
+    let mut governor = FrameGovernor::new();
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let seen = changes.clone();
+    governor.on_change(move |mode, _| seen.borrow_mut().push(mode));
+
+    // a few slow frames are tolerated
+    stats.cpu_ms = 14.;
+    for _ in 0..governor.drop_after - 1 {
+        assert_eq!(governor.update(&mut stats), PacingMode::Full);
+        assert!(governor.render());
+    }
+    stats.cpu_ms = 8.;
+    governor.update(&mut stats);
+    stats.cpu_ms = 14.;
+    for _ in 0..governor.drop_after {
+        governor.update(&mut stats);
+    }
+    assert_eq!(governor.mode(), PacingMode::Half);
+    assert!(stats.half_rate);
+    let drawn: Vec<bool> = (0..4).map(|_| governor.render()).collect();
+    assert_eq!(drawn, [true, false, true, false]);
+
+    // fitting the full rate interval without margin isn't enough to recover
+    stats.cpu_ms = 10.;
+    for _ in 0..governor.recover_after * 2 {
+        governor.update(&mut stats);
+    }
+    assert_eq!(governor.mode(), PacingMode::Half);
+    stats.cpu_ms = 5.;
+    for _ in 0..governor.recover_after {
+        governor.update(&mut stats);
+    }
+    assert_eq!(governor.mode(), PacingMode::Full);
+    assert!(!stats.half_rate);
+    assert_eq!(*changes.borrow(), [PacingMode::Half, PacingMode::Full]);
+}
+
+#[test]
+fn hmd_profiles() {
+    assert_eq!(HmdProfile::from_name("Oculus Rift S").recommended_msaa, 4);
+    assert_eq!(HmdProfile::from_name("Valve Index HMD").name, "Valve Index");
+    assert_eq!(HmdProfile::from_name("HTC VIVE Pro").name, "HTC Vive");
+    let pimax = HmdProfile::from_name("Pimax 5K Plus");
+    assert_eq!(pimax.resolution((2000, 1000)), (1500, 750));
+    // the first Rift isn't known
+    assert_eq!(HmdProfile::from_name("Oculus Rift CV1"), HmdProfile::generic());
+    assert_eq!(HmdProfile::generic().resolution((1512, 1680)), (1512, 1680));
+}
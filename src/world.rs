@@ -0,0 +1,146 @@
+use nalgebra::{self as na, Isometry3, Point3, Vector3, Translation3, Transform3, UnitQuaternion, Matrix4};
+
+use ::draw::EyeParams;
+
+/// A transform registered with a `WorldOrigin`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// Keeps positions near zero in large worlds. Single precision floats lose millimeter
+/// precision a few kilometers out, which shows up as jitter in VR, so the world is drawn
+/// relative to a movable origin kept in double precision. Nodes keep their positions in
+/// double precision and are handed out relative to the origin, and the locomotion offset
+/// (where the tracked space has been moved to) is adjusted whenever the origin moves.
+#[derive(Clone, Debug)]
+pub struct WorldOrigin {
+    origin: Vector3<f64>,
+    nodes: Vec<(Vector3<f64>, UnitQuaternion<f32>)>,
+    locomotion: Isometry3<f32>,
+    /// How far the locomotion offset can get from the origin before `update` rebases
+    pub rebase_distance: f32,
+}
+
+impl WorldOrigin {
+    /// Create an origin at the true world origin, rebasing every kilometer
+    pub fn new() -> WorldOrigin {
+        WorldOrigin {
+            origin: Vector3::zeros(),
+            nodes: Vec::new(),
+            locomotion: na::one(),
+            rebase_distance: 1000.,
+        }
+    }
+
+    /// The location of the origin in world coordinates
+    pub fn origin(&self) -> Point3<f64> {
+        Point3::from_coordinates(self.origin)
+    }
+
+    /// Convert a world position to a position relative to the origin
+    pub fn to_local(&self, world: &Point3<f64>) -> Point3<f32> {
+        na::convert(world - self.origin)
+    }
+
+    /// Convert a position relative to the origin to a world position
+    pub fn to_world(&self, local: &Point3<f32>) -> Point3<f64> {
+        na::convert::<_, Point3<f64>>(*local) + self.origin
+    }
+
+    /// Register a transform at the given world position and orientation, such as a static
+    /// object or a physics body, so that it moves with the origin
+    pub fn register(&mut self, pos: &Point3<f64>, rot: UnitQuaternion<f32>) -> NodeId {
+        self.nodes.push((pos.coords, rot));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// The transform of a node relative to the origin, for composing model matrices
+    pub fn node(&self, id: NodeId) -> Isometry3<f32> {
+        let (pos, rot) = self.nodes[id.0];
+        Isometry3::from_parts(Translation3::from_vector(na::convert(pos - self.origin)), rot)
+    }
+
+    /// Replace the transform of a node, given relative to the origin
+    pub fn set_node(&mut self, id: NodeId, iso: Isometry3<f32>) {
+        let pos: Vector3<f64> = na::convert(iso.translation.vector);
+        self.nodes[id.0] = (pos + self.origin, iso.rotation);
+    }
+
+    /// The model matrix of a node scaled by `scale`
+    pub fn model(&self, id: NodeId, scale: f32) -> Transform3<f32> {
+        Transform3::from_matrix_unchecked(self.node(id).to_homogeneous() * Matrix4::new_scaling(scale))
+    }
+
+    /// Where the tracked space is placed relative to the origin
+    pub fn locomotion(&self) -> Isometry3<f32> {
+        self.locomotion
+    }
+
+    /// Move the tracked space, given relative to the origin
+    pub fn set_locomotion(&mut self, iso: Isometry3<f32>) {
+        self.locomotion = iso;
+    }
+
+    /// Move the origin by `delta` world units. Nodes and the locomotion offset move the
+    /// opposite way relative to it, so nothing changes place in the world. Call this
+    /// between frames, since transforms fetched earlier are relative to the old origin.
+    pub fn shift_origin(&mut self, delta: Vector3<f64>) {
+        self.origin += delta;
+        let d: Vector3<f32> = na::convert(delta);
+        self.locomotion.translation.vector -= d;
+    }
+
+    /// Move the origin to the tracked space if it has wandered further than
+    /// `rebase_distance`, returning true if the origin moved
+    pub fn update(&mut self) -> bool {
+        let t = self.locomotion.translation.vector;
+        if t.norm() <= self.rebase_distance {
+            return false;
+        }
+        self.shift_origin(na::convert(t));
+        true
+    }
+
+    /// Move eye parameters from tracked space (as given by `VrMoment`) into the rebased
+    /// frame, so that head tracking, nodes and the origin agree
+    pub fn eye(&self, eye: &EyeParams) -> EyeParams {
+        let inv = self.locomotion.inverse().to_homogeneous();
+        EyeParams {
+            eye: self.locomotion * eye.eye,
+            view: Transform3::from_matrix_unchecked(eye.view.to_homogeneous() * inv),
+            .. *eye
+        }
+    }
+
+    /// Move a tracked pose (such as a controller) into the rebased frame
+    pub fn pose(&self, pose: &Isometry3<f32>) -> Isometry3<f32> {
+        self.locomotion * pose
+    }
+}
+
+impl Default for WorldOrigin {
+    fn default() -> WorldOrigin {
+        WorldOrigin::new()
+    }
+}
+
+#[test]
+fn origin_rebasing() {
+    let mut world = WorldOrigin::new();
+    let far = Point3::new(50_000.001, 2., -50_000.);
+    let node = world.register(&far, na::one());
+    // walk out to the object
+    world.set_locomotion(Isometry3::new(Vector3::new(50_000., 0., -50_000.), Vector3::zeros()));
+    assert!(world.update());
+    assert!(!world.update());
+
+    // everything is now near zero, and the millimeter offset survived
+    assert!(world.locomotion().translation.vector.norm() < 1e-3);
+    let local = world.node(node).translation.vector;
+    assert!(relative_eq!(local, Vector3::new(0.001, 2., 0.), epsilon = 1e-5));
+    assert!(relative_eq!(world.to_world(&Point3::from_coordinates(local)), far, epsilon = 1e-6));
+
+    // the eye sees the node where it was before rebasing
+    let head = EyeParams { eye: Point3::new(0., 2., 1.), ..Default::default() };
+    let eye = world.eye(&head);
+    assert!(relative_eq!(eye.eye, Point3::new(0., 2., 1.), epsilon = 1e-3));
+}